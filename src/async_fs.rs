@@ -0,0 +1,562 @@
+use std::cmp::min;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io::{
+    Error as IoError, ErrorKind, Read, Result as IoResult, Seek, SeekFrom,
+    Write,
+};
+use std::panic;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::{spawn_blocking, JoinError, JoinHandle};
+
+use crate::error::{Error, Result};
+use crate::file::File;
+use crate::fs::fnode::{DirEntry, Metadata, Version};
+use crate::repo::{OpenOptions, Repo, RepoInfo};
+
+// maximum size of buffer used by one blocking read or write
+const MAX_BUF_SIZE: usize = 2 * 1024 * 1024;
+
+// convert join error of blocking task to IO error, panic in the blocking
+// task is propagated to the caller
+fn join_err(err: JoinError) -> IoError {
+    if err.is_panic() {
+        panic::resume_unwind(err.into_panic());
+    }
+    IoError::new(ErrorKind::Other, "blocking task was cancelled")
+}
+
+// run a blocking function on the blocking thread pool
+pub(crate) async fn run_blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    match spawn_blocking(f).await {
+        Ok(ret) => ret,
+        Err(err) => Err(Error::from(join_err(err))),
+    }
+}
+
+/// An asynchronous handle to a [`Repo`].
+///
+/// This handle is created by [`RepoOpener::open_async`] or converted from an
+/// opened [`Repo`]. It can be cheaply cloned and shared between tasks, the
+/// operations on the underlying repository are serialised.
+///
+/// ZboxFS is synchronous internally, each operation is offloaded onto
+/// tokio's blocking thread pool using [`spawn_blocking`], so it must be used
+/// within a tokio runtime.
+///
+/// This must be enabled by Cargo feature `async`.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result};
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use zbox::RepoOpener;
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<()> {
+/// # init_env();
+/// let repo = RepoOpener::new()
+///     .create(true)
+///     .open_async("mem://async_repo", "pwd")
+///     .await?;
+///
+/// let mut file = repo.create_file("/foo.txt").await?;
+/// file.write_all(b"Hello, world!").await?;
+/// file.shutdown().await?;
+///
+/// let mut file = repo.open_file("/foo.txt").await?;
+/// let mut content = String::new();
+/// file.read_to_string(&mut content).await?;
+/// assert_eq!(content, "Hello, world!");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Repo`]: struct.Repo.html
+/// [`RepoOpener::open_async`]: struct.RepoOpener.html#method.open_async
+/// [`spawn_blocking`]: https://docs.rs/tokio/1/tokio/task/fn.spawn_blocking.html
+#[derive(Debug, Clone)]
+pub struct AsyncRepo {
+    repo: Arc<Mutex<Repo>>,
+}
+
+impl AsyncRepo {
+    /// Creates an asynchronous handle from an opened repository.
+    #[inline]
+    pub fn new(repo: Repo) -> Self {
+        AsyncRepo {
+            repo: Arc::new(Mutex::new(repo)),
+        }
+    }
+
+    // run a function with the repo on the blocking thread pool
+    async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Repo) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let repo = self.repo.clone();
+        run_blocking(move || {
+            let mut repo = repo.lock().unwrap();
+            f(&mut repo)
+        })
+        .await
+    }
+
+    /// Get repository metadata infomation.
+    ///
+    /// See [`Repo::info`](struct.Repo.html#method.info).
+    pub async fn info(&self) -> Result<RepoInfo> {
+        self.run(|repo| repo.info()).await
+    }
+
+    /// Returns whether the path points at an existing entity in repository.
+    ///
+    /// See [`Repo::path_exists`](struct.Repo.html#method.path_exists).
+    pub async fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.path_exists(path)).await
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a regular file.
+    ///
+    /// See [`Repo::is_file`](struct.Repo.html#method.is_file).
+    pub async fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.is_file(path)).await
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a directory.
+    ///
+    /// See [`Repo::is_dir`](struct.Repo.html#method.is_dir).
+    pub async fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.is_dir(path)).await
+    }
+
+    /// Create a file in read-write mode.
+    ///
+    /// See [`Repo::create_file`](struct.Repo.html#method.create_file).
+    pub async fn create_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<AsyncFile> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.create_file(path))
+            .await
+            .map(AsyncFile::new)
+    }
+
+    /// Attempts to open a file in read-only mode.
+    ///
+    /// See [`Repo::open_file`](struct.Repo.html#method.open_file).
+    pub async fn open_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<AsyncFile> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.open_file(path))
+            .await
+            .map(AsyncFile::new)
+    }
+
+    /// Opens a file at path with the options specified by `opts`.
+    ///
+    /// See [`OpenOptions::open`](struct.OpenOptions.html#method.open).
+    pub async fn open_file_with<P: AsRef<Path>>(
+        &self,
+        opts: OpenOptions,
+        path: P,
+    ) -> Result<AsyncFile> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| opts.open(repo, path))
+            .await
+            .map(AsyncFile::new)
+    }
+
+    /// Creates a new, empty directory at the specified path.
+    ///
+    /// See [`Repo::create_dir`](struct.Repo.html#method.create_dir).
+    pub async fn create_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.create_dir(path)).await
+    }
+
+    /// Recursively create a directory and all of its parent components if
+    /// they are missing.
+    ///
+    /// See [`Repo::create_dir_all`](struct.Repo.html#method.create_dir_all).
+    pub async fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.create_dir_all(path)).await
+    }
+
+    /// Returns a vector of all the entries within a directory.
+    ///
+    /// See [`Repo::read_dir`](struct.Repo.html#method.read_dir).
+    pub async fn read_dir<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Vec<DirEntry>> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.read_dir(path)).await
+    }
+
+    /// Get the metadata about a file or directory at specified path.
+    ///
+    /// See [`Repo::metadata`](struct.Repo.html#method.metadata).
+    pub async fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.metadata(path)).await
+    }
+
+    /// Return a vector of history versions of a regular file.
+    ///
+    /// See [`Repo::history`](struct.Repo.html#method.history).
+    pub async fn history<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Vec<Version>> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.history(path)).await
+    }
+
+    /// Copies the content of one file to another.
+    ///
+    /// See [`Repo::copy`](struct.Repo.html#method.copy).
+    pub async fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        let from = from.as_ref().to_path_buf();
+        let to = to.as_ref().to_path_buf();
+        self.run(move |repo| repo.copy(from, to)).await
+    }
+
+    /// Removes a regular file from the repository.
+    ///
+    /// See [`Repo::remove_file`](struct.Repo.html#method.remove_file).
+    pub async fn remove_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.remove_file(path)).await
+    }
+
+    /// Remove an existing empty directory.
+    ///
+    /// See [`Repo::remove_dir`](struct.Repo.html#method.remove_dir).
+    pub async fn remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.remove_dir(path)).await
+    }
+
+    /// Removes a directory at this path, after removing all its children.
+    ///
+    /// See [`Repo::remove_dir_all`](struct.Repo.html#method.remove_dir_all).
+    pub async fn remove_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |repo| repo.remove_dir_all(path)).await
+    }
+
+    /// Rename a file or directory to a new name, replacing the original file
+    /// if to already exists.
+    ///
+    /// See [`Repo::rename`](struct.Repo.html#method.rename).
+    pub async fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        let from = from.as_ref().to_path_buf();
+        let to = to.as_ref().to_path_buf();
+        self.run(move |repo| repo.rename(from, to)).await
+    }
+
+    /// Flush all pending writes to the underlying storage.
+    ///
+    /// See [`Repo::flush`](struct.Repo.html#method.flush).
+    pub async fn flush(&self) -> Result<()> {
+        self.run(|repo| repo.flush()).await
+    }
+}
+
+impl From<Repo> for AsyncRepo {
+    #[inline]
+    fn from(repo: Repo) -> Self {
+        AsyncRepo::new(repo)
+    }
+}
+
+// result of a blocking file operation
+enum Op {
+    Read(IoResult<Vec<u8>>),
+    Write(IoResult<usize>),
+    Flush(IoResult<()>),
+    Finish(IoResult<()>),
+}
+
+/// An asynchronous wrapper of [`File`].
+///
+/// It implements tokio's [`AsyncRead`] and [`AsyncWrite`] traits, each read
+/// or write is run on tokio's blocking thread pool. Like [`File`], written
+/// data becomes a permanent content version only after the file is finished,
+/// this is done by [`finish`] or [`AsyncWrite::poll_shutdown`].
+///
+/// An `AsyncFile` can be created by [`AsyncRepo`] or converted from a
+/// [`File`]. This must be enabled by Cargo feature `async`.
+///
+/// [`File`]: struct.File.html
+/// [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+/// [`AsyncWrite`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html
+/// [`AsyncWrite::poll_shutdown`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html#tymethod.poll_shutdown
+/// [`finish`]: struct.AsyncFile.html#method.finish
+/// [`AsyncRepo`]: struct.AsyncRepo.html
+pub struct AsyncFile {
+    file: Arc<Mutex<File>>,
+    task: Option<JoinHandle<Op>>, // in-flight blocking operation
+    rbuf: Vec<u8>, // data read ahead but not returned to caller yet
+    rpos: usize,   // position of unread data in read buffer
+}
+
+impl AsyncFile {
+    /// Creates an asynchronous wrapper of a file.
+    #[inline]
+    pub fn new(file: File) -> Self {
+        AsyncFile {
+            file: Arc::new(Mutex::new(file)),
+            task: None,
+            rbuf: Vec::new(),
+            rpos: 0,
+        }
+    }
+
+    // keep data read by a read operation, it will be returned by the
+    // following reads before a new read operation is started
+    fn keep_read(&mut self, ret: IoResult<Vec<u8>>) -> IoResult<()> {
+        self.rbuf = ret?;
+        self.rpos = 0;
+        Ok(())
+    }
+
+    // discard unread data and return its length, file position is ahead of
+    // the caller's position by that length
+    fn discard_read(&mut self) -> usize {
+        let unread = self.rbuf.len() - self.rpos;
+        self.rbuf.clear();
+        self.rpos = 0;
+        unread
+    }
+
+    // run a function with the file on the blocking thread pool
+    async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut File) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let file = self.file.clone();
+        run_blocking(move || {
+            let mut file = file.lock().unwrap();
+            file.run_in_trans(f)
+        })
+        .await
+    }
+
+    // poll in-flight blocking operation, or start a new one if there is none
+    fn poll_op<F>(&mut self, cx: &mut Context, f: F) -> Poll<IoResult<Op>>
+    where
+        F: FnOnce(&mut File) -> Op + Send + 'static,
+    {
+        if self.task.is_none() {
+            let file = self.file.clone();
+            self.task = Some(spawn_blocking(move || {
+                let mut file = file.lock().unwrap();
+                file.run_in_trans(f)
+            }));
+        }
+
+        let task = self.task.as_mut().unwrap();
+        let ret = match Pin::new(task).poll(cx) {
+            Poll::Ready(ret) => ret,
+            Poll::Pending => return Poll::Pending,
+        };
+        self.task.take();
+        Poll::Ready(ret.map_err(join_err))
+    }
+
+    /// Queries metadata about the file.
+    ///
+    /// See [`File::metadata`](struct.File.html#method.metadata).
+    pub async fn metadata(&self) -> Result<Metadata> {
+        self.run(|file| file.metadata()).await
+    }
+
+    /// Returns a list of all the file content versions.
+    ///
+    /// See [`File::history`](struct.File.html#method.history).
+    pub async fn history(&self) -> Result<Vec<Version>> {
+        self.run(|file| file.history()).await
+    }
+
+    /// Complete multi-part write to create a new version.
+    ///
+    /// See [`File::finish`](struct.File.html#method.finish).
+    pub async fn finish(&mut self) -> Result<()> {
+        self.run(|file| file.finish()).await
+    }
+
+    /// Truncates or extends the underlying file, create a new version of
+    /// content which size to become `size`.
+    ///
+    /// See [`File::set_len`](struct.File.html#method.set_len).
+    pub async fn set_len(&mut self, len: usize) -> Result<()> {
+        self.run(move |file| file.set_len(len)).await
+    }
+}
+
+impl From<File> for AsyncFile {
+    #[inline]
+    fn from(file: File) -> Self {
+        AsyncFile::new(file)
+    }
+}
+
+impl AsyncRead for AsyncFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<IoResult<()>> {
+        let me = self.get_mut();
+        let len = min(buf.remaining(), MAX_BUF_SIZE);
+
+        loop {
+            // return data left by previous read first
+            if me.rpos < me.rbuf.len() {
+                let end = min(me.rbuf.len(), me.rpos + buf.remaining());
+                buf.put_slice(&me.rbuf[me.rpos..end]);
+                me.rpos = end;
+                return Poll::Ready(Ok(()));
+            }
+
+            let op = match me.poll_op(cx, move |file| {
+                let mut data = vec![0u8; len];
+                Op::Read(file.read(&mut data).map(|read| {
+                    data.truncate(read);
+                    data
+                }))
+            }) {
+                Poll::Ready(op) => op?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            // the in-flight operation might be a different one which was
+            // abandoned by its caller, discard its result and start again,
+            // read result is kept as it might be for a different buffer
+            if let Op::Read(ret) = op {
+                me.keep_read(ret)?;
+                if me.rbuf.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let me = self.get_mut();
+
+        loop {
+            // unread data is discarded when write is started, file position
+            // is moved back to where the caller has read to
+            let unread = if me.task.is_none() {
+                me.discard_read()
+            } else {
+                0
+            };
+            let data = buf[..min(buf.len(), MAX_BUF_SIZE)].to_vec();
+            let op = match me.poll_op(cx, move |file| {
+                if unread > 0 {
+                    let pos = SeekFrom::Current(-(unread as i64));
+                    if let Err(err) = file.seek(pos) {
+                        return Op::Write(Err(err));
+                    }
+                }
+                Op::Write(file.write(&data))
+            }) {
+                Poll::Ready(op) => op?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match op {
+                Op::Write(ret) => return Poll::Ready(ret),
+                Op::Read(ret) => me.keep_read(ret)?,
+                _ => {}
+            }
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<IoResult<()>> {
+        let me = self.get_mut();
+
+        loop {
+            let op = match me.poll_op(cx, |file| Op::Flush(file.flush())) {
+                Poll::Ready(op) => op?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match op {
+                Op::Flush(ret) => return Poll::Ready(ret),
+                Op::Read(ret) => me.keep_read(ret)?,
+                _ => {}
+            }
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<IoResult<()>> {
+        let me = self.get_mut();
+
+        loop {
+            let op = match me
+                .poll_op(cx, |file| Op::Finish(map_io_err!(file.finish())))
+            {
+                Poll::Ready(op) => op?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match op {
+                Op::Finish(ret) => return Poll::Ready(ret),
+                Op::Read(ret) => me.keep_read(ret)?,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Debug for AsyncFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AsyncFile")
+            .field("file", &self.file)
+            .field("busy", &self.task.is_some())
+            .finish()
+    }
+}
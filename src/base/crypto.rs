@@ -107,6 +107,32 @@ extern "C" {
         k: *const u8,
     ) -> i32;
 
+    // ChaCha20-Poly1305 crypto
+    // ------------------------
+    fn crypto_aead_chacha20poly1305_ietf_encrypt(
+        c: *mut u8,
+        clen_p: *const u64,
+        m: *const u8,
+        mlen: u64,
+        ad: *const u8,
+        adlen: u64,
+        nsec: *const u8,
+        npub: *const u8,
+        k: *const u8,
+    ) -> i32;
+
+    fn crypto_aead_chacha20poly1305_ietf_decrypt(
+        m: *mut u8,
+        mlen_p: *const u64,
+        nsec: *const u8,
+        c: *const u8,
+        clen: u64,
+        ad: *const u8,
+        adlen: u64,
+        npub: *const u8,
+        k: *const u8,
+    ) -> i32;
+
     // AES256-GCM crypto (hardware only)
     // ---------------------------------
     fn crypto_aead_aes256gcm_is_available() -> i32;
@@ -582,6 +608,12 @@ impl Cost {
         }
     }
 
+    /// Check if either ops limit or memory limit is lower than the other
+    pub fn is_weaker_than(&self, other: &Cost) -> bool {
+        i32::from(self.ops_limit) < i32::from(other.ops_limit)
+            || i32::from(self.mem_limit) < i32::from(other.mem_limit)
+    }
+
     pub fn to_u8(self) -> u8 {
         let ops_limit = match self.ops_limit {
             OpsLimit::Interactive => 0u8,
@@ -662,6 +694,22 @@ pub enum Cipher {
     /// - Block size: 128 bits
     /// - MAC size: 128 bits
     Aes = 1,
+
+    /// ChaCha20-Poly1305-IETF, for interoperability with libraries which
+    /// only support the standard nonce size.
+    /// - Key size: 256 bits
+    /// - Nonce size: 96 bits
+    /// - Block size: 512 bits
+    /// - MAC size: 128 bits
+    ///
+    /// Nonces are randomly generated for each encryption, the shorter nonce
+    /// makes collision more likely than [`Xchacha`]. A nonce collision under
+    /// the same key breaks confidentiality, so the number of messages
+    /// encrypted with a single key should be kept well below 2^32. Prefer
+    /// [`Xchacha`] unless interoperability is required.
+    ///
+    /// [`Xchacha`]: enum.Cipher.html#variant.Xchacha
+    ChaCha20Poly1305 = 2,
 }
 
 impl Cipher {
@@ -671,6 +719,7 @@ impl Cipher {
         Ok(match s {
             0 => Cipher::Xchacha,
             1 => Cipher::Aes,
+            2 => Cipher::ChaCha20Poly1305,
             _ => return Err(Error::InvalidCipher),
         })
     }
@@ -681,6 +730,7 @@ impl From<Cipher> for u8 {
         match c {
             Cipher::Xchacha => 0,
             Cipher::Aes => 1,
+            Cipher::ChaCha20Poly1305 => 2,
         }
     }
 }
@@ -690,6 +740,7 @@ impl From<Cipher> for i32 {
         match c {
             Cipher::Xchacha => 0,
             Cipher::Aes => 1,
+            Cipher::ChaCha20Poly1305 => 2,
         }
     }
 }
@@ -699,6 +750,7 @@ impl From<i32> for Cipher {
         match n {
             0 => Cipher::Xchacha,
             1 => Cipher::Aes,
+            2 => Cipher::ChaCha20Poly1305,
             _ => unimplemented!(),
         }
     }
@@ -710,14 +762,62 @@ impl Default for Cipher {
     }
 }
 
+/// Returns the ciphers available on this platform.
+///
+/// [`Cipher::Xchacha`] and [`Cipher::ChaCha20Poly1305`] are always available,
+/// while [`Cipher::Aes`] is only available when it is supported by hardware.
+///
+/// [`init_env`] must be called before calling this function.
+///
+/// [`Cipher::Xchacha`]: enum.Cipher.html#variant.Xchacha
+/// [`Cipher::Aes`]: enum.Cipher.html#variant.Aes
+/// [`Cipher::ChaCha20Poly1305`]: enum.Cipher.html#variant.ChaCha20Poly1305
+/// [`init_env`]: fn.init_env.html
+pub fn available_ciphers() -> &'static [Cipher] {
+    if Crypto::is_aes_hardware_available() {
+        &[Cipher::Xchacha, Cipher::Aes, Cipher::ChaCha20Poly1305]
+    } else {
+        &[Cipher::Xchacha, Cipher::ChaCha20Poly1305]
+    }
+}
+
+/// Returns whether AES is supported by hardware on this platform.
+///
+/// [`Cipher::Aes`] can only be used when this returns `true`, it is also
+/// the default cipher used to create a repository in that case, otherwise
+/// [`Cipher::Xchacha`] is the default.
+///
+/// [`init_env`] must be called before calling this function.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{aes_hardware_available, init_env, Cipher};
+/// init_env();
+/// let cipher = if aes_hardware_available() {
+///     Cipher::Aes
+/// } else {
+///     Cipher::Xchacha
+/// };
+/// ```
+///
+/// [`Cipher::Aes`]: enum.Cipher.html#variant.Aes
+/// [`Cipher::Xchacha`]: enum.Cipher.html#variant.Xchacha
+/// [`init_env`]: fn.init_env.html
+#[inline]
+pub fn aes_hardware_available() -> bool {
+    Crypto::is_aes_hardware_available()
+}
+
 /// Authentication tag size
 const ATAG_SIZE: usize = 16;
 
 /// Nonce
 /// Note: AES nonce is extended, original is only 12 bytes. Since AES nonce
-/// is larger than Xchacha, we combine them into a single nonce type.
+/// is larger than the others, we combine them into a single nonce type.
 const AES_NONCE_SIZE: usize = 28;
 const XCHACHA_NONCE_SIZE: usize = 24;
+const CHACHA_NONCE_SIZE: usize = 12;
 type Nonce = [u8; AES_NONCE_SIZE];
 
 // encrypt/decrypt function type
@@ -779,6 +879,12 @@ impl Crypto {
                 enc_fn: crypto_aead_xchacha20poly1305_ietf_encrypt,
                 dec_fn: crypto_aead_xchacha20poly1305_ietf_decrypt,
             }),
+            Cipher::ChaCha20Poly1305 => Ok(Crypto {
+                cost,
+                cipher,
+                enc_fn: crypto_aead_chacha20poly1305_ietf_encrypt,
+                dec_fn: crypto_aead_chacha20poly1305_ietf_decrypt,
+            }),
             Cipher::Aes => {
                 if !Crypto::is_aes_hardware_available() {
                     return Err(Error::NoAesHardware);
@@ -994,6 +1100,7 @@ impl Crypto {
         match self.cipher {
             Cipher::Xchacha => XCHACHA_NONCE_SIZE,
             Cipher::Aes => AES_NONCE_SIZE,
+            Cipher::ChaCha20Poly1305 => CHACHA_NONCE_SIZE,
         }
     }
 
@@ -1041,13 +1148,13 @@ impl Crypto {
         let p_ctxt = ctxt.as_mut_ptr();
         let mut clen: u64 = 0;
 
-        // AES extended nonce is longer than Xchacha, so we can use it
-        // for both of the ciphers
+        // AES extended nonce is the longest, so we can use it for all of the
+        // ciphers
         let mut nonce: Nonce = [0u8; AES_NONCE_SIZE];
         Crypto::random_buf(&mut nonce);
 
         let result = match self.cipher {
-            Cipher::Xchacha => unsafe {
+            Cipher::Xchacha | Cipher::ChaCha20Poly1305 => unsafe {
                 (self.enc_fn)(
                     p_ctxt.add(nonce_size),
                     &mut clen as *mut u64,
@@ -1109,16 +1216,6 @@ impl Crypto {
         self.encrypt_with_ad(msg, key, &[0u8; 0])
     }
 
-    /// Encrypt message directly to dest buffer, zero copy
-    pub fn encrypt_to(
-        &self,
-        dst: &mut [u8],
-        msg: &[u8],
-        key: &Key,
-    ) -> Result<usize> {
-        self.encrypt_raw(dst, msg, key, &[0u8; 0])
-    }
-
     /// Decrypt message with specified key
     pub fn decrypt_raw(
         &self,
@@ -1129,10 +1226,15 @@ impl Crypto {
     ) -> Result<usize> {
         let mut msglen = msg.len() as u64;
         let nonce_size = self.nonce_size();
+
+        // cipher text could be encrypted by other cipher with shorter nonce
+        if ctxt.len() < nonce_size + ATAG_SIZE {
+            return Err(Error::Decrypt);
+        }
         let nonce = &ctxt[0..nonce_size];
 
         let result = match self.cipher {
-            Cipher::Xchacha => unsafe {
+            Cipher::Xchacha | Cipher::ChaCha20Poly1305 => unsafe {
                 (self.dec_fn)(
                     msg.as_mut_ptr(),
                     &mut msglen as *mut u64,
@@ -1174,6 +1276,9 @@ impl Crypto {
         key: &Key,
         ad: &[u8],
     ) -> Result<Vec<u8>> {
+        if ctxt.len() < self.nonce_size() + ATAG_SIZE {
+            return Err(Error::Decrypt);
+        }
         let mut msg = vec![0u8; self.decrypted_len(ctxt.len())];
         let dec_len = self.decrypt_raw(&mut msg, ctxt, key, ad)?;
         unsafe {
@@ -1186,17 +1291,6 @@ impl Crypto {
     pub fn decrypt(&self, ctxt: &[u8], key: &Key) -> Result<Vec<u8>> {
         self.decrypt_with_ad(ctxt, key, &[0u8; 0])
     }
-
-    /// Decrypt cipher text directly to dest buffer, zero copy
-    #[inline]
-    pub fn decrypt_to(
-        &self,
-        dst: &mut [u8],
-        ctxt: &[u8],
-        key: &Key,
-    ) -> Result<usize> {
-        self.decrypt_raw(dst, ctxt, key, &[0u8; 0])
-    }
 }
 
 impl Default for Crypto {
@@ -0,0 +1,266 @@
+use std::fmt::{self, Debug};
+
+use crate::error::{Error, Result};
+
+// a token in a path component pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    Any,  // '?', matches exactly one char
+    Star, // '*', matches any number of chars
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, ch: char) -> bool {
+        match *self {
+            Token::Char(c) => c == ch,
+            Token::Any => true,
+            Token::Star => unreachable!(),
+            Token::Class {
+                negated,
+                ref ranges,
+            } => ranges.iter().any(|&(lo, hi)| lo <= ch && ch <= hi) != negated,
+        }
+    }
+}
+
+// a path component in pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Component {
+    AnyDirs, // '**', matches zero or more path components
+    Name(Vec<Token>),
+}
+
+impl Component {
+    fn parse(s: &str) -> Result<Self> {
+        if s == "**" {
+            return Ok(Component::AnyDirs);
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = s.chars();
+        while let Some(ch) = chars.next() {
+            let token = match ch {
+                '?' => Token::Any,
+                '*' => {
+                    // consecutive stars are same as one star
+                    if tokens.last() == Some(&Token::Star) {
+                        continue;
+                    }
+                    Token::Star
+                }
+                '[' => Self::parse_class(&mut chars)?,
+                _ => Token::Char(ch),
+            };
+            tokens.push(token);
+        }
+        Ok(Component::Name(tokens))
+    }
+
+    // parse character class after '[', such as '[abc]', '[a-z]' or '[!0-9]',
+    // ']' can be included as the first char in class
+    fn parse_class(chars: &mut std::str::Chars) -> Result<Token> {
+        let mut negated = false;
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let ch = chars.next().ok_or(Error::InvalidArgument)?;
+            match ch {
+                '!' | '^' if first && !negated => {
+                    negated = true;
+                    continue;
+                }
+                ']' if !first || !ranges.is_empty() => break,
+                _ => {}
+            }
+            first = false;
+
+            // check if it is a range
+            let mut lookahead = chars.clone();
+            if lookahead.next() == Some('-') {
+                match lookahead.next() {
+                    Some(hi) if hi != ']' => {
+                        if hi < ch {
+                            return Err(Error::InvalidArgument);
+                        }
+                        ranges.push((ch, hi));
+                        *chars = lookahead;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            ranges.push((ch, ch));
+        }
+        Ok(Token::Class { negated, ranges })
+    }
+
+    // match a file name, using the classic wildcard backtracking algorithm
+    fn matches(&self, name: &str) -> bool {
+        let tokens = match *self {
+            Component::AnyDirs => return true,
+            Component::Name(ref tokens) => tokens,
+        };
+        let name: Vec<char> = name.chars().collect();
+        let (mut t, mut n) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while n < name.len() {
+            match tokens.get(t) {
+                Some(Token::Star) => {
+                    backtrack = Some((t, n));
+                    t += 1;
+                }
+                Some(token) if token.matches(name[n]) => {
+                    t += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    // let the last star match one more char
+                    Some((bt, bn)) => {
+                        t = bt + 1;
+                        n = bn + 1;
+                        backtrack = Some((bt, bn + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        tokens[t..].iter().all(|token| *token == Token::Star)
+    }
+}
+
+/// Glob pattern for matching paths
+///
+/// A pattern is matched component by component, the positions in the
+/// pattern which the matched path can be continued from are kept as a list
+/// of states. Empty components and leading '/' are ignored, so a pattern is
+/// always matched from the root.
+#[derive(Clone)]
+pub struct Glob {
+    comps: Vec<Component>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut comps: Vec<Component> = Vec::new();
+        for s in pattern.split('/').filter(|s| !s.is_empty()) {
+            let comp = Component::parse(s)?;
+
+            // consecutive '**' are same as one
+            if comp == Component::AnyDirs
+                && comps.last() == Some(&Component::AnyDirs)
+            {
+                continue;
+            }
+            comps.push(comp);
+        }
+        if comps.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Glob { comps })
+    }
+
+    /// Get the initial states, before any path component is matched
+    #[inline]
+    pub fn start(&self) -> Vec<usize> {
+        self.closure(vec![0])
+    }
+
+    /// Match a path component, return the new states
+    pub fn step(&self, states: &[usize], name: &str) -> Vec<usize> {
+        let mut next = Vec::new();
+        for &i in states.iter().filter(|&&i| i < self.comps.len()) {
+            match self.comps[i] {
+                // '**' consumes this component and stays
+                Component::AnyDirs => next.push(i),
+                ref comp if comp.matches(name) => next.push(i + 1),
+                _ => {}
+            }
+        }
+        self.closure(next)
+    }
+
+    /// Check if the whole pattern is matched
+    #[inline]
+    pub fn is_match(&self, states: &[usize]) -> bool {
+        states.contains(&self.comps.len())
+    }
+
+    /// Check if the pattern can match more path components
+    #[inline]
+    pub fn can_descend(&self, states: &[usize]) -> bool {
+        states.iter().any(|&i| i < self.comps.len())
+    }
+
+    // add states which can be reached without matching any component, that
+    // is, skipping '**'
+    fn closure(&self, mut states: Vec<usize>) -> Vec<usize> {
+        let mut idx = 0;
+        while idx < states.len() {
+            let i = states[idx];
+            if i < self.comps.len() && self.comps[i] == Component::AnyDirs {
+                states.push(i + 1);
+            }
+            idx += 1;
+        }
+        states.sort_unstable();
+        states.dedup();
+        states
+    }
+}
+
+impl Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Glob({} components)", self.comps.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, path: &str) -> bool {
+        let glob = Glob::new(pattern).unwrap();
+        let mut states = glob.start();
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            states = glob.step(&states, name);
+        }
+        glob.is_match(&states)
+    }
+
+    #[test]
+    fn glob_match() {
+        assert!(is_match("*.log", "/a.log"));
+        assert!(is_match("/*.log", "/.log"));
+        assert!(!is_match("*.log", "/dir/a.log"));
+        assert!(is_match("a?c", "/abc"));
+        assert!(!is_match("a?c", "/ac"));
+        assert!(is_match("*a*b*", "/xaybz"));
+        assert!(!is_match("*a*b", "/xaybz"));
+        assert!(is_match("[a-c]x", "/bx"));
+        assert!(!is_match("[!a-c]x", "/bx"));
+        assert!(is_match("[]]", "/]"));
+        assert!(is_match("[a-]", "/-"));
+        assert!(is_match("data/**/*.json", "/data/x.json"));
+        assert!(is_match("data/**/*.json", "/data/a/b/x.json"));
+        assert!(!is_match("data/**/*.json", "/other/a/x.json"));
+        assert!(is_match("**", "/a/b"));
+        assert!(is_match("a/**/**/b", "/a/b"));
+
+        // no descent when prefix doesn't match
+        let glob = Glob::new("b/**").unwrap();
+        let states = glob.step(&glob.start(), "a");
+        assert!(!glob.can_descend(&states));
+
+        // invalid patterns
+        assert_eq!(Glob::new("").unwrap_err(), Error::InvalidArgument);
+        assert_eq!(Glob::new("[ab").unwrap_err(), Error::InvalidArgument);
+        assert_eq!(Glob::new("[z-a]").unwrap_err(), Error::InvalidArgument);
+    }
+}
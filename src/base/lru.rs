@@ -59,13 +59,39 @@ where
         }
 
         self.used = (self.used as isize + delta) as usize;
-        if self.used > self.capacity {
-            self.remove_lru();
-        }
+        self.evict();
 
         ret
     }
 
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Get used capacity, measured by meter
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Set capacity and evict entries if it is exceeded
+    pub fn set_capacity(&mut self, capacity: usize) {
+        debug_assert!(capacity > 0);
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    // remove least recently used entries until used capacity is within
+    // limit or all the left entries are pinned
+    fn evict(&mut self) {
+        while self.used > self.capacity {
+            if self.remove_lru().is_none() {
+                break;
+            }
+        }
+    }
+
     #[inline]
     pub fn contains_key<Q: ?Sized>(&self, k: &Q) -> bool
     where
@@ -89,6 +115,12 @@ where
         self.map.entries()
     }
 
+    /// Remove all entries
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.used = 0;
+    }
+
     pub fn remove<Q: ?Sized>(&mut self, k: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -489,6 +489,18 @@ impl<W: Write> Encoder<W> {
         self.w.write_all(&self.buffer)
     }
 
+    /// Gets a reference to the wrapped writer.
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
+    /// Gets a mutable reference to the wrapped writer.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
     /// This function is used to flag that this session of compression is done
     /// with. The stream is finished up (final bytes are written), and then the
     /// wrapped writer is returned.
@@ -2,6 +2,7 @@
 //!
 
 pub(crate) mod crypto;
+pub(crate) mod glob;
 pub(crate) mod lru;
 pub(crate) mod lz4;
 mod refcnt;
@@ -11,7 +12,7 @@ pub(crate) mod version;
 pub(crate) mod vio;
 
 pub use self::refcnt::RefCnt;
-pub use self::time::Time;
+pub use self::time::{unix_secs, Time};
 pub use self::version::Version;
 
 use std::sync::{Arc, Once, RwLock};
@@ -1,7 +1,7 @@
 use std::fmt::{self, Debug};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[cfg(target_arch = "wasm32")]
 use js_sys;
@@ -30,6 +30,18 @@ impl Time {
     pub fn to_system_time(self) -> SystemTime {
         UNIX_EPOCH + self.0
     }
+
+    /// Convert from system time, return None if it is before unix epoch
+    #[inline]
+    pub fn from_system_time(time: SystemTime) -> Option<Self> {
+        time.duration_since(UNIX_EPOCH).ok().map(Time)
+    }
+
+    /// Get the amount of time elapsed since this time
+    #[inline]
+    pub fn elapsed(self) -> Duration {
+        Time::now().0.saturating_sub(self.0)
+    }
 }
 
 impl Debug for Time {
@@ -37,3 +49,27 @@ impl Debug for Time {
         write!(f, "Time({})", &self.0.as_secs())
     }
 }
+
+/// Serialize time as whole seconds since unix epoch
+///
+/// This is used by public types, such as `Metadata`, to keep their
+/// serialization language-agnostic. It is used with
+/// `#[serde(with = "unix_secs")]`.
+pub mod unix_secs {
+    use super::*;
+
+    pub fn serialize<S>(time: &Time, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(time.0.as_secs())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Time, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u64::deserialize(deserializer)
+            .map(|secs| Time(Duration::from_secs(secs)))
+    }
+}
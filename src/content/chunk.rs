@@ -161,6 +161,14 @@ impl ChunkMap {
         );
     }
 
+    // check if any chunk in the segment is in the map
+    pub fn has_segment(&self, seg_id: &Eid) -> bool {
+        match self.seg_ids.iter().position(|s| s == seg_id) {
+            Some(seg_idx) => self.map.values().any(|v| v.seg_idx == seg_idx),
+            None => false,
+        }
+    }
+
     pub fn remove_segment(&mut self, seg_id: &Eid) {
         if !self.is_enabled {
             return;
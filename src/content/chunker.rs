@@ -5,30 +5,35 @@ use std::ptr;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+
 // taken from pcompress implementation
 // https://github.com/moinakg/pcompress
 const PRIME: u64 = 153_191u64;
 const MASK: u64 = 0x00ff_ffff_ffffu64;
-const MIN_SIZE: usize = 16 * 1024; // minimal chunk size, 16k
-const AVG_SIZE: usize = 32 * 1024; // average chunk size, 32k
-const MAX_SIZE: usize = 64 * 1024; // maximum chunk size, 64k
+pub const DEFAULT_MIN_CHUNK_SIZE: usize = 16 * 1024; // minimal, 16k
+pub const DEFAULT_AVG_CHUNK_SIZE: usize = 32 * 1024; // average, 32k
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 64 * 1024; // maximum, 64k
+
+// chunk size limits, a segment can hold at most 256 chunks so the maximum
+// chunk size cannot be too big
+const CHUNK_SIZE_LOWER: usize = 1024;
+const CHUNK_SIZE_UPPER: usize = 256 * 1024;
 
 // Irreducible polynomial for Rabin modulus, from pcompress
 const FP_POLY: u64 = 0xbfe6_b8a5_bf37_8d83u64;
 
-// since we will skip MIN_SIZE when sliding window, it only
-// needs to target (AVG_SIZE - MIN_SIZE) cut length,
-// note the (AVG_SIZE - MIN_SIZE) must be 2^n
-const CUT_MASK: u64 = (AVG_SIZE - MIN_SIZE - 1) as u64;
-
 // rolling hash window constants
 const WIN_SIZE: usize = 16; // must be 2^n
 const WIN_MASK: usize = WIN_SIZE - 1;
 const WIN_SLIDE_OFFSET: usize = 64;
-const WIN_SLIDE_POS: usize = MIN_SIZE - WIN_SLIDE_OFFSET;
 
-// writer buffer length
-const WTR_BUF_LEN: usize = 8 * MAX_SIZE;
+// writer buffer length, in number of maximum chunk size
+const WTR_BUF_CHUNKS: usize = 8;
+
+// writer buffer length in low memory mode, it must be larger than maximum
+// chunk size so that a whole chunk can always fit in the buffer
+const LOW_MEM_WTR_BUF_CHUNKS: usize = 2;
 
 /// Pre-calculated chunker parameters
 #[derive(Clone, Deserialize, Serialize)]
@@ -36,11 +41,54 @@ pub struct ChunkerParams {
     poly_pow: u64,     // poly power
     out_map: Vec<u64>, // pre-computed out byte map, length is 256
     ir: Vec<u64>,      // irreducible polynomial, length is 256
+
+    // chunk sizes, stores created before they were configurable use the
+    // defaults
+    #[serde(default = "ChunkerParams::default_min_size")]
+    min_size: usize,
+    #[serde(default = "ChunkerParams::default_avg_size")]
+    avg_size: usize,
+    #[serde(default = "ChunkerParams::default_max_size")]
+    max_size: usize,
 }
 
 impl ChunkerParams {
-    pub fn new() -> Self {
-        let mut cp = ChunkerParams::default();
+    // create chunker parameters using specified chunk sizes
+    pub fn with_sizes(
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    ) -> Result<Self> {
+        Self::check_sizes(min_size, avg_size, max_size)?;
+        Ok(Self::build(min_size, avg_size, max_size))
+    }
+
+    // check if chunk sizes are valid, since we will skip minimum size when
+    // sliding window, it only needs to target (avg_size - min_size) cut
+    // length, so (avg_size - min_size) must be 2^n
+    pub fn check_sizes(
+        min_size: usize,
+        avg_size: usize,
+        max_size: usize,
+    ) -> Result<()> {
+        if min_size < CHUNK_SIZE_LOWER
+            || max_size > CHUNK_SIZE_UPPER
+            || min_size >= avg_size
+            || avg_size > max_size
+            || !(avg_size - min_size).is_power_of_two()
+        {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn build(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let mut cp = ChunkerParams {
+            min_size,
+            avg_size,
+            max_size,
+            ..Default::default()
+        };
 
         // calculate poly power, it is actually PRIME ^ WIN_SIZE
         for _ in 0..WIN_SIZE {
@@ -65,11 +113,40 @@ impl ChunkerParams {
 
         cp
     }
+
+    #[inline]
+    fn default_min_size() -> usize {
+        DEFAULT_MIN_CHUNK_SIZE
+    }
+
+    #[inline]
+    fn default_avg_size() -> usize {
+        DEFAULT_AVG_CHUNK_SIZE
+    }
+
+    #[inline]
+    fn default_max_size() -> usize {
+        DEFAULT_MAX_CHUNK_SIZE
+    }
+
+    #[inline]
+    fn cut_mask(&self) -> u64 {
+        (self.avg_size - self.min_size - 1) as u64
+    }
+
+    #[inline]
+    fn win_slide_pos(&self) -> usize {
+        self.min_size - WIN_SLIDE_OFFSET
+    }
 }
 
 impl Debug for ChunkerParams {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ChunkerParams()")
+        write!(
+            f,
+            "ChunkerParams({}, {}, {})",
+            self.min_size, self.avg_size, self.max_size
+        )
     }
 }
 
@@ -79,6 +156,9 @@ impl Default for ChunkerParams {
             poly_pow: 1,
             out_map: vec![0u64; 256],
             ir: vec![0u64; 256],
+            min_size: DEFAULT_MIN_CHUNK_SIZE,
+            avg_size: DEFAULT_AVG_CHUNK_SIZE,
+            max_size: DEFAULT_MAX_CHUNK_SIZE,
         };
         ret.out_map.shrink_to_fit();
         ret.ir.shrink_to_fit();
@@ -96,19 +176,32 @@ pub struct Chunker<W: Write + Seek> {
     win_idx: usize,
     roll_hash: u64,
     win: [u8; WIN_SIZE], // rolling hash circle window
-    buf: Vec<u8>,        // chunker buffer, fixed size: WTR_BUF_LEN
+    buf: Vec<u8>,        // chunker buffer, fixed size
 }
 
 impl<W: Write + Seek> Chunker<W> {
     pub fn new(params: ChunkerParams, dst: W) -> Self {
-        let mut buf = vec![0u8; WTR_BUF_LEN];
+        let buf_len = WTR_BUF_CHUNKS * params.max_size;
+        Self::with_buf_len(params, buf_len, dst)
+    }
+
+    // create a chunker with smaller buffer, at the cost of more data moving
+    // inside the buffer
+    pub fn new_low_memory(params: ChunkerParams, dst: W) -> Self {
+        let buf_len = LOW_MEM_WTR_BUF_CHUNKS * params.max_size;
+        Self::with_buf_len(params, buf_len, dst)
+    }
+
+    fn with_buf_len(params: ChunkerParams, buf_len: usize, dst: W) -> Self {
+        let mut buf = vec![0u8; buf_len];
         buf.shrink_to_fit();
+        let slide_pos = params.win_slide_pos();
 
         Chunker {
             dst,
             params,
-            pos: WIN_SLIDE_POS,
-            chunk_len: WIN_SLIDE_POS,
+            pos: slide_pos,
+            chunk_len: slide_pos,
             buf_clen: 0,
             win_idx: 0,
             roll_hash: 0,
@@ -117,6 +210,21 @@ impl<W: Write + Seek> Chunker<W> {
         }
     }
 
+    #[inline]
+    pub fn buf_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.dst
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.dst
+    }
+
     pub fn into_inner(mut self) -> IoResult<W> {
         self.flush()?;
         Ok(self.dst)
@@ -131,7 +239,7 @@ impl<W: Write + Seek> Write for Chunker<W> {
         }
 
         // copy source data into chunker buffer
-        let in_len = min(WTR_BUF_LEN - self.buf_clen, buf.len());
+        let in_len = min(self.buf.len() - self.buf_clen, buf.len());
         assert!(in_len > 0);
         self.buf[self.buf_clen..self.buf_clen + in_len]
             .copy_from_slice(&buf[..in_len]);
@@ -155,11 +263,13 @@ impl<W: Write + Seek> Write for Chunker<W> {
             self.chunk_len += 1;
             self.pos += 1;
 
-            if self.chunk_len >= MIN_SIZE {
+            if self.chunk_len >= self.params.min_size {
                 let chksum = self.roll_hash ^ self.params.ir[out];
 
                 // reached cut point, chunk can be produced now
-                if (chksum & CUT_MASK) == 0 || self.chunk_len >= MAX_SIZE {
+                if (chksum & self.params.cut_mask()) == 0
+                    || self.chunk_len >= self.params.max_size
+                {
                     // write the chunk to destination writer,
                     // ensure it is consumed in whole
                     let p = self.pos - self.chunk_len;
@@ -168,7 +278,7 @@ impl<W: Write + Seek> Write for Chunker<W> {
 
                     // not enough space in buffer, copy remaining to
                     // the head of buffer and reset buf position
-                    if self.pos + MAX_SIZE >= WTR_BUF_LEN {
+                    if self.pos + self.params.max_size >= self.buf.len() {
                         let left_len = self.buf_clen - self.pos;
                         unsafe {
                             ptr::copy::<u8>(
@@ -182,8 +292,9 @@ impl<W: Write + Seek> Write for Chunker<W> {
                     }
 
                     // jump to next start sliding position
-                    self.pos += WIN_SLIDE_POS;
-                    self.chunk_len = WIN_SLIDE_POS;
+                    let slide_pos = self.params.win_slide_pos();
+                    self.pos += slide_pos;
+                    self.chunk_len = slide_pos;
                 }
             }
         }
@@ -200,8 +311,8 @@ impl<W: Write + Seek> Write for Chunker<W> {
         }
 
         // reset chunker
-        self.pos = WIN_SLIDE_POS;
-        self.chunk_len = WIN_SLIDE_POS;
+        self.pos = self.params.win_slide_pos();
+        self.chunk_len = self.pos;
         self.buf_clen = 0;
         self.win_idx = 0;
         self.roll_hash = 0;
@@ -234,6 +345,15 @@ mod tests {
     use crate::base::utils::speed_str;
     use crate::content::chunk::Chunk;
 
+    fn default_params() -> ChunkerParams {
+        ChunkerParams::with_sizes(
+            DEFAULT_MIN_CHUNK_SIZE,
+            DEFAULT_AVG_CHUNK_SIZE,
+            DEFAULT_MAX_CHUNK_SIZE,
+        )
+        .unwrap()
+    }
+
     #[derive(Debug)]
     struct Sinker {
         len: usize,
@@ -293,7 +413,7 @@ mod tests {
 
         // perpare test data
         const DATA_LEN: usize = 765 * 1024;
-        let params = ChunkerParams::new();
+        let params = default_params();
         let mut data = vec![0u8; DATA_LEN];
         Crypto::random_buf(&mut data);
         let mut cur = Cursor::new(data);
@@ -310,13 +430,80 @@ mod tests {
         ckr.flush().unwrap();
     }
 
+    #[test]
+    fn chunker_low_memory() {
+        init_env();
+
+        // perpare test data
+        const DATA_LEN: usize = 765 * 1024;
+        let params = default_params();
+        let mut data = vec![0u8; DATA_LEN];
+        Crypto::random_buf(&mut data);
+
+        // chunk the same data using normal and low memory chunkers
+        let chunk = |mut ckr: Chunker<Sinker>| {
+            let mut cur = Cursor::new(&data);
+            copy(&mut cur, &mut ckr).unwrap();
+            let sinker = ckr.into_inner().unwrap();
+            assert_eq!(sinker.len, DATA_LEN);
+            sinker.chks
+        };
+        let sinker = Sinker {
+            len: 0,
+            chks: Vec::new(),
+        };
+        let chks = chunk(Chunker::new(params.clone(), sinker));
+        let sinker = Sinker {
+            len: 0,
+            chks: Vec::new(),
+        };
+        let low_mem_chks = chunk(Chunker::new_low_memory(params, sinker));
+
+        // chunk boundaries should be the same
+        assert_eq!(chks.len(), low_mem_chks.len());
+        for (a, b) in chks.iter().zip(low_mem_chks.iter()) {
+            assert_eq!((a.pos, a.len), (b.pos, b.len));
+        }
+    }
+
+    #[test]
+    fn chunker_sizes() {
+        init_env();
+
+        // perpare test data
+        const DATA_LEN: usize = 765 * 1024;
+        let (min, max) = (2048, 16 * 1024);
+        let params = ChunkerParams::with_sizes(min, 4096, max).unwrap();
+        let mut data = vec![0u8; DATA_LEN];
+        Crypto::random_buf(&mut data);
+        let sinker = Sinker {
+            len: 0,
+            chks: Vec::new(),
+        };
+
+        // all chunks except the last one should be within size limits
+        let mut ckr = Chunker::new(params, sinker);
+        copy(&mut Cursor::new(&data), &mut ckr).unwrap();
+        let sinker = ckr.into_inner().unwrap();
+        assert_eq!(sinker.len, DATA_LEN);
+        let (last, chks) = sinker.chks.split_last().unwrap();
+        assert!(last.len <= max);
+        for chk in chks {
+            assert!(chk.len >= min && chk.len <= max);
+        }
+
+        // invalid sizes
+        assert!(ChunkerParams::with_sizes(min, 5000, max).is_err());
+        assert!(ChunkerParams::with_sizes(4096, min, max).is_err());
+    }
+
     #[test]
     fn chunker_perf() {
         init_env();
 
         // perpare test data
         const DATA_LEN: usize = 10 * 1024 * 1024;
-        let params = ChunkerParams::new();
+        let params = default_params();
         let mut data = vec![0u8; DATA_LEN];
         let seed = RandomSeed::from(&[0u8; RANDOM_SEED_SIZE]);
         Crypto::random_buf_deterministic(&mut data, &seed);
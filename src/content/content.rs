@@ -1,9 +1,11 @@
 use std::cmp::min;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::fmt::{self, Debug};
 use std::io::{
-    Error as IoError, ErrorKind, Read, Result as IoResult, Seek, SeekFrom,
-    Write,
+    self, BufRead, Error as IoError, ErrorKind, Read, Result as IoResult, Seek,
+    SeekFrom, Write,
 };
+use std::ops::Range;
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
@@ -11,14 +13,17 @@ use serde::{Deserialize, Serialize};
 use super::chunk::ChunkMap;
 use super::entry::{CutableList, EntryList};
 use super::merkle_tree::{Leaves, MerkleTree, Writer as MerkleTreeWriter};
-use super::segment::Writer as SegWriter;
+use super::segment::{Segment, Writer as SegWriter};
 use super::span::{Extent, Span};
+use super::store::BufGuard;
 use super::{StoreRef, StoreWeakRef};
 use crate::base::crypto::{Crypto, Hash};
 use crate::error::{Error, Result};
 use crate::trans::cow::{CowCache, CowRef, Cowable, IntoCow};
-use crate::trans::{Eid, Finish, Id, TxMgrRef, TxMgrWeakRef, Txid};
-use crate::volume::VolumeWeakRef;
+use crate::trans::{
+    Eid, Finish, FrozenEntsRef, Id, TxMgrRef, TxMgrWeakRef, Txid,
+};
+use crate::volume::{VolumeWeakRef, FRAME_SIZE};
 
 /// Content
 #[derive(Default, Clone, Deserialize, Serialize)]
@@ -55,6 +60,46 @@ impl Content {
         self.mtree.root_hash()
     }
 
+    // get ids of all segments used by content, in content order
+    pub fn seg_ids(&self) -> Vec<Eid> {
+        let mut ids: Vec<Eid> = Vec::new();
+        for ent in self.ents.iter() {
+            if !ids.contains(ent.seg_id()) {
+                ids.push(ent.seg_id().clone());
+            }
+        }
+        ids
+    }
+
+    // check if content is fragmented, that is, it has more spans than the
+    // segments it uses
+    #[inline]
+    pub fn is_fragmented(&self) -> bool {
+        self.ents.span_cnt() > self.seg_ids().len()
+    }
+
+    // replace segment ids used by content
+    #[inline]
+    pub(super) fn remap_segs(&mut self, map: &HashMap<Eid, Eid>) -> Result<()> {
+        self.ents.remap_segs(map)
+    }
+
+    // get indices of chunks referenced in the specified segment
+    #[inline]
+    pub(super) fn chunk_indices(&self, seg_id: &Eid) -> BTreeSet<usize> {
+        self.ents.chunk_indices(seg_id)
+    }
+
+    // replace chunk indices in the specified segment used by content
+    #[inline]
+    pub(super) fn remap_chunks(
+        &mut self,
+        seg_id: &Eid,
+        map: &HashMap<usize, usize>,
+    ) -> Result<()> {
+        self.ents.remap_chunks(seg_id, map)
+    }
+
     // append chunk to content
     #[inline]
     fn append(&mut self, seg_id: &Eid, span: &Span) {
@@ -65,6 +110,7 @@ impl Content {
     pub fn merge_from(
         &mut self,
         other: &Content,
+        ad: &[u8],
         store: &StoreRef,
     ) -> Result<()> {
         // write other content into self
@@ -74,13 +120,18 @@ impl Content {
         }
 
         // merge merkle tree
-        let mut rdr = Reader::new(self.clone(), &Arc::downgrade(store));
+        let mut rdr = Reader::new(self.clone(), ad, &Arc::downgrade(store));
         self.mtree.merge(&other.leaves, &mut rdr)?;
 
         Ok(())
     }
 
-    pub fn truncate(&mut self, at: usize, store: &StoreRef) -> Result<()> {
+    pub fn truncate(
+        &mut self,
+        at: usize,
+        ad: &[u8],
+        store: &StoreRef,
+    ) -> Result<()> {
         // truncate content
         {
             let store = store.read().unwrap();
@@ -92,7 +143,7 @@ impl Content {
         }
 
         // truncate merkle tree
-        let mut rdr = Reader::new(self.clone(), &Arc::downgrade(store));
+        let mut rdr = Reader::new(self.clone(), ad, &Arc::downgrade(store));
         self.mtree.truncate(at, &mut rdr)?;
 
         Ok(())
@@ -129,6 +180,30 @@ impl Content {
         self.ents
             .unlink_weak(chk_map, store.make_mut_naive(), txmgr)
     }
+
+    // verify content data against its merkle tree, all the content data
+    // will be read and hashed
+    pub fn verify(&self, ad: &[u8], store: &StoreRef) -> Result<()> {
+        let mut rdr = Reader::new(self.clone(), ad, &Arc::downgrade(store));
+        let mut mtree_wtr = MerkleTreeWriter::new();
+        io::copy(&mut rdr, &mut mtree_wtr)?;
+        if mtree_wtr.finish().root_hash() != self.hash() {
+            return Err(Error::Corrupted);
+        }
+        Ok(())
+    }
+
+    // shrink all partially used segments
+    #[inline]
+    pub fn shrink_segs(
+        &self,
+        chk_map: &mut ChunkMap,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        let store = store.read().unwrap();
+        self.ents.shrink_segs(chk_map, &store, txmgr)
+    }
 }
 
 impl Seek for Content {
@@ -147,7 +222,12 @@ impl Debug for Content {
     }
 }
 
-impl Cowable for Content {}
+impl Cowable for Content {
+    #[inline]
+    fn frozen_copy(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
 
 impl<'de> IntoCow<'de> for Content {}
 
@@ -155,32 +235,135 @@ impl<'de> IntoCow<'de> for Content {}
 pub type ContentRef = CowRef<Content>;
 
 /// Content Reader
-#[derive(Debug)]
+///
+/// Content is read through a frame buffer, which is handed out by
+/// `fill_buf`, so it can be read line by line without another buffer.
 pub struct Reader {
     pos: u64,
     content: Content,
+    ad: Vec<u8>,
     store: StoreWeakRef,
+
+    // frozen entities, segments are read as they were when frozen
+    frozen: Option<FrozenEntsRef>,
+
+    // frame buffer, data from `buf_pos` is at reader position
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_size: usize,
+
+    // frame buffer is counted in store once it is allocated
+    buf_guard: Option<BufGuard>,
 }
 
 impl Reader {
-    pub fn new(content: Content, store: &StoreWeakRef) -> Self {
+    pub fn new(content: Content, ad: &[u8], store: &StoreWeakRef) -> Self {
         Reader {
             pos: 0,
             content,
+            ad: ad.to_vec(),
             store: store.clone(),
+            frozen: None,
+            buf: Vec::new(),
+            buf_pos: 0,
+            buf_size: FRAME_SIZE,
+            buf_guard: None,
         }
     }
-}
 
-impl Read for Reader {
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+    /// Set frame buffer size, default is `FRAME_SIZE`
+    #[inline]
+    pub fn with_buf_size(mut self, buf_size: usize) -> Self {
+        self.buf_size = buf_size.max(1);
+        self
+    }
+
+    /// Create a reader which reads segments as they were when frozen
+    ///
+    /// The segment data must not be removed while the reader is alive.
+    pub fn new_frozen(
+        content: Content,
+        frozen: &FrozenEntsRef,
+        ad: &[u8],
+        store: &StoreWeakRef,
+    ) -> Self {
+        Reader {
+            frozen: Some(frozen.clone()),
+            ..Reader::new(content, ad, store)
+        }
+    }
+
+    /// Read content in range and append to buffer, return the number of
+    /// bytes read
+    ///
+    /// Reader position is not changed. The range is truncated at the end of
+    /// content. Segment data which is not cached is read directly from
+    /// volume, so only the frames overlapping the range are decrypted.
+    pub fn read_range(
+        &self,
+        range: Range<usize>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        let end = min(range.end, self.content.len());
+        let start = min(range.start, end);
+        if start == end {
+            return Ok(0);
+        }
+
+        let store = self.store.upgrade().ok_or(Error::RepoClosed)?;
+        let store = store.read().unwrap();
+        let base = buf.len();
+        buf.resize(base + end - start, 0);
+        let mut pos = start;
+
+        for ent in self
+            .content
+            .ents
+            .iter()
+            .skip_while(|e| e.end_offset() <= start)
+            .take_while(|e| e.offset() < end)
+        {
+            // segment is locked before looking up its frozen copy
+            let seg_ref = store.get_seg(ent.seg_id())?;
+            let seg_cow = seg_ref.read().unwrap();
+            let frozen_seg;
+            let seg = match self.frozen {
+                Some(ref frozen) => match frozen.get::<Segment>(ent.seg_id()) {
+                    Some(seg) => {
+                        frozen_seg = seg;
+                        &*frozen_seg
+                    }
+                    None => seg_cow.committed(),
+                },
+                None => &*seg_cow,
+            };
+
+            for span in ent
+                .iter()
+                .skip_while(|s| s.end_offset() <= start)
+                .take_while(|s| s.offset < end)
+            {
+                let over_span = pos - span.offset;
+                let seg_offset = span.offset_in_seg(seg) + over_span;
+                let read_len = min(span.len - over_span, end - pos);
+                let dst = &mut buf[base + pos - start..][..read_len];
+                store.read_segdata_at(seg, &self.ad, dst, seg_offset)?;
+                pos += read_len;
+            }
+        }
+
+        Ok(end - start)
+    }
+
+    // read content at position into buffer, return the number of bytes read
+    fn read_at(&self, start: usize, buf: &mut [u8]) -> IoResult<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
 
         let store = map_io_err!(self.store.upgrade().ok_or(Error::RepoClosed))?;
         let store = store.read().unwrap();
-        let start = self.pos as usize;
+        let mut pos = start;
         let mut buf_read = 0;
 
         for ent in self
@@ -189,14 +372,26 @@ impl Read for Reader {
             .iter()
             .skip_while(|e| e.end_offset() <= start)
         {
+            // segment is locked before looking up its frozen copy
             let seg_ref = map_io_err!(store.get_seg(ent.seg_id()))?;
-            let seg = seg_ref.read().unwrap();
-            let segdata_ref = map_io_err!(store.get_segdata(seg.data_id()))?;
+            let seg_cow = seg_ref.read().unwrap();
+            let frozen_seg;
+            let seg = match self.frozen {
+                Some(ref frozen) => match frozen.get::<Segment>(ent.seg_id()) {
+                    Some(seg) => {
+                        frozen_seg = seg;
+                        &*frozen_seg
+                    }
+                    None => seg_cow.committed(),
+                },
+                None => &*seg_cow,
+            };
+            let segdata_ref = map_io_err!(store.get_segdata(seg, &self.ad))?;
             let segdata = segdata_ref.read().unwrap();
 
             for span in ent.iter().skip_while(|s| s.end_offset() <= start) {
-                let over_span = self.pos as usize - span.offset;
-                let mut seg_offset = span.offset_in_seg(&seg) + over_span;
+                let over_span = pos - span.offset;
+                let mut seg_offset = span.offset_in_seg(seg) + over_span;
                 let mut span_left = span.len - over_span;
 
                 while span_left > 0 {
@@ -212,7 +407,7 @@ impl Read for Reader {
                     buf_read += read;
                     seg_offset += read;
                     span_left -= read;
-                    self.pos += read as u64;
+                    pos += read;
                 }
             }
         }
@@ -221,23 +416,89 @@ impl Read for Reader {
     }
 }
 
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        // bypass frame buffer if it is empty and the read is large
+        if self.buf_pos >= self.buf.len() && buf.len() >= self.buf_size {
+            let read = self.read_at(self.pos as usize, buf)?;
+            self.pos += read as u64;
+            return Ok(read);
+        }
+
+        let read = {
+            let data = self.fill_buf()?;
+            let read = min(data.len(), buf.len());
+            buf[..read].copy_from_slice(&data[..read]);
+            read
+        };
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for Reader {
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        if self.buf_pos >= self.buf.len() {
+            if self.buf_guard.is_none() {
+                if let Some(store) = self.store.upgrade() {
+                    let store = store.read().unwrap();
+                    self.buf_guard = Some(store.rdr_buf_guard(self.buf_size));
+                }
+            }
+
+            // reuse buffer allocation, buffer is left empty if read failed
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.resize(self.buf_size, 0);
+            let read = self.read_at(self.pos as usize, &mut buf);
+            buf.truncate(*read.as_ref().unwrap_or(&0));
+            self.buf = buf;
+            self.buf_pos = 0;
+            read?;
+        }
+        Ok(&self.buf[self.buf_pos..])
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        let amt = min(amt, self.buf.len() - self.buf_pos);
+        self.buf_pos += amt;
+        self.pos += amt as u64;
+    }
+}
+
 impl Seek for Reader {
     fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
-        match pos {
-            SeekFrom::Start(pos) => {
-                self.pos = pos;
-            }
-            SeekFrom::End(pos) => {
-                self.pos = (self.content.len() as i64 + pos) as u64;
-            }
-            SeekFrom::Current(pos) => {
-                self.pos = (self.pos as i64 + pos) as u64;
-            }
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => pos,
+            SeekFrom::End(pos) => (self.content.len() as i64 + pos) as u64,
+            SeekFrom::Current(pos) => (self.pos as i64 + pos) as u64,
+        };
+
+        // keep buffered data if new position is still in frame buffer
+        let buf_start = self.pos - self.buf_pos as u64;
+        let buf_end = buf_start + self.buf.len() as u64;
+        if new_pos >= buf_start && new_pos <= buf_end {
+            self.buf_pos = (new_pos - buf_start) as usize;
+        } else {
+            self.buf.clear();
+            self.buf_pos = 0;
         }
+        self.pos = new_pos;
         Ok(self.pos)
     }
 }
 
+impl Debug for Reader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("pos", &self.pos)
+            .field("content", &self.content)
+            .field("frozen", &self.frozen)
+            .field("buffered", &(self.buf.len() - self.buf_pos))
+            .finish()
+    }
+}
+
 /// Content Writer
 #[derive(Debug)]
 pub struct Writer {
@@ -247,12 +508,21 @@ pub struct Writer {
     seg_wtr: SegWriter,
     mtree_wtr: MerkleTreeWriter,
     store: StoreWeakRef,
+
+    // bytes of chunks written so far and the bytes written to volume, and
+    // the chunks end and segment data end of chunks in current segment which
+    // are not written to volume yet
+    chunked: usize,
+    committed: usize,
+    pending: VecDeque<(usize, usize)>,
 }
 
 impl Writer {
     pub fn new(
         txid: Txid,
         chk_map: ChunkMap,
+        ad: &[u8],
+        compress: Option<bool>,
         store: &StoreWeakRef,
         txmgr: &TxMgrWeakRef,
         vol: &VolumeWeakRef,
@@ -261,12 +531,28 @@ impl Writer {
             txid,
             ctn: Content::new(),
             chk_map,
-            seg_wtr: SegWriter::new(txid, store, txmgr, vol),
+            seg_wtr: SegWriter::new(txid, ad, compress, store, txmgr, vol),
             mtree_wtr: MerkleTreeWriter::new(),
             store: store.clone(),
+            chunked: 0,
+            committed: 0,
+            pending: VecDeque::new(),
         }
     }
 
+    /// Use minimal buffer when writing segment data
+    #[inline]
+    pub fn with_low_memory(mut self, low_memory: bool) -> Self {
+        self.seg_wtr.set_low_memory(low_memory);
+        self
+    }
+
+    // size of buffer allocated for segment data
+    #[inline]
+    pub fn buf_len(&self) -> usize {
+        self.seg_wtr.buf_len()
+    }
+
     // append chunk to segment and content
     fn append_chunk(&mut self, chunk: &[u8], hash: &Hash) -> IoResult<()> {
         let chunk_len = chunk.len();
@@ -275,8 +561,13 @@ impl Writer {
         // create a new one and try it again
         let mut written = self.seg_wtr.write(chunk)?;
         if written == 0 {
-            // segment is full
+            // segment is full, its data is finished when renewing so all
+            // its chunks are written
             map_io_err!(self.seg_wtr.renew())?;
+            if let Some(&(chunked, _)) = self.pending.back() {
+                self.committed = chunked;
+            }
+            self.pending.clear();
             written = self.seg_wtr.write(chunk)?;
         }
         assert_eq!(written, chunk_len); // must written in whole
@@ -288,6 +579,7 @@ impl Writer {
         let span =
             Span::new(begin, begin + 1, 0, chunk_len, self.ctn.end_offset());
         self.ctn.append(seg.id(), &span);
+        self.pending.push_back((self.chunked, seg.len()));
 
         // and update chunk map
         self.chk_map.insert(hash, seg.id(), begin);
@@ -295,6 +587,31 @@ impl Writer {
         Ok(())
     }
 
+    // move committed length forward to the chunks written to volume
+    fn settle(&mut self) {
+        let data_committed = self.seg_wtr.committed_len();
+        while let Some(&(chunked, seg_end)) = self.pending.front() {
+            if seg_end > data_committed {
+                break;
+            }
+            self.committed = chunked;
+            self.pending.pop_front();
+        }
+    }
+
+    // make chunks written so far durable
+    #[inline]
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.seg_wtr.sync_data()
+    }
+
+    // number of content bytes written to volume in frames so far, chunks
+    // in the last partial frame are not included
+    #[inline]
+    pub fn committed_len(&self) -> usize {
+        self.committed
+    }
+
     // finish writer, return stage content and updated chunk map
     pub fn finish(mut self) -> Result<(Content, ChunkMap)> {
         // finish segment writer
@@ -313,6 +630,7 @@ impl Write for Writer {
 
         // calculate chunk hash
         let hash = Crypto::hash(chunk);
+        self.chunked += chunk_len;
 
         // update merkel tree
         let _ = self.mtree_wtr.write(chunk)?;
@@ -347,10 +665,19 @@ impl Write for Writer {
             );
             self.ctn.append(&loc.seg_id, &span);
             assert_eq!(chunk_len, chunk.len);
+
+            // duplicate chunk is written once the chunks before it are
+            match self.pending.back() {
+                Some(&(_, seg_end)) => {
+                    self.pending.push_back((self.chunked, seg_end))
+                }
+                None => self.committed = self.chunked,
+            }
         } else {
             // no duplication found, then append chunk to content
             self.append_chunk(chunk, &hash)?;
         }
+        self.settle();
 
         Ok(chunk_len)
     }
@@ -1,3 +1,4 @@
+use std::collections::{BTreeSet, HashMap};
 use std::io::{Result as IoResult, Seek, SeekFrom};
 use std::ops::Index;
 use std::slice::Iter;
@@ -8,7 +9,7 @@ use super::chunk::ChunkMap;
 use super::segment::Segment;
 use super::span::{Cutable, Extent, Span};
 use super::Store;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::trans::{Eid, Id, TxMgrRef};
 
 pub(super) trait CutableList: Clone + Extent {
@@ -296,6 +297,43 @@ impl EntryList {
         Ok((head, tail))
     }
 
+    // replace segment ids using the specified id map
+    pub fn remap_segs(&mut self, map: &HashMap<Eid, Eid>) -> Result<()> {
+        for ent in self.ents.iter_mut() {
+            ent.seg_id = map.get(&ent.seg_id).ok_or(Error::Corrupted)?.clone();
+        }
+        Ok(())
+    }
+
+    // get indices of chunks referenced in the specified segment
+    pub fn chunk_indices(&self, seg_id: &Eid) -> BTreeSet<usize> {
+        self.ents
+            .iter()
+            .filter(|ent| ent.seg_id == *seg_id)
+            .flat_map(|ent| {
+                ent.spans.iter().flat_map(|span| span.begin..span.end)
+            })
+            .collect()
+    }
+
+    // replace chunk indices in the specified segment using the specified
+    // index map
+    pub fn remap_chunks(
+        &mut self,
+        seg_id: &Eid,
+        map: &HashMap<usize, usize>,
+    ) -> Result<()> {
+        for ent in self.ents.iter_mut().filter(|ent| ent.seg_id == *seg_id) {
+            for span in ent.spans.iter_mut() {
+                let begin = *map.get(&span.begin).ok_or(Error::Corrupted)?;
+                let last = *map.get(&(span.end - 1)).ok_or(Error::Corrupted)?;
+                span.begin = begin;
+                span.end = last + 1;
+            }
+        }
+        Ok(())
+    }
+
     // create reference relationship between content and segment
     pub fn link(&self, store: &Store, txmgr: &TxMgrRef) -> Result<()> {
         for ent in self.ents.iter() {
@@ -343,6 +381,32 @@ impl EntryList {
         Ok(())
     }
 
+    // shrink all partially used segments regardless of their usage ratio and
+    // remove retired chunks from chunk map
+    pub fn shrink_segs(
+        &self,
+        chk_map: &mut ChunkMap,
+        store: &Store,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        for ent in self.ents.iter() {
+            let seg_ref = store.get_seg(&ent.seg_id)?;
+            let mut seg_cow = seg_ref.write().unwrap();
+
+            if seg_cow.has_garbage() {
+                let retired = Segment::shrink(&mut seg_cow, store, txmgr)?;
+                chk_map.remove_chunks(seg_cow.id(), &retired);
+            }
+        }
+
+        Ok(())
+    }
+
+    // get total number of spans in all entries
+    pub fn span_cnt(&self) -> usize {
+        self.ents.iter().map(|ent| ent.spans.len()).sum()
+    }
+
     // remove weak reference between content and segment, the weak reference is
     // the relationship hasn't been established by link(), used for stage
     // segment dereference
@@ -353,6 +353,12 @@ impl Writer {
         }
         self.leaves
     }
+
+    // finish writer and build merkle tree from written data, the data
+    // must be written from the beginning
+    pub fn finish(self) -> MerkleTree {
+        MerkleTree::build(&self.finish_with_leaves())
+    }
 }
 
 impl Write for Writer {
@@ -7,10 +7,16 @@ mod chunker;
 mod content;
 mod entry;
 mod merkle_tree;
+mod raw;
 mod segment;
 mod span;
 mod store;
 
 pub use self::chunk::ChunkMap;
+pub use self::chunker::{
+    ChunkerParams, DEFAULT_AVG_CHUNK_SIZE, DEFAULT_MAX_CHUNK_SIZE,
+    DEFAULT_MIN_CHUNK_SIZE,
+};
 pub use self::content::{Content, ContentRef, Reader as ContentReader};
+pub use self::raw::{import_raw, RawReader as ContentRawReader};
 pub use self::store::{Store, StoreRef, StoreWeakRef, Writer};
@@ -0,0 +1,300 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug};
+use std::io::{
+    Cursor, Error as IoError, ErrorKind, Read, Result as IoResult, Write,
+};
+use std::sync::Arc;
+
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use super::segment::{SegData, Segment};
+use super::{Content, StoreRef, StoreWeakRef};
+use crate::error::{Error, Result};
+use crate::trans::cow::IntoCow;
+use crate::trans::trans::Action;
+use crate::trans::{Eid, Finish, Id, TxMgrRef, Txid};
+use crate::volume::{
+    Compression, Reader as VolReader, VolumeWeakRef, Writer as VolWriter,
+};
+
+// Raw content stream layout:
+//
+// +------------+-------------+-----------+------------+-----+
+// | meta len   | meta        | data len  | data       | ... |
+// | (u64, LE)  | (msgpack)   | (u64, LE) | (raw bytes)|     |
+// +------------+-------------+-----------+------------+-----+
+//
+// meta is followed by segment data in the same order as the segments in
+// meta, each segment data is the bytes as they are stored in volume, that
+// is, decrypted but not decompressed. Segments can be shared with other
+// contents, so if a segment has chunks not referenced by this content, only
+// the referenced chunks are exported, in a segment whose data is not
+// compressed.
+
+// segment in raw content
+#[derive(Debug, Deserialize, Serialize)]
+struct RawSeg {
+    id: Eid,
+    seg: Segment,
+}
+
+// segment data in raw content stream
+#[derive(Debug)]
+enum RawData {
+    // segment data as it is stored in volume
+    Stored(Eid),
+
+    // the specified chunks in segment, decompressed
+    Chunks(Segment, Vec<usize>),
+}
+
+// raw content meta
+#[derive(Debug, Deserialize, Serialize)]
+struct RawMeta {
+    compress: bool, // volume's compression option, segment can override it
+    content: Content,
+    segs: Vec<RawSeg>,
+
+    // volume's compression algorithm, streams created before it was added
+    // only use the compress flag, which means LZ4
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+// read length prefix
+fn read_len<R: Read>(rdr: &mut R) -> Result<usize> {
+    let mut buf = [0u8; 8];
+    rdr.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+// read length prefixed bytes
+fn read_bytes<R: Read>(rdr: &mut R) -> Result<Vec<u8>> {
+    let len = read_len(rdr)?;
+    let mut buf = Vec::new();
+    rdr.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(Error::Corrupted);
+    }
+    Ok(buf)
+}
+
+// make length prefixed bytes
+fn prefix_len(buf: Vec<u8>) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(8 + buf.len());
+    ret.extend_from_slice(&(buf.len() as u64).to_le_bytes());
+    ret.extend_from_slice(&buf);
+    ret
+}
+
+/// Raw content reader
+///
+/// This reader reads content as a raw content stream, which can be imported
+/// by `import_raw` later.
+pub struct RawReader {
+    compress: bool, // whether any segment data is compressed
+    len: usize,
+    buf: Cursor<Vec<u8>>,
+    data: VecDeque<RawData>,
+    vol: VolumeWeakRef,
+    store: StoreWeakRef,
+    ad: Vec<u8>,
+}
+
+impl RawReader {
+    pub fn new(content: &Content, ad: &[u8], store: &StoreRef) -> Result<Self> {
+        let store_weak = Arc::downgrade(store);
+        let store = store.read().unwrap();
+        let vol = store.get_vol_weak();
+        let vol_comp = {
+            let vol = vol.upgrade().ok_or(Error::RepoClosed)?;
+            let vol = vol.read().unwrap();
+            vol.info().compression
+        };
+
+        // collect segments used by the content, segment can override
+        // volume's compression option
+        let mut content = content.clone();
+        let mut segs = Vec::new();
+        let mut data = VecDeque::new();
+        let mut compress = false;
+        for seg_id in content.seg_ids() {
+            let seg_ref = store.get_seg(&seg_id)?;
+            let seg = seg_ref.read().unwrap();
+            let indices: Vec<usize> =
+                content.chunk_indices(&seg_id).into_iter().collect();
+
+            let raw_seg = if indices.len() == seg.chunk_cnt() {
+                compress |= vol_comp.resolve(seg.compress()).is_enabled();
+                data.push_back(RawData::Stored(seg.data_id().clone()));
+                (**seg).clone()
+            } else {
+                // segment is shared with other contents, export the chunks
+                // referenced by this content only
+                let map: HashMap<usize, usize> = indices
+                    .iter()
+                    .enumerate()
+                    .map(|(new_idx, &idx)| (idx, new_idx))
+                    .collect();
+                content.remap_chunks(&seg_id, &map)?;
+                data.push_back(RawData::Chunks(
+                    (**seg).clone(),
+                    indices.clone(),
+                ));
+                seg.clone_chunks(&indices)
+            };
+            segs.push(RawSeg {
+                id: seg_id,
+                seg: raw_seg,
+            });
+        }
+
+        if segs.is_empty() {
+            compress = vol_comp.is_enabled();
+        }
+
+        // serialize meta
+        let len = content.len();
+        let meta = RawMeta {
+            compress: vol_comp.is_enabled(),
+            content,
+            segs,
+            compression: Some(vol_comp),
+        };
+        let mut buf = Vec::new();
+        meta.serialize(&mut Serializer::new(&mut buf))?;
+
+        Ok(RawReader {
+            compress,
+            len,
+            buf: Cursor::new(prefix_len(buf)),
+            data,
+            vol,
+            store: store_weak,
+            ad: ad.to_vec(),
+        })
+    }
+
+    /// Returns whether any segment data in stream is compressed.
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.compress
+    }
+
+    /// Returns the content length, in bytes.
+    #[inline]
+    pub fn content_len(&self) -> usize {
+        self.len
+    }
+
+    // load next segment data into buffer
+    fn load_next(&mut self) -> Result<bool> {
+        let mut buf = Vec::new();
+        match self.data.pop_front() {
+            Some(RawData::Stored(data_id)) => {
+                let vol = self.vol.upgrade().ok_or(Error::RepoClosed)?;
+                let mut rdr = VolReader::new_raw(&data_id, &vol, &self.ad)?;
+                rdr.read_to_end(&mut buf)?;
+            }
+            Some(RawData::Chunks(seg, indices)) => {
+                let store = self.store.upgrade().ok_or(Error::RepoClosed)?;
+                let store = store.read().unwrap();
+                let seg_data_ref = store.get_segdata(&seg, &self.ad)?;
+                let seg_data = seg_data_ref.read().unwrap();
+                buf.resize(seg.len(), 0);
+                let mut pos = 0;
+                for idx in indices {
+                    let chunk = &seg[idx];
+                    let end = pos + chunk.len;
+                    seg_data.read(&mut buf[pos..end], chunk.pos);
+                    pos = end;
+                }
+                buf.truncate(pos);
+            }
+            None => return Ok(false),
+        }
+        self.buf = Cursor::new(prefix_len(buf));
+        Ok(true)
+    }
+}
+
+impl Read for RawReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let read = self.buf.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            if !map_io_err!(self.load_next())? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl Debug for RawReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RawReader")
+            .field("compress", &self.compress)
+            .field("len", &self.len)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
+/// Import content from raw content stream
+///
+/// Segment data is written to volume as it is, so the stream must be created
+/// from a volume which uses the same compression algorithm. The returned content
+/// is not linked to its segments yet.
+pub fn import_raw<R: Read>(
+    rdr: &mut R,
+    txid: Txid,
+    ad: &[u8],
+    store: &StoreRef,
+    txmgr: &TxMgrRef,
+) -> Result<Content> {
+    // read meta
+    let buf = read_bytes(rdr)?;
+    let mut de = Deserializer::new(&buf[..]);
+    let meta: RawMeta = Deserialize::deserialize(&mut de)?;
+
+    let store = store.read().unwrap();
+    let vol = store.get_vol_weak();
+    {
+        let vol = vol.upgrade().ok_or(Error::RepoClosed)?;
+        let vol = vol.read().unwrap();
+        let meta_comp = meta
+            .compression
+            .unwrap_or_else(|| Compression::from_bool(meta.compress));
+        if !vol.info().compression.is_compatible(meta_comp) {
+            return Err(Error::InvalidArgument);
+        }
+    }
+
+    // write segment data and create segments
+    let mut seg_map = HashMap::new();
+    for raw_seg in meta.segs {
+        let data = read_bytes(rdr)?;
+        let seg = raw_seg.seg.clone_unlinked(!ad.is_empty());
+
+        // add a segment data stub to tx and write data directly to volume
+        SegData::add_to_trans(seg.data_id(), Action::New, txid, txmgr)?;
+        let mut wtr = VolWriter::new_raw(seg.data_id(), &vol, ad)?;
+        wtr.write_all(&data)?;
+        wtr.finish()?;
+
+        // add segment to tx and inject it to segment cache
+        let seg_ref = seg.into_cow(txmgr)?;
+        store.inject_seg_to_cache(&seg_ref);
+        let seg = seg_ref.read().unwrap();
+        seg_map.insert(raw_seg.id, seg.id().clone());
+    }
+
+    // point content to the new segments
+    let mut content = meta.content;
+    content.remap_segs(&seg_map)?;
+
+    Ok(content)
+}
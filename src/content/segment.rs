@@ -24,6 +24,9 @@ pub struct SegData {
     id: Eid,
     action: Option<Action>,
     data: Vec<u8>,
+
+    // associated data used to decrypt the data, only kept in memory
+    ad: Vec<u8>,
 }
 
 impl SegData {
@@ -32,6 +35,7 @@ impl SegData {
             id: id.clone(),
             action: None,
             data: Vec::new(),
+            ad: Vec::new(),
         }
     }
 
@@ -72,8 +76,13 @@ impl SegData {
         self.action.is_some()
     }
 
-    fn load(id: &Eid, vol: &VolumeRef) -> Result<Self> {
-        let mut rdr = VolReader::new(id, vol)?;
+    fn load(
+        id: &Eid,
+        vol: &VolumeRef,
+        ad: &[u8],
+        compress: Option<bool>,
+    ) -> Result<Self> {
+        let mut rdr = VolReader::new_with_compress(id, vol, ad, compress)?;
         let mut buf = Vec::new();
         rdr.read_to_end(&mut buf)?;
 
@@ -81,11 +90,13 @@ impl SegData {
             id: id.clone(),
             action: None,
             data: buf,
+            ad: ad.to_vec(),
         })
     }
 
-    fn save(&self, vol: &VolumeWeakRef) -> Result<()> {
-        let mut wtr = VolWriter::new(&self.id, vol)?;
+    fn save(&self, vol: &VolumeWeakRef, compress: Option<bool>) -> Result<()> {
+        let mut wtr =
+            VolWriter::new_with_compress(&self.id, vol, &[], compress)?;
         wtr.write_all(&self.data[..])?;
         wtr.finish()?;
         Ok(())
@@ -182,27 +193,82 @@ impl DataCache {
         }
     }
 
-    pub fn get(&self, id: &Eid, vol: &VolumeRef) -> Result<SegDataRef> {
+    pub fn get(
+        &self,
+        id: &Eid,
+        vol: &VolumeRef,
+        ad: &[u8],
+        compress: Option<bool>,
+    ) -> Result<SegDataRef> {
         let mut lru = self.lru.write().unwrap();
 
-        // get from cache first
+        // get from cache first, cached data can only be used if it was
+        // decrypted with the same associated data
         if let Some(val) = lru.get_refresh(id) {
-            return Ok(val.clone());
+            if val.read().unwrap().ad == ad {
+                return Ok(val.clone());
+            }
         }
 
         // if not in cache, load it from volume
         // then insert into cache
-        let ent = SegData::load(id, vol)?.into_ref();
+        let ent = SegData::load(id, vol, ad, compress)?.into_ref();
         lru.insert(id.clone(), ent.clone());
 
         Ok(ent)
     }
 
+    // read data at offset, cached data is used if there is any, otherwise
+    // only the frames covering the read are read from volume and the whole
+    // segment data is not cached, compressed data is always loaded in whole
+    pub fn read_at(
+        &self,
+        id: &Eid,
+        vol: &VolumeRef,
+        ad: &[u8],
+        compress: Option<bool>,
+        dst: &mut [u8],
+        offset: usize,
+    ) -> Result<()> {
+        {
+            let mut lru = self.lru.write().unwrap();
+            if let Some(val) = lru.get_refresh(id) {
+                let segdata = val.read().unwrap();
+                if segdata.ad == ad {
+                    segdata.read(dst, offset);
+                    return Ok(());
+                }
+            }
+        }
+
+        match VolReader::new_at(id, vol, ad, compress, offset)? {
+            Some(mut rdr) => rdr.read_exact(dst)?,
+            None => {
+                let segdata = self.get(id, vol, ad, compress)?;
+                segdata.read().unwrap().read(dst, offset);
+            }
+        }
+        Ok(())
+    }
+
     pub fn remove(&self, id: &Eid) -> Option<SegDataRef> {
         let mut lru = self.lru.write().unwrap();
         lru.remove(id)
     }
 
+    // get cached segment data size, in bytes
+    #[inline]
+    pub fn used(&self) -> usize {
+        let lru = self.lru.read().unwrap();
+        lru.used()
+    }
+
+    #[inline]
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut lru = self.lru.write().unwrap();
+        lru.set_capacity(capacity);
+    }
+
     // remove deleted segment data from cache
     pub fn remove_deleted(&self) {
         let mut lru = self.lru.write().unwrap();
@@ -225,6 +291,16 @@ pub struct Segment {
     used: usize, // currently used segment data length, in bytes
     data_id: Eid,
     chunks: Vec<Chunk>,
+
+    // segment data is encrypted with associated data, such segment is never
+    // shrunk as its data cannot be re-encrypted without the associated data
+    #[serde(default)]
+    with_ad: bool,
+
+    // segment data compression option, it overrides volume's compression
+    // option if it is set
+    #[serde(default)]
+    compress: Option<bool>,
 }
 
 impl Segment {
@@ -237,7 +313,49 @@ impl Segment {
             used: 0,
             data_id: Eid::new(),
             chunks: Vec::new(),
+            with_ad: false,
+            compress: None,
+        }
+    }
+
+    // create a copy of segment chunk layout with a new segment data id and
+    // no chunk references, used when importing raw segment data
+    pub(super) fn clone_unlinked(&self, with_ad: bool) -> Self {
+        Segment {
+            len: self.len,
+            used: 0,
+            data_id: Eid::new(),
+            chunks: self
+                .chunks
+                .iter()
+                .map(|c| Chunk::new(c.pos, c.len))
+                .collect(),
+            with_ad,
+            compress: self.compress,
+        }
+    }
+
+    // create a copy of segment chunk layout which only has the specified
+    // chunks, chunks are re-positioned in the order of the indices and have
+    // no references, segment data of the copy is not compressed
+    pub(super) fn clone_chunks(&self, indices: &[usize]) -> Self {
+        let mut seg = Segment::new();
+        for &idx in indices {
+            seg.append_chunk(self.chunks[idx].len);
         }
+        seg.with_ad = self.with_ad;
+        seg.compress = Some(false);
+        seg
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used
     }
 
     #[inline]
@@ -245,6 +363,11 @@ impl Segment {
         &self.data_id
     }
 
+    #[inline]
+    pub fn compress(&self) -> Option<bool> {
+        self.compress
+    }
+
     #[inline]
     pub fn chunk_cnt(&self) -> usize {
         self.chunks.len()
@@ -262,7 +385,13 @@ impl Segment {
 
     #[inline]
     pub fn is_shrinkable(&self) -> bool {
-        self.used < self.len >> 2
+        !self.with_ad && self.used < self.len >> 2
+    }
+
+    // check if segment has any unused data which can be shrunk
+    #[inline]
+    pub fn has_garbage(&self) -> bool {
+        !self.with_ad && !self.is_orphan() && self.used < self.len
     }
 
     // create a new chunk and append to segment
@@ -337,7 +466,7 @@ impl Segment {
         // load the segment data for shrinking, because it is going to be
         // shrank we remove it from cache immediately
         let seg_data_ref = {
-            store.get_segdata(seg.data_id())?;
+            store.get_segdata(seg, &[])?;
             store.remove_segdata_from_cache(seg.data_id()).unwrap()
         };
 
@@ -368,7 +497,7 @@ impl Segment {
         let mut new_seg_data = SegData::new(&new_data_id);
         let vol = store.get_vol_weak();
         new_seg_data.data = buf;
-        new_seg_data.save(&vol)?;
+        new_seg_data.save(&vol, seg.compress)?;
         SegData::add_to_trans(&new_data_id, Action::New, txid, txmgr)?;
 
         // update segment's length and its associated segment data id
@@ -432,7 +561,12 @@ impl Index<Range<usize>> for Segment {
     }
 }
 
-impl Cowable for Segment {}
+impl Cowable for Segment {
+    #[inline]
+    fn frozen_copy(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
 
 impl<'de> IntoCow<'de> for Segment {}
 
@@ -445,6 +579,9 @@ pub struct Writer {
     txid: Txid,
     seg: SegRef,
     data_wtr: Option<VolWriter>, // segment data writer
+    ad: Vec<u8>,                 // associated data for segment data
+    compress: Option<bool>,      // compression option for segment data
+    low_memory: bool,            // use minimal buffer for segment data
     txmgr: TxMgrWeakRef,
     store: StoreWeakRef,
     vol: VolumeWeakRef,
@@ -453,6 +590,8 @@ pub struct Writer {
 impl Writer {
     pub fn new(
         txid: Txid,
+        ad: &[u8],
+        compress: Option<bool>,
         store: &StoreWeakRef,
         txmgr: &TxMgrWeakRef,
         vol: &VolumeWeakRef,
@@ -461,6 +600,9 @@ impl Writer {
             txid,
             seg: Arc::default(),
             data_wtr: None,
+            ad: ad.to_vec(),
+            compress,
+            low_memory: false,
             txmgr: txmgr.clone(),
             store: store.clone(),
             vol: vol.clone(),
@@ -472,11 +614,27 @@ impl Writer {
         self.seg.clone()
     }
 
+    // use minimal buffer for segment data written after this
+    #[inline]
+    pub fn set_low_memory(&mut self, low_memory: bool) {
+        self.low_memory = low_memory;
+    }
+
+    // size of buffer allocated for current segment data
+    #[inline]
+    pub fn buf_len(&self) -> usize {
+        self.data_wtr
+            .as_ref()
+            .map_or(0, |data_wtr| data_wtr.buf_len())
+    }
+
     pub fn renew(&mut self) -> Result<()> {
         let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
 
         // create a new segment
-        let seg = Segment::new();
+        let mut seg = Segment::new();
+        seg.with_ad = !self.ad.is_empty();
+        seg.compress = self.compress;
 
         // add a segment data stub to tx, the actual data will be directly
         // written using volume writer instead of writing to the segment data
@@ -489,7 +647,16 @@ impl Writer {
         }
 
         // and then create a new segment data writer and add segment to tx
-        self.data_wtr = Some(VolWriter::new(&seg.data_id, &self.vol)?);
+        let mut data_wtr = VolWriter::new_with_compress(
+            &seg.data_id,
+            &self.vol,
+            &self.ad,
+            self.compress,
+        )?;
+        if self.low_memory {
+            data_wtr.set_low_memory();
+        }
+        self.data_wtr = Some(data_wtr);
         self.seg = seg.into_cow(&txmgr)?;
 
         // inject segment to segment cache in store
@@ -499,6 +666,23 @@ impl Writer {
 
         Ok(())
     }
+
+    // length of current segment data written to volume in frames
+    #[inline]
+    pub fn committed_len(&self) -> usize {
+        self.data_wtr
+            .as_ref()
+            .map_or(0, |data_wtr| data_wtr.committed_len())
+    }
+
+    // make segment data written so far durable
+    #[inline]
+    pub fn sync_data(&mut self) -> Result<()> {
+        match self.data_wtr {
+            Some(ref mut data_wtr) => data_wtr.sync_data(),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Write for Writer {
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fmt::{self, Debug};
 use std::io::{Result as IoResult, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
@@ -11,7 +12,7 @@ use super::content::{
     Cache as ContentCache, ContentRef, Writer as ContentWriter,
 };
 use super::segment::{
-    Cache as SegCache, DataCache as SegDataCache, SegDataRef, SegRef,
+    Cache as SegCache, DataCache as SegDataCache, SegDataRef, SegRef, Segment,
 };
 use super::Content;
 use crate::base::crypto::Hash;
@@ -54,6 +55,11 @@ pub struct Store {
     dedup_file: bool,
     content_map: HashMap<Hash, ContentMapEntry>,
 
+    // reference counts of contents shared by multiple versions when file
+    // dedup is disabled, content not in it is used by one version only
+    #[serde(default)]
+    shared: HashMap<Eid, RefCnt>,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     content_cache: ContentCache,
 
@@ -63,6 +69,18 @@ pub struct Store {
     #[serde(skip_serializing, skip_deserializing, default)]
     segdata_cache: SegDataCache,
 
+    // total buffer size of all living writers, in bytes
+    #[serde(skip_serializing, skip_deserializing, default)]
+    wtr_buf_size: Arc<AtomicUsize>,
+
+    // total buffer size of all living readers, in bytes
+    #[serde(skip_serializing, skip_deserializing, default)]
+    rdr_buf_size: Arc<AtomicUsize>,
+
+    // memory budget for caches and buffers, in bytes, 0 means no budget
+    #[serde(skip_serializing, skip_deserializing, default)]
+    mem_budget: Arc<AtomicUsize>,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     txmgr: TxMgrRef,
 
@@ -80,14 +98,26 @@ impl Store {
     // default content cache size
     const CONTENT_CACHE_SIZE: usize = 16;
 
-    pub fn new(dedup_file: bool, txmgr: &TxMgrRef, vol: &VolumeRef) -> Self {
+    // minimum memory for caches when memory budget is set, in bytes
+    const MIN_CACHE_MEM: usize = 64 * 1024;
+
+    pub fn new(
+        chunker_params: ChunkerParams,
+        dedup_file: bool,
+        txmgr: &TxMgrRef,
+        vol: &VolumeRef,
+    ) -> Self {
         Store {
-            chunker_params: ChunkerParams::new(),
+            chunker_params,
             dedup_file,
             content_map: HashMap::new(),
+            shared: HashMap::new(),
             content_cache: ContentCache::new(Self::CONTENT_CACHE_SIZE),
             seg_cache: SegCache::new(Self::SEG_CACHE_SIZE),
             segdata_cache: SegDataCache::new(Self::SEG_DATA_CACHE_SIZE),
+            wtr_buf_size: Arc::new(AtomicUsize::new(0)),
+            rdr_buf_size: Arc::new(AtomicUsize::new(0)),
+            mem_budget: Arc::new(AtomicUsize::new(0)),
             txmgr: txmgr.clone(),
             vol: vol.clone(),
         }
@@ -111,6 +141,54 @@ impl Store {
         Ok(store)
     }
 
+    // get segment data cache size, in bytes
+    #[inline]
+    pub fn segdata_cache_used(&self) -> usize {
+        self.segdata_cache.used()
+    }
+
+    // get total buffer size of all living writers, in bytes
+    #[inline]
+    pub fn wtr_buf_size(&self) -> usize {
+        self.wtr_buf_size.load(Ordering::Relaxed)
+    }
+
+    // get total buffer size of all living readers, in bytes
+    #[inline]
+    pub fn rdr_buf_size(&self) -> usize {
+        self.rdr_buf_size.load(Ordering::Relaxed)
+    }
+
+    // count a reader buffer until the returned guard is dropped, caches
+    // are shrunk if memory budget is exceeded
+    pub(super) fn rdr_buf_guard(&self, size: usize) -> BufGuard {
+        let guard = BufGuard::new(size, &self.rdr_buf_size);
+        self.balance_mem();
+        guard
+    }
+
+    /// Set memory budget for caches and buffers, in bytes
+    pub fn set_mem_budget(&self, budget: usize) {
+        self.mem_budget.store(budget, Ordering::Relaxed);
+        self.balance_mem();
+    }
+
+    // resize caches so that caches and living buffers stay within memory
+    // budget, the caches are given what the buffers leave, segment data
+    // cache, frame cache, depot's caches and address cache take 1/2, 1/4,
+    // 3/16 and 1/16 of it respectively
+    fn balance_mem(&self) {
+        let budget = self.mem_budget.load(Ordering::Relaxed);
+        if budget == 0 {
+            return;
+        }
+        let bufs = self.wtr_buf_size() + self.rdr_buf_size();
+        let caches = budget.saturating_sub(bufs).max(Self::MIN_CACHE_MEM);
+        self.segdata_cache.set_capacity(caches / 2);
+        let vol = self.vol.read().unwrap();
+        vol.set_cache_mem(caches / 4, caches / 16, caches * 3 / 16);
+    }
+
     #[inline]
     pub fn get_vol_weak(&self) -> VolumeWeakRef {
         Arc::downgrade(&self.vol)
@@ -121,6 +199,13 @@ impl Store {
         self.seg_cache.get(seg_id, &self.vol)
     }
 
+    /// Get segment data length and used length, in bytes
+    pub fn seg_usage(&self, seg_id: &Eid) -> Result<(usize, usize)> {
+        let seg_ref = self.get_seg(seg_id)?;
+        let seg = seg_ref.read().unwrap();
+        Ok((seg.len(), seg.used()))
+    }
+
     // inject intermediate segment to segment cache
     #[inline]
     pub fn inject_seg_to_cache(&self, seg: &SegRef) {
@@ -128,8 +213,29 @@ impl Store {
     }
 
     #[inline]
-    pub fn get_segdata(&self, segdata_id: &Eid) -> Result<SegDataRef> {
-        self.segdata_cache.get(segdata_id, &self.vol)
+    pub fn get_segdata(&self, seg: &Segment, ad: &[u8]) -> Result<SegDataRef> {
+        self.segdata_cache
+            .get(seg.data_id(), &self.vol, ad, seg.compress())
+    }
+
+    // read segment data at offset without loading the whole segment data
+    // if it is not cached
+    #[inline]
+    pub fn read_segdata_at(
+        &self,
+        seg: &Segment,
+        ad: &[u8],
+        dst: &mut [u8],
+        offset: usize,
+    ) -> Result<()> {
+        self.segdata_cache.read_at(
+            seg.data_id(),
+            &self.vol,
+            ad,
+            seg.compress(),
+            dst,
+            offset,
+        )
     }
 
     #[inline]
@@ -152,8 +258,10 @@ impl Store {
     ) -> Result<(bool, Eid)> {
         let mut store = store.write().unwrap();
 
+        // new content is cached, so it can be loaded before it is committed
         if !store.dedup_file {
             let ctn = content.clone().into_cow(&store.txmgr)?;
+            store.content_cache.insert(&ctn);
             let ctn = ctn.read().unwrap();
             return Ok((true, ctn.id().clone()));
         }
@@ -170,6 +278,7 @@ impl Store {
         if ent.content_id.is_empty() {
             // no duplication found
             let ctn = content.clone().into_cow(&txmgr)?;
+            store.content_cache.insert(&ctn);
             let ctn = ctn.read().unwrap();
             ent.content_id = ctn.id().clone();
             no_dup = true;
@@ -177,6 +286,37 @@ impl Store {
         Ok((no_dup, ent.content_id.clone()))
     }
 
+    /// Increase content reference in store, so the content can be shared
+    /// by another version
+    pub fn share_content(store: &StoreRef, content_id: &Eid) -> Result<()> {
+        let mut store = store.write().unwrap();
+
+        let txmgr = store.txmgr.clone();
+        let store = store.make_mut(&txmgr)?;
+
+        if !store.dedup_file {
+            // content used by one version only is not tracked yet, so its
+            // reference count starts from one
+            let refcnt =
+                store.shared.entry(content_id.clone()).or_insert_with(|| {
+                    let mut refcnt = RefCnt::new();
+                    refcnt.inc_ref().unwrap();
+                    refcnt
+                });
+            refcnt.inc_ref()?;
+            return Ok(());
+        }
+
+        let ctn_ref = store.get_content(content_id)?;
+        let ctn = ctn_ref.read().unwrap();
+        store
+            .content_map
+            .get_mut(ctn.hash())
+            .ok_or(Error::NoContent)
+            .and_then(ContentMapEntry::inc_ref)?;
+        Ok(())
+    }
+
     /// Decrease content reference in store
     ///
     /// If the content is not used anymore, remove and return it.
@@ -186,14 +326,23 @@ impl Store {
     ) -> Result<Option<ContentRef>> {
         let mut store = store.write().unwrap();
 
-        if !store.dedup_file {
-            return Ok(None);
-        }
-
         let txmgr = store.txmgr.clone();
         let store = store.make_mut(&txmgr)?;
 
         let ctn_ref = store.get_content(content_id)?;
+
+        if !store.dedup_file {
+            // without file dedup, content is only shared explicitly and
+            // content not shared can be removed straight away
+            if let Some(refcnt) = store.shared.get_mut(content_id) {
+                if refcnt.dec_ref()? > 0 {
+                    return Ok(None);
+                }
+                store.shared.remove(content_id);
+            }
+            return Ok(Some(ctn_ref));
+        }
+
         {
             let ctn = ctn_ref.read().unwrap();
             let refcnt = store
@@ -208,6 +357,88 @@ impl Store {
         }
         Ok(Some(ctn_ref))
     }
+
+    /// Remove a segment and its segment data, chunk references to the
+    /// segment are not checked
+    pub fn remove_seg(store: &StoreRef, seg_id: &Eid) -> Result<()> {
+        let mut store = store.write().unwrap();
+
+        // add store to transaction, so the removed segment is evicted from
+        // its cache on commit
+        let txmgr = store.txmgr.clone();
+        let store = store.make_mut(&txmgr)?;
+
+        let seg_ref = store.get_seg(seg_id)?;
+        let mut seg_cow = seg_ref.write().unwrap();
+        Segment::remove(&mut seg_cow, &txmgr)
+    }
+
+    /// Get ids and reference counts of all contents tracked in store
+    ///
+    /// Without file dedup, only contents shared by multiple versions are
+    /// tracked.
+    pub fn content_refs(&self) -> Vec<(Eid, u32)> {
+        if self.dedup_file {
+            self.content_map
+                .values()
+                .map(|ent| (ent.content_id.clone(), ent.refcnt.val()))
+                .collect()
+        } else {
+            self.shared
+                .iter()
+                .map(|(id, refcnt)| (id.clone(), refcnt.val()))
+                .collect()
+        }
+    }
+
+    /// Release content references beyond the number of versions using it
+    ///
+    /// If the content is not used by any version, remove and return it.
+    pub fn release_content(
+        store: &StoreRef,
+        content_id: &Eid,
+        used: u32,
+    ) -> Result<Option<ContentRef>> {
+        let mut store = store.write().unwrap();
+
+        let txmgr = store.txmgr.clone();
+        let store = store.make_mut(&txmgr)?;
+
+        if !store.dedup_file {
+            // content used by one version only is not tracked
+            match store.shared.get_mut(content_id) {
+                Some(refcnt) if used > 1 => {
+                    while refcnt.val() > used {
+                        refcnt.dec_ref()?;
+                    }
+                }
+                Some(_) => {
+                    store.shared.remove(content_id);
+                }
+                None => {}
+            }
+        } else {
+            let hash = store
+                .content_map
+                .iter()
+                .find(|(_, ent)| ent.content_id == *content_id)
+                .map(|(hash, _)| hash.clone())
+                .ok_or(Error::NoContent)?;
+            if used > 0 {
+                let ent = store.content_map.get_mut(&hash).unwrap();
+                while ent.refcnt.val() > used {
+                    ent.dec_ref()?;
+                }
+            } else {
+                store.content_map.remove(&hash);
+            }
+        }
+
+        if used > 0 {
+            return Ok(None);
+        }
+        store.get_content(content_id).map(Some)
+    }
 }
 
 impl Debug for Store {
@@ -215,6 +446,7 @@ impl Debug for Store {
         f.debug_struct("Store")
             .field("dedup_file", &self.dedup_file)
             .field("content_map", &self.content_map)
+            .field("shared", &self.shared)
             .finish()
     }
 }
@@ -235,30 +467,117 @@ impl<'de> IntoCow<'de> for Store {}
 pub type StoreRef = CowRef<Store>;
 pub type StoreWeakRef = CowWeakRef<Store>;
 
+// reader or writer buffer size guard, the size is counted in store until
+// the guard is dropped
+#[derive(Debug)]
+pub(super) struct BufGuard {
+    size: usize,
+    total: Arc<AtomicUsize>,
+}
+
+impl BufGuard {
+    fn new(size: usize, total: &Arc<AtomicUsize>) -> Self {
+        total.fetch_add(size, Ordering::Relaxed);
+        BufGuard {
+            size,
+            total: total.clone(),
+        }
+    }
+
+    // change the counted size, return true if it is grown
+    fn resize(&mut self, size: usize) -> bool {
+        if size > self.size {
+            self.total.fetch_add(size - self.size, Ordering::Relaxed);
+        } else {
+            self.total.fetch_sub(self.size - size, Ordering::Relaxed);
+        }
+        let grown = size > self.size;
+        self.size = size;
+        grown
+    }
+}
+
+impl Drop for BufGuard {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
 /// Store Writer
 #[derive(Debug)]
 pub struct Writer {
     inner: Chunker<ContentWriter>,
+    store: StoreWeakRef,
+    guard: BufGuard,
 }
 
 impl Writer {
     pub fn new(
         txid: Txid,
         chk_map: ChunkMap,
+        low_memory: bool,
+        ad: &[u8],
+        compress: Option<bool>,
         txmgr: &TxMgrWeakRef,
         store: &StoreWeakRef,
     ) -> Result<Self> {
-        let (params, vol) = {
+        let (params, vol, total) = {
             let store = store.upgrade().ok_or(Error::RepoClosed)?;
             let store = store.read().unwrap();
-            (store.chunker_params.clone(), Arc::downgrade(&store.vol))
+            (
+                store.chunker_params.clone(),
+                Arc::downgrade(&store.vol),
+                store.wtr_buf_size.clone(),
+            )
         };
-        let ctn_wtr = ContentWriter::new(txid, chk_map, store, txmgr, &vol);
+        let ctn_wtr =
+            ContentWriter::new(txid, chk_map, ad, compress, store, txmgr, &vol)
+                .with_low_memory(low_memory);
+        let inner = if low_memory {
+            Chunker::new_low_memory(params, ctn_wtr)
+        } else {
+            Chunker::new(params, ctn_wtr)
+        };
+
+        // chunker buffer is counted now, stage buffer of segment data is
+        // counted when it is allocated
+        let guard = BufGuard::new(inner.buf_len(), &total);
+        if let Some(store) = store.upgrade() {
+            store.read().unwrap().balance_mem();
+        }
+
         Ok(Writer {
-            inner: Chunker::new(params, ctn_wtr),
+            inner,
+            store: store.clone(),
+            guard,
         })
     }
 
+    // count chunker buffer and stage buffer allocated for segment data,
+    // caches are rebalanced if the buffers are grown
+    fn track_buf(&mut self) {
+        let size = self.inner.buf_len() + self.inner.get_ref().buf_len();
+        if self.guard.resize(size) {
+            if let Some(store) = self.store.upgrade() {
+                store.read().unwrap().balance_mem();
+            }
+        }
+    }
+
+    // flush buffered data in chunker and make it durable
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        self.track_buf();
+        self.inner.get_mut().sync_data()
+    }
+
+    // number of bytes passed through chunker and written to volume, data
+    // still buffered in chunker is not included
+    #[inline]
+    pub fn committed_len(&self) -> usize {
+        self.inner.get_ref().committed_len()
+    }
+
     pub fn finish(self) -> Result<(Content, ChunkMap)> {
         let ctn_wtr = self.inner.into_inner()?;
         ctn_wtr.finish()
@@ -266,20 +585,23 @@ impl Writer {
 }
 
 impl Write for Writer {
-    #[inline]
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-        self.inner.write(buf)
+        let written = self.inner.write(buf)?;
+        self.track_buf();
+        Ok(written)
     }
 
-    #[inline]
     fn flush(&mut self) -> IoResult<()> {
-        self.inner.flush()
+        self.inner.flush()?;
+        self.track_buf();
+        Ok(())
     }
 }
 
 impl Seek for Writer {
-    #[inline]
     fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
-        self.inner.seek(pos)
+        let pos = self.inner.seek(pos)?;
+        self.track_buf();
+        Ok(pos)
     }
 }
@@ -13,6 +13,12 @@ use libsqlite3_sys::Error as SqliteError;
 #[cfg(feature = "storage-redis")]
 use redis::RedisError;
 
+#[cfg(feature = "storage-postgres")]
+use postgres::Error as PostgresError;
+
+#[cfg(any(feature = "storage-redis", feature = "storage-postgres"))]
+use r2d2::Error as PoolError;
+
 #[cfg(feature = "storage-zbox")]
 use http::{Error as HttpError, StatusCode};
 
@@ -27,6 +33,13 @@ use jni::errors::Error as JniError;
 
 /// The error type for operations with [`Repo`] and [`File`].
 ///
+/// Errors from underlying IO and storage backends are wrapped and returned
+/// by [`source`], so the whole error chain can be walked. The IO errors
+/// returned by [`File`]'s `Read`, `Write` and `Seek` implementations wrap
+/// this error, which can be retrieved by `get_ref` and `downcast_ref`, or
+/// by converting the IO error back using `From`.
+///
+/// [`source`]: https://doc.rust-lang.org/std/error/trait.Error.html#method.source
 /// [`Repo`]: struct.Repo.html
 /// [`File`]: struct.File.html
 #[derive(Debug)]
@@ -41,8 +54,14 @@ pub enum Error {
     InvalidCipher,
     Encrypt,
     Decrypt,
-
-    InvalidUri,
+    WeakerCost,
+    MissingKeyFactor,
+
+    InvalidUri {
+        uri: String, // credentials are masked
+        reason: String,
+    },
+    UnsupportedBackend(String),
     InvalidSuperBlk,
     Corrupted,
     WrongVersion,
@@ -59,6 +78,7 @@ pub enum Error {
     InUse,
 
     NoContent,
+    UnsupportedCompression(String),
 
     InvalidArgument,
     InvalidPath,
@@ -71,6 +91,7 @@ pub enum Error {
     NotFile,
     NotEmpty,
     NoVersion,
+    TooManySymlinks,
 
     ReadOnly,
     CannotRead,
@@ -78,6 +99,11 @@ pub enum Error {
     NotWrite,
     NotFinish,
     Closed,
+    AppendOnly,
+    WouldBlock,
+    FileTooLarge,
+
+    NoSpace,
 
     Encode(EncodeError),
     Decode(DecodeError),
@@ -89,11 +115,21 @@ pub enum Error {
 
     #[cfg(feature = "storage-redis")]
     Redis(RedisError),
+    #[cfg(feature = "storage-redis")]
+    RedisPool(PoolError),
+
+    #[cfg(feature = "storage-postgres")]
+    Postgres(PostgresError),
+    #[cfg(feature = "storage-postgres")]
+    PostgresPool(PoolError),
 
     #[cfg(feature = "storage-zbox")]
     Http(HttpError),
     #[cfg(feature = "storage-zbox")]
-    HttpStatus(StatusCode),
+    HttpStatus {
+        status: StatusCode,
+        message: String, // response body, truncated
+    },
     #[cfg(feature = "storage-zbox")]
     Json(JsonError),
 
@@ -107,6 +143,26 @@ pub enum Error {
     RequestError,
 }
 
+// storage backends which can be enabled by Cargo features
+const KNOWN_BACKENDS: [&str; 8] = [
+    "mem", "file", "sqlite", "redis", "rediss", "postgres", "faulty", "zbox",
+];
+
+impl Error {
+    /// Create an invalid uri error, credentials in uri are masked
+    pub(crate) fn invalid_uri(uri: &str, reason: &str) -> Error {
+        let mut uri = uri.to_owned();
+        if let Some(end) = uri.find('@') {
+            let begin = uri[..end].find("://").map_or(0, |idx| idx + 3);
+            uri.replace_range(begin..end, "***");
+        }
+        Error::InvalidUri {
+            uri,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
@@ -120,8 +176,29 @@ impl Display for Error {
             Error::InvalidCipher => write!(f, "Invalid cipher"),
             Error::Encrypt => write!(f, "Encrypt error"),
             Error::Decrypt => write!(f, "Decrypt error"),
-
-            Error::InvalidUri => write!(f, "Invalid Uri"),
+            Error::WeakerCost => write!(f, "Cost is weaker than current"),
+            Error::MissingKeyFactor => write!(f, "Missing key factor"),
+
+            Error::InvalidUri {
+                ref uri,
+                ref reason,
+            } => write!(f, "Invalid Uri '{}': {}", uri, reason),
+            Error::UnsupportedBackend(ref scheme) => {
+                if KNOWN_BACKENDS.contains(&scheme.as_str()) {
+                    let feature = match scheme.as_str() {
+                        "rediss" => "redis-tls",
+                        scheme => scheme,
+                    };
+                    write!(
+                        f,
+                        "Storage backend '{}' is not enabled, enable Cargo \
+                         feature 'storage-{}' to use it",
+                        scheme, feature
+                    )
+                } else {
+                    write!(f, "Storage backend '{}' is not supported", scheme)
+                }
+            }
             Error::InvalidSuperBlk => write!(f, "Invalid super block"),
             Error::Corrupted => write!(f, "Volume is corrupted"),
             Error::WrongVersion => write!(f, "Version not match"),
@@ -138,6 +215,9 @@ impl Display for Error {
             Error::InUse => write!(f, "Entity is in use"),
 
             Error::NoContent => write!(f, "Content not found"),
+            Error::UnsupportedCompression(ref algo) => {
+                write!(f, "Compression '{}' is not supported", algo)
+            }
 
             Error::InvalidArgument => write!(f, "Invalid argument"),
             Error::InvalidPath => write!(f, "Invalid path"),
@@ -150,6 +230,9 @@ impl Display for Error {
             Error::NotFile => write!(f, "Path is not file"),
             Error::NotEmpty => write!(f, "Directory is not empty"),
             Error::NoVersion => write!(f, "File has no version"),
+            Error::TooManySymlinks => {
+                write!(f, "Too many levels of symbolic links")
+            }
 
             Error::ReadOnly => write!(f, "Opened as read only"),
             Error::CannotRead => write!(f, "Cannot read file"),
@@ -157,6 +240,11 @@ impl Display for Error {
             Error::NotWrite => write!(f, "File does not write yet"),
             Error::NotFinish => write!(f, "File does not finish yet"),
             Error::Closed => write!(f, "File is closed"),
+            Error::AppendOnly => write!(f, "File is append-only"),
+            Error::WouldBlock => write!(f, "File is locked"),
+            Error::FileTooLarge => write!(f, "File is too large"),
+
+            Error::NoSpace => write!(f, "No space left in storage"),
 
             Error::Encode(ref err) => err.fmt(f),
             Error::Decode(ref err) => err.fmt(f),
@@ -168,12 +256,26 @@ impl Display for Error {
 
             #[cfg(feature = "storage-redis")]
             Error::Redis(ref err) => err.fmt(f),
+            #[cfg(feature = "storage-redis")]
+            Error::RedisPool(ref err) => err.fmt(f),
+
+            #[cfg(feature = "storage-postgres")]
+            Error::Postgres(ref err) => err.fmt(f),
+            #[cfg(feature = "storage-postgres")]
+            Error::PostgresPool(ref err) => err.fmt(f),
 
             #[cfg(feature = "storage-zbox")]
             Error::Http(ref err) => err.fmt(f),
             #[cfg(feature = "storage-zbox")]
-            Error::HttpStatus(status_code) => {
-                write!(f, "Http status {}", status_code)
+            Error::HttpStatus {
+                status,
+                ref message,
+            } => {
+                if message.is_empty() {
+                    write!(f, "Http status {}", status)
+                } else {
+                    write!(f, "Http status {}: {}", status, message)
+                }
             }
             #[cfg(feature = "storage-zbox")]
             Error::Json(ref err) => err.fmt(f),
@@ -203,6 +305,13 @@ impl StdError for Error {
 
             #[cfg(feature = "storage-redis")]
             Error::Redis(ref err) => Some(err),
+            #[cfg(feature = "storage-redis")]
+            Error::RedisPool(ref err) => Some(err),
+
+            #[cfg(feature = "storage-postgres")]
+            Error::Postgres(ref err) => Some(err),
+            #[cfg(feature = "storage-postgres")]
+            Error::PostgresPool(ref err) => Some(err),
 
             #[cfg(feature = "storage-zbox")]
             Error::Http(ref err) => Some(err),
@@ -240,6 +349,11 @@ impl From<VarError> for Error {
 
 impl From<IoError> for Error {
     fn from(err: IoError) -> Error {
+        // unwrap the original error if it was converted to IO error
+        if matches!(err.get_ref(), Some(inner) if inner.is::<Error>()) {
+            let inner = err.into_inner().unwrap();
+            return *inner.downcast::<Error>().unwrap();
+        }
         Error::Io(err)
     }
 }
@@ -258,6 +372,20 @@ impl From<RedisError> for Error {
     }
 }
 
+#[cfg(feature = "storage-postgres")]
+impl From<PostgresError> for Error {
+    fn from(err: PostgresError) -> Error {
+        Error::Postgres(err)
+    }
+}
+
+#[cfg(feature = "storage-postgres")]
+impl From<PoolError> for Error {
+    fn from(err: PoolError) -> Error {
+        Error::PostgresPool(err)
+    }
+}
+
 #[cfg(feature = "storage-zbox")]
 impl From<HttpError> for Error {
     fn from(err: HttpError) -> Error {
@@ -265,6 +393,17 @@ impl From<HttpError> for Error {
     }
 }
 
+#[cfg(feature = "storage-zbox")]
+impl From<StatusCode> for Error {
+    #[inline]
+    fn from(status: StatusCode) -> Error {
+        Error::HttpStatus {
+            status,
+            message: String::new(),
+        }
+    }
+}
+
 #[cfg(feature = "storage-zbox")]
 impl From<JsonError> for Error {
     fn from(err: JsonError) -> Error {
@@ -299,8 +438,10 @@ impl From<Error> for i32 {
             Error::InvalidCipher => -1014,
             Error::Encrypt => -1015,
             Error::Decrypt => -1016,
+            Error::WeakerCost => -1017,
+            Error::MissingKeyFactor => -1018,
 
-            Error::InvalidUri => -1020,
+            Error::InvalidUri { .. } => -1020,
             Error::InvalidSuperBlk => -1021,
             Error::Corrupted => -1022,
             Error::WrongVersion => -1023,
@@ -309,6 +450,7 @@ impl From<Error> for i32 {
             Error::RepoOpened => -1026,
             Error::RepoClosed => -1027,
             Error::RepoExists => -1028,
+            Error::UnsupportedBackend(_) => -1029,
 
             Error::InTrans => -1030,
             Error::NotInTrans => -1031,
@@ -317,6 +459,7 @@ impl From<Error> for i32 {
             Error::InUse => -1034,
 
             Error::NoContent => -1040,
+            Error::UnsupportedCompression(_) => -1041,
 
             Error::InvalidArgument => -1050,
             Error::InvalidPath => -1051,
@@ -329,6 +472,7 @@ impl From<Error> for i32 {
             Error::NotFile => -1058,
             Error::NotEmpty => -1059,
             Error::NoVersion => -1060,
+            Error::TooManySymlinks => -1061,
 
             Error::ReadOnly => -1070,
             Error::CannotRead => -1071,
@@ -336,6 +480,11 @@ impl From<Error> for i32 {
             Error::NotWrite => -1073,
             Error::NotFinish => -1074,
             Error::Closed => -1075,
+            Error::AppendOnly => -1076,
+            Error::WouldBlock => -1077,
+            Error::FileTooLarge => -1078,
+
+            Error::NoSpace => -1080,
 
             Error::Encode(_) => -2000,
             Error::Decode(_) => -2010,
@@ -347,11 +496,18 @@ impl From<Error> for i32 {
 
             #[cfg(feature = "storage-redis")]
             Error::Redis(_) => -2050,
+            #[cfg(feature = "storage-redis")]
+            Error::RedisPool(_) => -2051,
+
+            #[cfg(feature = "storage-postgres")]
+            Error::Postgres(_) => -2070,
+            #[cfg(feature = "storage-postgres")]
+            Error::PostgresPool(_) => -2071,
 
             #[cfg(feature = "storage-zbox")]
             Error::Http(_) => -2060,
             #[cfg(feature = "storage-zbox")]
-            Error::HttpStatus(_) => -2061,
+            Error::HttpStatus { .. } => -2061,
             #[cfg(feature = "storage-zbox")]
             Error::Json(_) => -2062,
 
@@ -380,8 +536,13 @@ impl PartialEq for Error {
             (&Error::InvalidCipher, &Error::InvalidCipher) => true,
             (&Error::Encrypt, &Error::Encrypt) => true,
             (&Error::Decrypt, &Error::Decrypt) => true,
+            (&Error::WeakerCost, &Error::WeakerCost) => true,
+            (&Error::MissingKeyFactor, &Error::MissingKeyFactor) => true,
 
-            (&Error::InvalidUri, &Error::InvalidUri) => true,
+            (&Error::InvalidUri { .. }, &Error::InvalidUri { .. }) => true,
+            (Error::UnsupportedBackend(a), Error::UnsupportedBackend(b)) => {
+                a == b
+            }
             (&Error::InvalidSuperBlk, &Error::InvalidSuperBlk) => true,
             (&Error::Corrupted, &Error::Corrupted) => true,
             (&Error::WrongVersion, &Error::WrongVersion) => true,
@@ -398,6 +559,10 @@ impl PartialEq for Error {
             (&Error::InUse, &Error::InUse) => true,
 
             (&Error::NoContent, &Error::NoContent) => true,
+            (
+                Error::UnsupportedCompression(a),
+                Error::UnsupportedCompression(b),
+            ) => a == b,
 
             (&Error::InvalidArgument, &Error::InvalidArgument) => true,
             (&Error::InvalidPath, &Error::InvalidPath) => true,
@@ -410,6 +575,7 @@ impl PartialEq for Error {
             (&Error::NotFile, &Error::NotFile) => true,
             (&Error::NotEmpty, &Error::NotEmpty) => true,
             (&Error::NoVersion, &Error::NoVersion) => true,
+            (&Error::TooManySymlinks, &Error::TooManySymlinks) => true,
 
             (&Error::ReadOnly, &Error::ReadOnly) => true,
             (&Error::CannotRead, &Error::CannotRead) => true,
@@ -417,6 +583,11 @@ impl PartialEq for Error {
             (&Error::NotWrite, &Error::NotWrite) => true,
             (&Error::NotFinish, &Error::NotFinish) => true,
             (&Error::Closed, &Error::Closed) => true,
+            (&Error::AppendOnly, &Error::AppendOnly) => true,
+            (&Error::WouldBlock, &Error::WouldBlock) => true,
+            (&Error::FileTooLarge, &Error::FileTooLarge) => true,
+
+            (&Error::NoSpace, &Error::NoSpace) => true,
 
             (&Error::Encode(_), &Error::Encode(_)) => true,
             (&Error::Decode(_), &Error::Decode(_)) => true,
@@ -430,9 +601,25 @@ impl PartialEq for Error {
             (&Error::Redis(ref a), &Error::Redis(ref b)) => {
                 a.kind() == b.kind()
             }
+            #[cfg(feature = "storage-redis")]
+            (&Error::RedisPool(ref a), &Error::RedisPool(ref b)) => {
+                a.to_string() == b.to_string()
+            }
+
+            #[cfg(feature = "storage-postgres")]
+            (&Error::Postgres(ref a), &Error::Postgres(ref b)) => {
+                a.code() == b.code()
+            }
+            #[cfg(feature = "storage-postgres")]
+            (&Error::PostgresPool(ref a), &Error::PostgresPool(ref b)) => {
+                a.to_string() == b.to_string()
+            }
 
             #[cfg(feature = "storage-zbox")]
-            (&Error::HttpStatus(a), &Error::HttpStatus(b)) => a == b,
+            (
+                &Error::HttpStatus { status: a, .. },
+                &Error::HttpStatus { status: b, .. },
+            ) => a == b,
 
             #[cfg(feature = "storage-zbox-native")]
             (&Error::Reqwest(ref a), &Error::Reqwest(ref b)) => {
@@ -1,20 +1,71 @@
+use std::cmp::min;
 use std::fmt::{self, Debug};
-use std::io::{self, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{
+    self, BufRead, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write,
+};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use super::{Error, Result};
+use crate::base::crypto::{Crypto, HASH_SIZE};
+use crate::base::Time;
+use crate::content::ContentRawReader;
 use crate::fs::fnode::{
     Fnode, Metadata, Reader as FnodeReader, Version, Writer as FnodeWriter,
 };
-use crate::fs::Handle;
-use crate::trans::{TxHandle, TxMgr};
+use crate::fs::{Handle, LockKind, RepoEvent, MAX_CACHED_LEN};
+use crate::trans::{Id, TxHandle, TxMgr, Txid};
+
+// stream content from reader through hash function until end
+fn hash_content<R: Read>(
+    rdr: &mut R,
+    buf_size: usize,
+) -> Result<[u8; HASH_SIZE]> {
+    let mut state = Crypto::hash_init();
+    let mut buf = vec![0u8; buf_size];
+    loop {
+        match rdr.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => Crypto::hash_update(&mut state, &buf[..read]),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(Error::from(err)),
+        }
+    }
+    let hash = Crypto::hash_final(&mut state);
+    let mut ret = [0u8; HASH_SIZE];
+    ret.copy_from_slice(&hash);
+    Ok(ret)
+}
+
+// get read buffer size of a file handle
+#[inline]
+fn read_buf_size(handle: &Handle) -> usize {
+    if handle.low_memory {
+        handle.blk_size
+    } else {
+        handle.frame_size
+    }
+}
+
+// convert byte range to in-memory range, start cannot be greater than end
+fn mem_range(range: Range<u64>) -> Result<Range<usize>> {
+    if range.start > range.end {
+        return Err(Error::InvalidArgument);
+    }
+    let max = usize::MAX as u64;
+    Ok(min(range.start, max) as usize..min(range.end, max) as usize)
+}
 
 /// A reader for a specific vesion of file content.
 ///
 /// This reader can be obtained by [`version_reader`] method, and it
-/// implements [`Read`] trait.
+/// implements [`Read`] and [`BufRead`] traits.
 ///
 /// [`version_reader`]: struct.File.html#method.version_reader
 /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
 #[derive(Debug)]
 pub struct VersionReader {
     handle: Handle,
@@ -22,8 +73,14 @@ pub struct VersionReader {
 }
 
 impl VersionReader {
-    fn new(handle: &Handle, ver: usize) -> Result<Self> {
-        let rdr = FnodeReader::new(handle.fnode.clone(), ver, &handle.store)?;
+    pub(super) fn new(handle: &Handle, ver: usize) -> Result<Self> {
+        let rdr = FnodeReader::new(
+            handle.fnode.clone(),
+            ver,
+            &handle.aad,
+            read_buf_size(handle),
+            &handle.store,
+        )?;
         Ok(VersionReader {
             handle: handle.clone(),
             rdr,
@@ -32,11 +89,42 @@ impl VersionReader {
 
     /// Returns the content version associated with this reader.
     pub fn version(&self) -> Result<Version> {
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
         let fnode = self.handle.fnode.read().unwrap();
-        fnode
-            .ver(self.rdr.version_num())
-            .cloned()
-            .ok_or(Error::NoVersion)
+        fnode.version(self.rdr.version_num(), &store)
+    }
+
+    /// Returns BLAKE2b hash of the whole content of this version.
+    ///
+    /// The content is streamed through the hash function from the beginning,
+    /// the reader position is restored afterwards.
+    pub fn content_hash(&mut self) -> Result<[u8; 32]> {
+        let pos = self.rdr.stream_position()?;
+        self.rdr.seek(SeekFrom::Start(0))?;
+        let result = hash_content(&mut self.rdr, read_buf_size(&self.handle));
+        self.rdr.seek(SeekFrom::Start(pos))?;
+        result
+    }
+
+    /// Reads a byte range of this version and appends it to `buf`.
+    ///
+    /// Returns the number of bytes read, the range is truncated at the end
+    /// of content. Only the data overlapping the range is read and
+    /// decrypted, so it is cheaper than seeking and reading for a small
+    /// range of big content. The reader position is not changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if the range start is greater than
+    /// its end.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn read_range(
+        &mut self,
+        range: Range<u64>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        self.rdr.read_range(mem_range(range)?, buf)
     }
 }
 
@@ -47,6 +135,18 @@ impl Read for VersionReader {
     }
 }
 
+impl BufRead for VersionReader {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.rdr.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.rdr.consume(amt)
+    }
+}
+
 impl Seek for VersionReader {
     #[inline]
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
@@ -54,6 +154,132 @@ impl Seek for VersionReader {
     }
 }
 
+/// A reader for a file in a repository snapshot.
+///
+/// This reader can be obtained by [`SnapshotRepo::open_file_shared`], it
+/// reads the file content as it was when the snapshot was taken.
+///
+/// [`SnapshotRepo::open_file_shared`]: struct.SnapshotRepo.html#method.open_file_shared
+#[derive(Debug)]
+pub struct SnapshotFile {
+    meta: Metadata,
+    rdr: FnodeReader,
+}
+
+impl SnapshotFile {
+    #[inline]
+    pub(super) fn new(meta: Metadata, rdr: FnodeReader) -> Self {
+        SnapshotFile { meta, rdr }
+    }
+
+    /// Returns the metadata of this file when the snapshot was taken.
+    #[inline]
+    pub fn metadata(&self) -> Metadata {
+        self.meta
+    }
+
+    /// Reads a byte range of this file and appends it to `buf`.
+    ///
+    /// This is same as [`VersionReader::read_range`].
+    ///
+    /// [`VersionReader::read_range`]: struct.VersionReader.html#method.read_range
+    pub fn read_range(
+        &mut self,
+        range: Range<u64>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        self.rdr.read_range(mem_range(range)?, buf)
+    }
+}
+
+impl Read for SnapshotFile {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rdr.read(buf)
+    }
+}
+
+impl Seek for SnapshotFile {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.rdr.seek(pos)
+    }
+}
+
+/// An iterator over the lines of a [`File`].
+///
+/// This iterator is created by [`File::lines`]. Each line is returned without
+/// the trailing newline, which is either `\n` or `\r\n`. A line which is not
+/// valid UTF-8 is returned as an error of kind [`InvalidData`].
+///
+/// [`File`]: struct.File.html
+/// [`File::lines`]: struct.File.html#method.lines
+/// [`InvalidData`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidData
+#[derive(Debug)]
+pub struct Lines {
+    rdr: File,
+}
+
+impl Iterator for Lines {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        let mut buf = Vec::new();
+        match self.rdr.read_until(b'\n', &mut buf) {
+            Ok(0) => None,
+            Ok(_) => {
+                if buf.ends_with(b"\n") {
+                    buf.pop();
+                    if buf.ends_with(b"\r") {
+                        buf.pop();
+                    }
+                }
+                Some(String::from_utf8(buf).map_err(|err| {
+                    Error::from(IoError::new(ErrorKind::InvalidData, err))
+                }))
+            }
+            Err(err) => Some(Err(Error::from(err))),
+        }
+    }
+}
+
+/// A reader for the raw stored content of a file.
+///
+/// This reader can be obtained by [`read_raw`] method, and it implements
+/// [`Read`] trait. It yields a stream which contains the content data as it
+/// is stored in the repository, that is, decrypted but still compressed if
+/// compression is enabled, together with the metadata needed to store it
+/// again unchanged. The stream can be imported to a file by [`write_raw`].
+///
+/// [`read_raw`]: struct.File.html#method.read_raw
+/// [`write_raw`]: struct.File.html#method.write_raw
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+#[derive(Debug)]
+pub struct RawContentReader {
+    rdr: ContentRawReader,
+}
+
+impl RawContentReader {
+    /// Returns whether the content data in this stream is compressed.
+    #[inline]
+    pub fn is_compressed(&self) -> bool {
+        self.rdr.is_compressed()
+    }
+
+    /// Returns the byte size of the exported content.
+    #[inline]
+    pub fn content_len(&self) -> usize {
+        self.rdr.content_len()
+    }
+}
+
+impl Read for RawContentReader {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.rdr.read(buf)
+    }
+}
+
 /// A reference to an opened file in the repository.
 ///
 /// An instance of a `File` can be read and/or written depending on what options
@@ -62,8 +288,9 @@ impl Seek for VersionReader {
 ///
 /// Files are automatically closed when they go out of scope.
 ///
-/// As ZboxFS internally cached file content, it is no need to use buffered
-/// reader, such as [`BufReader<R>`].
+/// File content is read through an internal frame-sized buffer and `File`
+/// implements [`BufRead`], so it is no need to use buffered reader, such as
+/// [`BufReader<R>`].
 ///
 /// # Examples
 ///
@@ -307,6 +534,7 @@ impl Seek for VersionReader {
 /// ```
 ///
 /// [`Seek`]: https://doc.rust-lang.org/std/io/trait.Seek.html
+/// [`BufRead`]: https://doc.rust-lang.org/std/io/trait.BufRead.html
 /// [`BufReader<R>`]: https://doc.rust-lang.org/std/io/struct.BufReader.html
 /// [`flush`]: https://doc.rust-lang.org/std/io/trait.Write.html#tymethod.flush
 /// [`String`]: https://doc.rust-lang.org/std/string/struct.String.html
@@ -322,9 +550,15 @@ pub struct File {
     pos: SeekFrom, // must always be SeekFrom::Start
     rdr: Option<FnodeReader>,
     wtr: Option<FnodeWriter>,
+    wtr_at: Option<u64>, // next offset of positional write, see write_at
     tx_handle: Option<TxHandle>,
     can_read: bool,
     can_write: bool,
+    append: bool, // always write at EOF, see OpenOptions::append
+    lock: Option<LockKind>, // advisory lock held by this file
+    auto_sync: usize, // sync interval in bytes, 0 means disabled
+    unsynced: usize, // bytes written since last sync
+    writer: Option<Arc<PathBuf>>, // tracked by fs while file can write
 }
 
 impl File {
@@ -339,10 +573,62 @@ impl File {
             pos,
             rdr: None,
             wtr: None,
+            wtr_at: None,
             tx_handle: None,
             can_read,
             can_write,
+            append: false,
+            lock: None,
+            auto_sync: 0,
+            unsynced: 0,
+            writer: None,
+        }
+    }
+
+    #[inline]
+    pub(super) fn set_append(&mut self, append: bool) {
+        self.append = append;
+    }
+
+    #[inline]
+    pub(super) fn set_writer(&mut self, writer: Arc<PathBuf>) {
+        self.writer = Some(writer);
+    }
+
+    #[inline]
+    pub(super) fn set_auto_sync(&mut self, auto_sync: usize) {
+        self.auto_sync = auto_sync;
+    }
+
+    // set whether written content replaces the whole content instead of
+    // overwriting it from the beginning
+    #[inline]
+    pub(super) fn set_replace(&mut self, replace: bool) {
+        self.handle.replace = replace;
+    }
+
+    // count written bytes and sync data once auto sync interval is reached
+    fn auto_sync(&mut self, written: usize) -> Result<()> {
+        self.unsynced += written;
+        if self.auto_sync > 0 && self.unsynced >= self.auto_sync {
+            self.sync_data()?;
         }
+        Ok(())
+    }
+
+    /// Run a function on this file with its transaction bound to current
+    /// thread, so a multi-part write can continue on a different thread
+    #[cfg(feature = "async")]
+    pub(crate) fn run_in_trans<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut File) -> R,
+    {
+        let txid = self
+            .tx_handle
+            .as_ref()
+            .map(|tx_handle| tx_handle.txid)
+            .unwrap_or_default();
+        Txid::run_as(txid, || f(self))
     }
 
     /// Check if file system is closed
@@ -354,6 +640,19 @@ impl File {
         Ok(())
     }
 
+    // check if file can be written, writes of open files are rejected after
+    // repo is set to read-only with rejecting open handles
+    fn check_writable(&self) -> Result<()> {
+        if !self.can_write {
+            return Err(Error::CannotWrite);
+        }
+        let shutter = self.handle.shutter.read().unwrap();
+        if shutter.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
     /// Queries metadata about the file.
     pub fn metadata(&self) -> Result<Metadata> {
         self.check_closed()?;
@@ -361,11 +660,49 @@ impl File {
         Ok(fnode.metadata())
     }
 
+    /// Returns the current path of the file.
+    ///
+    /// The path is resolved from the file's location in the repository, so
+    /// it reflects any [`rename`] of the file or its ancestor directories
+    /// since the file was opened.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFound`] if the file or any of its ancestor
+    /// directories has been removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::path::Path;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    /// let file = repo.create_file("/dir/foo.txt")?;
+    /// repo.rename("/dir", "/dir2")?;
+    /// assert_eq!(file.path()?, Path::new("/dir2/foo.txt"));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`rename`]: struct.Repo.html#method.rename
+    /// [`Error::NotFound`]: enum.Error.html
+    pub fn path(&self) -> Result<PathBuf> {
+        self.check_closed()?;
+        Fnode::path(&self.handle.fnode)
+    }
+
     /// Returns a list of all the file content versions.
     pub fn history(&self) -> Result<Vec<Version>> {
         self.check_closed()?;
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
         let fnode = self.handle.fnode.read().unwrap();
-        Ok(fnode.history())
+        fnode.versions(&store)
     }
 
     /// Returns the current content version number.
@@ -397,6 +734,207 @@ impl File {
         VersionReader::new(&self.handle, ver_num)
     }
 
+    /// Returns an iterator over readers of all retained versions.
+    ///
+    /// The iterator yields each version in [`history`] together with a fresh
+    /// reader of it, from the oldest to the newest. Readers are created
+    /// lazily, so a version removed after this call will yield an error.
+    ///
+    /// [`history`]: struct.File.html#method.history
+    pub fn version_readers(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Version, VersionReader)>>> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+        let history = self.history()?;
+        let handle = self.handle.clone();
+        Ok(history.into_iter().map(move |ver| {
+            VersionReader::new(&handle, ver.num()).map(|rdr| (ver, rdr))
+        }))
+    }
+
+    /// Get a raw content reader of the current version.
+    ///
+    /// The returned reader yields the stored content without decompressing
+    /// it. It is useful for backup or replication, as the stream can be
+    /// re-stored verbatim to another repository by [`write_raw`], without
+    /// decompressing and compressing it again.
+    ///
+    /// Only data referenced by the current version is included in the
+    /// stream. Storage shared with other versions or files is exported in
+    /// part and uncompressed, so such stream can be larger than the stored
+    /// content.
+    ///
+    /// Content stored inline has no raw content, [`Error::NoContent`] will be
+    /// returned for it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// use std::io::Read;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo bar")?;
+    ///
+    /// let mut raw = Vec::new();
+    /// file.read_raw()?.read_to_end(&mut raw)?;
+    ///
+    /// let mut copy = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/copy.txt")?;
+    /// copy.write_raw(&raw[..])?;
+    ///
+    /// let mut content = String::new();
+    /// repo.open_file("/copy.txt")?.read_to_string(&mut content)?;
+    /// assert_eq!(content, "foo bar");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`write_raw`]: struct.File.html#method.write_raw
+    /// [`Error::NoContent`]: enum.Error.html
+    pub fn read_raw(&self) -> Result<RawContentReader> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+        let fnode = self.handle.fnode.read().unwrap();
+        let rdr = fnode.raw_reader(&self.handle.aad, &self.handle.store)?;
+        Ok(RawContentReader { rdr })
+    }
+
+    /// Returns BLAKE2b hash of the current version content.
+    ///
+    /// The content is streamed through the hash function without being read
+    /// into memory as a whole, the file position is not changed. Use
+    /// [`VersionReader::content_hash`] to get hash of other versions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo bar")?;
+    ///
+    /// let hash = file.content_hash()?;
+    /// let curr_ver = file.curr_version()?;
+    /// assert_eq!(file.version_reader(curr_ver)?.content_hash()?, hash);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`VersionReader::content_hash`]: struct.VersionReader.html#method.content_hash
+    pub fn content_hash(&mut self) -> Result<[u8; 32]> {
+        let curr_ver = self.curr_version()?;
+        self.version_reader(curr_ver)?.content_hash()
+    }
+
+    /// Reads the whole content of the current version into a shared buffer.
+    ///
+    /// The content is decrypted once and cached in the repository, later
+    /// calls on any handle of this file return the same buffer without
+    /// reading it again until the file gets a new version. It is useful for
+    /// small and frequently read files, such as configurations. Content
+    /// longer than 1 MiB is read but not cached.
+    ///
+    /// The file position is not changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// use std::sync::Arc;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo bar")?;
+    ///
+    /// let content = file.read_all_cached()?;
+    /// assert_eq!(&content[..], b"foo bar");
+    /// assert!(Arc::ptr_eq(&content, &file.read_all_cached()?));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn read_all_cached(&mut self) -> Result<Arc<[u8]>> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        let (id, curr_ver) = {
+            let fnode = self.handle.fnode.read().unwrap();
+            (fnode.id().clone(), fnode.curr_ver_num())
+        };
+        if let Some(content) = self.handle.contents.get(&id, curr_ver) {
+            return Ok(content);
+        }
+
+        let mut buf = Vec::new();
+        self.version_reader(curr_ver)?.read_to_end(&mut buf)?;
+        let content: Arc<[u8]> = Arc::from(buf);
+        if content.len() <= MAX_CACHED_LEN {
+            self.handle.contents.insert(&id, curr_ver, content.clone());
+        }
+        Ok(content)
+    }
+
+    /// Returns an iterator over the lines of this file, starting from the
+    /// current position.
+    ///
+    /// The content is read in frame-sized buffers and split on newlines. See
+    /// [`Lines`] for more details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// use std::io::{Seek, SeekFrom};
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo\nbar\n")?;
+    /// file.seek(SeekFrom::Start(0))?;
+    ///
+    /// let lines = file.lines().collect::<Result<Vec<String>>>()?;
+    /// assert_eq!(lines, vec!["foo", "bar"]);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Lines`]: struct.Lines.html
+    pub fn lines(self) -> Lines {
+        Lines { rdr: self }
+    }
+
     // calculate the seek position from the start based on file current size
     fn seek_pos(&self, pos: SeekFrom) -> SeekFrom {
         let curr_len = self.curr_len();
@@ -413,9 +951,7 @@ impl File {
     }
 
     fn begin_write(&mut self) -> Result<()> {
-        if !self.can_write {
-            return Err(Error::CannotWrite);
-        }
+        self.check_writable()?;
 
         if self.wtr.is_some() {
             return Err(Error::NotFinish);
@@ -428,6 +964,15 @@ impl File {
         match self.pos {
             SeekFrom::Start(pos) => {
                 let pos = pos as usize;
+
+                // append-only file can only be written at its end
+                if pos < curr_len {
+                    let fnode = self.handle.fnode.read().unwrap();
+                    if fnode.is_append_only() {
+                        return Err(Error::AppendOnly);
+                    }
+                }
+
                 if pos > curr_len {
                     // append zeros by setting file length
                     self.set_len(pos)?;
@@ -439,25 +984,49 @@ impl File {
             _ => unreachable!(),
         }
 
-        // begin write
+        // begin write, the transaction is not bound to current thread
+        // between writes, so the thread can still run other transactions
+        // before this write is finished. If current thread is in a joinable
+        // transaction, the write joins it instead.
         let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
-        let tx_handle = TxMgr::begin_trans(&txmgr)?;
-        tx_handle.run(|| {
-            let mut wtr =
-                FnodeWriter::new(self.handle.clone(), tx_handle.txid)?;
-            wtr.seek(self.seek_pos(self.pos))?;
-            self.wtr = Some(wtr);
-            Ok(())
+        let txid = TxMgr::join_trans(&txmgr)
+            .map(|tx_handle| tx_handle.txid)
+            .unwrap_or_default();
+        let tx_handle = Txid::run_as(txid, || {
+            let tx_handle = TxMgr::begin_trans(&txmgr)?;
+            tx_handle.run(|| {
+                let mut wtr =
+                    FnodeWriter::new(self.handle.clone(), tx_handle.txid)?;
+                wtr.seek(self.seek_pos(self.pos))?;
+                self.wtr = Some(wtr);
+                Ok(())
+            })?;
+            Ok::<_, Error>(tx_handle)
         })?;
         self.tx_handle = Some(tx_handle);
 
         Ok(())
     }
 
+    // move position to the latest EOF before a new write in append mode,
+    // the file might have been extended since it was opened. Append-only
+    // file is left as is, so writing elsewhere still fails with AppendOnly.
+    fn seek_append_end(&mut self) -> Result<()> {
+        if self.append && !self.handle.fnode.read().unwrap().is_append_only() {
+            self.pos = SeekFrom::Start(self.curr_len() as u64);
+            if let Some(ref mut rdr) = self.rdr {
+                rdr.seek(self.pos)?;
+            }
+        }
+        Ok(())
+    }
+
     // re-create reader on latest version
     fn renew_reader(&mut self) -> Result<()> {
         let mut rdr = FnodeReader::new_current(
             self.handle.fnode.clone(),
+            &self.handle.aad,
+            read_buf_size(&self.handle),
             &self.handle.store,
         )?;
         rdr.seek(self.pos)?;
@@ -465,6 +1034,19 @@ impl File {
         Ok(())
     }
 
+    // notify subscribers that this file is changed, the path is resolved
+    // again in case the file has been renamed since it was opened. Cached
+    // content of this file is dropped as well.
+    fn emit_modify(&self) {
+        {
+            let fnode = self.handle.fnode.read().unwrap();
+            self.handle.contents.invalidate(fnode.id());
+        }
+        let path = Fnode::path(&self.handle.fnode)
+            .unwrap_or_else(|_| self.handle.path.to_path_buf());
+        self.handle.events.emit(RepoEvent::Modify(path));
+    }
+
     /// Complete multi-part write to file and create a new version.
     ///
     /// This method will try to commit the transaction internally, no data will
@@ -484,15 +1066,37 @@ impl File {
         match self.wtr.take() {
             Some(wtr) => {
                 let tx_handle = self.tx_handle.take().unwrap();
+                let started = Time::now();
                 let mut end_pos = 0;
 
-                tx_handle.run_all_exclusive(|| {
-                    end_pos = wtr.finish()?;
-                    Ok(())
+                let writable = self.check_writable();
+                Txid::run_as(tx_handle.txid, || {
+                    tx_handle.run_all_exclusive(|| {
+                        writable?;
+                        end_pos = wtr.finish()?;
+                        Ok(())
+                    })
                 })?;
+                self.emit_modify();
+
+                {
+                    let fnode = self.handle.fnode.read().unwrap();
+                    log_event!(
+                        debug,
+                        "file.finish",
+                        id = fnode.id(),
+                        version = fnode.curr_ver_num(),
+                        bytes = fnode.curr_len(),
+                        duration = started.elapsed()
+                    );
+                }
+
+                self.unsynced = 0;
 
-                // set position
-                self.pos = SeekFrom::Start(end_pos as u64);
+                // set position, positional write doesn't change it
+                if self.wtr_at.take().is_none() {
+                    self.pos = SeekFrom::Start(end_pos as u64);
+                }
             }
             None => return Err(Error::NotWrite),
         }
@@ -505,6 +1109,284 @@ impl File {
         Ok(())
     }
 
+    /// Make data written so far durable without creating a new version.
+    ///
+    /// This method flushes data buffered in the current multi-part write and
+    /// the underlying storage, the write stays open and subsequent writes
+    /// continue the same in-progress version. It is useful when a file will
+    /// keep being appended to but written data should reach storage early.
+    ///
+    /// Data is written to storage in frames, so the tail of written data
+    /// which doesn't fill up a whole frame, as well as small data stored
+    /// inline, stays in memory until [`finish`] is called.
+    ///
+    /// Note that file metadata and version history are not updated until
+    /// [`finish`] is called, so the synced data is not visible to readers
+    /// and will be discarded if [`finish`] is never called. Calling this
+    /// method without an in-progress write does nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// use std::io::Write;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.log")?;
+    ///
+    /// file.write_all(b"first line\n")?;
+    /// file.sync_data()?;
+    /// file.write_all(b"second line\n")?;
+    /// file.finish()?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`finish`]: struct.File.html#method.finish
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.check_closed()?;
+
+        let result = match self.wtr {
+            Some(ref mut wtr) => match self.tx_handle {
+                Some(ref tx_handle) => Txid::run_as(tx_handle.txid, || {
+                    tx_handle.run(|| wtr.sync_data())
+                }),
+                None => unreachable!(),
+            },
+            None => return Ok(()),
+        };
+        if result.is_err() {
+            // tx has been aborted, clean up writer and tx handle
+            self.wtr.take();
+            self.wtr_at.take();
+            self.tx_handle.take();
+        }
+        self.unsynced = 0;
+        result
+    }
+
+    /// Returns the number of bytes of the in-progress write which have been
+    /// written to the underlying volume.
+    ///
+    /// Data passed to [`Write`] is buffered before it is split into chunks,
+    /// and chunks are written to the volume in whole frames, so this number
+    /// can be less than the bytes handed to `write`. It can be used to show
+    /// the real progress of a large upload. Counted data is in storage, call
+    /// [`sync_data`] to make it durable. The tail which doesn't fill up a
+    /// whole frame is not counted until [`finish`] is called.
+    ///
+    /// Small data stored inline is kept in memory until [`finish`] is
+    /// called, so it is not counted. Returns 0 if there is no in-progress
+    /// write.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use zbox::OpenOptions;
+    /// use std::io::Write;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.bin")?;
+    ///
+    /// file.write_all(&vec![42u8; 1024 * 1024])?;
+    /// assert!(file.committed_len() <= 1024 * 1024);
+    /// file.finish()?;
+    /// assert_eq!(file.committed_len(), 0);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`sync_data`]: struct.File.html#method.sync_data
+    /// [`finish`]: struct.File.html#method.finish
+    pub fn committed_len(&self) -> usize {
+        self.wtr.as_ref().map_or(0, |wtr| wtr.committed_len())
+    }
+
+    /// Reads a number of bytes starting from a given offset.
+    ///
+    /// Returns the number of bytes read, reading at or beyond the end of
+    /// file returns 0.
+    ///
+    /// Unlike [`Read`], this method doesn't change the file position.
+    ///
+    /// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        if self.rdr.is_none() {
+            self.renew_reader()?;
+        }
+
+        match self.rdr {
+            Some(ref mut rdr) => {
+                rdr.seek(SeekFrom::Start(offset))?;
+                let read = rdr.read(buf);
+
+                // restore reader to the file position
+                rdr.seek(self.pos)?;
+                Ok(read?)
+            }
+            None => unreachable!(),
+        }
+    }
+
+    /// Reads a byte range of the file and appends it to `buf`.
+    ///
+    /// Returns the number of bytes read, the range is truncated at the end
+    /// of file. Only the data overlapping the range is read and decrypted,
+    /// so it is cheaper than seeking and reading for a small range of a big
+    /// file, such as serving a HTTP range request.
+    ///
+    /// Like [`read_at`], this method doesn't change the file position.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if the range start is greater than
+    /// its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_once(b"Hello, world!")?;
+    ///
+    /// let mut buf = Vec::new();
+    /// assert_eq!(file.read_range(7..12, &mut buf)?, 5);
+    /// assert_eq!(&buf[..], b"world");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`read_at`]: struct.File.html#method.read_at
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn read_range(
+        &mut self,
+        range: Range<u64>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        self.check_closed()?;
+        if !self.can_read {
+            return Err(Error::CannotRead);
+        }
+
+        let range = mem_range(range)?;
+        if self.rdr.is_none() {
+            self.renew_reader()?;
+        }
+
+        match self.rdr {
+            Some(ref rdr) => rdr.read_range(range, buf),
+            None => unreachable!(),
+        }
+    }
+
+    /// Writes a buffer starting at a given offset.
+    ///
+    /// Returns the number of bytes written. Unlike [`Write`], this method
+    /// doesn't change the file position. If `offset` is beyond the end of
+    /// file, the gap is filled with 0s as [`set_len`] does.
+    ///
+    /// This is a multi-part write like [`Write`], [`finish`] must be called
+    /// to create a new version. Subsequent calls can write at any offset,
+    /// later writes overlay earlier ones and the content is copied on write
+    /// only when finishing. Data written at a different offset than where
+    /// the previous call ended is stored separately, so many scattered small
+    /// writes take more space until [`Repo::compact`] is run.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NotFinish`] if there is an unfinished write started
+    /// by [`Write`].
+    ///
+    /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`set_len`]: struct.File.html#method.set_len
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`Repo::compact`]: struct.Repo.html#method.compact
+    /// [`Error::NotFinish`]: enum.Error.html
+    pub fn write_at(&mut self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.check_closed()?;
+
+        let seek_to = match (self.wtr.is_some(), self.wtr_at) {
+            (false, _) => {
+                // begin write at the offset and then restore file position
+                let pos = self.pos;
+                self.pos = SeekFrom::Start(offset);
+                let result = self.begin_write();
+                self.pos = pos;
+                result?;
+                if let Some(ref mut rdr) = self.rdr {
+                    rdr.seek(pos)?;
+                }
+                None
+            }
+            (true, Some(next)) if next == offset => None,
+            (true, Some(_)) => {
+                // append-only file can only be written at its end
+                if (offset as usize) < self.curr_len()
+                    && self.handle.fnode.read().unwrap().is_append_only()
+                {
+                    return Err(Error::AppendOnly);
+                }
+                Some(offset as usize)
+            }
+            (true, None) => return Err(Error::NotFinish),
+        };
+
+        let mut ret = 0;
+        let writable = self.check_writable();
+        let result = match self.wtr {
+            Some(ref mut wtr) => match self.tx_handle {
+                Some(ref tx_handle) => Txid::run_as(tx_handle.txid, || {
+                    tx_handle.run(|| {
+                        writable?;
+                        if let Some(pos) = seek_to {
+                            wtr.seek_to(pos)?;
+                        }
+                        ret = wtr.write(buf)?;
+                        Ok(())
+                    })
+                }),
+                None => unreachable!(),
+            },
+            None => unreachable!(),
+        };
+        if let Err(err) = result {
+            // when write failed the tx has been aborted, so we need to clean up
+            // writer and tx handle here
+            self.wtr.take();
+            self.wtr_at.take();
+            self.tx_handle.take();
+            return Err(err);
+        }
+
+        self.wtr_at = Some(offset + ret as u64);
+        self.auto_sync(ret)?;
+        Ok(ret)
+    }
+
     /// Single-part write to file and create a new version.
     ///
     /// This method provides a convenient way of combining [`Write`] and
@@ -519,13 +1401,16 @@ impl File {
         match self.wtr {
             Some(_) => Err(Error::NotFinish),
             None => {
+                self.seek_append_end()?;
                 self.begin_write()?;
                 match self.wtr {
                     Some(ref mut wtr) => match self.tx_handle {
                         Some(ref tx_handle) => {
-                            tx_handle.run(|| {
-                                wtr.write_all(buf)?;
-                                Ok(())
+                            Txid::run_as(tx_handle.txid, || {
+                                tx_handle.run(|| {
+                                    wtr.write_all(buf)?;
+                                    Ok(())
+                                })
                             })?;
                         }
                         None => unreachable!(),
@@ -537,6 +1422,30 @@ impl File {
         }
     }
 
+    /// Append data to the end of file and create a new version.
+    ///
+    /// Data is always written at the current end of file regardless of the
+    /// file position and whether the file is opened in append mode. The
+    /// position is at the new end of file after this call.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file is not opened for writing
+    /// or not finished writing.
+    pub fn append_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+        let append = self.append;
+        self.append = true;
+        let result = self.write_once(buf);
+        self.append = append;
+        result
+    }
+
     /// Truncates or extends the underlying file, create a new version of
     /// content which size to become `size`.
     ///
@@ -557,15 +1466,14 @@ impl File {
             return Err(Error::NotFinish);
         }
 
-        if !self.can_write {
-            return Err(Error::CannotWrite);
-        }
+        self.check_writable()?;
 
         let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
         let tx_handle = TxMgr::begin_trans(&txmgr)?;
         tx_handle.run_all_exclusive(|| {
             Fnode::set_len(self.handle.clone(), len, tx_handle.txid)
         })?;
+        self.emit_modify();
 
         // re-create reader if there is an existing reader
         if self.rdr.is_some() {
@@ -574,16 +1482,284 @@ impl File {
 
         Ok(())
     }
+
+    /// Sets the modification time of the underlying file.
+    ///
+    /// The time can be in the future, but it cannot be before unix epoch.
+    /// Content of the file is not changed.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the file is not opened for writing
+    /// or not finished writing, or [`Error::InvalidArgument`] if `time` is
+    /// before unix epoch.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn set_modified(&mut self, time: SystemTime) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        self.check_writable()?;
+
+        let mtime =
+            Time::from_system_time(time).ok_or(Error::InvalidArgument)?;
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode = self.handle.fnode.write().unwrap();
+            fnode.make_mut(&txmgr)?.set_mtime(mtime);
+            Ok(())
+        })?;
+        self.emit_modify();
+
+        Ok(())
+    }
+
+    /// Restores a previous version and create a new version.
+    ///
+    /// The new version has the same content as the version `ver_num`, which
+    /// is not changed. Content is shared between the two versions, so no
+    /// data is read or written.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if the version `ver_num` doesn't exist,
+    /// or [`Error::AppendOnly`] if this file is append-only.
+    ///
+    /// [`Error::NoVersion`]: enum.Error.html
+    /// [`Error::AppendOnly`]: enum.Error.html
+    pub fn restore_version(&mut self, ver_num: usize) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        self.check_writable()?;
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            Fnode::restore_version(self.handle.clone(), ver_num)
+        })?;
+        self.emit_modify();
+
+        // re-create reader if there is an existing reader
+        if self.rdr.is_some() {
+            self.renew_reader()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes a historical version of this file.
+    ///
+    /// Content of the removed version is dereferenced, so space which is not
+    /// used by any other version can be reclaimed. Version numbers of other
+    /// versions are not changed. The [`version_limit`] option is not
+    /// affected.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::NoVersion`] if the version `ver_num` doesn't exist,
+    /// or [`Error::InvalidArgument`] if it is the current version.
+    ///
+    /// [`version_limit`]: struct.OpenOptions.html#method.version_limit
+    /// [`Error::NoVersion`]: enum.Error.html
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn remove_version(&mut self, ver_num: usize) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        self.check_writable()?;
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            Fnode::remove_old_version(self.handle.clone(), ver_num)
+        })?;
+        self.emit_modify();
+
+        Ok(())
+    }
+
+    /// Imports a raw content stream and create a new version.
+    ///
+    /// The stream must be produced by [`read_raw`] from a repository which
+    /// has the same compression option. Its content is stored as it is and
+    /// replaces the whole content of this file.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if the compression option of the
+    /// stream doesn't match this repository's.
+    ///
+    /// [`read_raw`]: struct.File.html#method.read_raw
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn write_raw<R: Read>(&mut self, mut rdr: R) -> Result<()> {
+        self.check_closed()?;
+        if self.wtr.is_some() {
+            return Err(Error::NotFinish);
+        }
+
+        self.check_writable()?;
+
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let tx_handle = TxMgr::begin_trans(&txmgr)?;
+        let mut len = 0;
+        tx_handle.run_all_exclusive(|| {
+            len = Fnode::write_raw(
+                self.handle.clone(),
+                &mut rdr,
+                tx_handle.txid,
+            )?;
+            Ok(())
+        })?;
+        self.emit_modify();
+
+        // set position to the end of new content
+        self.pos = SeekFrom::Start(len as u64);
+
+        // re-create reader if there is an existing reader
+        if self.rdr.is_some() {
+            self.renew_reader()?;
+        }
+
+        Ok(())
+    }
+
+    /// Acquires a shared advisory lock on this file, blocking until it can
+    /// be acquired.
+    ///
+    /// Locks are advisory, like `flock`, they don't prevent any operation on
+    /// the file and only bind the callers who choose to use them. A file can
+    /// have multiple shared locks or one exclusive lock at a time. Locks are
+    /// held on the file itself rather than the path, so they are kept when
+    /// the file is renamed.
+    ///
+    /// Each `File` holds at most one lock, calling this method when holding
+    /// an exclusive lock converts it to a shared lock. Converting is not
+    /// atomic, the existing lock is released first. The lock is released
+    /// when [`unlock`] is called or this `File` is dropped.
+    ///
+    /// Locks are only visible within the opened repository, they are not
+    /// shared with other processes which open the same repository.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.lock_exclusive()?;
+    /// file.write_once(b"Hello, world!")?;
+    /// file.unlock()?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`unlock`]: struct.File.html#method.unlock
+    pub fn lock_shared(&mut self) -> Result<()> {
+        self.acquire_lock(LockKind::Shared, true)
+    }
+
+    /// Acquires an exclusive advisory lock on this file, blocking until it
+    /// can be acquired.
+    ///
+    /// See [`lock_shared`] for details.
+    ///
+    /// [`lock_shared`]: struct.File.html#method.lock_shared
+    pub fn lock_exclusive(&mut self) -> Result<()> {
+        self.acquire_lock(LockKind::Exclusive, true)
+    }
+
+    /// Tries to acquire a shared advisory lock on this file.
+    ///
+    /// This is same as [`lock_shared`] but it doesn't block.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WouldBlock`] if the lock cannot be acquired
+    /// immediately. If this `File` was holding an exclusive lock, it is
+    /// released.
+    ///
+    /// [`lock_shared`]: struct.File.html#method.lock_shared
+    /// [`Error::WouldBlock`]: enum.Error.html
+    pub fn try_lock_shared(&mut self) -> Result<()> {
+        self.acquire_lock(LockKind::Shared, false)
+    }
+
+    /// Tries to acquire an exclusive advisory lock on this file.
+    ///
+    /// This is same as [`lock_exclusive`] but it doesn't block.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WouldBlock`] if the lock cannot be acquired
+    /// immediately. If this `File` was holding a shared lock, it is released.
+    ///
+    /// [`lock_exclusive`]: struct.File.html#method.lock_exclusive
+    /// [`Error::WouldBlock`]: enum.Error.html
+    pub fn try_lock_exclusive(&mut self) -> Result<()> {
+        self.acquire_lock(LockKind::Exclusive, false)
+    }
+
+    /// Releases the advisory lock held by this file.
+    ///
+    /// It does nothing if this file doesn't hold a lock.
+    pub fn unlock(&mut self) -> Result<()> {
+        self.check_closed()?;
+        self.release_lock();
+        Ok(())
+    }
+
+    // acquire advisory lock, an existing lock of other kind is released
+    fn acquire_lock(&mut self, kind: LockKind, wait: bool) -> Result<()> {
+        self.check_closed()?;
+        if self.lock == Some(kind) {
+            return Ok(());
+        }
+        self.release_lock();
+        let fnode_id = self.handle.fnode.read().unwrap().id().clone();
+        self.handle.locks.lock(&fnode_id, kind, wait)?;
+        self.lock = Some(kind);
+        Ok(())
+    }
+
+    // release advisory lock if it is held
+    fn release_lock(&mut self) {
+        if let Some(kind) = self.lock.take() {
+            let fnode_id = self.handle.fnode.read().unwrap().id().clone();
+            self.handle.locks.unlock(&fnode_id, kind);
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        self.release_lock();
+    }
 }
 
 impl Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         map_io_err!(self.check_closed())?;
         if !self.can_read {
-            return Err(IoError::new(
-                ErrorKind::Other,
-                Error::CannotRead.to_string(),
-            ));
+            return Err(IoError::new(ErrorKind::Other, Error::CannotRead));
         }
 
         // if reader is not created yet, create a new reader and seek to
@@ -595,7 +1771,7 @@ impl Read for File {
         match self.rdr {
             Some(ref mut rdr) => {
                 let read = rdr.read(buf)?;
-                let new_pos = rdr.seek(SeekFrom::Current(0)).unwrap();
+                let new_pos = rdr.stream_position()?;
                 self.pos = SeekFrom::Start(new_pos);
                 Ok(read)
             }
@@ -604,22 +1780,58 @@ impl Read for File {
     }
 }
 
+impl BufRead for File {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        map_io_err!(self.check_closed())?;
+        if !self.can_read {
+            return map_io_err!(Err(Error::CannotRead));
+        }
+
+        // if reader is not created yet, create a new reader and seek to
+        // the current file position
+        if self.rdr.is_none() {
+            map_io_err!(self.renew_reader())?;
+        }
+
+        match self.rdr {
+            Some(ref mut rdr) => rdr.fill_buf(),
+            None => unreachable!(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(ref mut rdr) = self.rdr {
+            rdr.consume(amt);
+            if let SeekFrom::Start(pos) = self.pos {
+                self.pos = SeekFrom::Start(pos + amt as u64);
+            }
+        }
+    }
+}
+
 impl Write for File {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         map_io_err!(self.check_closed())?;
+        if self.wtr_at.is_some() {
+            return map_io_err!(Err(Error::NotFinish));
+        }
         if self.wtr.is_none() {
+            map_io_err!(self.seek_append_end())?;
             map_io_err!(self.begin_write())?;
         }
 
         let mut ret = 0;
+        let writable = self.check_writable();
         map_io_err!(match self.wtr {
             Some(ref mut wtr) => match self.tx_handle {
-                Some(ref tx_handle) => tx_handle
-                    .run(|| {
+                Some(ref tx_handle) => Txid::run_as(tx_handle.txid, || {
+                    tx_handle.run(|| {
+                        writable?;
                         ret = wtr.write(buf)?;
                         Ok(())
                     })
-                    .map(|_| ret),
+                })
+                .map(|_| ret),
                 None => unreachable!(),
             },
             None => unreachable!(),
@@ -631,6 +1843,10 @@ impl Write for File {
             self.tx_handle.take();
             err
         }))
+        .and_then(|written| {
+            map_io_err!(self.auto_sync(written))?;
+            Ok(written)
+        })
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -638,9 +1854,11 @@ impl Write for File {
         match self.wtr {
             Some(ref mut wtr) => match self.tx_handle {
                 Some(ref tx_handle) => {
-                    map_io_err!(tx_handle.run(|| {
-                        wtr.flush()?;
-                        Ok(())
+                    map_io_err!(Txid::run_as(tx_handle.txid, || {
+                        tx_handle.run(|| {
+                            wtr.flush()?;
+                            Ok(())
+                        })
                     }))?;
                     Ok(())
                 }
@@ -648,7 +1866,7 @@ impl Write for File {
             },
             None => Err(IoError::new(
                 ErrorKind::PermissionDenied,
-                Error::CannotWrite.to_string(),
+                Error::CannotWrite,
             )),
         }
     }
@@ -658,10 +1876,7 @@ impl Seek for File {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         map_io_err!(self.check_closed())?;
         if self.wtr.is_some() {
-            return Err(IoError::new(
-                ErrorKind::Other,
-                Error::NotFinish.to_string(),
-            ));
+            return Err(IoError::new(ErrorKind::Other, Error::NotFinish));
         }
 
         self.pos = match self.rdr {
@@ -679,6 +1894,7 @@ impl Seek for File {
 impl Debug for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("File")
+            .field("path", &self.handle.path)
             .field("pos", &self.pos)
             .field("rdr", &self.rdr)
             .field("wtr", &self.wtr)
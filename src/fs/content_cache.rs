@@ -0,0 +1,70 @@
+use std::sync::{Arc, Mutex};
+
+use crate::base::lru::{Lru, Meter, PinChecker};
+use crate::trans::Eid;
+
+// Default capacity of content cache, in bytes
+const DEFAULT_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// Maximum content length of a file which can be cached
+pub const MAX_CACHED_LEN: usize = 1024 * 1024;
+
+// cached content and the version number it was read from
+type CachedContent = (usize, Arc<[u8]>);
+
+// Content meter, measured by content bytes size
+#[derive(Debug, Default)]
+struct ContentMeter;
+
+impl Meter<CachedContent> for ContentMeter {
+    #[inline]
+    fn measure(&self, item: &CachedContent) -> isize {
+        item.1.len() as isize
+    }
+}
+
+// Content LRU
+type ContentLru =
+    Lru<Eid, CachedContent, ContentMeter, PinChecker<CachedContent>>;
+
+/// Decrypted content cache of small files
+///
+/// Each file has at most one entry, which is keyed by fnode id and holds
+/// content of a single version. The entry is replaced when a newer version
+/// is read and removed when the file content is changed.
+#[derive(Debug)]
+pub struct ContentCache {
+    lru: Mutex<ContentLru>,
+}
+
+impl ContentCache {
+    pub fn new() -> ContentCacheRef {
+        Arc::new(ContentCache {
+            lru: Mutex::new(ContentLru::new(DEFAULT_CAPACITY)),
+        })
+    }
+
+    /// Get cached content of the specified fnode version
+    pub fn get(&self, id: &Eid, ver_num: usize) -> Option<Arc<[u8]>> {
+        let mut lru = self.lru.lock().unwrap();
+        match lru.get_refresh(id) {
+            Some((ver, content)) if *ver == ver_num => Some(content.clone()),
+            _ => None,
+        }
+    }
+
+    /// Cache content of the specified fnode version
+    pub fn insert(&self, id: &Eid, ver_num: usize, content: Arc<[u8]>) {
+        debug_assert!(content.len() <= MAX_CACHED_LEN);
+        let mut lru = self.lru.lock().unwrap();
+        lru.insert(id.clone(), (ver_num, content));
+    }
+
+    /// Remove cached content of fnode
+    pub fn invalidate(&self, id: &Eid) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(id);
+    }
+}
+
+pub type ContentCacheRef = Arc<ContentCache>;
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+/// Repository mutation events received from [`Repo::subscribe`].
+///
+/// Events are sent after the transaction making the change is committed,
+/// paths are the ones used by the operation.
+///
+/// [`Repo::subscribe`]: struct.Repo.html#method.subscribe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoEvent {
+    /// A file, directory or symbolic link is created.
+    Create(PathBuf),
+
+    /// Content or metadata of a file or directory is changed.
+    Modify(PathBuf),
+
+    /// A file, directory or symbolic link is removed.
+    Remove(PathBuf),
+
+    /// A file or directory is renamed, the target is replaced if it existed.
+    Rename {
+        /// Path before renaming.
+        from: PathBuf,
+
+        /// Path after renaming.
+        to: PathBuf,
+    },
+
+    /// The receiver didn't keep up, events are dropped before this one.
+    Lagged {
+        /// Number of dropped events.
+        dropped: usize,
+    },
+}
+
+// event subscriber and number of events dropped since last sent one
+#[derive(Debug)]
+struct Subscriber {
+    tx: SyncSender<RepoEvent>,
+    dropped: usize,
+}
+
+impl Subscriber {
+    // send event without blocking, return false if the receiver is gone
+    fn send(&mut self, event: &RepoEvent) -> bool {
+        if self.dropped > 0 {
+            let lagged = RepoEvent::Lagged {
+                dropped: self.dropped,
+            };
+            match self.tx.try_send(lagged) {
+                Ok(_) => self.dropped = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    return true;
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+
+        match self.tx.try_send(event.clone()) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Event hub
+///
+/// Events are delivered to subscribers through bounded channels, writers
+/// never block on slow subscribers.
+#[derive(Debug, Default)]
+pub struct EventHub {
+    subs: Mutex<Vec<Subscriber>>,
+
+    // events held for threads running a joinable transaction, they are
+    // sent when the transaction is committed
+    held: Mutex<HashMap<ThreadId, Vec<RepoEvent>>>,
+}
+
+impl EventHub {
+    // capacity of each subscriber's channel
+    const CHANNEL_CAPACITY: usize = 1024;
+
+    pub fn new() -> EventHubRef {
+        Arc::new(EventHub::default())
+    }
+
+    /// Add a subscriber and return its receiver
+    pub fn subscribe(&self) -> Receiver<RepoEvent> {
+        let (tx, rx) = sync_channel(Self::CHANNEL_CAPACITY);
+        let mut subs = self.subs.lock().unwrap();
+        subs.push(Subscriber { tx, dropped: 0 });
+        rx
+    }
+
+    /// Send event to all subscribers, closed subscribers are removed
+    pub fn emit(&self, event: RepoEvent) {
+        {
+            let mut held = self.held.lock().unwrap();
+            if let Some(events) = held.get_mut(&thread::current().id()) {
+                events.push(event);
+                return;
+            }
+        }
+
+        let mut subs = self.subs.lock().unwrap();
+        if !subs.is_empty() {
+            subs.retain_mut(|sub| sub.send(&event));
+        }
+    }
+
+    /// Hold events emitted on current thread until release() is called
+    pub fn hold(&self) {
+        let mut held = self.held.lock().unwrap();
+        held.insert(thread::current().id(), Vec::new());
+    }
+
+    /// Stop holding events on current thread, the held events are sent if
+    /// `send` is true, otherwise they are discarded
+    pub fn release(&self, send: bool) {
+        let events = {
+            let mut held = self.held.lock().unwrap();
+            held.remove(&thread::current().id()).unwrap_or_default()
+        };
+        if send {
+            for event in events {
+                self.emit(event);
+            }
+        }
+    }
+}
+
+pub type EventHubRef = Arc<EventHub>;
@@ -3,34 +3,46 @@
 use std::cmp::{min, Ordering};
 use std::collections::VecDeque;
 use std::fmt::{self, Debug};
-use std::io::{Read, Result as IoResult, Seek, SeekFrom, Write};
+use std::io::{
+    self, BufRead, Cursor, Error as IoError, ErrorKind, Read,
+    Result as IoResult, Seek, SeekFrom, Write,
+};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::result::Result as StdResult;
 use std::sync::Arc;
 use std::time::SystemTime;
+use std::vec;
 
 use serde::{Deserialize, Serialize};
 
+use super::fs::ShutterRef;
 use super::{Handle, Options};
+use crate::base::crypto::{Crypto, Hash};
 use crate::base::lru::{CountMeter, Lru, PinChecker};
-use crate::base::Time;
+use crate::base::{unix_secs, Time};
 use crate::content::{
-    ChunkMap, Content, ContentReader, Store, StoreRef, StoreWeakRef,
-    Writer as StoreWriter,
+    import_raw, ChunkMap, Content, ContentRawReader, ContentReader, Store,
+    StoreRef, StoreWeakRef, Writer as StoreWriter,
 };
 use crate::error::{Error, Result};
 use crate::trans::cow::{Cow, CowCache, CowRef, CowWeakRef, Cowable, IntoCow};
 use crate::trans::trans::{Action, Transable};
 use crate::trans::{Eid, Id, TxMgrRef, Txid};
-use crate::volume::VolumeRef;
+use crate::volume::{VolumeRef, VolumeWeakRef, FRAME_SIZE};
 
 // maximum sub nodes for a fnode
 const SUB_NODES_CNT: usize = 8;
 
 /// A structure representing a type of file with accessors for each file type.
+///
+/// It is serialized as its variant name, that is, `File`, `Dir` or
+/// `SymLink`.
 #[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
 pub enum FileType {
     File,
     Dir,
+    SymLink,
 }
 
 impl FileType {
@@ -43,6 +55,11 @@ impl FileType {
     pub fn is_dir(self) -> bool {
         self == FileType::Dir
     }
+
+    /// Test whether this file type represents a symbolic link.
+    pub fn is_symlink(self) -> bool {
+        self == FileType::SymLink
+    }
 }
 
 impl Default for FileType {
@@ -56,6 +73,7 @@ impl From<FileType> for i32 {
         match ftype {
             FileType::File => 0,
             FileType::Dir => 1,
+            FileType::SymLink => 2,
         }
     }
 }
@@ -65,6 +83,7 @@ impl From<FileType> for String {
         match ftype {
             FileType::File => String::from("File"),
             FileType::Dir => String::from("Dir"),
+            FileType::SymLink => String::from("SymLink"),
         }
     }
 }
@@ -87,22 +106,134 @@ impl ChildEntry {
     }
 }
 
+// children entries are kept sorted by name, entries stored by old versions
+// might not be sorted so they are sorted when loaded
+fn sorted_kids<'de, D>(deserializer: D) -> StdResult<Vec<ChildEntry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut kids = Vec::<ChildEntry>::deserialize(deserializer)?;
+    kids.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    Ok(kids)
+}
+
 /// A representation of a permanent file content.
+///
+/// It can be serialized with [serde], the fields are `num`, `len` and
+/// `created_at`. The creation time is serialized as seconds since unix
+/// epoch.
+///
+/// [serde]: https://serde.rs
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Version {
-    num: usize,      // version number
+    num: usize, // version number
+
+    #[serde(skip)]
     content_id: Eid, // content id
+
+    #[serde(rename = "len")]
+    content_len: usize,
+
+    #[serde(rename = "created_at", with = "unix_secs")]
+    ctime: Time,
+
+    // content data stored inline, content id is empty if it is set
+    #[serde(skip)]
+    inline: Option<Vec<u8>>,
+
+    // content hash, it is not saved by versions created before it was
+    // introduced and will be filled in when the version is queried
+    #[serde(skip)]
+    content_hash: Option<Hash>,
+}
+
+// persisted form of version, it is separated from the public serialization
+// of version so the public one can be changed without breaking the storage
+// format
+#[derive(Deserialize, Serialize)]
+struct StoredVersion {
+    num: usize,
+    content_id: Eid,
     content_len: usize,
     ctime: Time,
+    #[serde(default)]
+    inline: Option<Vec<u8>>,
+    #[serde(default)]
+    content_hash: Option<Hash>,
+}
+
+impl From<&Version> for StoredVersion {
+    fn from(ver: &Version) -> Self {
+        StoredVersion {
+            num: ver.num,
+            content_id: ver.content_id.clone(),
+            content_len: ver.content_len,
+            ctime: ver.ctime,
+            inline: ver.inline.clone(),
+            content_hash: ver.content_hash.clone(),
+        }
+    }
+}
+
+impl From<StoredVersion> for Version {
+    fn from(ver: StoredVersion) -> Self {
+        Version {
+            num: ver.num,
+            content_id: ver.content_id,
+            content_len: ver.content_len,
+            ctime: ver.ctime,
+            inline: ver.inline,
+            content_hash: ver.content_hash,
+        }
+    }
+}
+
+// serialize fnode versions in persisted form
+mod stored_versions {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(
+        vers: &VecDeque<Version>,
+        serializer: S,
+    ) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(vers.iter().map(StoredVersion::from))
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> StdResult<VecDeque<Version>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let vers = Vec::<StoredVersion>::deserialize(deserializer)?;
+        Ok(vers.into_iter().map(Version::from).collect())
+    }
 }
 
 impl Version {
-    fn new(num: usize, content_id: &Eid, len: usize) -> Self {
+    fn new(num: usize, content_id: &Eid, len: usize, hash: &Hash) -> Self {
         Version {
             num,
             content_id: content_id.clone(),
             content_len: len,
             ctime: Time::now(),
+            inline: None,
+            content_hash: Some(hash.clone()),
+        }
+    }
+
+    fn new_inline(num: usize, data: Vec<u8>) -> Self {
+        Version {
+            num,
+            content_id: Eid::new_empty(),
+            content_len: data.len(),
+            ctime: Time::now(),
+            content_hash: Some(Crypto::hash(&data)),
+            inline: Some(data),
         }
     }
 
@@ -122,6 +253,36 @@ impl Version {
     pub fn created_at(&self) -> SystemTime {
         self.ctime.to_system_time()
     }
+
+    /// Returns whether this version of content is stored inline.
+    ///
+    /// Inline content is stored in file metadata rather than in data
+    /// segments, see [`inline_threshold`].
+    ///
+    /// [`inline_threshold`]: struct.RepoOpener.html#method.inline_threshold
+    pub fn is_inline(&self) -> bool {
+        self.inline.is_some()
+    }
+
+    /// Returns the hash of this version of content.
+    ///
+    /// The hash is calculated when the version is created, so two versions
+    /// can be compared without reading their content. Versions of same
+    /// content always have the same hash.
+    ///
+    /// This is the same hash as [`File::content_hash`] for content no larger
+    /// than 256KB. Larger content is hashed in 256KB pieces using a Merkle
+    /// tree, so its hash is different from the one returned by
+    /// [`File::content_hash`].
+    ///
+    /// [`File::content_hash`]: struct.File.html#method.content_hash
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut ret = [0u8; 32];
+        if let Some(ref hash) = self.content_hash {
+            ret.copy_from_slice(hash);
+        }
+        ret
+    }
 }
 
 /// Metadata information about a file or a directory.
@@ -130,15 +291,30 @@ impl Version {
 /// [`Repo::metadata`] represents known metadata about a file such as its type,
 /// size, modification times and etc.
 ///
+/// It can be serialized with [serde], the fields are `file_type`, `len`,
+/// `curr_version`, `created_at`, `modified_at` and `append_only`. The times
+/// are serialized as seconds since unix epoch.
+///
 /// [`File::metadata`]: struct.File.html#method.metadata
 /// [`Repo::metadata`]: struct.Repo.html#method.metadata
-#[derive(Debug, Copy, Clone)]
+/// [serde]: https://serde.rs
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 pub struct Metadata {
+    #[serde(rename = "file_type")]
     ftype: FileType,
+
+    #[serde(rename = "len")]
     content_len: usize,
+
     curr_version: usize,
+
+    #[serde(rename = "created_at", with = "unix_secs")]
     ctime: Time,
+
+    #[serde(rename = "modified_at", with = "unix_secs")]
     mtime: Time,
+
+    append_only: bool,
 }
 
 impl Metadata {
@@ -157,6 +333,11 @@ impl Metadata {
         self.ftype == FileType::File
     }
 
+    /// Returns whether this metadata is for a symbolic link.
+    pub fn is_symlink(&self) -> bool {
+        self.ftype == FileType::SymLink
+    }
+
     /// Returns the size of the current version of file, in bytes, this
     /// metadata is for.
     pub fn content_len(&self) -> usize {
@@ -177,6 +358,11 @@ impl Metadata {
     pub fn modified_at(&self) -> SystemTime {
         self.mtime.to_system_time()
     }
+
+    /// Returns whether the file is append-only.
+    pub fn is_append_only(&self) -> bool {
+        self.append_only
+    }
 }
 
 /// Entries returned by the [`read_dir`] function.
@@ -185,15 +371,31 @@ impl Metadata {
 /// repository. Each entry can be inspected via methods to learn about the
 /// absolute path or other metadata.
 ///
+/// It can be serialized with [serde], the fields are `path`, `file_name`
+/// and `metadata`.
+///
 /// [`read_dir`]: struct.Repo.html#method.read_dir
-#[derive(Debug)]
+/// [serde]: https://serde.rs
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DirEntry {
     path: PathBuf,
+
+    #[serde(rename = "file_name")]
     name: String,
+
     metadata: Metadata,
 }
 
 impl DirEntry {
+    #[inline]
+    pub(super) fn new(path: PathBuf, name: &str, metadata: Metadata) -> Self {
+        DirEntry {
+            path,
+            name: name.to_string(),
+            metadata,
+        }
+    }
+
     /// Returns the absolute path to the file that this entry represents.
     pub fn path(&self) -> &Path {
         self.path.as_path()
@@ -211,6 +413,173 @@ impl DirEntry {
     }
 }
 
+/// A continuation cursor returned by the [`read_dir_page`] function.
+///
+/// The cursor is opaque, it remembers the position after the last entry of
+/// a page and can be passed to [`read_dir_page`] to get the next page. It
+/// stays valid when entries are added to or removed from the directory
+/// between calls.
+///
+/// [`read_dir_page`]: struct.Repo.html#method.read_dir_page
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DirCursor {
+    after: String,
+}
+
+impl DirCursor {
+    #[inline]
+    pub(crate) fn new(after: &str) -> Self {
+        DirCursor {
+            after: after.to_string(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn after(&self) -> &str {
+        &self.after
+    }
+}
+
+/// An iterator over the entries within a directory.
+///
+/// This iterator is returned from the [`read_dir_iter`] function. Entries
+/// are yielded in name order, their names are loaded page by page and their
+/// metadata is loaded lazily when iterating. Entries removed from the
+/// directory after this iterator is created are skipped. An error is yielded
+/// if the repo is closed during iterating.
+///
+/// [`read_dir_iter`]: struct.Repo.html#method.read_dir_iter
+pub struct ReadDir {
+    parent: FnodeRef,
+    path: PathBuf,
+    names: vec::IntoIter<String>, // names in current page
+    after: Option<String>,        // last name loaded
+    has_more: bool,
+    cache: Cache,
+    vol: VolumeWeakRef,
+    shutter: ShutterRef,
+}
+
+impl ReadDir {
+    // number of children names loaded in one page
+    const PAGE_SIZE: usize = 256;
+
+    // check if repo is closed, iteration is ended if it is
+    fn check_closed(&mut self) -> Result<VolumeRef> {
+        let closed = self.shutter.read().unwrap().is_closed();
+        match self.vol.upgrade() {
+            Some(vol) if !closed => Ok(vol),
+            _ => {
+                self.names = Vec::new().into_iter();
+                self.has_more = false;
+                Err(Error::RepoClosed)
+            }
+        }
+    }
+
+    // load next page of children names
+    fn load_page(&mut self) -> Result<()> {
+        let (names, has_more) = {
+            let par = self.parent.read().unwrap();
+            if !par.is_dir() {
+                return Err(Error::NotDir);
+            }
+            par.children_page(self.after.as_deref(), Self::PAGE_SIZE)
+        };
+        if let Some(last) = names.last() {
+            self.after = Some(last.clone());
+        }
+        self.names = names.into_iter();
+        self.has_more = has_more;
+        Ok(())
+    }
+
+    // get next child entry and its fnode
+    pub(super) fn next_child(
+        &mut self,
+    ) -> Option<Result<(DirEntry, FnodeRef)>> {
+        if self.names.len() == 0 && !self.has_more {
+            return None;
+        }
+        let vol = match self.check_closed() {
+            Ok(vol) => vol,
+            Err(err) => return Some(Err(err)),
+        };
+
+        loop {
+            let name = match self.names.next() {
+                Some(name) => name,
+                None if self.has_more => {
+                    if let Err(err) = self.load_page() {
+                        self.has_more = false;
+                        return Some(Err(err));
+                    }
+                    continue;
+                }
+                None => return None,
+            };
+
+            let mut par = self.parent.write().unwrap();
+            let par = par.make_mut_naive();
+
+            // skip the child which has been removed
+            if !par.has_child(&name) {
+                continue;
+            }
+
+            let child =
+                par.load_child(&name, self.parent.clone(), &self.cache, &vol);
+            return Some(child.map(|child_ref| {
+                let ent = DirEntry {
+                    path: Fnode::child_path(&self.path, &name),
+                    metadata: child_ref.read().unwrap().metadata(),
+                    name,
+                };
+                (ent, child_ref)
+            }));
+        }
+    }
+
+    // read a child directory using the same fnode cache, volume and shutter
+    #[inline]
+    pub(super) fn read_child(
+        &self,
+        child: FnodeRef,
+        path: &Path,
+    ) -> Result<ReadDir> {
+        Fnode::read_dir(child, path, &self.cache, &self.vol, &self.shutter)
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_child().map(|child| child.map(|(ent, _)| ent))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let upper = if self.has_more {
+            None
+        } else {
+            Some(self.names.len())
+        };
+        (0, upper)
+    }
+}
+
+impl Debug for ReadDir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadDir")
+            .field("path", &self.path)
+            .field("after", &self.after)
+            .field("has_more", &self.has_more)
+            .finish()
+    }
+}
+
 type SubNodes = Lru<
     String,
     FnodeWeakRef,
@@ -225,10 +594,30 @@ pub struct Fnode {
     opts: Options,
     ctime: Time,
     mtime: Time,
-    kids: Vec<ChildEntry>,
+    #[serde(deserialize_with = "sorted_kids")]
+    kids: Vec<ChildEntry>, // sorted by name
+    #[serde(with = "stored_versions")]
     vers: VecDeque<Version>,
     chk_map: ChunkMap,
 
+    // append-only flag, append-only file cannot be truncated, overwritten
+    // or removed
+    #[serde(default)]
+    append_only: bool,
+
+    // cached recursive size of all files under directory, it is only
+    // maintained when directory size tracking is enabled
+    #[serde(default)]
+    dir_size: usize,
+
+    // hash of associated data which current content is encrypted with
+    #[serde(default)]
+    aad_hash: Option<Hash>,
+
+    // target path of symbolic link
+    #[serde(default)]
+    target: Option<String>,
+
     // parent fnode
     #[serde(skip_serializing, skip_deserializing, default)]
     parent: Option<FnodeRef>,
@@ -251,6 +640,10 @@ impl Fnode {
             kids: Vec::new(),
             vers: VecDeque::new(),
             chk_map: ChunkMap::new(opts.dedup_chunk),
+            append_only: false,
+            dir_size: 0,
+            aad_hash: None,
+            target: None,
             parent: None,
             sub_nodes: Self::default_sub_nodes(),
         }
@@ -287,6 +680,119 @@ impl Fnode {
         Ok(kid)
     }
 
+    /// Create new regular file fnode under parent with content
+    ///
+    /// Unlike creating an empty file and then writing to it, the content is
+    /// added as the first version, so no version is retired. Thus it can be
+    /// used for creating multiple files in one transaction.
+    pub fn new_file_under(
+        parent: &FnodeRef,
+        name: &str,
+        data: &[u8],
+        opts: Options,
+        txid: Txid,
+        txmgr: &TxMgrRef,
+        store: &StoreRef,
+    ) -> Result<FnodeRef> {
+        if !parent.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
+        }
+
+        let mut kid = Fnode::new(FileType::File, opts);
+        if opts.inline_threshold > 0 && data.len() <= opts.inline_threshold {
+            kid.add_inline_version(data.to_vec(), store, txmgr)?;
+        } else {
+            let mut wtr = StoreWriter::new(
+                txid,
+                kid.chk_map.clone(),
+                false,
+                &[],
+                opts.compress,
+                &Arc::downgrade(txmgr),
+                &Arc::downgrade(store),
+            )?;
+            wtr.write_all(data)?;
+            let (stg_ctn, chk_map) = wtr.finish()?;
+
+            // merge stage content to an empty content, so that the merged
+            // content has full hash and can be deduped
+            let mut ctn = Content::new();
+            ctn.merge_from(&stg_ctn, &[], store)?;
+            if !kid.add_version(ctn, store, txmgr)? {
+                // content is duplicated, weak unlink the stage content
+                stg_ctn.unlink_weak(&mut kid.chk_map, store, txmgr)?;
+            }
+            kid.chk_map = chk_map;
+        }
+        let kid = kid.into_cow(txmgr)?;
+
+        // add child to parent
+        Fnode::add_child(parent, &kid, name, txmgr)?;
+
+        Ok(kid)
+    }
+
+    /// Create new regular file fnode under parent sharing current content
+    /// of another file
+    ///
+    /// The source content is added as the first version, its chunks are
+    /// referenced rather than copied.
+    pub fn new_clone_under(
+        parent: &FnodeRef,
+        name: &str,
+        src: &Fnode,
+        txmgr: &TxMgrRef,
+        store: &StoreRef,
+    ) -> Result<FnodeRef> {
+        assert!(src.is_file());
+        if !parent.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
+        }
+
+        let mut kid = Fnode::new(FileType::File, src.get_opts());
+        match src.curr_inline() {
+            Some(data) => {
+                kid.add_inline_version(data.to_vec(), store, txmgr)?
+            }
+            None => {
+                let ctn = src.clone_current_content(store)?;
+                kid.add_version(ctn, store, txmgr)?;
+            }
+        }
+        kid.aad_hash = src.aad_hash();
+        let kid = kid.into_cow(txmgr)?;
+
+        // add child to parent
+        Fnode::add_child(parent, &kid, name, txmgr)?;
+
+        Ok(kid)
+    }
+
+    /// Create new symbolic link fnode under parent
+    pub fn new_symlink_under(
+        parent: &FnodeRef,
+        name: &str,
+        target: &str,
+        txmgr: &TxMgrRef,
+    ) -> Result<FnodeRef> {
+        let kid = {
+            let mut pfnode_cow = parent.write().unwrap();
+            let pfnode = pfnode_cow.make_mut(txmgr)?;
+            if !pfnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+
+            let mut kid = Fnode::new(FileType::SymLink, Options::default());
+            kid.target = Some(target.to_string());
+            kid.into_cow(txmgr)?
+        };
+
+        // add child to parent
+        Fnode::add_child(parent, &kid, name, txmgr)?;
+
+        Ok(kid)
+    }
+
     #[inline]
     fn default_sub_nodes() -> SubNodes {
         Lru::new(SUB_NODES_CNT)
@@ -304,6 +810,18 @@ impl Fnode {
         self.ftype == FileType::Dir
     }
 
+    /// Check if fnode is symbolic link
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.ftype == FileType::SymLink
+    }
+
+    /// Get target path of symbolic link
+    #[inline]
+    pub fn link_target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
     /// Check if fnode is root
     #[inline]
     pub fn is_root(&self) -> bool {
@@ -318,6 +836,7 @@ impl Fnode {
             curr_version: self.curr_ver_num(),
             ctime: self.ctime,
             mtime: self.mtime,
+            append_only: self.append_only,
         }
     }
 
@@ -327,7 +846,63 @@ impl Fnode {
         match self.ftype {
             FileType::File => self.curr_ver().content_len(),
             FileType::Dir => 0,
+            FileType::SymLink => self.link_target().map_or(0, str::len),
+        }
+    }
+
+    /// Get cached recursive size of directory
+    #[inline]
+    pub fn dir_size(&self) -> usize {
+        self.dir_size
+    }
+
+    // get size counted in parent's directory size
+    fn tracked_size(&self) -> usize {
+        match self.ftype {
+            FileType::File if self.opts.track_dir_sizes => self.curr_len(),
+            FileType::File => 0,
+            FileType::Dir => self.dir_size,
+            FileType::SymLink => 0,
+        }
+    }
+
+    // add size delta to directory and all its ancestors
+    //
+    // ancestors are locked one at a time, so this must be called after
+    // releasing locks on the changed fnode and its parent
+    fn adjust_dir_size(
+        dir: Option<FnodeRef>,
+        delta: isize,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        if delta == 0 {
+            return Ok(());
         }
+
+        let mut curr = dir;
+        while let Some(dir) = curr {
+            let mut dir_cow = dir.write().unwrap();
+            let dir = dir_cow.make_mut(txmgr)?;
+            dir.dir_size = (dir.dir_size as isize + delta) as usize;
+            curr = dir.parent.clone();
+        }
+        Ok(())
+    }
+
+    /// Propagate file content length change to its ancestor directories
+    pub fn propagate_len(
+        fnode: &FnodeRef,
+        old_len: usize,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        let (parent, delta) = {
+            let f = fnode.read().unwrap();
+            if !f.opts.track_dir_sizes {
+                return Ok(());
+            }
+            (f.parent.clone(), f.curr_len() as isize - old_len as isize)
+        };
+        Fnode::adjust_dir_size(parent, delta, txmgr)
     }
 
     /// Get fnode version list
@@ -336,40 +911,151 @@ impl Fnode {
         Vec::from(self.vers.clone())
     }
 
+    /// Get fnode version list with content hash filled in
+    pub fn versions(&self, store: &StoreRef) -> Result<Vec<Version>> {
+        self.vers
+            .iter()
+            .map(|ver| self.ver_with_hash(ver, store))
+            .collect()
+    }
+
+    /// Get a specified version with content hash filled in
+    pub fn version(&self, ver_num: usize, store: &StoreRef) -> Result<Version> {
+        let ver = self.ver(ver_num).ok_or(Error::NoVersion)?;
+        self.ver_with_hash(ver, store)
+    }
+
+    // clone version and fill in its content hash if it is not saved
+    fn ver_with_hash(
+        &self,
+        ver: &Version,
+        store: &StoreRef,
+    ) -> Result<Version> {
+        let mut ver = ver.clone();
+        if ver.content_hash.is_none() {
+            ver.content_hash = Some(self.ver_hash(&ver, store)?);
+        }
+        Ok(ver)
+    }
+
+    // get content hash of a version
+    fn ver_hash(&self, ver: &Version, store: &StoreRef) -> Result<Hash> {
+        if let Some(ref hash) = ver.content_hash {
+            return Ok(hash.clone());
+        }
+        if let Some(ref data) = ver.inline {
+            return Ok(Crypto::hash(data));
+        }
+        let content = self.ver_content(ver, store)?;
+        Ok(content.hash().clone())
+    }
+
     /// Get fnode options
     #[inline]
     pub fn get_opts(&self) -> Options {
         self.opts
     }
 
-    /// Load root fnode
+    /// Set fnode modified time
     #[inline]
-    pub fn load_root(root_id: &Eid, vol: &VolumeRef) -> Result<FnodeRef> {
-        let root = Cow::<Fnode>::load(root_id, vol)?;
-        Ok(root)
+    pub fn set_mtime(&mut self, mtime: Time) {
+        self.mtime = mtime;
     }
 
-    // load one child fnode
-    fn load_child(
-        &mut self,
-        name: &str,
-        self_ref: FnodeRef,
-        cache: &Cache,
-        vol: &VolumeRef,
-    ) -> Result<FnodeRef> {
-        // get child fnode from sub node list first
-        if let Some(fnode) = self
-            .sub_nodes
-            .get_refresh(name)
-            .and_then(|sub| sub.upgrade())
-        {
-            return Ok(fnode);
-        }
+    /// Set fnode created and modified time same as the other fnode
+    #[inline]
+    pub fn copy_times(&mut self, other: &Fnode) {
+        self.ctime = other.ctime;
+        self.mtime = other.mtime;
+    }
 
-        // if child is not in sub node list, load it from fnode cache
-        self.kids
-            .iter()
-            .find(|c| c.name == name)
+    /// Check if fnode is append-only
+    #[inline]
+    pub fn is_append_only(&self) -> bool {
+        self.append_only
+    }
+
+    /// Set fnode append-only flag
+    #[inline]
+    pub fn set_append_only(&mut self, append_only: bool) {
+        self.append_only = append_only;
+    }
+
+    /// Check if current content is encrypted with associated data
+    #[inline]
+    pub fn is_aad_bound(&self) -> bool {
+        self.aad_hash.is_some()
+    }
+
+    #[inline]
+    fn aad_hash_of(aad: &[u8]) -> Option<Hash> {
+        if aad.is_empty() {
+            None
+        } else {
+            Some(Crypto::hash(aad))
+        }
+    }
+
+    // check associated data before changing current content, non-empty
+    // content can only be changed using the same associated data it is
+    // encrypted with
+    fn check_aad(&self, aad: &[u8]) -> Result<()> {
+        if self.curr_len() > 0 && self.aad_hash != Self::aad_hash_of(aad) {
+            return Err(Error::Decrypt);
+        }
+        Ok(())
+    }
+
+    /// Bind current content to associated data
+    #[inline]
+    pub fn bind_aad(&mut self, aad: &[u8]) {
+        self.aad_hash = Self::aad_hash_of(aad);
+    }
+
+    /// Get hash of associated data which current content is bound to
+    #[inline]
+    pub fn aad_hash(&self) -> Option<Hash> {
+        self.aad_hash.clone()
+    }
+
+    /// Set hash of associated data which current content is bound to
+    #[inline]
+    pub fn set_aad_hash(&mut self, aad_hash: Option<Hash>) {
+        self.aad_hash = aad_hash;
+    }
+
+    /// Load root fnode
+    #[inline]
+    pub fn load_root(root_id: &Eid, vol: &VolumeRef) -> Result<FnodeRef> {
+        let root = Cow::<Fnode>::load(root_id, vol)?;
+        Ok(root)
+    }
+
+    /// Load fnode from volume directly, fnode cache is not used
+    #[inline]
+    pub fn load(id: &Eid, vol: &VolumeRef) -> Result<FnodeRef> {
+        Cow::<Fnode>::load(id, vol)
+    }
+
+    // load one child fnode
+    fn load_child(
+        &mut self,
+        name: &str,
+        self_ref: FnodeRef,
+        cache: &Cache,
+        vol: &VolumeRef,
+    ) -> Result<FnodeRef> {
+        // get child fnode from sub node list first
+        if let Some(fnode) = self
+            .sub_nodes
+            .get_refresh(name)
+            .and_then(|sub| sub.upgrade())
+        {
+            return Ok(fnode);
+        }
+
+        // if child is not in sub node list, load it from fnode cache
+        self.find_child(name)
             .ok_or(Error::NotFound)
             .and_then(|child| cache.get(&child.id, vol).map_err(Error::from))
             .map(|child| {
@@ -387,9 +1073,18 @@ impl Fnode {
             })
     }
 
+    // find child entry by name
+    #[inline]
+    fn find_child(&self, name: &str) -> Option<&ChildEntry> {
+        self.kids
+            .binary_search_by(|c| c.name.as_str().cmp(name))
+            .ok()
+            .map(|idx| &self.kids[idx])
+    }
+
     #[inline]
     pub fn has_child(&self, name: &str) -> bool {
-        self.kids.iter().any(|c| c.name == name)
+        self.find_child(name).is_some()
     }
 
     #[inline]
@@ -404,28 +1099,135 @@ impl Fnode {
         cache: &Cache,
         vol: &VolumeRef,
     ) -> Result<FnodeRef> {
+        // children added in current transaction are only in the arm seen
+        // by the transaction
         let mut par = parent.write().unwrap();
-        par.make_mut_naive()
-            .load_child(name, parent.clone(), cache, vol)
+        par.make_mut_naive_in_trans().load_child(
+            name,
+            parent.clone(),
+            cache,
+            vol,
+        )
     }
 
-    fn children_names(&self) -> Vec<String> {
-        self.kids.iter().map(|k| k.name.clone()).collect()
+    // get at most `limit` children names ordered by name, which are greater
+    // than `after`, and a flag indicating if there are more names
+    fn children_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> (Vec<String>, bool) {
+        let start = match after {
+            Some(after) => {
+                self.kids.partition_point(|k| k.name.as_str() <= after)
+            }
+            None => 0,
+        };
+        let end = min(start.saturating_add(limit), self.kids.len());
+        let names = self.kids[start..end]
+            .iter()
+            .map(|k| k.name.clone())
+            .collect();
+        (names, end < self.kids.len())
+    }
+
+    /// Get names and file types of all children
+    pub fn children_types(&self) -> Vec<(String, FileType)> {
+        self.kids
+            .iter()
+            .map(|k| (k.name.clone(), k.ftype))
+            .collect()
+    }
+
+    /// Get id of a child
+    #[inline]
+    pub fn child_id(&self, name: &str) -> Option<&Eid> {
+        self.find_child(name).map(|c| &c.id)
     }
 
-    /// Get children dir entry list
+    /// Get names and ids of all children, sorted by name
+    pub fn children_ids(&self) -> Vec<(String, Eid)> {
+        self.kids
+            .iter()
+            .map(|k| (k.name.clone(), k.id.clone()))
+            .collect()
+    }
+
+    /// Get an iterator over children dir entries
     pub fn read_dir(
         parent: FnodeRef,
         path: &Path,
         cache: &Cache,
+        vol: &VolumeWeakRef,
+        shutter: &ShutterRef,
+    ) -> Result<ReadDir> {
+        if !parent.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
+        }
+        let mut rdr = ReadDir {
+            parent,
+            path: path.to_path_buf(),
+            names: Vec::new().into_iter(),
+            after: None,
+            has_more: true,
+            cache: cache.clone(),
+            vol: vol.clone(),
+            shutter: shutter.clone(),
+        };
+        rdr.load_page()?;
+        Ok(rdr)
+    }
+
+    /// Get a page of children dir entry list ordered by name
+    ///
+    /// At most `limit` entries whose names are greater than `after` are
+    /// returned, together with a flag indicating if there are more entries.
+    pub fn read_dir_page(
+        parent: FnodeRef,
+        path: &Path,
+        after: Option<&str>,
+        limit: usize,
+        cache: &Cache,
         vol: &VolumeRef,
-    ) -> Result<Vec<DirEntry>> {
+    ) -> Result<(Vec<DirEntry>, bool)> {
         let mut par = parent.write().unwrap();
         let par = par.make_mut_naive();
         if !par.is_dir() {
             return Err(Error::NotDir);
         }
 
+        let (child_names, has_more) = par.children_page(after, limit);
+
+        let ents = par.dir_entries(&parent, path, &child_names, cache, vol)?;
+        Ok((ents, has_more))
+    }
+
+    // make dir entries for the specified children
+    fn dir_entries(
+        &mut self,
+        parent: &FnodeRef,
+        path: &Path,
+        child_names: &[String],
+        cache: &Cache,
+        vol: &VolumeRef,
+    ) -> Result<Vec<DirEntry>> {
+        let mut ret = Vec::new();
+        for name in child_names.iter() {
+            let child_ref =
+                self.load_child(name, parent.clone(), cache, vol)?;
+            let child = child_ref.read().unwrap();
+            ret.push(DirEntry {
+                path: Fnode::child_path(path, name),
+                metadata: child.metadata(),
+                name: name.clone(),
+            });
+        }
+
+        Ok(ret)
+    }
+
+    // make path of a child, it always uses '/' as separator
+    pub(super) fn child_path(path: &Path, name: &str) -> PathBuf {
         let parent_path = {
             #[cfg(windows)]
             {
@@ -440,21 +1242,7 @@ impl Fnode {
                 path
             }
         };
-
-        let mut ret = Vec::new();
-        let child_names = par.children_names();
-
-        for name in child_names.iter() {
-            let child_ref = par.load_child(name, parent.clone(), cache, vol)?;
-            let child = child_ref.read().unwrap();
-            ret.push(DirEntry {
-                path: parent_path.join(name),
-                metadata: child.metadata(),
-                name: name.clone(),
-            });
-        }
-
-        Ok(ret)
+        parent_path.join(name)
     }
 
     /// Add child to parent fnode
@@ -464,22 +1252,31 @@ impl Fnode {
         name: &str,
         txmgr: &TxMgrRef,
     ) -> Result<()> {
-        let mut parent_cow = parent.write().unwrap();
-        let par = parent_cow.make_mut(txmgr)?;
-
-        // add to child to parent's children list
-        let mut kid = child.write().unwrap();
-        par.kids.push(ChildEntry::new(kid.id(), kid.ftype, name));
-
-        // update child's parent
-        kid.make_mut(txmgr)?.parent = Some(parent.clone());
-
-        // add to parent's sub node list and update modified time
-        par.sub_nodes
-            .insert(name.to_string(), Arc::downgrade(child));
-        par.mtime = Time::now();
+        let size = {
+            let mut parent_cow = parent.write().unwrap();
+            let par = parent_cow.make_mut(txmgr)?;
+
+            // add to child to parent's children list, keeping it sorted
+            let mut kid = child.write().unwrap();
+            let idx = par
+                .kids
+                .binary_search_by(|c| c.name.as_str().cmp(name))
+                .unwrap_or_else(|idx| idx);
+            par.kids
+                .insert(idx, ChildEntry::new(kid.id(), kid.ftype, name));
+
+            // update child's parent
+            kid.make_mut(txmgr)?.parent = Some(parent.clone());
+
+            // add to parent's sub node list and update modified time
+            par.sub_nodes
+                .insert(name.to_string(), Arc::downgrade(child));
+            par.mtime = Time::now();
+
+            kid.tracked_size()
+        };
 
-        Ok(())
+        Fnode::adjust_dir_size(Some(parent.clone()), size as isize, txmgr)
     }
 
     /// Remove child fnode from parent
@@ -487,25 +1284,63 @@ impl Fnode {
         fnode: &FnodeRef,
         txmgr: &TxMgrRef,
     ) -> Result<()> {
-        let child = fnode.read().unwrap();
-        match child.parent {
-            Some(ref parent) => {
-                let mut par = parent.write().unwrap();
-                let par = par.make_mut(txmgr)?;
-                let child_idx = par
-                    .kids
-                    .iter()
-                    .position(|c| c.id == *child.id())
-                    .ok_or(Error::NotFound)?;
-                {
-                    let name = &par.kids[child_idx].name;
-                    par.sub_nodes.remove(name);
+        let (parent, size) = {
+            let child = fnode.read().unwrap();
+            match child.parent {
+                Some(ref parent) => {
+                    let mut par = parent.write().unwrap();
+                    let par = par.make_mut(txmgr)?;
+                    let child_idx = par
+                        .kids
+                        .iter()
+                        .position(|c| c.id == *child.id())
+                        .ok_or(Error::NotFound)?;
+                    {
+                        let name = &par.kids[child_idx].name;
+                        par.sub_nodes.remove(name);
+                    }
+                    par.kids.remove(child_idx);
+                    (parent.clone(), child.tracked_size())
+                }
+                None => return Err(Error::IsRoot),
+            }
+        };
+
+        Fnode::adjust_dir_size(Some(parent), -(size as isize), txmgr)
+    }
+
+    /// Resolve current path of fnode by walking up its parents
+    ///
+    /// Return `NotFound` error if fnode or any of its ancestors has been
+    /// removed from its parent.
+    pub fn path(fnode: &FnodeRef) -> Result<PathBuf> {
+        let mut names = Vec::new();
+        let mut curr = fnode.clone();
+        loop {
+            let (id, parent) = {
+                let node = curr.read().unwrap();
+                (node.id().clone(), node.parent.clone())
+            };
+            match parent {
+                Some(parent) => {
+                    {
+                        let par = parent.read().unwrap();
+                        let child = par
+                            .kids
+                            .iter()
+                            .find(|c| c.id == id)
+                            .ok_or(Error::NotFound)?;
+                        names.push(child.name.clone());
+                    }
+                    curr = parent;
                 }
-                par.kids.remove(child_idx);
-                Ok(())
+                None => break,
             }
-            None => Err(Error::IsRoot),
         }
+
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        Ok(path)
     }
 
     /// get a specified version
@@ -540,6 +1375,11 @@ impl Fnode {
             .ok_or(Error::NoVersion)?;
         let ver = self.vers.remove(idx).unwrap();
 
+        // inline content is not in store
+        if ver.is_inline() {
+            return Ok(());
+        }
+
         if let Some(ctn) = Store::deref_content(store, &ver.content_id)? {
             // content is not used anymore, remove it
             let mut content = ctn.write().unwrap();
@@ -562,6 +1402,112 @@ impl Fnode {
         Ok(())
     }
 
+    /// Remove oldest versions so that at most `keep` versions are retained,
+    /// return the number of removed versions
+    pub fn prune_versions(
+        &mut self,
+        keep: usize,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<usize> {
+        let keep = keep.max(1);
+        let mut pruned = 0;
+        while self.vers.len() > keep {
+            let retire = self.vers.front().unwrap().num;
+            self.remove_version(retire, store, txmgr)?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+
+    // get content of a specified version, inline content has no content
+    fn ver_content(&self, ver: &Version, store: &StoreRef) -> Result<Content> {
+        if ver.is_inline() {
+            return Err(Error::NoContent);
+        }
+        let store = store.read().unwrap();
+        let ctn_ref = store.get_content(&ver.content_id)?;
+        let ctn = ctn_ref.read().unwrap();
+        Ok(ctn.clone())
+    }
+
+    /// Get content ids of all versions which are not stored inline
+    pub fn content_ids(&self) -> Vec<Eid> {
+        self.vers
+            .iter()
+            .filter(|v| !v.is_inline())
+            .map(|v| v.content_id.clone())
+            .collect()
+    }
+
+    /// Get ids of all segments used by all versions
+    pub fn seg_ids(&self, store: &StoreRef) -> Result<Vec<Eid>> {
+        let mut ids: Vec<Eid> = Vec::new();
+        for ver in self.vers.iter().filter(|v| !v.is_inline()) {
+            for id in self.ver_content(ver, store)?.seg_ids() {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Check if chunk map refers to any of the segments
+    pub fn refers_segs(&self, seg_ids: &[Eid]) -> bool {
+        seg_ids.iter().any(|id| self.chk_map.has_segment(id))
+    }
+
+    /// Remove segments from chunk map, so they won't be used for chunk
+    /// dedup anymore
+    pub fn forget_segs(&mut self, seg_ids: &[Eid]) {
+        for id in seg_ids {
+            self.chk_map.remove_segment(id);
+        }
+    }
+
+    /// Check if any segment used by all versions can be shrunk
+    pub fn has_garbage(&self, store: &StoreRef) -> Result<bool> {
+        let seg_ids = self.seg_ids(store)?;
+        let store = store.read().unwrap();
+        for seg_id in seg_ids.iter() {
+            let seg_ref = store.get_seg(seg_id)?;
+            let seg = seg_ref.read().unwrap();
+            if seg.has_garbage() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Shrink all partially used segments of all versions
+    pub fn shrink_segs(
+        &mut self,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        let vers = self.vers.clone();
+        for ver in vers.iter().filter(|v| !v.is_inline()) {
+            let content = self.ver_content(ver, store)?;
+            content.shrink_segs(&mut self.chk_map, store, txmgr)?;
+        }
+        Ok(())
+    }
+
+    /// Check if current version content is fragmented
+    pub fn is_fragmented(&self, store: &StoreRef) -> Result<bool> {
+        if self.curr_ver().is_inline() {
+            return Ok(false);
+        }
+        let content = self.ver_content(self.curr_ver(), store)?;
+        Ok(content.is_fragmented())
+    }
+
+    /// Get content hash of current version
+    pub fn content_hash(&self, store: &StoreRef) -> Result<Hash> {
+        self.ver_hash(self.curr_ver(), store)
+    }
+
     // add a new content version to fnode
     // return true if the content is not duplicated, otherwise return false
     pub fn add_version(
@@ -575,33 +1521,101 @@ impl Fnode {
         // try to dedup content in store
         let (no_dup, deduped_id) = Store::dedup_content(store, &content)?;
 
-        // create a new version and append to version list
-        let ver =
-            Version::new(self.curr_ver_num() + 1, &deduped_id, content.len());
-        self.mtime = ver.ctime;
-        self.vers.push_back(ver);
-
         // if content is not duplicated, link the content
         if no_dup {
             content.link(store, txmgr)?;
         }
 
-        // evict retired version if any
+        // create a new version and append to version list
+        let ver = Version::new(
+            self.curr_ver_num() + 1,
+            &deduped_id,
+            content.len(),
+            content.hash(),
+        );
+        self.push_version(ver, store, txmgr)?;
+
+        Ok(no_dup)
+    }
+
+    /// Add a new version sharing content with an existing version
+    pub fn add_shared_version(
+        &mut self,
+        ver: &Version,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        assert!(self.is_file() && !ver.is_inline());
+        let hash = self.ver_hash(ver, store)?;
+        Store::share_content(store, &ver.content_id)?;
+        let ver = Version::new(
+            self.curr_ver_num() + 1,
+            &ver.content_id,
+            ver.content_len,
+            &hash,
+        );
+        self.push_version(ver, store, txmgr)
+    }
+
+    /// Add a new version whose content is stored inline
+    pub fn add_inline_version(
+        &mut self,
+        data: Vec<u8>,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        assert!(self.is_file());
+        let ver = Version::new_inline(self.curr_ver_num() + 1, data);
+        self.push_version(ver, store, txmgr)
+    }
+
+    // append version to version list and evict retired version if any
+    fn push_version(
+        &mut self,
+        ver: Version,
+        store: &StoreRef,
+        txmgr: &TxMgrRef,
+    ) -> Result<()> {
+        self.mtime = ver.ctime;
+        self.vers.push_back(ver);
         if self.vers.len() > self.opts.version_limit as usize {
             let retire = self.vers.front().unwrap().num;
             self.remove_version(retire, store, txmgr)?;
         }
+        Ok(())
+    }
 
-        Ok(no_dup)
+    /// Get inline data of current version, return None if it is not inline
+    #[inline]
+    pub fn curr_inline(&self) -> Option<&[u8]> {
+        self.curr_ver().inline.as_deref()
+    }
+
+    // check if content written with associated data can be stored inline,
+    // only empty or inline content can be continued writing inline
+    fn can_inline(&self, aad: &[u8]) -> bool {
+        self.opts.inline_threshold > 0
+            && aad.is_empty()
+            && self.curr_len() <= self.opts.inline_threshold
+            && (self.curr_len() == 0 || self.curr_ver().is_inline())
     }
 
     /// Get reader for sepcified version number
-    pub fn version_reader(
+    fn version_reader(
         &self,
         ver_num: usize,
+        aad: &[u8],
+        buf_size: usize,
         store: &StoreWeakRef,
-    ) -> Result<ContentReader> {
+    ) -> Result<VersionReader> {
         let ver = self.ver(ver_num).ok_or(Error::NoVersion)?;
+        if let Some(ref data) = ver.inline {
+            // inline content is never bound to associated data
+            if !aad.is_empty() {
+                return Err(Error::Decrypt);
+            }
+            return Ok(VersionReader::Inline(Cursor::new(data.clone())));
+        }
         let content = {
             let store = store.upgrade().ok_or(Error::RepoClosed)?;
             let st = store.read().unwrap();
@@ -609,23 +1623,165 @@ impl Fnode {
             let ctn = ctn_ref.read().unwrap();
             ctn.clone()
         };
-        Ok(ContentReader::new(content, store))
+        let rdr =
+            ContentReader::new(content, aad, store).with_buf_size(buf_size);
+        Ok(VersionReader::Content(rdr))
+    }
+
+    /// Get content id of current version
+    ///
+    /// Return None if current content is stored inline.
+    #[inline]
+    pub fn curr_content_id(&self) -> Option<&Eid> {
+        let ver = self.curr_ver();
+        if ver.is_inline() {
+            None
+        } else {
+            Some(&ver.content_id)
+        }
     }
 
     /// Clone a new current content
+    ///
+    /// Return `NoContent` error if current content is stored inline.
+    #[inline]
     pub fn clone_current_content(&self, store: &StoreRef) -> Result<Content> {
-        let store = store.read().unwrap();
-        let curr_ctn = store.get_content(&self.curr_ver().content_id)?;
-        let content = curr_ctn.read().unwrap();
-        Ok(content.clone())
+        self.ver_content(self.curr_ver(), store)
+    }
+
+    /// Get raw content reader for current version
+    pub fn raw_reader(
+        &self,
+        aad: &[u8],
+        store: &StoreWeakRef,
+    ) -> Result<ContentRawReader> {
+        let store = store.upgrade().ok_or(Error::RepoClosed)?;
+        let content = self.clone_current_content(&store)?;
+        ContentRawReader::new(&content, aad, &store)
+    }
+
+    /// Import raw content stream as a new version, return content length
+    pub fn write_raw<R: Read>(
+        handle: Handle,
+        rdr: &mut R,
+        txid: Txid,
+    ) -> Result<usize> {
+        // importing raw content replaces the whole content
+        if handle.fnode.read().unwrap().is_append_only() {
+            return Err(Error::AppendOnly);
+        }
+
+        let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let content = import_raw(rdr, txid, &handle.aad, &store, &txmgr)?;
+        handle.check_len(content.len())?;
+
+        // dedup content and add it as a new version
+        let old_len = {
+            let mut fnode_cow = handle.fnode.write().unwrap();
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            let old_len = fnode.curr_len();
+            if !fnode.add_version(content.clone(), &store, &txmgr)? {
+                // content is duplicated, weak unlink the imported content
+                content.unlink_weak(&mut fnode.chk_map, &store, &txmgr)?;
+            }
+            fnode.bind_aad(&handle.aad);
+            old_len
+        };
+        Fnode::propagate_len(&handle.fnode, old_len, &txmgr)?;
+
+        Ok(content.len())
+    }
+
+    /// Add a new version with the content of a specified version
+    ///
+    /// The content is shared with the specified version, no data is copied.
+    pub fn restore_version(handle: Handle, ver_num: usize) -> Result<()> {
+        let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+
+        let old_len = {
+            let mut fnode_cow = handle.fnode.write().unwrap();
+            let ver = fnode_cow.ver(ver_num).ok_or(Error::NoVersion)?.clone();
+
+            // restoring replaces the whole content
+            if fnode_cow.is_append_only() {
+                return Err(Error::AppendOnly);
+            }
+            fnode_cow.check_aad(&handle.aad)?;
+
+            let old_len = fnode_cow.curr_len();
+            match ver.inline {
+                Some(data) => {
+                    let fnode = fnode_cow.make_mut(&txmgr)?;
+                    fnode.add_inline_version(data, &store, &txmgr)?;
+                }
+                None => {
+                    let fnode = fnode_cow.make_mut(&txmgr)?;
+                    fnode.add_shared_version(&ver, &store, &txmgr)?;
+                }
+            }
+            old_len
+        };
+        Fnode::propagate_len(&handle.fnode, old_len, &txmgr)
+    }
+
+    /// Remove a historical version and dereference its content, the current
+    /// version cannot be removed
+    pub fn remove_old_version(handle: Handle, ver_num: usize) -> Result<()> {
+        let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+
+        let mut fnode_cow = handle.fnode.write().unwrap();
+        if fnode_cow.ver(ver_num).is_none() {
+            return Err(Error::NoVersion);
+        }
+        if ver_num == fnode_cow.curr_ver_num() {
+            return Err(Error::InvalidArgument);
+        }
+        fnode_cow
+            .make_mut(&txmgr)?
+            .remove_version(ver_num, &store, &txmgr)
+    }
+
+    /// Rewrite current version content as a new version, so its data is
+    /// stored contiguously in new segments
+    ///
+    /// The modified time is not changed.
+    pub fn defrag(handle: Handle, txid: Txid) -> Result<()> {
+        let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let mtime = handle.fnode.read().unwrap().mtime;
+
+        let mut rdr = Reader::new_current(
+            handle.fnode.clone(),
+            &handle.aad,
+            FRAME_SIZE,
+            &handle.store,
+        )?;
+        let mut wtr = Writer::new(handle.clone(), txid)?;
+        io::copy(&mut rdr, &mut wtr)?;
+        wtr.finish()?;
+
+        let mut fnode_cow = handle.fnode.write().unwrap();
+        fnode_cow.make_mut(&txmgr)?.mtime = mtime;
+
+        Ok(())
     }
 
     /// Set file to specified length
     ///
     /// if new length is equal to old length, do nothing
     pub fn set_len(handle: Handle, len: usize, txid: Txid) -> Result<()> {
+        handle.check_len(len)?;
+
         let curr_len = {
             let fnode = handle.fnode.read().unwrap();
+            if fnode.is_append_only() && len < fnode.curr_len() {
+                return Err(Error::AppendOnly);
+            }
+            if len > 0 {
+                fnode.check_aad(&handle.aad)?;
+            }
             fnode.curr_len()
         };
 
@@ -634,16 +1790,33 @@ impl Fnode {
                 // truncate
                 let store = handle.store.upgrade().ok_or(Error::RepoClosed)?;
                 let txmgr = handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
-                let mut fnode_cow = handle.fnode.write().unwrap();
-                let new_ctn = {
-                    let mut ctn = fnode_cow.clone_current_content(&store)?;
-                    ctn.truncate(len, &store)?;
-                    ctn
-                };
-
-                // dedup content, if it is not duplicated then link the content
-                let fnode = fnode_cow.make_mut(&txmgr)?;
-                fnode.add_version(new_ctn, &store, &txmgr)?;
+                {
+                    let mut fnode_cow = handle.fnode.write().unwrap();
+                    let inline =
+                        fnode_cow.curr_inline().map(|d| d[..len].to_vec());
+                    match inline {
+                        Some(data) => {
+                            // truncate inline content
+                            let fnode = fnode_cow.make_mut(&txmgr)?;
+                            fnode.add_inline_version(data, &store, &txmgr)?;
+                        }
+                        None => {
+                            let new_ctn = {
+                                let mut ctn =
+                                    fnode_cow.clone_current_content(&store)?;
+                                ctn.truncate(len, &handle.aad, &store)?;
+                                ctn
+                            };
+
+                            // dedup content, if it is not duplicated then
+                            // link the content
+                            let fnode = fnode_cow.make_mut(&txmgr)?;
+                            fnode.add_version(new_ctn, &store, &txmgr)?;
+                            fnode.bind_aad(&handle.aad);
+                        }
+                    }
+                }
+                Fnode::propagate_len(&handle.fnode, curr_len, &txmgr)?;
             }
             Ordering::Less => {
                 // append
@@ -676,6 +1849,9 @@ impl Debug for Fnode {
             .field("kids", &self.kids)
             .field("vers", &self.vers)
             .field("chk_map", &self.chk_map)
+            .field("append_only", &self.append_only)
+            .field("dir_size", &self.dir_size)
+            .field("aad_hash", &self.aad_hash)
             .field("sub_nodes", &self.sub_nodes)
             .finish()
     }
@@ -700,6 +1876,15 @@ impl Cowable for Fnode {
             });
         Ok(())
     }
+
+    fn frozen_copy(&self) -> Option<Self> {
+        // references to other fnodes are not kept in the copy
+        Some(Fnode {
+            parent: None,
+            sub_nodes: Fnode::default_sub_nodes(),
+            ..self.clone()
+        })
+    }
 }
 
 impl<'de> IntoCow<'de> for Fnode {}
@@ -710,37 +1895,145 @@ pub type FnodeRef = CowRef<Fnode>;
 /// Fnode weak reference type
 pub type FnodeWeakRef = CowWeakRef<Fnode>;
 
+// version content reader, content reader has a frame buffer so it can be
+// read line by line, inline content is already in memory
+#[derive(Debug)]
+enum VersionReader {
+    Content(ContentReader),
+    Inline(Cursor<Vec<u8>>),
+}
+
+impl VersionReader {
+    fn read_range(
+        &self,
+        range: Range<usize>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        match self {
+            VersionReader::Content(rdr) => rdr.read_range(range, buf),
+            VersionReader::Inline(rdr) => {
+                let data = rdr.get_ref();
+                let end = min(range.end, data.len());
+                let start = min(range.start, end);
+                buf.extend_from_slice(&data[start..end]);
+                Ok(end - start)
+            }
+        }
+    }
+}
+
+impl Read for VersionReader {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            VersionReader::Content(rdr) => rdr.read(buf),
+            VersionReader::Inline(rdr) => rdr.read(buf),
+        }
+    }
+}
+
+impl BufRead for VersionReader {
+    #[inline]
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        match self {
+            VersionReader::Content(rdr) => rdr.fill_buf(),
+            VersionReader::Inline(rdr) => rdr.fill_buf(),
+        }
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        match self {
+            VersionReader::Content(rdr) => rdr.consume(amt),
+            VersionReader::Inline(rdr) => rdr.consume(amt),
+        }
+    }
+}
+
+impl Seek for VersionReader {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        match self {
+            VersionReader::Content(rdr) => rdr.seek(pos),
+            VersionReader::Inline(rdr) => rdr.seek(pos),
+        }
+    }
+
+    // get position without discarding buffered data
+    #[inline]
+    fn stream_position(&mut self) -> IoResult<u64> {
+        match self {
+            VersionReader::Content(rdr) => rdr.stream_position(),
+            VersionReader::Inline(rdr) => rdr.stream_position(),
+        }
+    }
+}
+
 /// Fnode Reader
 #[derive(Debug)]
 pub struct Reader {
     ver: usize,
-    rdr: ContentReader,
+    rdr: VersionReader,
 }
 
 impl Reader {
-    /// Create a reader for specified version
+    /// Create a reader for specified version, content is read through a
+    /// buffer of `buf_size` bytes
     pub fn new(
         fnode: FnodeRef,
         ver: usize,
+        aad: &[u8],
+        buf_size: usize,
         store: &StoreWeakRef,
     ) -> Result<Self> {
         let fnode = fnode.read().unwrap();
-        let rdr = fnode.version_reader(ver, store)?;
+        let rdr = fnode.version_reader(ver, aad, buf_size, store)?;
         Ok(Reader { ver, rdr })
     }
 
-    /// Create a reader for current version
-    pub fn new_current(fnode: FnodeRef, store: &StoreWeakRef) -> Result<Self> {
+    /// Create a reader for current version, content is read through a
+    /// buffer of `buf_size` bytes
+    pub fn new_current(
+        fnode: FnodeRef,
+        aad: &[u8],
+        buf_size: usize,
+        store: &StoreWeakRef,
+    ) -> Result<Self> {
         let fnode = fnode.read().unwrap();
         let ver = fnode.curr_ver_num();
-        let rdr = fnode.version_reader(ver, store)?;
+        let rdr = fnode.version_reader(ver, aad, buf_size, store)?;
         Ok(Reader { ver, rdr })
     }
 
+    /// Create a reader for inline content which is not in fnode
+    #[inline]
+    pub(super) fn new_inline(ver: usize, data: Vec<u8>) -> Self {
+        let rdr = VersionReader::Inline(Cursor::new(data));
+        Reader { ver, rdr }
+    }
+
+    /// Create a reader for content which is not in fnode
+    #[inline]
+    pub(super) fn new_content(ver: usize, rdr: ContentReader) -> Self {
+        let rdr = VersionReader::Content(rdr);
+        Reader { ver, rdr }
+    }
+
     #[inline]
     pub fn version_num(&self) -> usize {
         self.ver
     }
+
+    /// Read content in range and append to buffer, reader position is not
+    /// changed
+    #[inline]
+    pub fn read_range(
+        &self,
+        range: Range<usize>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        self.rdr.read_range(range, buf)
+    }
 }
 
 impl Read for Reader {
@@ -750,76 +2043,339 @@ impl Read for Reader {
     }
 }
 
+impl BufRead for Reader {
+    #[inline]
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        self.rdr.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.rdr.consume(amt)
+    }
+}
+
 impl Seek for Reader {
     #[inline]
     fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
         self.rdr.seek(pos)
     }
+
+    #[inline]
+    fn stream_position(&mut self) -> IoResult<u64> {
+        self.rdr.stream_position()
+    }
 }
 
 /// Fnode Writer
+///
+/// Data is buffered in memory while the content can be stored inline, it
+/// is written to store once the buffered data exceeds inline threshold.
+///
+/// Data written to store is staged as a content starting from the write
+/// position. When write position is moved, the current stage is finished
+/// and a new one is started, all stages are then merged into current
+/// content in order when finishing, so later writes overlay earlier ones.
 #[derive(Debug)]
 pub struct Writer {
-    inner: StoreWriter,
+    txid: Txid,
+    inline: Option<Cursor<Vec<u8>>>,
+
+    // inline data after current position which is not overwritten yet, it
+    // is written to store when finishing
+    tail: Vec<u8>,
+
+    inner: Option<StoreWriter>,
     handle: Handle,
+
+    // current write position and content length including written data
+    pos: usize,
+    len: usize,
+
+    // finished stage contents, and the chunk map carried over to the next
+    // stage
+    stages: Vec<Content>,
+    chk_map: Option<ChunkMap>,
+
+    // whether current stage has data written
+    staged: bool,
 }
 
 impl Writer {
     pub fn new(handle: Handle, txid: Txid) -> Result<Self> {
-        let chk_map = {
+        let (inline, len) = {
             let f = handle.fnode.read().unwrap();
-            f.chk_map.clone()
+            f.check_aad(&handle.aad)?;
+            let inline = if f.can_inline(&handle.aad) {
+                let data = if handle.replace {
+                    Vec::new()
+                } else {
+                    f.curr_inline().unwrap_or_default().to_vec()
+                };
+                Some(Cursor::new(data))
+            } else {
+                None
+            };
+            let len = if handle.replace { 0 } else { f.curr_len() };
+            (inline, len)
+        };
+        let mut wtr = Writer {
+            txid,
+            inline,
+            tail: Vec::new(),
+            inner: None,
+            handle,
+            pos: 0,
+            len,
+            stages: Vec::new(),
+            chk_map: None,
+            staged: false,
         };
-        let inner =
-            StoreWriter::new(txid, chk_map, &handle.txmgr, &handle.store)?;
-        Ok(Writer { inner, handle })
+        if wtr.inline.is_none() {
+            wtr.inner = Some(wtr.new_inner()?);
+        }
+        Ok(wtr)
+    }
+
+    fn new_inner(&mut self) -> Result<StoreWriter> {
+        let (chk_map, compress) = {
+            let f = self.handle.fnode.read().unwrap();
+            let chk_map = match self.chk_map.take() {
+                Some(chk_map) => chk_map,
+                None => f.chk_map.clone(),
+            };
+            (chk_map, f.opts.compress)
+        };
+        StoreWriter::new(
+            self.txid,
+            chk_map,
+            self.handle.low_memory,
+            &self.handle.aad,
+            compress,
+            &self.handle.txmgr,
+            &self.handle.store,
+        )
+    }
+
+    // write inline buffer before current position to store and keep the
+    // rest as tail, so the stage content will cover all the inline content
+    fn spill(&mut self) -> Result<()> {
+        let buf = self.inline.take().unwrap();
+        let pos = buf.position() as usize;
+        let mut data = buf.into_inner();
+        if pos > data.len() {
+            // position was moved beyond the end, fill the gap with zeros
+            data.resize(pos, 0);
+        }
+        self.tail = data.split_off(pos);
+        let mut inner = self.new_inner()?;
+        inner.seek(SeekFrom::Start(0))?;
+        inner.write_all(&data)?;
+        self.inner = Some(inner);
+        self.staged = true;
+        Ok(())
+    }
+
+    // finish current stage and start a new one, inline data not overwritten
+    // yet is written to current stage first
+    fn finish_stage(&mut self) -> Result<()> {
+        let mut inner = self.inner.take().unwrap();
+        inner.write_all(&self.tail)?;
+        self.tail.clear();
+        let (stg_ctn, chk_map) = inner.finish()?;
+        self.stages.push(stg_ctn);
+        self.chk_map = Some(chk_map);
+        self.inner = Some(self.new_inner()?);
+        self.staged = false;
+        Ok(())
+    }
+
+    // move write position to `pos`, data written after this overlays the
+    // data written before, gap beyond the end is filled with zeros
+    pub fn seek_to(&mut self, pos: usize) -> Result<()> {
+        self.handle.check_len(pos)?;
+        if let Some(ref mut inline) = self.inline {
+            inline.seek(SeekFrom::Start(pos as u64))?;
+            self.pos = pos;
+            return Ok(());
+        }
+        if pos == self.pos {
+            return Ok(());
+        }
+
+        if self.staged {
+            self.finish_stage()?;
+        }
+        let start = min(pos, self.len);
+        self.inner
+            .as_mut()
+            .unwrap()
+            .seek(SeekFrom::Start(start as u64))?;
+        self.pos = start;
+        io::copy(&mut io::repeat(0).take((pos - start) as u64), self)?;
+        Ok(())
+    }
+
+    // make data written so far durable, inline data is kept in memory until
+    // finish
+    pub fn sync_data(&mut self) -> Result<()> {
+        match self.inner {
+            Some(ref mut inner) => inner.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    // number of bytes written to volume so far, inline data is always kept
+    // in memory so it is not counted
+    pub fn committed_len(&self) -> usize {
+        let staged: usize = self.stages.iter().map(Content::len).sum();
+        match self.inner {
+            Some(ref inner) => staged + inner.committed_len(),
+            None => 0,
+        }
     }
 
-    pub fn finish(self) -> Result<usize> {
+    // finish writing inline content
+    fn finish_inline(self, buf: Cursor<Vec<u8>>) -> Result<usize> {
         let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
         let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
-        let (stg_ctn, chk_map) = self.inner.finish()?;
-        let handle = &self.handle;
+        let end_pos = buf.position() as usize;
 
-        let mut fnode_cow = handle.fnode.write().unwrap();
+        let old_len = {
+            let mut fnode_cow = self.handle.fnode.write().unwrap();
+
+            // skip the version if it is same as current version
+            let data = buf.into_inner();
+            if self.handle.skip_identical
+                && fnode_cow.content_hash(&store)? == Crypto::hash(&data)
+            {
+                return Ok(end_pos);
+            }
 
-        // merge stage content to current content
-        let merged_ctn = {
-            let mut ctn = fnode_cow.clone_current_content(&store)?;
-            ctn.merge_from(&stg_ctn, &store)?;
-            ctn
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            let old_len = fnode.curr_len();
+            fnode.add_inline_version(data, &store, &txmgr)?;
+            fnode.bind_aad(&[]);
+            old_len
         };
+        Fnode::propagate_len(&self.handle.fnode, old_len, &txmgr)?;
+
+        Ok(end_pos)
+    }
 
-        // dedup content and add deduped content as a new version
-        let fnode = fnode_cow.make_mut(&txmgr)?;
-        if !fnode.add_version(merged_ctn, &store, &txmgr)? {
-            // content is duplicated, weak unlink the stage content
-            stg_ctn.unlink_weak(&mut fnode.chk_map, &store, &txmgr)?;
+    pub fn finish(mut self) -> Result<usize> {
+        if let Some(buf) = self.inline.take() {
+            return self.finish_inline(buf);
         }
 
-        // udpate fnode chunk map
-        fnode.chk_map = chk_map;
+        let store = self.handle.store.upgrade().ok_or(Error::RepoClosed)?;
+        let txmgr = self.handle.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+        let mut inner = self.inner.take().unwrap();
+        inner.write_all(&self.tail)?;
+        let (stg_ctn, mut chk_map) = inner.finish()?;
+        let handle = &self.handle;
 
-        Ok(stg_ctn.end_offset())
+        let old_len = {
+            let mut fnode_cow = handle.fnode.write().unwrap();
+
+            // merge stage contents to current content in order, stage
+            // content replaces the whole inline content, or the whole
+            // content in replace mode
+            let merged_ctn = {
+                let mut ctn =
+                    if handle.replace || fnode_cow.curr_ver().is_inline() {
+                        Content::new()
+                    } else {
+                        fnode_cow.clone_current_content(&store)?
+                    };
+                for stage in self.stages.iter().chain(Some(&stg_ctn)) {
+                    ctn.merge_from(stage, &handle.aad, &store)?;
+                }
+                ctn
+            };
+
+            // skip the version if it is same as current version
+            let skip = handle.skip_identical
+                && fnode_cow.content_hash(&store)? == *merged_ctn.hash();
+
+            // dedup content and add deduped content as a new version
+            let fnode = fnode_cow.make_mut(&txmgr)?;
+            let old_len = fnode.curr_len();
+            if skip {
+                // store is not added to transaction by dedup when version
+                // is skipped, add it so the segments removed below are
+                // evicted from its cache on commit
+                let mut store = store.write().unwrap();
+                store.make_mut(&txmgr)?;
+            }
+            if skip || !fnode.add_version(merged_ctn, &store, &txmgr)? {
+                // content is duplicated, weak unlink the stage content
+                stg_ctn.unlink_weak(&mut chk_map, &store, &txmgr)?;
+            }
+
+            // remove segments of earlier stages which are wholly overlaid
+            // by later stages
+            for stage in self.stages.iter() {
+                stage.unlink_weak(&mut chk_map, &store, &txmgr)?;
+            }
+
+            // udpate fnode chunk map and associated data binding
+            fnode.chk_map = chk_map;
+            fnode.bind_aad(&handle.aad);
+
+            old_len
+        };
+        Fnode::propagate_len(&handle.fnode, old_len, &txmgr)?;
+
+        Ok(stg_ctn.end_offset() - self.tail.len())
     }
 }
 
 impl Write for Writer {
-    #[inline]
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-        self.inner.write(buf)
+        // content cannot grow beyond the maximum length
+        map_io_err!(self.handle.check_len(self.pos + buf.len()))?;
+
+        if let Some(ref mut inline) = self.inline {
+            let threshold = {
+                let f = self.handle.fnode.read().unwrap();
+                f.opts.inline_threshold
+            };
+            if inline.position() as usize + buf.len() <= threshold {
+                let written = inline.write(buf)?;
+                self.pos += written;
+                self.len = self.len.max(self.pos);
+                return Ok(written);
+            }
+            map_io_err!(self.spill())?;
+        }
+        let written = self.inner.as_mut().unwrap().write(buf)?;
+        let overwritten = min(written, self.tail.len());
+        self.tail.drain(..overwritten);
+        self.pos += written;
+        self.len = self.len.max(self.pos);
+        self.staged = true;
+        Ok(written)
     }
 
     #[inline]
     fn flush(&mut self) -> IoResult<()> {
-        self.inner.flush()
+        match self.inner {
+            Some(ref mut inner) => inner.flush(),
+            None => Ok(()),
+        }
     }
 }
 
 impl Seek for Writer {
     #[inline]
     fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
-        self.inner.seek(pos)
+        let pos = match self.inline {
+            Some(ref mut inline) => inline.seek(pos),
+            None => self.inner.as_mut().unwrap().seek(pos),
+        }?;
+        self.pos = pos as usize;
+        Ok(pos)
     }
 }
 
@@ -0,0 +1,242 @@
+use std::fmt::{self, Debug};
+use std::path::{Path, PathBuf};
+
+use super::fnode::{
+    Cache as FnodeCache, DirEntry, Fnode, FnodeRef, Metadata,
+    Reader as FnodeReader,
+};
+use super::fs::{check_path, normalize_path, ShutterRef, MAX_SYMLINK_HOPS};
+use crate::content::{ContentReader, StoreRef, StoreWeakRef};
+use crate::error::{Error, Result};
+use crate::trans::{Eid, Id, WalPin};
+use crate::volume::VolumeWeakRef;
+
+/// Frozen file system
+///
+/// It sees the file system as it was when the wal queue was pinned. Fnodes
+/// and content are loaded on demand, the ones updated after that point are
+/// read from the copies kept in the pin. Entities removed after it is taken
+/// are not recycled until it is dropped.
+pub struct FrozenFs {
+    root: FnodeRef,
+    fcache: FnodeCache,
+    store: StoreWeakRef,
+    vol: VolumeWeakRef,
+    shutter: ShutterRef,
+    pin: WalPin,
+}
+
+impl FrozenFs {
+    /// Create frozen file system
+    ///
+    /// The wal queue must be pinned at the point to be frozen.
+    pub(super) fn new(
+        root: &FnodeRef,
+        fcache: &FnodeCache,
+        store: &StoreRef,
+        vol: VolumeWeakRef,
+        shutter: &ShutterRef,
+        pin: WalPin,
+    ) -> Self {
+        FrozenFs {
+            root: root.clone(),
+            fcache: fcache.clone(),
+            store: StoreRef::downgrade(store),
+            vol,
+            shutter: shutter.clone(),
+            pin,
+        }
+    }
+
+    // check if file system is closed
+    fn check_closed(&self) -> Result<()> {
+        let shutter = self.shutter.read().unwrap();
+        if shutter.is_closed() {
+            return Err(Error::RepoClosed);
+        }
+        Ok(())
+    }
+
+    // run a function with the fnode as it was when frozen, root fnode is
+    // used when `id` is None
+    fn with_fnode<F, R>(&self, id: Option<&Eid>, f: F) -> Result<R>
+    where
+        F: FnOnce(&Fnode) -> R,
+    {
+        self.check_closed()?;
+        let fnode_ref = match id {
+            Some(id) => {
+                let vol = self.vol.upgrade().ok_or(Error::RepoClosed)?;
+                self.fcache.get(id, &vol)?
+            }
+            None => self.root.clone(),
+        };
+        Ok(self.pin.ents().with(&fnode_ref, f))
+    }
+
+    // resolve path to its normalized form and fnode id
+    //
+    // Symbolic links in the middle of path are always followed, the last
+    // path component is followed only if `follow_symlinks` is true.
+    fn resolve_node(
+        &self,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<(PathBuf, Option<Eid>)> {
+        let mut path = check_path(path)?;
+        let mut hops = 0;
+
+        'resolve: loop {
+            let mut dir = PathBuf::from("/");
+            let mut dir_id: Option<Eid> = None;
+            let names: Vec<String> = path
+                .iter()
+                .skip(1)
+                .map(|name| name.to_str().unwrap().to_string())
+                .collect();
+            for (idx, name) in names.iter().enumerate() {
+                let is_last = idx == names.len() - 1;
+                let child_id = self
+                    .with_fnode(dir_id.as_ref(), |dir| {
+                        if dir.is_dir() {
+                            Ok(dir.child_id(name).cloned())
+                        } else {
+                            Err(Error::NotDir)
+                        }
+                    })??
+                    .ok_or(Error::NotFound)?;
+                let target = self.with_fnode(Some(&child_id), |child| {
+                    child.link_target().map(str::to_string)
+                })?;
+
+                match target {
+                    Some(target) if follow_symlinks || !is_last => {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Err(Error::TooManySymlinks);
+                        }
+
+                        // relative target is relative to the link's parent
+                        // dir, resolve again with the rest of path appended
+                        let mut next = dir.join(target);
+                        next.extend(&names[idx + 1..]);
+                        path = normalize_path(&next);
+                        continue 'resolve;
+                    }
+                    _ => {
+                        dir.push(name);
+                        dir_id = Some(child_id);
+                    }
+                }
+            }
+
+            return Ok((dir, dir_id));
+        }
+    }
+
+    /// Resolve path to its normalized form
+    ///
+    /// Symbolic links in the middle of path are always followed, the last
+    /// path component is followed only if `follow_symlinks` is true.
+    #[inline]
+    pub fn resolve(
+        &self,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<PathBuf> {
+        self.resolve_node(path, follow_symlinks)
+            .map(|(path, _)| path)
+    }
+
+    /// Get metadata, symbolic link is followed if `follow_symlinks` is true
+    pub fn metadata(
+        &self,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<Metadata> {
+        let (_, id) = self.resolve_node(path, follow_symlinks)?;
+        self.with_fnode(id.as_ref(), Fnode::metadata)
+    }
+
+    /// Read directory entries, sorted by name
+    pub fn read_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
+        let (path, id) = self.resolve_node(path, true)?;
+        let kids = self.with_fnode(id.as_ref(), |dir| {
+            if dir.is_dir() {
+                Ok(dir.children_ids())
+            } else {
+                Err(Error::NotDir)
+            }
+        })??;
+
+        kids.into_iter()
+            .map(|(name, id)| {
+                let meta = self.with_fnode(Some(&id), Fnode::metadata)?;
+                let child_path = Fnode::child_path(&path, &name);
+                Ok(DirEntry::new(child_path, &name, meta))
+            })
+            .collect()
+    }
+
+    /// Get target path of symbolic link
+    pub fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let (_, id) = self.resolve_node(path, false)?;
+        self.with_fnode(id.as_ref(), |fnode| {
+            fnode
+                .link_target()
+                .map(PathBuf::from)
+                .ok_or(Error::InvalidArgument)
+        })?
+    }
+
+    /// Open a regular file for reading
+    pub fn open(&self, path: &Path) -> Result<(Metadata, FnodeReader)> {
+        let (_, id) = self.resolve_node(path, true)?;
+        let (meta, inline, content_id) =
+            self.with_fnode(id.as_ref(), |fnode| {
+                if fnode.is_dir() {
+                    return Err(Error::IsDir);
+                } else if !fnode.is_file() {
+                    return Err(Error::NotFile);
+                }
+                Ok((
+                    fnode.metadata(),
+                    fnode.curr_inline().map(<[u8]>::to_vec),
+                    fnode.curr_content_id().cloned(),
+                ))
+            })??;
+
+        let ver = meta.curr_version();
+        let rdr = match (inline, content_id) {
+            (Some(data), _) => FnodeReader::new_inline(ver, data),
+            (None, Some(content_id)) => {
+                let store = self.store.upgrade().ok_or(Error::RepoClosed)?;
+                let ctn_ref = {
+                    let store = store.read().unwrap();
+                    store.get_content(&content_id)?
+                };
+                let content = self.pin.ents().with(&ctn_ref, Clone::clone);
+                FnodeReader::new_content(
+                    ver,
+                    ContentReader::new_frozen(
+                        content,
+                        self.pin.ents(),
+                        &[],
+                        &self.store,
+                    ),
+                )
+            }
+            (None, None) => return Err(Error::NoContent),
+        };
+        Ok((meta, rdr))
+    }
+}
+
+impl Debug for FrozenFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrozenFs")
+            .field("root", &self.root.read().unwrap().id())
+            .field("ents", self.pin.ents())
+            .finish()
+    }
+}
@@ -1,21 +1,64 @@
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::SystemTime;
 
-use log::info;
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
 use super::fnode::{
-    Cache as FnodeCache, DirEntry, FileType, Fnode, FnodeRef, Metadata, Version,
+    Cache as FnodeCache, DirCursor, DirEntry, FileType, Fnode, FnodeRef,
+    Metadata, ReadDir, Version,
 };
-use super::{Config, Handle, Options};
-use crate::base::crypto::Cost;
-use crate::base::IntoRef;
-use crate::content::{Store, StoreRef};
+use super::{
+    Config, ContentCache, ContentCacheRef, EventHub, EventHubRef, FrozenFs,
+    Handle, LockTable, LockTableRef, Options, RepoEvent, Walk,
+};
+use crate::base::crypto::{Cipher, Cost, Crypto, Hash, Key};
+use crate::base::glob::Glob;
+use crate::base::{IntoRef, Time};
+use crate::content::{ChunkMap, ChunkerParams, Store, StoreRef};
 use crate::error::{Error, Result};
 use crate::trans::cow::IntoCow;
-use crate::trans::{Eid, Id, TxMgr, TxMgrRef};
-use crate::volume::{Info as VolumeInfo, Volume, VolumeRef};
+use crate::trans::{Eid, Id, TxHandle, TxMgr, TxMgrRef};
+use crate::volume::{
+    CacheHook, Info as VolumeInfo, SyncLevel, TransportConfig, Volume,
+    VolumeRef,
+};
+
+// maximum number of symbolic links followed when resolving a path
+pub(super) const MAX_SYMLINK_HOPS: usize = 40;
+
+// lexically resolve '.' and '..' components of absolute path, '..' above
+// root stays at root if `clamp` is true, otherwise None is returned
+fn resolve_components(path: &Path, clamp: bool) -> Option<PathBuf> {
+    let mut ret = PathBuf::from("/");
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => ret.push(name),
+            Component::ParentDir if !ret.pop() && !clamp => return None,
+            _ => {}
+        }
+    }
+    Some(ret)
+}
+
+// lexically normalize absolute path, resolving '.' and '..' components
+#[inline]
+pub(super) fn normalize_path(path: &Path) -> PathBuf {
+    resolve_components(path, true).unwrap()
+}
+
+// validate and normalize an absolute path, duplicate separators, '.' and
+// trailing separator are removed and '..' is resolved lexically, the path
+// must be valid UTF-8 and '..' cannot go above root
+pub(super) fn check_path(path: &Path) -> Result<PathBuf> {
+    if path.to_str().is_none() || !path.has_root() {
+        return Err(Error::InvalidPath);
+    }
+    resolve_components(path, false).ok_or(Error::InvalidPath)
+}
 
 // mask secrets in uri
 fn mask_uri(uri: &str) -> String {
@@ -33,25 +76,43 @@ pub struct Info {
     pub opts: Options,
     pub vol_info: VolumeInfo,
     pub read_only: bool,
+    pub was_dirty: bool,
 }
 
 /// Shutter
 #[derive(Debug)]
-pub struct Shutter(bool);
+pub struct Shutter {
+    closed: bool,
+    read_only: bool, // reject writes of open handles
+}
 
 impl Shutter {
     fn new() -> ShutterRef {
-        Shutter(false).into_ref()
+        Shutter {
+            closed: false,
+            read_only: false,
+        }
+        .into_ref()
     }
 
     #[inline]
     pub fn is_closed(&self) -> bool {
-        self.0
+        self.closed
     }
 
     #[inline]
     fn close(&mut self) {
-        self.0 = true
+        self.closed = true
+    }
+
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    #[inline]
+    fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only
     }
 }
 
@@ -59,6 +120,19 @@ impl IntoRef for Shutter {}
 
 pub type ShutterRef = Arc<RwLock<Shutter>>;
 
+/// Corrupted entity found by check, with the path referencing it
+pub type Corruption = (PathBuf, Eid, Error);
+
+// an entry to be moved by move_all, with the target it replaces
+struct Move {
+    from: PathBuf,
+    to: PathBuf,
+    src: FnodeRef,
+    tgt_parent: FnodeRef,
+    name: String,
+    replace: Option<FnodeRef>,
+}
+
 /// Super block payload
 #[derive(Debug, Deserialize, Serialize)]
 struct Payload {
@@ -105,7 +179,13 @@ pub struct Fs {
     txmgr: TxMgrRef,
     vol: VolumeRef,
     shutter: ShutterRef,
+    events: EventHubRef,
+    locks: LockTableRef,
+    contents: ContentCacheRef,
+    handles: RwLock<Vec<Weak<PathBuf>>>, // paths of open handles
+    writers: RwLock<Vec<Weak<PathBuf>>>, // paths of handles open for write
     opts: Options,
+    max_file_len: Option<usize>, // default maximum file content length
     read_only: bool,
 }
 
@@ -113,23 +193,48 @@ impl Fs {
     // default cache size
     const FNODE_CACHE_SIZE: usize = 16;
 
+    // number of entities re-encrypted before their new addresses are saved
+    const RECRYPT_BATCH_SIZE: usize = 256;
+
     /// Check if fs exists
-    pub fn exists(uri: &str) -> Result<bool> {
+    pub fn exists(uri: &str, transport: &TransportConfig) -> Result<bool> {
         let vol = Volume::new(uri)?;
+        vol.set_transport_config(transport)?;
         vol.exists()
     }
 
     /// Create new fs
-    pub fn create(uri: &str, pwd: &str, cfg: &Config) -> Result<Fs> {
+    pub fn create(
+        uri: &str,
+        pwd: &str,
+        key_file: Option<Key>,
+        cfg: &Config,
+        transport: &TransportConfig,
+        sync: SyncLevel,
+    ) -> Result<Fs> {
         let root_id = Eid::new();
         let walq_id = Eid::new();
         let store_id = Eid::new();
         let payload = Payload::new(&root_id, &walq_id, &store_id, cfg.opts);
+        let started = Time::now();
+
+        // cipher must be supported before anything is written to storage
+        if cfg.cipher == Cipher::Aes && !Crypto::is_aes_hardware_available() {
+            return Err(Error::NoAesHardware);
+        }
+
+        // validate chunk sizes before anything is written to storage
+        let chunker_params = ChunkerParams::with_sizes(
+            cfg.chunk_min_size,
+            cfg.chunk_avg_size,
+            cfg.chunk_max_size,
+        )?;
 
         // create and initialise volume
         let mut vol = Volume::new(uri)?;
-        info!("create repo: {}", mask_uri(&vol.info().uri));
-
+        vol.set_transport_config(transport)?;
+        vol.set_sync_level(sync);
+        vol.set_key_file(key_file);
         vol.init(pwd, cfg, &payload.seri()?)?;
 
         let vol = vol.into_ref();
@@ -143,8 +248,13 @@ impl Fs {
         let mut store_ref: Option<StoreRef> = None;
         let mut root_ref: Option<FnodeRef> = None;
         TxMgr::begin_trans(&txmgr)?.run_all(|| {
-            let store_cow = Store::new(cfg.opts.dedup_file, &txmgr, &vol)
-                .into_cow_with_id(&store_id, &txmgr)?;
+            let store_cow = Store::new(
+                chunker_params.clone(),
+                cfg.opts.dedup_file,
+                &txmgr,
+                &vol,
+            )
+            .into_cow_with_id(&store_id, &txmgr)?;
             let root_cow = Fnode::new(FileType::Dir, cfg.opts)
                 .into_cow_with_id(&root_id, &txmgr)?;
             root_ref = Some(root_cow);
@@ -152,7 +262,12 @@ impl Fs {
             Ok(())
         })?;
 
-        info!("repo created");
+        log_event!(
+            info,
+            "repo.create",
+            uri = mask_uri(uri),
+            duration = started.elapsed()
+        );
 
         Ok(Fs {
             root: root_ref.unwrap(),
@@ -161,7 +276,13 @@ impl Fs {
             txmgr,
             vol,
             shutter: Shutter::new(),
+            events: EventHub::new(),
+            locks: LockTable::new(),
+            contents: ContentCache::new(),
+            handles: RwLock::new(Vec::new()),
+            writers: RwLock::new(Vec::new()),
             opts: cfg.opts,
+            max_file_len: None,
             read_only: false,
         })
     }
@@ -170,23 +291,76 @@ impl Fs {
     pub fn open(
         uri: &str,
         pwd: &str,
+        key_file: Option<Key>,
         read_only: bool,
         force: bool,
+        transport: &TransportConfig,
+        sync: SyncLevel,
     ) -> Result<Fs> {
+        let started = Time::now();
         let mut vol = Volume::new(uri)?;
-
-        info!(
-            "open repo: {}, read_only: {}",
-            mask_uri(&vol.info().uri),
-            read_only
-        );
+        vol.set_transport_config(transport)?;
+        vol.set_sync_level(sync);
+        vol.set_key_file(key_file);
 
         // open volume
         let payload = vol.open(pwd, force)?;
+        Self::open_volume(uri, vol, &payload, read_only, started)
+    }
+
+    /// Open fs using a volume key derived by `derive_key`
+    pub fn open_with_key(
+        uri: &str,
+        vkey: &Key,
+        read_only: bool,
+        force: bool,
+        transport: &TransportConfig,
+        sync: SyncLevel,
+    ) -> Result<Fs> {
+        let started = Time::now();
+        let mut vol = Volume::new(uri)?;
+        vol.set_transport_config(transport)?;
+        vol.set_sync_level(sync);
+
+        // open volume
+        let payload = vol.open_with_key(vkey, force)?;
+        Self::open_volume(uri, vol, &payload, read_only, started)
+    }
+
+    /// Derive volume key from password
+    pub fn derive_key(
+        uri: &str,
+        pwd: &str,
+        transport: &TransportConfig,
+    ) -> Result<Key> {
+        let vol = Volume::new(uri)?;
+        vol.set_transport_config(transport)?;
+        vol.derive_key(pwd)
+    }
+
+    /// Verify password of repo without opening it
+    pub fn verify_password(
+        uri: &str,
+        pwd: &str,
+        transport: &TransportConfig,
+    ) -> Result<bool> {
+        let vol = Volume::new(uri)?;
+        vol.set_transport_config(transport)?;
+        vol.verify_password(pwd)
+    }
+
+    // create fs components on an opened volume
+    fn open_volume(
+        uri: &str,
+        vol: Volume,
+        payload: &[u8],
+        read_only: bool,
+        started: Time,
+    ) -> Result<Fs> {
         let vol = vol.into_ref();
 
         // deserialize payload
-        let payload = Payload::deseri(&payload)?;
+        let payload = Payload::deseri(payload)?;
 
         // open transaction manager
         let txmgr = TxMgr::open(&payload.walq_id, &vol)?.into_ref();
@@ -195,19 +369,39 @@ impl Fs {
         let store = Store::open(&payload.store_id, &txmgr, &vol)?;
         let root = Fnode::load_root(&payload.root_id, &vol)?;
         let fcache = FnodeCache::new(Self::FNODE_CACHE_SIZE);
+        let has_cipher_change = vol.read().unwrap().has_cipher_change();
+
+        log_event!(
+            info,
+            "repo.open",
+            uri = mask_uri(uri),
+            read_only = read_only,
+            duration = started.elapsed()
+        );
 
-        info!("repo opened");
-
-        Ok(Fs {
+        let mut fs = Fs {
             root,
             fcache,
             store,
             txmgr,
             vol,
             shutter: Shutter::new(),
+            events: EventHub::new(),
+            locks: LockTable::new(),
+            contents: ContentCache::new(),
+            handles: RwLock::new(Vec::new()),
+            writers: RwLock::new(Vec::new()),
             opts: payload.opts,
+            max_file_len: None,
             read_only,
-        })
+        };
+
+        // resume unfinished cipher change
+        if has_cipher_change && !read_only {
+            fs.resume_cipher_change()?;
+        }
+
+        Ok(fs)
     }
 
     #[inline]
@@ -215,11 +409,107 @@ impl Fs {
         self.read_only
     }
 
+    /// Set read-only flag
+    ///
+    /// Writes of open handles are rejected if both `read_only` and
+    /// `reject_handles` are true, otherwise they can still write.
+    pub fn set_read_only(
+        &mut self,
+        read_only: bool,
+        reject_handles: bool,
+    ) -> Result<()> {
+        // save deferred commits before becoming read-only, as they cannot
+        // be saved by sync after that
+        if read_only && !self.read_only {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.sync()?;
+        }
+
+        // resume unfinished cipher change skipped when opened in read-only
+        // mode, stay read-only if it cannot be done
+        if !read_only
+            && self.read_only
+            && self.vol.read().unwrap().has_cipher_change()
+        {
+            {
+                let txmgr = self.txmgr.read().unwrap();
+                if txmgr.has_trans() || txmgr.is_pinned() {
+                    return Err(Error::InUse);
+                }
+            }
+            self.resume_cipher_change()?;
+        }
+        self.read_only = read_only;
+
+        let mut shutter = self.shutter.write().unwrap();
+        shutter.set_read_only(read_only && reject_handles);
+        Ok(())
+    }
+
     #[inline]
     pub fn get_opts(&self) -> Options {
         self.opts
     }
 
+    /// Enable background commit
+    pub fn enable_background_commit(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        TxMgr::start_committer(&self.txmgr)?;
+
+        Ok(())
+    }
+
+    /// Save all deferred commits
+    pub fn sync(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let mut txmgr = self.txmgr.write().unwrap();
+        txmgr.sync()
+    }
+
+    /// Save all deferred commits and flush underlying storage
+    pub fn flush(&mut self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.sync()?;
+        }
+        let mut vol = self.vol.write().unwrap();
+        vol.flush()
+    }
+
+    /// Check if there are writes not yet flushed to underlying storage
+    pub fn has_pending_writes(&self) -> bool {
+        if self.read_only {
+            return false;
+        }
+
+        {
+            let txmgr = self.txmgr.read().unwrap();
+            if txmgr.has_trans() || txmgr.is_unsynced() {
+                return true;
+            }
+        }
+        let vol = self.vol.read().unwrap();
+        vol.has_pending_writes()
+    }
+
+    /// Check underlying storage is reachable
+    #[inline]
+    pub fn ping(&self) -> Result<()> {
+        let vol = self.vol.read().unwrap();
+        vol.ping()
+    }
+
     /// Get file system information
     pub fn info(&self) -> Info {
         let vol = self.vol.read().unwrap();
@@ -227,6 +517,7 @@ impl Fs {
             opts: self.opts,
             vol_info: vol.info(),
             read_only: self.read_only,
+            was_dirty: self.txmgr.read().unwrap().was_dirty(),
         }
     }
 
@@ -245,6 +536,225 @@ impl Fs {
         vol.reset_password(old_pwd, new_pwd, cost)
     }
 
+    /// Change cipher of volume and re-encrypt all entities with it
+    ///
+    /// An unfinished change is resumed if the same cipher is given, or is
+    /// rolled back if the original cipher is given.
+    pub fn change_cipher(
+        &mut self,
+        cipher: Cipher,
+        pwd: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // cannot change cipher while any file is being written, or any
+        // frozen fs is holding removed entities
+        self.sync()?;
+        {
+            let txmgr = self.txmgr.read().unwrap();
+            if txmgr.has_trans() || txmgr.is_pinned() {
+                return Err(Error::InUse);
+            }
+        }
+
+        // collect ids before anything is changed, so entities which cannot
+        // be re-encrypted are found early
+        let ids = self.collect_ids()?;
+        {
+            let mut vol = self.vol.write().unwrap();
+            if !vol.begin_cipher_change(pwd, cipher)? {
+                return Ok(());
+            }
+        }
+
+        self.recrypt_all(&ids, progress)
+    }
+
+    // resume unfinished cipher change
+    fn resume_cipher_change(&mut self) -> Result<()> {
+        let ids = self.collect_ids()?;
+        self.recrypt_all(&ids, &mut |_, _| {})
+    }
+
+    // collect ids of all entities in the repo, return wal ids and the
+    // other entity ids
+    fn collect_ids(&self) -> Result<(Vec<Eid>, Vec<Eid>)> {
+        let (wal_ids, ent_ids) =
+            self.txmgr.read().unwrap().collect_wal_ids()?;
+        let mut ids: HashSet<Eid> = ent_ids.into_iter().collect();
+        ids.insert(self.store.read().unwrap().id().clone());
+        ids.insert(self.root.read().unwrap().id().clone());
+
+        let store = self.store.read().unwrap();
+        let mut fnodes = vec![self.root.clone()];
+        while let Some(fnode_ref) = fnodes.pop() {
+            let fnode = fnode_ref.read().unwrap();
+
+            for (_, id) in fnode.children_ids() {
+                fnodes.push(Fnode::load(&id, &self.vol)?);
+                ids.insert(id);
+            }
+
+            // data bound to associated data cannot be decrypted without
+            // it, so it cannot be re-encrypted
+            if fnode.is_aad_bound() {
+                return Err(Error::InvalidArgument);
+            }
+
+            for ctn_id in fnode.content_ids() {
+                if !ids.insert(ctn_id.clone()) {
+                    continue;
+                }
+                let ctn_ref = store.get_content(&ctn_id)?;
+                let ctn = ctn_ref.read().unwrap();
+                for seg_id in ctn.seg_ids() {
+                    if ids.contains(&seg_id) {
+                        continue;
+                    }
+                    let seg_ref = store.get_seg(&seg_id)?;
+                    let seg = seg_ref.read().unwrap();
+                    ids.insert(seg.data_id().clone());
+                    ids.insert(seg_id);
+                }
+            }
+        }
+
+        Ok((wal_ids, ids.into_iter().collect()))
+    }
+
+    // re-encrypt all entities and finish cipher change
+    fn recrypt_all(
+        &mut self,
+        ids: &(Vec<Eid>, Vec<Eid>),
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let (wal_ids, ent_ids) = ids;
+        let total = wal_ids.len() + ent_ids.len();
+        let mut done = 0;
+
+        for wal_id in wal_ids.iter() {
+            self.vol.write().unwrap().recrypt_wal(wal_id)?;
+            done += 1;
+            progress(done, total);
+        }
+
+        for batch in ent_ids.chunks(Self::RECRYPT_BATCH_SIZE) {
+            {
+                let mut vol = self.vol.write().unwrap();
+                for id in batch {
+                    vol.recrypt(id)?;
+                    done += 1;
+                    progress(done, total);
+                }
+            }
+
+            // persist newly allocated blocks before switching addresses to
+            // them, so they won't be re-allocated if crashed in between
+            self.txmgr.write().unwrap().save_walq()?;
+            self.vol.write().unwrap().commit_recrypt()?;
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.flush()?;
+        vol.finish_cipher_change()
+    }
+
+    /// Get memory used by caches and buffers, in bytes
+    ///
+    /// Return sizes of frame cache, segment data cache, address cache,
+    /// depot's caches, writer buffers and reader buffers.
+    pub fn memory_usage(&self) -> (usize, usize, usize, usize, usize, usize) {
+        let (frame_cache, addr_cache, depot_cache) = {
+            let vol = self.vol.read().unwrap();
+            vol.cache_mem_used()
+        };
+        let store = self.store.read().unwrap();
+        (
+            frame_cache,
+            store.segdata_cache_used(),
+            addr_cache,
+            depot_cache,
+            store.wtr_buf_size(),
+            store.rdr_buf_size(),
+        )
+    }
+
+    /// Size caches to keep caches and buffers under memory budget
+    ///
+    /// Caches are resized again whenever a reader or writer buffer is
+    /// allocated, so they only take what the living buffers leave.
+    pub fn set_memory_budget(&self, budget: usize) {
+        let store = self.store.read().unwrap();
+        store.set_mem_budget(budget);
+    }
+
+    /// Set frame cache size in bytes and address cache size in number of
+    /// addresses, the one not specified is not changed
+    pub fn set_cache_sizes(
+        &self,
+        frame_cache: Option<usize>,
+        addr_cache: Option<usize>,
+    ) {
+        let vol = self.vol.read().unwrap();
+        if let Some(capacity) = frame_cache {
+            vol.set_frame_cache_capacity(capacity);
+        }
+        if let Some(capacity) = addr_cache {
+            vol.set_addr_cache_capacity(capacity);
+        }
+    }
+
+    /// Set number of frames read ahead and decrypted in parallel
+    #[inline]
+    pub fn set_read_ahead(&self, frames: usize) {
+        let vol = self.vol.read().unwrap();
+        vol.set_read_ahead(frames);
+    }
+
+    /// Set default maximum content length of files opened later
+    #[inline]
+    pub fn set_max_file_len(&mut self, max_len: Option<usize>) {
+        self.max_file_len = max_len;
+    }
+
+    /// Get hit and miss counts of frame cache and address cache
+    pub fn cache_stats(&self) -> ((usize, usize), (usize, usize)) {
+        let vol = self.vol.read().unwrap();
+        vol.cache_stats()
+    }
+
+    /// Get used bytes, capacity in bytes and number of objects of storage
+    /// local cache
+    pub fn local_cache_usage(&self) -> Result<(usize, usize, usize)> {
+        let vol = self.vol.read().unwrap();
+        vol.local_cache_usage()
+    }
+
+    /// Get used bytes and maximum bytes of storage if it has a size cap
+    pub fn space_limit(&self) -> Option<(usize, usize)> {
+        let vol = self.vol.read().unwrap();
+        vol.space_limit()
+    }
+
+    /// Set cache event hook
+    pub fn set_cache_hook(&self, hook: CacheHook) {
+        let vol = self.vol.read().unwrap();
+        vol.set_cache_hook(hook);
+    }
+
+    /// Set volume label
+    pub fn set_label(&mut self, label: Option<String>) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut vol = self.vol.write().unwrap();
+        vol.set_label(label)
+    }
+
     /// Repair possibly damaged super block
     #[inline]
     pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
@@ -252,45 +762,243 @@ impl Fs {
         vol.repair_super_block(pwd)
     }
 
-    /// Resolve path
-    pub fn resolve(&self, path: &Path) -> Result<FnodeRef> {
-        // only resolve absolute path
-        if !path.has_root() {
-            return Err(Error::InvalidPath);
+    /// Check consistency of a closed fs
+    ///
+    /// It opens the fs in read-only mode and walks through all the fnodes,
+    /// contents and segments. For each content, its data is read and
+    /// verified against its merkle tree, except content bound to associated
+    /// data, because the data is not available here.
+    ///
+    /// Each content and segment is checked only once even if it is shared.
+    /// Corrupted entities are collected rather than failing on the first
+    /// one, each one is reported with the path of fnode referencing it.
+    ///
+    /// Return the number of checked fnodes, contents, segments and the list
+    /// of corrupted entities.
+    pub fn check(
+        uri: &str,
+        pwd: &str,
+    ) -> Result<(usize, usize, usize, Vec<Corruption>)> {
+        let fs = Fs::open(
+            uri,
+            pwd,
+            None,
+            true,
+            false,
+            &TransportConfig::default(),
+            SyncLevel::default(),
+        )?;
+        let mut fnode_cnt = 0;
+        let mut ctn_ids = HashSet::new();
+        let mut seg_ids = HashSet::new();
+        let mut bad_seg_ids = HashSet::new();
+        let mut corrupted = Vec::new();
+        let mut fnodes = vec![(PathBuf::from("/"), fs.root.clone())];
+
+        while let Some((path, fnode_ref)) = fnodes.pop() {
+            fnode_cnt += 1;
+            let fnode = fnode_ref.read().unwrap();
+
+            // load all children directly from volume
+            for (name, id) in fnode.children_ids() {
+                let child_path = path.join(&name);
+                match Fnode::load(&id, &fs.vol) {
+                    Ok(child) => fnodes.push((child_path, child)),
+                    Err(err) => corrupted.push((child_path, id, err)),
+                }
+            }
+
+            for ctn_id in fnode.content_ids() {
+                if !ctn_ids.insert(ctn_id.clone()) {
+                    continue;
+                }
+
+                let ctn_ref =
+                    match fs.store.read().unwrap().get_content(&ctn_id) {
+                        Ok(ctn_ref) => ctn_ref,
+                        Err(err) => {
+                            corrupted.push((path.clone(), ctn_id, err));
+                            continue;
+                        }
+                    };
+                let ctn = ctn_ref.read().unwrap();
+
+                // check segments and decrypt segment data
+                let mut is_good = true;
+                for seg_id in ctn.seg_ids() {
+                    if !seg_ids.insert(seg_id.clone()) {
+                        is_good &= !bad_seg_ids.contains(&seg_id);
+                        continue;
+                    }
+                    let store = fs.store.read().unwrap();
+                    let result = store.get_seg(&seg_id).and_then(|seg_ref| {
+                        if fnode.is_aad_bound() {
+                            return Ok(());
+                        }
+                        let seg = seg_ref.read().unwrap();
+                        store.get_segdata(&seg, &[]).map(|_| ())
+                    });
+                    if let Err(err) = result {
+                        bad_seg_ids.insert(seg_id.clone());
+                        corrupted.push((path.clone(), seg_id, err));
+                        is_good = false;
+                    }
+                }
+
+                // verify content data against merkle tree
+                if is_good && !fnode.is_aad_bound() {
+                    if let Err(err) = ctn.verify(&[], &fs.store) {
+                        corrupted.push((path.clone(), ctn_id, err));
+                    }
+                }
+            }
         }
 
-        let mut fnode = self.root.clone();
+        Ok((fnode_cnt, ctn_ids.len(), seg_ids.len(), corrupted))
+    }
+
+    /// Resolve path
+    ///
+    /// Symbolic links in the middle of path are always followed, the last
+    /// path component is followed only if `follow_symlinks` is true.
+    pub fn resolve(
+        &self,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<FnodeRef> {
+        let mut path = check_path(path)?;
+        let mut hops = 0;
+
+        'resolve: loop {
+            let mut fnode = self.root.clone();
+            let mut dir = PathBuf::from("/");
+
+            // loop through path component and skip root
+            let names: Vec<String> = path
+                .iter()
+                .skip(1)
+                .map(|name| name.to_str().unwrap().to_string())
+                .collect();
+            for (idx, name) in names.iter().enumerate() {
+                fnode = Fnode::child(&fnode, name, &self.fcache, &self.vol)?;
+
+                let target = {
+                    let fnode = fnode.read().unwrap();
+                    let is_last = idx == names.len() - 1;
+                    if fnode.is_symlink() && (follow_symlinks || !is_last) {
+                        fnode.link_target().map(PathBuf::from)
+                    } else {
+                        None
+                    }
+                };
+
+                match target {
+                    Some(target) => {
+                        hops += 1;
+                        if hops > MAX_SYMLINK_HOPS {
+                            return Err(Error::TooManySymlinks);
+                        }
+
+                        // relative target is relative to the link's parent
+                        // dir, resolve again with the rest of path appended
+                        let mut next = dir.join(target);
+                        next.extend(&names[idx + 1..]);
+                        path = normalize_path(&next);
+                        continue 'resolve;
+                    }
+                    None => dir.push(name),
+                }
+            }
 
-        // loop through path component and skip root
-        for name in path.iter().skip(1) {
-            let name = name.to_str().unwrap();
-            fnode = Fnode::child(&fnode, name, &self.fcache, &self.vol)?;
+            return Ok(fnode);
         }
-        Ok(fnode)
     }
 
     // resolve path to parent fnode and child file name
     fn resolve_parent(&self, path: &Path) -> Result<(FnodeRef, String)> {
+        let path = check_path(path)?;
         let parent_path = path.parent().ok_or(Error::IsRoot)?;
         let file_name = path
             .file_name()
             .and_then(|s| s.to_str())
             .ok_or(Error::InvalidPath)?;
-        let parent = self.resolve(parent_path)?;
+        let parent = self.resolve(parent_path, true)?;
         Ok((parent, file_name.to_string()))
     }
 
     /// Open fnode
-    pub fn open_fnode(&mut self, path: &Path) -> Result<Handle> {
-        let fnode = self.resolve(path)?;
+    pub fn open_fnode(&self, path: &Path) -> Result<Handle> {
+        let fnode = self.resolve(path, true)?;
+        let path = Arc::new(path.to_path_buf());
+
+        // register the handle and remove closed ones
+        {
+            let mut handles = self.handles.write().unwrap();
+            handles.retain(|handle| handle.strong_count() > 0);
+            handles.push(Arc::downgrade(&path));
+        }
+
+        let (blk_size, frame_size) = {
+            let vol = self.vol.read().unwrap();
+            (vol.blk_size(), vol.frame_size())
+        };
+
         Ok(Handle {
             fnode,
             store: Arc::downgrade(&self.store),
             txmgr: Arc::downgrade(&self.txmgr),
             shutter: self.shutter.clone(),
+            events: self.events.clone(),
+            locks: self.locks.clone(),
+            contents: self.contents.clone(),
+            path,
+            low_memory: false,
+            skip_identical: false,
+            aad: Vec::new(),
+            max_len: self.max_file_len,
+            replace: false,
+            blk_size,
+            frame_size,
         })
     }
 
+    /// Register an open handle which can write, it is tracked until the
+    /// returned path is dropped
+    pub fn register_writer(&self, handle: &Handle) -> Arc<PathBuf> {
+        let path = Arc::new((*handle.path).clone());
+        let mut writers = self.writers.write().unwrap();
+        writers.retain(|writer| writer.strong_count() > 0);
+        writers.push(Arc::downgrade(&path));
+        path
+    }
+
+    // check if any handle open for write is alive
+    fn has_writers(&self) -> bool {
+        let writers = self.writers.read().unwrap();
+        writers.iter().any(|writer| writer.strong_count() > 0)
+    }
+
+    /// Get paths of all open handles, sorted by path
+    ///
+    /// Each path is the one used when the handle was opened, a path is
+    /// reported once for each handle opened on it.
+    pub fn open_handles(&self) -> Vec<PathBuf> {
+        let handles = self.handles.read().unwrap();
+        let mut paths: Vec<PathBuf> = handles
+            .iter()
+            .filter_map(|handle| handle.upgrade())
+            .map(|path| (*path).clone())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Subscribe to mutation events
+    #[inline]
+    pub fn subscribe(&self) -> Receiver<RepoEvent> {
+        self.events.subscribe()
+    }
+
     /// Create fnode
     pub fn create_fnode(
         &mut self,
@@ -328,15 +1036,64 @@ impl Fs {
             Ok(())
         })?;
 
+        // cache the new fnode, so it can still be resolved when the
+        // transaction is joined by others and not committed yet
+        self.fcache.insert(&fnode);
+        self.events.emit(RepoEvent::Create(path.to_path_buf()));
+
         Ok(fnode)
     }
 
-    /// Recursively create directories along the path
-    pub fn create_dir_all(&mut self, path: &Path) -> Result<()> {
-        match self.create_fnode(path, FileType::Dir, Options::default()) {
-            Ok(_) => return Ok(()),
-            Err(ref err) if *err == Error::NotFound => {}
-            Err(err) => return Err(err),
+    /// Create a symbolic link pointing to target
+    pub fn symlink(&mut self, target: &Path, link: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let target = target.to_str().ok_or(Error::InvalidPath)?;
+        if target.is_empty() {
+            return Err(Error::InvalidPath);
+        }
+
+        let (parent, name) = self.resolve_parent(link)?;
+
+        {
+            let parent = parent.read().unwrap();
+            if !parent.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if parent.has_child(&name) {
+                return Err(Error::AlreadyExists);
+            }
+        }
+
+        // begin and run transaction
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            Fnode::new_symlink_under(&parent, &name, target, &self.txmgr)?;
+            Ok(())
+        })?;
+        self.events.emit(RepoEvent::Create(link.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Read target path of a symbolic link
+    pub fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        let fnode_ref = self.resolve(path, false)?;
+        let fnode = fnode_ref.read().unwrap();
+        fnode
+            .link_target()
+            .map(PathBuf::from)
+            .ok_or(Error::InvalidArgument)
+    }
+
+    /// Recursively create directories along the path
+    pub fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+        let path = &check_path(path)?;
+        match self.create_fnode(path, FileType::Dir, Options::default()) {
+            Ok(_) => return Ok(()),
+            Err(ref err) if *err == Error::NotFound => {}
+            Err(err) => return Err(err),
         }
         match path.parent() {
             Some(p) => self.create_dir_all(p)?,
@@ -346,37 +1103,704 @@ impl Fs {
         Ok(())
     }
 
+    // get dir fnode at path, missing dirs are created in current transaction
+    // and saved in the dir map as they cannot be resolved before commit
+    fn ensure_dir(
+        &self,
+        path: &Path,
+        dirs: &mut HashMap<PathBuf, FnodeRef>,
+    ) -> Result<FnodeRef> {
+        if let Some(dir) = dirs.get(path) {
+            return Ok(dir.clone());
+        }
+
+        match self.resolve(path, true) {
+            Ok(fnode) => return Ok(fnode),
+            Err(ref err) if *err == Error::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let parent_path = path.parent().ok_or(Error::IsRoot)?;
+        let name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or(Error::InvalidPath)?;
+        let parent = self.ensure_dir(parent_path, dirs)?;
+
+        // the name might be taken by a file created in current transaction
+        if parent.read().unwrap().has_child(name) {
+            return Err(Error::NotDir);
+        }
+
+        let dir = Fnode::new_under(
+            &parent,
+            name,
+            FileType::Dir,
+            Options::default(),
+            &self.txmgr,
+            &self.store,
+        )?;
+        dirs.insert(path.to_path_buf(), dir.clone());
+        Ok(dir)
+    }
+
+    /// Create files with content in a single transaction
+    pub fn write_batch<I>(&mut self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (PathBuf, Vec<u8>)>,
+    {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut dirs = HashMap::new();
+        let mut created = Vec::new();
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            for (path, data) in entries {
+                let path = check_path(&path)?;
+                let parent_path = path.parent().ok_or(Error::IsRoot)?;
+                let name = path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .ok_or(Error::InvalidPath)?;
+                let parent = self.ensure_dir(parent_path, &mut dirs)?;
+
+                {
+                    let parent = parent.read().unwrap();
+                    if !parent.is_dir() {
+                        return Err(Error::NotDir);
+                    }
+                    if parent.has_child(name) {
+                        return Err(Error::AlreadyExists);
+                    }
+                }
+
+                Fnode::new_file_under(
+                    &parent,
+                    name,
+                    &data,
+                    self.opts,
+                    tx_handle.txid,
+                    &self.txmgr,
+                    &self.store,
+                )?;
+                created.push(path);
+            }
+            Ok(())
+        })?;
+
+        // missing directories are created before files
+        let mut new_dirs: Vec<PathBuf> =
+            dirs.into_iter().map(|d| d.0).collect();
+        new_dirs.sort();
+        for path in new_dirs.into_iter().chain(created) {
+            self.events.emit(RepoEvent::Create(path));
+        }
+
+        Ok(())
+    }
+
     /// Read directory entries
+    #[inline]
     pub fn read_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
-        let parent = self.resolve(path)?;
-        Fnode::read_dir(parent, path, &self.fcache, &self.vol)
+        self.read_dir_iter(path)?.collect()
+    }
+
+    /// Get an iterator over directory entries
+    pub fn read_dir_iter(&self, path: &Path) -> Result<ReadDir> {
+        let path = &check_path(path)?;
+        let parent = self.resolve(path, true)?;
+        Fnode::read_dir(
+            parent,
+            path,
+            &self.fcache,
+            &Arc::downgrade(&self.vol),
+            &self.shutter,
+        )
+    }
+
+    /// Read a page of directory entries
+    pub fn read_dir_page(
+        &self,
+        path: &Path,
+        cursor: Option<&DirCursor>,
+        limit: usize,
+    ) -> Result<(Vec<DirEntry>, Option<DirCursor>)> {
+        if limit == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        let path = &check_path(path)?;
+        let parent = self.resolve(path, true)?;
+        let (ents, has_more) = Fnode::read_dir_page(
+            parent,
+            path,
+            cursor.map(|c| c.after()),
+            limit,
+            &self.fcache,
+            &self.vol,
+        )?;
+        let next = if has_more {
+            ents.last().map(|ent| DirCursor::new(ent.file_name()))
+        } else {
+            None
+        };
+        Ok((ents, next))
     }
 
     /// Get metadata of specified path
     pub fn metadata(&self, path: &Path) -> Result<Metadata> {
-        let fnode_ref = self.resolve(path)?;
+        let fnode_ref = self.resolve(path, true)?;
+        let fnode = fnode_ref.read().unwrap();
+        Ok(fnode.metadata())
+    }
+
+    /// Get metadata of specified path without following symbolic link
+    pub fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        let fnode_ref = self.resolve(path, false)?;
         let fnode = fnode_ref.read().unwrap();
         Ok(fnode.metadata())
     }
 
-    /// Get file version list of specified path
-    pub fn history(&self, path: &Path) -> Result<Vec<Version>> {
-        let fnode_ref = self.resolve(path)?;
-        let fnode = fnode_ref.read().unwrap();
-        if fnode.is_dir() {
-            return Err(Error::IsDir);
-        }
-        Ok(fnode.history())
+    /// Get file version list of specified path
+    pub fn history(&self, path: &Path) -> Result<Vec<Version>> {
+        let fnode_ref = self.resolve(path, true)?;
+        let fnode = fnode_ref.read().unwrap();
+        if fnode.is_dir() {
+            return Err(Error::IsDir);
+        }
+        fnode.versions(&self.store)
+    }
+
+    /// Get a recursive iterator over the directory tree under a path
+    pub fn walk(&self, path: &Path) -> Result<Walk> {
+        let path = &check_path(path)?;
+        let fnode_ref = self.resolve(path, true)?;
+        let metadata = fnode_ref.read().unwrap().metadata();
+        let rdr = if metadata.is_dir() {
+            Some(Fnode::read_dir(
+                fnode_ref,
+                path,
+                &self.fcache,
+                &Arc::downgrade(&self.vol),
+                &self.shutter,
+            )?)
+        } else {
+            None
+        };
+        Ok(Walk::new(path.to_path_buf(), metadata, rdr))
+    }
+
+    /// Find all paths matching a glob pattern
+    ///
+    /// Only the directories which can still match the pattern are descended,
+    /// symbolic links are not followed.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        let glob = Glob::new(pattern)?;
+        let mut ret = Vec::new();
+        let mut dirs =
+            vec![(self.root.clone(), PathBuf::from("/"), glob.start())];
+
+        while let Some((dir, path, states)) = dirs.pop() {
+            let kids = dir.read().unwrap().children_types();
+            for (name, ftype) in kids {
+                let next = glob.step(&states, &name);
+                if next.is_empty() {
+                    continue;
+                }
+
+                let child_path = Fnode::child_path(&path, &name);
+                if glob.is_match(&next) {
+                    ret.push(child_path.clone());
+                }
+                if ftype == FileType::Dir && glob.can_descend(&next) {
+                    let child =
+                        Fnode::child(&dir, &name, &self.fcache, &self.vol)?;
+                    dirs.push((child, child_path, next));
+                }
+            }
+        }
+
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Walk the directory tree under a directory and get all entries, the
+    /// directory itself is excluded
+    pub fn walk_dir(&self, path: &Path) -> Result<Vec<DirEntry>> {
+        let mut ret = Vec::new();
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for child in self.read_dir(&dir)? {
+                if child.metadata().is_dir() {
+                    dirs.push(child.path().to_path_buf());
+                }
+                ret.push(child);
+            }
+        }
+        Ok(ret)
+    }
+
+    // walk the whole directory tree and get all regular files
+    fn files(&self) -> Result<Vec<DirEntry>> {
+        Ok(self
+            .walk_dir(Path::new("/"))?
+            .into_iter()
+            .filter(|ent| ent.metadata().is_file())
+            .collect())
+    }
+
+    /// Get recursive size of all files under a directory
+    ///
+    /// If directory size tracking is not enabled, this walks the directory
+    /// tree, so it takes O(n) time where n is the total number of files and
+    /// directories under it.
+    pub fn dir_size(&self, path: &Path) -> Result<usize> {
+        {
+            let fnode_ref = self.resolve(path, true)?;
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if self.opts.track_dir_sizes {
+                return Ok(fnode.dir_size());
+            }
+        }
+
+        Ok(self
+            .walk_dir(path)?
+            .iter()
+            .map(|ent| ent.metadata().content_len())
+            .sum())
+    }
+
+    /// Get content hash of current version of a regular file
+    pub fn content_hash(&self, path: &Path) -> Result<Hash> {
+        let fnode_ref = self.resolve(path, true)?;
+        let fnode = fnode_ref.read().unwrap();
+        if fnode.is_dir() {
+            return Err(Error::IsDir);
+        }
+        fnode.content_hash(&self.store)
+    }
+
+    /// Get paths of all files modified after specified time
+    ///
+    /// This walks the whole directory tree, so it takes O(n) time where n is
+    /// the total number of files and directories in the repo.
+    pub fn modified_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        let mut ret: Vec<PathBuf> = self
+            .files()?
+            .iter()
+            .filter(|ent| ent.metadata().modified_at() > since)
+            .map(|ent| ent.path().to_path_buf())
+            .collect();
+        ret.sort();
+        Ok(ret)
+    }
+
+    // get ids of segments used by all file versions
+    fn used_seg_ids(&self) -> Result<HashSet<Eid>> {
+        let mut seg_ids = HashSet::new();
+        for ent in self.files()? {
+            let fnode_ref = self.resolve(ent.path(), true)?;
+            let fnode = fnode_ref.read().unwrap();
+            seg_ids.extend(fnode.seg_ids(&self.store)?);
+        }
+        Ok(seg_ids)
+    }
+
+    /// Get total length of segments used by all file versions, in bytes
+    ///
+    /// Segments shared by multiple files or versions are counted once.
+    pub fn disk_usage(&self) -> Result<usize> {
+        let seg_ids = self.used_seg_ids()?;
+        let store = self.store.read().unwrap();
+        let mut usage = 0;
+        for seg_id in seg_ids.iter() {
+            usage += store.seg_usage(seg_id)?.0;
+        }
+        Ok(usage)
+    }
+
+    /// Get number of segments used by all file versions
+    ///
+    /// Segments shared by multiple files or versions are counted once.
+    #[inline]
+    pub fn seg_count(&self) -> Result<usize> {
+        self.used_seg_ids().map(|seg_ids| seg_ids.len())
+    }
+
+    /// Get space usage of the repo
+    ///
+    /// Return logical length of all files' current versions, length of
+    /// blocks allocated in volume, number of segments used by all file
+    /// versions and bytes saved by deduplication, all lengths are in bytes.
+    ///
+    /// Segments are walked through content store, segment data is not read.
+    pub fn space_usage(&self) -> Result<(usize, usize, usize, usize)> {
+        let mut logical = 0;
+        let mut referenced = 0;
+        let mut seg_ids = HashSet::new();
+        for ent in self.files()? {
+            let fnode_ref = self.resolve(ent.path(), true)?;
+            let fnode = fnode_ref.read().unwrap();
+            logical += fnode.curr_len();
+            referenced += fnode
+                .history()
+                .iter()
+                .filter(|ver| !ver.is_inline())
+                .map(Version::content_len)
+                .sum::<usize>();
+            seg_ids.extend(fnode.seg_ids(&self.store)?);
+        }
+
+        let stored = {
+            let store = self.store.read().unwrap();
+            let mut stored = 0;
+            for seg_id in seg_ids.iter() {
+                stored += store.seg_usage(seg_id)?.1;
+            }
+            stored
+        };
+
+        let physical = {
+            let vol = self.vol.read().unwrap();
+            vol.allocated_len()
+        };
+
+        Ok((
+            logical,
+            physical,
+            seg_ids.len(),
+            referenced.saturating_sub(stored),
+        ))
+    }
+
+    /// Compact all files in the repo
+    ///
+    /// If `gc` is true, orphan contents and segments are swept first. Then
+    /// for each file, it rewrites fragmented current version if `defrag` is
+    /// true, removes versions beyond `keep_versions` if it is specified, and
+    /// shrinks partially used segments if `gc` is true. Each file is
+    /// compacted in its own transactions.
+    ///
+    /// Deferred commits are saved after compaction.
+    ///
+    /// Return the number of touched files, the number of pruned versions,
+    /// the number and total length of swept orphan segments.
+    pub fn compact(
+        &mut self,
+        gc: bool,
+        defrag: bool,
+        keep_versions: Option<usize>,
+    ) -> Result<(usize, usize, usize, usize)> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // cannot compact while any file is open for write or being written,
+        // fail early here and each compaction transaction below is only
+        // begun when no other transaction is in progress, so writes started
+        // in the meantime are detected as well
+        if self.has_writers() || self.txmgr.read().unwrap().has_trans() {
+            return Err(Error::InUse);
+        }
+
+        let (orphan_segs, orphan_len) =
+            if gc { self.sweep_orphans()? } else { (0, 0) };
+
+        let mut files_touched = 0;
+        let mut vers_pruned = 0;
+
+        for ent in self.files()? {
+            let handle = self.open_fnode(ent.path())?;
+            let mut touched = false;
+
+            // rewrite fragmented content, it is useless when file dedup is
+            // enabled because the rewritten content will be deduped to the
+            // original one
+            // content bound to associated data is skipped as well, because
+            // the data is not available here
+            if defrag
+                && !self.opts.dedup_file
+                && !handle.fnode.read().unwrap().is_aad_bound()
+                && handle.fnode.read().unwrap().is_fragmented(&self.store)?
+            {
+                let tx_handle = TxMgr::begin_idle_trans(&self.txmgr)?;
+                tx_handle.run_all(|| {
+                    Fnode::defrag(handle.clone(), tx_handle.txid)
+                })?;
+                touched = true;
+            }
+
+            // prune versions beyond the limit
+            if let Some(keep) = keep_versions {
+                if handle.fnode.read().unwrap().history().len() > keep.max(1) {
+                    let tx_handle = TxMgr::begin_idle_trans(&self.txmgr)?;
+                    tx_handle.run_all(|| {
+                        let mut fnode = handle.fnode.write().unwrap();
+                        vers_pruned += fnode
+                            .make_mut(&self.txmgr)?
+                            .prune_versions(keep, &self.store, &self.txmgr)?;
+                        Ok(())
+                    })?;
+                    touched = true;
+                }
+            }
+
+            // shrink partially used segments
+            if gc && handle.fnode.read().unwrap().has_garbage(&self.store)? {
+                let tx_handle = TxMgr::begin_idle_trans(&self.txmgr)?;
+                tx_handle.run_all(|| {
+                    let mut fnode = handle.fnode.write().unwrap();
+                    fnode
+                        .make_mut(&self.txmgr)?
+                        .shrink_segs(&self.store, &self.txmgr)
+                })?;
+                touched = true;
+            }
+
+            if touched {
+                files_touched += 1;
+            }
+        }
+
+        self.sync()?;
+
+        Ok((files_touched, vers_pruned, orphan_segs, orphan_len))
+    }
+
+    // add a reference to current content of a file without adding a
+    // version, so the content is leaked when the file is removed
+    #[cfg(test)]
+    pub(crate) fn leak_content(&self, path: &Path) -> Result<()> {
+        let fnode_ref = self.resolve(path, true)?;
+        let ctn_id = fnode_ref.read().unwrap().content_ids().pop().unwrap();
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| Store::share_content(&self.store, &ctn_id))
+    }
+
+    // remove contents tracked in store but not used by any file version,
+    // and the segments which are not used by any other content
+    //
+    // Segments of such contents are diffed against segments reachable from
+    // file versions, the unreachable ones are orphans and removed even if
+    // their chunks are still referenced. References to contents beyond the
+    // number of versions using them are released as well.
+    //
+    // Return the number and total length of removed segments.
+    fn sweep_orphans(&mut self) -> Result<(usize, usize)> {
+        let mut used: HashMap<Eid, u32> = HashMap::new();
+        let mut live_segs = HashSet::new();
+        let mut fnodes = Vec::new();
+        for ent in self.files()? {
+            let fnode_ref = self.resolve(ent.path(), true)?;
+            {
+                let fnode = fnode_ref.read().unwrap();
+                for ctn_id in fnode.content_ids() {
+                    *used.entry(ctn_id).or_insert(0) += 1;
+                }
+                live_segs.extend(fnode.seg_ids(&self.store)?);
+            }
+            fnodes.push(fnode_ref);
+        }
+
+        // contents with more references than versions using them
+        let stale: Vec<(Eid, u32)> = {
+            let store = self.store.read().unwrap();
+            store
+                .content_refs()
+                .into_iter()
+                .filter_map(|(ctn_id, refcnt)| {
+                    let used = used.get(&ctn_id).cloned().unwrap_or(0);
+                    if refcnt > used {
+                        Some((ctn_id, used))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        if stale.is_empty() {
+            return Ok((0, 0));
+        }
+
+        // find orphan segments before the contents are released
+        let mut orphans: Vec<Eid> = Vec::new();
+        let mut orphan_len = 0;
+        {
+            let store = self.store.read().unwrap();
+            for (ctn_id, _) in stale.iter().filter(|(_, used)| *used == 0) {
+                let ctn_ref = store.get_content(ctn_id)?;
+                let ctn = ctn_ref.read().unwrap();
+                for seg_id in ctn.seg_ids() {
+                    if !live_segs.contains(&seg_id)
+                        && !orphans.contains(&seg_id)
+                    {
+                        orphan_len += store.seg_usage(&seg_id)?.0;
+                        orphans.push(seg_id);
+                    }
+                }
+            }
+        }
+
+        // release contents, segments are removed along with the orphan
+        // contents if their chunks are not referenced anymore
+        let mut leaked = Vec::new();
+        let tx_handle = TxMgr::begin_idle_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut chk_map = ChunkMap::new(false);
+            for (ctn_id, used) in stale.iter() {
+                if let Some(ctn) =
+                    Store::release_content(&self.store, ctn_id, *used)?
+                {
+                    let mut content = ctn.write().unwrap();
+                    content.unlink(&mut chk_map, &self.store, &self.txmgr)?;
+                    content.make_del(&self.txmgr)?;
+                }
+            }
+
+            // orphan segments must not be used by chunk dedup anymore
+            for fnode_ref in fnodes.iter() {
+                let mut fnode = fnode_ref.write().unwrap();
+                if fnode.refers_segs(&orphans) {
+                    fnode.make_mut(&self.txmgr)?.forget_segs(&orphans);
+                }
+            }
+
+            // orphan segments whose chunks are still referenced are not
+            // removed by releasing the contents
+            let store = self.store.read().unwrap();
+            for seg_id in orphans.iter() {
+                if !store.get_seg(seg_id)?.read().unwrap().is_orphan() {
+                    leaked.push(seg_id.clone());
+                }
+            }
+            Ok(())
+        })?;
+
+        // remove the leaked orphan segments, they are not used by any
+        // content
+        if !leaked.is_empty() {
+            let tx_handle = TxMgr::begin_idle_trans(&self.txmgr)?;
+            tx_handle.run_all(|| {
+                for seg_id in leaked.iter() {
+                    Store::remove_seg(&self.store, seg_id)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok((orphans.len(), orphan_len))
+    }
+
+    /// Set modified time of specified path
+    pub fn set_mtime(&mut self, path: &Path, mtime: Time) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path, true)?;
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode = fnode_ref.write().unwrap();
+            fnode.make_mut(&self.txmgr)?.set_mtime(mtime);
+            Ok(())
+        })?;
+        self.events.emit(RepoEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Set append-only flag of specified file
+    pub fn set_append_only(
+        &mut self,
+        path: &Path,
+        append_only: bool,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path, true)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+            if fnode.is_append_only() == append_only {
+                return Ok(());
+            }
+        }
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode = fnode_ref.write().unwrap();
+            fnode.make_mut(&self.txmgr)?.set_append_only(append_only);
+            Ok(())
+        })?;
+        self.events.emit(RepoEvent::Modify(path.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Remove oldest versions of a regular file, keep at most `keep` latest
+    /// versions and return the number of removed versions
+    pub fn prune_versions(
+        &mut self,
+        path: &Path,
+        keep: usize,
+    ) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let fnode_ref = self.resolve(path, true)?;
+        {
+            let fnode = fnode_ref.read().unwrap();
+            if !fnode.is_file() {
+                return Err(Error::NotFile);
+            }
+            if fnode.history().len() <= keep.max(1) {
+                return Ok(0);
+            }
+        }
+
+        let mut pruned = 0;
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode = fnode_ref.write().unwrap();
+            pruned = fnode.make_mut(&self.txmgr)?.prune_versions(
+                keep,
+                &self.store,
+                &self.txmgr,
+            )?;
+            Ok(())
+        })?;
+        self.events.emit(RepoEvent::Modify(path.to_path_buf()));
+
+        Ok(pruned)
     }
 
     /// Copy a regular file to another
-    pub fn copy(&mut self, from: &Path, to: &Path) -> Result<()> {
+    pub fn copy(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        preserve_times: bool,
+    ) -> Result<()> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
         let opts;
-        let src = self.resolve(from)?;
+        let src = self.resolve(from, true)?;
         {
             let fnode = src.read().unwrap();
             if !fnode.is_file() {
@@ -398,6 +1822,9 @@ impl Fs {
                         if !fnode.is_file() {
                             return Err(Error::NotFile);
                         }
+                        if fnode.is_append_only() {
+                            return Err(Error::AppendOnly);
+                        }
                     }
                     tgt
                 }
@@ -414,32 +1841,142 @@ impl Fs {
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
         tx_handle.run_all_exclusive(|| {
             // get current version of source
-            let ctn = {
+            let (ctn, inline, aad_hash) = {
                 let fnode = src.read().unwrap();
-                fnode.clone_current_content(&self.store)?
+                match fnode.curr_inline() {
+                    Some(data) => (None, Some(data.to_vec()), None),
+                    None => (
+                        Some(fnode.clone_current_content(&self.store)?),
+                        None,
+                        fnode.aad_hash(),
+                    ),
+                }
             };
 
             // then add it to target
-            let mut fnode_cow = tgt.fnode.write().unwrap();
-            let fnode = fnode_cow.make_mut(&self.txmgr)?;
-            let result = fnode.add_version(ctn, &self.store, &self.txmgr)?;
-            assert!(!(self.opts.dedup_file && result));
+            let old_len = {
+                let mut fnode_cow = tgt.fnode.write().unwrap();
+                let fnode = fnode_cow.make_mut(&self.txmgr)?;
+                let old_len = fnode.curr_len();
+                if let Some(data) = inline {
+                    // inline content is copied along with the version
+                    fnode.add_inline_version(data, &self.store, &self.txmgr)?;
+                } else if let Some(ctn) = ctn {
+                    let result =
+                        fnode.add_version(ctn, &self.store, &self.txmgr)?;
+                    assert!(!(self.opts.dedup_file && result));
+                }
+                fnode.set_aad_hash(aad_hash);
+                if preserve_times {
+                    fnode.copy_times(&src.read().unwrap());
+                }
+                old_len
+            };
+            Fnode::propagate_len(&tgt.fnode, old_len, &self.txmgr)
+        })?;
+        self.events.emit(RepoEvent::Modify(to.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Create a new regular file sharing current content of another file
+    pub fn clone_file(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let src = self.resolve(from, true)?;
+        if !src.read().unwrap().is_file() {
+            return Err(Error::NotFile);
+        }
+
+        let (parent, name) = self.resolve_parent(to)?;
+        {
+            let parent = parent.read().unwrap();
+            if !parent.is_dir() {
+                return Err(Error::NotDir);
+            }
+            if parent.has_child(&name) {
+                return Err(Error::AlreadyExists);
+            }
+        }
 
+        // begin and run transaction
+        let mut fnode = FnodeRef::default();
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all_exclusive(|| {
+            fnode = Fnode::new_clone_under(
+                &parent,
+                &name,
+                &src.read().unwrap(),
+                &self.txmgr,
+                &self.store,
+            )?;
             Ok(())
         })?;
 
+        self.fcache.insert(&fnode);
+        self.events.emit(RepoEvent::Create(to.to_path_buf()));
+
         Ok(())
     }
 
-    /// Copy a dir to another recursively
-    pub fn copy_dir_all(&mut self, from: &Path, to: &Path) -> Result<()> {
+    // walk directory tree under a path and collect its entries' paths and
+    // file types, a directory is added before or after its children
+    fn walk_tree(
+        &self,
+        path: &Path,
+        post_order: bool,
+        entries: &mut Vec<(PathBuf, FileType)>,
+    ) -> Result<()> {
+        for child in self.read_dir(path)? {
+            let child_path = child.path().to_path_buf();
+            let file_type = child.metadata().file_type();
+            if file_type == FileType::Dir {
+                if !post_order {
+                    entries.push((child_path.clone(), file_type));
+                }
+                self.walk_tree(&child_path, post_order, entries)?;
+                if post_order {
+                    entries.push((child_path, file_type));
+                }
+            } else {
+                entries.push((child_path, file_type));
+            }
+        }
+        Ok(())
+    }
+
+    // check if path can be a target dir, return false if it doesn't exist
+    fn check_target_dir(&self, path: &Path) -> Result<bool> {
+        match self.resolve(path, true) {
+            Ok(tgt) => {
+                if !tgt.read().unwrap().is_dir() {
+                    return Err(Error::NotDir);
+                }
+                Ok(true)
+            }
+            Err(ref err) if *err == Error::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get entries copied by copy_dir_all, as source and target paths and
+    /// file type, a directory comes before its children
+    pub fn plan_copy_dir_all(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<(PathBuf, PathBuf, FileType)>> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
+        let (from, to) = (&check_path(from)?, &check_path(to)?);
+
         // if target and source are same fnode, do nothing
         if from == to {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
         if to.starts_with(from) {
@@ -448,58 +1985,125 @@ impl Fs {
 
         // sanity check source and target
         {
-            let src = self.resolve(from)?;
-            {
-                let fnode = src.read().unwrap();
-                if !fnode.is_dir() {
-                    return Err(Error::NotDir);
-                }
+            let src = self.resolve(from, true)?;
+            if !src.read().unwrap().is_dir() {
+                return Err(Error::NotDir);
             }
+            self.check_target_dir(to)?;
+        }
 
-            match self.resolve(to) {
-                Ok(tgt) => {
-                    assert!(!Arc::ptr_eq(&tgt, &src));
-                    let fnode = tgt.read().unwrap();
-                    if !fnode.is_dir() {
-                        return Err(Error::NotDir);
+        let mut entries = Vec::new();
+        self.walk_tree(from, false, &mut entries)?;
+
+        let mut plan = Vec::with_capacity(entries.len());
+        for (child_from, file_type) in entries {
+            let child_to = to.join(child_from.strip_prefix(from).unwrap());
+            if file_type == FileType::Dir {
+                self.check_target_dir(&child_to)?;
+            }
+            plan.push((child_from, child_to, file_type));
+        }
+        Ok(plan)
+    }
+
+    /// Copy a dir to another recursively
+    pub fn copy_dir_all(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        preserve_times: bool,
+    ) -> Result<()> {
+        let plan = self.plan_copy_dir_all(from, to)?;
+        let (from, to) = (&check_path(from)?, &check_path(to)?);
+        if from == to {
+            return Ok(());
+        }
+
+        // create target dir if it doesn't exist
+        if !self.check_target_dir(to)? {
+            self.create_fnode(to, FileType::Dir, Options::default())?;
+        }
+
+        // copy dir tree
+        for (child_from, child_to, file_type) in plan.iter() {
+            match file_type {
+                FileType::File => {
+                    self.copy(child_from, child_to, preserve_times)?
+                }
+                FileType::Dir => {
+                    if !self.check_target_dir(child_to)? {
+                        self.create_fnode(
+                            child_to,
+                            FileType::Dir,
+                            Options::default(),
+                        )?;
                     }
                 }
-                Err(ref err) if *err == Error::NotFound => {
-                    // create target dir if it doesn't exist
-                    self.create_fnode(to, FileType::Dir, Options::default())?;
+                FileType::SymLink => {
+                    // symbolic link is copied as link, not its target
+                    let target = self.read_link(child_from)?;
+                    self.symlink(&target, child_to)?;
+                    if preserve_times {
+                        self.copy_times(child_from, child_to)?;
+                    }
                 }
-                Err(err) => return Err(err),
             }
         }
 
-        // copy dir tree
-        for child in self.read_dir(from)? {
-            let child_from = child.path();
-            let child_to = to.join(child.file_name());
-            match child.metadata().file_type() {
-                FileType::File => self.copy(child_from, &child_to)?,
-                FileType::Dir => self.copy_dir_all(child_from, &child_to)?,
+        // directory times are copied after its children are added, as adding
+        // child changes its modified time
+        if preserve_times {
+            for (child_from, child_to, file_type) in plan.iter().rev() {
+                if *file_type == FileType::Dir {
+                    self.copy_times(child_from, child_to)?;
+                }
             }
+            self.copy_times(from, to)?;
         }
 
         Ok(())
     }
 
-    /// Remove a regular file
+    // copy created and modified time from one fnode to another, symbolic
+    // links are not followed
+    fn copy_times(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let src = self.resolve(from, false)?;
+        let tgt = self.resolve(to, false)?;
+
+        // begin and run transaction
+        let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
+        tx_handle.run_all(|| {
+            let mut fnode = tgt.write().unwrap();
+            fnode
+                .make_mut(&self.txmgr)?
+                .copy_times(&src.read().unwrap());
+            Ok(())
+        })?;
+        self.events.emit(RepoEvent::Modify(to.to_path_buf()));
+
+        Ok(())
+    }
+
+    /// Remove a regular file or symbolic link
     pub fn remove_file(&mut self, path: &Path) -> Result<()> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
-        let fnode_ref = self.resolve(path)?;
+        let fnode_ref = self.resolve(path, false)?;
         {
             let fnode = fnode_ref.read().unwrap();
-            if !fnode.is_file() {
+            if fnode.is_dir() {
                 return Err(Error::NotFile);
             }
+            if fnode.is_append_only() {
+                return Err(Error::AppendOnly);
+            }
         }
 
-        // begin and run transaction
+        // begin and run transaction, the fnode is moved in so it is not in
+        // use after the transaction
+        let events = self.events.clone();
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
         tx_handle.run_all_exclusive(move || {
             Fnode::remove_from_parent(&fnode_ref, &self.txmgr)?;
@@ -511,6 +2115,7 @@ impl Fs {
             self.fcache.remove(fnode.id());
             Ok(())
         })?;
+        events.emit(RepoEvent::Remove(path.to_path_buf()));
 
         Ok(())
     }
@@ -521,7 +2126,7 @@ impl Fs {
             return Err(Error::ReadOnly);
         }
 
-        let fnode_ref = self.resolve(path)?;
+        let fnode_ref = self.resolve(path, false)?;
         {
             let fnode = fnode_ref.read().unwrap();
             if !fnode.is_dir() {
@@ -535,7 +2140,9 @@ impl Fs {
             }
         }
 
-        // begin and run transaction
+        // begin and run transaction, the fnode is moved in so it is not in
+        // use after the transaction
+        let events = self.events.clone();
         let tx_handle = TxMgr::begin_trans(&self.txmgr)?;
         tx_handle.run_all(move || {
             Fnode::remove_from_parent(&fnode_ref, &self.txmgr)?;
@@ -544,48 +2151,70 @@ impl Fs {
             self.fcache.remove(fnode.id());
             Ok(())
         })?;
+        events.emit(RepoEvent::Remove(path.to_path_buf()));
 
         Ok(())
     }
 
+    /// Get entries removed by remove_dir_all, as paths and file types in
+    /// the order they are removed, root dir itself is not removed
+    pub fn plan_remove_dir_all(
+        &self,
+        path: &Path,
+    ) -> Result<Vec<(PathBuf, FileType)>> {
+        let path = check_path(path)?;
+        let mut plan = Vec::new();
+        self.walk_tree(&path, true, &mut plan)?;
+        if path.parent().is_some() {
+            plan.push((path, FileType::Dir));
+        }
+        Ok(plan)
+    }
+
     /// Remove an existing directory recursively
     pub fn remove_dir_all(&mut self, path: &Path) -> Result<()> {
-        for child in self.read_dir(path)? {
-            let child_path = child.path();
-            match child.metadata().file_type() {
-                FileType::File => self.remove_file(child_path)?,
-                FileType::Dir => self.remove_dir_all(child_path)?,
-            }
+        if self.read_only {
+            return Err(Error::ReadOnly);
         }
-        match self.remove_dir(path) {
-            Ok(_) => Ok(()),
-            Err(ref err) if *err == Error::IsRoot => Ok(()),
-            Err(err) => Err(err),
+
+        for (path, file_type) in self.plan_remove_dir_all(path)? {
+            match file_type {
+                FileType::File | FileType::SymLink => {
+                    self.remove_file(&path)?
+                }
+                FileType::Dir => self.remove_dir(&path)?,
+            }
         }
+        Ok(())
     }
 
     /// Rename a file or directory to new name
-    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+    pub fn rename(
+        &mut self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Option<Metadata>> {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
 
+        let (from, to) = (&check_path(from)?, &check_path(to)?);
         if from == to {
-            return Ok(());
+            return Ok(None);
         }
 
         if to.starts_with(from) {
             return Err(Error::InvalidArgument);
         }
 
-        let src = self.resolve(from)?;
-        let tgt = match self.resolve(to) {
+        let src = self.resolve(from, false)?;
+        let tgt = match self.resolve(to, false) {
             Ok(tgt) => Some(tgt),
             Err(ref err) if *err == Error::NotFound => None,
             Err(err) => return Err(err),
         };
 
-        {
+        let tgt_md = {
             let src_fnode = src.read().unwrap();
             if src_fnode.is_root() {
                 return Err(Error::IsRoot);
@@ -596,11 +2225,14 @@ impl Fs {
                 if tgt_fnode.is_root() {
                     return Err(Error::IsRoot);
                 }
-                if src_fnode.is_file() && tgt_fnode.is_dir() {
+                if !src_fnode.is_dir() && tgt_fnode.is_dir() {
                     return Err(Error::IsDir);
                 }
+                if tgt_fnode.is_append_only() {
+                    return Err(Error::AppendOnly);
+                }
                 if src_fnode.is_dir() {
-                    if tgt_fnode.is_file() {
+                    if !tgt_fnode.is_dir() {
                         return Err(Error::NotDir);
                     }
                     if tgt_fnode.children_cnt() > 0 {
@@ -608,7 +2240,10 @@ impl Fs {
                     }
                 }
             }
-        }
+
+            // capture target metadata before it is replaced
+            tgt.as_ref().map(|tgt| tgt.read().unwrap().metadata())
+        };
 
         let (tgt_parent, name) = self.resolve_parent(to)?;
 
@@ -632,7 +2267,332 @@ impl Fs {
 
             // and then add to target
             Fnode::add_child(&tgt_parent, &src, &name, &self.txmgr)
-        })
+        })?;
+        self.events.emit(RepoEvent::Rename {
+            from: from.clone(),
+            to: to.clone(),
+        });
+
+        Ok(tgt_md)
+    }
+
+    /// Rename a file or directory only if `to` doesn't exist
+    pub fn rename_no_replace(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let (from, to) = (&check_path(from)?, &check_path(to)?);
+        if to.starts_with(from) {
+            return Err(if from == to {
+                Error::AlreadyExists
+            } else {
+                Error::InvalidArgument
+            });
+        }
+
+        let src = self.resolve(from, false)?;
+        if src.read().unwrap().is_root() {
+            return Err(Error::IsRoot);
+        }
+
+        let (tgt_parent, name) = self.resolve_parent(to)?;
+
+        // begin and run transaction, target existence is checked inside
+        // the transaction so no one else can create it in between
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            if tgt_parent.read().unwrap().has_child(&name) {
+                return Err(Error::AlreadyExists);
+            }
+            Fnode::remove_from_parent(&src, &self.txmgr)?;
+            Fnode::add_child(&tgt_parent, &src, &name, &self.txmgr)
+        })?;
+        self.events.emit(RepoEvent::Rename {
+            from: from.clone(),
+            to: to.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Move a regular file, it is moved into `to` if `to` is a directory
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let from = &check_path(from)?;
+        if !self.resolve(from, false)?.read().unwrap().is_file() {
+            return Err(Error::NotFile);
+        }
+
+        let to = &check_path(to)?;
+        let to = match self.resolve(to, true) {
+            Ok(tgt) if tgt.read().unwrap().is_dir() => {
+                to.join(from.file_name().ok_or(Error::InvalidPath)?)
+            }
+            Ok(_) => to.to_path_buf(),
+            Err(ref err) if *err == Error::NotFound => to.to_path_buf(),
+            Err(err) => return Err(err),
+        };
+        self.rename(from, &to).map(|_| ())
+    }
+
+    // collect entries to be moved when merging directory `from` into
+    // existing directory `to`, source directories emptied after moving are
+    // collected in post-order
+    fn plan_move_all(
+        &self,
+        from: &Path,
+        to: &Path,
+        moves: &mut Vec<Move>,
+        emptied: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        for child in self.read_dir(from)? {
+            let child_from = child.path().to_path_buf();
+            let child_to = to.join(child.file_name());
+            let src_is_dir = child.metadata().is_dir();
+
+            let replace = match self.resolve(&child_to, false) {
+                Ok(tgt) => {
+                    let (tgt_is_dir, tgt_is_append_only) = {
+                        let tgt = tgt.read().unwrap();
+                        (tgt.is_dir(), tgt.is_append_only())
+                    };
+                    match (src_is_dir, tgt_is_dir) {
+                        // merge into existing directory
+                        (true, true) => {
+                            self.plan_move_all(
+                                &child_from,
+                                &child_to,
+                                moves,
+                                emptied,
+                            )?;
+                            continue;
+                        }
+                        (true, false) => return Err(Error::NotDir),
+                        (false, true) => return Err(Error::IsDir),
+                        (false, false) if tgt_is_append_only => {
+                            return Err(Error::AppendOnly);
+                        }
+                        (false, false) => Some(tgt),
+                    }
+                }
+                Err(ref err) if *err == Error::NotFound => None,
+                Err(err) => return Err(err),
+            };
+
+            let src = self.resolve(&child_from, false)?;
+            let (tgt_parent, name) = self.resolve_parent(&child_to)?;
+            moves.push(Move {
+                from: child_from,
+                to: child_to,
+                src,
+                tgt_parent,
+                name,
+                replace,
+            });
+        }
+
+        emptied.push(from.to_path_buf());
+        Ok(())
+    }
+
+    /// Move a directory tree into another directory, entries are re-parented
+    /// and existing directories in target are merged
+    pub fn move_all(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let (from, to) = (&check_path(from)?, &check_path(to)?);
+        if from == to {
+            return Ok(());
+        }
+
+        if to.starts_with(from) {
+            return Err(Error::InvalidArgument);
+        }
+
+        if !self.resolve(from, false)?.read().unwrap().is_dir() {
+            return Err(Error::NotDir);
+        }
+
+        // if target doesn't exist, this is just a rename
+        match self.resolve(to, false) {
+            Ok(tgt) => {
+                if !tgt.read().unwrap().is_dir() {
+                    return Err(Error::NotDir);
+                }
+            }
+            Err(ref err) if *err == Error::NotFound => {
+                return self.rename(from, to).map(|_| ());
+            }
+            Err(err) => return Err(err),
+        }
+
+        let mut moves = Vec::new();
+        let mut emptied = Vec::new();
+        self.plan_move_all(from, to, &mut moves, &mut emptied)?;
+        let events: Vec<RepoEvent> = moves
+            .iter()
+            .map(|mv| RepoEvent::Rename {
+                from: mv.from.clone(),
+                to: mv.to.clone(),
+            })
+            .collect();
+
+        // begin and run transaction, the fnodes are moved in so they are not
+        // in use after the transaction
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            for mv in moves {
+                Fnode::remove_from_parent(&mv.src, &self.txmgr)?;
+
+                // remove target if it exists
+                if let Some(tgt_fnode) = mv.replace {
+                    Fnode::remove_from_parent(&tgt_fnode, &self.txmgr)?;
+                    let mut tgt_fnode = tgt_fnode.write().unwrap();
+                    if tgt_fnode.is_file() {
+                        tgt_fnode
+                            .make_mut(&self.txmgr)?
+                            .clear_versions(&self.store, &self.txmgr)?;
+                    }
+                    tgt_fnode.make_del(&self.txmgr)?;
+                    self.fcache.remove(tgt_fnode.id());
+                }
+
+                Fnode::add_child(
+                    &mv.tgt_parent,
+                    &mv.src,
+                    &mv.name,
+                    &self.txmgr,
+                )?;
+            }
+            Ok(())
+        })?;
+        for event in events {
+            self.events.emit(event);
+        }
+
+        // the moved entries still refer to their old parents until the
+        // transaction above is completed, so the emptied source directories
+        // can only be removed afterwards
+        for path in emptied {
+            self.remove_dir(&path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Begin a joinable transaction, operations on current thread join it
+    /// and their events are held until it is ended
+    pub fn begin_joinable_trans(&self) -> Result<TxHandle> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let tx_handle = TxMgr::begin_joinable_trans(&self.txmgr)?;
+        self.events.hold();
+        Ok(tx_handle)
+    }
+
+    /// End a joinable transaction, held events are sent if it is committed
+    pub fn end_joinable_trans<T>(
+        &self,
+        tx_handle: &TxHandle,
+        result: Result<T>,
+    ) -> Result<T> {
+        let result = tx_handle.end_joinable(result);
+        self.events.release(result.is_ok());
+        result
+    }
+
+    /// Exchange two files or directories
+    pub fn swap(&mut self, a: &Path, b: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let (a, b) = (&check_path(a)?, &check_path(b)?);
+        if a == b {
+            return Ok(());
+        }
+
+        // cannot exchange with own ancestor or descendant
+        if a.starts_with(b) || b.starts_with(a) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let fnode_a = self.resolve(a, false)?;
+        let fnode_b = self.resolve(b, false)?;
+        if fnode_a.read().unwrap().is_root()
+            || fnode_b.read().unwrap().is_root()
+        {
+            return Err(Error::IsRoot);
+        }
+
+        let (parent_a, name_a) = self.resolve_parent(a)?;
+        let (parent_b, name_b) = self.resolve_parent(b)?;
+
+        // begin and run transaction
+        TxMgr::begin_trans(&self.txmgr)?.run_all_exclusive(|| {
+            Fnode::remove_from_parent(&fnode_a, &self.txmgr)?;
+            Fnode::remove_from_parent(&fnode_b, &self.txmgr)?;
+            Fnode::add_child(&parent_b, &fnode_a, &name_b, &self.txmgr)?;
+            Fnode::add_child(&parent_a, &fnode_b, &name_a, &self.txmgr)
+        })?;
+
+        // both paths are still there but with exchanged entries
+        self.events.emit(RepoEvent::Modify(a.clone()));
+        self.events.emit(RepoEvent::Modify(b.clone()));
+
+        Ok(())
+    }
+
+    /// Freeze current state of the whole file system
+    ///
+    /// Nothing is read when freezing, the frozen state is loaded lazily
+    /// when it is read.
+    pub fn freeze(&self) -> Result<FrozenFs> {
+        // pin wal queue, so entities removed afterwards are not recycled
+        // and entities updated afterwards are kept as they are now
+        let pin = TxMgr::pin_wals(&self.txmgr);
+        Ok(FrozenFs::new(
+            &self.root,
+            &self.fcache,
+            &self.store,
+            Arc::downgrade(&self.vol),
+            &self.shutter,
+            pin,
+        ))
+    }
+
+    /// Take a snapshot of the underlying storage
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        // save deferred commits first, so the snapshot is consistent
+        if !self.read_only {
+            let mut txmgr = self.txmgr.write().unwrap();
+            txmgr.sync()?;
+        }
+        let vol = self.vol.read().unwrap();
+        vol.snapshot()
+    }
+
+    /// Restore a snapshot to a new memory storage and open fs on it
+    pub fn from_snapshot(snapshot: &[u8], pwd: &str) -> Result<Fs> {
+        let uri = format!("mem://{}", Eid::new().to_string());
+        let mut vol = Volume::new(&uri)?;
+        vol.restore(snapshot)?;
+
+        // remove the restored storage if it cannot be opened
+        let ret = Self::open(
+            &uri,
+            pwd,
+            None,
+            false,
+            false,
+            &TransportConfig::default(),
+            SyncLevel::default(),
+        );
+        if ret.is_err() {
+            let _ = vol.destroy();
+        }
+        ret
     }
 
     /// Destroy the whole file system
@@ -640,15 +2600,22 @@ impl Fs {
     pub fn destroy(uri: &str) -> Result<()> {
         let mut vol = Volume::new(uri)?;
         vol.destroy()?;
-        info!("repo destroyed");
+        log_event!(info, "repo.destroy", uri = mask_uri(uri));
         Ok(())
     }
 }
 
 impl Drop for Fs {
     fn drop(&mut self) {
+        if let Err(err) = self.sync() {
+            log_event!(warn, "repo.sync_failed", error = err);
+        }
         let mut shutter = self.shutter.write().unwrap();
         shutter.close();
-        info!("repo closed");
+        log_event!(
+            info,
+            "repo.close",
+            uri = mask_uri(&self.vol.read().unwrap().info().uri)
+        );
     }
 }
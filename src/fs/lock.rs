@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::error::{Error, Result};
+use crate::trans::Eid;
+
+/// Advisory lock kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+// lock holders of a fnode
+#[derive(Debug, Default)]
+struct LockState {
+    shared: usize,
+    exclusive: bool,
+}
+
+impl LockState {
+    #[inline]
+    fn can_lock(&self, kind: LockKind) -> bool {
+        match kind {
+            LockKind::Shared => !self.exclusive,
+            LockKind::Exclusive => !self.exclusive && self.shared == 0,
+        }
+    }
+}
+
+/// Advisory lock table
+///
+/// Locks are keyed by fnode id, so they are held on a file regardless of
+/// the path used to open it. Locks are advisory, they don't prevent any
+/// file operation and only bind the callers who use them.
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: Mutex<HashMap<Eid, LockState>>,
+    cond: Condvar,
+}
+
+impl LockTable {
+    pub fn new() -> LockTableRef {
+        Arc::new(LockTable::default())
+    }
+
+    /// Acquire a lock on fnode, wait until it is available if `wait` is
+    /// true, otherwise return `WouldBlock` error
+    pub fn lock(&self, id: &Eid, kind: LockKind, wait: bool) -> Result<()> {
+        let mut locks = self.locks.lock().unwrap();
+        loop {
+            let state = locks.entry(id.clone()).or_default();
+            if state.can_lock(kind) {
+                match kind {
+                    LockKind::Shared => state.shared += 1,
+                    LockKind::Exclusive => state.exclusive = true,
+                }
+                return Ok(());
+            }
+            if !wait {
+                return Err(Error::WouldBlock);
+            }
+            locks = self.cond.wait(locks).unwrap();
+        }
+    }
+
+    /// Release a lock on fnode
+    pub fn unlock(&self, id: &Eid, kind: LockKind) {
+        let mut locks = self.locks.lock().unwrap();
+        if let Some(state) = locks.get_mut(id) {
+            match kind {
+                LockKind::Shared => {
+                    state.shared = state.shared.saturating_sub(1)
+                }
+                LockKind::Exclusive => state.exclusive = false,
+            }
+            if state.shared == 0 && !state.exclusive {
+                locks.remove(id);
+            }
+        }
+        self.cond.notify_all();
+    }
+}
+
+pub type LockTableRef = Arc<LockTable>;
@@ -2,27 +2,59 @@
 //! fs module document
 //!
 
+mod content_cache;
+mod event;
 pub mod fnode;
+mod frozen;
 mod fs;
+mod lock;
+mod walk;
+
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
-pub use self::fnode::{DirEntry, FileType, Fnode, FnodeRef, Metadata, Version};
+pub use self::content_cache::{ContentCache, ContentCacheRef, MAX_CACHED_LEN};
+pub use self::event::{EventHub, EventHubRef, RepoEvent};
+pub use self::fnode::{
+    DirCursor, DirEntry, FileType, Fnode, FnodeRef, Metadata, ReadDir, Version,
+};
+pub use self::frozen::FrozenFs;
 pub use self::fs::{Fs, ShutterRef};
+pub use self::lock::{LockKind, LockTable, LockTableRef};
+pub use self::walk::Walk;
 
 use crate::base::crypto::{Cipher, Cost, Crypto};
-use crate::content::StoreWeakRef;
+use crate::content::{
+    StoreWeakRef, DEFAULT_AVG_CHUNK_SIZE, DEFAULT_MAX_CHUNK_SIZE,
+    DEFAULT_MIN_CHUNK_SIZE,
+};
+use crate::error::{Error, Result};
 use crate::trans::TxMgrWeakRef;
+use crate::volume::{Compression, BLKS_PER_FRAME, BLK_SIZE};
 
 // Default file versoin limit
 const DEFAULT_VERSION_LIMIT: u8 = 1;
 
+// Maximum file size which can be stored inline in fnode
+pub const MAX_INLINE_THRESHOLD: usize = 64 * 1024;
+
+// Minimum memory budget for caches and buffers
+pub const MIN_MEMORY_BUDGET: usize = 1024 * 1024;
+
 // Options
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Options {
     pub version_limit: u8,
     pub dedup_chunk: bool,
     pub dedup_file: bool,
+    #[serde(default)]
+    pub track_dir_sizes: bool,
+    #[serde(default)]
+    pub inline_threshold: usize,
+    #[serde(default)]
+    pub compress: Option<bool>,
 }
 
 impl Default for Options {
@@ -31,6 +63,9 @@ impl Default for Options {
             version_limit: DEFAULT_VERSION_LIMIT,
             dedup_chunk: false,
             dedup_file: false,
+            track_dir_sizes: false,
+            inline_threshold: 0,
+            compress: None,
         }
     }
 }
@@ -40,8 +75,14 @@ impl Default for Options {
 pub struct Config {
     pub cost: Cost,
     pub cipher: Cipher,
-    pub compress: bool,
+    pub compression: Compression,
     pub opts: Options,
+    pub label: Option<String>,
+    pub blk_size: usize,
+    pub blks_per_frame: usize,
+    pub chunk_min_size: usize,
+    pub chunk_avg_size: usize,
+    pub chunk_max_size: usize,
 }
 
 impl Default for Config {
@@ -53,8 +94,14 @@ impl Default for Config {
             } else {
                 Cipher::Xchacha
             },
-            compress: false,
+            compression: Compression::None,
             opts: Options::default(),
+            label: None,
+            blk_size: BLK_SIZE,
+            blks_per_frame: BLKS_PER_FRAME,
+            chunk_min_size: DEFAULT_MIN_CHUNK_SIZE,
+            chunk_avg_size: DEFAULT_AVG_CHUNK_SIZE,
+            chunk_max_size: DEFAULT_MAX_CHUNK_SIZE,
         }
     }
 }
@@ -66,4 +113,26 @@ pub struct Handle {
     pub store: StoreWeakRef,
     pub txmgr: TxMgrWeakRef,
     pub shutter: ShutterRef,
+    pub events: EventHubRef,
+    pub locks: LockTableRef,
+    pub contents: ContentCacheRef, // cached content of small files
+    pub path: Arc<PathBuf>, // path when opened, tracked by fs while alive
+    pub low_memory: bool,   // use minimal buffering
+    pub skip_identical: bool, // skip version same as current one
+    pub aad: Vec<u8>,       // associated data for content encryption
+    pub max_len: Option<usize>, // maximum content length
+    pub replace: bool,      // written content replaces whole content
+    pub blk_size: usize,    // volume block size
+    pub frame_size: usize,  // volume frame size
+}
+
+impl Handle {
+    /// Check if content length is within the maximum length
+    #[inline]
+    pub fn check_len(&self, len: usize) -> Result<()> {
+        match self.max_len {
+            Some(max_len) if len > max_len => Err(Error::FileTooLarge),
+            _ => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,200 @@
+use std::fmt::{self, Debug};
+use std::path::PathBuf;
+
+use super::fnode::{Metadata, ReadDir};
+use crate::error::{Error, Result};
+
+// a directory being walked
+struct Frame {
+    rdr: ReadDir,
+
+    // the directory itself, it is only set in post-order walk and returned
+    // after all its children
+    ent: Option<(PathBuf, Metadata)>,
+
+    depth: usize,
+}
+
+/// A recursive iterator over a directory tree.
+///
+/// This iterator is returned from the [`walk`] function. It walks the tree
+/// depth-first and yields path and metadata of each entry, including the
+/// root itself at depth 0. Symbolic links in the tree are yielded as links,
+/// they are not followed.
+///
+/// By default, a directory is yielded before its children (pre-order), an
+/// error is yielded for an entry which cannot be read and the walk continues
+/// with the next entry.
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// # let mut repo = RepoOpener::new()
+/// #     .create(true)
+/// #     .open("mem://foo", "pwd")?;
+/// repo.create_dir_all("/dir/sub")?;
+/// repo.create_file("/dir/sub/file")?;
+///
+/// let mut walk = repo.walk("/dir")?;
+/// walk.max_depth(1);
+/// for ent in walk {
+///     let (path, metadata) = ent?;
+///     println!("{}: {:?}", path.display(), metadata.file_type());
+/// }
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`walk`]: struct.Repo.html#method.walk
+pub struct Walk {
+    // the root entry and its children reader, it is taken at the first
+    // iteration
+    root: Option<((PathBuf, Metadata), Option<ReadDir>)>,
+
+    stack: Vec<Frame>,
+    max_depth: usize,
+    post_order: bool,
+    strict: bool,
+    failed: bool,
+}
+
+impl Walk {
+    pub(super) fn new(
+        path: PathBuf,
+        metadata: Metadata,
+        rdr: Option<ReadDir>,
+    ) -> Self {
+        Walk {
+            root: Some(((path, metadata), rdr)),
+            stack: Vec::new(),
+            max_depth: usize::MAX,
+            post_order: false,
+            strict: false,
+            failed: false,
+        }
+    }
+
+    /// Sets the maximum depth of entries to walk.
+    ///
+    /// The root is at depth 0, its children are at depth 1 and so on.
+    /// Setting it to 0 only yields the root. Default is no limit.
+    pub fn max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the option for yielding a directory after its children.
+    ///
+    /// Post-order is useful when entries are going to be removed, as a
+    /// directory can only be removed after its children. Default is false.
+    pub fn post_order(&mut self, post_order: bool) -> &mut Self {
+        self.post_order = post_order;
+        self
+    }
+
+    /// Sets the option for stopping the walk at the first error.
+    ///
+    /// When true, the walk ends after the first error is yielded. Default is
+    /// false, the walk continues with the next entry.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    // descend into a directory, return the entry if it should be yielded now
+    fn enter(
+        &mut self,
+        ent: (PathBuf, Metadata),
+        rdr: ReadDir,
+        depth: usize,
+    ) -> Option<(PathBuf, Metadata)> {
+        let (ent, ret) = if self.post_order {
+            (Some(ent), None)
+        } else {
+            (None, Some(ent))
+        };
+        self.stack.push(Frame { rdr, ent, depth });
+        ret
+    }
+
+    // yield an error, and end the walk if it is in strict mode
+    fn fail(&mut self, err: Error) -> Option<Result<(PathBuf, Metadata)>> {
+        if self.strict {
+            self.failed = true;
+        }
+        Some(Err(err))
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<(PathBuf, Metadata)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        if let Some((ent, rdr)) = self.root.take() {
+            match rdr {
+                Some(rdr) if self.max_depth > 0 => {
+                    if let Some(ent) = self.enter(ent, rdr, 0) {
+                        return Some(Ok(ent));
+                    }
+                }
+                _ => return Some(Ok(ent)),
+            }
+        }
+
+        loop {
+            let (child, depth) = {
+                let frame = self.stack.last_mut()?;
+                (frame.rdr.next_child(), frame.depth + 1)
+            };
+
+            match child {
+                Some(Ok((child, child_ref))) => {
+                    let ent = (child.path().to_path_buf(), child.metadata());
+                    if !ent.1.is_dir() || depth >= self.max_depth {
+                        return Some(Ok(ent));
+                    }
+
+                    let rdr = {
+                        let frame = self.stack.last().unwrap();
+                        frame.rdr.read_child(child_ref, &ent.0)
+                    };
+                    match rdr {
+                        Ok(rdr) => {
+                            if let Some(ent) = self.enter(ent, rdr, depth) {
+                                return Some(Ok(ent));
+                            }
+                        }
+                        Err(err) => return self.fail(err),
+                    }
+                }
+                Some(Err(err)) => return self.fail(err),
+                None => {
+                    // all children are walked, yield the directory itself if
+                    // it is in post-order
+                    let frame = self.stack.pop().unwrap();
+                    if let Some(ent) = frame.ent {
+                        return Some(Ok(ent));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Debug for Walk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Walk")
+            .field("max_depth", &self.max_depth)
+            .field("post_order", &self.post_order)
+            .field("strict", &self.strict)
+            .finish()
+    }
+}
@@ -70,6 +70,13 @@
 //! Directory navigation can use [`Path`] and [`PathBuf`]. The path separator
 //! should always be "/", even on Windows.
 //!
+//! Paths must be absolute and valid UTF-8, otherwise [`InvalidPath`] error
+//! is returned. Paths are normalized lexically before use: duplicate
+//! separators, "." components and trailing separator are removed, and ".."
+//! removes the preceding component. A ".." which goes above the root is
+//! also rejected with [`InvalidPath`]. So `/a/../b`, `/a/./b`, `//a///b` and
+//! `/a/b/` all refer to the same entity as their normalized forms.
+//!
 //! ```
 //! # use zbox::{init_env, RepoOpener};
 //! use std::path::Path;
@@ -90,15 +97,46 @@
 //! [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
 //! [`Path`]: https://doc.rust-lang.org/std/path/struct.Path.html
 //! [`PathBuf`]: https://doc.rust-lang.org/std/path/struct.PathBuf.html
+//! [`InvalidPath`]: enum.Error.html#variant.InvalidPath
 //! [`init_env`]: fn.init_env.html
 //! [`Repo`]: struct.Repo.html
 //! [`File`]: struct.File.html
 //! [`RepoOpener`]: struct.RepoOpener.html
 
-// convert zbox error to IO error
+// emit a structured log event with key-value fields, for example,
+// `log_event!(debug, "tx.commit", txid = txid, duration = elapsed)`
+//
+// the event is routed through `tracing` if "tracing" feature is enabled,
+// otherwise it is emitted as a `log` record formatted as
+// "tx.commit: txid=123 duration=1.2ms"
+macro_rules! log_event {
+    (error, $($rest:tt)*) => { log_event!(@emit ERROR, Error, $($rest)*) };
+    (warn, $($rest:tt)*) => { log_event!(@emit WARN, Warn, $($rest)*) };
+    (info, $($rest:tt)*) => { log_event!(@emit INFO, Info, $($rest)*) };
+    (debug, $($rest:tt)*) => { log_event!(@emit DEBUG, Debug, $($rest)*) };
+    (trace, $($rest:tt)*) => { log_event!(@emit TRACE, Trace, $($rest)*) };
+    (@emit $tlvl:ident, $llvl:ident, $name:expr $(, $key:ident = $val:expr)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        tracing::event!(
+            tracing::Level::$tlvl,
+            event = $name
+            $(, $key = ?$val)*
+        );
+        #[cfg(not(feature = "tracing"))]
+        log::log!(
+            log::Level::$llvl,
+            concat!("{}:" $(, " ", stringify!($key), "={:?}")*),
+            $name
+            $(, $val)*
+        );
+    }};
+}
+
+// convert zbox error to IO error, the zbox error is kept as its inner
+// error so the error source chain is not lost
 macro_rules! map_io_err {
     ($x:expr) => {
-        $x.map_err(|e| IoError::new(ErrorKind::Other, e.to_string()));
+        $x.map_err(|e| IoError::new(ErrorKind::Other, e));
     };
 }
 
@@ -115,6 +153,8 @@ macro_rules! from_io_err {
     };
 }
 
+#[cfg(feature = "async")]
+mod async_fs;
 mod base;
 mod content;
 mod error;
@@ -125,17 +165,40 @@ mod trans;
 mod version;
 mod volume;
 
-pub use self::base::crypto::{Cipher, MemLimit, OpsLimit};
+pub use self::base::crypto::{
+    aes_hardware_available, available_ciphers, Cipher, MemLimit, OpsLimit,
+};
 pub use self::base::{init_env, zbox_version};
 pub use self::error::{Error, Result};
-pub use self::file::{File, VersionReader};
-pub use self::fs::fnode::{DirEntry, FileType, Metadata, Version};
-pub use self::repo::{OpenOptions, Repo, RepoInfo, RepoOpener};
+pub use self::file::{
+    File, Lines, RawContentReader, SnapshotFile, VersionReader,
+};
+pub use self::fs::fnode::{
+    DirCursor, DirEntry, FileType, Metadata, ReadDir, Version,
+};
+pub use self::fs::{RepoEvent, Walk};
+pub use self::repo::{
+    CacheStats, CacheUsage, CheckReport, CompactOptions, CompactReport,
+    CopyOptions, CorruptEntity, Difference, Manifest, ManifestChange,
+    ManifestEntry, MemoryReport, OpenOptions, ReplicationStats, Repo, RepoInfo,
+    RepoKey, RepoOpener, SnapshotRepo, SpaceUsage, TxnRepo,
+};
 pub use self::trans::Eid;
+pub use self::volume::{
+    available_backends, parse_repo_uri, register_storage, CacheEvent,
+    CacheHook, Compression, CryptoCtx, RepoUri, Span, Storable, StorageFactory,
+    SyncLevel, TransportConfig,
+};
 
 #[cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
 pub use self::volume::FaultyController;
 
+#[cfg(all(feature = "storage-zbox", target_arch = "wasm32"))]
+pub use self::volume::load_indexeddb_cache;
+
+#[cfg(feature = "async")]
+pub use self::async_fs::{AsyncFile, AsyncRepo};
+
 #[cfg(target_os = "android")]
 extern crate jni;
 
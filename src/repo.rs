@@ -1,14 +1,32 @@
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::time::SystemTime;
 
-use super::{File, Result};
-use crate::base::crypto::{Cipher, Cost, MemLimit, OpsLimit};
-use crate::base::{self, Time};
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+
+use super::{File, Result, SnapshotFile, VersionReader};
+use crate::base::crypto::{
+    Cipher, Cost, Crypto, Key, MemLimit, OpsLimit, KEY_SIZE,
+};
+use crate::base::{self, vio, Time};
 use crate::error::Error;
-use crate::fs::{Config, DirEntry, FileType, Fs, Metadata, Options, Version};
+use crate::fs::{
+    Config, DirCursor, DirEntry, FileType, FrozenFs, Fs, Metadata, Options,
+    ReadDir, RepoEvent, Version, Walk, MAX_INLINE_THRESHOLD, MIN_MEMORY_BUDGET,
+};
 use crate::trans::Eid;
+use crate::volume::{
+    CacheEvent, CacheHook, Compression, SyncLevel, TransportConfig,
+};
+
+#[cfg(feature = "async")]
+use crate::async_fs::{run_blocking, AsyncRepo};
 
 /// A builder used to create a repository [`Repo`] in various manners.
 ///
@@ -68,6 +86,16 @@ pub struct RepoOpener {
     create_new: bool,
     read_only: bool,
     force: bool,
+    background_commit: bool,
+    cache_hook: CacheHook,
+    memory_budget: Option<usize>,
+    frame_cache_size: Option<usize>,
+    address_cache_size: Option<usize>,
+    read_ahead: usize,
+    max_file_len: Option<usize>,
+    key_file: Option<PathBuf>,
+    transport: TransportConfig,
+    sync: SyncLevel,
 }
 
 impl RepoOpener {
@@ -99,7 +127,17 @@ impl RepoOpener {
     ///
     /// This option is only used for creating a repository. `Cipher::Aes` is
     /// the default if CPU supports AES-NI instructions, otherwise it will fall
-    /// back to `Cipher::Xchacha`.
+    /// back to `Cipher::Xchacha`. Use [`aes_hardware_available`] to check
+    /// it before choosing a cipher.
+    ///
+    /// # Errors
+    ///
+    /// Creating a repository with `Cipher::Aes` when AES is not supported
+    /// by hardware returns [`Error::NoAesHardware`], nothing is written to
+    /// storage in that case.
+    ///
+    /// [`aes_hardware_available`]: fn.aes_hardware_available.html
+    /// [`Error::NoAesHardware`]: enum.Error.html
     pub fn cipher(&mut self, cipher: Cipher) -> &mut Self {
         self.cfg.cipher = cipher;
         self
@@ -129,11 +167,39 @@ impl RepoOpener {
     /// Sets the option for data compression.
     ///
     /// This options indicates whether the LZ4 compression should be used in
-    /// the repository. Default is false.
+    /// the repository. This setting is a repository-wise setting, individual
+    /// file can overwrite it by setting [`compress`] in [`OpenOptions`].
+    /// Default is false.
     ///
-    /// This option is only used when creating a repository.
+    /// This option is only used when creating a repository. It is a shortcut
+    /// of setting [`compression`] to `Compression::Lz4`.
+    ///
+    /// [`compress`]: struct.OpenOptions.html#method.compress
+    /// [`OpenOptions`]: struct.OpenOptions.html
+    /// [`compression`]: struct.RepoOpener.html#method.compression
     pub fn compress(&mut self, compress: bool) -> &mut Self {
-        self.cfg.compress = compress;
+        self.cfg.compression = Compression::from_bool(compress);
+        self
+    }
+
+    /// Sets the data compression algorithm.
+    ///
+    /// This option indicates which compression algorithm should be used in
+    /// the repository, see [`Compression`] for details. Default is
+    /// `Compression::None`.
+    ///
+    /// This option is only used when creating a repository.
+    ///
+    /// # Errors
+    ///
+    /// Opening repository with `Compression::Zstd` without Cargo feature
+    /// `compress-zstd` enabled will return
+    /// [`Error::UnsupportedCompression`] error.
+    ///
+    /// [`Compression`]: enum.Compression.html
+    /// [`Error::UnsupportedCompression`]: enum.Error.html
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.cfg.compression = compression;
         self
     }
 
@@ -175,6 +241,112 @@ impl RepoOpener {
         self
     }
 
+    /// Sets the option for directory size tracking.
+    ///
+    /// This option indicates whether each directory should keep a cached
+    /// recursive size of all files under it, so that [`dir_size`] takes O(1)
+    /// time. The cached sizes are updated on every file write, removal and
+    /// rename, which adds overhead to those operations. As every write also
+    /// updates the root directory, concurrent writes to different files
+    /// might fail with [`InUse`] error. Default is false.
+    ///
+    /// This option is only used when creating a repository.
+    ///
+    /// [`dir_size`]: struct.Repo.html#method.dir_size
+    /// [`InUse`]: enum.Error.html
+    pub fn track_dir_sizes(&mut self, track_dir_sizes: bool) -> &mut Self {
+        self.cfg.opts.track_dir_sizes = track_dir_sizes;
+        self
+    }
+
+    /// Sets the maximum file size for inline storage.
+    ///
+    /// Files whose content is not larger than `inline_threshold` bytes are
+    /// stored inline in file metadata rather than in data segments, which
+    /// reduces storage overhead and speeds up reading for small files. A file
+    /// is moved to data segments once its content grows beyond the
+    /// threshold, it is not moved back if it is truncated later.
+    ///
+    /// The `inline_threshold` must not be larger than 64 KiB, 0 means
+    /// disabled. Default is 0. Content written with [`aad`] is never stored
+    /// inline.
+    ///
+    /// This option is only used when creating a repository.
+    ///
+    /// [`aad`]: struct.OpenOptions.html#method.aad
+    pub fn inline_threshold(&mut self, inline_threshold: usize) -> &mut Self {
+        self.cfg.opts.inline_threshold = inline_threshold;
+        self
+    }
+
+    /// Sets the block size of the repository, in bytes.
+    ///
+    /// Block is the unit of space allocation in the underlying storage. The
+    /// `block_size` must be a power of two within [1 KiB, 64 KiB], default is
+    /// 8 KiB. Not all storages support block size other than the default,
+    /// Zbox Cloud storage only supports the default block size.
+    ///
+    /// This option is only used when creating a repository. Opening an
+    /// existing repository always uses the block size it was created with.
+    pub fn block_size(&mut self, block_size: usize) -> &mut Self {
+        self.cfg.blk_size = block_size;
+        self
+    }
+
+    /// Sets the number of blocks in a frame.
+    ///
+    /// Frame is the unit of data encryption, a frame consists of
+    /// `blocks_per_frame` blocks. The frame size must not be larger than
+    /// 1 MiB, default is 16 blocks.
+    ///
+    /// This option is only used when creating a repository. Opening an
+    /// existing repository always uses the value it was created with.
+    pub fn blocks_per_frame(&mut self, blocks_per_frame: usize) -> &mut Self {
+        self.cfg.blks_per_frame = blocks_per_frame;
+        self
+    }
+
+    /// Sets the minimum, average and maximum chunk size for file content
+    /// deduplication, in bytes.
+    ///
+    /// File content is split into variable-length chunks using a rolling
+    /// hash, so that identical data produces identical chunks. Smaller
+    /// chunks find more duplicates but cost more CPU and metadata, larger
+    /// chunks are cheaper but find fewer duplicates.
+    ///
+    /// The sizes must satisfy `min < avg <= max`, `avg - min` must be a
+    /// power of two, `min` must be at least 1 KiB and `max` must not be
+    /// larger than 256 KiB. Default is 16 KiB, 32 KiB and 64 KiB.
+    ///
+    /// This option is only used when creating a repository. Opening an
+    /// existing repository always uses the chunk sizes it was created with,
+    /// so chunk boundaries stay the same across reopen.
+    pub fn chunk_params(
+        &mut self,
+        min: usize,
+        avg: usize,
+        max: usize,
+    ) -> &mut Self {
+        self.cfg.chunk_min_size = min;
+        self.cfg.chunk_avg_size = avg;
+        self.cfg.chunk_max_size = max;
+        self
+    }
+
+    /// Sets a human-readable label for the repository.
+    ///
+    /// The label is purely descriptive and is stored in the super block. It
+    /// can be at most 255 bytes long. Default is no label.
+    ///
+    /// This option is only used when creating a repository. Use
+    /// [`set_label`] to change it later.
+    ///
+    /// [`set_label`]: struct.Repo.html#method.set_label
+    pub fn label(&mut self, label: &str) -> &mut Self {
+        self.cfg.label = Some(label.to_string());
+        self
+    }
+
     /// Sets the option for read-only mode.
     ///
     /// This option cannot be true with either `create` or `create_new` is true.
@@ -194,6 +366,332 @@ impl RepoOpener {
         self
     }
 
+    /// Sets the option for background commit.
+    ///
+    /// Normally, each transaction is fully persisted before it returns, which
+    /// serializes throughput for write-heavy applications. When this option
+    /// is set to true, the final step of persisting a committed transaction is
+    /// deferred and batched with the following ones. A batch is persisted once
+    /// it holds 64 transactions, or by a background thread about 100ms after
+    /// its first transaction is committed, whichever comes first. Call
+    /// [`Repo::sync`] to force all deferred commits to be persisted. They are
+    /// also persisted when the repository is closed.
+    ///
+    /// This option trades durability for throughput. If the application
+    /// crashes before the deferred commits are persisted, the most recent
+    /// transactions will be rolled back as a whole when the repository is
+    /// opened next time, but the repository will never be left in a partially
+    /// written state.
+    ///
+    /// This option is ignored in read-only mode. Default is false.
+    ///
+    /// [`Repo::sync`]: struct.Repo.html#method.sync
+    pub fn background_commit(&mut self, background_commit: bool) -> &mut Self {
+        self.background_commit = background_commit;
+        self
+    }
+
+    /// Sets a hook to receive cache events.
+    ///
+    /// The hook is called with a [`CacheEvent`] when data is read through
+    /// the repository's caches, including the local cache of remote storage.
+    /// It can be used to monitor cache effectiveness and network usage.
+    ///
+    /// The hook is called on the reading thread while the repository's
+    /// internal storage lock is held, so it should return quickly and must
+    /// not call back into the repository or its files, or wait on any
+    /// thread which does, otherwise it will deadlock. Use atomics or send the
+    /// events through a channel if more work is needed.
+    ///
+    /// Only events after the repository is opened are reported. Default is
+    /// no hook.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use zbox::{CacheEvent, RepoOpener};
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let fetched = Arc::new(AtomicUsize::new(0));
+    /// let counter = fetched.clone();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .on_cache_event(Arc::new(move |event| {
+    ///         if let CacheEvent::Fetch { bytes } = event {
+    ///             counter.fetch_add(bytes, Ordering::Relaxed);
+    ///         }
+    ///     }))
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`CacheEvent`]: enum.CacheEvent.html
+    pub fn on_cache_event(
+        &mut self,
+        hook: Arc<dyn Fn(CacheEvent) + Send + Sync>,
+    ) -> &mut Self {
+        self.cache_hook = CacheHook::new(hook);
+        self
+    }
+
+    /// Sets the memory budget for internal caches, in bytes.
+    ///
+    /// Buffers of files being read or written are taken from `budget` first,
+    /// the caches are then resized to share what is left, each time a file
+    /// allocates its buffers. Each file being written needs about 640 KB
+    /// buffers, or at most 256 KB if it is opened with [`low_memory`], and
+    /// each file being read needs a frame buffer, so the budget should be
+    /// large enough for all concurrent reads and writes, otherwise the
+    /// caches are shrunk to minimum and the budget is exceeded. Caches whose
+    /// sizes are set explicitly, such as by [`frame_cache_size`], are not
+    /// resized. Use [`Repo::memory_usage`] to monitor actual memory usage.
+    ///
+    /// The `budget` must be at least 1 MB. Default is no budget, in which
+    /// case the data caches take about 20 MB.
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository.
+    ///
+    /// [`low_memory`]: struct.OpenOptions.html#method.low_memory
+    /// [`frame_cache_size`]: struct.RepoOpener.html#method.frame_cache_size
+    /// [`Repo::memory_usage`]: struct.Repo.html#method.memory_usage
+    pub fn memory_budget(&mut self, budget: usize) -> &mut Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Sets the size of decrypted frame cache, in bytes.
+    ///
+    /// Data read from the underlying storage is decrypted in frames and the
+    /// frames are kept in this cache. A bigger cache saves reading and
+    /// decrypting the same data again. It overrides the size derived from
+    /// [`memory_budget`].
+    ///
+    /// The `size` must be greater than zero. Default is 4 MB.
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository.
+    ///
+    /// [`memory_budget`]: struct.RepoOpener.html#method.memory_budget
+    pub fn frame_cache_size(&mut self, size: usize) -> &mut Self {
+        self.frame_cache_size = Some(size);
+        self
+    }
+
+    /// Sets the size of entity address cache, in number of addresses.
+    ///
+    /// The address cache keeps the storage locations of recently used
+    /// entities, such as files and directories. Addresses of fragmented
+    /// entities take more room, so fewer of them fit in the cache. It
+    /// overrides the size derived from [`memory_budget`].
+    ///
+    /// The `size` must be greater than zero. Default is 64.
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository.
+    ///
+    /// [`memory_budget`]: struct.RepoOpener.html#method.memory_budget
+    pub fn address_cache_size(&mut self, size: usize) -> &mut Self {
+        self.address_cache_size = Some(size);
+        self
+    }
+
+    /// Sets the number of frames to read ahead when reading a file.
+    ///
+    /// Data is stored in encrypted frames. When read-ahead is enabled, the
+    /// frames following the one being read are fetched from the underlying
+    /// storage and decrypted in parallel on background threads, which speeds
+    /// up sequential reading of large files. Frames are still returned in
+    /// order and frames already in the frame cache are not decrypted again.
+    ///
+    /// The number of threads is limited by `frames` and the available CPU
+    /// cores. Default is 0, which disables read-ahead.
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .read_ahead(4)
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn read_ahead(&mut self, frames: usize) -> &mut Self {
+        self.read_ahead = frames;
+        self
+    }
+
+    /// Sets the default maximum content length of files, in bytes.
+    ///
+    /// Writing to a file or setting its length beyond this limit returns
+    /// [`Error::FileTooLarge`], and no new version is created. It guards
+    /// against a single file filling up the storage. The limit can be
+    /// overridden for a file by [`OpenOptions::max_len`].
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository. Default is no limit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .max_file_len(64 * 1024 * 1024)
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::FileTooLarge`]: enum.Error.html
+    /// [`OpenOptions::max_len`]: struct.OpenOptions.html#method.max_len
+    pub fn max_file_len(&mut self, max_len: usize) -> &mut Self {
+        self.max_file_len = Some(max_len);
+        self
+    }
+
+    /// Sets the key file used together with password to unlock the
+    /// repository.
+    ///
+    /// The content of key file is hashed and combined with the password to
+    /// derive the repository key, so both of them are needed to open the
+    /// repository. If the password is empty when creating a repository, only
+    /// the key file is used. Which key factors are required is recorded in
+    /// the repository, opening it without a required factor will return
+    /// [`MissingKeyFactor`] error.
+    ///
+    /// The key file can be any file with non-empty content, and it must not
+    /// be changed after the repository is created. Password can be added to
+    /// or removed from a repository using key file by [`reset_password`].
+    ///
+    /// This option only applies to [`open`]. Note that [`Repo::derive_key`],
+    /// [`Repo::check`] and [`Repo::repair_super_block`] only support
+    /// repositories unlocked by password alone.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .key_file("/path/to/key_file")
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`MissingKeyFactor`]: enum.Error.html
+    /// [`reset_password`]: struct.Repo.html#method.reset_password
+    /// [`open`]: struct.RepoOpener.html#method.open
+    /// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+    /// [`Repo::check`]: struct.Repo.html#method.check
+    /// [`Repo::repair_super_block`]: struct.Repo.html#method.repair_super_block
+    pub fn key_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.key_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the network transport configuration for remote storage.
+    ///
+    /// This sets timeouts and retry policy for requests sent to remote
+    /// storage, such as Zbox Cloud Storage, see [`TransportConfig`] for
+    /// details. It is ignored by other storages. The timeouts must be
+    /// greater than zero.
+    ///
+    /// This option is not persisted and only applies to this opened
+    /// repository. Default is `TransportConfig::default()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use std::time::Duration;
+    /// # use zbox::{init_env, Result, RepoOpener, TransportConfig};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .transport_config(TransportConfig {
+    ///         request_timeout: Duration::from_secs(10),
+    ///         max_retries: 5,
+    ///         ..Default::default()
+    ///     })
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`TransportConfig`]: struct.TransportConfig.html
+    pub fn transport_config(&mut self, cfg: TransportConfig) -> &mut Self {
+        self.transport = cfg;
+        self
+    }
+
+    /// Sets write durability level for file storage.
+    ///
+    /// By default, writes to file storage rely on OS buffering and are not
+    /// explicitly synced to disk, so recently committed data, or even the
+    /// whole repository just created, can be lost on power failure. Setting
+    /// it to [`SyncLevel::Data`] or [`SyncLevel::Full`] syncs super block,
+    /// wal, index and block writes to disk, see [`SyncLevel`] for details.
+    ///
+    /// Syncing waits for the disk on every write, so it can significantly
+    /// reduce write throughput. For temporary repositories which need not
+    /// survive a crash, keep it as [`SyncLevel::Off`].
+    ///
+    /// This option is ignored by other storages. It is not persisted and
+    /// only applies to this opened repository. Default is
+    /// [`SyncLevel::Off`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener, SyncLevel};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .sync_writes(SyncLevel::Full)
+    ///     .open("mem://foo", "pwd")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`SyncLevel`]: enum.SyncLevel.html
+    /// [`SyncLevel::Off`]: enum.SyncLevel.html#variant.Off
+    /// [`SyncLevel::Data`]: enum.SyncLevel.html#variant.Data
+    /// [`SyncLevel::Full`]: enum.SyncLevel.html#variant.Full
+    pub fn sync_writes(&mut self, level: SyncLevel) -> &mut Self {
+        self.sync = level;
+        self
+    }
+
     /// Opens a repository at URI with the password and options specified by
     /// `self`.
     ///
@@ -213,9 +711,12 @@ impl RepoOpener {
     /// - Memory storage, URI identifier is `mem://`
     ///
     ///   After the identifier is a name to distinguish a particular memory
-    ///   storage location.
+    ///   storage location. Memory storage grows unbounded by default, a size
+    ///   cap can be set by the `max_size` parameter, in bytes or with a
+    ///   `kb`, `mb` or `gb` suffix. Writing beyond the cap returns
+    ///   [`Error::NoSpace`], the cap applies to this opened repository only.
     ///
-    ///   For example, `mem://foobar`.
+    ///   For example, `mem://foobar` or `mem://foobar?max_size=64mb`.
     ///
     /// - OS file system storage, URI identifier is `file://`
     ///
@@ -236,14 +737,33 @@ impl RepoOpener {
     ///
     ///   This storage must be enabled by Cargo feature `storage-sqlite`.
     ///
-    /// - Redis storage, URI identifier is `redis://`
+    /// - Redis storage, URI identifier is `redis://` or `rediss://`
     ///
     ///   After the identifier is the path to a Redis instance. Unix socket is
     ///   supported. The URI format is:
     ///
-    ///   `redis://[+unix+][:<passwd>@]<hostname>[:port][/<db>]`
+    ///   `redis://[+unix+][:<passwd>@]<hostname>[:port][/<db>][?<params>]`
     ///
-    ///   This storage must be enabled by Cargo feature `storage-redis`.
+    ///   Connections are pooled, the parameters are:
+    ///
+    ///   - `pool`: maximum number of connections in the pool, default is 4
+    ///   - `cluster`: `true` to connect to a Redis Cluster, default is
+    ///     `false`. Cluster nodes are separated by comma, for example,
+    ///     `redis://host1:7000,host2:7001?cluster=true`.
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-redis`. Use
+    ///   `rediss://` to connect over TLS, which must be enabled by Cargo
+    ///   feature `storage-redis-tls`.
+    ///
+    /// - PostgreSQL storage, URI identifier is `postgres://`
+    ///
+    ///   After the identifier is the connection string to a PostgreSQL
+    ///   database. The repository is stored in tables created in the
+    ///   database's current schema. The URI format is:
+    ///
+    ///   `postgres://[<user>[:<passwd>]@]<hostname>[:port][/<db>]`
+    ///
+    ///   This storage must be enabled by Cargo feature `storage-postgres`.
     ///
     /// After a repository is opened, all of the other methods provided by
     /// ZboxFS will be thread-safe.
@@ -255,40 +775,267 @@ impl RepoOpener {
     ///
     /// Open a memory based repository without enable `create` option will
     /// return an error.
+    ///
+    /// Opening a repository which is already opened, or is being created,
+    /// returns [`Error::RepoOpened`], unless [`force`] is set. Creating a
+    /// repository with [`create_new`] when it already exists returns
+    /// [`Error::RepoExists`]. When a repository is created from several
+    /// threads at the same time, only one of them succeeds and the others
+    /// get one of these errors.
+    ///
+    /// [`force`]: struct.RepoOpener.html#method.force
+    /// [`create_new`]: struct.RepoOpener.html#method.create_new
+    /// [`Error::RepoOpened`]: enum.Error.html
+    /// [`Error::RepoExists`]: enum.Error.html
+    /// [`Error::NoSpace`]: enum.Error.html
     pub fn open(&self, uri: &str, pwd: &str) -> Result<Repo> {
-        // version limit must be greater than 0
-        if self.cfg.opts.version_limit == 0 {
-            return Err(Error::InvalidArgument);
-        }
+        self.check_options()?;
+        let key_file = self.read_key_file()?;
 
-        if self.create {
+        let mut repo = if self.create {
             if self.read_only {
                 return Err(Error::InvalidArgument);
             }
-            if Repo::exists(uri)? {
+            if Fs::exists(uri, &self.transport)? {
                 if self.create_new {
                     return Err(Error::RepoExists);
                 }
-                Repo::open(uri, pwd, self.read_only, self.force)
+                Repo::open(
+                    uri,
+                    pwd,
+                    key_file,
+                    self.read_only,
+                    self.force,
+                    &self.transport,
+                    self.sync,
+                )?
             } else {
-                Repo::create(uri, pwd, &self.cfg)
+                Repo::create(
+                    uri,
+                    pwd,
+                    key_file,
+                    &self.cfg,
+                    &self.transport,
+                    self.sync,
+                )?
             }
         } else {
-            Repo::open(uri, pwd, self.read_only, self.force)
+            Repo::open(
+                uri,
+                pwd,
+                key_file,
+                self.read_only,
+                self.force,
+                &self.transport,
+                self.sync,
+            )?
+        };
+
+        self.set_up(&mut repo)?;
+        Ok(repo)
+    }
+
+    /// Opens an existing repository at URI with a key derived from its
+    /// password.
+    ///
+    /// This is same as [`open`], except that the key derived by
+    /// [`Repo::derive_key`] is used instead of the password, so the slow
+    /// password hashing is skipped. It is useful for applications which
+    /// open the same repository repeatedly, such as command line tools.
+    ///
+    /// The key cannot be used to create a repository, so the [`create`] and
+    /// [`create_new`] options are ignored. The key becomes invalid after
+    /// the password is reset, and [`Decrypt`] error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, Repo, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # drop(RepoOpener::new().create(true).open("mem://foo", "pwd")?);
+    /// let key = Repo::derive_key("mem://foo", "pwd")?;
+    /// let mut repo = RepoOpener::new().open_with_key("mem://foo", &key)?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`open`]: struct.RepoOpener.html#method.open
+    /// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+    /// [`create`]: struct.RepoOpener.html#method.create
+    /// [`create_new`]: struct.RepoOpener.html#method.create_new
+    /// [`Decrypt`]: enum.Error.html
+    pub fn open_with_key(&self, uri: &str, key: &RepoKey) -> Result<Repo> {
+        self.check_options()?;
+        let fs = Fs::open_with_key(
+            uri,
+            &key.0,
+            self.read_only,
+            self.force,
+            &self.transport,
+            self.sync,
+        )?;
+        let mut repo = Repo { fs };
+        self.set_up(&mut repo)?;
+        Ok(repo)
+    }
+
+    // validate options before opening repo
+    fn check_options(&self) -> Result<()> {
+        // version limit must be greater than 0
+        if self.cfg.opts.version_limit == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        // inline threshold must be within limit
+        if self.cfg.opts.inline_threshold > MAX_INLINE_THRESHOLD {
+            return Err(Error::InvalidArgument);
+        }
+
+        // compression must be supported
+        self.cfg.compression.check()?;
+
+        // memory budget must be large enough
+        if let Some(budget) = self.memory_budget {
+            if budget < MIN_MEMORY_BUDGET {
+                return Err(Error::InvalidArgument);
+            }
         }
+
+        // cache sizes must be greater than zero
+        if self.frame_cache_size == Some(0)
+            || self.address_cache_size == Some(0)
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        // transport timeouts must be greater than zero
+        if self.transport.connect_timeout.is_zero()
+            || self.transport.request_timeout.is_zero()
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    // read key file and hash its content
+    fn read_key_file(&self) -> Result<Option<Key>> {
+        match self.key_file {
+            Some(ref path) => {
+                let mut content = Vec::new();
+                vio::File::open(path)?.read_to_end(&mut content)?;
+                if content.is_empty() {
+                    return Err(Error::InvalidArgument);
+                }
+                let mut key = Key::new_empty();
+                key.copy(&Crypto::hash(&content));
+                Ok(Some(key))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // apply runtime options to opened repo
+    fn set_up(&self, repo: &mut Repo) -> Result<()> {
+        if self.background_commit {
+            repo.fs.enable_background_commit()?;
+        }
+
+        repo.fs.set_cache_hook(self.cache_hook.clone());
+
+        if let Some(budget) = self.memory_budget {
+            repo.fs.set_memory_budget(budget);
+        }
+        repo.fs
+            .set_cache_sizes(self.frame_cache_size, self.address_cache_size);
+        repo.fs.set_read_ahead(self.read_ahead);
+        repo.fs.set_max_file_len(self.max_file_len);
+
+        Ok(())
+    }
+
+    /// Opens a repository asynchronously and returns an asynchronous handle
+    /// to it.
+    ///
+    /// This is same as [`open`] but it runs on tokio's blocking thread pool,
+    /// so it must be called within a tokio runtime. See [`AsyncRepo`] for
+    /// details.
+    ///
+    /// This method must be enabled by Cargo feature `async`.
+    ///
+    /// [`open`]: struct.RepoOpener.html#method.open
+    /// [`AsyncRepo`]: struct.AsyncRepo.html
+    #[cfg(feature = "async")]
+    pub async fn open_async(&self, uri: &str, pwd: &str) -> Result<AsyncRepo> {
+        let opener = self.clone();
+        let uri = uri.to_owned();
+        let pwd = pwd.to_owned();
+        run_blocking(move || opener.open(&uri, &pwd))
+            .await
+            .map(AsyncRepo::new)
     }
 }
 
-/// Options and flags which can be used to configure how a file is opened.
+/// A key derived from repository password.
 ///
-/// This builder exposes the ability to configure how a [`File`] is opened and
-/// what operations are permitted on the opened file. The [`Repo::open_file`]
-/// and [`Repo::create_file`] methods are aliases for commonly used options
-/// using this builder.
+/// The key is derived by [`Repo::derive_key`] and can be used to open the
+/// repository by [`RepoOpener::open_with_key`], without hashing password
+/// again. It should be kept as secret as the password itself.
 ///
-/// Generally speaking, when using `OpenOptions`, you'll first call [`new`], then
-/// chain calls to methods to set each option, then call [`open`], passing the
-/// path of the file you're trying to open. This will give you a [`Result`]
+/// The key is kept in protected memory and zeroed when dropped. Use
+/// [`as_bytes`] and [`from_bytes`] to save it to and load it from a secure
+/// place, such as OS keyring.
+///
+/// [`Repo::derive_key`]: struct.Repo.html#method.derive_key
+/// [`RepoOpener::open_with_key`]: struct.RepoOpener.html#method.open_with_key
+/// [`as_bytes`]: struct.RepoKey.html#method.as_bytes
+/// [`from_bytes`]: struct.RepoKey.html#method.from_bytes
+#[derive(Clone, PartialEq)]
+pub struct RepoKey(Key);
+
+impl RepoKey {
+    /// Creates a key from raw bytes returned by [`as_bytes`].
+    ///
+    /// Returns [`Error::InvalidArgument`] if the length of `bytes` is not
+    /// the key size.
+    ///
+    /// [`as_bytes`]: struct.RepoKey.html#method.as_bytes
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != KEY_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+        let mut key = Key::new_empty();
+        key.copy(bytes);
+        Ok(RepoKey(key))
+    }
+
+    /// Returns raw bytes of the key.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl Debug for RepoKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RepoKey(..)")
+    }
+}
+
+/// Options and flags which can be used to configure how a file is opened.
+///
+/// This builder exposes the ability to configure how a [`File`] is opened and
+/// what operations are permitted on the opened file. The [`Repo::open_file`]
+/// and [`Repo::create_file`] methods are aliases for commonly used options
+/// using this builder.
+///
+/// Generally speaking, when using `OpenOptions`, you'll first call [`new`], then
+/// chain calls to methods to set each option, then call [`open`], passing the
+/// path of the file you're trying to open. This will give you a [`Result`]
 /// with a [`File`] inside that you can further operate on.
 ///
 /// # Examples
@@ -327,8 +1074,15 @@ pub struct OpenOptions {
     truncate: bool,
     create: bool,
     create_new: bool,
+    append_only: bool,
+    low_memory: bool,
+    skip_identical_versions: bool,
+    auto_sync_bytes: usize,
+    max_len: Option<usize>,
     version_limit: Option<u8>,
     dedup_chunk: Option<bool>,
+    compress: Option<bool>,
+    aad: Vec<u8>,
 }
 
 impl OpenOptions {
@@ -357,9 +1111,14 @@ impl OpenOptions {
     /// Sets the option for the append mode.
     ///
     /// This option, when true, means that writes will append to a file instead
-    /// of overwriting previous content. Note that setting
+    /// of overwriting previous content. Like `O_APPEND`, every new write
+    /// starts at the end of file at that time, even if the file was seeked
+    /// or extended after it was opened. Positional writes by
+    /// [`File::write_at`] are not affected. Note that setting
     /// `.write(true).append(true)` has the same effect as setting only
     /// `.append(true)`.
+    ///
+    /// [`File::write_at`]: struct.File.html#method.write_at
     pub fn append(&mut self, append: bool) -> &mut OpenOptions {
         self.append = append;
         if append {
@@ -405,6 +1164,123 @@ impl OpenOptions {
         self
     }
 
+    /// Sets the option to make the file append-only.
+    ///
+    /// This option, when true, means that the file will be marked as
+    /// append-only after it is opened. See [`Repo::set_append_only`] for
+    /// details. Note that setting `.append_only(true)` implies
+    /// `.append(true)`.
+    ///
+    /// [`Repo::set_append_only`]: struct.Repo.html#method.set_append_only
+    pub fn append_only(&mut self, append_only: bool) -> &mut OpenOptions {
+        self.append_only = append_only;
+        if append_only {
+            self.append = true;
+            self.write = true;
+        }
+        self
+    }
+
+    /// Sets the option to use minimal buffering for the opened file.
+    ///
+    /// This option, when true, means that the file will use block-sized
+    /// read buffer for [`lines`] and the smallest staging buffer that can hold
+    /// a data chunk when writing, instead of the default frame-sized and
+    /// larger buffers. This reduces memory footprint when many files are
+    /// opened at the same time, at the cost of throughput.
+    ///
+    /// Data is staged in storage until a whole frame is collected, as frame
+    /// is the unit of encryption. With this option, the staging buffer only
+    /// grows to hold data staged and is released after each frame is
+    /// written, so a file costs at most a chunker buffer plus one frame,
+    /// about 256 KB by default. Buffers used by compression encoder are not
+    /// reduced.
+    ///
+    /// [`lines`]: struct.File.html#method.lines
+    pub fn low_memory(&mut self, low_memory: bool) -> &mut OpenOptions {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Sets the option to skip creating a version identical to the current
+    /// one.
+    ///
+    /// This option, when true, means that [`finish`] will not create a new
+    /// version if the written content is same as the current version, so
+    /// re-saving unchanged content doesn't fill up the version history.
+    /// Content is compared using [`Version::content_hash`].
+    ///
+    /// Default is false.
+    ///
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`Version::content_hash`]: struct.Version.html#method.content_hash
+    pub fn skip_identical_versions(
+        &mut self,
+        skip_identical_versions: bool,
+    ) -> &mut OpenOptions {
+        self.skip_identical_versions = skip_identical_versions;
+        self
+    }
+
+    /// Sets the number of bytes after which written data is made durable
+    /// automatically.
+    ///
+    /// When it is greater than 0, every time `auto_sync_bytes` bytes are
+    /// written to the file, written data is flushed to storage as if
+    /// [`File::sync_data`] is called. This bounds the amount of data which
+    /// could be lost on crash for a file kept open for a long time, such as
+    /// a log file, without calling [`File::sync_data`] explicitly. No new
+    /// version is created, the data is still only visible after [`finish`]
+    /// is called.
+    ///
+    /// Default is 0, which means data is not synced automatically.
+    ///
+    /// [`File::sync_data`]: struct.File.html#method.sync_data
+    /// [`finish`]: struct.File.html#method.finish
+    pub fn auto_sync_bytes(
+        &mut self,
+        auto_sync_bytes: usize,
+    ) -> &mut OpenOptions {
+        self.auto_sync_bytes = auto_sync_bytes;
+        self
+    }
+
+    /// Sets the maximum content length of the file, in bytes.
+    ///
+    /// Writing to the file or setting its length beyond this limit returns
+    /// [`Error::FileTooLarge`]. The check is done on each write, so the
+    /// write stops as soon as the limit is reached and the oversize version
+    /// is never created. It will fall back to repository's [`max_file_len`]
+    /// if it is not set.
+    ///
+    /// [`Error::FileTooLarge`]: enum.Error.html
+    /// [`max_file_len`]: struct.RepoOpener.html#method.max_file_len
+    pub fn max_len(&mut self, max_len: usize) -> &mut OpenOptions {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Sets the associated data used to encrypt file content.
+    ///
+    /// When `aad` is not empty, content written through the opened file is
+    /// authenticated together with `aad`, so it can only be read back using
+    /// the same associated data. Reading with a different, or without, `aad`
+    /// will fail with [`Error::Decrypt`]. Writing to non-empty content bound
+    /// to different associated data also fails, unless the file is opened
+    /// with [`truncate`].
+    ///
+    /// This option cannot be used together with file or chunk
+    /// deduplication, opening file will return [`Error::InvalidArgument`]
+    /// in that case.
+    ///
+    /// [`Error::Decrypt`]: enum.Error.html#variant.Decrypt
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`truncate`]: struct.OpenOptions.html#method.truncate
+    pub fn aad(&mut self, aad: &[u8]) -> &mut OpenOptions {
+        self.aad = aad.to_vec();
+        self
+    }
+
     /// Sets the maximum number of file versions allowed.
     ///
     /// The `version_limit` must be within [1, 255], default is 1. It will fall
@@ -428,6 +1304,21 @@ impl OpenOptions {
         self
     }
 
+    /// Sets the option for file data compression.
+    ///
+    /// This option indicates whether the LZ4 compression should be used when
+    /// writing data to a file. It will fall back to repository's
+    /// [`compress`] if it is not set.
+    ///
+    /// This option is only used when creating a file, the setting is kept
+    /// with the file so its data can always be read back correctly.
+    ///
+    /// [`compress`]: struct.RepoOpener.html#method.compress
+    pub fn compress(&mut self, compress: bool) -> &mut OpenOptions {
+        self.compress = Some(compress);
+        self
+    }
+
     /// Opens a file at path with the options specified by `self`.
     pub fn open<P: AsRef<Path>>(
         &self,
@@ -458,12 +1349,19 @@ pub struct RepoInfo {
     uri: String,
     cost: Cost,
     cipher: Cipher,
-    compress: bool,
+    compression: Compression,
     version_limit: u8,
     dedup_chunk: bool,
     dedup_file: bool,
+    track_dir_sizes: bool,
+    inline_threshold: usize,
     read_only: bool,
+    was_dirty: bool,
     ctime: Time,
+    label: Option<String>,
+    blk_size: usize,
+    blks_per_frame: usize,
+    chunk_params: (usize, usize, usize),
 }
 
 impl RepoInfo {
@@ -512,7 +1410,13 @@ impl RepoInfo {
     /// Returns whether compression is enabled.
     #[inline]
     pub fn compress(&self) -> bool {
-        self.compress
+        self.compression.is_enabled()
+    }
+
+    /// Returns the data compression algorithm.
+    #[inline]
+    pub fn compression(&self) -> Compression {
+        self.compression
     }
 
     /// Returns the default maximum number of file versions.
@@ -533,492 +1437,3836 @@ impl RepoInfo {
         self.dedup_file
     }
 
+    /// Returns whether the directory size tracking is enabled.
+    #[inline]
+    pub fn track_dir_sizes(&self) -> bool {
+        self.track_dir_sizes
+    }
+
+    /// Returns the maximum file size for inline storage.
+    #[inline]
+    pub fn inline_threshold(&self) -> usize {
+        self.inline_threshold
+    }
+
     /// Returns whether this repository is read-only.
     #[inline]
     pub fn is_read_only(&self) -> bool {
         self.read_only
     }
 
+    /// Returns whether this repository was not closed cleanly and recovery
+    /// happened when it was opened.
+    ///
+    /// Transactions which were still in progress when the repository was
+    /// last closed, for example because of a crash, are rolled back when it
+    /// is opened, so the repository is restored to the consistent state
+    /// before those transactions started. This returns true if any
+    /// transaction was rolled back.
+    ///
+    /// Committed transactions are never rolled back, but deferred commits
+    /// not saved yet when using [`background_commit`] are lost without
+    /// being reported.
+    ///
+    /// [`background_commit`]: struct.RepoOpener.html#method.background_commit
+    #[inline]
+    pub fn was_dirty(&self) -> bool {
+        self.was_dirty
+    }
+
     /// Returns the creation time of this repository.
     #[inline]
     pub fn created_at(&self) -> SystemTime {
         self.ctime.to_system_time()
     }
-}
 
-// open a regular file with options
-fn open_file_with_options<P: AsRef<Path>>(
-    fs: &mut Fs,
-    path: P,
-    open_opts: &OpenOptions,
-) -> Result<File> {
-    if fs.is_read_only()
-        && (open_opts.write
-            || open_opts.append
-            || open_opts.truncate
-            || open_opts.create
-            || open_opts.create_new)
-    {
-        return Err(Error::ReadOnly);
+    /// Returns the label of this repository, if it has one.
+    #[inline]
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
     }
 
-    let path = path.as_ref();
-
-    match fs.resolve(path) {
-        Ok(_) => {
-            if open_opts.create_new {
-                return Err(Error::AlreadyExists);
-            }
-        }
-        Err(ref err) if *err == Error::NotFound && open_opts.create => {
-            let mut opts = fs.get_opts();
-            if let Some(version_limit) = open_opts.version_limit {
-                opts.version_limit = version_limit;
-            }
-            if let Some(dedup_chunk) = open_opts.dedup_chunk {
-                opts.dedup_chunk = dedup_chunk;
-            }
-            fs.create_fnode(path, FileType::File, opts)?;
-        }
-        Err(err) => return Err(err),
+    /// Returns the block size of this repository, in bytes.
+    #[inline]
+    pub fn block_size(&self) -> usize {
+        self.blk_size
     }
 
-    let curr_len;
-    let handle = fs.open_fnode(path)?;
-    {
-        let fnode = handle.fnode.read().unwrap();
-        if fnode.is_dir() {
-            return Err(Error::IsDir);
-        }
-        curr_len = fnode.curr_len();
+    /// Returns the number of blocks in a frame.
+    #[inline]
+    pub fn blocks_per_frame(&self) -> usize {
+        self.blks_per_frame
     }
 
-    let pos = if open_opts.append {
-        SeekFrom::Start(curr_len as u64)
-    } else {
-        SeekFrom::Start(0)
-    };
-    let mut file = File::new(handle, pos, open_opts.read, open_opts.write);
-
-    if open_opts.truncate && curr_len > 0 {
-        file.set_len(0)?;
+    /// Returns the minimum, average and maximum content chunk size, in
+    /// bytes.
+    #[inline]
+    pub fn chunk_params(&self) -> (usize, usize, usize) {
+        self.chunk_params
     }
-
-    Ok(file)
 }
 
-/// An encrypted repository contains the whole file system.
-///
-/// A `Repo` represents a secure collection which consists of files,
-/// directories and their associated data. Similar to [`std::fs`], `Repo`
-/// provides methods to manipulate the enclosed file system.
-///
-/// # Storages
-///
-/// ZboxFS supports a variety of underlying storages, which are listed below.
-///
-/// | Storage            | URI identifier  | Cargo Feature       |
-/// | ------------------ | --------------- | ------------------- |
-/// | Memory             | "mem://"        | N/A                 |
-/// | OS file system     | "file://"       | storage-file        |
-/// | SQLite             | "sqlite://"     | storage-sqlite      |
-/// | Redis              | "redis://"      | storage-redis       |
-/// | Zbox Cloud Storage | "zbox://"       | storage-zbox-native |
-///
-/// \* Visit [zbox.io](https://zbox.io) to learn more about Zbox Cloud Storage.
-///
-/// By default, only memory storage is enabled. To use other storages, you need
-/// to specify it as dependency features in your Cargo.toml.
-///
-/// For example, to use OS file as underlying storage, specify its feature in
-/// your project's Cargo.toml file.
-///
-/// ```toml
-/// [dependencies]
-/// zbox = { version = "0.9.2", features = ["storage-file"] }
-/// ```
-///
-/// # Create and open `Repo`
-///
-/// `Repo` can be created on different underlying storages using [`RepoOpener`].
-/// It uses an URI-like string to specify its storage type and location. The
-/// URI string starts with an identifier which specifies the storage type, as
-/// shown in above table. You can check more location URI details at:
-/// [RepoOpener](struct.RepoOpener.html#method.open).
-///
-/// `Repo` can only be opened once at a time. After opened, it keeps locked
-/// from other open attempts until it goes out of scope. Once `Repo` is dropped,
-/// any opened files or version readers won't be accessible.
+/// Options for compacting a repository.
 ///
-/// Optionally, `Repo` can be opened in [`read-only`] mode if you only need
-/// read access.
+/// This builder exposes the ability to configure how [`Repo::compact`]
+/// reclaims storage space. By default, all of the compaction steps are
+/// enabled and versions are pruned down to the repository-wide
+/// [`version_limit`].
 ///
 /// # Examples
 ///
-/// Create an OS file system based repository.
-///
-/// ```no_run
-/// # #![allow(unused_mut, unused_variables, dead_code)]
-/// # use zbox::Result;
-/// use zbox::{init_env, RepoOpener};
-///
-/// # fn foo() -> Result<()> {
-/// init_env();
-/// let mut repo = RepoOpener::new()
-///     .create(true)
-///     .open("file:///path/to/repo", "pwd")?;
-/// # Ok(())
-/// # }
-/// ```
-///
-/// Create a memory based repository.
-///
 /// ```
-/// # #![allow(unused_mut, unused_variables, dead_code)]
 /// # use zbox::{init_env, Result, RepoOpener};
-/// # fn foo() -> Result<()> {
-/// # init_env();
-/// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
-/// # Ok(())
-/// # }
-/// ```
-///
-/// Open a repository in read-only mode.
+/// use zbox::CompactOptions;
 ///
-/// ```no_run
-/// # #![allow(unused_mut, unused_variables, dead_code)]
-/// # use zbox::{Result, RepoOpener};
 /// # fn foo() -> Result<()> {
-/// let mut repo = RepoOpener::new()
-///     .read_only(true)
-///     .open("file:///path/to/repo", "pwd")?;
+/// # init_env();
+/// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// let mut opts = CompactOptions::new();
+/// opts.defrag(false).keep_versions(2);
+/// let report = repo.compact(opts)?;
+/// println!("reclaimed {} bytes", report.bytes_reclaimed());
 /// # Ok(())
 /// # }
+/// # foo().unwrap();
 /// ```
 ///
-/// [`std::fs`]: https://doc.rust-lang.org/std/fs/index.html
-/// [`init_env`]: fn.init_env.html
-/// [`RepoOpener`]: struct.RepoOpener.html
-/// [`read-only`]: struct.RepoOpener.html#method.read_only
-pub struct Repo {
-    fs: Fs,
+/// [`Repo::compact`]: struct.Repo.html#method.compact
+/// [`version_limit`]: struct.RepoOpener.html#method.version_limit
+#[derive(Debug, Clone, Copy)]
+pub struct CompactOptions {
+    gc: bool,
+    defrag: bool,
+    prune_versions: bool,
+    keep_versions: Option<u8>,
 }
 
-impl Repo {
-    /// Returns whether the URI points at an existing repository.
-    #[inline]
-    pub fn exists(uri: &str) -> Result<bool> {
-        Fs::exists(uri)
+impl CompactOptions {
+    /// Creates a new set of options with all compaction steps enabled.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // create repo
-    #[inline]
-    fn create(uri: &str, pwd: &str, cfg: &Config) -> Result<Repo> {
-        let fs = Fs::create(uri, pwd, cfg)?;
-        Ok(Repo { fs })
+    /// Sets the option for garbage collection.
+    ///
+    /// When true, contents tracked by the content store but not used by any
+    /// file version are removed, along with their segments which are not
+    /// reachable from any file version. Unused data in segments referenced
+    /// by files is discarded as well.
+    ///
+    /// Without [`dedup_file`], the content store only tracks contents
+    /// shared by multiple versions, such as restored versions, so an
+    /// unshared content lost by its version cannot be found.
+    ///
+    /// [`dedup_file`]: struct.RepoOpener.html#method.dedup_file
+    pub fn gc(&mut self, gc: bool) -> &mut Self {
+        self.gc = gc;
+        self
     }
 
-    // open repo
-    #[inline]
-    fn open(
-        uri: &str,
-        pwd: &str,
-        read_only: bool,
-        force: bool,
-    ) -> Result<Repo> {
-        let fs = Fs::open(uri, pwd, read_only, force)?;
-        Ok(Repo { fs })
+    /// Sets the option for defragmenting files.
+    ///
+    /// When true, the current version of a fragmented file is rewritten
+    /// contiguously as a new version, its modified time is not changed. This
+    /// option has no effect if the repository is created with [`dedup_file`]
+    /// enabled.
+    ///
+    /// [`dedup_file`]: struct.RepoOpener.html#method.dedup_file
+    pub fn defrag(&mut self, defrag: bool) -> &mut Self {
+        self.defrag = defrag;
+        self
     }
 
-    /// Get repository metadata information.
-    pub fn info(&self) -> Result<RepoInfo> {
-        let meta = self.fs.info();
-        Ok(RepoInfo {
+    /// Sets the option for pruning file versions.
+    ///
+    /// When true, the oldest versions of a file are removed if it has more
+    /// versions than the limit.
+    pub fn prune_versions(&mut self, prune_versions: bool) -> &mut Self {
+        self.prune_versions = prune_versions;
+        self
+    }
+
+    /// Sets the maximum number of versions kept for each file when pruning
+    /// versions.
+    ///
+    /// If it is not set, the repository-wide [`version_limit`] is used. The
+    /// current version is always kept.
+    ///
+    /// [`version_limit`]: struct.RepoOpener.html#method.version_limit
+    pub fn keep_versions(&mut self, keep_versions: u8) -> &mut Self {
+        self.keep_versions = Some(keep_versions);
+        self
+    }
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        CompactOptions {
+            gc: true,
+            defrag: true,
+            prune_versions: true,
+            keep_versions: None,
+        }
+    }
+}
+
+/// Options for copying files and directories.
+///
+/// This builder exposes the ability to configure how [`Repo::copy_with`]
+/// and [`Repo::copy_dir_all_with`] copy files. By default, it behaves the
+/// same as [`Repo::copy`] and [`Repo::copy_dir_all`].
+///
+/// # Examples
+///
+/// ```
+/// # use zbox::{init_env, Result, RepoOpener};
+/// use zbox::CopyOptions;
+///
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// # repo.create_file("/foo.txt")?;
+/// let mut opts = CopyOptions::new();
+/// opts.preserve_times(true);
+/// repo.copy_with("/foo.txt", "/bar.txt", opts)?;
+/// # Ok(())
+/// # }
+/// # foo().unwrap();
+/// ```
+///
+/// [`Repo::copy_with`]: struct.Repo.html#method.copy_with
+/// [`Repo::copy_dir_all_with`]: struct.Repo.html#method.copy_dir_all_with
+/// [`Repo::copy`]: struct.Repo.html#method.copy
+/// [`Repo::copy_dir_all`]: struct.Repo.html#method.copy_dir_all
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CopyOptions {
+    preserve_times: bool,
+}
+
+impl CopyOptions {
+    /// Creates a new set of options with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the option for preserving created and modified time.
+    ///
+    /// When true, the destination gets the same created and modified time as
+    /// the source. Default is false, the destination times are set to when it
+    /// is copied.
+    pub fn preserve_times(&mut self, preserve_times: bool) -> &mut Self {
+        self.preserve_times = preserve_times;
+        self
+    }
+}
+
+/// Report of a repository compaction.
+///
+/// This structure is returned from [`Repo::compact`].
+///
+/// [`Repo::compact`]: struct.Repo.html#method.compact
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompactReport {
+    bytes_reclaimed: usize,
+    segments_reclaimed: usize,
+    files_touched: usize,
+    versions_pruned: usize,
+}
+
+impl CompactReport {
+    /// Returns the number of bytes reclaimed, measured by [`disk_usage`].
+    ///
+    /// Length of swept orphan segments is included, although they are not
+    /// counted in [`disk_usage`].
+    ///
+    /// [`disk_usage`]: struct.Repo.html#method.disk_usage
+    #[inline]
+    pub fn bytes_reclaimed(&self) -> usize {
+        self.bytes_reclaimed
+    }
+
+    /// Returns the number of segments removed, including swept orphan
+    /// segments. Segments which are shrunk are not included.
+    #[inline]
+    pub fn segments_reclaimed(&self) -> usize {
+        self.segments_reclaimed
+    }
+
+    /// Returns the number of files changed by compaction.
+    #[inline]
+    pub fn files_touched(&self) -> usize {
+        self.files_touched
+    }
+
+    /// Returns the number of file versions removed.
+    #[inline]
+    pub fn versions_pruned(&self) -> usize {
+        self.versions_pruned
+    }
+}
+
+/// A corrupted entity found by [`Repo::check`].
+///
+/// [`Repo::check`]: struct.Repo.html#method.check
+#[derive(Debug)]
+pub struct CorruptEntity {
+    path: PathBuf,
+    id: Eid,
+    error: Error,
+}
+
+impl CorruptEntity {
+    /// Returns the path of file or directory which refers to the entity.
+    ///
+    /// If the entity is shared by multiple files, only the first one found
+    /// is returned.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Returns the entity ID.
+    #[inline]
+    pub fn id(&self) -> &Eid {
+        &self.id
+    }
+
+    /// Returns the error found when checking the entity.
+    ///
+    /// For example, [`Error::Corrupted`] means content data doesn't match
+    /// its hash.
+    ///
+    /// [`Error::Corrupted`]: enum.Error.html#variant.Corrupted
+    #[inline]
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+}
+
+/// Report of a repository consistency check.
+///
+/// This structure is returned from [`Repo::check`].
+///
+/// [`Repo::check`]: struct.Repo.html#method.check
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    fnodes: usize,
+    contents: usize,
+    segments: usize,
+    corrupted: Vec<CorruptEntity>,
+}
+
+impl CheckReport {
+    /// Returns whether no corrupted entity is found.
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.corrupted.is_empty()
+    }
+
+    /// Returns the number of files and directories checked.
+    #[inline]
+    pub fn fnodes(&self) -> usize {
+        self.fnodes
+    }
+
+    /// Returns the number of file contents checked.
+    #[inline]
+    pub fn contents(&self) -> usize {
+        self.contents
+    }
+
+    /// Returns the number of data segments checked.
+    #[inline]
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Returns the list of corrupted entities.
+    #[inline]
+    pub fn corrupted(&self) -> &[CorruptEntity] {
+        &self.corrupted
+    }
+}
+
+/// Report of repository space usage.
+///
+/// This structure is returned from [`Repo::space_usage`]. All sizes are in
+/// bytes.
+///
+/// [`Repo::space_usage`]: struct.Repo.html#method.space_usage
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpaceUsage {
+    logical_bytes: usize,
+    physical_bytes: usize,
+    segments: usize,
+    dedup_savings: usize,
+    limit: Option<(usize, usize)>,
+}
+
+impl SpaceUsage {
+    /// Returns the total content length of current versions of all files.
+    #[inline]
+    pub fn logical_bytes(&self) -> usize {
+        self.logical_bytes
+    }
+
+    /// Returns the length of blocks allocated in the underlying storage.
+    ///
+    /// This includes repository metadata and blocks not used anymore, as
+    /// blocks are allocated only, it never decreases.
+    #[inline]
+    pub fn physical_bytes(&self) -> usize {
+        self.physical_bytes
+    }
+
+    /// Returns the number of data segments used by all file versions.
+    #[inline]
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Returns the number of bytes saved by deduplication.
+    ///
+    /// This is the total content length of all file versions stored in
+    /// segments minus the length of segment data actually used by them.
+    #[inline]
+    pub fn dedup_savings(&self) -> usize {
+        self.dedup_savings
+    }
+
+    /// Returns the size cap of the underlying storage, or `None` if the
+    /// storage is not capped.
+    ///
+    /// Only memory storage can have a size cap, see [`RepoOpener::open`].
+    ///
+    /// [`RepoOpener::open`]: struct.RepoOpener.html#method.open
+    #[inline]
+    pub fn capacity(&self) -> Option<usize> {
+        self.limit.map(|(_, max_size)| max_size)
+    }
+
+    /// Returns the number of bytes can still be written to the underlying
+    /// storage before it is full, or `None` if the storage is not capped.
+    #[inline]
+    pub fn available(&self) -> Option<usize> {
+        self.limit
+            .map(|(used, max_size)| max_size.saturating_sub(used))
+    }
+}
+
+/// Report of repository memory usage.
+///
+/// This structure is returned from [`Repo::memory_usage`]. All sizes are in
+/// bytes.
+///
+/// [`Repo::memory_usage`]: struct.Repo.html#method.memory_usage
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryReport {
+    frame_cache: usize,
+    data_cache: usize,
+    address_cache: usize,
+    storage_cache: usize,
+    write_buffers: usize,
+    read_buffers: usize,
+}
+
+impl MemoryReport {
+    /// Returns the size of decrypted frame cache.
+    #[inline]
+    pub fn frame_cache(&self) -> usize {
+        self.frame_cache
+    }
+
+    /// Returns the size of file content data cache.
+    #[inline]
+    pub fn data_cache(&self) -> usize {
+        self.data_cache
+    }
+
+    /// Returns the size of entity address cache.
+    #[inline]
+    pub fn address_cache(&self) -> usize {
+        self.address_cache
+    }
+
+    /// Returns the size of in-memory caches of the underlying storage, such
+    /// as the sector cache of file storage.
+    #[inline]
+    pub fn storage_cache(&self) -> usize {
+        self.storage_cache
+    }
+
+    /// Returns the size of buffers used by files being written.
+    #[inline]
+    pub fn write_buffers(&self) -> usize {
+        self.write_buffers
+    }
+
+    /// Returns the size of buffers used by files and version readers being
+    /// read.
+    #[inline]
+    pub fn read_buffers(&self) -> usize {
+        self.read_buffers
+    }
+
+    /// Returns the total size of caches and buffers.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.frame_cache
+            + self.data_cache
+            + self.address_cache
+            + self.storage_cache
+            + self.write_buffers
+            + self.read_buffers
+    }
+}
+
+/// Hit and miss counts of repository's internal caches.
+///
+/// This is returned from [`Repo::cache_stats`]. The counts are accumulated
+/// since the repository is opened.
+///
+/// [`Repo::cache_stats`]: struct.Repo.html#method.cache_stats
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    frame_hits: usize,
+    frame_misses: usize,
+    address_hits: usize,
+    address_misses: usize,
+}
+
+impl CacheStats {
+    /// Returns the number of frames read from decrypted frame cache.
+    #[inline]
+    pub fn frame_hits(&self) -> usize {
+        self.frame_hits
+    }
+
+    /// Returns the number of frames read from underlying storage.
+    #[inline]
+    pub fn frame_misses(&self) -> usize {
+        self.frame_misses
+    }
+
+    /// Returns the number of entity addresses found in address cache.
+    #[inline]
+    pub fn address_hits(&self) -> usize {
+        self.address_hits
+    }
+
+    /// Returns the number of entity address lookups missed address cache,
+    /// including lookups of entities which don't exist.
+    #[inline]
+    pub fn address_misses(&self) -> usize {
+        self.address_misses
+    }
+}
+
+/// Usage of storage backend's local cache.
+///
+/// This is returned from [`Repo::local_cache_usage`].
+///
+/// [`Repo::local_cache_usage`]: struct.Repo.html#method.local_cache_usage
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheUsage {
+    used: usize,
+    capacity: usize,
+    entries: usize,
+}
+
+impl CacheUsage {
+    /// Returns the bytes used by cached objects.
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the cache capacity in bytes.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of cached objects.
+    #[inline]
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+}
+
+/// An entry in a repository [`Manifest`].
+///
+/// [`Manifest`]: struct.Manifest.html
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    file_type: FileType,
+    content_len: usize,
+    modified_at: SystemTime,
+    content_hash: Option<String>,
+}
+
+impl ManifestEntry {
+    /// Returns the absolute path of this entry.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Returns the file type of this entry.
+    #[inline]
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Returns the content length of this entry, in bytes.
+    ///
+    /// It is always zero for directories.
+    #[inline]
+    pub fn content_len(&self) -> usize {
+        self.content_len
+    }
+
+    /// Returns the last modification time of this entry.
+    #[inline]
+    pub fn modified_at(&self) -> SystemTime {
+        self.modified_at
+    }
+
+    /// Returns the hex encoded content hash of this entry.
+    ///
+    /// It is `None` for directories.
+    #[inline]
+    pub fn content_hash(&self) -> Option<&str> {
+        self.content_hash.as_deref()
+    }
+}
+
+/// A snapshot of all files and directories in a repository.
+///
+/// A manifest is created by [`Repo::manifest`], it can be serialized using
+/// `serde` and later be checked by [`Repo::verify_against_manifest`].
+///
+/// [`Repo::manifest`]: struct.Repo.html#method.manifest
+/// [`Repo::verify_against_manifest`]: struct.Repo.html#method.verify_against_manifest
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Returns all entries in this manifest, sorted by path.
+    #[inline]
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+}
+
+// an entry in exported manifest, it has no content hash so the manifest
+// can be created without reading any content
+#[derive(PartialEq, Deserialize, Serialize)]
+struct ExportedEntry {
+    path: PathBuf,
+    file_type: FileType,
+    content_len: usize,
+    curr_version: usize,
+    modified_at: SystemTime,
+}
+
+impl From<&DirEntry> for ExportedEntry {
+    fn from(ent: &DirEntry) -> Self {
+        let md = ent.metadata();
+        ExportedEntry {
+            path: ent.path().to_path_buf(),
+            file_type: md.file_type(),
+            content_len: md.content_len(),
+            curr_version: md.curr_version(),
+            modified_at: md.modified_at(),
+        }
+    }
+}
+
+// compare current entries against old entries by path, both are keyed by
+// their paths
+fn diff_entries<T, F>(curr: &[T], olds: &[T], path_of: F) -> Vec<Difference>
+where
+    T: PartialEq,
+    F: Fn(&T) -> &Path,
+{
+    let mut olds: HashMap<&Path, &T> =
+        olds.iter().map(|ent| (path_of(ent), ent)).collect();
+    let mut ret = Vec::new();
+
+    for ent in curr.iter() {
+        let path = path_of(ent);
+        match olds.remove(path) {
+            Some(old) if old != ent => {
+                ret.push(Difference::Modified(path.to_path_buf()))
+            }
+            Some(_) => {}
+            None => ret.push(Difference::Added(path.to_path_buf())),
+        }
+    }
+    ret.extend(
+        olds.into_keys()
+            .map(|path| Difference::Removed(path.to_path_buf())),
+    );
+
+    ret.sort_by(|a, b| a.path().cmp(b.path()));
+    ret
+}
+
+/// A difference found between a repository and a [`Manifest`].
+///
+/// This enum is returned from [`Repo::verify_against_manifest`], and from
+/// [`Repo::diff_manifest`] as [`ManifestChange`].
+///
+/// [`Manifest`]: struct.Manifest.html
+/// [`Repo::verify_against_manifest`]: struct.Repo.html#method.verify_against_manifest
+/// [`Repo::diff_manifest`]: struct.Repo.html#method.diff_manifest
+/// [`ManifestChange`]: type.ManifestChange.html
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Difference {
+    /// The path exists in repository but not in manifest.
+    Added(PathBuf),
+
+    /// The path exists in manifest but not in repository.
+    Removed(PathBuf),
+
+    /// The path exists in both, but its type, length, modification time or
+    /// content hash is changed. For [`Repo::diff_manifest`], it is changed
+    /// if its type, length, modification time or current version number is
+    /// changed.
+    ///
+    /// [`Repo::diff_manifest`]: struct.Repo.html#method.diff_manifest
+    Modified(PathBuf),
+}
+
+/// A change found between a repository and an exported manifest.
+///
+/// This is returned from [`Repo::diff_manifest`].
+///
+/// [`Repo::diff_manifest`]: struct.Repo.html#method.diff_manifest
+pub type ManifestChange = Difference;
+
+impl Difference {
+    /// Returns the path of this difference.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        match self {
+            Difference::Added(path)
+            | Difference::Removed(path)
+            | Difference::Modified(path) => path.as_path(),
+        }
+    }
+}
+
+/// Report of a repository replication.
+///
+/// This structure is returned from [`Repo::replicate_to`].
+///
+/// [`Repo::replicate_to`]: struct.Repo.html#method.replicate_to
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationStats {
+    files_transferred: usize,
+    files_skipped: usize,
+    versions_transferred: usize,
+    bytes_transferred: usize,
+    entries_removed: usize,
+    manifest: Manifest,
+}
+
+impl ReplicationStats {
+    /// Returns the number of files which have content transferred.
+    #[inline]
+    pub fn files_transferred(&self) -> usize {
+        self.files_transferred
+    }
+
+    /// Returns the number of files skipped because they are not changed
+    /// or are already transferred.
+    #[inline]
+    pub fn files_skipped(&self) -> usize {
+        self.files_skipped
+    }
+
+    /// Returns the number of file versions transferred.
+    #[inline]
+    pub fn versions_transferred(&self) -> usize {
+        self.versions_transferred
+    }
+
+    /// Returns the number of content bytes transferred.
+    #[inline]
+    pub fn bytes_transferred(&self) -> usize {
+        self.bytes_transferred
+    }
+
+    /// Returns the number of entries removed from the destination
+    /// repository.
+    #[inline]
+    pub fn entries_removed(&self) -> usize {
+        self.entries_removed
+    }
+
+    /// Returns the manifest of the source repository when it was
+    /// replicated.
+    ///
+    /// It can be passed to the next [`Repo::replicate_to`] to only transfer
+    /// the changes made after this replication.
+    ///
+    /// [`Repo::replicate_to`]: struct.Repo.html#method.replicate_to
+    #[inline]
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+}
+
+// remove a file, symbolic link or directory recursively
+fn remove_entry(repo: &mut Repo, path: &Path, ftype: FileType) -> Result<()> {
+    match ftype {
+        FileType::Dir => repo.remove_dir_all(path),
+        _ => repo.remove_file(path),
+    }
+}
+
+// open a regular file with options
+fn open_file_with_options<P: AsRef<Path>>(
+    fs: &mut Fs,
+    path: P,
+    open_opts: &OpenOptions,
+) -> Result<File> {
+    if fs.is_read_only()
+        && (open_opts.write
+            || open_opts.append
+            || open_opts.truncate
+            || open_opts.create
+            || open_opts.create_new
+            || open_opts.append_only)
+    {
+        return Err(Error::ReadOnly);
+    }
+
+    let path = path.as_ref();
+
+    match fs.resolve(path, true) {
+        Ok(_) => {
+            if open_opts.create_new {
+                return Err(Error::AlreadyExists);
+            }
+        }
+        Err(ref err) if *err == Error::NotFound && open_opts.create => {
+            let mut opts = fs.get_opts();
+            if let Some(version_limit) = open_opts.version_limit {
+                opts.version_limit = version_limit;
+            }
+            if let Some(dedup_chunk) = open_opts.dedup_chunk {
+                opts.dedup_chunk = dedup_chunk;
+            }
+            if open_opts.compress.is_some() {
+                opts.compress = open_opts.compress;
+            }
+            fs.create_fnode(path, FileType::File, opts)?;
+        }
+        Err(err) => return Err(err),
+    }
+
+    let curr_len;
+    let is_append_only;
+    let mut handle = fs.open_fnode(path)?;
+    handle.low_memory = open_opts.low_memory;
+    handle.skip_identical = open_opts.skip_identical_versions;
+    if open_opts.max_len.is_some() {
+        handle.max_len = open_opts.max_len;
+    }
+    handle.aad = open_opts.aad.clone();
+    {
+        let fnode = handle.fnode.read().unwrap();
+        if fnode.is_dir() {
+            return Err(Error::IsDir);
+        }
+        if !handle.aad.is_empty() {
+            let opts = fnode.get_opts();
+            if opts.dedup_file || opts.dedup_chunk {
+                return Err(Error::InvalidArgument);
+            }
+        }
+        curr_len = fnode.curr_len();
+        is_append_only = fnode.is_append_only();
+    }
+
+    // append-only file is always positioned at its end
+    let pos = if open_opts.append || is_append_only {
+        SeekFrom::Start(curr_len as u64)
+    } else {
+        SeekFrom::Start(0)
+    };
+    let writer = if open_opts.write {
+        Some(fs.register_writer(&handle))
+    } else {
+        None
+    };
+    let mut file = File::new(handle, pos, open_opts.read, open_opts.write);
+    if let Some(writer) = writer {
+        file.set_writer(writer);
+    }
+    file.set_append(open_opts.append);
+    file.set_auto_sync(open_opts.auto_sync_bytes);
+
+    if open_opts.truncate && curr_len > 0 {
+        file.set_len(0)?;
+    }
+
+    if open_opts.append_only && !is_append_only {
+        fs.set_append_only(path, true)?;
+    }
+
+    Ok(file)
+}
+
+/// An encrypted repository contains the whole file system.
+///
+/// A `Repo` represents a secure collection which consists of files,
+/// directories and their associated data. Similar to [`std::fs`], `Repo`
+/// provides methods to manipulate the enclosed file system.
+///
+/// # Storages
+///
+/// ZboxFS supports a variety of underlying storages, which are listed below.
+///
+/// | Storage            | URI identifier  | Cargo Feature       |
+/// | ------------------ | --------------- | ------------------- |
+/// | Memory             | "mem://"        | N/A                 |
+/// | OS file system     | "file://"       | storage-file        |
+/// | SQLite             | "sqlite://"     | storage-sqlite      |
+/// | Redis              | "redis://"      | storage-redis       |
+/// | Redis over TLS     | "rediss://"     | storage-redis-tls   |
+/// | PostgreSQL         | "postgres://"   | storage-postgres    |
+/// | Zbox Cloud Storage | "zbox://"       | storage-zbox-native |
+///
+/// \* Visit [zbox.io](https://zbox.io) to learn more about Zbox Cloud Storage.
+///
+/// By default, only memory storage is enabled. To use other storages, you need
+/// to specify it as dependency features in your Cargo.toml.
+///
+/// For example, to use OS file as underlying storage, specify its feature in
+/// your project's Cargo.toml file.
+///
+/// ```toml
+/// [dependencies]
+/// zbox = { version = "0.9.2", features = ["storage-file"] }
+/// ```
+///
+/// # Create and open `Repo`
+///
+/// `Repo` can be created on different underlying storages using [`RepoOpener`].
+/// It uses an URI-like string to specify its storage type and location. The
+/// URI string starts with an identifier which specifies the storage type, as
+/// shown in above table. You can check more location URI details at:
+/// [RepoOpener](struct.RepoOpener.html#method.open).
+///
+/// `Repo` can only be opened once at a time. After opened, it keeps locked
+/// from other open attempts until it goes out of scope. Once `Repo` is dropped,
+/// any opened files or version readers won't be accessible.
+///
+/// Repositories at different locations, such as two `mem://` names or two
+/// `file://` paths, are independent of each other and can be opened at the
+/// same time in one process, from one or more threads. An unfinished write to
+/// a file in one repository doesn't block writing to other repositories.
+///
+/// Optionally, `Repo` can be opened in [`read-only`] mode if you only need
+/// read access.
+///
+/// # Examples
+///
+/// Create an OS file system based repository.
+///
+/// ```no_run
+/// # #![allow(unused_mut, unused_variables, dead_code)]
+/// # use zbox::Result;
+/// use zbox::{init_env, RepoOpener};
+///
+/// # fn foo() -> Result<()> {
+/// init_env();
+/// let mut repo = RepoOpener::new()
+///     .create(true)
+///     .open("file:///path/to/repo", "pwd")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Create a memory based repository.
+///
+/// ```
+/// # #![allow(unused_mut, unused_variables, dead_code)]
+/// # use zbox::{init_env, Result, RepoOpener};
+/// # fn foo() -> Result<()> {
+/// # init_env();
+/// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Open a repository in read-only mode.
+///
+/// ```no_run
+/// # #![allow(unused_mut, unused_variables, dead_code)]
+/// # use zbox::{Result, RepoOpener};
+/// # fn foo() -> Result<()> {
+/// let mut repo = RepoOpener::new()
+///     .read_only(true)
+///     .open("file:///path/to/repo", "pwd")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`std::fs`]: https://doc.rust-lang.org/std/fs/index.html
+/// [`init_env`]: fn.init_env.html
+/// [`RepoOpener`]: struct.RepoOpener.html
+/// [`read-only`]: struct.RepoOpener.html#method.read_only
+pub struct Repo {
+    fs: Fs,
+}
+
+impl Repo {
+    /// Returns whether the URI points at an existing repository.
+    #[inline]
+    pub fn exists(uri: &str) -> Result<bool> {
+        Fs::exists(uri, &TransportConfig::default())
+    }
+
+    // create repo
+    #[inline]
+    fn create(
+        uri: &str,
+        pwd: &str,
+        key_file: Option<Key>,
+        cfg: &Config,
+        transport: &TransportConfig,
+        sync: SyncLevel,
+    ) -> Result<Repo> {
+        let fs = Fs::create(uri, pwd, key_file, cfg, transport, sync)?;
+        Ok(Repo { fs })
+    }
+
+    // open repo
+    #[inline]
+    fn open(
+        uri: &str,
+        pwd: &str,
+        key_file: Option<Key>,
+        read_only: bool,
+        force: bool,
+        transport: &TransportConfig,
+        sync: SyncLevel,
+    ) -> Result<Repo> {
+        let fs =
+            Fs::open(uri, pwd, key_file, read_only, force, transport, sync)?;
+        Ok(Repo { fs })
+    }
+
+    /// Get repository metadata information.
+    pub fn info(&self) -> Result<RepoInfo> {
+        let meta = self.fs.info();
+        Ok(RepoInfo {
             volume_id: meta.vol_info.id.clone(),
             ver: meta.vol_info.ver.clone(),
             uri: meta.vol_info.uri.clone(),
             cost: meta.vol_info.cost,
             cipher: meta.vol_info.cipher,
-            compress: meta.vol_info.compress,
+            compression: meta.vol_info.compression,
             version_limit: meta.opts.version_limit,
             dedup_chunk: meta.opts.dedup_chunk,
             dedup_file: meta.opts.dedup_file,
+            track_dir_sizes: meta.opts.track_dir_sizes,
+            inline_threshold: meta.opts.inline_threshold,
             read_only: meta.read_only,
+            was_dirty: meta.was_dirty,
             ctime: meta.vol_info.ctime,
+            label: meta.vol_info.label.clone(),
+            blk_size: meta.vol_info.blk_size,
+            blks_per_frame: meta.vol_info.blks_per_frame,
+            chunk_params: (
+                meta.vol_info.chunk_min_size,
+                meta.vol_info.chunk_avg_size,
+                meta.vol_info.chunk_max_size,
+            ),
+        })
+    }
+
+    /// Sets whether this repository is read-only.
+    ///
+    /// This switches write access of an opened repository, without closing
+    /// and reopening it, so caches and the repository lock are kept. It is
+    /// the same as opening with [`read_only`] once it is set, all write
+    /// operations and opening files with write access return
+    /// [`Error::ReadOnly`]. Deferred commits are saved before switching to
+    /// read-only, see [`background_commit`].
+    ///
+    /// Files already opened with write access are not affected if
+    /// `reject_handles` is false, they can still be written and finished as
+    /// usual. If it is true, their next [`write`], [`write_at`] or
+    /// [`finish`] returns [`Error::ReadOnly`] and the unfinished write is
+    /// aborted, as do other operations changing the file. Switching back to
+    /// writable allows those files to be written again.
+    ///
+    /// Switching back to writable resumes an unfinished cipher change which
+    /// was skipped because the repository was opened in read-only mode, see
+    /// [`change_cipher`]. The repository stays read-only if it fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InUse` if there is an unfinished cipher change but
+    /// any file is being written or any snapshot is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Error, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.set_read_only(true, true)?;
+    /// assert!(repo.info()?.is_read_only());
+    /// assert_eq!(repo.create_file("/foo").unwrap_err(), Error::ReadOnly);
+    ///
+    /// repo.set_read_only(false, true)?;
+    /// repo.create_file("/foo")?;
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`read_only`]: struct.RepoOpener.html#method.read_only
+    /// [`background_commit`]: struct.RepoOpener.html#method.background_commit
+    /// [`write`]: struct.File.html#method.write
+    /// [`write_at`]: struct.File.html#method.write_at
+    /// [`finish`]: struct.File.html#method.finish
+    /// [`change_cipher`]: struct.Repo.html#method.change_cipher
+    /// [`Error::ReadOnly`]: enum.Error.html
+    #[inline]
+    pub fn set_read_only(
+        &mut self,
+        read_only: bool,
+        reject_handles: bool,
+    ) -> Result<()> {
+        self.fs.set_read_only(read_only, reject_handles)
+    }
+
+    /// Checks the storage backend is reachable.
+    ///
+    /// This method does a cheap round-trip to the backend, such as checking
+    /// the repository still exists, without reading or writing any data. For
+    /// remote backends it also confirms the access credentials are valid.
+    ///
+    /// Returns an error if the backend cannot be reached, or
+    /// [`NotFound`] if the repository has been removed from it.
+    ///
+    /// [`NotFound`]: enum.Error.html
+    #[inline]
+    pub fn ping(&self) -> Result<()> {
+        self.fs.ping()
+    }
+
+    /// Reset password for the repository.
+    ///
+    /// The new `ops_limit` and `mem_limit` take effect immediately, they are
+    /// saved to super block and used to derive key from the new password when
+    /// the repository is opened next time. Lower limits are accepted, use
+    /// [`reset_password_checked`] to guard against weakening them.
+    ///
+    /// If the repository was opened with a [key file], it is still required
+    /// after reset, and an empty `new_pwd` makes the key file the only key
+    /// factor.
+    ///
+    /// Note: if this method failed due to IO error, super block might be
+    /// damaged. If it is the case, use
+    /// [repair_super_block](struct.Repo.html#method.repair_super_block)
+    /// to restore super block before re-opening the repo.
+    ///
+    /// [`reset_password_checked`]: struct.Repo.html#method.reset_password_checked
+    /// [key file]: struct.RepoOpener.html#method.key_file
+    #[inline]
+    pub fn reset_password(
+        &mut self,
+        old_pwd: &str,
+        new_pwd: &str,
+        ops_limit: OpsLimit,
+        mem_limit: MemLimit,
+    ) -> Result<()> {
+        let cost = Cost::new(ops_limit, mem_limit);
+        self.fs.reset_password(old_pwd, new_pwd, cost)
+    }
+
+    /// Reset password for the repository, rejecting weaker password hashing
+    /// cost.
+    ///
+    /// This method is the same as [`reset_password`], except that it returns
+    /// [`WeakerCost`] error if either `ops_limit` or `mem_limit` is lower than
+    /// the current one, unless `allow_weaken` is true. The password is not
+    /// changed if the cost is rejected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::{Error, MemLimit, OpsLimit};
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .ops_limit(OpsLimit::Moderate)
+    ///     .open("mem://foo", "pwd")?;
+    /// let result = repo.reset_password_checked(
+    ///     "pwd",
+    ///     "new pwd",
+    ///     OpsLimit::Interactive,
+    ///     MemLimit::Interactive,
+    ///     false,
+    /// );
+    /// assert_eq!(result.unwrap_err(), Error::WeakerCost);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`reset_password`]: struct.Repo.html#method.reset_password
+    /// [`WeakerCost`]: enum.Error.html
+    pub fn reset_password_checked(
+        &mut self,
+        old_pwd: &str,
+        new_pwd: &str,
+        ops_limit: OpsLimit,
+        mem_limit: MemLimit,
+        allow_weaken: bool,
+    ) -> Result<()> {
+        let cost = Cost::new(ops_limit, mem_limit);
+        if !allow_weaken && cost.is_weaker_than(&self.fs.info().vol_info.cost) {
+            return Err(Error::WeakerCost);
+        }
+        self.fs.reset_password(old_pwd, new_pwd, cost)
+    }
+
+    /// Changes the cipher of this repository.
+    ///
+    /// All file data, metadata and write-ahead logs are decrypted and
+    /// re-encrypted with `new_cipher`, so this can take a long time for a
+    /// large repository. Deferred commits are saved before the change starts.
+    /// `pwd` is the repository password, it is verified before anything is
+    /// changed. Nothing is done if `new_cipher` is already in use.
+    ///
+    /// The change is crash safe. Entities are re-encrypted in batches and
+    /// remain readable with either cipher until it is finished, the cipher
+    /// recorded in super block is updated last. If it is interrupted, the
+    /// change is resumed next time the repository is opened in read-write
+    /// mode, or it can be resumed by calling this method again with the same
+    /// `new_cipher`. Calling it with the original cipher rolls back an
+    /// interrupted change instead. If resuming fails, opening the repository
+    /// in read-write mode returns the error, it can still be opened in
+    /// read-only mode.
+    ///
+    /// Entity addresses are re-encrypted along with entities. Metadata kept
+    /// by the storage itself, such as the index and sectors of file storage,
+    /// is re-encrypted at the end of the change. Zbox storage doesn't
+    /// support that and keeps the cipher it was created with for its own
+    /// metadata, other built-in storages don't encrypt such metadata. Custom
+    /// storage supports it by implementing [`Storable::change_crypto_ctx`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::ReadOnly` if the repository is opened in read-only
+    /// mode, `Error::InUse` if any file is being written or any snapshot
+    /// taken by [`snapshot_handle`] is alive, and `Error::Decrypt` if `pwd`
+    /// is wrong.
+    ///
+    /// Returns `Error::InvalidArgument` if any file is bound to associated
+    /// data, because its content cannot be decrypted without the data, or
+    /// if `new_cipher` is neither side of an interrupted change.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use zbox::Cipher;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .cipher(Cipher::Xchacha)
+    ///     .open("mem://foo", "pwd")?;
+    /// repo.change_cipher(Cipher::ChaCha20Poly1305, "pwd")?;
+    /// assert_eq!(repo.info()?.cipher(), Cipher::ChaCha20Poly1305);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`snapshot_handle`]: struct.Repo.html#method.snapshot_handle
+    /// [`Storable::change_crypto_ctx`]: trait.Storable.html#method.change_crypto_ctx
+    #[inline]
+    pub fn change_cipher(
+        &mut self,
+        new_cipher: Cipher,
+        pwd: &str,
+    ) -> Result<()> {
+        self.fs.change_cipher(new_cipher, pwd, &mut |_, _| {})
+    }
+
+    /// Changes the cipher of this repository, reporting progress.
+    ///
+    /// This method is the same as [`change_cipher`], except that `progress`
+    /// is called with the number of re-encrypted entities and the total
+    /// number of entities after each entity is re-encrypted.
+    ///
+    /// [`change_cipher`]: struct.Repo.html#method.change_cipher
+    pub fn change_cipher_with_progress<F>(
+        &mut self,
+        new_cipher: Cipher,
+        pwd: &str,
+        mut progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.fs.change_cipher(new_cipher, pwd, &mut progress)
+    }
+
+    /// Sets the label of this repository.
+    ///
+    /// The label is saved to super block immediately. An empty `label`
+    /// removes the existing label.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `label` is longer than 255 bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .label("photos")
+    ///     .open("mem://foo", "pwd")?;
+    /// assert_eq!(repo.info()?.label(), Some("photos"));
+    ///
+    /// repo.set_label("holiday photos")?;
+    /// assert_eq!(repo.info()?.label(), Some("holiday photos"));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn set_label(&mut self, label: &str) -> Result<()> {
+        let label = if label.is_empty() {
+            None
+        } else {
+            Some(label.to_string())
+        };
+        self.fs.set_label(label)
+    }
+
+    /// Repair possibly damaged super block.
+    ///
+    /// This method will try to repair super block using backup. One scenario
+    /// is when [reset_password](struct.Repo.html#method.reset_password) failed
+    /// due to IO error, super block might be damaged. Using this method can
+    /// restore the damaged super block from backup. If super block is all
+    /// good, this method is no-op.
+    ///
+    /// This method is not useful for memory-based storage and must be called
+    /// when repo is closed.
+    #[inline]
+    pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
+        Fs::repair_super_block(uri, pwd)
+    }
+
+    /// Derives a key from the password of repository at URI.
+    ///
+    /// The password is hashed with the salt and cost saved in the
+    /// repository, so this is as slow as opening the repository with
+    /// password. The key can then be used to open the repository by
+    /// [`RepoOpener::open_with_key`] quickly. A key derived from wrong
+    /// password is not detected until it is used to open the repository.
+    ///
+    /// [`RepoOpener::open_with_key`]: struct.RepoOpener.html#method.open_with_key
+    #[inline]
+    pub fn derive_key(uri: &str, pwd: &str) -> Result<RepoKey> {
+        Fs::derive_key(uri, pwd, &TransportConfig::default()).map(RepoKey)
+    }
+
+    /// Checks whether the password of repository at URI is correct.
+    ///
+    /// Returns `Ok(true)` if the password is correct and `Ok(false)` if it is
+    /// wrong. Only the super block is read and decrypted, the repository is
+    /// not opened, so this method doesn't acquire the repository lock and
+    /// can be called while the repository is opened by others. It is as slow
+    /// as opening the repository with password, because the key is derived
+    /// using the cost saved in the repository.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository cannot be accessed or its super
+    /// block is damaged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, Repo, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let _repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .open("mem://foo", "pwd")?;
+    ///
+    /// assert!(Repo::verify_password("mem://foo", "pwd")?);
+    /// assert!(!Repo::verify_password("mem://foo", "wrong pwd")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn verify_password(uri: &str, pwd: &str) -> Result<bool> {
+        Fs::verify_password(uri, pwd, &TransportConfig::default())
+    }
+
+    /// Check consistency of a repository.
+    ///
+    /// This method opens the repository in read-only mode, then walks
+    /// through all the files and directories. It verifies every referenced
+    /// file, content and segment can be loaded, every segment data can be
+    /// decrypted and every file content matches its hash. Content which is
+    /// bound to associated data is not decrypted, because the data is not
+    /// available here.
+    ///
+    /// Corrupted entities are collected in the returned [`CheckReport`]
+    /// rather than failing on the first one. This method will read all the
+    /// data in the repository, so it could take a long time.
+    ///
+    /// This method must be called when repo is closed.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the repository cannot be opened, for example, its
+    /// super block is damaged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, Repo, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// {
+    ///     let mut repo = RepoOpener::new()
+    ///         .create(true)
+    ///         .open("mem://foo", "pwd")?;
+    ///     let mut file = repo.create_file("/foo.txt")?;
+    ///     file.write_once(b"Hello, world!")?;
+    /// }
+    ///
+    /// let report = Repo::check("mem://foo", "pwd")?;
+    /// assert!(report.is_ok());
+    /// assert_eq!(report.fnodes(), 2);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`CheckReport`]: struct.CheckReport.html
+    pub fn check(uri: &str, pwd: &str) -> Result<CheckReport> {
+        let (fnodes, contents, segments, corrupted) = Fs::check(uri, pwd)?;
+        Ok(CheckReport {
+            fnodes,
+            contents,
+            segments,
+            corrupted: corrupted
+                .into_iter()
+                .map(|(path, id, error)| CorruptEntity { path, id, error })
+                .collect(),
+        })
+    }
+
+    /// Returns whether the path points at an existing entity in repository.
+    ///
+    /// `path` must be an absolute path.
+    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(self
+            .fs
+            .resolve(path.as_ref(), true)
+            .map(|_| true)
+            .unwrap_or(false))
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a regular file.
+    ///
+    /// Symbolic links are followed.
+    ///
+    /// `path` must be an absolute path.
+    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        match self.fs.resolve(path.as_ref(), true) {
+            Ok(fnode_ref) => {
+                let fnode = fnode_ref.read().unwrap();
+                Ok(fnode.is_file())
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Returns whether the path exists in repository and is pointing at
+    /// a directory.
+    ///
+    /// Symbolic links are followed.
+    ///
+    /// `path` must be an absolute path.
+    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        match self.fs.resolve(path.as_ref(), true) {
+            Ok(fnode_ref) => {
+                let fnode = fnode_ref.read().unwrap();
+                Ok(fnode.is_dir())
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Create a file in read-write mode.
+    ///
+    /// This method will create a file if it does not exist, and will
+    /// truncate it if it does. See the
+    /// [`OpenOptions::open`](struct.OpenOptions.html#method.open) method for
+    /// more details.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is **not** atomic.
+    #[inline]
+    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .open(self, path)
+    }
+
+    /// Attempts to open a file in read-only mode.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// See the [`OpenOptions::open`] method for more details.
+    ///
+    /// # Errors
+    /// This method will return an error if path does not already exist.
+    /// Other errors may also be returned according to [`OpenOptions::open`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let mut f = repo.open_file("foo.txt")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`OpenOptions::open`]: struct.OpenOptions.html#method.open
+    #[inline]
+    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        OpenOptions::new().open(self, path)
+    }
+
+    /// Opens a file for shared reading.
+    ///
+    /// Unlike [`open_file`], this method only borrows the repository
+    /// immutably, so files can be opened and read concurrently while the
+    /// repository is shared between threads. The returned reader is bound to
+    /// the file's current version at the time it is opened, content written
+    /// later is not visible to it. It doesn't implement [`Write`], so the
+    /// file cannot be modified through it.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if `path` does not exist and `Error::IsDir`
+    /// if it is a directory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"foo")?;
+    ///
+    /// let repo = &repo;
+    /// let mut rdr = repo.open_file_shared("/foo.txt")?;
+    /// let mut content = String::new();
+    /// rdr.read_to_string(&mut content)?;
+    /// assert_eq!(content, "foo");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`open_file`]: struct.Repo.html#method.open_file
+    /// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    pub fn open_file_shared<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<VersionReader> {
+        let handle = self.fs.open_fnode(path.as_ref())?;
+        let curr_ver = {
+            let fnode = handle.fnode.read().unwrap();
+            if fnode.is_dir() {
+                return Err(Error::IsDir);
+            }
+            fnode.curr_ver_num()
+        };
+        VersionReader::new(&handle, curr_ver)
+    }
+
+    /// Returns the paths of all currently open file handles.
+    ///
+    /// Every [`File`] and [`VersionReader`] which is still alive is reported,
+    /// so this can be used to find leaked handles which cause
+    /// `Error::InUse`. A version reader obtained from a file is reported
+    /// separately and outlives the file.
+    ///
+    /// Each path is the one used when the handle was opened, it is reported
+    /// once for each open handle and the list is sorted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::path::PathBuf;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let file = repo.create_file("/foo.txt")?;
+    /// assert_eq!(repo.open_handles(), vec![PathBuf::from("/foo.txt")]);
+    ///
+    /// drop(file);
+    /// assert!(repo.open_handles().is_empty());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`File`]: struct.File.html
+    /// [`VersionReader`]: struct.VersionReader.html
+    #[inline]
+    pub fn open_handles(&self) -> Vec<PathBuf> {
+        self.fs.open_handles()
+    }
+
+    /// Subscribes to mutation events of this repository.
+    ///
+    /// A [`RepoEvent`] is sent to the returned receiver after each change
+    /// made through this repository is committed, such as creating, writing,
+    /// removing or renaming a file. Multiple subscribers can be added and
+    /// each of them receives all events, a subscriber is removed when its
+    /// receiver is dropped.
+    ///
+    /// The channel is bounded, writers never wait for the receiver. When the
+    /// channel is full, events are dropped and a [`RepoEvent::Lagged`] event
+    /// with the number of dropped events is sent before the next delivered
+    /// event.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::path::PathBuf;
+    /// use zbox::RepoEvent;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let events = repo.subscribe();
+    /// repo.create_dir("/dir")?;
+    /// assert_eq!(
+    ///     events.try_recv().unwrap(),
+    ///     RepoEvent::Create(PathBuf::from("/dir"))
+    /// );
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`RepoEvent`]: enum.RepoEvent.html
+    /// [`RepoEvent::Lagged`]: enum.RepoEvent.html#variant.Lagged
+    #[inline]
+    pub fn subscribe(&self) -> Receiver<RepoEvent> {
+        self.fs.subscribe()
+    }
+
+    /// Creates an empty file if it does not exist, or updates its
+    /// modification time if it does.
+    ///
+    /// Updating an existing file or directory only changes its modification
+    /// time, no new content version will be created.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.touch("/foo.txt")?;
+    /// assert!(repo.is_file("/foo.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    pub fn touch<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        match self.fs.resolve(path, true) {
+            Ok(_) => self.fs.set_mtime(path, Time::now()),
+            Err(ref err) if *err == Error::NotFound => OpenOptions::new()
+                .create_new(true)
+                .open(self, path)
+                .map(|_| ()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Truncates or extends a file to `len` bytes without opening it.
+    ///
+    /// This is a shortcut of opening the file for writing and calling
+    /// [`File::set_len`] on it. If `len` is greater than the current content
+    /// size, the content is extended and filled with 0s. A new content
+    /// version is created.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// This method will return [`Error::IsDir`] if `path` is a directory, or
+    /// [`Error::ReadOnly`] if the repository is opened in read-only mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"Hello, world!")?;
+    /// repo.truncate("/foo.txt", 5)?;
+    /// assert_eq!(repo.metadata("/foo.txt")?.content_len(), 5);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`File::set_len`]: struct.File.html#method.set_len
+    /// [`Error::IsDir`]: enum.Error.html
+    /// [`Error::ReadOnly`]: enum.Error.html
+    pub fn truncate<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        len: usize,
+    ) -> Result<()> {
+        let mut file = OpenOptions::new().write(true).open(self, path)?;
+        file.set_len(len)
+    }
+
+    /// Sets the modification time of a file or directory.
+    ///
+    /// `path` must be an absolute path. The time can be in the future, but it
+    /// cannot be before unix epoch, otherwise [`Error::InvalidArgument`] is
+    /// returned. Content of the file is not changed.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let mtime = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    /// repo.touch("/foo.txt")?;
+    /// repo.set_modified("/foo.txt", mtime)?;
+    /// assert_eq!(repo.metadata("/foo.txt")?.modified_at(), mtime);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    pub fn set_modified<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        time: SystemTime,
+    ) -> Result<()> {
+        let mtime =
+            Time::from_system_time(time).ok_or(Error::InvalidArgument)?;
+        self.fs.set_mtime(path.as_ref(), mtime)
+    }
+
+    /// Creates a new, empty directory at the specified path.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    #[inline]
+    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs
+            .create_fnode(path.as_ref(), FileType::Dir, Options::default())
+            .map(|_| ())
+    }
+
+    /// Recursively create a directory and all of its parent components if they
+    /// are missing.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is **not** atomic in whole, but creating each entry is
+    /// atomic.
+    #[inline]
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs.create_dir_all(path.as_ref())
+    }
+
+    /// Creates files with content in a single transaction.
+    ///
+    /// Each entry is a pair of absolute file path and file content, the
+    /// content becomes the first version of the file. Missing parent
+    /// directories are created as well. As all the files are created
+    /// in one transaction, this is much faster than creating them one by one
+    /// when there are many small files, such as importing a directory tree.
+    ///
+    /// All the files must not exist, otherwise [`Error::AlreadyExists`] is
+    /// returned. If any entry fails, none of the files and directories will
+    /// be created.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`Error::AlreadyExists`]: enum.Error.html#variant.AlreadyExists
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::path::PathBuf;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.write_batch(vec![
+    ///     (PathBuf::from("/dir/foo"), b"foo".to_vec()),
+    ///     (PathBuf::from("/dir/bar"), b"bar".to_vec()),
+    /// ])?;
+    /// assert!(repo.is_file("/dir/foo")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn write_batch<I>(&mut self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (PathBuf, Vec<u8>)>,
+    {
+        self.fs.write_batch(entries)
+    }
+
+    /// Runs multiple operations in a single transaction.
+    ///
+    /// The closure `f` is given a [`TxnRepo`], which provides a subset of
+    /// `Repo` methods for file system and file operations. All operations
+    /// run through it, including writing to files, are done in one
+    /// transaction. The transaction is committed if `f` returns `Ok`, and
+    /// aborted if `f` returns an error or panics, in which case none of the
+    /// changes will be persisted.
+    ///
+    /// If an operation changing the repository, including writing to files,
+    /// failed in `f` but `f` still returns `Ok`, the transaction is aborted
+    /// and [`Error::Uncompleted`] is returned. Writes to files must be
+    /// finished before `f` returns. Events of the changes are sent to
+    /// subscribers only after the transaction is committed.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ReadOnly`] if the repository is opened in read-only
+    /// mode, or the error returned by `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.transaction(|txn| {
+    ///     txn.create_dir("/app")?;
+    ///     txn.create_file("/app/config")?.write_once(b"config")?;
+    ///     txn.create_file("/app/index")?.write_once(b"index")?;
+    ///     Ok(())
+    /// })?;
+    /// assert!(repo.is_file("/app/index")?);
+    ///
+    /// // nothing is changed if any step failed
+    /// let result = repo.transaction(|txn| {
+    ///     txn.remove_file("/app/index")?;
+    ///     txn.create_dir("/app")
+    /// });
+    /// assert!(result.is_err());
+    /// assert!(repo.is_file("/app/index")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`TxnRepo`]: struct.TxnRepo.html
+    /// [`Error::Uncompleted`]: enum.Error.html
+    /// [`Error::ReadOnly`]: enum.Error.html
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut TxnRepo) -> Result<T>,
+    {
+        let tx_handle = self.fs.begin_joinable_trans()?;
+        let mut txn = TxnRepo {
+            repo: self,
+            failed: false,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut txn)));
+        let failed = txn.failed;
+        match result {
+            Ok(Ok(_)) if failed => self
+                .fs
+                .end_joinable_trans(&tx_handle, Err(Error::Uncompleted)),
+            Ok(result) => self.fs.end_joinable_trans(&tx_handle, result),
+            Err(payload) => {
+                let _ = self.fs.end_joinable_trans::<T>(
+                    &tx_handle,
+                    Err(Error::Uncompleted),
+                );
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Returns a vector of all the entries within a directory.
+    ///
+    /// `path` must be an absolute path.
+    #[inline]
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        self.fs.read_dir(path.as_ref())
+    }
+
+    /// Returns an iterator over the entries within a directory.
+    ///
+    /// Unlike [`read_dir`], entries are loaded lazily while iterating, so it
+    /// is cheaper when only some of the entries are needed. The order of
+    /// entries is same as [`read_dir`]. Entries removed from the directory
+    /// while iterating are skipped, entries added are not returned.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/dir/sub")?;
+    /// let found = repo
+    ///     .read_dir_iter("/dir")?
+    ///     .find(|ent| ent.as_ref().map_or(true, |e| e.file_name() == "sub"));
+    /// assert!(found.is_some());
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`read_dir`]: struct.Repo.html#method.read_dir
+    #[inline]
+    pub fn read_dir_iter<P: AsRef<Path>>(&self, path: P) -> Result<ReadDir> {
+        self.fs.read_dir_iter(path.as_ref())
+    }
+
+    /// Returns a recursive iterator over the directory tree at `root`.
+    ///
+    /// The tree is walked depth-first, the iterator yields path and metadata
+    /// of `root` and all the entries under it. Maximum depth, post-order and
+    /// strict mode can be set on the returned [`Walk`].
+    ///
+    /// Entries are loaded lazily, the repository can be changed while
+    /// walking, for example, removing entries in post-order.
+    ///
+    /// `root` must be an absolute path, it can also be a file.
+    ///
+    /// [`Walk`]: struct.Walk.html
+    #[inline]
+    pub fn walk<P: AsRef<Path>>(&self, root: P) -> Result<Walk> {
+        self.fs.walk(root.as_ref())
+    }
+
+    /// Returns all paths matching a glob pattern, sorted by path.
+    ///
+    /// The pattern is matched against absolute paths in the repository, a
+    /// relative pattern is matched from the root. The following wildcards
+    /// are supported:
+    ///
+    /// - `?` matches any single character
+    /// - `*` matches any sequence of characters within a path component
+    /// - `**` as a whole path component matches zero or more directories
+    /// - `[abc]`, `[a-z]` match one character in the class, `[!abc]` or
+    ///   `[^abc]` match one character not in it
+    ///
+    /// Directories are only descended when they can match the rest of the
+    /// pattern, symbolic links are not followed. Returns
+    /// [`Error::InvalidArgument`] if the pattern is empty or malformed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::path::PathBuf;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/data/2021")?;
+    /// repo.create_file("/data/2021/a.json")?;
+    /// repo.create_file("/data/b.txt")?;
+    ///
+    /// let paths = repo.glob("data/**/*.json")?;
+    /// assert_eq!(paths, vec![PathBuf::from("/data/2021/a.json")]);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html
+    #[inline]
+    pub fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        self.fs.glob(pattern)
+    }
+
+    /// Returns a page of entries within a directory.
+    ///
+    /// Entries are ordered by name. At most `limit` entries after `cursor`
+    /// are returned, together with a [`DirCursor`] to get the next page, or
+    /// `None` if there are no more entries. Pass `None` as `cursor` to get
+    /// the first page.
+    ///
+    /// The cursor stays valid across calls even if the directory is changed,
+    /// entries added or removed after the cursor position will be reflected
+    /// in the following pages.
+    ///
+    /// `path` must be an absolute path. `limit` must be greater than 0.
+    ///
+    /// [`DirCursor`]: struct.DirCursor.html
+    #[inline]
+    pub fn read_dir_page<P: AsRef<Path>>(
+        &self,
+        path: P,
+        cursor: Option<DirCursor>,
+        limit: usize,
+    ) -> Result<(Vec<DirEntry>, Option<DirCursor>)> {
+        self.fs.read_dir_page(path.as_ref(), cursor.as_ref(), limit)
+    }
+
+    /// Get the metadata about a file or directory at specified path.
+    ///
+    /// Symbolic links are followed, use [`symlink_metadata`] to get metadata
+    /// of the link itself.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// [`symlink_metadata`]: #method.symlink_metadata
+    #[inline]
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        self.fs.metadata(path.as_ref())
+    }
+
+    /// Get the metadata about a file, directory or symbolic link at
+    /// specified path, without following symbolic link.
+    ///
+    /// `path` must be an absolute path.
+    #[inline]
+    pub fn symlink_metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Metadata> {
+        self.fs.symlink_metadata(path.as_ref())
+    }
+
+    /// Creates a new symbolic link at `link` pointing to `target`.
+    ///
+    /// `link` must be an absolute path. `target` is stored as is and not
+    /// required to exist, a relative `target` is resolved against the
+    /// directory containing the link.
+    ///
+    /// Symbolic links are followed by path resolution, at most 40 links can
+    /// be followed when resolving a path, otherwise
+    /// [`Error::TooManySymlinks`] is returned.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`Error::TooManySymlinks`]: enum.Error.html#variant.TooManySymlinks
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # use std::path::Path;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    /// repo.symlink("dir", "/link")?;
+    /// assert!(repo.is_dir("/link")?);
+    /// assert_eq!(repo.read_link("/link")?, Path::new("dir"));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        target: P,
+        link: Q,
+    ) -> Result<()> {
+        self.fs.symlink(target.as_ref(), link.as_ref())
+    }
+
+    /// Returns the target path stored in a symbolic link.
+    ///
+    /// `path` must be an absolute path. If `path` is not a symbolic link,
+    /// [`Error::InvalidArgument`] is returned.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    #[inline]
+    pub fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        self.fs.read_link(path.as_ref())
+    }
+
+    /// Returns the total size of all regular files under a directory, in bytes.
+    ///
+    /// Only the current version of each file is counted, and the size is
+    /// calculated recursively.
+    ///
+    /// If the repository is created with [`track_dir_sizes`] enabled, this
+    /// method returns the cached size in O(1) time. Otherwise it walks the
+    /// directory tree, so it takes O(n) time where n is the total number of
+    /// files and directories under it.
+    ///
+    /// `path` must be an absolute path to a directory.
+    ///
+    /// [`track_dir_sizes`]: struct.RepoOpener.html#method.track_dir_sizes
+    #[inline]
+    pub fn dir_size<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        self.fs.dir_size(path.as_ref())
+    }
+
+    /// Return a vector of history versions of a regular file at specified path.
+    ///
+    /// `path` must be an absolute path to a regular file.
+    #[inline]
+    pub fn history<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Version>> {
+        self.fs.history(path.as_ref())
+    }
+
+    /// Removes oldest versions of a regular file, keep at most `keep_last`
+    /// latest versions.
+    ///
+    /// Unlike [`version_limit`], which prunes versions automatically on
+    /// write, this prunes versions only once and doesn't change the limit.
+    /// Content of the removed versions is dereferenced, so space which is not
+    /// used by any other version can be reclaimed. The current version is
+    /// always kept, so `keep_last` of 0 is treated as 1.
+    ///
+    /// `path` must be an absolute path to a regular file. Returns the number
+    /// of removed versions.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result};
+    /// use zbox::{OpenOptions, RepoOpener};
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .version_limit(10)
+    ///     .open("mem://foo", "pwd")?;
+    /// let mut file = OpenOptions::new()
+    ///     .create(true)
+    ///     .open(&mut repo, "/foo.txt")?;
+    /// file.write_once(b"foo")?;
+    /// file.write_once(b"bar")?;
+    /// file.write_once(b"baz")?;
+    ///
+    /// repo.prune_versions("/foo.txt", 1)?;
+    /// assert_eq!(repo.history("/foo.txt")?.len(), 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`version_limit`]: struct.RepoOpener.html#method.version_limit
+    #[inline]
+    pub fn prune_versions<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        keep_last: usize,
+    ) -> Result<usize> {
+        self.fs.prune_versions(path.as_ref(), keep_last)
+    }
+
+    /// Returns paths of all regular files modified after `since`.
+    ///
+    /// The returned paths are absolute and sorted. Directories are not
+    /// included.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    #[inline]
+    pub fn modified_since(&self, since: SystemTime) -> Result<Vec<PathBuf>> {
+        self.fs.modified_since(since)
+    }
+
+    /// Returns the number of bytes used by file content in the repository.
+    ///
+    /// This is the total length of data segments referenced by all versions
+    /// of all files, segments shared by multiple files or versions are only
+    /// counted once. Repository metadata is not included.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    #[inline]
+    pub fn disk_usage(&self) -> Result<usize> {
+        self.fs.disk_usage()
+    }
+
+    /// Returns space usage of the repository.
+    ///
+    /// The usage is collected from the content store and volume allocator,
+    /// file content is not read. If the underlying storage has a size cap,
+    /// the cap and available space are also reported. See [`SpaceUsage`]
+    /// for details.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    ///
+    /// [`SpaceUsage`]: struct.SpaceUsage.html
+    pub fn space_usage(&self) -> Result<SpaceUsage> {
+        let (logical_bytes, physical_bytes, segments, dedup_savings) =
+            self.fs.space_usage()?;
+        Ok(SpaceUsage {
+            logical_bytes,
+            physical_bytes,
+            segments,
+            dedup_savings,
+            limit: self.fs.space_limit(),
+        })
+    }
+
+    /// Returns memory used by the repository's caches and buffers.
+    ///
+    /// Data caches, address cache, in-memory caches of the underlying
+    /// storage and buffers of files being read or written are included,
+    /// small metadata caches are not.
+    ///
+    /// See [`RepoOpener::memory_budget`] to limit memory usage.
+    ///
+    /// [`RepoOpener::memory_budget`]: struct.RepoOpener.html#method.memory_budget
+    pub fn memory_usage(&self) -> MemoryReport {
+        let (
+            frame_cache,
+            data_cache,
+            address_cache,
+            storage_cache,
+            write_buffers,
+            read_buffers,
+        ) = self.fs.memory_usage();
+        MemoryReport {
+            frame_cache,
+            data_cache,
+            address_cache,
+            storage_cache,
+            write_buffers,
+            read_buffers,
+        }
+    }
+
+    /// Returns hit and miss counts of the repository's frame cache and
+    /// address cache.
+    ///
+    /// See [`RepoOpener::frame_cache_size`] and
+    /// [`RepoOpener::address_cache_size`] to change the cache sizes.
+    ///
+    /// [`RepoOpener::frame_cache_size`]: struct.RepoOpener.html#method.frame_cache_size
+    /// [`RepoOpener::address_cache_size`]: struct.RepoOpener.html#method.address_cache_size
+    pub fn cache_stats(&self) -> CacheStats {
+        let ((frame_hits, frame_misses), (address_hits, address_misses)) =
+            self.fs.cache_stats();
+        CacheStats {
+            frame_hits,
+            frame_misses,
+            address_hits,
+            address_misses,
+        }
+    }
+
+    /// Returns usage of the storage backend's local cache.
+    ///
+    /// Only storage which caches remote objects locally has local cache,
+    /// currently it is Zbox Cloud storage. Its capacity and eviction policy
+    /// are set by `cache_size` and `cache_policy` in the repository URI.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if the storage has no local cache.
+    #[inline]
+    pub fn local_cache_usage(&self) -> Result<CacheUsage> {
+        let (used, capacity, entries) = self.fs.local_cache_usage()?;
+        Ok(CacheUsage {
+            used,
+            capacity,
+            entries,
+        })
+    }
+
+    /// Creates a manifest of all files and directories in the repository.
+    ///
+    /// The manifest records path, type, length, modification time and
+    /// content hash of current version for each entry, the root directory is
+    /// not included.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    pub fn manifest(&self) -> Result<Manifest> {
+        let mut entries = Vec::new();
+        for ent in self.fs.walk_dir(Path::new("/"))? {
+            let md = ent.metadata();
+            let content_hash = if md.is_file() {
+                Some(self.fs.content_hash(ent.path())?.to_string())
+            } else {
+                None
+            };
+            entries.push(ManifestEntry {
+                path: ent.path().to_path_buf(),
+                file_type: md.file_type(),
+                content_len: md.content_len(),
+                modified_at: md.modified_at(),
+                content_hash,
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Manifest { entries })
+    }
+
+    /// Compares the repository against a manifest.
+    ///
+    /// Returns all the differences found, sorted by path. An empty vector
+    /// means the repository matches the manifest.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    pub fn verify_against_manifest(
+        &self,
+        manifest: &Manifest,
+    ) -> Result<Vec<Difference>> {
+        let curr = self.manifest()?;
+        Ok(diff_entries(
+            &curr.entries,
+            &manifest.entries,
+            ManifestEntry::path,
+        ))
+    }
+
+    /// Exports the directory tree structure of the repository.
+    ///
+    /// The exported manifest records path, type, length, modification time
+    /// and current version number of all files and directories, the root
+    /// directory is not included. It is serialized in [MessagePack] format.
+    ///
+    /// It is built from the file and directory nodes only. Each node is
+    /// loaded to get its metadata, but unlike [`manifest`], which also loads
+    /// the content of each file to get its hash, no content or file data is
+    /// read. So it is cheaper to create and can be exchanged between
+    /// repositories before transferring content, see [`diff_manifest`].
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::path::PathBuf;
+    /// use zbox::ManifestChange;
+    ///
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    /// let manifest = repo.export_manifest()?;
+    ///
+    /// repo.create_file("/dir/file")?;
+    /// let changes = repo.diff_manifest(&manifest)?;
+    /// let added = ManifestChange::Added(PathBuf::from("/dir/file"));
+    /// assert!(changes.contains(&added));
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [MessagePack]: https://msgpack.org
+    /// [`manifest`]: struct.Repo.html#method.manifest
+    /// [`diff_manifest`]: struct.Repo.html#method.diff_manifest
+    pub fn export_manifest(&self) -> Result<Vec<u8>> {
+        let mut entries: Vec<ExportedEntry> = self
+            .fs
+            .walk_dir(Path::new("/"))?
+            .iter()
+            .map(ExportedEntry::from)
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut buf = Vec::new();
+        entries.serialize(&mut Serializer::new(&mut buf))?;
+        Ok(buf)
+    }
+
+    /// Compares the repository against an exported manifest.
+    ///
+    /// `other` is a manifest exported by [`export_manifest`], it can be
+    /// exported from this or another repository. Returns all the changes
+    /// from `other` to this repository, sorted by path. An empty vector
+    /// means the repository matches the manifest.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Decode` if `other` is not a valid exported manifest.
+    ///
+    /// [`export_manifest`]: struct.Repo.html#method.export_manifest
+    pub fn diff_manifest(&self, other: &[u8]) -> Result<Vec<ManifestChange>> {
+        let olds: Vec<ExportedEntry> =
+            Deserialize::deserialize(&mut Deserializer::new(other))?;
+        let curr: Vec<ExportedEntry> = Deserialize::deserialize(
+            &mut Deserializer::new(&self.export_manifest()?[..]),
+        )?;
+        Ok(diff_entries(&curr, &olds, |ent| ent.path.as_path()))
+    }
+
+    /// Replicates this repository to another repository.
+    ///
+    /// All files, directories and symbolic links in this repository are
+    /// copied to `dst`, which can be on a different storage. File content
+    /// is streamed through [`VersionReader`] and written to `dst` version by
+    /// version, so version history is preserved as far as the version limit
+    /// of `dst` allows. Modification times of files are preserved as well.
+    ///
+    /// `since` is the manifest returned by a previous replication, see
+    /// [`ReplicationStats::manifest`]. Files not changed since then are
+    /// skipped without reading `dst`, and entries removed since then are
+    /// also removed from `dst`. Without `since`, nothing is removed from
+    /// `dst`.
+    ///
+    /// Files whose current content in `dst` already has the same content
+    /// hash are skipped, and a partially replicated file only gets the
+    /// versions it doesn't have yet. So an interrupted replication can be
+    /// resumed by running it again, already transferred files are not
+    /// transferred twice.
+    ///
+    /// This method walks the whole directory tree, so it takes O(n) time
+    /// where n is the total number of files and directories in the
+    /// repository.
+    ///
+    /// This method is **not** atomic, but replicating each file version is
+    /// atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InUse`] if an entry to be replaced or removed in
+    /// `dst` is being used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// let mut backup =
+    ///     RepoOpener::new().create(true).open("mem://bar", "pwd")?;
+    ///
+    /// repo.create_dir("/dir")?;
+    /// repo.create_file("/dir/file")?.write_once(b"foo")?;
+    /// let stats = repo.replicate_to(&mut backup, None)?;
+    /// assert_eq!(stats.files_transferred(), 1);
+    ///
+    /// // only changes are transferred next time
+    /// repo.create_file("/dir/file2")?.write_once(b"bar")?;
+    /// let stats = repo.replicate_to(&mut backup, Some(stats.manifest().clone()))?;
+    /// assert_eq!(stats.files_transferred(), 1);
+    /// assert_eq!(stats.files_skipped(), 1);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`VersionReader`]: struct.VersionReader.html
+    /// [`ReplicationStats::manifest`]: struct.ReplicationStats.html#method.manifest
+    /// [`Error::InUse`]: enum.Error.html
+    pub fn replicate_to(
+        &self,
+        dst: &mut Repo,
+        since: Option<Manifest>,
+    ) -> Result<ReplicationStats> {
+        let manifest = self.manifest()?;
+        let mut stats = ReplicationStats::default();
+        let olds: HashMap<&Path, &ManifestEntry> = since
+            .as_ref()
+            .map(|since| since.entries.iter().map(|ent| (ent.path(), ent)))
+            .into_iter()
+            .flatten()
+            .collect();
+
+        for ent in manifest.entries.iter() {
+            let path = ent.path();
+
+            // remove entry in destination if its type is different
+            let mut dst_md = match dst.symlink_metadata(path) {
+                Ok(md) => Some(md),
+                Err(ref err) if *err == Error::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            if let Some(md) = dst_md {
+                if md.file_type() != ent.file_type() {
+                    remove_entry(dst, path, md.file_type())?;
+                    stats.entries_removed += 1;
+                    dst_md = None;
+                }
+            }
+
+            match ent.file_type() {
+                FileType::Dir => {
+                    if dst_md.is_none() {
+                        dst.create_dir(path)?;
+                    }
+                }
+                FileType::SymLink => {
+                    let target = self.read_link(path)?;
+                    if dst_md.is_some() {
+                        if dst.read_link(path)? == target {
+                            continue;
+                        }
+                        dst.remove_file(path)?;
+                    }
+                    dst.symlink(target, path)?;
+                }
+                FileType::File => {
+                    // file not changed since last replication
+                    if dst_md.is_some() && olds.get(path) == Some(&ent) {
+                        stats.files_skipped += 1;
+                        continue;
+                    }
+
+                    let (vers, bytes) =
+                        self.replicate_file(dst, ent, dst_md.is_some())?;
+                    if vers > 0 {
+                        stats.files_transferred += 1;
+                        stats.versions_transferred += vers;
+                        stats.bytes_transferred += bytes;
+                    } else {
+                        stats.files_skipped += 1;
+                    }
+                }
+            }
+        }
+
+        // remove entries which are removed since last replication, children
+        // come before their parents in reverse order
+        if let Some(since) = since {
+            for ent in since.entries.iter().rev() {
+                let path = ent.path();
+                if manifest
+                    .entries
+                    .binary_search_by(|e| e.path().cmp(path))
+                    .is_ok()
+                {
+                    continue;
+                }
+                match dst.symlink_metadata(path) {
+                    Ok(md) => {
+                        remove_entry(dst, path, md.file_type())?;
+                        stats.entries_removed += 1;
+                    }
+                    Err(ref err) if *err == Error::NotFound => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        stats.manifest = manifest;
+        Ok(stats)
+    }
+
+    // replicate versions of a file which are not in destination yet, return
+    // the number of versions and bytes transferred
+    fn replicate_file(
+        &self,
+        dst: &mut Repo,
+        ent: &ManifestEntry,
+        exists: bool,
+    ) -> Result<(usize, usize)> {
+        let path = ent.path();
+        let handle = self.fs.open_fnode(path)?;
+        let version_limit =
+            handle.fnode.read().unwrap().get_opts().version_limit;
+        let src = File::new(handle, SeekFrom::Start(0), true, false);
+        let history = src.history()?;
+
+        let mut dst_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .version_limit(version_limit)
+            .open(dst, path)?;
+        dst_file.set_replace(true);
+
+        // versions after the latest one already in destination are to be
+        // transferred, for a new file skip the leading versions same as its
+        // initial empty content
+        let dst_hash = dst_file.history()?.last().map(Version::content_hash);
+        let start = if exists {
+            history
+                .iter()
+                .rposition(|ver| Some(ver.content_hash()) == dst_hash)
+                .map_or(0, |idx| idx + 1)
+        } else {
+            history
+                .iter()
+                .position(|ver| Some(ver.content_hash()) != dst_hash)
+                .unwrap_or(history.len())
+        };
+
+        let mut bytes = 0;
+        for ver in history[start..].iter() {
+            if ver.content_len() == 0 {
+                dst_file.set_len(0)?;
+            } else {
+                let mut rdr = src.version_reader(ver.num())?;
+                dst_file.seek(SeekFrom::Start(0))?;
+                bytes += io::copy(&mut rdr, &mut dst_file)? as usize;
+                dst_file.finish()?;
+            }
+        }
+
+        // preserve modification time
+        if dst_file.metadata()?.modified_at() != ent.modified_at() {
+            dst_file.set_modified(ent.modified_at())?;
+        }
+
+        Ok((history.len() - start, bytes))
+    }
+
+    /// Compacts the repository to reclaim storage space.
+    ///
+    /// Depends on the [`CompactOptions`], this method optionally sweeps
+    /// orphan contents and segments which are not used by any file version,
+    /// then for each file it rewrites the fragmented current version,
+    /// removes versions beyond the limit and shrinks partially used
+    /// segments. Contents of the remaining versions are not changed.
+    ///
+    /// Defragmenting a file adds a new version with the same content, so the
+    /// fragmented version is only reclaimed when it is pruned afterwards.
+    ///
+    /// Segments no longer used by any version are removed when the versions
+    /// are removed, compaction reclaims them by pruning versions. Deferred
+    /// commits are saved to storage after compaction, see
+    /// [`background_commit`].
+    ///
+    /// This method is **not** atomic, the sweep and each file are compacted
+    /// in their own transactions. It returns [`InUse`] error if any file is
+    /// open for writing.
+    ///
+    /// [`CompactOptions`]: struct.CompactOptions.html
+    /// [`background_commit`]: struct.RepoOpener.html#method.background_commit
+    /// [`InUse`]: enum.Error.html
+    pub fn compact(&mut self, opts: CompactOptions) -> Result<CompactReport> {
+        let keep_versions = if opts.prune_versions {
+            Some(
+                opts.keep_versions
+                    .unwrap_or_else(|| self.fs.get_opts().version_limit)
+                    as usize,
+            )
+        } else {
+            None
+        };
+
+        let usage = self.fs.disk_usage()?;
+        let seg_cnt = self.fs.seg_count()?;
+        let (files_touched, versions_pruned, orphan_segs, orphan_len) =
+            self.fs.compact(opts.gc, opts.defrag, keep_versions)?;
+
+        // orphan segments are not counted in disk usage
+        Ok(CompactReport {
+            bytes_reclaimed: usage.saturating_sub(self.fs.disk_usage()?)
+                + orphan_len,
+            segments_reclaimed: seg_cnt.saturating_sub(self.fs.seg_count()?)
+                + orphan_segs,
+            files_touched,
+            versions_pruned,
         })
     }
 
-    /// Reset password for the repository.
+    /// Copies the content of one file to another.
+    ///
+    /// This method will **overwrite** the content of `to`.
+    ///
+    /// `from` and `to` must be absolute paths to regular files.
+    ///
+    /// If `from` and `to` both point to the same file, this method is no-op.
+    ///
+    /// This method is **not** atomic.
+    #[inline]
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.copy(from.as_ref(), to.as_ref(), false)
+    }
+
+    /// Copies the content of one file to another with options.
+    ///
+    /// This is same as [`copy`], except it is configured by `opts`.
+    ///
+    /// This method is **not** atomic.
+    ///
+    /// [`copy`]: struct.Repo.html#method.copy
+    #[inline]
+    pub fn copy_with<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+        opts: CopyOptions,
+    ) -> Result<()> {
+        self.fs
+            .copy(from.as_ref(), to.as_ref(), opts.preserve_times)
+    }
+
+    /// Creates a new file which shares the content of another file.
+    ///
+    /// `from` must be an absolute path to a regular file and `to` must be an
+    /// absolute path which doesn't exist. The new file has the same options
+    /// and current content as `from`, but no history.
+    ///
+    /// No data is read or written, the new file references the same chunks
+    /// as `from`. Both files can be written afterwards independently, writing
+    /// to one of them doesn't change the other.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error in the following situations, but is
+    /// not limited to just these cases:
+    ///
+    /// * `from` is not a regular file
+    /// * `to` already exists
+    /// * parent of `to` doesn't exist or is not a directory
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables, dead_code)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// let mut file = repo.create_file("/foo.txt")?;
+    /// file.write_once(b"Hello, World!")?;
+    ///
+    /// repo.clone_file("/foo.txt", "/bar.txt")?;
+    ///
+    /// let mut content = String::new();
+    /// let mut file = repo.open_file("/bar.txt")?;
+    /// file.read_to_string(&mut content)?;
+    /// assert_eq!(content, "Hello, World!");
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    #[inline]
+    pub fn clone_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.clone_file(from.as_ref(), to.as_ref())
+    }
+
+    /// Copies a directory to another recursively.
+    ///
+    /// This method will **overwrite** the content of files in `to` with
+    /// the files in `from` which have same relative location.
+    ///
+    /// `from` and `to` must be absolute paths to directories.
+    ///
+    /// If `to` is not empty, the entire directory tree of `from` will be
+    /// merged to `to`.
+    ///
+    /// This method will stop if any errors happened.
+    ///
+    /// If `from` and `to` both point to the same directory, this method is
+    /// no-op.
+    ///
+    /// This method is **not** atomic.
+    #[inline]
+    pub fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.copy_dir_all(from.as_ref(), to.as_ref(), false)
+    }
+
+    /// Copies a directory to another recursively with options.
+    ///
+    /// This is same as [`copy_dir_all`], except it is configured by `opts`.
+    /// When times are preserved, they are also preserved for the directories
+    /// and symbolic links in `to`.
+    ///
+    /// This method is **not** atomic.
+    ///
+    /// [`copy_dir_all`]: struct.Repo.html#method.copy_dir_all
+    #[inline]
+    pub fn copy_dir_all_with<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+        opts: CopyOptions,
+    ) -> Result<()> {
+        self.fs
+            .copy_dir_all(from.as_ref(), to.as_ref(), opts.preserve_times)
+    }
+
+    /// Returns what [`copy_dir_all`] would copy, without copying anything.
     ///
-    /// Note: if this method failed due to IO error, super block might be
-    /// damaged. If it is the case, use
-    /// [repair_super_block](struct.Repo.html#method.repair_super_block)
-    /// to restore super block before re-opening the repo.
+    /// Each item is a pair of source and target paths of a file, directory
+    /// or symbolic link under `from`, in the order they would be copied. A
+    /// directory comes before its children. Existing files at the target
+    /// paths would be overwritten.
+    ///
+    /// The directory tree is walked the same way as [`copy_dir_all`] does,
+    /// and the same checks are applied, so this returns the same error
+    /// [`copy_dir_all`] would return before copying starts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use std::path::Path;
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/src/dir")?;
+    /// repo.create_file("/src/dir/file")?;
+    ///
+    /// let plan = repo.plan_copy_dir_all("/src", "/dst")?;
+    /// assert_eq!(plan.len(), 2);
+    /// assert_eq!(plan[1].0, Path::new("/src/dir/file"));
+    /// assert_eq!(plan[1].1, Path::new("/dst/dir/file"));
+    /// assert!(!repo.path_exists("/dst")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`copy_dir_all`]: struct.Repo.html#method.copy_dir_all
+    pub fn plan_copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        from: P,
+        to: Q,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let plan = self.fs.plan_copy_dir_all(from.as_ref(), to.as_ref())?;
+        Ok(plan.into_iter().map(|(from, to, _)| (from, to)).collect())
+    }
+
+    /// Imports a directory from the OS file system to the repository
+    /// recursively, returns the number of files imported.
+    ///
+    /// `os_path` is a directory in the OS file system, `repo_path` must be an
+    /// absolute path. Missing directories in `repo_path` will be created.
+    ///
+    /// This method will **overwrite** the content of files in `repo_path`
+    /// with the files in `os_path` which have same relative location. File
+    /// content is streamed into the repository, so large files are not
+    /// loaded into memory entirely.
+    ///
+    /// Symbolic links and special files, such as sockets and devices, are
+    /// skipped.
+    ///
+    /// This method will stop if any errors happened.
+    ///
+    /// This method is **not** atomic, but importing each file is atomic.
+    pub fn import_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        os_path: P,
+        repo_path: Q,
+    ) -> Result<usize> {
+        let os_path = os_path.as_ref();
+        let repo_path = repo_path.as_ref();
+
+        if !vio::metadata(os_path)?.is_dir() {
+            return Err(Error::NotDir);
+        }
+
+        // create target dir if it doesn't exist
+        match self.fs.resolve(repo_path, true) {
+            Ok(fnode) => {
+                if !fnode.read().unwrap().is_dir() {
+                    return Err(Error::NotDir);
+                }
+            }
+            Err(ref err) if *err == Error::NotFound => {
+                self.fs.create_dir_all(repo_path)?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        let mut cnt = 0;
+        for entry in vio::read_dir(os_path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let name = file_name.to_str().ok_or(Error::InvalidPath)?;
+            let child = repo_path.join(name);
+
+            // file type of directory entry doesn't follow symbolic links
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                cnt += self.import_dir(entry.path(), &child)?;
+            } else if file_type.is_file() {
+                let mut src = vio::File::open(entry.path())?;
+                let mut dst = self.create_file(&child)?;
+                if io::copy(&mut src, &mut dst)? > 0 {
+                    dst.finish()?;
+                }
+                cnt += 1;
+            }
+        }
+
+        Ok(cnt)
+    }
+
+    /// Exports a directory in the repository to the OS file system
+    /// recursively, returns the number of files exported.
+    ///
+    /// `repo_path` must be an absolute path to a directory, `os_path` is a
+    /// directory in the OS file system. Missing directories in `os_path`
+    /// will be created.
+    ///
+    /// This method will **overwrite** the files in `os_path` with the files in
+    /// `repo_path` which have same relative location. Only the current version
+    /// of each file is exported, and its content is streamed out, so large
+    /// files are not loaded into memory entirely.
+    ///
+    /// Symbolic links are skipped.
+    ///
+    /// This method will stop if any errors happened.
+    pub fn export_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        repo_path: P,
+        os_path: Q,
+    ) -> Result<usize> {
+        let repo_path = repo_path.as_ref();
+        let os_path = os_path.as_ref();
+
+        let entries = self.read_dir(repo_path)?;
+        vio::create_dir_all(os_path)?;
+
+        let mut cnt = 0;
+        for entry in entries {
+            let target = os_path.join(entry.file_name());
+            match entry.metadata().file_type() {
+                FileType::Dir => {
+                    cnt += self.export_dir(entry.path(), &target)?;
+                }
+                FileType::File => {
+                    let handle = self.fs.open_fnode(entry.path())?;
+                    let mut src =
+                        File::new(handle, SeekFrom::Start(0), true, false);
+                    let mut dst = vio::File::create(&target)?;
+                    io::copy(&mut src, &mut dst)?;
+                    cnt += 1;
+                }
+                FileType::SymLink => {}
+            }
+        }
+
+        Ok(cnt)
+    }
+
+    /// Removes a regular file or symbolic link from the repository.
+    ///
+    /// Symbolic link itself is removed, not its target.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
     #[inline]
-    pub fn reset_password(
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs.remove_file(path.as_ref())
+    }
+
+    /// Remove an existing empty directory.
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is atomic.
+    #[inline]
+    pub fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs.remove_dir(path.as_ref())
+    }
+
+    /// Removes a directory at this path, after removing all its children.
+    /// Use carefully!
+    ///
+    /// `path` must be an absolute path.
+    ///
+    /// This method is **not** atomic in whole, but removing each entry is
+    /// atomic.
+    #[inline]
+    pub fn remove_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.fs.remove_dir_all(path.as_ref())
+    }
+
+    /// Returns paths [`remove_dir_all`] would remove, without removing
+    /// anything.
+    ///
+    /// Paths are in the order they would be removed, which is children
+    /// before their parent directory, and `path` itself is the last one. The
+    /// root directory is never removed, so it is not included.
+    ///
+    /// The directory tree is walked the same way as [`remove_dir_all`]
+    /// does, so the applications can show the paths for confirmation before
+    /// removing them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use std::path::PathBuf;
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/dir/sub")?;
+    /// repo.create_file("/dir/sub/file")?;
+    ///
+    /// let plan = repo.plan_remove_dir_all("/dir")?;
+    /// assert_eq!(
+    ///     plan,
+    ///     vec![
+    ///         PathBuf::from("/dir/sub/file"),
+    ///         PathBuf::from("/dir/sub"),
+    ///         PathBuf::from("/dir"),
+    ///     ]
+    /// );
+    /// assert!(repo.path_exists("/dir/sub/file")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`remove_dir_all`]: struct.Repo.html#method.remove_dir_all
+    pub fn plan_remove_dir_all<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Vec<PathBuf>> {
+        let plan = self.fs.plan_remove_dir_all(path.as_ref())?;
+        Ok(plan.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Sets or clears the append-only flag of a regular file.
+    ///
+    /// An append-only file can only be extended. Truncating it, overwriting
+    /// its existing content, replacing it by [`copy`] or [`rename`], or
+    /// removing it will return [`Error::AppendOnly`] error. The flag must be
+    /// cleared before doing any of those operations.
+    ///
+    /// `path` must be an absolute path to a regular file.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`copy`]: struct.Repo.html#method.copy
+    /// [`rename`]: struct.Repo.html#method.rename
+    /// [`Error::AppendOnly`]: enum.Error.html
+    #[inline]
+    pub fn set_append_only<P: AsRef<Path>>(
         &mut self,
-        old_pwd: &str,
-        new_pwd: &str,
-        ops_limit: OpsLimit,
-        mem_limit: MemLimit,
+        path: P,
+        append_only: bool,
     ) -> Result<()> {
-        let cost = Cost::new(ops_limit, mem_limit);
-        self.fs.reset_password(old_pwd, new_pwd, cost)
+        self.fs.set_append_only(path.as_ref(), append_only)
     }
 
-    /// Repair possibly damaged super block.
+    /// Rename a file or directory to a new name, replacing the original file
+    /// if `to` already exists.
     ///
-    /// This method will try to repair super block using backup. One scenario
-    /// is when [reset_password](struct.Repo.html#method.reset_password) failed
-    /// due to IO error, super block might be damaged. Using this method can
-    /// restore the damaged super block from backup. If super block is all
-    /// good, this method is no-op.
+    /// It can also move a file or directory to a different directory, the
+    /// content is not copied. See [`move_file`] and [`move_all`].
     ///
-    /// This method is not useful for memory-based storage and must be called
-    /// when repo is closed.
+    /// `from` and `to` must be absolute paths.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`move_file`]: struct.Repo.html#method.move_file
+    /// [`move_all`]: struct.Repo.html#method.move_all
     #[inline]
-    pub fn repair_super_block(uri: &str, pwd: &str) -> Result<()> {
-        Fs::repair_super_block(uri, pwd)
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.rename(from.as_ref(), to.as_ref()).map(|_| ())
     }
 
-    /// Returns whether the path points at an existing entity in repository.
+    /// Rename a file or directory to a new name, failing if `to` already
+    /// exists.
     ///
-    /// `path` must be an absolute path.
-    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        Ok(self
-            .fs
-            .resolve(path.as_ref())
-            .map(|_| true)
-            .unwrap_or(false))
+    /// This is same as [`rename`], except it never replaces `to`. It is the
+    /// analogue of the `RENAME_NOREPLACE` flag of Linux `renameat2`. The
+    /// existence check and the rename are done in one transaction, so when
+    /// several threads rename to the same `to`, only one of them will
+    /// succeed. This can be used to implement simple coordination, such as
+    /// lock files.
+    ///
+    /// `from` and `to` must be absolute paths.
+    ///
+    /// This method is atomic.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyExists`] if `to` already exists, including
+    /// when `from` and `to` are the same path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Error, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new()
+    /// #     .create(true)
+    /// #     .open("mem://foo", "pwd")?;
+    /// repo.create_file("/tmp1")?;
+    /// repo.create_file("/tmp2")?;
+    ///
+    /// repo.rename_no_replace("/tmp1", "/lock")?;
+    /// assert_eq!(
+    ///     repo.rename_no_replace("/tmp2", "/lock").unwrap_err(),
+    ///     Error::AlreadyExists
+    /// );
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`rename`]: struct.Repo.html#method.rename
+    /// [`Error::AlreadyExists`]: enum.Error.html
+    #[inline]
+    pub fn rename_no_replace<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.rename_no_replace(from.as_ref(), to.as_ref())
+    }
+
+    /// Moves a regular file to another location.
+    ///
+    /// If `to` is an existing directory, the file is moved into it and keeps
+    /// its name, otherwise this is same as [`rename`], the file at `to` is
+    /// replaced if it exists. The file is moved across directories by
+    /// re-linking it, its content is not copied, and its versions are kept.
+    ///
+    /// `from` and `to` must be absolute paths.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`rename`]: struct.Repo.html#method.rename
+    #[inline]
+    pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        self.fs.move_file(from.as_ref(), to.as_ref())
+    }
+
+    /// Moves the whole directory tree of `from_dir` into `to_dir`.
+    ///
+    /// If `to_dir` doesn't exist, this is same as [`rename`]. Otherwise the
+    /// entries under `from_dir` are merged into `to_dir`: subdirectories
+    /// which exist in both are merged recursively, files in `to_dir` are
+    /// replaced by those at the same relative location in `from_dir`, and
+    /// `from_dir` is removed afterwards.
+    ///
+    /// Unlike [`copy_dir_all`] followed by [`remove_dir_all`], entries are
+    /// re-linked to their new parent directories and no file content is
+    /// copied, so file versions and deduplicated content are kept unchanged
+    /// and no extra space is used.
+    ///
+    /// `from_dir` and `to_dir` must be absolute paths to directories, and
+    /// `to_dir` cannot be inside `from_dir`.
+    ///
+    /// Re-linking all the entries is done in one transaction, so either all
+    /// or none of them are moved. The emptied directories under `from_dir`
+    /// are removed after that.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IsDir`] or [`Error::NotDir`] if a file and a
+    /// directory are at the same relative location, or
+    /// [`Error::AppendOnly`] if a file to be replaced is append-only. Nothing
+    /// is moved in these cases.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![allow(unused_mut, unused_variables)]
+    /// # use zbox::{init_env, Result, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// # let mut repo = RepoOpener::new().create(true).open("mem://foo", "pwd")?;
+    /// repo.create_dir_all("/inbox/2020")?;
+    /// repo.create_file("/inbox/2020/a.txt")?;
+    /// repo.create_dir_all("/archive/2020")?;
+    /// repo.create_file("/archive/2020/b.txt")?;
+    ///
+    /// repo.move_all("/inbox", "/archive")?;
+    /// assert!(!repo.path_exists("/inbox")?);
+    /// assert!(repo.is_file("/archive/2020/a.txt")?);
+    /// assert!(repo.is_file("/archive/2020/b.txt")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`rename`]: struct.Repo.html#method.rename
+    /// [`copy_dir_all`]: struct.Repo.html#method.copy_dir_all
+    /// [`remove_dir_all`]: struct.Repo.html#method.remove_dir_all
+    /// [`Error::IsDir`]: enum.Error.html
+    /// [`Error::NotDir`]: enum.Error.html
+    /// [`Error::AppendOnly`]: enum.Error.html
+    #[inline]
+    pub fn move_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from_dir: P,
+        to_dir: Q,
+    ) -> Result<()> {
+        self.fs.move_all(from_dir.as_ref(), to_dir.as_ref())
+    }
+
+    /// Exchange two files or directories.
+    ///
+    /// The entries at paths `a` and `b` are swapped, so that `a` refers to
+    /// what `b` referred to before and vice versa. It is useful for
+    /// double-buffered files, readers opening either path always see a
+    /// complete file. Both `a` and `b` must exist, and one cannot be an
+    /// ancestor of the other.
+    ///
+    /// `a` and `b` must be absolute paths.
+    ///
+    /// This method is atomic.
+    #[inline]
+    pub fn swap<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        a: P,
+        b: Q,
+    ) -> Result<()> {
+        self.fs.swap(a.as_ref(), b.as_ref())
+    }
+
+    /// Exchange two files or directories.
+    ///
+    /// This is same as [`swap`], named after the `RENAME_EXCHANGE` flag of
+    /// Linux `renameat2`. Both `a` and `b` must exist, otherwise
+    /// [`Error::NotFound`] is returned. Their parent directories are not
+    /// created.
+    ///
+    /// `a` and `b` must be absolute paths.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`swap`]: struct.Repo.html#method.swap
+    /// [`Error::NotFound`]: enum.Error.html
+    #[inline]
+    pub fn exchange<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        a: P,
+        b: Q,
+    ) -> Result<()> {
+        self.fs.swap(a.as_ref(), b.as_ref())
+    }
+
+    /// Rename a file or directory to a new name, replacing the original file
+    /// if `to` already exists, and return metadata of the replaced one.
+    ///
+    /// This is same as [`rename`], except it returns the metadata of `to`
+    /// before it is replaced, or `None` if `to` didn't exist. Content of the
+    /// replaced file is discarded, so it should be archived before calling
+    /// this method if needed.
+    ///
+    /// `from` and `to` must be absolute paths.
+    ///
+    /// This method is atomic.
+    ///
+    /// [`rename`]: struct.Repo.html#method.rename
+    #[inline]
+    pub fn rename_returning<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> Result<Option<Metadata>> {
+        self.fs.rename(from.as_ref(), to.as_ref())
+    }
+
+    /// Persists all deferred commits to the underlying storage.
+    ///
+    /// This method is only useful when the repository is opened with
+    /// [`background_commit`] enabled, otherwise every transaction is already
+    /// persisted when it is committed and this method is no-op.
+    ///
+    /// [`background_commit`]: struct.RepoOpener.html#method.background_commit
+    #[inline]
+    pub fn sync(&mut self) -> Result<()> {
+        self.fs.sync()
     }
 
-    /// Returns whether the path exists in repository and is pointing at
-    /// a regular file.
+    /// Flushes all pending writes to the underlying storage.
     ///
-    /// `path` must be an absolute path.
-    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        match self.fs.resolve(path.as_ref()) {
-            Ok(fnode_ref) => {
-                let fnode = fnode_ref.read().unwrap();
-                Ok(fnode.is_file())
-            }
-            Err(_) => Ok(false),
-        }
+    /// This saves all deferred commits, like [`sync`] does, and then asks the
+    /// underlying storage to flush its buffered writes. It can be used to
+    /// checkpoint a long-running process at safe points instead of relying
+    /// on the repository being dropped.
+    ///
+    /// This method does not release the repository lock, the repository
+    /// stays open and usable after flushing.
+    ///
+    /// This method is no-op if the repository is opened in read-only mode.
+    ///
+    /// [`sync`]: struct.Repo.html#method.sync
+    #[inline]
+    pub fn flush(&mut self) -> Result<()> {
+        self.fs.flush()
     }
 
-    /// Returns whether the path exists in repository and is pointing at
-    /// a directory.
+    /// Returns whether there are writes not yet flushed to the underlying
+    /// storage.
     ///
-    /// `path` must be an absolute path.
-    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
-        match self.fs.resolve(path.as_ref()) {
-            Ok(fnode_ref) => {
-                let fnode = fnode_ref.read().unwrap();
-                Ok(fnode.is_dir())
-            }
-            Err(_) => Ok(false),
-        }
+    /// This includes transactions in progress, deferred commits and
+    /// buffered writes in the underlying storage. Call [`flush`] to persist
+    /// them.
+    ///
+    /// [`flush`]: struct.Repo.html#method.flush
+    #[inline]
+    pub fn has_pending_writes(&self) -> bool {
+        self.fs.has_pending_writes()
     }
 
-    /// Create a file in read-write mode.
+    /// Takes a snapshot of a memory-backed repository.
     ///
-    /// This method will create a file if it does not exist, and will
-    /// truncate it if it does. See the
-    /// [`OpenOptions::open`](struct.OpenOptions.html#method.open) method for
-    /// more details.
+    /// The whole underlying memory storage, including super blocks, WAL,
+    /// addresses and data blocks, is dumped into an opaque byte blob. The
+    /// blob is encrypted exactly as the storage, so the password is still
+    /// needed to open it with [`from_snapshot`]. Deferred commits are saved
+    /// before the snapshot is taken.
     ///
-    /// `path` must be an absolute path.
+    /// Returns [`Error::InvalidArgument`] if the repository is not stored in
+    /// memory storage.
     ///
-    /// This method is **not** atomic.
+    /// # Examples
+    ///
+    /// ```
+    /// # use zbox::{init_env, Result, Repo, RepoOpener};
+    /// # fn foo() -> Result<()> {
+    /// # init_env();
+    /// let mut repo = RepoOpener::new()
+    ///     .create(true)
+    ///     .open("mem://fixture", "pwd")?;
+    /// repo.create_dir("/dir")?;
+    ///
+    /// // each restored repository is independent of others
+    /// let snapshot = repo.snapshot()?;
+    /// let mut repo2 = Repo::from_snapshot(&snapshot, "pwd")?;
+    /// repo2.remove_dir("/dir")?;
+    /// assert!(repo.is_dir("/dir")?);
+    /// # Ok(())
+    /// # }
+    /// # foo().unwrap();
+    /// ```
+    ///
+    /// [`from_snapshot`]: struct.Repo.html#method.from_snapshot
+    /// [`Error::InvalidArgument`]: enum.Error.html
     #[inline]
-    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
-        OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .open(self, path)
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        self.fs.snapshot()
     }
 
-    /// Attempts to open a file in read-only mode.
+    /// Takes a read-only snapshot of the repository at this point in time.
     ///
-    /// `path` must be an absolute path.
+    /// The returned [`SnapshotRepo`] sees all files and directories as they
+    /// were when this method is called, changes made to the repository
+    /// afterwards, by this or other threads, are not visible through it. It
+    /// can be used to take a consistent backup while the repository is
+    /// still being written.
     ///
-    /// See the [`OpenOptions::open`] method for more details.
+    /// Taking a snapshot doesn't read anything, metadata and content are
+    /// read from the repository on demand. Only the metadata changed after
+    /// the snapshot is taken is copied in memory, at the time it is
+    /// changed. Content removed or overwritten after the snapshot is taken
+    /// is kept until the snapshot is dropped, so its space is only
+    /// reclaimed by the first transaction committed after that, or after
+    /// the repository is opened again if it was closed while the snapshot
+    /// is alive.
     ///
-    /// # Errors
-    /// This method will return an error if path does not already exist.
-    /// Other errors may also be returned according to [`OpenOptions::open`].
+    /// The snapshot cannot be read after the repository is closed.
     ///
     /// # Examples
     ///
     /// ```
     /// # #![allow(unused_mut, unused_variables, dead_code)]
     /// # use zbox::{init_env, Result, RepoOpener};
+    /// use std::io::Read;
     /// # fn foo() -> Result<()> {
     /// # init_env();
     /// # let mut repo = RepoOpener::new()
     /// #     .create(true)
     /// #     .open("mem://foo", "pwd")?;
-    /// let mut f = repo.open_file("foo.txt")?;
+    /// repo.create_file("/foo.txt")?.write_once(b"Hello, World!")?;
+    ///
+    /// let snapshot = repo.snapshot_handle()?;
+    /// repo.remove_file("/foo.txt")?;
+    ///
+    /// let mut content = String::new();
+    /// let mut file = snapshot.open_file_shared("/foo.txt")?;
+    /// file.read_to_string(&mut content)?;
+    /// assert_eq!(content, "Hello, World!");
     /// # Ok(())
     /// # }
+    /// # foo().unwrap();
     /// ```
     ///
-    /// [`OpenOptions::open`]: struct.OpenOptions.html#method.open
+    /// [`SnapshotRepo`]: struct.SnapshotRepo.html
     #[inline]
-    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
-        OpenOptions::new().open(self, path)
+    pub fn snapshot_handle(&self) -> Result<SnapshotRepo> {
+        let frozen = self.fs.freeze()?;
+        Ok(SnapshotRepo { frozen })
     }
 
-    /// Creates a new, empty directory at the specified path.
+    /// Opens a repository from a snapshot taken by [`snapshot`].
     ///
-    /// `path` must be an absolute path.
+    /// The snapshot is loaded into a fresh memory storage with a random
+    /// location, which can be found from the repository's [`info`]. The
+    /// repository is opened in read-write mode and `pwd` must be the
+    /// password of the repository when the snapshot was taken.
     ///
-    /// This method is atomic.
+    /// [`snapshot`]: struct.Repo.html#method.snapshot
+    /// [`info`]: struct.Repo.html#method.info
     #[inline]
-    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs
-            .create_fnode(path.as_ref(), FileType::Dir, Options::default())
-            .map(|_| ())
+    pub fn from_snapshot(snapshot: &[u8], pwd: &str) -> Result<Repo> {
+        let fs = Fs::from_snapshot(snapshot, pwd)?;
+        Ok(Repo { fs })
     }
 
-    /// Recursively create a directory and all of its parent components if they
-    /// are missing.
+    /// Permanently destroy a repository specified by `uri`.
     ///
-    /// `path` must be an absolute path.
+    /// This will permanently delete all files and directories in a
+    /// repository, together with its underlying storage, such as the
+    /// directory for file storage, the database file for SQLite storage or
+    /// the keys for Redis storage. Use it with caution.
     ///
-    /// This method is **not** atomic in whole, but creating each entry is
-    /// atomic.
+    /// The repository must be closed, otherwise [`Error::InUse`] is
+    /// returned. If a repository was not closed properly, open it with
+    /// [`force`] and close it before destroying it.
+    ///
+    /// [`Error::InUse`]: enum.Error.html
+    /// [`force`]: struct.RepoOpener.html#method.force
     #[inline]
-    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.create_dir_all(path.as_ref())
+    pub fn destroy(uri: &str) -> Result<()> {
+        Fs::destroy(uri)
     }
+}
 
-    /// Returns a vector of all the entries within a directory.
-    ///
-    /// `path` must be an absolute path.
+impl Debug for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Repo").finish()
+    }
+}
+
+/// A repository handle used in a transaction.
+///
+/// This is given to the closure run by [`Repo::transaction`], operations run
+/// through it are done in the same transaction. It provides the `Repo`
+/// methods which can be run in a transaction, see the `Repo` method with the
+/// same name for details.
+///
+/// [`Repo::transaction`]: struct.Repo.html#method.transaction
+pub struct TxnRepo<'a> {
+    repo: &'a mut Repo,
+
+    // if any operation changing the repo failed
+    failed: bool,
+}
+
+impl<'a> TxnRepo<'a> {
+    // mark the transaction as failed if the operation failed
     #[inline]
-    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
-        self.fs.read_dir(path.as_ref())
+    fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.failed = true;
+        }
+        result
     }
 
-    /// Get the metadata about a file or directory at specified path.
-    ///
-    /// `path` must be an absolute path.
+    /// See [`Repo::path_exists`](struct.Repo.html#method.path_exists).
+    #[inline]
+    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        self.repo.path_exists(path)
+    }
+
+    /// See [`Repo::is_file`](struct.Repo.html#method.is_file).
+    #[inline]
+    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        self.repo.is_file(path)
+    }
+
+    /// See [`Repo::is_dir`](struct.Repo.html#method.is_dir).
+    #[inline]
+    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        self.repo.is_dir(path)
+    }
+
+    /// See [`Repo::metadata`](struct.Repo.html#method.metadata).
     #[inline]
     pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
-        self.fs.metadata(path.as_ref())
+        self.repo.metadata(path)
     }
 
-    /// Return a vector of history versions of a regular file at specified path.
-    ///
-    /// `path` must be an absolute path to a regular file.
+    /// See [`Repo::read_dir`](struct.Repo.html#method.read_dir).
     #[inline]
-    pub fn history<P: AsRef<Path>>(&self, path: P) -> Result<Vec<Version>> {
-        self.fs.history(path.as_ref())
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        self.repo.read_dir(path)
     }
 
-    /// Copies the content of one file to another.
-    ///
-    /// This method will **overwrite** the content of `to`.
-    ///
-    /// `from` and `to` must be absolute paths to regular files.
-    ///
-    /// If `from` and `to` both point to the same file, this method is no-op.
+    /// See [`Repo::create_file`](struct.Repo.html#method.create_file).
+    #[inline]
+    pub fn create_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        let result = self.repo.create_file(path);
+        self.check(result)
+    }
+
+    /// See [`Repo::open_file`](struct.Repo.html#method.open_file).
+    #[inline]
+    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> Result<File> {
+        self.repo.open_file(path)
+    }
+
+    /// Opens a file with the options specified by `options`.
     ///
-    /// This method is **not** atomic.
+    /// See [`OpenOptions::open`](struct.OpenOptions.html#method.open).
     #[inline]
-    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
+    pub fn open_file_with<P: AsRef<Path>>(
         &mut self,
-        from: P,
-        to: Q,
-    ) -> Result<()> {
-        self.fs.copy(from.as_ref(), to.as_ref())
+        path: P,
+        options: &OpenOptions,
+    ) -> Result<File> {
+        let result = options.open(self.repo, path);
+        self.check(result)
     }
 
-    /// Copies a directory to another recursively.
-    ///
-    /// This method will **overwrite** the content of files in `to` with
-    /// the files in `from` which have same relative location.
-    ///
-    /// `from` and `to` must be absolute paths to directories.
-    ///
-    /// If `to` is not empty, the entire directory tree of `from` will be
-    /// merged to `to`.
-    ///
-    /// This method will stop if any errors happened.
-    ///
-    /// If `from` and `to` both point to the same directory, this method is
-    /// no-op.
-    ///
-    /// This method is **not** atomic.
+    /// See [`Repo::create_dir`](struct.Repo.html#method.create_dir).
     #[inline]
-    pub fn copy_dir_all<P: AsRef<Path>, Q: AsRef<Path>>(
+    pub fn create_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let result = self.repo.create_dir(path);
+        self.check(result)
+    }
+
+    /// See [`Repo::create_dir_all`](struct.Repo.html#method.create_dir_all).
+    #[inline]
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let result = self.repo.create_dir_all(path);
+        self.check(result)
+    }
+
+    /// See [`Repo::copy`](struct.Repo.html#method.copy).
+    #[inline]
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(
         &mut self,
         from: P,
         to: Q,
     ) -> Result<()> {
-        self.fs.copy_dir_all(from.as_ref(), to.as_ref())
+        let result = self.repo.copy(from, to);
+        self.check(result)
     }
 
-    /// Removes a regular file from the repository.
-    ///
-    /// `path` must be an absolute path.
-    ///
-    /// This method is atomic.
+    /// See [`Repo::remove_file`](struct.Repo.html#method.remove_file).
     #[inline]
     pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_file(path.as_ref())
+        let result = self.repo.remove_file(path);
+        self.check(result)
     }
 
-    /// Remove an existing empty directory.
-    ///
-    /// `path` must be an absolute path.
-    ///
-    /// This method is atomic.
+    /// See [`Repo::remove_dir`](struct.Repo.html#method.remove_dir).
     #[inline]
     pub fn remove_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_dir(path.as_ref())
+        let result = self.repo.remove_dir(path);
+        self.check(result)
     }
 
-    /// Removes a directory at this path, after removing all its children.
-    /// Use carefully!
-    ///
-    /// `path` must be an absolute path.
-    ///
-    /// This method is **not** atomic in whole, but removing each entry is
-    /// atomic.
+    /// See [`Repo::remove_dir_all`](struct.Repo.html#method.remove_dir_all).
     #[inline]
     pub fn remove_dir_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        self.fs.remove_dir_all(path.as_ref())
+        let result = self.repo.remove_dir_all(path);
+        self.check(result)
     }
 
-    /// Rename a file or directory to a new name, replacing the original file
-    /// if `to` already exists.
-    ///
-    /// `from` and `to` must be absolute paths.
-    ///
-    /// This method is atomic.
+    /// See [`Repo::rename`](struct.Repo.html#method.rename).
     #[inline]
     pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
         &mut self,
         from: P,
         to: Q,
     ) -> Result<()> {
-        self.fs.rename(from.as_ref(), to.as_ref())
+        let result = self.repo.rename(from, to);
+        self.check(result)
     }
+}
 
-    /// Permanently destroy a repository specified by `uri`.
+impl<'a> Debug for TxnRepo<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TxnRepo").finish()
+    }
+}
+
+/// A read-only snapshot of a repository.
+///
+/// It is taken by [`Repo::snapshot_handle`] and provides the `Repo` reading
+/// methods, see the `Repo` method with the same name for details. All of
+/// them see the repository as it was when the snapshot was taken.
+///
+/// Files bound to associated data cannot be read from a snapshot.
+///
+/// [`Repo::snapshot_handle`]: struct.Repo.html#method.snapshot_handle
+#[derive(Debug)]
+pub struct SnapshotRepo {
+    frozen: FrozenFs,
+}
+
+impl SnapshotRepo {
+    /// See [`Repo::path_exists`](struct.Repo.html#method.path_exists).
+    #[inline]
+    pub fn path_exists<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(self.frozen.resolve(path.as_ref(), true).is_ok())
+    }
+
+    /// See [`Repo::is_file`](struct.Repo.html#method.is_file).
+    #[inline]
+    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(self
+            .frozen
+            .metadata(path.as_ref(), true)
+            .map(|md| md.is_file())
+            .unwrap_or(false))
+    }
+
+    /// See [`Repo::is_dir`](struct.Repo.html#method.is_dir).
+    #[inline]
+    pub fn is_dir<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        Ok(self
+            .frozen
+            .metadata(path.as_ref(), true)
+            .map(|md| md.is_dir())
+            .unwrap_or(false))
+    }
+
+    /// See [`Repo::metadata`](struct.Repo.html#method.metadata).
+    #[inline]
+    pub fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        self.frozen.metadata(path.as_ref(), true)
+    }
+
+    /// See [`Repo::symlink_metadata`](struct.Repo.html#method.symlink_metadata).
+    #[inline]
+    pub fn symlink_metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Metadata> {
+        self.frozen.metadata(path.as_ref(), false)
+    }
+
+    /// See [`Repo::read_dir`](struct.Repo.html#method.read_dir).
+    #[inline]
+    pub fn read_dir<P: AsRef<Path>>(&self, path: P) -> Result<Vec<DirEntry>> {
+        self.frozen.read_dir(path.as_ref())
+    }
+
+    /// See [`Repo::read_link`](struct.Repo.html#method.read_link).
+    #[inline]
+    pub fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        self.frozen.read_link(path.as_ref())
+    }
+
+    /// Opens a file for reading.
+    ///
+    /// This is same as [`Repo::open_file_shared`], except the returned
+    /// reader reads the file content when the snapshot was taken.
     ///
-    /// This will permanently delete all files and directories in a repository
-    /// regardless it is opened or not. Use it with caution.
+    /// [`Repo::open_file_shared`]: struct.Repo.html#method.open_file_shared
     #[inline]
-    pub fn destroy(uri: &str) -> Result<()> {
-        Fs::destroy(uri)
+    pub fn open_file_shared<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<SnapshotFile> {
+        let (meta, rdr) = self.frozen.open(path.as_ref())?;
+        Ok(SnapshotFile::new(meta, rdr))
     }
 }
 
-impl Debug for Repo {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Repo").finish()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::init_env;
+
+    #[test]
+    fn compact_sweep_orphans() {
+        init_env();
+
+        for &dedup in [false, true].iter() {
+            let uri =
+                format!("mem://compact_sweep_orphans_{}?max_size=64mb", dedup);
+            let mut repo = RepoOpener::new()
+                .create(true)
+                .dedup_file(dedup)
+                .open(&uri, "pwd")
+                .unwrap();
+            let avail = repo.space_usage().unwrap().available().unwrap();
+
+            // pseudo random data, so it is not compressible
+            let mut seed = 11u32;
+            let data: Vec<u8> = (0..300 * 1024)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (seed >> 16) as u8
+                })
+                .collect();
+            let mut f = repo.create_file("/keep").unwrap();
+            f.write_once(&[1u8; 1000]).unwrap();
+            drop(f);
+            let mut f = repo.create_file("/file").unwrap();
+            f.write_once(&data).unwrap();
+            drop(f);
+
+            // content reference leaked by a version makes the content and
+            // its segments orphans after the file is removed
+            repo.fs.leak_content(Path::new("/file")).unwrap();
+            repo.remove_file("/file").unwrap();
+            repo.create_dir("/dir").unwrap();
+            repo.create_dir("/dir2").unwrap();
+            let avail2 = repo.space_usage().unwrap().available().unwrap();
+            assert!(avail2 + data.len() / 2 < avail);
+
+            // only sweep orphans
+            let mut opts = CompactOptions::new();
+            opts.defrag(false).prune_versions(false);
+            let report = repo.compact(opts).unwrap();
+            assert!(report.segments_reclaimed() > 0);
+            assert!(report.bytes_reclaimed() >= data.len());
+            assert_eq!(report.files_touched(), 0);
+            repo.create_dir("/dir3").unwrap();
+            repo.create_dir("/dir4").unwrap();
+            let avail3 = repo.space_usage().unwrap().available().unwrap();
+            assert!(avail3 + data.len() / 2 > avail);
+
+            // nothing is left to sweep after reopen
+            drop(repo);
+            let mut repo = RepoOpener::new().open(&uri, "pwd").unwrap();
+            let report = repo.compact(CompactOptions::default()).unwrap();
+            assert_eq!(report.segments_reclaimed(), 0);
+            let mut buf = Vec::new();
+            let mut f = repo.open_file("/keep").unwrap();
+            f.read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, vec![1u8; 1000]);
+        }
     }
 }
@@ -6,6 +6,7 @@ use std::sync::{Arc, RwLock, Weak};
 
 use serde::{Deserialize, Serialize};
 
+use super::frozen::FrozenEnt;
 use super::trans::{Action, Transable};
 use super::{Eid, EntityType, Id, TxMgrRef, Txid};
 use crate::base::lru::{CountMeter, Lru, Pinnable};
@@ -20,6 +21,12 @@ pub trait Cowable: Debug + Default + Clone + Send + Sync {
     }
 
     fn on_complete_commit(&mut self) {}
+
+    /// Copy kept for frozen entities before it is updated, entity which is
+    /// not read through frozen entities returns None
+    fn frozen_copy(&self) -> Option<Self> {
+        None
+    }
 }
 
 /// Copy-on-write wrapper
@@ -146,6 +153,13 @@ where
         Ok(self.other_inner_mut())
     }
 
+    /// Get inner object of the last commit, changes made by transaction in
+    /// progress are not visible
+    #[inline]
+    pub fn committed(&self) -> &T {
+        self.inner()
+    }
+
     /// Get mutable reference of inner object without adding the cow to
     /// transaction
     #[inline]
@@ -153,6 +167,20 @@ where
         self.inner_mut()
     }
 
+    /// Get mutable reference of inner object seen by current transaction,
+    /// without adding the cow to transaction
+    pub fn make_mut_naive_in_trans(&mut self) -> &mut T {
+        let curr_txid = Txid::current_or_empty();
+        if self.txid.is_none()
+            || self.txid != Some(curr_txid)
+            || self.action == Some(Action::New)
+        {
+            self.inner_mut()
+        } else {
+            self.other_inner_mut()
+        }
+    }
+
     /// Mark cow as deleted
     #[inline]
     pub fn make_del(&mut self, txmgr: &TxMgrRef) -> Result<()> {
@@ -398,6 +426,12 @@ where
         }
     }
 
+    fn frozen_copy(&self) -> Option<FrozenEnt> {
+        self.inner()
+            .frozen_copy()
+            .map(|inner| Arc::new(inner) as FrozenEnt)
+    }
+
     fn complete_commit(&mut self) {
         match self.action {
             Some(action) => {
@@ -416,8 +450,9 @@ where
     fn abort(&mut self) {
         match self.action {
             Some(action) => {
-                if let Action::Update = action {
-                    // discard the new inner object
+                // discard the new inner object, a deleted cow might also be
+                // updated before it is deleted
+                if let Action::Update | Action::Delete = action {
                     self.other_mut().take();
                 }
             }
@@ -0,0 +1,75 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex, Weak};
+
+use serde::{Deserialize, Serialize};
+
+use super::cow::{CowRef, Cowable};
+use super::{Eid, Id};
+
+/// Frozen copy of an entity
+pub type FrozenEnt = Arc<dyn Any + Send + Sync>;
+
+/// Entities frozen at a point in time
+///
+/// It keeps the committed copy of an entity before the entity is first
+/// updated after that point, so the entity can still be read as it was at
+/// that time. Entities not updated since then are read from their cows
+/// directly.
+#[derive(Default)]
+pub struct FrozenEnts {
+    ents: Mutex<HashMap<Eid, FrozenEnt>>,
+}
+
+impl FrozenEnts {
+    // keep the entity copy if it is not kept yet
+    pub(super) fn keep<F>(&self, id: &Eid, copy: F)
+    where
+        F: FnOnce() -> Option<FrozenEnt>,
+    {
+        let mut ents = self.ents.lock().unwrap();
+        if !ents.contains_key(id) {
+            if let Some(ent) = copy() {
+                ents.insert(id.clone(), ent);
+            }
+        }
+    }
+
+    /// Get frozen copy of an entity if it has been updated since frozen
+    pub fn get<T: Any + Send + Sync>(&self, id: &Eid) -> Option<Arc<T>> {
+        let ents = self.ents.lock().unwrap();
+        ents.get(id)
+            .cloned()
+            .and_then(|ent| ent.downcast::<T>().ok())
+    }
+
+    /// Run a function with the entity as it was when frozen
+    pub fn with<'de, T, F, R>(&self, cow_ref: &CowRef<T>, f: F) -> R
+    where
+        T: Cowable + Deserialize<'de> + Serialize + 'static,
+        F: FnOnce(&T) -> R,
+    {
+        // entity copy is kept while the cow is locked by committing, so
+        // cow must be locked before looking up the copy
+        let cow = cow_ref.read().unwrap();
+        match self.get::<T>(cow.id()) {
+            Some(ent) => f(&ent),
+            None => f(cow.committed()),
+        }
+    }
+}
+
+impl Debug for FrozenEnts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrozenEnts")
+            .field("ents", &self.ents.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Frozen entities reference type
+pub type FrozenEntsRef = Arc<FrozenEnts>;
+
+/// Frozen entities weak reference type
+pub type FrozenEntsWeakRef = Weak<FrozenEnts>;
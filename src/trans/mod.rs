@@ -4,14 +4,16 @@
 
 pub mod cow;
 mod eid;
+mod frozen;
 pub mod trans;
 mod txid;
 mod txmgr;
 mod wal;
 
 pub use self::eid::{Eid, Id};
+pub use self::frozen::FrozenEntsRef;
 pub use self::txid::Txid;
-pub use self::txmgr::{TxHandle, TxMgr, TxMgrRef, TxMgrWeakRef};
+pub use self::txmgr::{TxHandle, TxMgr, TxMgrRef, TxMgrWeakRef, WalPin};
 pub use self::wal::EntityType;
 
 use std::io::Write;
@@ -5,6 +5,7 @@ use linked_hash_map::LinkedHashMap;
 use log::{debug, error};
 use serde::{Deserialize, Serialize};
 
+use super::frozen::{FrozenEnt, FrozenEntsRef};
 use super::wal::Wal;
 use super::{Eid, EntityType, Id, Txid};
 use crate::base::IntoRef;
@@ -23,6 +24,9 @@ pub enum Action {
 pub trait Transable: Debug + Id + Send + Sync {
     fn action(&self) -> Action;
     fn commit(&mut self, vol: &VolumeRef) -> Result<()>;
+    fn frozen_copy(&self) -> Option<FrozenEnt> {
+        None
+    }
     fn complete_commit(&mut self);
     fn abort(&mut self);
 }
@@ -90,7 +94,14 @@ impl Trans {
     }
 
     /// Commit transaction
-    pub fn commit(&mut self, vol: &VolumeRef) -> Result<Wal> {
+    ///
+    /// Entities updated by the transaction are kept in each of `frozen`
+    /// before they are committed.
+    pub fn commit(
+        &mut self,
+        frozen: &[FrozenEntsRef],
+        vol: &VolumeRef,
+    ) -> Result<Wal> {
         debug!("commit tx#{}, cohorts: {}", self.txid, self.cohorts.len());
 
         //dbg!(&self.cohorts);
@@ -115,6 +126,13 @@ impl Trans {
                 }
             }
 
+            // keep entity copy before it is changed
+            if ent.action() == Action::Update {
+                for ents in frozen {
+                    ents.keep(ent.id(), || ent.frozen_copy());
+                }
+            }
+
             // commit entity
             ent.commit(vol)?;
         }
@@ -57,6 +57,25 @@ impl Txid {
         TXID.with(|t| *t.borrow_mut() = 0);
     }
 
+    /// Run a function with current thread transaction ID temporarily set to
+    /// `txid`, the previous one is restored afterwards
+    pub fn run_as<F, R>(txid: Txid, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // restore the previous txid when dropped, even if `f` panics
+        struct Restore(u64);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                TXID.with(|t| *t.borrow_mut() = self.0);
+            }
+        }
+
+        let _restore = Restore(TXID.with(|t| t.replace(txid.0)));
+        f()
+    }
+
     /// Get next txid by increase one
     pub fn next(&mut self) -> Txid {
         self.0 = self.0.checked_add(1).unwrap();
@@ -124,3 +143,24 @@ impl<'de> Deserialize<'de> for Txid {
         deserializer.deserialize_u64(TxidVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn run_as_restore() {
+        Txid::reset_current();
+        let txid = Txid::from(42);
+        assert_eq!(Txid::run_as(txid, Txid::current_or_empty), txid);
+        assert_eq!(Txid::current_or_empty(), Txid::from(0));
+
+        // previous txid is restored even if the function panics
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Txid::run_as(txid, || panic!("run_as panic"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(Txid::current_or_empty(), Txid::from(0));
+    }
+}
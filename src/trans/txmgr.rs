@@ -1,15 +1,21 @@
 use std::collections::HashMap;
 use std::fmt::{self, Debug};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Condvar;
 use std::sync::{Arc, Mutex, RwLock, Weak};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
-use lazy_static::lazy_static;
 use linked_hash_map::LinkedHashMap;
 use log::{debug, warn};
 
+use super::frozen::{FrozenEntsRef, FrozenEntsWeakRef};
 use super::trans::{Action, Trans, TransRef, TransableRef};
 use super::wal::{EntityType, WalQueueMgr};
 use super::{Eid, Txid};
-use crate::base::IntoRef;
+use crate::base::{IntoRef, Time};
 use crate::error::{Error, Result};
 use crate::volume::{Arm, VolumeRef};
 
@@ -25,15 +31,37 @@ pub struct TxMgr {
     // wal queue manager
     walq_mgr: WalQueueMgr,
 
+    // lock for running exclusive transactions
+    excl_lock: Arc<Mutex<()>>,
+
+    // joinable transactions and whether any operation joined it failed
+    joinable: HashMap<Txid, bool>,
+
+    // entities frozen by wal queue pins
+    frozen: Vec<FrozenEntsWeakRef>,
+
+    // background committer
+    #[cfg(not(target_arch = "wasm32"))]
+    committer: Option<Arc<Committer>>,
+
     vol: VolumeRef,
 }
 
 impl TxMgr {
+    // max time a deferred commit can wait before it is saved
+    #[cfg(not(target_arch = "wasm32"))]
+    const COMMIT_WINDOW: Duration = Duration::from_millis(100);
+
     pub fn new(walq_id: &Eid, vol: &VolumeRef) -> Self {
         TxMgr {
             txs: LinkedHashMap::new(),
             ents: HashMap::new(),
             walq_mgr: WalQueueMgr::new(walq_id, vol),
+            excl_lock: Arc::new(Mutex::new(())),
+            joinable: HashMap::new(),
+            frozen: Vec::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            committer: None,
             vol: vol.clone(),
         }
     }
@@ -45,14 +73,131 @@ impl TxMgr {
         Ok(txmgr)
     }
 
+    /// Start background committer
+    ///
+    /// After started, saving wal queue on commit is deferred and commits
+    /// are saved in batches. A batch is saved when it is full, or by a
+    /// background thread when its first commit has waited for the commit
+    /// window, whichever comes first. The thread sleeps while there is no
+    /// deferred commit, and exits after the transaction manager is dropped.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_committer(txmgr: &TxMgrRef) -> Result<()> {
+        let committer = Arc::new(Committer::default());
+        let weak = Arc::downgrade(txmgr);
+        let cmtr = committer.clone();
+        thread::Builder::new()
+            .name("zbox-committer".to_string())
+            .spawn(move || {
+                while cmtr.wait() {
+                    match weak.upgrade() {
+                        Some(txmgr) => {
+                            let mut tm = txmgr.write().unwrap();
+                            if let Err(err) = tm.sync() {
+                                warn!("background commit failed: {}", err);
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            })?;
+
+        let mut tm = txmgr.write().unwrap();
+        tm.walq_mgr.set_defer_commit(true);
+        tm.committer = Some(committer);
+        debug!("background committer started");
+
+        Ok(())
+    }
+
+    /// Save all deferred commits
+    #[inline]
+    pub fn sync(&mut self) -> Result<()> {
+        self.walq_mgr.sync()
+    }
+
+    /// Check if there are deferred commits not saved yet
+    #[inline]
+    pub fn is_unsynced(&self) -> bool {
+        self.walq_mgr.is_unsynced()
+    }
+
+    /// Save wal queue and flush volume, including the block watermark
+    #[inline]
+    pub fn save_walq(&mut self) -> Result<()> {
+        self.walq_mgr.save()
+    }
+
+    /// Collect ids of wal queue and wals, and ids of entities in the wals
+    #[inline]
+    pub fn collect_wal_ids(&self) -> Result<(Vec<Eid>, Vec<Eid>)> {
+        self.walq_mgr.collect_ids()
+    }
+
+    /// Pin wal queue, entities removed by transactions committed while it
+    /// is pinned are not recycled until the returned pin is dropped
+    ///
+    /// Entities updated while it is pinned are also kept in the pin as they
+    /// were when pinned.
+    pub fn pin_wals(txmgr: &TxMgrRef) -> WalPin {
+        let mut tm = txmgr.write().unwrap();
+        let ents = FrozenEntsRef::default();
+        tm.walq_mgr.pin();
+        tm.frozen.push(Arc::downgrade(&ents));
+        WalPin {
+            ents,
+            txmgr: Arc::downgrade(txmgr),
+        }
+    }
+
+    /// Check if wal queue is pinned
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.walq_mgr.is_pinned()
+    }
+
+    /// Check if incomplete transactions were rolled back when opening
+    #[inline]
+    pub fn was_dirty(&self) -> bool {
+        self.walq_mgr.was_dirty()
+    }
+
+    /// Check if there is any transaction in progress
+    #[inline]
+    pub fn has_trans(&self) -> bool {
+        !self.txs.is_empty()
+    }
+
     /// Begin a transaction
+    ///
+    /// If current thread is in a joinable transaction, that transaction is
+    /// joined instead.
     pub fn begin_trans(txmgr: &TxMgrRef) -> Result<TxHandle> {
         // check if current thread is already in transaction
+        if Txid::is_in_trans() {
+            return Self::join_trans(txmgr).ok_or(Error::InTrans);
+        }
+        Self::start_trans(txmgr, false)
+    }
+
+    /// Begin a transaction only if no other transaction is in progress
+    ///
+    /// The check and the beginning are done under the same lock, so no
+    /// other transaction can sneak in between. Return `Error::InUse` if
+    /// there is any transaction in progress.
+    pub fn begin_idle_trans(txmgr: &TxMgrRef) -> Result<TxHandle> {
         if Txid::is_in_trans() {
             return Err(Error::InTrans);
         }
+        Self::start_trans(txmgr, true)
+    }
 
+    // start a new transaction, fail if `idle_only` is true and there is
+    // any transaction in progress
+    fn start_trans(txmgr: &TxMgrRef, idle_only: bool) -> Result<TxHandle> {
         let mut tm = txmgr.write().unwrap();
+        if idle_only && tm.has_trans() {
+            return Err(Error::InUse);
+        }
 
         // try to redo abort tx if any tx failed abortion before,
         tm.walq_mgr.hot_redo_abort()?;
@@ -86,9 +231,42 @@ impl TxMgr {
         Ok(TxHandle {
             txid,
             txmgr: Arc::downgrade(txmgr),
+            joined: false,
         })
     }
 
+    /// Begin a joinable transaction
+    ///
+    /// Transactions begun later on current thread join this one, they
+    /// neither commit nor abort it. The transaction must be ended by
+    /// [`TxHandle::end_joinable`].
+    pub fn begin_joinable_trans(txmgr: &TxMgrRef) -> Result<TxHandle> {
+        // joinable transaction cannot be nested
+        if Txid::is_in_trans() {
+            return Err(Error::InTrans);
+        }
+
+        let tx_handle = Self::begin_trans(txmgr)?;
+        let mut tm = txmgr.write().unwrap();
+        tm.joinable.insert(tx_handle.txid, false);
+        Ok(tx_handle)
+    }
+
+    /// Join the joinable transaction current thread is in, if any
+    pub fn join_trans(txmgr: &TxMgrRef) -> Option<TxHandle> {
+        let txid = Txid::current().ok()?;
+        let tm = txmgr.read().unwrap();
+        if tm.joinable.contains_key(&txid) {
+            Some(TxHandle {
+                txid,
+                txmgr: Arc::downgrade(txmgr),
+                joined: true,
+            })
+        } else {
+            None
+        }
+    }
+
     /// Add entity to transaction
     pub fn add_to_trans(
         &mut self,
@@ -99,6 +277,9 @@ impl TxMgr {
         ent_type: EntityType,
         arm: Arm,
     ) -> Result<()> {
+        // the arm to be written might be the last saved one of the entity
+        self.walq_mgr.sync_entity(id)?;
+
         let cur_txid = self.ents.entry(id.clone()).or_insert(txid);
         if *cur_txid != txid {
             // entity is already in other transaction
@@ -120,27 +301,49 @@ impl TxMgr {
 
     // commit transaction
     fn commit_trans(&mut self, txid: Txid) -> Result<()> {
+        let started = Time::now();
         let result = {
             let tx_ref = self.txs.get(&txid).unwrap().clone();
             let mut tx = tx_ref.write().unwrap();
+            self.frozen.retain(|ents| ents.strong_count() > 0);
+            let frozen: Vec<FrozenEntsRef> =
+                self.frozen.iter().filter_map(Weak::upgrade).collect();
 
             // commit tx, if any errors then abort the tx
             match tx
-                .commit(&self.vol)
+                .commit(&frozen, &self.vol)
                 .and_then(|wal| self.walq_mgr.commit_trans(wal))
             {
                 Ok(_) => {
                     tx.complete_commit();
-                    debug!("tx#{} committed", txid);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        if let Some(ref committer) = self.committer {
+                            if self.walq_mgr.is_unsynced() {
+                                committer.schedule();
+                            }
+                        }
+                    }
+                    log_event!(
+                        debug,
+                        "tx.commit",
+                        txid = txid.val(),
+                        duration = started.elapsed()
+                    );
                     Ok(())
                 }
                 Err(err) => Err(err),
             }
         };
 
-        if result.is_err() {
+        if let Err(ref err) = result {
             // error happened during commit, abort the tx
-            debug!("commit tx failed: {:?}", result);
+            log_event!(
+                debug,
+                "tx.commit_failed",
+                txid = txid.val(),
+                error = err
+            );
             self.abort_trans(txid);
         } else {
             // commit succeed, remove tx from tx manager
@@ -153,7 +356,7 @@ impl TxMgr {
 
     // abort transaction
     fn abort_trans(&mut self, txid: Txid) {
-        debug!("abort tx#{}", txid);
+        let started = Time::now();
 
         {
             let tx_ref = self.txs.get(&txid).unwrap().clone();
@@ -165,8 +368,18 @@ impl TxMgr {
                 .abort(&self.vol)
                 .and_then(|_| self.walq_mgr.end_abort(txid))
             {
-                Ok(_) => debug!("tx#{} aborted", txid),
-                Err(err) => warn!("abort tx#{} failed: {}", txid, err),
+                Ok(_) => log_event!(
+                    debug,
+                    "tx.abort",
+                    txid = txid.val(),
+                    duration = started.elapsed()
+                ),
+                Err(err) => log_event!(
+                    warn,
+                    "tx.abort_failed",
+                    txid = txid.val(),
+                    error = err
+                ),
             }
         }
 
@@ -185,22 +398,92 @@ impl Debug for TxMgr {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for TxMgr {
+    fn drop(&mut self) {
+        if let Some(ref committer) = self.committer {
+            committer.stop();
+        }
+    }
+}
+
 impl IntoRef for TxMgr {}
 
+// background committer state
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct CommitterState {
+    // when the deferred commits should be saved
+    deadline: Option<Instant>,
+    stopped: bool,
+}
+
+// Background committer
+//
+// It wakes up the committer thread when the commit window of deferred
+// commits is due.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Committer {
+    state: Mutex<CommitterState>,
+    cond: Condvar,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Committer {
+    // schedule saving deferred commits if it is not scheduled yet
+    fn schedule(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.deadline.is_none() {
+            state.deadline = Some(Instant::now() + TxMgr::COMMIT_WINDOW);
+            self.cond.notify_one();
+        }
+    }
+
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        self.cond.notify_one();
+    }
+
+    // wait until the scheduled deadline is due, return false if stopped
+    fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return false;
+            }
+            match state.deadline {
+                None => state = self.cond.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.deadline = None;
+                        return true;
+                    }
+                    state = self
+                        .cond
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+}
+
 /// TxMgr reference type
 pub type TxMgrRef = Arc<RwLock<TxMgr>>;
 pub type TxMgrWeakRef = Weak<RwLock<TxMgr>>;
 
-// lock for running exclusive transactions
-lazy_static! {
-    static ref EXCL_TX_LOCK: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
-}
-
 // Transaction handle
 #[derive(Debug, Default, Clone)]
 pub struct TxHandle {
     pub txid: Txid,
     pub txmgr: TxMgrWeakRef,
+
+    // if this handle joined a joinable transaction
+    joined: bool,
 }
 
 impl TxHandle {
@@ -234,42 +517,122 @@ impl TxHandle {
     where
         F: FnOnce() -> Result<()>,
     {
-        let _lock = EXCL_TX_LOCK.lock().unwrap();
+        // exclusive transactions are serialised within a repo only
+        let excl_lock = self
+            .txmgr
+            .upgrade()
+            .map(|txmgr| txmgr.read().unwrap().excl_lock.clone())
+            .unwrap_or_default();
+        let _lock = excl_lock.lock().unwrap();
         self.run_all(oper)
     }
 
     /// Commit a transaction
+    ///
+    /// A joined transaction is committed when the joinable transaction
+    /// ends, so this does nothing for it.
     #[inline]
     pub fn commit(&self) -> Result<()> {
+        if self.joined {
+            return Ok(());
+        }
         let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
         let mut tm = txmgr.write().unwrap();
         tm.commit_trans(self.txid)
     }
 
     /// Abort a transaction
+    ///
+    /// A joined transaction is only marked as failed, it will be aborted
+    /// when the joinable transaction ends.
     fn abort(&self, err: Error) -> Result<()> {
         let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
         let mut tm = txmgr.write().unwrap();
 
         debug!("run tx failed: {:?}", err);
-        tm.abort_trans(self.txid);
+        if self.joined {
+            if let Some(failed) = tm.joinable.get_mut(&self.txid) {
+                *failed = true;
+            }
+        } else {
+            tm.abort_trans(self.txid);
+        }
 
         // return the original error
         Err(err)
     }
+
+    /// End a joinable transaction
+    ///
+    /// The transaction is committed if `result` is ok and no operations
+    /// joined it failed, otherwise it is aborted. If an operation failed but
+    /// `result` is ok, [`Error::Uncompleted`] is returned.
+    pub fn end_joinable<T>(&self, result: Result<T>) -> Result<T> {
+        let txmgr = self.txmgr.upgrade().ok_or(Error::RepoClosed)?;
+
+        // commit exclusively as other exclusive transactions do
+        let excl_lock = txmgr.read().unwrap().excl_lock.clone();
+        let _lock = excl_lock.lock().unwrap();
+
+        let mut tm = txmgr.write().unwrap();
+        let failed = tm.joinable.remove(&self.txid).unwrap_or_default();
+        match result {
+            Ok(_) if failed => {
+                tm.abort_trans(self.txid);
+                Err(Error::Uncompleted)
+            }
+            Ok(ret) => tm.commit_trans(self.txid).map(|_| ret),
+            Err(err) => {
+                debug!("run joinable tx failed: {:?}", err);
+                tm.abort_trans(self.txid);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Wal queue pin
+///
+/// The pin is released when it is dropped, entities held by it are then
+/// recycled.
+#[derive(Debug)]
+pub struct WalPin {
+    ents: FrozenEntsRef,
+    txmgr: TxMgrWeakRef,
+}
+
+impl WalPin {
+    /// Get entities frozen when pinned
+    #[inline]
+    pub fn ents(&self) -> &FrozenEntsRef {
+        &self.ents
+    }
+}
+
+impl Drop for WalPin {
+    fn drop(&mut self) {
+        if let Some(txmgr) = self.txmgr.upgrade() {
+            let mut tm = txmgr.write().unwrap();
+            if let Err(err) = tm.walq_mgr.unpin() {
+                warn!("recycle held txs failed: {}", err);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde::{Deserialize, Serialize};
+    use std::sync::mpsc;
+    use std::thread;
     #[cfg(feature = "storage-file")]
     use tempdir::TempDir;
 
     use crate::base::init_env;
     use crate::fs::Config;
-    use crate::trans::cow::{CowRef, Cowable, IntoCow};
-    use crate::trans::TxMgr;
+    use crate::trans::cow::{Cow, CowRef, Cowable, IntoCow};
+    use crate::trans::{Id, TxMgr};
     use crate::volume::{ArmAccess, Volume};
 
     fn setup_mem_vol(loc: &str) -> VolumeRef {
@@ -450,6 +813,148 @@ mod tests {
         .unwrap();
     }
 
+    fn trans_deferred(vol: VolumeRef) {
+        let walq_id = Eid::new();
+        let tm = TxMgr::new(&walq_id, &vol).into_ref();
+        {
+            let mut tm = tm.write().unwrap();
+            tm.walq_mgr.set_defer_commit(true);
+        }
+        let val = 42;
+        let val2 = 43;
+        let mut a = Arc::default();
+
+        // tx #1, new and then sync
+        let tx = TxMgr::begin_trans(&tm).unwrap();
+        tx.run_all(|| {
+            a = Obj::new(val).into_cow(&tm)?;
+            Ok(())
+        })
+        .unwrap();
+        {
+            let mut tm = tm.write().unwrap();
+            tm.sync().unwrap();
+        }
+
+        // tx #2, update without sync
+        let tx = TxMgr::begin_trans(&tm).unwrap();
+        tx.run_all(|| {
+            let mut a_cow = a.write().unwrap();
+            let a = a_cow.make_mut(&tm)?;
+            a.val = val2;
+            Ok(())
+        })
+        .unwrap();
+        Obj::ensure(&a, val2, Arm::Left);
+
+        // simulate crash by dropping tx manager without sync
+        let id = {
+            let a = a.read().unwrap();
+            a.id().clone()
+        };
+        drop(a);
+        drop(tm);
+
+        // tx #2 should be rolled back as a whole after re-open
+        let tm = TxMgr::open(&walq_id, &vol).unwrap().into_ref();
+        let a = Cow::<Obj>::load(&id, &vol).unwrap();
+        Obj::ensure(&a, val, Arm::Right);
+        {
+            let mut tm = tm.write().unwrap();
+            tm.walq_mgr.set_defer_commit(true);
+        }
+
+        // update the same entity twice without sync, the first deferred
+        // commit must be saved before its entity is updated again
+        for v in [val2, val2 + 1].iter() {
+            let tx = TxMgr::begin_trans(&tm).unwrap();
+            tx.run_all(|| {
+                let mut a_cow = a.write().unwrap();
+                let a = a_cow.make_mut(&tm)?;
+                a.val = *v;
+                Ok(())
+            })
+            .unwrap();
+            assert!(tm.read().unwrap().is_unsynced());
+        }
+        Obj::ensure(&a, val2 + 1, Arm::Right);
+
+        // simulate crash again, only the first update should be kept
+        drop(a);
+        drop(tm);
+        let tm = TxMgr::open(&walq_id, &vol).unwrap().into_ref();
+        assert!(tm.read().unwrap().was_dirty());
+        let a = Cow::<Obj>::load(&id, &vol).unwrap();
+        Obj::ensure(&a, val2, Arm::Left);
+        {
+            let mut tm = tm.write().unwrap();
+            tm.walq_mgr.set_defer_commit(true);
+        }
+
+        // new entities without sync, their txs began with reserved txids
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let tx = TxMgr::begin_trans(&tm).unwrap();
+            tx.run_all(|| {
+                let b = Obj::new(val).into_cow(&tm)?;
+                let b = b.read().unwrap();
+                ids.push(b.id().clone());
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        // simulate crash again, both txs should be rolled back
+        drop(tm);
+        let tm = TxMgr::open(&walq_id, &vol).unwrap().into_ref();
+        assert!(tm.read().unwrap().was_dirty());
+        for id in ids.iter() {
+            assert!(Cow::<Obj>::load(id, &vol).is_err());
+        }
+
+        // deferred commits are saved when the batch is full
+        {
+            let mut tm = tm.write().unwrap();
+            tm.walq_mgr.set_defer_commit(true);
+        }
+        for i in 0..WalQueueMgr::COMMIT_BATCH_SIZE {
+            let tx = TxMgr::begin_trans(&tm).unwrap();
+            tx.run_all(|| {
+                Obj::new(val).into_cow(&tm)?;
+                Ok(())
+            })
+            .unwrap();
+            let unsynced = tm.read().unwrap().is_unsynced();
+            assert_eq!(unsynced, i < WalQueueMgr::COMMIT_BATCH_SIZE - 1);
+        }
+    }
+
+    #[test]
+    fn test_idle_trans() {
+        let vol = setup_mem_vol("txmgr.idle");
+        let tm = TxMgr::new(&Eid::new(), &vol).into_ref();
+        let (began_tx, began_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+
+        // hold a transaction on another thread
+        let tm2 = tm.clone();
+        let child = thread::spawn(move || {
+            let tx = TxMgr::begin_trans(&tm2).unwrap();
+            began_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+            tx.commit().unwrap();
+        });
+        began_rx.recv().unwrap();
+
+        assert_eq!(TxMgr::begin_idle_trans(&tm).unwrap_err(), Error::InUse);
+        assert!(!Txid::is_in_trans());
+        done_tx.send(()).unwrap();
+        child.join().unwrap();
+
+        let tx = TxMgr::begin_idle_trans(&tm).unwrap();
+        tx.commit().unwrap();
+    }
+
     #[test]
     fn test_trans_mem() {
         {
@@ -460,6 +965,10 @@ mod tests {
             let vol = setup_mem_vol("txmgr.bar");
             trans_abort(vol);
         }
+        {
+            let vol = setup_mem_vol("txmgr.baz");
+            trans_deferred(vol);
+        }
     }
 
     #[cfg(feature = "storage-file")]
@@ -473,6 +982,10 @@ mod tests {
             let (vol, _tmpdir) = setup_file_vol();
             trans_abort(vol);
         }
+        {
+            let (vol, _tmpdir) = setup_file_vol();
+            trans_deferred(vol);
+        }
     }
 
     #[cfg(feature = "storage-zbox")]
@@ -207,9 +207,26 @@ struct WalQueue {
     // in-progress tx id list
     doing: HashSet<Txid>,
 
+    // txids up to this may have begun without being saved in the in-progress
+    // tx list, they are treated as in progress when opening. It is the last
+    // persisted field, so wal queues saved before it was added can be loaded.
+    #[serde(default)]
+    txid_resv: u64,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     aborting: HashMap<Txid, Wal>,
 
+    // number of pins which hold retired txs from being recycled, those txs
+    // are kept in the completed tx queue and recycled after all pins are
+    // released
+    #[serde(skip_serializing, skip_deserializing, default)]
+    pins: usize,
+
+    // number of txs at the back of completed tx queue which are committed
+    // but not saved yet, they are not recycled until saved
+    #[serde(skip_serializing, skip_deserializing, default)]
+    unsaved: usize,
+
     #[serde(skip_serializing, skip_deserializing, default)]
     wal_armor: VolumeWalArmor<Wal>,
 
@@ -236,7 +253,10 @@ impl WalQueue {
             blk_wmark: 0,
             done: VecDeque::new(),
             doing: HashSet::new(),
+            txid_resv: 0,
             aborting: HashMap::new(),
+            pins: 0,
+            unsaved: 0,
             wal_armor: VolumeWalArmor::new(vol),
             allocator,
             vol: vol.clone(),
@@ -254,6 +274,18 @@ impl WalQueue {
         self.blk_wmark = blk_wmark;
     }
 
+    // add txids reserved but not saved in the in-progress tx list to it,
+    // return the restored txid watermark
+    fn restore_reserved(&mut self) -> u64 {
+        if self.txid_resv > self.txid_wmark {
+            for txid in self.txid_wmark + 1..=self.txid_resv {
+                self.doing.insert(Txid::from(txid));
+            }
+            self.txid_wmark = self.txid_resv;
+        }
+        self.txid_wmark
+    }
+
     #[inline]
     fn has_doing(&self) -> bool {
         !self.doing.is_empty()
@@ -264,6 +296,23 @@ impl WalQueue {
         !self.aborting.is_empty()
     }
 
+    // collect ids of wal queue and all wals in it, and ids of entities
+    // recorded in those wals
+    fn collect_ids(&self) -> Result<(Vec<Eid>, Vec<Eid>)> {
+        let mut wal_ids = vec![self.id.clone()];
+        let mut ent_ids = Vec::new();
+        for txid in self.done.iter().chain(self.doing.iter()) {
+            let wal_id = Wal::derive_id(*txid);
+            match self.wal_armor.load_item(&wal_id) {
+                Ok(wal) => ent_ids.extend(wal.entries.keys().cloned()),
+                Err(ref err) if *err == Error::NotFound => continue,
+                Err(err) => return Err(err),
+            }
+            wal_ids.push(wal_id);
+        }
+        Ok((wal_ids, ent_ids))
+    }
+
     #[inline]
     fn open(&mut self, vol: &VolumeRef) {
         self.wal_armor = VolumeWalArmor::new(vol);
@@ -276,10 +325,8 @@ impl WalQueue {
         self.doing.insert(txid);
     }
 
-    fn recycle_trans(&mut self) -> Result<()> {
-        // get retiree from the end of done queue
-        let retiree_txid = self.done.front().unwrap();
-        let retiree_id = Wal::derive_id(*retiree_txid);
+    fn recycle_trans(&self, retiree_txid: Txid) -> Result<()> {
+        let retiree_id = Wal::derive_id(retiree_txid);
 
         // load the retired wal
         debug!("recycle tx#{}", retiree_txid);
@@ -298,12 +345,25 @@ impl WalQueue {
         }
     }
 
-    fn commit_trans(&mut self, wal: Wal) -> Result<()> {
-        // recycle the retired trans
-        while self.done.len() >= Self::COMMITTED_QUEUE_SIZE {
-            self.recycle_trans()?;
+    // recycle retired txs until completed tx queue is within the size,
+    // nothing is recycled if the queue is pinned. Unsaved txs can still be
+    // rolled back, so they are not recycled either.
+    fn recycle_retirees(&mut self, size: usize) -> Result<()> {
+        if self.pins > 0 {
+            return Ok(());
+        }
+        while self.done.len() > size.max(self.unsaved) {
+            let retiree_txid = *self.done.front().unwrap();
+            self.recycle_trans(retiree_txid)?;
             self.done.pop_front();
         }
+        Ok(())
+    }
+
+    fn commit_trans(&mut self, wal: Wal) -> Result<()> {
+        // recycle the retired trans, if the queue is pinned they are left
+        // in the queue, so they can still be recycled after re-open
+        self.recycle_retirees(Self::COMMITTED_QUEUE_SIZE - 1)?;
 
         // remove txid from doing list and enqueue it
         self.doing.remove(&wal.txid);
@@ -312,6 +372,17 @@ impl WalQueue {
         Ok(())
     }
 
+    #[inline]
+    fn pin(&mut self) {
+        self.pins += 1;
+    }
+
+    // release a pin, and recycle the retired txs if it is the last one
+    fn unpin(&mut self) -> Result<()> {
+        self.pins -= 1;
+        self.recycle_retirees(Self::COMMITTED_QUEUE_SIZE)
+    }
+
     #[inline]
     fn begin_abort(&mut self, wal: &Wal) {
         self.aborting.insert(wal.txid, wal.clone());
@@ -428,10 +499,26 @@ pub struct WalQueueMgr {
     // block allocator
     allocator: AllocatorRef,
 
+    // if wal queue saving is deferred after commit
+    defer_commit: bool,
+
+    // entities in deferred commits not saved yet
+    unsynced_ents: HashSet<Eid>,
+
+    // if incomplete txs were rolled back when opening
+    was_dirty: bool,
+
     vol: VolumeRef,
 }
 
 impl WalQueueMgr {
+    // max number of deferred commits before wal queue is saved
+    pub const COMMIT_BATCH_SIZE: usize = 64;
+
+    // number of txids reserved each time a deferred tx begins, the
+    // wal queue is saved only when the reserved txids are used up
+    const TXID_RESV_SIZE: u64 = 64;
+
     pub fn new(walq_id: &Eid, vol: &VolumeRef) -> Self {
         let allocator = {
             let vol = vol.read().unwrap();
@@ -443,6 +530,9 @@ impl WalQueueMgr {
             walq_backup: None,
             walq_armor: VolumeWalArmor::new(vol),
             allocator,
+            defer_commit: false,
+            unsynced_ents: HashSet::new(),
+            was_dirty: false,
             vol: vol.clone(),
         }
     }
@@ -452,16 +542,25 @@ impl WalQueueMgr {
         self.walq = self.walq_armor.load_item(walq_id)?;
         self.walq.open(&self.vol);
 
-        // restore watermarks
-        let (txid_wmark, blk_wmark) = self.walq.watermarks();
+        // restore watermarks, reserved txids are skipped as some of them
+        // might have been used
+        let txid_wmark = self.walq.restore_reserved();
+        let (_, blk_wmark) = self.walq.watermarks();
         self.txid_wmark = Txid::from(txid_wmark);
         {
             let mut allocator = self.allocator.write().unwrap();
             allocator.set_block_wmark(blk_wmark);
         }
 
-        // now redo abort tx if any
+        // now redo abort tx if any, those txs were left incomplete because
+        // the repo was not closed cleanly
         if self.walq.has_doing() {
+            log_event!(
+                warn,
+                "repo.recover",
+                incomplete_txs = self.walq.doing.len()
+            );
+            self.was_dirty = true;
             self.backup_walq();
             self.walq
                 .cold_redo_abort()
@@ -502,26 +601,120 @@ impl WalQueueMgr {
         self.walq = self.walq_backup.take().unwrap();
     }
 
+    #[inline]
     fn save_walq(&mut self) -> Result<()> {
+        self.save_walq_resv(self.txid_wmark.val())
+    }
+
+    // save wal queue with txids up to `txid_resv` reserved
+    fn save_walq_resv(&mut self, txid_resv: u64) -> Result<()> {
         // get current block watermark and set it to wal queue
         let blk_wmark = {
             let allocator = self.allocator.read().unwrap();
             allocator.block_wmark()
         };
         self.walq.set_watermarks(self.txid_wmark.val(), blk_wmark);
+        self.walq.txid_resv = txid_resv;
 
         // flush volume then save wal queue
         {
             let mut vol = self.vol.write().unwrap();
             vol.flush()
         }
-        .and_then(|_| self.walq_armor.save_item(&mut self.walq))
+        .and_then(|_| self.walq_armor.save_item(&mut self.walq))?;
+
+        self.walq.unsaved = 0;
+        self.unsynced_ents.clear();
+        Ok(())
+    }
+
+    // save wal queue with current block watermark, so blocks allocated so
+    // far won't be allocated again after re-open
+    #[inline]
+    pub fn save(&mut self) -> Result<()> {
+        self.save_walq()
+    }
+
+    #[inline]
+    pub fn collect_ids(&self) -> Result<(Vec<Eid>, Vec<Eid>)> {
+        self.walq.collect_ids()
+    }
+
+    #[inline]
+    pub fn set_defer_commit(&mut self, defer_commit: bool) {
+        self.defer_commit = defer_commit;
+    }
+
+    // Save wal queue if there are deferred commits.
+    //
+    // A deferred commit is already completed in memory. If it is not saved
+    // before crash, the transaction will be rolled back as a whole when the
+    // wal queue is opened next time.
+    pub fn sync(&mut self) -> Result<()> {
+        if self.is_unsynced() {
+            let unsaved = self.walq.unsaved;
+            self.save_walq()?;
+            debug!("{} deferred commits saved", unsaved);
+        }
+        Ok(())
+    }
+
+    // check if there are deferred commits not saved yet
+    #[inline]
+    pub fn is_unsynced(&self) -> bool {
+        self.walq.unsaved > 0
+    }
+
+    // Save wal queue if the entity is in deferred commits not saved yet.
+    //
+    // Updating the entity again will overwrite its arm which is the last
+    // saved one, so the deferred commits must be saved before that.
+    pub fn sync_entity(&mut self, id: &Eid) -> Result<()> {
+        if self.unsynced_ents.contains(id) {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // pin wal queue, so entities removed by committed txs are not
+    // recycled until it is unpinned
+    #[inline]
+    pub fn pin(&mut self) {
+        self.walq.pin();
+    }
+
+    #[inline]
+    pub fn unpin(&mut self) -> Result<()> {
+        self.walq.unpin()
+    }
+
+    // check if wal queue is pinned
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.walq.pins > 0
+    }
+
+    // check if incomplete txs were rolled back when opening
+    #[inline]
+    pub fn was_dirty(&self) -> bool {
+        self.was_dirty
     }
 
     pub fn begin_trans(&mut self, txid: Txid) -> Result<()> {
         self.backup_walq();
         self.walq.begin_trans(txid);
-        self.save_walq().map_err(|err| {
+
+        let result = if !self.defer_commit {
+            self.save_walq()
+        } else if txid.val() > self.walq.txid_resv {
+            // reserve a batch of txids, the txs begun with them don't need
+            // to save wal queue until they are used up
+            self.save_walq_resv(txid.val() + Self::TXID_RESV_SIZE - 1)
+        } else {
+            Ok(())
+        };
+
+        result.map_err(|err| {
             self.restore_walq();
             err
         })
@@ -529,9 +722,28 @@ impl WalQueueMgr {
 
     pub fn commit_trans(&mut self, wal: Wal) -> Result<()> {
         self.backup_walq();
+        let ent_ids: Vec<Eid> = if self.defer_commit {
+            wal.entries.keys().cloned().collect()
+        } else {
+            Vec::new()
+        };
         self.walq
             .commit_trans(wal)
-            .and_then(|_| self.save_walq())
+            .and_then(|_| {
+                if self.defer_commit {
+                    // wal queue will be saved by an explicit sync, or when
+                    // the batch is full
+                    self.walq.unsaved += 1;
+                    self.unsynced_ents.extend(ent_ids);
+                    if self.walq.unsaved >= Self::COMMIT_BATCH_SIZE {
+                        self.save_walq()?;
+                        debug!("deferred commits batch saved");
+                    }
+                    Ok(())
+                } else {
+                    self.save_walq()
+                }
+            })
             .map_err(|err| {
                 // if commit failed, restore the walq backup
                 self.restore_walq();
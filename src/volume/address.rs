@@ -4,13 +4,17 @@ use std::slice::Iter;
 
 use serde::{Deserialize, Serialize};
 
-use super::{BLKS_PER_FRAME, BLK_SIZE, FRAME_SIZE};
-
 /// Block span
+///
+/// A span is a range of continuous blocks, it can be iterated to get each
+/// block index in it.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
 pub struct Span {
-    pub begin: usize, // begin block index
-    pub cnt: usize,   // number of blocks in the span
+    /// Begin block index
+    pub begin: usize,
+
+    /// Number of blocks in the span
+    pub cnt: usize,
 }
 
 impl Span {
@@ -25,8 +29,8 @@ impl Span {
     }
 
     #[inline]
-    pub fn bytes_len(&self) -> usize {
-        self.cnt * BLK_SIZE
+    pub fn bytes_len(&self, blk_size: usize) -> usize {
+        self.cnt * blk_size
     }
 
     pub fn split_to(&mut self, at: usize) -> Span {
@@ -85,12 +89,12 @@ impl LocSpan {
         }
     }
 
-    pub fn split_to(&mut self, at: usize) -> LocSpan {
+    pub fn split_to(&mut self, at: usize, blk_size: usize) -> LocSpan {
         let ret = LocSpan {
             span: self.span.split_to(at),
             offset: self.offset,
         };
-        self.offset += ret.span.bytes_len();
+        self.offset += ret.span.bytes_len(blk_size);
         ret
     }
 }
@@ -127,17 +131,22 @@ impl Addr {
     }
 
     // divide address to frames
-    pub fn divide_to_frames(&self) -> Vec<Addr> {
+    pub fn divide_to_frames(
+        &self,
+        blk_size: usize,
+        blks_per_frame: usize,
+    ) -> Vec<Addr> {
+        let frame_size = blk_size * blks_per_frame;
         let mut frames = vec![Addr::default()];
         let mut frm_idx = 0;
         let mut blk_cnt = 0;
 
         for loc_span in self.list.iter() {
             let mut loc_span = *loc_span;
-            loc_span.offset = frm_idx * FRAME_SIZE + blk_cnt * BLK_SIZE;
+            loc_span.offset = frm_idx * frame_size + blk_cnt * blk_size;
 
             loop {
-                let blk_left = BLKS_PER_FRAME - blk_cnt;
+                let blk_left = blks_per_frame - blk_cnt;
 
                 if loc_span.span.cnt <= blk_left {
                     // span can fit into frame
@@ -148,11 +157,11 @@ impl Addr {
 
                 // span cannot fit into frame, must split span first
                 let at = loc_span.span.begin + blk_left;
-                let split = loc_span.split_to(at);
+                let split = loc_span.split_to(at, blk_size);
 
                 // finish current frame and start a new frame
                 frames[frm_idx].list.push(split);
-                frames[frm_idx].len = FRAME_SIZE;
+                frames[frm_idx].len = frame_size;
                 frames.push(Addr::default());
                 frm_idx += 1;
                 blk_cnt = 0;
@@ -160,7 +169,7 @@ impl Addr {
         }
 
         // fix the last frame's length
-        frames.last_mut().unwrap().len = self.len - frm_idx * FRAME_SIZE;
+        frames.last_mut().unwrap().len = self.len - frm_idx * frame_size;
         assert_eq!(self.len, frames.iter().map(|a| a.len).sum::<usize>());
 
         frames
@@ -187,6 +196,7 @@ impl Index<usize> for Addr {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::volume::{BLKS_PER_FRAME, BLK_SIZE, FRAME_SIZE};
 
     #[test]
     fn split_addr() {
@@ -196,7 +206,7 @@ mod tests {
             len: 3,
             list: vec![lspan.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 1);
         assert_eq!(frms[0].len, addr.len);
         assert_eq!(frms[0].list[0], lspan);
@@ -207,7 +217,7 @@ mod tests {
             len: FRAME_SIZE,
             list: vec![lspan.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 1);
         assert_eq!(frms[0].len, addr.len);
         assert_eq!(frms[0].list[0], lspan);
@@ -218,7 +228,7 @@ mod tests {
             len: FRAME_SIZE + 3,
             list: vec![lspan.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 2);
         assert_eq!(frms[0].len, FRAME_SIZE);
         assert_eq!(frms[0].list[0], LocSpan::new(0, BLKS_PER_FRAME, 0));
@@ -235,7 +245,7 @@ mod tests {
             len: BLK_SIZE + 3,
             list: vec![lspan.clone(), lspan2.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 1);
         assert_eq!(frms[0].len, addr.len);
         assert_eq!(frms[0].list.len(), 2);
@@ -249,7 +259,7 @@ mod tests {
             len: BLK_SIZE + FRAME_SIZE,
             list: vec![lspan.clone(), lspan2.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 2);
         assert_eq!(frms[0].len, FRAME_SIZE);
         assert_eq!(frms[0].list.len(), 2);
@@ -271,7 +281,7 @@ mod tests {
             len: FRAME_SIZE * 2 + 3,
             list: vec![lspan.clone()],
         };
-        let frms = addr.divide_to_frames();
+        let frms = addr.divide_to_frames(BLK_SIZE, BLKS_PER_FRAME);
         assert_eq!(frms.len(), 3);
         assert_eq!(frms[0].len, FRAME_SIZE);
         assert_eq!(frms[0].list.len(), 1);
@@ -32,7 +32,7 @@ impl Arm {
     }
 
     #[inline]
-    fn both_eid(id: &Eid) -> (Eid, Eid) {
+    pub(super) fn both_eid(id: &Eid) -> (Eid, Eid) {
         (Arm::Left.to_eid(id), Arm::Right.to_eid(id))
     }
 
@@ -0,0 +1,129 @@
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Data compression algorithm.
+///
+/// It is set by [`RepoOpener::compression`] when creating a repository and
+/// is stored in the repository, so data is always read back using the same
+/// algorithm.
+///
+/// `Zstd` compression requires Cargo feature `compress-zstd`, its level must
+/// be within [1, 22]. Higher level gives better compression ratio but it is
+/// slower.
+///
+/// [`RepoOpener::compression`]: struct.RepoOpener.html#method.compression
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub enum Compression {
+    /// No compression
+    #[default]
+    None,
+
+    /// LZ4 compression
+    Lz4,
+
+    /// Zstd compression with level
+    Zstd(i32),
+}
+
+impl Compression {
+    // zstd compression level range
+    const MIN_ZSTD_LEVEL: i32 = 1;
+    const MAX_ZSTD_LEVEL: i32 = 22;
+
+    /// Returns whether data is compressed.
+    #[inline]
+    pub fn is_enabled(self) -> bool {
+        self != Compression::None
+    }
+
+    // convert from the boolean compression option, which is always LZ4
+    #[inline]
+    pub(crate) fn from_bool(compress: bool) -> Self {
+        if compress {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+
+    // resolve compression for file data, file can override volume's
+    // compression, in which case the volume's algorithm is used if it is
+    // enabled or falls back to LZ4
+    #[inline]
+    pub(crate) fn resolve(self, compress: Option<bool>) -> Self {
+        match compress {
+            Some(true) if !self.is_enabled() => Compression::Lz4,
+            Some(true) => self,
+            Some(false) => Compression::None,
+            None => self,
+        }
+    }
+
+    // check if data compressed by the other can be read by this one, that is,
+    // they are using the same algorithm
+    #[inline]
+    pub(crate) fn is_compatible(self, other: Compression) -> bool {
+        mem::discriminant(&self) == mem::discriminant(&other)
+    }
+
+    // check if the compression is supported in this build
+    pub(crate) fn check(self) -> Result<()> {
+        match self {
+            Compression::Zstd(level) => {
+                if !cfg!(feature = "compress-zstd") {
+                    return Err(Error::UnsupportedCompression(
+                        self.to_string(),
+                    ));
+                }
+                if !(Self::MIN_ZSTD_LEVEL..=Self::MAX_ZSTD_LEVEL)
+                    .contains(&level)
+                {
+                    return Err(Error::InvalidArgument);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd(_) => write!(f, "zstd"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_compression() {
+        let zstd = Compression::Zstd(3);
+        assert_eq!(Compression::None.resolve(None), Compression::None);
+        assert_eq!(Compression::None.resolve(Some(true)), Compression::Lz4);
+        assert_eq!(Compression::Lz4.resolve(Some(false)), Compression::None);
+        assert_eq!(zstd.resolve(Some(true)), zstd);
+        assert_eq!(zstd.resolve(None), zstd);
+        assert!(zstd.is_compatible(Compression::Zstd(9)));
+        assert!(!zstd.is_compatible(Compression::Lz4));
+        assert_eq!(
+            Compression::Zstd(0).check().unwrap_err(),
+            if cfg!(feature = "compress-zstd") {
+                Error::InvalidArgument
+            } else {
+                Error::UnsupportedCompression("zstd".to_string())
+            }
+        );
+    }
+}
@@ -5,15 +5,22 @@
 mod address;
 mod allocator;
 mod armor;
+mod compression;
 mod storage;
 mod super_block;
 mod volume;
 
+pub use self::address::Span;
 pub use self::allocator::{Allocator, AllocatorRef};
 pub use self::armor::{
     Arm, ArmAccess, Armor, Seq, VolumeArmor, VolumeWalArmor,
 };
-pub use self::storage::StorageRef;
+pub use self::compression::Compression;
+pub use self::storage::{
+    available_backends, parse_repo_uri, register_storage, CacheEvent,
+    CacheHook, CryptoCtx, RepoUri, Storable, StorageFactory, StorageRef,
+    SyncLevel, TransportConfig,
+};
 pub use self::volume::{
     Info, Reader, Volume, VolumeRef, VolumeWeakRef, Writer,
 };
@@ -21,7 +28,15 @@ pub use self::volume::{
 #[cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
 pub use self::storage::FaultyController;
 
-// block and frame size
+#[cfg(all(feature = "storage-zbox", target_arch = "wasm32"))]
+pub use self::storage::load_indexeddb_cache;
+
+// default block and frame size
 pub const BLK_SIZE: usize = 8 * 1024;
 pub const BLKS_PER_FRAME: usize = 16;
 pub const FRAME_SIZE: usize = BLKS_PER_FRAME * BLK_SIZE;
+
+// block size range and maximum frame size
+pub const MIN_BLK_SIZE: usize = 1024;
+pub const MAX_BLK_SIZE: usize = 64 * 1024;
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
@@ -0,0 +1,55 @@
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// Cache events reported to the hook set by [`RepoOpener::on_cache_event`].
+///
+/// Events are reported from the repository's frame cache, and from the local
+/// cache of remote storage such as Zbox Cloud Storage.
+///
+/// [`RepoOpener::on_cache_event`]: struct.RepoOpener.html#method.on_cache_event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheEvent {
+    /// Requested data is found in cache.
+    Hit,
+
+    /// Requested data is not found in cache.
+    Miss,
+
+    /// Data is evicted from cache to make space for new data.
+    Evict,
+
+    /// Data is fetched from the underlying storage, for remote storage this
+    /// means data is downloaded from network.
+    Fetch {
+        /// Number of bytes fetched.
+        bytes: usize,
+    },
+}
+
+/// Cache event hook
+///
+/// An empty hook costs nothing but a check when reporting event.
+#[derive(Clone, Default)]
+pub struct CacheHook(Option<Arc<dyn Fn(CacheEvent) + Send + Sync>>);
+
+impl CacheHook {
+    #[inline]
+    pub fn new(hook: Arc<dyn Fn(CacheEvent) + Send + Sync>) -> Self {
+        CacheHook(Some(hook))
+    }
+
+    #[inline]
+    pub fn emit(&self, event: CacheEvent) {
+        if let Some(ref hook) = self.0 {
+            hook(event);
+        }
+    }
+}
+
+impl Debug for CacheHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CacheHook")
+            .field(&self.0.as_ref().map(|_| "Fn"))
+            .finish()
+    }
+}
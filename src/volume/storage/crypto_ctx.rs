@@ -0,0 +1,80 @@
+use std::fmt::{self, Debug};
+
+use crate::base::crypto::{Crypto, Key};
+use crate::error::{Error, Result};
+
+/// Crypto context passed to storage backend.
+///
+/// It is given to [`Storable::init`] and [`Storable::open`], a backend
+/// which keeps its own metadata, such as an index, can use it to encrypt
+/// that metadata with the repository's cipher and storage key. The key
+/// itself is not accessible.
+///
+/// While the repository's cipher is being changed, metadata can still be
+/// encrypted with the previous cipher and [`decrypt`] falls back to it.
+/// Such context is also given to [`Storable::change_crypto_ctx`] to
+/// re-encrypt the metadata.
+///
+/// [`Storable::init`]: trait.Storable.html#tymethod.init
+/// [`Storable::open`]: trait.Storable.html#tymethod.open
+/// [`Storable::change_crypto_ctx`]: trait.Storable.html#method.change_crypto_ctx
+/// [`decrypt`]: #method.decrypt
+#[derive(Clone)]
+pub struct CryptoCtx {
+    crypto: Crypto,
+    prev_crypto: Option<Crypto>,
+    key: Key,
+}
+
+impl CryptoCtx {
+    #[inline]
+    pub(crate) fn new(crypto: Crypto, key: Key) -> Self {
+        CryptoCtx {
+            crypto,
+            prev_crypto: None,
+            key,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn with_prev_crypto(
+        mut self,
+        prev_crypto: Option<Crypto>,
+    ) -> Self {
+        self.prev_crypto = prev_crypto;
+        self
+    }
+
+    #[cfg(any(feature = "storage-file", feature = "storage-zbox"))]
+    #[inline]
+    pub(crate) fn into_parts(self) -> (Crypto, Option<Crypto>, Key) {
+        (self.crypto, self.prev_crypto, self.key)
+    }
+
+    /// Encrypts `msg` using the storage key.
+    #[inline]
+    pub fn encrypt(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        self.crypto.encrypt(msg, &self.key)
+    }
+
+    /// Decrypts `ctxt` encrypted by [`encrypt`](#method.encrypt), or by
+    /// the previous cipher if the repository's cipher is being changed.
+    pub fn decrypt(&self, ctxt: &[u8]) -> Result<Vec<u8>> {
+        match self.crypto.decrypt(ctxt, &self.key) {
+            Err(ref err) if *err == Error::Decrypt => match self.prev_crypto {
+                Some(ref prev_crypto) => prev_crypto.decrypt(ctxt, &self.key),
+                None => Err(Error::Decrypt),
+            },
+            ret => ret,
+        }
+    }
+}
+
+impl Debug for CryptoCtx {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CryptoCtx")
+            .field("crypto", &self.crypto)
+            .field("prev_crypto", &self.prev_crypto)
+            .finish()
+    }
+}
@@ -1,13 +1,12 @@
 use std::fmt::{self, Debug};
 
-use crate::base::crypto::{Crypto, Key};
 use crate::base::IntoRef;
 use crate::error::Result;
 use crate::trans::Eid;
 use crate::volume::address::Span;
 use crate::volume::storage::faulty_ctl::Controller;
 use crate::volume::storage::mem::MemStorage;
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CryptoCtx, Storable};
 
 /// Faulty Storage
 ///
@@ -39,14 +38,25 @@ impl Storable for FaultyStorage {
     }
 
     #[inline]
-    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
-        self.inner.init(crypto, key)
+    fn init(&mut self, ctx: CryptoCtx) -> Result<()> {
+        self.inner.init(ctx)
     }
 
     #[inline]
-    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
+    fn open(&mut self, ctx: CryptoCtx, force: bool) -> Result<()> {
         self.ctlr.make_random_error()?;
-        self.inner.open(crypto, key, force)
+        self.inner.open(ctx, force)
+    }
+
+    #[inline]
+    fn change_crypto_ctx(&mut self, ctx: CryptoCtx) -> Result<bool> {
+        self.ctlr.make_random_error()?;
+        self.inner.change_crypto_ctx(ctx)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.inner.set_block_size(blk_size)
     }
 
     #[inline]
@@ -140,6 +150,7 @@ impl IntoRef for FaultyStorage {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::crypto::{Crypto, Key};
     use crate::base::init_env;
     use crate::error::Error;
 
@@ -149,8 +160,8 @@ mod tests {
 
         let crypto = Crypto::default();
         let key = Key::new_empty();
-        let loc = "foo";
-        let loc2 = "bar";
+        let loc = "faulty_static_foo";
+        let loc2 = "faulty_static_bar";
         let id = Eid::new();
         let id2 = Eid::new();
         let buf = vec![1, 2, 3];
@@ -159,8 +170,10 @@ mod tests {
         {
             let mut fs = FaultyStorage::new(&loc);
             let mut fs2 = FaultyStorage::new(&loc2);
-            fs.init(crypto.clone(), key.clone()).unwrap();
-            fs2.init(crypto.clone(), key.clone()).unwrap();
+            fs.init(CryptoCtx::new(crypto.clone(), key.clone()))
+                .unwrap();
+            fs2.init(CryptoCtx::new(crypto.clone(), key.clone()))
+                .unwrap();
             fs.put_address(&id, &buf).unwrap();
             fs2.put_address(&id2, &buf2).unwrap();
         }
@@ -170,8 +183,10 @@ mod tests {
             let mut fs2 = FaultyStorage::new(&loc2);
             assert!(fs.exists().unwrap());
             assert!(fs2.exists().unwrap());
-            fs.open(crypto.clone(), key.clone(), false).unwrap();
-            fs2.open(crypto.clone(), key.clone(), false).unwrap();
+            fs.open(CryptoCtx::new(crypto.clone(), key.clone()), false)
+                .unwrap();
+            fs2.open(CryptoCtx::new(crypto.clone(), key.clone()), false)
+                .unwrap();
             assert_eq!(fs.get_address(&id).unwrap(), buf);
             assert_eq!(fs.get_address(&id2).unwrap_err(), Error::NotFound);
             assert_eq!(fs2.get_address(&id2).unwrap(), buf2);
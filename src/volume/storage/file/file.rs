@@ -13,7 +13,7 @@ use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
 use crate::volume::storage::index_mgr::{IndexMgr, Lsmt, MemTab, Tab};
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CryptoCtx, Storable, SyncLevel};
 
 /// File Storage
 pub struct FileStorage {
@@ -22,6 +22,7 @@ pub struct FileStorage {
     wal_base: PathBuf,
     idx_mgr: IndexMgr,
     sec_mgr: SectorMgr,
+    sync: SyncLevel,
 }
 
 impl FileStorage {
@@ -41,22 +42,28 @@ impl FileStorage {
     const SUBKEY_ID_SECTOR: u64 = 43;
 
     pub fn new(base: &Path) -> Self {
-        let idx_base = base.join(Self::INDEX_DIR);
-        let idx_mgr = IndexMgr::new(
-            Box::new(FileArmor::<Lsmt>::new(&idx_base)),
-            Box::new(FileArmor::<MemTab>::new(&idx_base)),
-            Box::new(FileArmor::<Tab>::new(&idx_base)),
-        );
-
+        let sync = SyncLevel::default();
         FileStorage {
             is_attached: false,
             base: base.to_path_buf(),
             wal_base: base.join(Self::WAL_DIR),
-            idx_mgr,
+            idx_mgr: Self::new_idx_mgr(&base.join(Self::INDEX_DIR), sync),
             sec_mgr: SectorMgr::new(&base.join(Self::DATA_DIR)),
+            sync,
         }
     }
 
+    // create index manager whose armors write with the sync level
+    fn new_idx_mgr(idx_base: &Path, sync: SyncLevel) -> IndexMgr {
+        let mut lsmt = FileArmor::<Lsmt>::new(idx_base);
+        let mut memtab = FileArmor::<MemTab>::new(idx_base);
+        let mut tab = FileArmor::<Tab>::new(idx_base);
+        lsmt.set_sync_level(sync);
+        memtab.set_sync_level(sync);
+        tab.set_sync_level(sync);
+        IndexMgr::new(Box::new(lsmt), Box::new(memtab), Box::new(tab))
+    }
+
     #[inline]
     fn super_block_path(&self, suffix: u64) -> PathBuf {
         let mut path = self.base.join(Self::SUPER_BLK_FILE_NAME);
@@ -85,26 +92,41 @@ impl FileStorage {
         self.base.join(Self::DATA_DIR)
     }
 
-    fn set_crypto_ctx(&mut self, crypto: Crypto, key: Key) {
-        self.idx_mgr
-            .set_crypto_ctx(crypto.clone(), key.derive(Self::SUBKEY_ID_INDEX));
+    fn set_crypto_ctx(
+        &mut self,
+        crypto: Crypto,
+        prev_crypto: Option<Crypto>,
+        key: Key,
+    ) {
+        self.idx_mgr.set_crypto_ctx(
+            crypto.clone(),
+            prev_crypto.clone(),
+            key.derive(Self::SUBKEY_ID_INDEX),
+        );
         let hash_key = key.derive(Self::SUBKEY_ID_SECTOR);
-        self.sec_mgr.set_crypto_ctx(crypto, key, hash_key);
+        self.sec_mgr
+            .set_crypto_ctx(crypto, prev_crypto, key, hash_key);
     }
 
     fn lock_repo(&mut self, force: bool) -> Result<()> {
         let lock_path = self.lock_path();
-        if lock_path.exists() {
-            if force {
+
+        // lock file is created exclusively, so only one opener can succeed
+        // when the repo is opened concurrently
+        match vio::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => {}
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+                if !force {
+                    return Err(Error::RepoOpened);
+                }
                 warn!("Repo was locked, forced to open");
-            } else {
-                return Err(Error::RepoOpened);
             }
+            Err(err) => return Err(Error::from(err)),
         }
-        let _ = vio::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&lock_path)?;
         self.is_attached = true;
         Ok(())
     }
@@ -125,31 +147,63 @@ impl Storable for FileStorage {
         Ok(())
     }
 
-    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+    fn init(&mut self, ctx: CryptoCtx) -> Result<()> {
         // create dir structure
         vio::create_dir_all(self.index_dir())?;
         vio::create_dir_all(self.data_dir())?;
+        self.sync.sync_dir(&self.base)?;
+        self.sync.sync_parent(&self.base)?;
+
+        // lock repo before initialising it, in case it is being created
+        // concurrently
+        self.lock_repo(false)?;
 
         // set crypto context
-        self.set_crypto_ctx(crypto, key);
+        let (crypto, prev_crypto, key) = ctx.into_parts();
+        self.set_crypto_ctx(crypto, prev_crypto, key);
 
         // initialise index manager
-        self.idx_mgr.init()?;
-
-        self.lock_repo(false)
+        self.idx_mgr.init()
     }
 
     #[inline]
-    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()> {
-        self.set_crypto_ctx(crypto, key);
+    fn open(&mut self, ctx: CryptoCtx, force: bool) -> Result<()> {
+        let (crypto, prev_crypto, key) = ctx.into_parts();
+        self.set_crypto_ctx(crypto, prev_crypto, key);
         self.idx_mgr.open()?;
         self.lock_repo(force)
     }
 
+    fn change_crypto_ctx(&mut self, ctx: CryptoCtx) -> Result<bool> {
+        // index and sectors are saved with the new crypto, those not saved
+        // yet are still read with the previous one
+        let (crypto, prev_crypto, key) = ctx.into_parts();
+        self.set_crypto_ctx(crypto, prev_crypto, key);
+        self.idx_mgr.recrypt()?;
+        self.sec_mgr.recrypt()?;
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.sec_mgr.set_block_size(blk_size);
+        Ok(())
+    }
+
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         let path = self.super_block_path(suffix);
         let mut buf = Vec::new();
-        let mut file = vio::OpenOptions::new().read(true).open(&path)?;
+        let mut file = match vio::OpenOptions::new().read(true).open(&path) {
+            Ok(file) => file,
+            // repo is locked and still being created
+            Err(ref err)
+                if err.kind() == ErrorKind::NotFound
+                    && self.lock_path().exists() =>
+            {
+                return Err(Error::RepoOpened);
+            }
+            Err(err) => return Err(Error::from(err)),
+        };
         file.read_to_end(&mut buf)?;
         Ok(buf)
     }
@@ -162,7 +216,8 @@ impl Storable for FileStorage {
             .truncate(true)
             .open(&path)?;
         file.write_all(super_blk).and_then(|_| file.flush())?;
-        Ok(())
+        self.sync.sync_file(&file)?;
+        self.sync.sync_dir(&self.base)
     }
 
     fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
@@ -187,7 +242,8 @@ impl Storable for FileStorage {
             .truncate(true)
             .open(&path)?;
         file.write_all(wal).and_then(|_| file.flush())?;
-        Ok(())
+        self.sync.sync_file(&file)?;
+        self.sync.sync_parent(&path)
     }
 
     fn del_wal(&mut self, id: &Eid) -> Result<()> {
@@ -237,12 +293,31 @@ impl Storable for FileStorage {
 
     #[inline]
     fn destroy(&mut self) -> Result<()> {
+        // cannot destroy an opened repo
         if self.lock_path().exists() {
-            warn!("Destroy an opened repo");
+            return Err(Error::InUse);
         }
         vio::remove_dir_all(&self.base)?;
         Ok(())
     }
+
+    fn set_sync_level(&mut self, level: SyncLevel) {
+        // armors are owned by index manager, so it is re-created with
+        // the new level, this is fine as it is not initialised or opened
+        self.idx_mgr = Self::new_idx_mgr(&self.index_dir(), level);
+        self.sec_mgr.set_sync_level(level);
+        self.sync = level;
+    }
+
+    #[inline]
+    fn cache_mem_used(&self) -> usize {
+        self.sec_mgr.cache_mem_used()
+    }
+
+    #[inline]
+    fn set_cache_mem(&mut self, capacity: usize) {
+        self.sec_mgr.set_cache_mem(capacity);
+    }
 }
 
 impl Drop for FileStorage {
@@ -269,7 +344,9 @@ mod tests {
     use std::time::Instant;
 
     use super::*;
-    use crate::base::crypto::{Crypto, RandomSeed, RANDOM_SEED_SIZE};
+    use crate::base::crypto::{
+        Cipher, Cost, Crypto, RandomSeed, RANDOM_SEED_SIZE,
+    };
     use crate::base::init_env;
     use crate::base::utils::speed_str;
     use crate::error::Error;
@@ -293,7 +370,8 @@ mod tests {
         let blk = vec![1, 2, 3];
         let blk2 = vec![4, 5, 6];
         let mut fs = FileStorage::new(&dir);
-        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+        fs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         // put super block
         fs.put_super_block(&blk, 0).unwrap();
@@ -310,7 +388,8 @@ mod tests {
     fn wal_oper() {
         let (dir, _tmpdir) = setup();
         let mut fs = FileStorage::new(&dir);
-        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+        fs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let id = Eid::new();
         let id2 = Eid::new();
@@ -336,7 +415,8 @@ mod tests {
         // re-open storage
         drop(fs);
         let mut fs = FileStorage::new(&dir);
-        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        fs.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         // wal 1 is deleted, wal 2 should still be there
         assert_eq!(fs.get_wal(&id).unwrap_err(), Error::NotFound);
@@ -348,7 +428,8 @@ mod tests {
     fn index_oper() {
         let (dir, _tmpdir) = setup();
         let mut fs = FileStorage::new(&dir);
-        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+        fs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let id = Eid::new();
         let id2 = Eid::new();
@@ -376,7 +457,8 @@ mod tests {
         // re-open storage
         drop(fs);
         let mut fs = FileStorage::new(&dir);
-        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        fs.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         // address 1 is deleted, address 2 should still be there
         assert_eq!(fs.get_address(&id).unwrap_err(), Error::NotFound);
@@ -388,7 +470,8 @@ mod tests {
     fn block_oper() {
         let (dir, _tmpdir) = setup();
         let mut fs = FileStorage::new(&dir);
-        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+        fs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let mut blks = vec![1u8; BLK_SIZE * 4];
         blks[0] = 42u8;
@@ -433,7 +516,8 @@ mod tests {
         // re-open storage
         drop(fs);
         let mut fs = FileStorage::new(&dir);
-        fs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        fs.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         // blocks should still be there
         let blk = &mut tgt[..BLK_SIZE];
@@ -471,7 +555,7 @@ mod tests {
             Box::new(FileArmor::<MemTab>::new(&dir)),
             Box::new(FileArmor::<Tab>::new(&dir)),
         );
-        idx_mgr.set_crypto_ctx(crypto.clone(), key.clone());
+        idx_mgr.set_crypto_ctx(crypto.clone(), None, key.clone());
         idx_mgr.init().unwrap();
 
         let mut ids = Vec::new();
@@ -527,7 +611,7 @@ mod tests {
             Box::new(FileArmor::<MemTab>::new(&dir)),
             Box::new(FileArmor::<Tab>::new(&dir)),
         );
-        idx_mgr.set_crypto_ctx(crypto.clone(), key.clone());
+        idx_mgr.set_crypto_ctx(crypto.clone(), None, key.clone());
         idx_mgr.open().unwrap();
 
         // verify again
@@ -539,11 +623,71 @@ mod tests {
         assert_eq!(idx_mgr.get(&ids[45]).unwrap_err(), Error::NotFound);
     }
 
+    #[test]
+    fn crypto_ctx_change() {
+        let (dir, _tmpdir) = setup();
+        let key = Key::new_empty();
+        let old = Crypto::new(Cost::default(), Cipher::Xchacha).unwrap();
+        let new =
+            Crypto::new(Cost::default(), Cipher::ChaCha20Poly1305).unwrap();
+        let ctx = |crypto: &Crypto, prev_crypto: Option<&Crypto>| {
+            CryptoCtx::new(crypto.clone(), key.clone())
+                .with_prev_crypto(prev_crypto.cloned())
+        };
+        let verify = |fs: &mut FileStorage, ids: &[Eid], blks: &[u8]| {
+            for (i, id) in ids.iter().enumerate() {
+                let addr = fs.get_address(id).unwrap();
+                assert_eq!(&addr[..], &(i as u32).to_le_bytes()[..]);
+            }
+            let mut tgt = vec![0u8; blks.len()];
+            fs.get_blocks(&mut tgt, Span::new(0, 4)).unwrap();
+            assert_eq!(tgt, blks);
+            fs.get_blocks(&mut tgt, Span::new(4096, 4)).unwrap();
+            assert_eq!(tgt, blks);
+        };
+
+        // write enough addresses to have index tabs, and blocks in two
+        // sectors
+        let mut fs = FileStorage::new(&dir);
+        fs.init(ctx(&old, None)).unwrap();
+        let ids: Vec<Eid> = (0..10_000).map(|_| Eid::new()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            fs.put_address(id, &(i as u32).to_le_bytes()).unwrap();
+        }
+        let blks = vec![42u8; BLK_SIZE * 4];
+        fs.put_blocks(Span::new(0, 4), &blks).unwrap();
+        fs.put_blocks(Span::new(4096, 4), &blks).unwrap();
+        fs.flush().unwrap();
+
+        // interrupted after index is re-encrypted
+        fs.set_crypto_ctx(new.clone(), Some(old.clone()), key.clone());
+        fs.idx_mgr.recrypt().unwrap();
+        drop(fs);
+
+        // both ciphers can be read before it is resumed
+        let mut fs = FileStorage::new(&dir);
+        fs.open(ctx(&old, Some(&new)), false).unwrap();
+        verify(&mut fs, &ids, &blks);
+        assert!(fs.change_crypto_ctx(ctx(&new, Some(&old))).unwrap());
+        drop(fs);
+
+        // nothing is left with the old cipher
+        let mut fs = FileStorage::new(&dir);
+        assert_eq!(
+            fs.open(ctx(&old, None), false).unwrap_err(),
+            Error::Decrypt
+        );
+        let mut fs = FileStorage::new(&dir);
+        fs.open(ctx(&new, None), false).unwrap();
+        verify(&mut fs, &ids, &blks);
+    }
+
     #[test]
     fn test_perf() {
         let (dir, _tmpdir) = setup();
         let mut fs = FileStorage::new(&dir);
-        fs.init(Crypto::default(), Key::new_empty()).unwrap();
+        fs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         const DATA_LEN: usize = 36 * 1024 * 1024;
         const BLK_CNT: usize = DATA_LEN / BLK_SIZE;
@@ -13,6 +13,7 @@ use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::trans::Finish;
 use crate::volume::storage::index_mgr::Accessor;
+use crate::volume::storage::SyncLevel;
 use crate::volume::{Arm, ArmAccess, Armor};
 
 // read/write frame size
@@ -32,10 +33,19 @@ pub struct CryptoReader {
 
     crypto: Crypto,
     key: Key,
+
+    // crypto to try if the file is not encrypted with the current one, the
+    // whole file is encrypted with the same crypto
+    prev_crypto: Option<Crypto>,
 }
 
 impl CryptoReader {
-    fn new(file: vio::File, crypto: &Crypto, key: &Key) -> Self {
+    fn new(
+        file: vio::File,
+        crypto: &Crypto,
+        prev_crypto: &Option<Crypto>,
+        key: &Key,
+    ) -> Self {
         CryptoReader {
             file: file.take(FRAME_SIZE as u64),
             enc_frame: Vec::with_capacity(FRAME_SIZE),
@@ -44,6 +54,31 @@ impl CryptoReader {
             read: 0,
             crypto: crypto.clone(),
             key: key.clone(),
+            prev_crypto: prev_crypto.clone(),
+        }
+    }
+
+    // decrypt frame, switch to the previous crypto if current one fails
+    fn decrypt_frame(&mut self) -> Result<usize> {
+        match self.crypto.decrypt_raw(
+            &mut self.frame,
+            &self.enc_frame,
+            &self.key,
+            &[0u8; 0],
+        ) {
+            Err(ref err) if *err == Error::Decrypt => {
+                let prev_crypto =
+                    self.prev_crypto.take().ok_or(Error::Decrypt)?;
+                let frame_len = prev_crypto.decrypt_raw(
+                    &mut self.frame,
+                    &self.enc_frame,
+                    &self.key,
+                    &[0u8; 0],
+                )?;
+                self.crypto = prev_crypto;
+                Ok(frame_len)
+            }
+            ret => ret,
         }
     }
 }
@@ -59,11 +94,7 @@ impl Read for CryptoReader {
             }
 
             // decrypt frame
-            self.frame_len = map_io_err!(self.crypto.decrypt_to(
-                &mut self.frame,
-                &self.enc_frame,
-                &self.key
-            ))?;
+            self.frame_len = map_io_err!(self.decrypt_frame())?;
 
             self.file.set_limit(FRAME_SIZE as u64);
             self.enc_frame.clear();
@@ -98,10 +129,16 @@ pub struct CryptoWriter {
 
     crypto: Crypto,
     key: Key,
+    sync: SyncLevel,
 }
 
 impl CryptoWriter {
-    fn new(file: vio::File, crypto: &Crypto, key: &Key) -> Self {
+    fn new(
+        file: vio::File,
+        crypto: &Crypto,
+        key: &Key,
+        sync: SyncLevel,
+    ) -> Self {
         CryptoWriter {
             file,
             stg: vec![0u8; crypto.decrypted_len(FRAME_SIZE)],
@@ -111,6 +148,7 @@ impl CryptoWriter {
             written: 0,
             crypto: crypto.clone(),
             key: key.clone(),
+            sync,
         }
     }
 
@@ -121,10 +159,11 @@ impl CryptoWriter {
         }
 
         // encrypt stage to frame
-        self.frame_len = self.crypto.encrypt_to(
+        self.frame_len = self.crypto.encrypt_raw(
             &mut self.frame,
             &self.stg[..self.stg_len],
             &self.key,
+            &[0u8; 0],
         )?;
 
         // write encrypted frame to file
@@ -161,7 +200,7 @@ impl Finish for CryptoWriter {
     #[inline]
     fn finish(mut self) -> Result<()> {
         self.write_frame()?;
-        Ok(())
+        self.sync.sync_file(&self.file)
     }
 }
 
@@ -170,7 +209,9 @@ impl Finish for CryptoWriter {
 pub struct FileArmor<T> {
     base: PathBuf,
     crypto: Crypto,
+    prev_crypto: Option<Crypto>,
     key: Key,
+    sync: SyncLevel,
     _t: PhantomData<T>,
 }
 
@@ -179,10 +220,17 @@ impl<T> FileArmor<T> {
         FileArmor {
             base: base.to_path_buf(),
             crypto: Crypto::default(),
+            prev_crypto: None,
             key: Key::new_empty(),
+            sync: SyncLevel::default(),
             _t: PhantomData,
         }
     }
+
+    #[inline]
+    pub fn set_sync_level(&mut self, level: SyncLevel) {
+        self.sync = level;
+    }
 }
 
 impl<'de, T: ArmAccess<'de> + Debug> Armor<'de> for FileArmor<T> {
@@ -194,18 +242,27 @@ impl<'de, T: ArmAccess<'de> + Debug> Armor<'de> for FileArmor<T> {
         let path = arm_id.to_path_buf(&self.base);
         let file =
             from_io_err!(vio::OpenOptions::new().read(true).open(&path))?;
-        Ok(CryptoReader::new(file, &self.crypto, &self.key))
+        Ok(CryptoReader::new(
+            file,
+            &self.crypto,
+            &self.prev_crypto,
+            &self.key,
+        ))
     }
 
     fn get_item_writer(&self, arm_id: &Eid) -> Result<Self::ItemWriter> {
         let path = arm_id.to_path_buf(&self.base);
         ensure_parents_dir(&path)?;
+        let is_new = !path.exists();
         let file = vio::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
             .open(&path)?;
-        Ok(CryptoWriter::new(file, &self.crypto, &self.key))
+        if is_new {
+            self.sync.sync_parent(&path)?;
+        }
+        Ok(CryptoWriter::new(file, &self.crypto, &self.key, self.sync))
     }
 
     fn del_arm(&self, arm_id: &Eid) -> Result<()> {
@@ -251,8 +308,14 @@ where
     type Item = T;
 
     #[inline]
-    fn set_crypto_ctx(&mut self, crypto: Crypto, key: Key) {
+    fn set_crypto_ctx(
+        &mut self,
+        crypto: Crypto,
+        prev_crypto: Option<Crypto>,
+        key: Key,
+    ) {
         self.crypto = crypto;
+        self.prev_crypto = prev_crypto;
         self.key = key;
     }
 
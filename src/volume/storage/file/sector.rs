@@ -1,5 +1,6 @@
 use std::fmt::{self, Debug};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
 use std::path::{Path, PathBuf};
 use std::u16;
 
@@ -9,21 +10,19 @@ use serde::{Deserialize, Serialize};
 
 use super::file_armor::FileArmor;
 use crate::base::crypto::{Crypto, HashKey, Key};
-use crate::base::lru::{CountMeter, Lru, PinChecker};
+use crate::base::lru::{Lru, Meter, PinChecker};
 use crate::base::utils::{ensure_parents_dir, remove_empty_parent_dir};
 use crate::base::vio;
 use crate::error::{Error, Result};
 use crate::trans::{Eid, Id};
 use crate::volume::address::Span;
 use crate::volume::storage::index_mgr::Accessor;
+use crate::volume::storage::SyncLevel;
 use crate::volume::{Arm, ArmAccess, Armor, Seq, BLK_SIZE};
 
 // how many blocks in a sector, must be 2^n and less than u16::MAX
 pub const BLKS_PER_SECTOR: usize = 4 * 1024;
 
-// sector size, in bytes
-pub const SECTOR_SIZE: usize = BLK_SIZE * BLKS_PER_SECTOR;
-
 // block deletion mark
 const BLK_DELETE_MARK: u16 = u16::MAX;
 
@@ -66,6 +65,12 @@ impl Sector {
         }
     }
 
+    // memory used by sector, in bytes
+    #[inline]
+    fn mem_size(&self) -> usize {
+        size_of::<Sector>() + self.blk_map.len() * size_of::<u16>()
+    }
+
     #[inline]
     fn is_finished(&self) -> bool {
         self.curr_size > 0
@@ -78,7 +83,7 @@ impl Sector {
     }
 
     // mark blocks as deleted
-    fn mark_blocks_deletion(&mut self, span: Span) {
+    fn mark_blocks_deletion(&mut self, span: Span, blk_size: usize) {
         let insec_idx = span.begin % BLKS_PER_SECTOR;
         let mut deleted_size = 0;
 
@@ -86,7 +91,7 @@ impl Sector {
         for idx in insec_idx..insec_idx + span.cnt {
             if self.blk_map[idx] != BLK_DELETE_MARK {
                 self.blk_map[idx] = BLK_DELETE_MARK;
-                deleted_size += BLK_SIZE;
+                deleted_size += blk_size;
             }
         }
 
@@ -146,6 +151,17 @@ impl Debug for Sector {
     }
 }
 
+// sector cache meter, measured by sector memory size
+#[derive(Debug, Default)]
+struct SectorMeter;
+
+impl Meter<Sector> for SectorMeter {
+    #[inline]
+    fn measure(&self, sec: &Sector) -> isize {
+        sec.mem_size() as isize
+    }
+}
+
 // sector manager
 pub struct SectorMgr {
     base: PathBuf,
@@ -153,12 +169,18 @@ pub struct SectorMgr {
     sec_armor: FileArmor<Sector>,
 
     // sector cache
-    sec_cache: Lru<usize, Sector, CountMeter<Sector>, PinChecker<Sector>>,
+    sec_cache: Lru<usize, Sector, SectorMeter, PinChecker<Sector>>,
 
     // sector data file cache
     sec_data_cache: LinkedHashMap<usize, vio::File>,
 
     hash_key: HashKey,
+
+    // block size, in bytes
+    blk_size: usize,
+
+    // write durability level
+    sync: SyncLevel,
 }
 
 impl SectorMgr {
@@ -170,23 +192,112 @@ impl SectorMgr {
         SectorMgr {
             base: base.to_path_buf(),
             sec_armor: FileArmor::new(base),
-            sec_cache: Lru::new(SECTOR_CACHE_SIZE),
+            sec_cache: Lru::new(SECTOR_CACHE_SIZE * Self::sector_mem_size()),
             sec_data_cache: LinkedHashMap::new(),
             hash_key: HashKey::new_empty(),
+            blk_size: BLK_SIZE,
+            sync: SyncLevel::default(),
         }
     }
 
+    // memory size of a sector in cache, in bytes
+    #[inline]
+    fn sector_mem_size() -> usize {
+        size_of::<Sector>() + BLKS_PER_SECTOR * size_of::<u16>()
+    }
+
+    // get memory used by sector cache, in bytes
+    #[inline]
+    pub fn cache_mem_used(&self) -> usize {
+        self.sec_cache.used()
+    }
+
+    // set sector cache capacity in bytes, the cache always keeps at least
+    // two sectors as the sector just opened must stay in cache
+    #[inline]
+    pub fn set_cache_mem(&mut self, capacity: usize) {
+        self.sec_cache
+            .set_capacity(capacity.max(2 * Self::sector_mem_size()));
+    }
+
+    #[inline]
+    pub fn set_block_size(&mut self, blk_size: usize) {
+        self.blk_size = blk_size;
+    }
+
+    #[inline]
+    pub fn set_sync_level(&mut self, level: SyncLevel) {
+        self.sync = level;
+        self.sec_armor.set_sync_level(level);
+    }
+
     #[inline]
     pub fn set_crypto_ctx(
         &mut self,
         crypto: Crypto,
+        prev_crypto: Option<Crypto>,
         key: Key,
         hash_key: HashKey,
     ) {
-        self.sec_armor.set_crypto_ctx(crypto, key);
+        self.sec_armor.set_crypto_ctx(crypto, prev_crypto, key);
         self.hash_key = hash_key;
     }
 
+    // get sector id from its data file path, which is named by the id in
+    // hex string
+    fn sector_data_path_to_id(path: &Path) -> Option<Eid> {
+        if path.extension()? != Self::SECTOR_DATA_EXT {
+            return None;
+        }
+        let name = path.file_stem()?.to_str()?;
+        if name.len() != Eid::EID_SIZE * 2 {
+            return None;
+        }
+        let mut buf = [0u8; Eid::EID_SIZE];
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(name.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(Eid::from_slice(&buf))
+    }
+
+    // re-encrypt all sectors with the crypto currently set, sectors are
+    // found by their data files as sector index cannot be derived from id,
+    // both arms are saved so neither is left with the previous crypto
+    pub fn recrypt(&mut self) -> Result<()> {
+        // cached sectors are always saved, drop them as their arms and
+        // sequences are changed
+        self.sec_cache.clear();
+
+        for dir in vio::read_dir(&self.base)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for sub_dir in vio::read_dir(dir.path())? {
+                let sub_dir = sub_dir?;
+                if !sub_dir.file_type()?.is_dir() {
+                    continue;
+                }
+                for entry in vio::read_dir(sub_dir.path())? {
+                    let sec_id =
+                        match Self::sector_data_path_to_id(&entry?.path()) {
+                            Some(sec_id) => sec_id,
+                            None => continue,
+                        };
+                    let mut sec = match self.sec_armor.load_item(&sec_id) {
+                        Ok(sec) => sec,
+                        Err(ref err) if *err == Error::NotFound => continue,
+                        Err(err) => return Err(err),
+                    };
+                    self.sec_armor.save_item(&mut sec)?;
+                    self.sec_armor.save_item(&mut sec)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     // convert sector index to Eid
     fn sector_idx_to_id(&self, sec_idx: usize) -> Eid {
         let buf = (sec_idx as u64).to_le_bytes();
@@ -255,11 +366,15 @@ impl SectorMgr {
                 return Err(Error::NotFound);
             }
             ensure_parents_dir(&path)?;
+            let is_new = !path.exists();
             let data_file = vio::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
                 .open(&path)?;
+            if is_new {
+                self.sync.sync_parent(&path)?;
+            }
             self.sec_data_cache.insert(sec_idx, data_file);
             if self.sec_data_cache.len() >= SECTOR_DATA_CACHE_SIZE {
                 self.sec_data_cache.pop_front();
@@ -273,7 +388,7 @@ impl SectorMgr {
 
     // read data blocks
     pub fn read_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
-        assert_eq!(dst.len(), span.bytes_len());
+        assert_eq!(dst.len(), span.bytes_len(self.blk_size));
 
         let mut read = 0;
         for sec_span in span.divide_by(BLKS_PER_SECTOR) {
@@ -289,11 +404,11 @@ impl SectorMgr {
                 {
                     return Err(Error::NotFound);
                 }
-                u64::from(insec_idx) * BLK_SIZE as u64
+                u64::from(insec_idx) * self.blk_size as u64
             };
 
             // read blocks bytes
-            let read_len = sec_span.bytes_len();
+            let read_len = sec_span.bytes_len(self.blk_size);
             sec_data.seek(SeekFrom::Start(blk_offset))?;
             sec_data.read_exact(&mut dst[read..read + read_len])?;
             read += read_len;
@@ -304,17 +419,18 @@ impl SectorMgr {
 
     // write data blocks to sector
     pub fn write_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
-        assert_eq!(blks.len(), span.bytes_len());
+        assert_eq!(blks.len(), span.bytes_len(self.blk_size));
 
         for sec_span in span.divide_by(BLKS_PER_SECTOR) {
             let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
             let mut sec_data = self.open_sector_data(sec_idx, true)?;
-            let blk_offset = (sec_span.begin % BLKS_PER_SECTOR) * BLK_SIZE;
+            let blk_offset = (sec_span.begin % BLKS_PER_SECTOR) * self.blk_size;
 
             // write blocks bytes to sector data file
-            let write_len = sec_span.bytes_len();
+            let write_len = sec_span.bytes_len(self.blk_size);
             sec_data.seek(SeekFrom::Start(blk_offset as u64))?;
             sec_data.write_all(&blks[..write_len])?;
+            self.sync.sync_file(&sec_data)?;
             blks = &blks[write_len..];
             drop(sec_data);
 
@@ -347,10 +463,11 @@ impl SectorMgr {
 
             // if we reached the end of sector, mark it as finished
             if sec_span.end() % BLKS_PER_SECTOR == 0 {
+                let blk_size = self.blk_size;
                 let is_shrinkable = {
                     let sec = self.open_sector(sec_idx, false)?;
-                    sec.curr_size = SECTOR_SIZE;
-                    sec.actual_size = BLK_SIZE
+                    sec.curr_size = blk_size * BLKS_PER_SECTOR;
+                    sec.actual_size = blk_size
                         * sec
                             .blk_map
                             .iter()
@@ -388,7 +505,7 @@ impl SectorMgr {
             .open(&dst_path)?;
 
         // copy all not deleted blocks to destination file
-        let mut buf = vec![0u8; BLK_SIZE];
+        let mut buf = vec![0u8; self.blk_size];
         let mut written_blk_cnt = 0;
         for insec_idx in sec.blk_map.iter_mut() {
             // skip deleted block
@@ -396,7 +513,7 @@ impl SectorMgr {
                 continue;
             }
 
-            let data_offset = *insec_idx as usize * BLK_SIZE;
+            let data_offset = *insec_idx as usize * self.blk_size;
             if data_offset >= sec.curr_size {
                 break;
             }
@@ -409,9 +526,10 @@ impl SectorMgr {
             *insec_idx = written_blk_cnt;
             written_blk_cnt += 1;
         }
+        self.sync.sync_file(&dst_file)?;
 
         // set sector new size, save sector and update sector in cache
-        sec.actual_size = written_blk_cnt as usize * BLK_SIZE;
+        sec.actual_size = written_blk_cnt as usize * self.blk_size;
         sec.curr_size = sec.actual_size;
         self.sec_armor.save_item(&mut sec)?;
         self.sec_cache.insert(sec.idx, sec);
@@ -420,12 +538,14 @@ impl SectorMgr {
         drop(sec_data);
         self.sec_data_cache.remove(&sec_idx);
         vio::rename(&dst_path, &data_file_path)?;
+        self.sync.sync_parent(&data_file_path)?;
 
         Ok(())
     }
 
     // delete data blocks
     pub fn del_blocks(&mut self, span: Span) -> Result<()> {
+        let blk_size = self.blk_size;
         for sec_span in span.divide_by(BLKS_PER_SECTOR) {
             let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
             let sec_id;
@@ -437,7 +557,7 @@ impl SectorMgr {
                 match self.open_sector(sec_idx, false) {
                     Ok(sec) => {
                         // mark blocks as deleted
-                        sec.mark_blocks_deletion(sec_span);
+                        sec.mark_blocks_deletion(sec_span, blk_size);
 
                         sec_id = sec.id.clone();
                         actual_size = sec.actual_size;
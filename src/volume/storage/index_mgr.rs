@@ -16,7 +16,12 @@ use crate::volume::{Arm, ArmAccess, Seq};
 pub trait Accessor: Send + Sync {
     type Item;
 
-    fn set_crypto_ctx(&mut self, crypto: Crypto, key: Key);
+    fn set_crypto_ctx(
+        &mut self,
+        crypto: Crypto,
+        prev_crypto: Option<Crypto>,
+        key: Key,
+    );
     fn load(&self, id: &Eid) -> Result<Self::Item>;
     fn save(&self, item: &mut Self::Item) -> Result<()>;
     fn remove(&self, id: &Eid) -> Result<()>;
@@ -721,17 +726,32 @@ impl IndexMgr {
         }
     }
 
-    pub fn set_crypto_ctx(&mut self, crypto: Crypto, key: Key) {
+    // set crypto context, index items encrypted with the previous crypto
+    // can still be loaded
+    pub fn set_crypto_ctx(
+        &mut self,
+        crypto: Crypto,
+        prev_crypto: Option<Crypto>,
+        key: Key,
+    ) {
         let sub_key = key.derive(Self::SUBKEY_ID_LSMT);
         *self.lsmt.id_mut() = Eid::from_slice(sub_key.derive(0).as_slice());
-        self.lsmt_armor.set_crypto_ctx(crypto.clone(), sub_key);
+        self.lsmt_armor.set_crypto_ctx(
+            crypto.clone(),
+            prev_crypto.clone(),
+            sub_key,
+        );
 
         let sub_key = key.derive(Self::SUBKEY_ID_MEMTAB);
         *self.memtab.id_mut() = Eid::from_slice(sub_key.derive(0).as_slice());
-        self.memtab_armor.set_crypto_ctx(crypto.clone(), sub_key);
+        self.memtab_armor.set_crypto_ctx(
+            crypto.clone(),
+            prev_crypto.clone(),
+            sub_key,
+        );
 
         let sub_key = key.derive(Self::SUBKEY_ID_TAB);
-        self.tab_armor.set_crypto_ctx(crypto, sub_key);
+        self.tab_armor.set_crypto_ctx(crypto, prev_crypto, sub_key);
     }
 
     pub fn init(&mut self) -> Result<()> {
@@ -792,6 +812,27 @@ impl IndexMgr {
         }
         Ok(())
     }
+
+    // re-encrypt the whole index with the crypto currently set, every item
+    // is saved twice so neither of its arms is left with the previous
+    // crypto
+    pub fn recrypt(&mut self) -> Result<()> {
+        for lvl in self.lsmt.lvls.iter() {
+            for tab_info in lvl.tabs.iter() {
+                let mut tab = self.tab_armor.load(&tab_info.id)?;
+                self.tab_armor.save(&mut tab)?;
+                self.tab_armor.save(&mut tab)?;
+            }
+        }
+
+        for _ in 0..2 {
+            self.lsmt_armor.save(&mut self.lsmt)?;
+            self.memtab_armor.save(&mut self.memtab)?;
+        }
+        self.memtab.is_changed = false;
+
+        Ok(())
+    }
 }
 
 impl Debug for IndexMgr {
@@ -4,22 +4,29 @@ use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 use log::warn;
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
 
-use crate::base::crypto::{Crypto, Key};
 use crate::base::IntoRef;
 use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CryptoCtx, Storable};
 use crate::volume::BLK_SIZE;
 
 // memory storage depot
+#[derive(Deserialize, Serialize)]
 struct Depot {
+    #[serde(skip)]
     is_opened: bool,
     super_blk_map: HashMap<u64, Vec<u8>>,
     wal_map: HashMap<Eid, Vec<u8>>,
     blk_map: HashMap<usize, Vec<u8>>,
     addr_map: HashMap<Eid, Vec<u8>>,
+
+    // bytes used by blocks and addresses
+    #[serde(skip)]
+    used: usize,
 }
 
 impl Depot {
@@ -30,8 +37,15 @@ impl Depot {
             wal_map: HashMap::new(),
             blk_map: HashMap::new(),
             addr_map: HashMap::new(),
+            used: 0,
         }
     }
+
+    // re-calculate used bytes, it is not serialized in snapshot
+    fn recount(&mut self) {
+        self.used = self.blk_map.values().map(Vec::len).sum::<usize>()
+            + self.addr_map.values().map(Vec::len).sum::<usize>();
+    }
 }
 
 lazy_static! {
@@ -45,6 +59,8 @@ lazy_static! {
 pub struct MemStorage {
     is_attached: bool, // attached to depot flag
     loc: String,
+    blk_size: usize,
+    max_size: Option<usize>, // size cap of blocks and addresses, in bytes
 }
 
 impl MemStorage {
@@ -52,6 +68,66 @@ impl MemStorage {
         MemStorage {
             is_attached: false,
             loc: loc.to_string(),
+            blk_size: BLK_SIZE,
+            max_size: None,
+        }
+    }
+
+    // create memory storage from uri location
+    // example: name[?max_size=64mb]
+    //
+    // `max_size` is the size cap of blocks and addresses, it is in bytes
+    // and can have a `kb`, `mb` or `gb` suffix.
+    pub fn from_uri(loc: &str) -> Result<Self> {
+        let (name, query) = match loc.find('?') {
+            Some(idx) => (&loc[..idx], &loc[idx + 1..]),
+            None => (loc, ""),
+        };
+        let full_uri = format!("mem://{}", loc);
+        let invalid = |reason: &str| Error::invalid_uri(&full_uri, reason);
+
+        let mut storage = MemStorage::new(name);
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let idx = param
+                .find('=')
+                .ok_or_else(|| invalid("parameter has no value"))?;
+            let value = &param[idx + 1..];
+            match &param[..idx] {
+                "max_size" => {
+                    let size = Self::parse_size(value)
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| invalid("invalid max_size"))?;
+                    storage.max_size = Some(size);
+                }
+                _ => return Err(invalid("unknown parameter")),
+            }
+        }
+        Ok(storage)
+    }
+
+    // parse size string with optional unit suffix, such as '64mb'
+    fn parse_size(value: &str) -> Option<usize> {
+        let value = value.to_lowercase();
+        let (num, unit) = if let Some(num) = value.strip_suffix("kb") {
+            (num, 1024)
+        } else if let Some(num) = value.strip_suffix("mb") {
+            (num, 1024 * 1024)
+        } else if let Some(num) = value.strip_suffix("gb") {
+            (num, 1024 * 1024 * 1024)
+        } else {
+            (value.as_str(), 1)
+        };
+        num.parse::<usize>().ok()?.checked_mul(unit)
+    }
+
+    // check if adding bytes to depot exceeds size cap
+    #[inline]
+    fn check_space(&self, depot: &Depot, adding: usize) -> Result<()> {
+        match self.max_size {
+            Some(max_size) if depot.used + adding > max_size => {
+                Err(Error::NoSpace)
+            }
+            _ => Ok(()),
         }
     }
 
@@ -82,27 +158,49 @@ impl Storable for MemStorage {
         Ok(())
     }
 
-    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
-        {
-            let mut storages = STORAGES.lock().unwrap();
-            storages.insert(self.loc.to_string(), Depot::new());
+    fn init(&mut self, _ctx: CryptoCtx) -> Result<()> {
+        // depot is added and locked at once, so a repo being created
+        // concurrently at the same location won't be replaced or opened.
+        // Depot which has no super block is not a repo, so it is replaced.
+        let mut storages = STORAGES.lock().unwrap();
+        if let Some(depot) = storages.get(&self.loc) {
+            if depot.is_opened || !depot.super_blk_map.is_empty() {
+                return Err(Error::RepoExists);
+            }
         }
-        self.lock_repo(false)
+        let mut depot = Depot::new();
+        depot.is_opened = true;
+        storages.insert(self.loc.to_string(), depot);
+        self.is_attached = true;
+        Ok(())
     }
 
     #[inline]
-    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+    fn open(&mut self, _ctx: CryptoCtx, force: bool) -> Result<()> {
         self.lock_repo(force)
     }
 
+    #[inline]
+    fn change_crypto_ctx(&mut self, _ctx: CryptoCtx) -> Result<bool> {
+        // nothing is encrypted with crypto context
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.blk_size = blk_size;
+        Ok(())
+    }
+
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         let storages = STORAGES.lock().unwrap();
         let depot = storages.get(&self.loc).ok_or(Error::NotFound)?;
-        depot
-            .super_blk_map
-            .get(&suffix)
-            .cloned()
-            .ok_or(Error::NotFound)
+        match depot.super_blk_map.get(&suffix) {
+            Some(super_blk) => Ok(super_blk.clone()),
+            // repo is locked and still being created
+            None if depot.is_opened => Err(Error::RepoOpened),
+            None => Err(Error::NotFound),
+        }
     }
 
     fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
@@ -145,6 +243,9 @@ impl Storable for MemStorage {
     fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
         let mut storages = STORAGES.lock().unwrap();
         let depot = storages.get_mut(&self.loc).unwrap();
+        let old_len = depot.addr_map.get(id).map_or(0, Vec::len);
+        self.check_space(depot, addr.len().saturating_sub(old_len))?;
+        depot.used = depot.used + addr.len() - old_len;
         depot.addr_map.insert(id.clone(), addr.to_vec());
         Ok(())
     }
@@ -152,20 +253,22 @@ impl Storable for MemStorage {
     fn del_address(&mut self, id: &Eid) -> Result<()> {
         let mut storages = STORAGES.lock().unwrap();
         let depot = storages.get_mut(&self.loc).unwrap();
-        depot.addr_map.remove(id);
+        if let Some(addr) = depot.addr_map.remove(id) {
+            depot.used -= addr.len();
+        }
         Ok(())
     }
 
     fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
-        assert_eq!(dst.len(), span.bytes_len());
+        assert_eq!(dst.len(), span.bytes_len(self.blk_size));
         let storages = STORAGES.lock().unwrap();
         let depot = storages.get(&self.loc).unwrap();
         let mut read = 0;
         for blk_idx in span {
             match depot.blk_map.get(&blk_idx) {
                 Some(blk) => {
-                    dst[read..read + BLK_SIZE].copy_from_slice(blk);
-                    read += BLK_SIZE;
+                    dst[read..read + self.blk_size].copy_from_slice(blk);
+                    read += self.blk_size;
                 }
                 None => return Err(Error::NotFound),
             }
@@ -174,12 +277,23 @@ impl Storable for MemStorage {
     }
 
     fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
-        assert_eq!(blks.len(), span.bytes_len());
+        assert_eq!(blks.len(), span.bytes_len(self.blk_size));
         let mut storages = STORAGES.lock().unwrap();
         let depot = storages.get_mut(&self.loc).unwrap();
+
+        // blocks are all written or none of them is written
+        let new_blks = span
+            .into_iter()
+            .filter(|blk_idx| !depot.blk_map.contains_key(blk_idx))
+            .count();
+        self.check_space(depot, new_blks * self.blk_size)?;
+        depot.used += new_blks * self.blk_size;
+
         for blk_idx in span {
-            depot.blk_map.insert(blk_idx, blks[..BLK_SIZE].to_vec());
-            blks = &blks[BLK_SIZE..];
+            depot
+                .blk_map
+                .insert(blk_idx, blks[..self.blk_size].to_vec());
+            blks = &blks[self.blk_size..];
         }
         Ok(())
     }
@@ -188,7 +302,9 @@ impl Storable for MemStorage {
         let mut storages = STORAGES.lock().unwrap();
         let depot = storages.get_mut(&self.loc).unwrap();
         for blk_idx in span {
-            depot.blk_map.remove(&blk_idx);
+            if let Some(blk) = depot.blk_map.remove(&blk_idx) {
+                depot.used -= blk.len();
+            }
         }
         Ok(())
     }
@@ -200,13 +316,43 @@ impl Storable for MemStorage {
 
     fn destroy(&mut self) -> Result<()> {
         let mut storages = STORAGES.lock().unwrap();
-        if let Some(depot) = storages.remove(&self.loc) {
+
+        // cannot destroy an opened repo
+        if let Some(depot) = storages.get(&self.loc) {
             if depot.is_opened {
-                warn!("Destroyed an opened repo");
+                return Err(Error::InUse);
             }
         }
+        storages.remove(&self.loc);
         Ok(())
     }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let storages = STORAGES.lock().unwrap();
+        let depot = storages.get(&self.loc).ok_or(Error::NotFound)?;
+        let mut buf = Vec::new();
+        depot.serialize(&mut Serializer::new(&mut buf))?;
+        Ok(buf)
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut de = Deserializer::new(snapshot);
+        let mut depot: Depot = Deserialize::deserialize(&mut de)?;
+        depot.recount();
+        let mut storages = STORAGES.lock().unwrap();
+        if storages.contains_key(&self.loc) {
+            return Err(Error::AlreadyExists);
+        }
+        storages.insert(self.loc.clone(), depot);
+        Ok(())
+    }
+
+    fn space_limit(&self) -> Option<(usize, usize)> {
+        let max_size = self.max_size?;
+        let storages = STORAGES.lock().unwrap();
+        let used = storages.get(&self.loc).map_or(0, |depot| depot.used);
+        Some((used, max_size))
+    }
 }
 
 impl Drop for MemStorage {
@@ -239,10 +385,42 @@ mod tests {
     use std::time::Instant;
 
     use super::*;
-    use crate::base::crypto::{Crypto, RandomSeed, RANDOM_SEED_SIZE};
+    use crate::base::crypto::{Crypto, Key, RandomSeed, RANDOM_SEED_SIZE};
     use crate::base::init_env;
     use crate::base::utils::speed_str;
 
+    #[test]
+    fn init_existing() {
+        init_env();
+        let crypto = Crypto::default();
+        let key = Key::new_empty();
+
+        // depot without super block can be initialised again
+        {
+            let mut ms = MemStorage::new("mem_init_existing");
+            ms.init(CryptoCtx::new(crypto.clone(), key.clone()))
+                .unwrap();
+            ms.put_address(&Eid::new(), &[1, 2, 3]).unwrap();
+        }
+        let mut ms = MemStorage::new("mem_init_existing");
+        ms.init(CryptoCtx::new(crypto.clone(), key.clone()))
+            .unwrap();
+        ms.put_super_block(&[1, 2, 3], 0).unwrap();
+
+        // opened depot or depot with super block cannot
+        let mut ms2 = MemStorage::new("mem_init_existing");
+        assert_eq!(
+            ms2.init(CryptoCtx::new(crypto.clone(), key.clone()))
+                .unwrap_err(),
+            Error::RepoExists
+        );
+        drop(ms);
+        assert_eq!(
+            ms2.init(CryptoCtx::new(crypto, key)).unwrap_err(),
+            Error::RepoExists
+        );
+    }
+
     #[test]
     fn test_perf() {
         init_env();
@@ -253,8 +431,9 @@ mod tests {
         let seed = RandomSeed::from(&[0u8; RANDOM_SEED_SIZE]);
         Crypto::random_buf_deterministic(&mut buf, &seed);
 
-        let mut ms = MemStorage::new("foo");
-        ms.init(Crypto::default(), Key::new_empty()).unwrap();
+        let mut ms = MemStorage::new("mem_perf");
+        ms.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
         let span = Span::new(0, BLK_CNT);
 
         // write
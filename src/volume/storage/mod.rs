@@ -1,10 +1,20 @@
 #![allow(clippy::module_inception)]
 
+mod cache_hook;
+mod crypto_ctx;
+mod read_ahead;
 mod storage;
+mod sync_level;
+mod transport_config;
 
+pub use self::cache_hook::{CacheEvent, CacheHook};
+pub use self::crypto_ctx::CryptoCtx;
 pub use self::storage::{
-    Reader, Storage, StorageRef, WalReader, WalWriter, Writer,
+    available_backends, parse_repo_uri, register_storage, Reader, RepoUri,
+    Storage, StorageFactory, StorageRef, WalReader, WalWriter, Writer,
 };
+pub use self::sync_level::SyncLevel;
+pub use self::transport_config::TransportConfig;
 
 #[cfg(feature = "storage-mem")]
 mod mem;
@@ -27,65 +37,174 @@ mod sqlite;
 #[cfg(feature = "storage-redis")]
 mod redis;
 
+#[cfg(feature = "storage-postgres")]
+mod postgres;
+
 #[cfg(feature = "storage-zbox")]
 mod zbox;
 
+#[cfg(all(feature = "storage-zbox", target_arch = "wasm32"))]
+pub use self::zbox::load_indexeddb_cache;
+
 #[cfg(any(feature = "storage-file", feature = "storage-zbox"))]
 mod index_mgr;
 
 use std::fmt::Debug;
 
-use crate::base::crypto::{Crypto, Key};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
-
-/// Storable trait
+use crate::volume::BLK_SIZE;
+
+/// Storage backend trait.
+///
+/// This is the low-level interface a repository uses to talk to its
+/// underlying storage. All data passed to a storage backend is already
+/// encrypted, so a backend only needs to keep opaque bytes keyed by super
+/// block suffix, entity id or block index.
+///
+/// Custom backends can be plugged in using [`register_storage`].
+///
+/// [`register_storage`]: fn.register_storage.html
 pub trait Storable: Debug + Send + Sync {
-    // check if storage exists
+    /// Checks if storage exists.
     fn exists(&self) -> Result<bool>;
 
-    // make connection to storage
+    /// Cheap round-trip to check storage is reachable and accessible,
+    /// storage which has a better way to do this should override it.
+    fn ping(&self) -> Result<()> {
+        if self.exists()? {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    /// Makes connection to storage.
     fn connect(&mut self, force: bool) -> Result<()>;
 
-    // initial a storage
-    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()>;
+    /// Initialises a storage.
+    fn init(&mut self, ctx: CryptoCtx) -> Result<()>;
+
+    /// Opens a storage.
+    fn open(&mut self, ctx: CryptoCtx, force: bool) -> Result<()>;
+
+    /// Re-encrypts metadata kept by the storage using `ctx` after the
+    /// repository's cipher is changed, `ctx` is used from then on.
+    ///
+    /// It can be interrupted and called again, `ctx` can still decrypt
+    /// metadata encrypted with the previous cipher. Returns false if the
+    /// storage doesn't support it, the crypto context it is opened with is
+    /// then kept. Storage which doesn't encrypt metadata using the crypto
+    /// context should override it to return true.
+    fn change_crypto_ctx(&mut self, _ctx: CryptoCtx) -> Result<bool> {
+        Ok(false)
+    }
 
-    // open a storage
-    fn open(&mut self, crypto: Crypto, key: Key, force: bool) -> Result<()>;
+    /// Sets block size before storage is initialised or opened, storage
+    /// which supports block size other than the default should override it.
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        if blk_size == BLK_SIZE {
+            Ok(())
+        } else {
+            Err(Error::InvalidArgument)
+        }
+    }
 
-    // super block read/write, must not buffered
-    // write no need to be atomic, but must gurantee any successful
-    // write is persistent
+    /// Reads super block, must not be buffered.
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>>;
+
+    /// Writes super block, must not be buffered.
+    ///
+    /// Write doesn't need to be atomic, but any successful write must be
+    /// persistent.
     fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()>;
 
-    // wal read/write, must not buffered
-    // update no need to be atomic, but must gurantee any successful update
-    // is persistent.
-    // Note: wal deletion doesn't need to be persistent and can be buffered
+    /// Reads wal, must not be buffered.
     fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>>;
+
+    /// Writes wal, must not be buffered.
+    ///
+    /// Update doesn't need to be atomic, but any successful update must be
+    /// persistent.
     fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()>;
+
+    /// Deletes wal, deletion doesn't need to be persistent and can be
+    /// buffered.
     fn del_wal(&mut self, id: &Eid) -> Result<()>;
 
-    // address read/write, can be buffered
-    // storage doesn't need to gurantee update is persistent
+    /// Reads address, can be buffered.
     fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>>;
+
+    /// Writes address, can be buffered and doesn't need to be persistent
+    /// until [`flush`](#tymethod.flush).
     fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()>;
+
+    /// Deletes address, can be buffered.
     fn del_address(&mut self, id: &Eid) -> Result<()>;
 
-    // block read/write, can be buffered
-    // storage doesn't need to gurantee update is persistent
+    /// Reads blocks in `span` to `dst`, can be buffered.
     fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()>;
+
+    /// Writes blocks in `span`, can be buffered and doesn't need to be
+    /// persistent until [`flush`](#tymethod.flush).
     fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()>;
+
+    /// Deletes blocks in `span`, can be buffered.
     fn del_blocks(&mut self, span: Span) -> Result<()>;
 
-    // flush possibly buffered wal, address and block to storage,
-    // storage must gurantee write is persistent
+    /// Flushes possibly buffered wal, address and block to storage, storage
+    /// must guarantee write is persistent.
     fn flush(&mut self) -> Result<()>;
 
-    // permanently destroy this storage
+    /// Permanently destroys this storage.
     fn destroy(&mut self) -> Result<()>;
+
+    /// Sets cache event hook, storage which has its own cache should report
+    /// cache events to it.
+    fn set_cache_hook(&mut self, _hook: CacheHook) {}
+
+    /// Sets network transport config before storage is connected, storage
+    /// which talks to remote over network should override it.
+    fn set_transport_config(&mut self, _cfg: &TransportConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Sets write durability level before storage is initialised or
+    /// opened, storage on local file system should override it.
+    fn set_sync_level(&mut self, _level: SyncLevel) {}
+
+    /// Gets used bytes and maximum bytes the storage can hold, only storage
+    /// which has a size cap supports it.
+    fn space_limit(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Gets used bytes, capacity in bytes and number of objects of local
+    /// cache, only storage which caches remote objects locally supports it.
+    fn local_cache_usage(&self) -> Result<(usize, usize, usize)> {
+        Err(Error::InvalidArgument)
+    }
+
+    /// Gets memory used by in-memory caches of the storage, in bytes.
+    fn cache_mem_used(&self) -> usize {
+        0
+    }
+
+    /// Sets memory capacity of in-memory caches of the storage, in bytes,
+    /// storage which has its own in-memory cache should override it.
+    fn set_cache_mem(&mut self, _capacity: usize) {}
+
+    /// Dumps the whole storage to an opaque snapshot, only storage which
+    /// keeps everything in memory supports it.
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Err(Error::InvalidArgument)
+    }
+
+    /// Loads a snapshot made by [`snapshot`](#method.snapshot).
+    fn restore(&mut self, _snapshot: &[u8]) -> Result<()> {
+        Err(Error::InvalidArgument)
+    }
 }
 
 /// Dummy storage
@@ -104,12 +223,12 @@ impl Storable for DummyStorage {
     }
 
     #[inline]
-    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+    fn init(&mut self, _ctx: CryptoCtx) -> Result<()> {
         unimplemented!()
     }
 
     #[inline]
-    fn open(&mut self, _crypto: Crypto, _key: Key, _force: bool) -> Result<()> {
+    fn open(&mut self, _ctx: CryptoCtx, _force: bool) -> Result<()> {
         unimplemented!()
     }
 
@@ -0,0 +1,556 @@
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+
+use log::warn;
+use postgres::types::ToSql;
+use postgres::{Client, Config, Error as PostgresError, NoTls, Statement};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+
+use crate::error::{Error, Result};
+use crate::trans::Eid;
+use crate::volume::address::Span;
+use crate::volume::storage::{CryptoCtx, Storable};
+use crate::volume::BLK_SIZE;
+
+// sql statements, statement index is its position in this list
+fn sqls() -> Vec<String> {
+    let tbl_lock = PostgresStorage::TBL_REPO_LOCK;
+    let tbl_super_blk = PostgresStorage::TBL_SUPER_BLOCK;
+    let tbl_wals = PostgresStorage::TBL_WALS;
+    let tbl_addrs = PostgresStorage::TBL_ADDRESSES;
+    let tbl_blks = PostgresStorage::TBL_BLOCKS;
+
+    vec![
+        // repo lock sql
+        format!("SELECT lock FROM {} WHERE lock = 1", tbl_lock),
+        format!(
+            "INSERT INTO {}(lock) VALUES (1) ON CONFLICT DO NOTHING",
+            tbl_lock
+        ),
+        format!("DELETE FROM {} WHERE lock = 1", tbl_lock),
+        // super block sql
+        format!("SELECT data FROM {} WHERE suffix = $1", tbl_super_blk),
+        format!(
+            "INSERT INTO {}(suffix, data) VALUES ($1, $2)
+             ON CONFLICT (suffix) DO UPDATE SET data = EXCLUDED.data",
+            tbl_super_blk
+        ),
+        // wal sql
+        format!("SELECT data FROM {} WHERE id = $1", tbl_wals),
+        format!(
+            "INSERT INTO {}(id, data) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data",
+            tbl_wals
+        ),
+        format!("DELETE FROM {} WHERE id = $1", tbl_wals),
+        // addresses sql
+        format!("SELECT data FROM {} WHERE id = $1", tbl_addrs),
+        format!(
+            "INSERT INTO {}(id, data) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data",
+            tbl_addrs
+        ),
+        format!("DELETE FROM {} WHERE id = $1", tbl_addrs),
+        // blocks sql
+        format!(
+            "SELECT data FROM {} WHERE blk_idx >= $1 AND blk_idx < $2
+             ORDER BY blk_idx",
+            tbl_blks
+        ),
+        format!("INSERT INTO {}(blk_idx, data) VALUES ($1, $2)", tbl_blks),
+        format!(
+            "DELETE FROM {} WHERE blk_idx >= $1 AND blk_idx < $2",
+            tbl_blks
+        ),
+    ]
+}
+
+// pooled connection and the statements prepared on it
+struct Conn {
+    client: Client,
+    stmts: Vec<Statement>,
+}
+
+impl Conn {
+    // prepare and cache all sql statements on this connection if they are
+    // not prepared yet, tables must exist
+    fn prepare_stmts(&mut self) -> Result<()> {
+        if !self.stmts.is_empty() {
+            return Ok(());
+        }
+
+        let mut stmts = Vec::new();
+        for sql in sqls() {
+            stmts.push(self.client.prepare(&sql)?);
+        }
+        self.stmts = stmts;
+
+        Ok(())
+    }
+
+    // get a prepared statement
+    #[inline]
+    fn stmt(&mut self, stmt_idx: usize) -> Result<Statement> {
+        self.prepare_stmts()?;
+        Ok(self.stmts[stmt_idx].clone())
+    }
+}
+
+// connection manager of the pool, statements are prepared lazily on each
+// connection and kept with it while it is in the pool
+struct Manager(PostgresConnectionManager<NoTls>);
+
+impl ManageConnection for Manager {
+    type Connection = Conn;
+    type Error = PostgresError;
+
+    fn connect(&self) -> std::result::Result<Conn, PostgresError> {
+        self.0.connect().map(|client| Conn {
+            client,
+            stmts: Vec::new(),
+        })
+    }
+
+    fn is_valid(
+        &self,
+        conn: &mut Conn,
+    ) -> std::result::Result<(), PostgresError> {
+        self.0.is_valid(&mut conn.client)
+    }
+
+    fn has_broken(&self, conn: &mut Conn) -> bool {
+        self.0.has_broken(&mut conn.client)
+    }
+}
+
+/// Postgres Storage
+///
+/// Connections are pooled, each storage operation checks out a connection
+/// from the pool, so concurrent operations are not serialized on a single
+/// connection. Statements are prepared once on each connection.
+pub struct PostgresStorage {
+    is_attached: bool, // attached to postgres db
+    pool: Pool<Manager>,
+    blk_size: usize,
+}
+
+impl PostgresStorage {
+    // table name constants
+    const TBL_REPO_LOCK: &'static str = "repo_lock";
+    const TBL_SUPER_BLOCK: &'static str = "super_block";
+    const TBL_WALS: &'static str = "wals";
+    const TBL_ADDRESSES: &'static str = "addresses";
+    const TBL_BLOCKS: &'static str = "blocks";
+
+    // max number of connections in pool
+    const POOL_SIZE: u32 = 4;
+
+    pub fn new(path: &str) -> Result<Self> {
+        // url format:
+        // postgres://[<user>[:<passwd>]@]<hostname>[:port][/<db>][?<params>]
+        let config = Config::from_str(&format!("postgres://{}", path))?;
+        let manager = Manager(PostgresConnectionManager::new(config, NoTls));
+
+        // connections are made lazily, so no connection is made here. A
+        // connection is checked out for each operation, so it is not
+        // validated on every check out.
+        let pool = Pool::builder()
+            .max_size(Self::POOL_SIZE)
+            .test_on_check_out(false)
+            .build_unchecked(manager);
+
+        Ok(PostgresStorage {
+            is_attached: false,
+            pool,
+            blk_size: BLK_SIZE,
+        })
+    }
+
+    // check out a connection from pool
+    #[inline]
+    fn get_conn(&self) -> Result<PooledConnection<Manager>> {
+        Ok(self.pool.get()?)
+    }
+
+    // run SELECT statement on a bytea column, return the first row
+    fn select_blob(
+        &self,
+        stmt_idx: usize,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<u8>> {
+        let mut conn = self.get_conn()?;
+        let stmt = conn.stmt(stmt_idx)?;
+        match conn.client.query_opt(&stmt, params)? {
+            Some(row) => Ok(row.get(0)),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    // run DML statement, such as INSERT and DELETE
+    fn run_dml(
+        &self,
+        stmt_idx: usize,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<()> {
+        let mut conn = self.get_conn()?;
+        let stmt = conn.stmt(stmt_idx)?;
+        conn.client.execute(&stmt, params)?;
+        Ok(())
+    }
+
+    // check if repo lock exists
+    fn is_locked(conn: &mut Conn) -> Result<bool> {
+        let stmt = conn.stmt(0)?;
+        let row = conn.client.query_opt(&stmt, &[])?;
+        Ok(row.is_some())
+    }
+
+    fn lock_repo(&mut self, force: bool) -> Result<()> {
+        // insert lock row atomically, no row inserted means repo is locked
+        let inserted = {
+            let mut conn = self.get_conn()?;
+            let stmt = conn.stmt(1)?;
+            conn.client.execute(&stmt, &[])?
+        };
+        if inserted == 0 {
+            // repo is locked
+            if force {
+                warn!("Repo was locked, forced to open");
+            } else {
+                return Err(Error::RepoOpened);
+            }
+        }
+        self.is_attached = true;
+        Ok(())
+    }
+}
+
+impl Storable for PostgresStorage {
+    fn exists(&self) -> Result<bool> {
+        // check super block existence to determine if repo exists
+        let mut conn = self.get_conn()?;
+        let client = &mut conn.client;
+        let row = client.query_one(
+            "SELECT to_regclass($1::text) IS NOT NULL",
+            &[&Self::TBL_SUPER_BLOCK],
+        )?;
+        if !row.get::<_, bool>(0) {
+            return Ok(false);
+        }
+        let sql =
+            format!("SELECT 1 FROM {} WHERE suffix = 0", Self::TBL_SUPER_BLOCK);
+        let row = client.query_opt(sql.as_str(), &[])?;
+        Ok(row.is_some())
+    }
+
+    fn connect(&mut self, _force: bool) -> Result<()> {
+        // make sure database can be connected
+        self.get_conn()?;
+        Ok(())
+    }
+
+    fn init(&mut self, _ctx: CryptoCtx) -> Result<()> {
+        // create tables
+        let sql = format!(
+            "
+            CREATE TABLE {} (
+                lock        INTEGER PRIMARY KEY
+            );
+            CREATE TABLE {} (
+                suffix      BIGINT PRIMARY KEY,
+                data        BYTEA
+            );
+            CREATE TABLE {} (
+                id          TEXT PRIMARY KEY,
+                data        BYTEA
+            );
+            CREATE TABLE {} (
+                id          TEXT PRIMARY KEY,
+                data        BYTEA
+            );
+            CREATE TABLE {} (
+                blk_idx     BIGINT PRIMARY KEY,
+                data        BYTEA
+            );
+        ",
+            Self::TBL_REPO_LOCK,
+            Self::TBL_SUPER_BLOCK,
+            Self::TBL_WALS,
+            Self::TBL_ADDRESSES,
+            Self::TBL_BLOCKS
+        );
+        self.get_conn()?.client.batch_execute(&sql)?;
+        self.lock_repo(false)
+    }
+
+    #[inline]
+    fn open(&mut self, _ctx: CryptoCtx, force: bool) -> Result<()> {
+        self.lock_repo(force)
+    }
+
+    #[inline]
+    fn change_crypto_ctx(&mut self, _ctx: CryptoCtx) -> Result<bool> {
+        // nothing is encrypted with crypto context
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.blk_size = blk_size;
+        Ok(())
+    }
+
+    #[inline]
+    fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
+        self.select_blob(3, &[&(suffix as i64)])
+    }
+
+    #[inline]
+    fn put_super_block(&mut self, super_blk: &[u8], suffix: u64) -> Result<()> {
+        self.run_dml(4, &[&(suffix as i64), &super_blk])
+    }
+
+    #[inline]
+    fn get_wal(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.select_blob(5, &[&id.to_string()])
+    }
+
+    #[inline]
+    fn put_wal(&mut self, id: &Eid, wal: &[u8]) -> Result<()> {
+        self.run_dml(6, &[&id.to_string(), &wal])
+    }
+
+    #[inline]
+    fn del_wal(&mut self, id: &Eid) -> Result<()> {
+        self.run_dml(7, &[&id.to_string()])
+    }
+
+    #[inline]
+    fn get_address(&mut self, id: &Eid) -> Result<Vec<u8>> {
+        self.select_blob(8, &[&id.to_string()])
+    }
+
+    #[inline]
+    fn put_address(&mut self, id: &Eid, addr: &[u8]) -> Result<()> {
+        self.run_dml(9, &[&id.to_string(), &addr])
+    }
+
+    #[inline]
+    fn del_address(&mut self, id: &Eid) -> Result<()> {
+        self.run_dml(10, &[&id.to_string()])
+    }
+
+    fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
+        let mut conn = self.get_conn()?;
+
+        // read all blocks in the span in one query
+        let stmt = conn.stmt(11)?;
+        let rows = conn
+            .client
+            .query(&stmt, &[&(span.begin as i64), &(span.end() as i64)])?;
+        if rows.len() != span.cnt {
+            return Err(Error::NotFound);
+        }
+
+        let mut read = 0;
+        for row in rows {
+            let blk: &[u8] = row.get(0);
+            assert_eq!(blk.len(), self.blk_size);
+            dst[read..read + self.blk_size].copy_from_slice(blk);
+            read += self.blk_size;
+        }
+
+        Ok(())
+    }
+
+    fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
+        let mut conn = self.get_conn()?;
+
+        // write all blocks in the span in one transaction
+        let stmt = conn.stmt(12)?;
+        let mut tx = conn.client.transaction()?;
+        for blk_idx in span {
+            tx.execute(&stmt, &[&(blk_idx as i64), &&blks[..self.blk_size]])?;
+            blks = &blks[self.blk_size..];
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn del_blocks(&mut self, span: Span) -> Result<()> {
+        self.run_dml(13, &[&(span.begin as i64), &(span.end() as i64)])
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        // statements cannot be prepared if tables don't exist
+        let mut conn = self.get_conn()?;
+        if conn.prepare_stmts().is_ok() && Self::is_locked(&mut conn)? {
+            // repo is locked, cannot destroy an opened repo
+            return Err(Error::InUse);
+        }
+
+        let sql = format!(
+            "DROP TABLE IF EXISTS {}, {}, {}, {}, {}",
+            Self::TBL_REPO_LOCK,
+            Self::TBL_SUPER_BLOCK,
+            Self::TBL_WALS,
+            Self::TBL_ADDRESSES,
+            Self::TBL_BLOCKS
+        );
+        conn.stmts.clear();
+        conn.client.batch_execute(&sql)?;
+
+        // repo lock is dropped along with the tables
+        self.is_attached = false;
+        Ok(())
+    }
+}
+
+impl Drop for PostgresStorage {
+    fn drop(&mut self) {
+        if self.is_attached {
+            // remove repo lock and ignore errors
+            let _ = self.run_dml(2, &[]);
+            self.is_attached = false;
+        }
+    }
+}
+
+impl Debug for PostgresStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PostgresStorage").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::base::crypto::{Crypto, Key};
+    use crate::base::init_env;
+
+    // run a local postgres instance before test, for example,
+    // $ docker run -d --name some-postgres -p 5432:5432 \
+    //     -e POSTGRES_PASSWORD=pwd postgres
+    #[test]
+    fn postgres_storage() {
+        init_env();
+        let path = "postgres:pwd@127.0.0.1/postgres";
+        let mut ps = PostgresStorage::new(path).unwrap();
+        ps.destroy().unwrap();
+        drop(ps);
+
+        let mut ps = PostgresStorage::new(path).unwrap();
+        assert!(!ps.exists().unwrap());
+        ps.connect(false).unwrap();
+        ps.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
+        assert!(!ps.exists().unwrap());
+
+        let id = Eid::new();
+        let buf = vec![1, 2, 3];
+        let blks = vec![42u8; BLK_SIZE * 3];
+        let mut dst = vec![0u8; BLK_SIZE * 3];
+
+        // super block
+        ps.put_super_block(&buf, 0).unwrap();
+        let s = ps.get_super_block(0).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        assert!(ps.exists().unwrap());
+
+        // wal
+        ps.put_wal(&id, &buf).unwrap();
+        let s = ps.get_wal(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ps.del_wal(&id).unwrap();
+        assert_eq!(ps.get_wal(&id).unwrap_err(), Error::NotFound);
+
+        // address
+        ps.put_address(&id, &buf).unwrap();
+        let s = ps.get_address(&id).unwrap();
+        assert_eq!(&s[..], &buf[..]);
+        ps.del_address(&id).unwrap();
+        assert_eq!(ps.get_address(&id).unwrap_err(), Error::NotFound);
+
+        // block
+        let span = Span::new(0, 3);
+        ps.put_blocks(span, &blks).unwrap();
+        ps.get_blocks(&mut dst, span).unwrap();
+        assert_eq!(&dst[..], &blks[..]);
+        ps.del_blocks(Span::new(1, 2)).unwrap();
+        assert_eq!(ps.get_blocks(&mut dst, span).unwrap_err(), Error::NotFound);
+        assert_eq!(
+            ps.get_blocks(&mut dst[..BLK_SIZE], Span::new(1, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // readers check out their own connections from pool, so they are
+        // not serialized on a single connection
+        ps.put_address(&id, &buf).unwrap();
+        let ps = Arc::new(ps);
+        let start = Instant::now();
+        let rdrs: Vec<_> = (0..2)
+            .map(|_| {
+                let ps = ps.clone();
+                let id = id.clone();
+                thread::spawn(move || {
+                    let mut conn = ps.get_conn().unwrap();
+                    conn.client.batch_execute("SELECT pg_sleep(0.5)").unwrap();
+                    ps.select_blob(8, &[&id.to_string()]).unwrap()
+                })
+            })
+            .collect();
+        for rdr in rdrs {
+            assert_eq!(rdr.join().unwrap(), buf);
+        }
+        assert!(start.elapsed() < Duration::from_millis(1000));
+        let mut ps = Arc::try_unwrap(ps).ok().unwrap();
+        ps.del_address(&id).unwrap();
+
+        // repo is locked
+        let mut ps2 = PostgresStorage::new(path).unwrap();
+        ps2.connect(false).unwrap();
+        assert_eq!(
+            ps2.open(
+                CryptoCtx::new(Crypto::default(), Key::new_empty()),
+                false
+            )
+            .unwrap_err(),
+            Error::RepoOpened
+        );
+        drop(ps2);
+
+        // re-open
+        drop(ps);
+        let mut ps = PostgresStorage::new(path).unwrap();
+        ps.connect(false).unwrap();
+        ps.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
+
+        ps.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
+            .unwrap();
+        assert_eq!(&dst[..BLK_SIZE], &blks[..BLK_SIZE]);
+        assert_eq!(
+            ps.get_blocks(&mut dst[..BLK_SIZE], Span::new(2, 1))
+                .unwrap_err(),
+            Error::NotFound
+        );
+
+        // repo must be closed before it can be destroyed
+        assert_eq!(ps.destroy().unwrap_err(), Error::InUse);
+        drop(ps);
+        PostgresStorage::new(path).unwrap().destroy().unwrap();
+    }
+}
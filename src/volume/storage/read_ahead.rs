@@ -0,0 +1,112 @@
+use std::fmt::{self, Debug};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::base::crypto::{Crypto, Key};
+use crate::error::Result;
+
+/// Decrypted frame buffer and its data length
+pub type DecFrame = Result<(Vec<u8>, usize)>;
+
+// frame decryption job
+struct Job {
+    crypto: Crypto,
+    key: Arc<Key>,
+    ad: Arc<Vec<u8>>,
+    frame: Vec<u8>,
+    dec_frame_size: usize,
+    tx: Sender<DecFrame>,
+}
+
+impl Job {
+    fn run(self) {
+        let mut dec_frame = vec![0u8; self.dec_frame_size];
+        let result = self
+            .crypto
+            .decrypt_raw(&mut dec_frame, &self.frame, &self.key, &self.ad)
+            .map(|len| (dec_frame, len));
+
+        // receiver might have gone if the reader is dropped or moved to
+        // other position, the result is simply discarded then
+        let _ = self.tx.send(result);
+    }
+}
+
+/// Frame decryption thread pool for read-ahead
+///
+/// Frames are decrypted on worker threads, each frame's result is delivered
+/// through its own channel so the reader can consume them in order.
+pub struct DecryptPool {
+    tx: Mutex<Option<Sender<Job>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl DecryptPool {
+    pub fn new(threads: usize) -> Self {
+        let (tx, rx) = channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || loop {
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job.run(),
+                        Err(_) => break, // pool is dropped
+                    }
+                })
+            })
+            .collect();
+
+        DecryptPool {
+            tx: Mutex::new(Some(tx)),
+            workers,
+        }
+    }
+
+    /// Decrypt an encrypted frame on worker thread
+    pub fn decrypt(
+        &self,
+        crypto: &Crypto,
+        key: &Arc<Key>,
+        ad: &Arc<Vec<u8>>,
+        frame: Vec<u8>,
+        dec_frame_size: usize,
+    ) -> Receiver<DecFrame> {
+        let (tx, rx) = channel();
+        let job = Job {
+            crypto: crypto.clone(),
+            key: key.clone(),
+            ad: ad.clone(),
+            frame,
+            dec_frame_size,
+            tx,
+        };
+
+        // if the job cannot be sent, its sender is dropped along with it
+        // and the receiver will get an error
+        if let Some(ref pool_tx) = *self.tx.lock().unwrap() {
+            let _ = pool_tx.send(job);
+        }
+        rx
+    }
+}
+
+impl Drop for DecryptPool {
+    fn drop(&mut self) {
+        // close the job channel and wait for workers to exit
+        self.tx.lock().unwrap().take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Debug for DecryptPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecryptPool")
+            .field("threads", &self.workers.len())
+            .finish()
+    }
+}
@@ -1,15 +1,18 @@
 use std::fmt::{self, Debug};
-use std::sync::Mutex;
 
 use log::warn;
-use redis::{self, Client, Commands, Connection};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use redis::cluster::{ClusterClient, ClusterConnection};
+use redis::{
+    self, Client, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike,
+    RedisError,
+};
 
-use crate::base::crypto::{Crypto, Key};
 use crate::base::IntoRef;
 use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CryptoCtx, Storable};
 use crate::volume::BLK_SIZE;
 
 // redis key for repo lock
@@ -42,66 +45,271 @@ fn blk_key(blk_idx: usize) -> String {
     format!("block:{}", blk_idx)
 }
 
+// parse uri to redis connection urls of all nodes, pool size and whether it
+// is a redis cluster
+fn parse_uri(path: &str, tls: bool) -> Result<(Vec<String>, u32, bool)> {
+    let scheme = if tls { "rediss" } else { "redis" };
+    let full_uri = format!("{}://{}", scheme, path);
+    let invalid = |reason: &str| Error::invalid_uri(&full_uri, reason);
+
+    let (location, query) = match path.find('?') {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => (path, ""),
+    };
+
+    // parse parameters, parameters other than pool and cluster are passed
+    // to redis as they are
+    let mut pool_size = RedisStorage::POOL_SIZE;
+    let mut cluster = false;
+    let mut params = Vec::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let idx = param
+            .find('=')
+            .ok_or_else(|| invalid("parameter has no value"))?;
+        let value = &param[idx + 1..];
+        match &param[..idx] {
+            "pool" => {
+                pool_size = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| invalid("invalid pool"))?;
+            }
+            "cluster" => {
+                cluster = value
+                    .parse::<bool>()
+                    .map_err(|_| invalid("invalid cluster"))?;
+            }
+            _ => params.push(param),
+        }
+    }
+    let query = if params.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", params.join("&"))
+    };
+
+    // unix socket
+    if let Some(p) = location.strip_prefix("+unix+") {
+        if tls || cluster {
+            return Err(invalid("unix socket doesn't support tls or cluster"));
+        }
+        return Ok((
+            vec![format!("redis+unix:///{}{}", p, query)],
+            pool_size,
+            false,
+        ));
+    }
+
+    // cluster nodes are separated by comma
+    let hosts: Vec<&str> = if cluster {
+        location.split(',').collect()
+    } else {
+        vec![location]
+    };
+    if hosts.iter().any(|host| host.is_empty()) {
+        return Err(invalid("empty host"));
+    }
+    let nodes = hosts
+        .iter()
+        .map(|host| format!("{}://{}{}", scheme, host, query))
+        .collect();
+
+    Ok((nodes, pool_size, cluster))
+}
+
+// parse output of CLUSTER NODES command, return address of master nodes
+//
+// each line is: <id> <ip:port@cport[,hostname]> <flags> <master> ..., flags
+// are comma separated, failed masters and masters without address are
+// skipped as they cannot be connected
+fn parse_cluster_masters(nodes: &str) -> Vec<(String, u16)> {
+    nodes
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = fields.nth(1)?;
+            let flags: Vec<&str> = fields.next()?.split(',').collect();
+            if !flags.contains(&"master")
+                || flags
+                    .iter()
+                    .any(|flag| *flag == "fail" || *flag == "noaddr")
+            {
+                return None;
+            }
+            let addr = addr.split(['@', ',']).next()?;
+            let idx = addr.rfind(':')?;
+            let port = addr[idx + 1..].parse::<u16>().ok()?;
+            Some((addr[..idx].to_string(), port))
+        })
+        .collect()
+}
+
+// connection to a single redis server or a redis cluster
+enum Conn {
+    Single(Connection),
+    Cluster(ClusterConnection),
+}
+
+impl Conn {
+    #[inline]
+    fn as_conn(&mut self) -> &mut dyn ConnectionLike {
+        match self {
+            Conn::Single(ref mut conn) => conn,
+            Conn::Cluster(ref mut conn) => conn,
+        }
+    }
+}
+
+// connection manager of the pool
+enum Manager {
+    Single(Client),
+    Cluster(ClusterClient),
+}
+
+impl ManageConnection for Manager {
+    type Connection = Conn;
+    type Error = RedisError;
+
+    fn connect(&self) -> std::result::Result<Conn, RedisError> {
+        match self {
+            Manager::Single(ref client) => {
+                client.get_connection().map(Conn::Single)
+            }
+            Manager::Cluster(ref client) => {
+                client.get_connection().map(Conn::Cluster)
+            }
+        }
+    }
+
+    fn is_valid(&self, conn: &mut Conn) -> std::result::Result<(), RedisError> {
+        redis::cmd("PING").query(conn.as_conn())
+    }
+
+    fn has_broken(&self, conn: &mut Conn) -> bool {
+        !conn.as_conn().is_open()
+    }
+}
+
 /// Redis Storage
+///
+/// Connections are pooled, each storage operation checks out a connection
+/// from the pool, so concurrent operations are not serialized on a single
+/// connection.
 pub struct RedisStorage {
-    is_attached: bool, // attached to redis
-    client: Client,
-    conn: Option<Mutex<Connection>>,
+    is_attached: bool,  // attached to redis
+    nodes: Vec<String>, // connection urls of seed nodes
+    cluster: bool,      // connect to redis cluster
+    pool: Pool<Manager>,
+    blk_size: usize,
 }
 
 impl RedisStorage {
-    pub fn new(path: &str) -> Result<Self> {
+    // default max number of connections in pool
+    const POOL_SIZE: u32 = 4;
+
+    pub fn new(path: &str, tls: bool) -> Result<Self> {
         // url format:
-        // redis://[:<passwd>@]<hostname>[:port][/<db>]
-        // redis+unix:///[:<passwd>@]<path>[?db=<db>]
-        let url = if let Some(p) = path.strip_prefix("+unix+") {
-            format!("redis+unix:///{}", p)
+        // redis[s]://[:<passwd>@]<hostname>[:port][/<db>][?<params>]
+        // redis[s]://<node>[,<node>...]?cluster=true[&<params>]
+        // redis://+unix+[:<passwd>@]<path>[?db=<db>][&<params>]
+        let (nodes, pool_size, cluster) = parse_uri(path, tls)?;
+        let manager = if cluster {
+            let nodes = nodes.iter().map(String::as_str).collect();
+            Manager::Cluster(ClusterClient::open(nodes)?)
         } else {
-            format!("redis://{}", path)
+            Manager::Single(Client::open(nodes[0].as_str())?)
         };
-        let client = Client::open(url.as_str())?;
+
+        // connections are made lazily, so no connection is made here. A
+        // connection is checked out for each operation, so it is not pinged
+        // on every check out.
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .test_on_check_out(false)
+            .build_unchecked(manager);
 
         Ok(RedisStorage {
             is_attached: false,
-            client,
-            conn: None,
+            nodes,
+            cluster,
+            pool,
+            blk_size: BLK_SIZE,
         })
     }
 
+    // check out a connection from pool
+    #[inline]
+    fn get_conn(&self) -> Result<PooledConnection<Manager>> {
+        self.pool.get().map_err(Error::RedisPool)
+    }
+
     fn get_bytes(&self, key: &str) -> Result<Vec<u8>> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                if !conn.exists::<&str, bool>(key)? {
-                    return Err(Error::NotFound);
-                }
-                let ret = conn.get(key)?;
-                Ok(ret)
-            }
-            None => unreachable!(),
-        }
+        let mut conn = self.get_conn()?;
+        redis::cmd("GET")
+            .arg(key)
+            .query::<Option<Vec<u8>>>(conn.as_conn())?
+            .ok_or(Error::NotFound)
     }
 
     fn set_bytes(&self, key: &str, val: &[u8]) -> Result<()> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                conn.set(key, val)?;
-                Ok(())
-            }
-            None => unreachable!(),
-        }
+        let mut conn = self.get_conn()?;
+        redis::cmd("SET")
+            .arg(key)
+            .arg(val)
+            .query::<()>(conn.as_conn())?;
+        Ok(())
     }
 
     fn del(&self, key: &str) -> Result<()> {
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                conn.del(key)?;
-                Ok(())
+        let mut conn = self.get_conn()?;
+        redis::cmd("DEL").arg(key).query::<()>(conn.as_conn())?;
+        Ok(())
+    }
+
+    // get connection info of all master nodes, which are queried from the
+    // first available seed node in cluster mode
+    fn masters(&self) -> Result<Vec<ConnectionInfo>> {
+        let seed = Client::open(self.nodes[0].as_str())?;
+        if !self.cluster {
+            return Ok(vec![seed.get_connection_info().clone()]);
+        }
+
+        let mut last_err = None;
+        for node in self.nodes.iter() {
+            let client = Client::open(node.as_str())?;
+            let nodes = client.get_connection().and_then(|mut conn| {
+                redis::cmd("CLUSTER")
+                    .arg("NODES")
+                    .query::<String>(&mut conn)
+            });
+            match nodes {
+                Ok(nodes) => {
+                    let info = client.get_connection_info();
+                    return Ok(parse_cluster_masters(&nodes)
+                        .into_iter()
+                        .map(|(host, port)| {
+                            let addr = match info.addr {
+                                ConnectionAddr::TcpTls { insecure, .. } => {
+                                    ConnectionAddr::TcpTls {
+                                        host,
+                                        port,
+                                        insecure,
+                                    }
+                                }
+                                _ => ConnectionAddr::Tcp(host, port),
+                            };
+                            ConnectionInfo {
+                                addr,
+                                redis: info.redis.clone(),
+                            }
+                        })
+                        .collect());
+                }
+                Err(err) => last_err = Some(err),
             }
-            None => unreachable!(),
         }
+        Err(Error::from(last_err.unwrap()))
     }
 
     fn lock_repo(&mut self, force: bool) -> Result<()> {
@@ -127,27 +335,42 @@ impl RedisStorage {
 impl Storable for RedisStorage {
     fn exists(&self) -> Result<bool> {
         // check super block existence to determine if repo exists
-        let mut conn = self.client.get_connection()?;
+        let mut conn = self.get_conn()?;
         let key = super_blk_key(0);
-        conn.exists::<&str, bool>(&key).map_err(Error::from)
+        redis::cmd("EXISTS")
+            .arg(key)
+            .query::<bool>(conn.as_conn())
+            .map_err(Error::from)
     }
 
     fn connect(&mut self, _force: bool) -> Result<()> {
-        let conn = self.client.get_connection()?;
-        self.conn = Some(Mutex::new(conn));
+        // make sure redis can be connected
+        self.get_conn()?;
         Ok(())
     }
 
     #[inline]
-    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+    fn init(&mut self, _ctx: CryptoCtx) -> Result<()> {
         self.lock_repo(false)
     }
 
     #[inline]
-    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+    fn open(&mut self, _ctx: CryptoCtx, force: bool) -> Result<()> {
         self.lock_repo(force)
     }
 
+    #[inline]
+    fn change_crypto_ctx(&mut self, _ctx: CryptoCtx) -> Result<bool> {
+        // nothing is encrypted with crypto context
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.blk_size = blk_size;
+        Ok(())
+    }
+
     #[inline]
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         let key = super_blk_key(suffix);
@@ -201,9 +424,9 @@ impl Storable for RedisStorage {
         for blk_idx in span {
             let key = blk_key(blk_idx);
             let blk = self.get_bytes(&key)?;
-            assert_eq!(blk.len(), BLK_SIZE);
-            dst[read..read + BLK_SIZE].copy_from_slice(&blk);
-            read += BLK_SIZE;
+            assert_eq!(blk.len(), self.blk_size);
+            dst[read..read + self.blk_size].copy_from_slice(&blk);
+            read += self.blk_size;
         }
 
         Ok(())
@@ -212,8 +435,8 @@ impl Storable for RedisStorage {
     fn put_blocks(&mut self, span: Span, mut blks: &[u8]) -> Result<()> {
         for blk_idx in span {
             let key = blk_key(blk_idx);
-            self.set_bytes(&key, &blks[..BLK_SIZE])?;
-            blks = &blks[BLK_SIZE..];
+            self.set_bytes(&key, &blks[..self.blk_size])?;
+            blks = &blks[self.blk_size..];
         }
 
         Ok(())
@@ -237,18 +460,16 @@ impl Storable for RedisStorage {
 
         let key = repo_lock_key();
         if self.get_bytes(&key).is_ok() {
-            // repo is locked
-            warn!("Destroy an opened repo");
+            // repo is locked, cannot destroy an opened repo
+            return Err(Error::InUse);
         }
 
-        match self.conn {
-            Some(ref conn) => {
-                let mut conn = conn.lock().unwrap();
-                redis::cmd("FLUSHDB").execute(&mut *conn);
-                Ok(())
-            }
-            None => unreachable!(),
+        // keys are spread over cluster masters, so flush each of them
+        for info in self.masters()? {
+            let mut conn = Client::open(info)?.get_connection()?;
+            redis::cmd("FLUSHDB").query::<()>(&mut conn)?;
         }
+        Ok(())
     }
 }
 
@@ -274,16 +495,64 @@ impl IntoRef for RedisStorage {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::crypto::{Crypto, Key};
     use crate::base::init_env;
 
+    #[test]
+    fn redis_parse_uri() {
+        let (nodes, pool_size, cluster) =
+            parse_uri("127.0.0.1:6379/2", false).unwrap();
+        assert_eq!(nodes, vec!["redis://127.0.0.1:6379/2"]);
+        assert_eq!(pool_size, RedisStorage::POOL_SIZE);
+        assert!(!cluster);
+
+        let (nodes, pool_size, cluster) =
+            parse_uri("host1:7000,host2:7001?pool=8&cluster=true", true)
+                .unwrap();
+        assert_eq!(nodes, vec!["rediss://host1:7000", "rediss://host2:7001"]);
+        assert_eq!(pool_size, 8);
+        assert!(cluster);
+
+        let (nodes, _, _) =
+            parse_uri("+unix+:pwd@/tmp/redis.sock?pool=2&db=1", false).unwrap();
+        assert_eq!(nodes, vec!["redis+unix:///:pwd@/tmp/redis.sock?db=1"]);
+
+        assert!(parse_uri("127.0.0.1?pool=0", false).is_err());
+        assert!(parse_uri("127.0.0.1?cluster=yes", false).is_err());
+        assert!(parse_uri("host1,?cluster=true", false).is_err());
+        assert!(parse_uri("+unix+/tmp/redis.sock?cluster=true", false).is_err());
+        assert!(parse_uri("+unix+/tmp/redis.sock", true).is_err());
+    }
+
+    #[test]
+    fn redis_parse_cluster_masters() {
+        let nodes = "\
+07c37dfeb235 127.0.0.1:30004@31004 slave e7d1eecce10f 0 1426238317239 4 connected
+67ed2db8d677 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7 127.0.0.1:30003@31003,host3 master - 0 1426238318243 3 connected 10923-16383
+6ec23923021c 127.0.0.1:30005@31005 master,fail - 1426238316232 0 5 disconnected
+e7d1eecce10f 127.0.0.1:30001@31001 myself,master - 0 0 1 connected 0-5460
+a1b2c3d4e5f6 :0@0 master,noaddr - 0 0 6 disconnected
+";
+        assert_eq!(
+            parse_cluster_masters(nodes),
+            vec![
+                ("127.0.0.1".to_string(), 30002),
+                ("127.0.0.1".to_string(), 30003),
+                ("127.0.0.1".to_string(), 30001),
+            ]
+        );
+    }
+
     // run a local redis instance before test, for example,
     // $ docker run -d --name some-redis -p 6379:6379 redis
     #[test]
     fn redis_storage() {
         init_env();
-        let mut rs = RedisStorage::new("127.0.0.1").unwrap();
+        let mut rs = RedisStorage::new("127.0.0.1", false).unwrap();
         rs.connect(false).unwrap();
-        rs.init(Crypto::default(), Key::new_empty()).unwrap();
+        rs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let id = Eid::new();
         let buf = vec![1, 2, 3];
@@ -332,9 +601,10 @@ mod tests {
 
         // re-open
         drop(rs);
-        let mut rs = RedisStorage::new("127.0.0.1").unwrap();
+        let mut rs = RedisStorage::new("127.0.0.1", false).unwrap();
         rs.connect(false).unwrap();
-        rs.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        rs.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         rs.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
             .unwrap();
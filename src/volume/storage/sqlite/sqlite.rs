@@ -7,12 +7,11 @@ use std::thread::panicking;
 use libsqlite3_sys as ffi;
 use log::warn;
 
-use crate::base::crypto::{Crypto, Key};
 use crate::base::vio;
 use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CryptoCtx, Storable};
 use crate::volume::BLK_SIZE;
 
 // check result code returned by sqlite
@@ -123,6 +122,7 @@ pub struct SqliteStorage {
     file_path: CString, // database file path
     db: *mut ffi::sqlite3,
     stmts: Vec<*mut ffi::sqlite3_stmt>,
+    blk_size: usize,
 }
 
 impl SqliteStorage {
@@ -139,6 +139,7 @@ impl SqliteStorage {
             file_path: CString::new(file_path).unwrap(),
             db: ptr::null_mut(),
             stmts: Vec::with_capacity(14),
+            blk_size: BLK_SIZE,
         }
     }
 
@@ -334,7 +335,7 @@ impl Storable for SqliteStorage {
         Ok(())
     }
 
-    fn init(&mut self, _crypto: Crypto, _key: Key) -> Result<()> {
+    fn init(&mut self, _ctx: CryptoCtx) -> Result<()> {
         // create tables
         let sql = format!(
             "
@@ -381,11 +382,23 @@ impl Storable for SqliteStorage {
     }
 
     #[inline]
-    fn open(&mut self, _crypto: Crypto, _key: Key, force: bool) -> Result<()> {
+    fn open(&mut self, _ctx: CryptoCtx, force: bool) -> Result<()> {
         self.prepare_stmts()?;
         self.lock_repo(force)
     }
 
+    #[inline]
+    fn change_crypto_ctx(&mut self, _ctx: CryptoCtx) -> Result<bool> {
+        // nothing is encrypted with crypto context
+        Ok(true)
+    }
+
+    #[inline]
+    fn set_block_size(&mut self, blk_size: usize) -> Result<()> {
+        self.blk_size = blk_size;
+        Ok(())
+    }
+
     fn get_super_block(&mut self, suffix: u64) -> Result<Vec<u8>> {
         // prepare statements
         self.prepare_stmts()?;
@@ -481,9 +494,9 @@ impl Storable for SqliteStorage {
             // bind parameters and run sql
             bind_int(stmt, 1, blk_idx)?;
             let blk = run_select_blob(stmt)?;
-            assert_eq!(blk.len(), BLK_SIZE);
-            dst[read..read + BLK_SIZE].copy_from_slice(&blk);
-            read += BLK_SIZE;
+            assert_eq!(blk.len(), self.blk_size);
+            dst[read..read + self.blk_size].copy_from_slice(&blk);
+            read += self.blk_size;
         }
 
         Ok(())
@@ -498,10 +511,10 @@ impl Storable for SqliteStorage {
 
             // bind parameters and run sql
             bind_int(stmt, 1, blk_idx)?;
-            bind_blob(stmt, 2, &blks[..BLK_SIZE])?;
+            bind_blob(stmt, 2, &blks[..self.blk_size])?;
             run_dml(stmt)?;
 
-            blks = &blks[BLK_SIZE..];
+            blks = &blks[self.blk_size..];
         }
 
         Ok(())
@@ -534,8 +547,8 @@ impl Storable for SqliteStorage {
             let stmt = self.stmts[0];
             reset_stmt(stmt)?;
             if let ffi::SQLITE_ROW = unsafe { ffi::sqlite3_step(stmt) } {
-                // repo is locked
-                warn!("Destroy an opened repo");
+                // repo is locked, cannot destroy an opened repo
+                return Err(Error::InUse);
             }
         }
         vio::remove_file(self.file_path.to_str().unwrap())?;
@@ -587,6 +600,7 @@ unsafe impl Sync for SqliteStorage {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::base::crypto::{Crypto, Key};
     use crate::base::init_env;
     use tempdir::TempDir;
 
@@ -598,7 +612,8 @@ mod tests {
         let mut ss = SqliteStorage::new(dir.to_str().unwrap());
 
         ss.connect(false).unwrap();
-        ss.init(Crypto::default(), Key::new_empty()).unwrap();
+        ss.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let id = Eid::new();
         let buf = vec![1, 2, 3];
@@ -646,7 +661,8 @@ mod tests {
         drop(ss);
         let mut ss = SqliteStorage::new(dir.to_str().unwrap());
         ss.connect(false).unwrap();
-        ss.open(Crypto::default(), Key::new_empty(), false).unwrap();
+        ss.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         ss.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
             .unwrap();
@@ -0,0 +1,85 @@
+#[cfg(feature = "storage-file")]
+use std::path::Path;
+
+#[cfg(feature = "storage-file")]
+use crate::base::vio;
+#[cfg(feature = "storage-file")]
+use crate::error::Result;
+
+/// Write durability level for storage on local file system.
+///
+/// It is set by [`RepoOpener::sync_writes`] and only applies to file
+/// storage, other storages ignore it.
+///
+/// Data written to a file is usually kept in OS page cache and written to
+/// disk later, so it can be lost on power failure or OS crash even after it
+/// is flushed. Higher level gives better crash safety but lower write
+/// throughput, as each write waits for the disk.
+///
+/// [`RepoOpener::sync_writes`]: struct.RepoOpener.html#method.sync_writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncLevel {
+    /// Rely on OS buffering, nothing is explicitly synced to disk.
+    ///
+    /// This is the fastest level, suitable for temporary repositories.
+    Off,
+
+    /// Sync file data to disk after super block, wal, index and block
+    /// writes.
+    ///
+    /// File metadata and directory entries are not synced, so a newly
+    /// created file may still be lost on power failure.
+    Data,
+
+    /// Sync file data and metadata to disk after super block, wal, index
+    /// and block writes, as well as the directories containing them.
+    ///
+    /// This is the slowest and safest level.
+    Full,
+}
+
+#[cfg(feature = "storage-file")]
+impl SyncLevel {
+    // sync file to disk according to sync level
+    pub(crate) fn sync_file(self, file: &vio::File) -> Result<()> {
+        match self {
+            SyncLevel::Off => {}
+            SyncLevel::Data => file.sync_data()?,
+            SyncLevel::Full => file.sync_all()?,
+        }
+        Ok(())
+    }
+
+    // sync directory entries to disk, only when level is full
+    #[cfg(unix)]
+    pub(crate) fn sync_dir(self, dir: &Path) -> Result<()> {
+        if self == SyncLevel::Full {
+            vio::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // directory cannot be opened as a file on other platforms, its entries
+    // are synced together with the file
+    #[cfg(not(unix))]
+    #[inline]
+    pub(crate) fn sync_dir(self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    // sync parent directory of a path
+    #[inline]
+    pub(crate) fn sync_parent(self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(dir) => self.sync_dir(dir),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for SyncLevel {
+    #[inline]
+    fn default() -> Self {
+        SyncLevel::Off
+    }
+}
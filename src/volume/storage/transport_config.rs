@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+/// Network transport configuration for remote storage.
+///
+/// It is set by [`RepoOpener::transport_config`] and only applies to storage
+/// which talks to a remote over network, such as Zbox Cloud Storage. Other
+/// storages ignore it.
+///
+/// Only idempotent requests are retried, that is, reading and writing an
+/// object. A request is retried when it failed with a transient error, such
+/// as a server error (HTTP status 5xx), a connection error or a timeout.
+/// The delay before `n`-th retry is `backoff * 2^(n - 1)`, capped at 60
+/// seconds.
+///
+/// [`RepoOpener::transport_config`]: struct.RepoOpener.html#method.transport_config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportConfig {
+    /// Timeout for establishing a connection.
+    pub connect_timeout: Duration,
+
+    /// Timeout for a whole request, from sending it to finishing reading its
+    /// response.
+    pub request_timeout: Duration,
+
+    /// Maximum number of retries for a failed idempotent request, 0 means
+    /// no retry.
+    pub max_retries: u32,
+
+    /// Delay before the first retry, it is doubled for each subsequent
+    /// retry.
+    pub backoff: Duration,
+}
+
+impl Default for TransportConfig {
+    #[inline]
+    fn default() -> Self {
+        TransportConfig {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            max_retries: 3,
+            backoff: Duration::from_millis(200),
+        }
+    }
+}
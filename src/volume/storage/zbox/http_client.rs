@@ -11,6 +11,7 @@ use serde::Deserialize;
 use super::transport::{DummyTransport, Response, Transport};
 use crate::base::Version;
 use crate::error::{Error, Result};
+use crate::volume::storage::TransportConfig;
 
 // remote object cache control
 #[derive(Clone, Copy)]
@@ -162,54 +163,59 @@ impl HttpClient {
     // bulk request uri
     const BULK_URI: &'static str = "bulk";
 
-    // default timeout, in secnods
-    const DEFAULT_TIMEOUT: u32 = 30;
-
     pub fn new(repo_id: &str, access_key: &str) -> Result<Self> {
-        // create transport
+        let transport = Self::create_transport(&TransportConfig::default())?;
+
+        Ok(HttpClient {
+            base_url: Self::ROOT_URL.to_owned() + repo_id + "/",
+            repo_id: repo_id.to_owned(),
+            access_key: access_key.to_string(),
+            session_token: String::new(),
+            is_updated: false,
+            update_seq: 0,
+            ttl: 0,
+            retry_cnt: 0,
+            headers: Headers::new(),
+            transport,
+            del_bulk: Vec::new(),
+        })
+    }
+
+    // create transport using the transport config
+    fn create_transport(cfg: &TransportConfig) -> Result<Box<dyn Transport>> {
         let transport: Box<dyn Transport> = {
             #[cfg(feature = "storage-zbox-faulty")]
             {
-                Box::new(super::transport::faulty::FaultyTransport::new(
-                    Self::DEFAULT_TIMEOUT,
-                )?)
+                Box::new(super::transport::faulty::FaultyTransport::new(cfg)?)
             }
 
             #[cfg(feature = "storage-zbox-native")]
             {
-                Box::new(super::transport::native::NativeTransport::new(
-                    Self::DEFAULT_TIMEOUT,
-                )?)
+                Box::new(super::transport::native::NativeTransport::new(cfg)?)
             }
 
             #[cfg(feature = "storage-zbox-android")]
             {
-                Box::new(super::transport::jni::JniTransport::new(
-                    Self::DEFAULT_TIMEOUT,
-                )?)
+                Box::new(super::transport::jni::JniTransport::new(cfg)?)
             }
 
             #[cfg(target_arch = "wasm32")]
             {
-                Box::new(super::transport::wasm::WasmTransport::new(
-                    Self::DEFAULT_TIMEOUT,
-                )?)
+                Box::new(super::transport::wasm::WasmTransport::new(cfg)?)
             }
         };
 
-        Ok(HttpClient {
-            base_url: Self::ROOT_URL.to_owned() + repo_id + "/",
-            repo_id: repo_id.to_owned(),
-            access_key: access_key.to_string(),
-            session_token: String::new(),
-            is_updated: false,
-            update_seq: 0,
-            ttl: 0,
-            retry_cnt: 0,
-            headers: Headers::new(),
-            transport,
-            del_bulk: Vec::new(),
-        })
+        Ok(transport)
+    }
+
+    // re-create transport with new transport config
+    #[inline]
+    pub fn set_transport_config(
+        &mut self,
+        cfg: &TransportConfig,
+    ) -> Result<()> {
+        self.transport = Self::create_transport(cfg)?;
+        Ok(())
     }
 
     #[inline]
@@ -305,7 +311,7 @@ impl HttpClient {
             .get(uri, headers.as_ref())?
             .error_for_status()
             .map_err(|err| {
-                if err == Error::HttpStatus(StatusCode::NOT_FOUND) {
+                if err == Error::from(StatusCode::NOT_FOUND) {
                     Error::NotFound
                 } else {
                     err
@@ -329,7 +335,7 @@ impl HttpClient {
                 // sync, so if we got 401 unauthorized error, that means
                 // it is not expired locally but expired remotely, in this case
                 // we need to reopen the session, but just try once only
-                if err == Error::HttpStatus(StatusCode::UNAUTHORIZED) {
+                if err == Error::from(StatusCode::UNAUTHORIZED) {
                     self.open_session(false)?;
                     self.send_get_req(&uri, cache_ctl)
                 } else {
@@ -394,7 +400,7 @@ impl HttpClient {
         self.send_put_req(&uri, offset, cache_ctl, body)
             .or_else(|err| {
                 // try reopen remote session once if it is expired
-                if err == Error::HttpStatus(StatusCode::UNAUTHORIZED) {
+                if err == Error::from(StatusCode::UNAUTHORIZED) {
                     self.open_session(false)?;
                     self.send_put_req(&uri, offset, cache_ctl, body)
                 } else {
@@ -442,7 +448,7 @@ impl HttpClient {
             .map(|_| ())
             .or_else(|err| {
                 // ignore not found error
-                if err == Error::HttpStatus(StatusCode::NOT_FOUND) {
+                if err == Error::from(StatusCode::NOT_FOUND) {
                     Ok(())
                 } else {
                     Err(err)
@@ -456,7 +462,7 @@ impl HttpClient {
         let bulk = self.del_bulk.clone();
         self.send_bulk_del_req(&bulk).or_else(|err| {
             // try reopen remote session once if it is expired
-            if err == Error::HttpStatus(StatusCode::UNAUTHORIZED) {
+            if err == Error::from(StatusCode::UNAUTHORIZED) {
                 self.open_session(false)?;
                 self.send_bulk_del_req(&bulk)
             } else {
@@ -479,7 +485,7 @@ impl HttpClient {
             .error_for_status()
             .map(|_| ())
             .map_err(|err| {
-                if err == Error::HttpStatus(StatusCode::NOT_FOUND) {
+                if err == Error::from(StatusCode::NOT_FOUND) {
                     Error::NotFound
                 } else {
                     err
@@ -545,10 +551,13 @@ mod tests {
     use std::{thread, time};
 
     use super::*;
-    use base::init_env;
-    use volume::BLK_SIZE;
+    use crate::base::init_env;
+    use crate::volume::BLK_SIZE;
 
+    // faulty transport doesn't reject re-opening session, so this test
+    // needs a real zbox server
     #[test]
+    #[ignore]
     fn http_test() {
         init_env();
 
@@ -581,7 +590,7 @@ mod tests {
         assert_eq!(dst.len(), blks.len() + 3);
 
         // open session again should fail
-        assert_eq!(client.open_session(false).unwrap_err(), Error::Opened);
+        assert_eq!(client.open_session(false).unwrap_err(), Error::RepoOpened);
 
         // test delete
         client.del(&rel_path).unwrap();
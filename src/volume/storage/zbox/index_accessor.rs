@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use super::local_cache::LocalCacheRef;
 use crate::base::crypto::{Crypto, Key};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::storage::index_mgr::Accessor;
 use crate::volume::ArmAccess;
@@ -15,6 +15,7 @@ use crate::volume::ArmAccess;
 pub struct IndexAccessor<T> {
     local_cache: LocalCacheRef,
     crypto: Crypto,
+    prev_crypto: Option<Crypto>,
     key: Key,
     _t: PhantomData<T>,
 }
@@ -26,6 +27,7 @@ impl<T> IndexAccessor<T> {
         IndexAccessor {
             local_cache: local_cache.clone(),
             crypto: Crypto::default(),
+            prev_crypto: None,
             key: Key::new_empty(),
             _t: PhantomData,
         }
@@ -39,8 +41,14 @@ where
     type Item = T;
 
     #[inline]
-    fn set_crypto_ctx(&mut self, crypto: Crypto, key: Key) {
+    fn set_crypto_ctx(
+        &mut self,
+        crypto: Crypto,
+        prev_crypto: Option<Crypto>,
+        key: Key,
+    ) {
         self.crypto = crypto;
+        self.prev_crypto = prev_crypto;
         self.key = key;
     }
 
@@ -48,7 +56,15 @@ where
         let mut local_cache = self.local_cache.write().unwrap();
         let rel_path = id.to_path_buf(Self::DIR_NAME);
         let buf = local_cache.get(&rel_path)?;
-        let buf = self.crypto.decrypt(&buf, &self.key)?;
+        let buf = match self.crypto.decrypt(&buf, &self.key) {
+            Err(ref err) if *err == Error::Decrypt => match self.prev_crypto {
+                Some(ref prev_crypto) => {
+                    prev_crypto.decrypt(&buf, &self.key)?
+                }
+                None => return Err(Error::Decrypt),
+            },
+            ret => ret?,
+        };
         let mut de = Deserializer::new(&buf[..]);
         let ret = Deserialize::deserialize(&mut de)?;
         Ok(ret)
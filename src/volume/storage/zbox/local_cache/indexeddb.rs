@@ -0,0 +1,384 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use js_sys::{Array, Function, Promise, Reflect, Uint8Array};
+use log::warn;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{IdbDatabase, IdbFactory, IdbOpenDbRequest, IdbTransactionMode};
+
+use super::CacheBackend;
+use crate::error::{Error, Result};
+
+// IndexedDB only has asynchronous API, so the backend reads from an
+// in-memory mirror of the database and writes through to IndexedDB in
+// background, like the wasm transport keeps everything behind sync calls.
+// The mirror is loaded when the database is opened, objects written before
+// the loading completes are queued and persisted after it.
+//
+// Database handles are JS objects which cannot be held by the backend
+// because it must be Send and Sync, so the mirrors are kept in a thread
+// local registry keyed by database name. Browser runs wasm on one thread,
+// so all backends of the same repo share the same mirror.
+
+// version of cache database
+const DB_VERSION: u32 = 1;
+
+// object store name in cache database
+const STORE_NAME: &str = "objects";
+
+// write operation to be persisted to IndexedDB
+enum Op {
+    Put(String, Vec<u8>),
+    Delete(String),
+    Clear,
+}
+
+impl Op {
+    // check if the operation overrides the object
+    fn overrides(&self, key: &str) -> bool {
+        match self {
+            Op::Put(k, _) | Op::Delete(k) => k == key,
+            Op::Clear => true,
+        }
+    }
+}
+
+// IndexedDB database state
+enum DbState {
+    Loading,
+    Ready(IdbDatabase),
+    Failed,
+}
+
+// in-memory mirror of a cache database
+struct Mirror {
+    objs: HashMap<String, Vec<u8>>,
+    state: DbState,
+
+    // operations issued while the database is loading
+    pending: Vec<Op>,
+
+    // promise resolvers waiting for the loading
+    waiters: Vec<Function>,
+}
+
+impl Mirror {
+    fn new() -> Self {
+        Mirror {
+            objs: HashMap::new(),
+            state: DbState::Loading,
+            pending: Vec::new(),
+            waiters: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, op: Op) {
+        match self.state {
+            DbState::Loading => self.pending.push(op),
+            DbState::Ready(ref db) => {
+                if let Err(err) = persist(db, &op) {
+                    warn!("write to indexeddb failed: {:?}", err);
+                }
+            }
+            DbState::Failed => {}
+        }
+    }
+
+    // merge loaded objects and complete the loading, objects overridden
+    // while loading are skipped
+    fn complete_load(&mut self, db: Option<IdbDatabase>, loaded: Vec<Op>) {
+        for op in loaded {
+            if let Op::Put(key, obj) = op {
+                if !self.pending.iter().any(|p| p.overrides(&key)) {
+                    self.objs.insert(key, obj);
+                }
+            }
+        }
+
+        match db {
+            Some(db) => {
+                for op in self.pending.drain(..) {
+                    if let Err(err) = persist(&db, &op) {
+                        warn!("write to indexeddb failed: {:?}", err);
+                    }
+                }
+                self.state = DbState::Ready(db);
+            }
+            None => {
+                self.pending.clear();
+                self.state = DbState::Failed;
+            }
+        }
+
+        for resolve in self.waiters.drain(..) {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    }
+}
+
+type MirrorRef = Rc<RefCell<Mirror>>;
+
+thread_local! {
+    static MIRRORS: RefCell<HashMap<String, MirrorRef>> =
+        RefCell::new(HashMap::new());
+}
+
+// get IndexedDB factory in either window or worker scope
+fn idb_factory() -> Option<IdbFactory> {
+    Reflect::get(&js_sys::global(), &JsValue::from_str("indexedDB"))
+        .ok()
+        .and_then(|factory| factory.dyn_into::<IdbFactory>().ok())
+}
+
+// persist a write operation, readwrite transactions on the same object
+// store are run in the order they are created
+fn persist(db: &IdbDatabase, op: &Op) -> std::result::Result<(), JsValue> {
+    let tx = db.transaction_with_str_and_mode(
+        STORE_NAME,
+        IdbTransactionMode::Readwrite,
+    )?;
+    let store = tx.object_store(STORE_NAME)?;
+    match op {
+        Op::Put(key, obj) => {
+            // if the put is aborted, remove the key so the stale object
+            // won't be seen after reload
+            let db = db.clone();
+            let key = key.clone();
+            let data = Uint8Array::from(&obj[..]);
+            store.put_with_key(&data, &JsValue::from_str(&key))?;
+            let on_abort = Closure::once_into_js(move |_: JsValue| {
+                if let Err(err) = persist(&db, &Op::Delete(key)) {
+                    warn!("remove from indexeddb failed: {:?}", err);
+                }
+            });
+            tx.set_onabort(Some(on_abort.unchecked_ref()));
+        }
+        Op::Delete(key) => {
+            store.delete(&JsValue::from_str(key))?;
+        }
+        Op::Clear => {
+            store.clear()?;
+        }
+    }
+    Ok(())
+}
+
+// load all objects in database into mirror
+fn load_db(
+    db: IdbDatabase,
+    mirror: MirrorRef,
+) -> std::result::Result<(), JsValue> {
+    let tx = db.transaction_with_str_and_mode(
+        STORE_NAME,
+        IdbTransactionMode::Readonly,
+    )?;
+    let store = tx.object_store(STORE_NAME)?;
+    let keys_req = store.get_all_keys()?;
+    let vals_req = store.get_all()?;
+
+    // requests are completed in order, so keys are ready when values are
+    let req = vals_req.clone();
+    let mirror2 = mirror.clone();
+    let on_success = Closure::once_into_js(move |_: JsValue| {
+        let loaded = keys_req
+            .result()
+            .and_then(|keys| {
+                Ok((Array::from(&keys), Array::from(&req.result()?)))
+            })
+            .map(|(keys, vals)| {
+                keys.iter()
+                    .zip(vals.iter())
+                    .filter_map(|(key, val)| {
+                        let key = key.as_string()?;
+                        let obj = Uint8Array::new(&val).to_vec();
+                        Some(Op::Put(key, obj))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        mirror2.borrow_mut().complete_load(Some(db), loaded);
+    });
+    vals_req.set_onsuccess(Some(on_success.unchecked_ref()));
+
+    let on_error = Closure::once_into_js(move |_: JsValue| {
+        warn!("load indexeddb cache failed");
+        mirror.borrow_mut().complete_load(None, Vec::new());
+    });
+    tx.set_onerror(Some(on_error.unchecked_ref()));
+
+    Ok(())
+}
+
+// open database and start loading it into mirror
+fn open_db(
+    db_name: &str,
+    mirror: &MirrorRef,
+) -> std::result::Result<(), JsValue> {
+    let factory =
+        idb_factory().ok_or_else(|| JsValue::from_str("no indexedDB"))?;
+    let req: IdbOpenDbRequest = factory.open_with_u32(db_name, DB_VERSION)?;
+
+    // create object store when the database is newly created
+    let req2 = req.clone();
+    let on_upgrade = Closure::once_into_js(move |_: JsValue| {
+        let created = req2.result().and_then(|db| {
+            db.unchecked_into::<IdbDatabase>()
+                .create_object_store(STORE_NAME)
+        });
+        if let Err(err) = created {
+            warn!("create indexeddb object store failed: {:?}", err);
+        }
+    });
+    req.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+    let req2 = req.clone();
+    let mirror2 = mirror.clone();
+    let on_success = Closure::once_into_js(move |_: JsValue| {
+        let loading = req2
+            .result()
+            .and_then(|db| load_db(db.unchecked_into(), mirror2.clone()));
+        if let Err(err) = loading {
+            warn!("load indexeddb cache failed: {:?}", err);
+            mirror2.borrow_mut().complete_load(None, Vec::new());
+        }
+    });
+    req.set_onsuccess(Some(on_success.unchecked_ref()));
+
+    let mirror2 = mirror.clone();
+    let on_error = Closure::once_into_js(move |_: JsValue| {
+        warn!("open indexeddb cache failed");
+        mirror2.borrow_mut().complete_load(None, Vec::new());
+    });
+    req.set_onerror(Some(on_error.unchecked_ref()));
+
+    Ok(())
+}
+
+// get mirror of a database, open the database if it is not opened yet
+fn get_mirror(db_name: &str) -> MirrorRef {
+    MIRRORS.with(|mirrors| {
+        let mut mirrors = mirrors.borrow_mut();
+        if let Some(mirror) = mirrors.get(db_name) {
+            return mirror.clone();
+        }
+
+        let mirror = Rc::new(RefCell::new(Mirror::new()));
+        if let Err(err) = open_db(db_name, &mirror) {
+            // fall back to memory only
+            warn!("open indexeddb cache failed: {:?}", err);
+            mirror.borrow_mut().state = DbState::Failed;
+        }
+        mirrors.insert(db_name.to_string(), mirror.clone());
+        mirror
+    })
+}
+
+#[inline]
+fn db_name(repo_id: &str) -> String {
+    format!("zbox_cache_{}", repo_id)
+}
+
+/// Load IndexedDB local cache of a repo
+///
+/// The returned promise is resolved when cached objects are loaded from
+/// IndexedDB. Wait for it before opening the repo, so the cached objects
+/// are reused rather than fetched again. Otherwise the cache is loaded
+/// in background after the repo is opened.
+pub fn load_indexeddb_cache(repo_id: &str) -> Promise {
+    let mirror = get_mirror(&db_name(repo_id));
+    Promise::new(&mut |resolve, _reject| {
+        let mut mirror = mirror.borrow_mut();
+        match mirror.state {
+            DbState::Loading => mirror.waiters.push(resolve),
+            _ => {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        }
+    })
+}
+
+// IndexedDB backend, cached objects persist across page reloads
+pub struct IdbBackend {
+    db_name: String,
+}
+
+impl IdbBackend {
+    #[inline]
+    pub fn new(repo_id: &str) -> Self {
+        let db_name = db_name(repo_id);
+        get_mirror(&db_name);
+        IdbBackend { db_name }
+    }
+
+    #[inline]
+    fn mirror(&self) -> MirrorRef {
+        get_mirror(&self.db_name)
+    }
+}
+
+impl CacheBackend for IdbBackend {
+    #[inline]
+    fn contains(&mut self, rel_path: &Path) -> bool {
+        let mirror = self.mirror();
+        let mirror = mirror.borrow();
+        mirror.objs.contains_key(rel_path.to_str().unwrap())
+    }
+
+    fn get_exact(
+        &mut self,
+        rel_path: &Path,
+        offset: usize,
+        dst: &mut [u8],
+    ) -> Result<()> {
+        let mirror = self.mirror();
+        let mirror = mirror.borrow();
+        let obj = mirror
+            .objs
+            .get(rel_path.to_str().unwrap())
+            .ok_or(Error::NotFound)?;
+        if offset + dst.len() > obj.len() {
+            return Err(Error::NotFound);
+        }
+        dst.copy_from_slice(&obj[offset..offset + dst.len()]);
+        Ok(())
+    }
+
+    fn get(&mut self, rel_path: &Path) -> Result<Vec<u8>> {
+        let mirror = self.mirror();
+        let mirror = mirror.borrow();
+        mirror
+            .objs
+            .get(rel_path.to_str().unwrap())
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn insert(&mut self, rel_path: &Path, obj: &[u8]) -> Result<()> {
+        let key = rel_path.to_str().unwrap().to_string();
+        let mirror = self.mirror();
+        let mut mirror = mirror.borrow_mut();
+        mirror.objs.insert(key.clone(), obj.to_vec());
+        mirror.write(Op::Put(key, obj.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&mut self, rel_path: &Path) -> Result<()> {
+        let key = rel_path.to_str().unwrap().to_string();
+        let mirror = self.mirror();
+        let mut mirror = mirror.borrow_mut();
+        mirror.objs.remove(&key);
+        mirror.write(Op::Delete(key));
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let mirror = self.mirror();
+        let mut mirror = mirror.borrow_mut();
+        mirror.objs.clear();
+        mirror.write(Op::Clear);
+        Ok(())
+    }
+}
@@ -8,10 +8,11 @@ use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
 use super::super::http_client::{CacheControl, HttpClient};
-use super::{CacheBackend, CacheType, DummyBackend};
+use super::{copy_exact, CacheBackend, CachePolicy, CacheType, DummyBackend};
 use crate::base::crypto::{Crypto, Key};
 use crate::base::IntoRef;
 use crate::error::{Error, Result};
+use crate::volume::storage::{CacheEvent, CacheHook, TransportConfig};
 
 // cached item in local cache
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -38,7 +39,8 @@ struct CacheMeta {
     // update sequence, used to sync with remote
     useq: u64,
 
-    // LRU list
+    // LRU list, objects are not refreshed when accessed if cache policy
+    // is FIFO
     lru: LinkedHashMap<PathBuf, CacheItem>,
 }
 
@@ -51,9 +53,28 @@ pub struct LocalCache {
     // local cache change flag
     is_changed: bool,
 
+    // eviction policy
+    policy: CachePolicy,
+
+    // read-only flag, a read-only cache is never written or evicted
+    readonly: bool,
+
+    // read-your-writes flag and write cache, objects written recently are
+    // kept in write cache until remote confirms they are visible
+    read_your_writes: bool,
+    write_cache: LinkedHashMap<PathBuf, Vec<u8>>,
+    write_cache_used: usize,
+
+    // write cache capacity, in bytes, it uses local cache capacity if not
+    // set
+    write_cache_capacity: Option<usize>,
+
     // http client
     client: HttpClient,
 
+    // cache event hook
+    hook: CacheHook,
+
     crypto: Crypto,
     key: Key,
 }
@@ -61,10 +82,15 @@ pub struct LocalCache {
 impl LocalCache {
     const META_FILE_NAME: &'static str = "cache_meta";
 
+    // minimum memory capacity, in bytes, it must be able to hold any object
+    const MIN_MEM_CAPACITY: usize = 256 * 1024;
+
     pub fn new(
         cache_type: CacheType,
         capacity_in_mb: usize,
-        base: &Path,
+        cache_dir: &Path,
+        readonly: bool,
+        read_your_writes: bool,
         repo_id: &str,
         access_key: &str,
     ) -> Result<Self> {
@@ -82,13 +108,25 @@ impl LocalCache {
             CacheType::File => {
                 #[cfg(target_arch = "wasm32")]
                 {
-                    let _ = base;
+                    let _ = cache_dir;
                     Box::new(super::browser::WasmBackend::new())
                 }
                 #[cfg(not(target_arch = "wasm32"))]
                 {
-                    let base = base.join(repo_id);
-                    Box::new(super::file::FileBackend::new(&base))
+                    Box::new(super::file::FileBackend::new(cache_dir))
+                }
+            }
+            CacheType::IndexedDb => {
+                #[cfg(target_arch = "wasm32")]
+                {
+                    Box::new(super::indexeddb::IdbBackend::new(repo_id))
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    return Err(Error::invalid_uri(
+                        "zbox://",
+                        "indexeddb cache is only available in browser",
+                    ));
                 }
             }
         };
@@ -97,7 +135,14 @@ impl LocalCache {
             meta,
             backend,
             is_changed: false,
+            policy: CachePolicy::default(),
+            readonly,
+            read_your_writes,
+            write_cache: LinkedHashMap::new(),
+            write_cache_used: 0,
+            write_cache_capacity: None,
             client,
+            hook: CacheHook::default(),
             crypto: Crypto::default(),
             key: Key::new_empty(),
         })
@@ -109,6 +154,62 @@ impl LocalCache {
         self.key = key;
     }
 
+    #[inline]
+    pub fn set_policy(&mut self, policy: CachePolicy) {
+        self.policy = policy;
+    }
+
+    // get used size in bytes, capacity in bytes and number of objects
+    #[inline]
+    pub fn usage(&self) -> (usize, usize, usize) {
+        (self.meta.used, self.meta.capacity, self.meta.lru.len())
+    }
+
+    // get memory used by cache, in bytes, objects are kept in memory only
+    // if it is memory-based cache, besides the write cache
+    #[inline]
+    pub fn mem_used(&self) -> usize {
+        let used = if self.meta.cache_type == CacheType::Mem {
+            self.meta.used
+        } else {
+            0
+        };
+        used + self.write_cache_used
+    }
+
+    // set memory capacity in bytes, memory-based cache is shrunk to fit in
+    // it, and write cache takes half of it if read-your-writes is enabled
+    pub fn set_mem_capacity(&mut self, capacity: usize) {
+        let capacity = capacity.max(Self::MIN_MEM_CAPACITY);
+        let write_cache_capacity = if self.read_your_writes {
+            capacity / 2
+        } else {
+            0
+        };
+        self.write_cache_capacity = Some(write_cache_capacity);
+        self.trim_write_cache();
+
+        if self.meta.cache_type == CacheType::Mem {
+            self.meta.capacity = capacity - write_cache_capacity;
+            if let Err(err) = self.reserve_place(0) {
+                warn!("shrink local cache failed: {}", err);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn set_cache_hook(&mut self, hook: CacheHook) {
+        self.hook = hook;
+    }
+
+    #[inline]
+    pub fn set_transport_config(
+        &mut self,
+        cfg: &TransportConfig,
+    ) -> Result<()> {
+        self.client.set_transport_config(cfg)
+    }
+
     #[inline]
     pub fn repo_exists(&self) -> Result<bool> {
         self.client.repo_exists()
@@ -127,12 +228,17 @@ impl LocalCache {
             self.backend.remove(&item.0)?;
             self.meta.lru.remove(&item.0);
             self.meta.used -= item.1;
+            self.hook.emit(CacheEvent::Evict);
         }
 
         Ok(())
     }
 
     // make a specified size place in local cache for an object
+    //
+    // an object is read right after it is ensured in local cache while the
+    // cache is still locked, so an in-flight read never loses its object to
+    // eviction
     fn reserve_place(&mut self, len: usize) -> Result<()> {
         // if local cache still has enough space
         if self.meta.used + len <= self.meta.capacity {
@@ -173,6 +279,80 @@ impl LocalCache {
         self.evict(&to_evict)
     }
 
+    // get object from remote, if it is not visible in remote yet but still
+    // in write cache, serve it from write cache
+    fn get_remote(
+        &mut self,
+        rel_path: &Path,
+        cache_ctl: CacheControl,
+    ) -> Result<Vec<u8>> {
+        match self.client.get(rel_path, cache_ctl) {
+            Ok(obj) => {
+                self.hook.emit(CacheEvent::Fetch { bytes: obj.len() });
+
+                // remote has confirmed the object, so it can be removed
+                // from write cache
+                self.remove_from_write_cache(rel_path);
+                Ok(obj)
+            }
+            Err(ref err) if *err == Error::NotFound => {
+                match self.write_cache.get_refresh(rel_path) {
+                    Some(obj) => Ok(obj.clone()),
+                    None => Err(Error::NotFound),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // add an object to write cache, offset is the position where the object
+    // is written to
+    fn add_to_write_cache(
+        &mut self,
+        rel_path: &Path,
+        offset: usize,
+        obj: &[u8],
+    ) {
+        if !self.read_your_writes {
+            return;
+        }
+
+        if offset == 0 {
+            self.remove_from_write_cache(rel_path);
+            self.write_cache
+                .insert(rel_path.to_path_buf(), obj.to_vec());
+            self.write_cache_used += obj.len();
+        } else if let Some(cached) = self.write_cache.get_refresh(rel_path) {
+            // partial put can only be applied to object already in write
+            // cache, otherwise the object must be visible in remote
+            self.write_cache_used -= cached.len();
+            cached.resize(offset, 0);
+            cached.extend_from_slice(obj);
+            self.write_cache_used += cached.len();
+        }
+
+        self.trim_write_cache();
+    }
+
+    // evict the oldest objects in write cache if it exceeds its capacity,
+    // which is the same as local cache if it is not set
+    fn trim_write_cache(&mut self) {
+        let capacity = self.write_cache_capacity.unwrap_or(self.meta.capacity);
+        while self.write_cache_used > capacity {
+            match self.write_cache.pop_front() {
+                Some((_, obj)) => self.write_cache_used -= obj.len(),
+                None => break,
+            }
+        }
+    }
+
+    #[inline]
+    fn remove_from_write_cache(&mut self, rel_path: &Path) {
+        if let Some(obj) = self.write_cache.remove(rel_path) {
+            self.write_cache_used -= obj.len();
+        }
+    }
+
     // ensure data is downloaded to local cache
     fn ensure_in_local(
         &mut self,
@@ -183,15 +363,19 @@ impl LocalCache {
 
         // if object is already in cache
         if self.backend.contains(rel_path) {
-            let _ = self.meta.lru.get_refresh(rel_path);
+            if self.policy == CachePolicy::Lru {
+                let _ = self.meta.lru.get_refresh(rel_path);
+            }
+            self.hook.emit(CacheEvent::Hit);
             return Ok(());
         }
+        self.hook.emit(CacheEvent::Miss);
 
         // if object is not in cache, get it from remote and then add
         // to local cache
         let remote = {
             let obj =
-                self.client.get(rel_path, CacheControl::from(is_pinned))?;
+                self.get_remote(rel_path, CacheControl::from(is_pinned))?;
             self.reserve_place(obj.len())?;
             obj
         };
@@ -219,6 +403,10 @@ impl LocalCache {
     }
 
     fn save_meta(&mut self) -> Result<()> {
+        if self.readonly {
+            return Ok(());
+        }
+
         // get latest update sequence from http client
         self.meta.useq = self.client.get_update_seq();
 
@@ -233,6 +421,9 @@ impl LocalCache {
 
     #[inline]
     pub fn init(&mut self) -> Result<()> {
+        if self.readonly {
+            return Err(Error::ReadOnly);
+        }
         self.backend.clear().and_then(|_| self.save_meta())
     }
 
@@ -247,7 +438,10 @@ impl LocalCache {
             Ok(meta) => {
                 // cache type must match
                 if self.meta.cache_type != meta.cache_type {
-                    return Err(Error::InvalidUri);
+                    return Err(Error::invalid_uri(
+                        "zbox://",
+                        "cache_type doesn't match existing local cache",
+                    ));
                 }
 
                 // get remote update sequence
@@ -262,6 +456,16 @@ impl LocalCache {
                     return Ok(());
                 }
 
+                // read-only cache cannot be cleared, so it cannot be used
+                if self.readonly {
+                    warn!(
+                        "update seq not match, local: {}, remote: {}, \
+                         read-only cache is out of date",
+                        meta.useq, remote_useq,
+                    );
+                    return Err(Error::NotInSync);
+                }
+
                 // otherwise, clear the local cache
                 warn!(
                     "update seq not match, local: {}, remote: {}, \
@@ -285,12 +489,25 @@ impl LocalCache {
         offset: usize,
         dst: &mut [u8],
     ) -> Result<()> {
+        // read-only cache serves hits only, misses are read from remote
+        // directly without adding to local cache
+        if self.readonly && !self.backend.contains(rel_path) {
+            self.hook.emit(CacheEvent::Miss);
+            let obj = self.get_remote(rel_path, CacheControl::from(false))?;
+            return copy_exact(&obj, offset, dst);
+        }
+
         self.ensure_in_local(rel_path, false)?;
         self.backend.get_exact(rel_path, offset, dst)
     }
 
     #[inline]
     pub fn get(&mut self, rel_path: &Path) -> Result<Vec<u8>> {
+        if self.readonly && !self.backend.contains(rel_path) {
+            self.hook.emit(CacheEvent::Miss);
+            return self.get_remote(rel_path, CacheControl::from(true));
+        }
+
         self.ensure_in_local(rel_path, true)?;
         self.backend.get(rel_path)
     }
@@ -309,10 +526,11 @@ impl LocalCache {
 
         // then save to remote
         self.client.put(rel_path, offset, cache_ctl, obj)?;
+        self.add_to_write_cache(rel_path, offset, obj);
 
         // save object to local cache at last and only save when it is
         // a full-put object
-        if offset == 0 {
+        if offset == 0 && !self.readonly {
             self.reserve_place(obj.len())?;
 
             self.backend.insert(rel_path, obj)?;
@@ -346,6 +564,15 @@ impl LocalCache {
 
     // delete object from local cache only
     fn del_local(&mut self, rel_path: &Path) -> Result<()> {
+        if self.readonly {
+            // cached object cannot be removed from read-only cache, so
+            // it cannot be changed either
+            if self.backend.contains(rel_path) {
+                return Err(Error::ReadOnly);
+            }
+            return Ok(());
+        }
+
         self.backend.remove(rel_path)?;
         self.is_changed = true;
         if let Some(cache_obj) = self.meta.lru.remove(rel_path) {
@@ -357,6 +584,7 @@ impl LocalCache {
     #[inline]
     pub fn del(&mut self, rel_path: &Path) -> Result<()> {
         // remove from local cache first then remove from remote
+        self.remove_from_write_cache(rel_path);
         self.del_local(rel_path)
             .and_then(|_| self.client.del(rel_path))
     }
@@ -371,9 +599,13 @@ impl LocalCache {
     }
 
     pub fn destroy_repo(&mut self) -> Result<()> {
-        self.client
-            .destroy_repo()
-            .and_then(|_| self.backend.clear())
+        self.client.destroy_repo()?;
+        self.write_cache.clear();
+        self.write_cache_used = 0;
+        if !self.readonly {
+            self.backend.clear()?;
+        }
+        Ok(())
     }
 }
 
@@ -384,7 +616,14 @@ impl Default for LocalCache {
             meta: CacheMeta::default(),
             backend: Box::new(DummyBackend::default()),
             is_changed: false,
+            policy: CachePolicy::default(),
+            readonly: false,
+            read_your_writes: false,
+            write_cache: LinkedHashMap::new(),
+            write_cache_used: 0,
+            write_cache_capacity: None,
             client: HttpClient::default(),
+            hook: CacheHook::default(),
             crypto: Crypto::default(),
             key: Key::new_empty(),
         }
@@ -396,6 +635,9 @@ impl Debug for LocalCache {
         f.debug_struct("LocalCache")
             .field("meta", &self.meta)
             .field("is_changed", &self.is_changed)
+            .field("readonly", &self.readonly)
+            .field("read_your_writes", &self.read_your_writes)
+            .field("write_cache_used", &self.write_cache_used)
             .finish()
     }
 }
@@ -409,16 +651,24 @@ mod tests {
     extern crate tempdir;
 
     use self::tempdir::TempDir;
+    #[cfg(feature = "storage-zbox-faulty")]
+    use super::super::super::transport::faulty::set_visibility_delay;
     use super::*;
-    use base::init_env;
+    use crate::base::init_env;
 
-    fn test_local_cache(cache_type: CacheType, base: &Path) {
+    fn test_local_cache(cache_type: CacheType, base: &Path, repo_id: &str) {
         init_env();
-        let repo_id = "repo456";
         let access_key = "accessKey456";
-        let mut cache =
-            LocalCache::new(cache_type, 1, base, &repo_id, &access_key)
-                .unwrap();
+        let mut cache = LocalCache::new(
+            cache_type,
+            1,
+            base,
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
 
         let k300 = 300 * 1000;
         let k400 = 400 * 1000;
@@ -444,7 +694,7 @@ mod tests {
         assert!(!cache.repo_exists().unwrap());
 
         // test init
-        cache.connect().unwrap();
+        cache.connect(false).unwrap();
         cache.init().unwrap();
         assert_eq!(cache.meta.lru.len(), 0);
 
@@ -477,6 +727,12 @@ mod tests {
         assert_eq!(cache.meta.lru.len(), 2);
         assert_eq!(cache.meta.used, k500 + k300);
 
+        // reading beyond end of object is an error
+        let mut dst = vec![0u8; 3];
+        cache.get_to(&rel_path, k300 - 3, &mut dst).unwrap();
+        assert_eq!(&dst[..], &obj[k300 - 3..]);
+        cache.get_to(&rel_path, k300 - 2, &mut dst).unwrap_err();
+
         // get object not exists should fail
         let result = cache.get(&not_exists).unwrap_err();
         assert_eq!(result, Error::NotFound);
@@ -498,10 +754,17 @@ mod tests {
 
         // re-open local cache with bigger capacity
         drop(cache);
-        let mut cache =
-            LocalCache::new(cache_type, 2, base, &repo_id, &access_key)
-                .unwrap();
-        cache.connect().unwrap();
+        let mut cache = LocalCache::new(
+            cache_type,
+            2,
+            base,
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(false).unwrap();
         cache.open().unwrap();
 
         // delete object not exists should succeed
@@ -515,10 +778,17 @@ mod tests {
 
         // re-open cache with smaller capacity
         drop(cache);
-        let mut cache =
-            LocalCache::new(cache_type, 1, base, &repo_id, &access_key)
-                .unwrap();
-        cache.connect().unwrap();
+        let mut cache = LocalCache::new(
+            cache_type,
+            1,
+            base,
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(false).unwrap();
         cache.open().unwrap();
         if cache_type == CacheType::File {
             assert_eq!(cache.meta.lru.len(), 3);
@@ -534,7 +804,7 @@ mod tests {
 
     #[test]
     fn local_cache_mem() {
-        test_local_cache(CacheType::Mem, Path::new(""));
+        test_local_cache(CacheType::Mem, Path::new(""), "repo_lc_mem");
     }
 
     #[test]
@@ -544,6 +814,243 @@ mod tests {
         //if base.exists() {
         //std::fs::remove_dir_all(&base).unwrap();
         //}
-        test_local_cache(CacheType::File, &base);
+        test_local_cache(CacheType::File, &base, "repo_lc_file");
+    }
+
+    fn test_cache_policy(policy: CachePolicy, repo_id: &str) -> Vec<PathBuf> {
+        init_env();
+        let mut cache = LocalCache::new(
+            CacheType::Mem,
+            1,
+            Path::new(""),
+            false,
+            false,
+            repo_id,
+            "accessKey",
+        )
+        .unwrap();
+        cache.set_policy(policy);
+        cache.connect(false).unwrap();
+        cache.init().unwrap();
+
+        let k300 = 300 * 1000;
+        let k400 = 400 * 1000;
+        let k500 = 500 * 1000;
+        let rel_path = Path::new("data/aa/bb/k300");
+        let rel_path2 = Path::new("data/aa/bb/k400");
+        let rel_path3 = Path::new("data/aa/bb/k500");
+        cache.put(&rel_path, 0, &vec![1u8; k300]).unwrap();
+        cache.put(&rel_path2, 0, &vec![2u8; k400]).unwrap();
+        assert_eq!(cache.usage(), (k300 + k400, 1024 * 1024, 2));
+
+        // access the first object, then add the third one which makes
+        // eviction happen
+        let mut tgt = vec![0u8; k300];
+        cache.get_to(&rel_path, 0, &mut tgt).unwrap();
+        cache.put(&rel_path3, 0, &vec![3u8; k500]).unwrap();
+        assert_eq!(cache.usage().2, 2);
+
+        cache.meta.lru.keys().cloned().collect()
+    }
+
+    #[test]
+    fn local_cache_policy() {
+        // LRU keeps the recently accessed object
+        let keys = test_cache_policy(CachePolicy::Lru, "repo_lru");
+        assert_eq!(
+            keys,
+            vec![
+                PathBuf::from("data/aa/bb/k300"),
+                PathBuf::from("data/aa/bb/k500")
+            ]
+        );
+
+        // FIFO evicts the earliest cached object
+        let keys = test_cache_policy(CachePolicy::Fifo, "repo_fifo");
+        assert_eq!(
+            keys,
+            vec![
+                PathBuf::from("data/aa/bb/k400"),
+                PathBuf::from("data/aa/bb/k500")
+            ]
+        );
+    }
+
+    #[test]
+    fn local_cache_readonly() {
+        init_env();
+        let tmpdir = TempDir::new("zbox_test").expect("Create temp dir failed");
+        let base = tmpdir.path().to_path_buf();
+        let repo_id = "repo789";
+        let access_key = "accessKey789";
+        let rel_path = Path::new("data/aa/bb/k100");
+        let rel_path2 = Path::new("data/aa/bb/k200");
+        let obj = vec![1u8; 100 * 1000];
+        let obj2 = vec![2u8; 200 * 1000];
+
+        // populate cache
+        let mut cache = LocalCache::new(
+            CacheType::File,
+            1,
+            &base,
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(false).unwrap();
+        cache.init().unwrap();
+        cache.put(&rel_path, 0, &obj).unwrap();
+        cache.flush().unwrap();
+        drop(cache);
+
+        // put another object to remote only, so it is not cached
+        let mut cache = LocalCache::new(
+            CacheType::File,
+            1,
+            &base.join("other"),
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(true).unwrap();
+        cache.open().unwrap();
+        cache.put(&rel_path2, 0, &obj2).unwrap();
+        cache.flush().unwrap();
+        drop(cache);
+
+        // re-populate shared cache so its update sequence is in sync
+        let mut cache = LocalCache::new(
+            CacheType::File,
+            1,
+            &base,
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(true).unwrap();
+        cache.open().unwrap();
+        cache.get(&rel_path).unwrap();
+        cache.flush().unwrap();
+        drop(cache);
+
+        let meta_path = base.join(LocalCache::META_FILE_NAME);
+        let meta = std::fs::read(&meta_path).unwrap();
+
+        // open read-only cache
+        let mut cache = LocalCache::new(
+            CacheType::File,
+            1,
+            &base,
+            true,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(true).unwrap();
+        assert_eq!(cache.init().unwrap_err(), Error::ReadOnly);
+        cache.open().unwrap();
+
+        // cache hit
+        let mut dst = vec![0u8; 10];
+        cache.get_to(&rel_path, 0, &mut dst).unwrap();
+        assert_eq!(&dst[..], &obj[..10]);
+        assert_eq!(cache.get(&rel_path).unwrap(), obj);
+
+        // cache miss is served from remote without adding to cache
+        assert_eq!(cache.get(&rel_path2).unwrap(), obj2);
+        assert!(!base.join(&rel_path2).exists());
+
+        // reading beyond end of remote object is an error
+        cache
+            .get_to(&rel_path2, obj2.len() - 5, &mut dst)
+            .unwrap_err();
+
+        // cached object cannot be changed
+        assert_eq!(cache.del(&rel_path).unwrap_err(), Error::ReadOnly);
+        cache.flush().unwrap();
+        drop(cache);
+
+        // cache should not be modified
+        assert_eq!(std::fs::read(&meta_path).unwrap(), meta);
+        assert!(base.join(&rel_path).exists());
+    }
+
+    #[cfg(feature = "storage-zbox-faulty")]
+    #[test]
+    fn local_cache_read_your_writes() {
+        init_env();
+        let repo_id = "repo_ryw";
+        let access_key = "accessKey_ryw";
+        let rel_path = Path::new("data/aa/bb/k10");
+        let rel_path2 = Path::new("data/aa/bb/k20");
+        let obj = vec![1u8; 10 * 1000];
+        let mut expected = obj[..50].to_vec();
+        expected.extend_from_slice(&obj);
+
+        // each written object is not visible for the next read
+        set_visibility_delay(&repo_id, 1);
+
+        // without read-your-writes, partially put object is not in local
+        // cache, so reading it right after write will get not found
+        let mut cache = LocalCache::new(
+            CacheType::Mem,
+            1,
+            Path::new(""),
+            false,
+            false,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(false).unwrap();
+        cache.init().unwrap();
+        cache.put(&rel_path, 0, &obj).unwrap();
+        cache.put(&rel_path, 50, &obj).unwrap();
+        assert_eq!(cache.get(&rel_path).unwrap_err(), Error::NotFound);
+        assert_eq!(cache.get(&rel_path).unwrap(), expected);
+        drop(cache);
+
+        // with read-your-writes, object is served from write cache until
+        // remote makes it visible
+        let mut cache = LocalCache::new(
+            CacheType::Mem,
+            1,
+            Path::new(""),
+            false,
+            true,
+            &repo_id,
+            &access_key,
+        )
+        .unwrap();
+        cache.connect(true).unwrap();
+        cache.open().unwrap();
+        cache.put(&rel_path2, 0, &obj).unwrap();
+        cache.put(&rel_path2, 50, &obj).unwrap();
+        assert_eq!(cache.write_cache_used, expected.len());
+        let mut dst = vec![0u8; 10];
+        cache.get_to(&rel_path2, 45, &mut dst).unwrap();
+        assert_eq!(&dst[..], &expected[45..55]);
+        assert_eq!(cache.write_cache_used, expected.len());
+
+        // remote has confirmed the object, write cache should be empty
+        cache.del_local(&rel_path2).unwrap();
+        assert_eq!(cache.get(&rel_path2).unwrap(), expected);
+        assert_eq!(cache.write_cache_used, 0);
+        assert!(cache.write_cache.is_empty());
+
+        // deleted object should not be served from write cache
+        cache.put(&rel_path2, 0, &obj).unwrap();
+        cache.del(&rel_path2).unwrap();
+        assert!(cache.write_cache.is_empty());
+        assert_eq!(cache.get(&rel_path2).unwrap_err(), Error::NotFound);
+
+        set_visibility_delay(&repo_id, 0);
     }
 }
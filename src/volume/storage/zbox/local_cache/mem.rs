@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use super::CacheBackend;
-use crate::error::Result;
+use super::{copy_exact, CacheBackend};
+use crate::error::{Error, Result};
 
 pub struct MemBackend {
     map: HashMap<PathBuf, Vec<u8>>,
@@ -29,10 +29,8 @@ impl CacheBackend for MemBackend {
         offset: usize,
         dst: &mut [u8],
     ) -> Result<()> {
-        let obj = &self.map[rel_path];
-        let len = dst.len();
-        dst.copy_from_slice(&obj[offset..offset + len]);
-        Ok(())
+        let obj = self.map.get(rel_path).ok_or(Error::NotFound)?;
+        copy_exact(obj, offset, dst)
     }
 
     #[inline]
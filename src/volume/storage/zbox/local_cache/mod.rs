@@ -4,9 +4,12 @@
 mod browser;
 #[cfg(not(target_arch = "wasm32"))]
 mod file;
+#[cfg(target_arch = "wasm32")]
+mod indexeddb;
 mod local_cache;
 mod mem;
 
+use std::io::{Error as IoError, ErrorKind};
 use std::path::Path;
 use std::str::FromStr;
 
@@ -14,6 +17,9 @@ use serde::{Deserialize, Serialize};
 
 pub use self::local_cache::{LocalCache, LocalCacheRef};
 
+#[cfg(target_arch = "wasm32")]
+pub use self::indexeddb::load_indexeddb_cache;
+
 use crate::error::{Error, Result};
 
 // local cache type
@@ -21,6 +27,7 @@ use crate::error::{Error, Result};
 pub enum CacheType {
     Mem,
     File,
+    IndexedDb, // browser only
 }
 
 impl FromStr for CacheType {
@@ -31,7 +38,8 @@ impl FromStr for CacheType {
         match s {
             "mem" => Ok(CacheType::Mem),
             "file" => Ok(CacheType::File),
-            _ => Err(Error::InvalidUri),
+            "indexeddb" => Ok(CacheType::IndexedDb),
+            _ => Err(Error::invalid_uri(s, "unknown cache type")),
         }
     }
 }
@@ -43,6 +51,49 @@ impl Default for CacheType {
     }
 }
 
+// local cache eviction policy
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CachePolicy {
+    Lru,  // evict least recently used objects first
+    Fifo, // evict earliest cached objects first
+}
+
+impl FromStr for CachePolicy {
+    type Err = Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lru" => Ok(CachePolicy::Lru),
+            "fifo" => Ok(CachePolicy::Fifo),
+            _ => Err(Error::invalid_uri(s, "unknown cache policy")),
+        }
+    }
+}
+
+impl Default for CachePolicy {
+    #[inline]
+    fn default() -> Self {
+        CachePolicy::Lru
+    }
+}
+
+// copy object content starting from offset to fill up destination, return
+// an error if the object is too short
+fn copy_exact(obj: &[u8], offset: usize, dst: &mut [u8]) -> Result<()> {
+    let end = offset
+        .checked_add(dst.len())
+        .filter(|end| *end <= obj.len())
+        .ok_or_else(|| {
+            Error::from(IoError::new(
+                ErrorKind::UnexpectedEof,
+                "object is too short",
+            ))
+        })?;
+    dst.copy_from_slice(&obj[offset..end]);
+    Ok(())
+}
+
 // local cache storage backend trait
 pub(self) trait CacheBackend: Send + Sync {
     fn contains(&mut self, rel_path: &Path) -> bool;
@@ -6,3 +6,6 @@ mod transport;
 mod zbox;
 
 pub use self::zbox::ZboxStorage;
+
+#[cfg(target_arch = "wasm32")]
+pub use self::local_cache::load_indexeddb_cache;
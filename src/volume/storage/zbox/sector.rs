@@ -204,7 +204,7 @@ impl SectorMgr {
             }
 
             let offset = (sec_span.begin % BLKS_PER_SECTOR) * BLK_SIZE;
-            let len = sec_span.bytes_len();
+            let len = sec_span.bytes_len(BLK_SIZE);
 
             // if the blocks to be read are still in the staging sector
             if sec_idx == self.sec_idx && offset < self.sec_top {
@@ -231,7 +231,7 @@ impl SectorMgr {
         for sec_span in span.divide_by(BLKS_PER_SECTOR) {
             let sec_idx = sec_span.begin / BLKS_PER_SECTOR;
             let offset = (sec_span.begin % BLKS_PER_SECTOR) * BLK_SIZE;
-            let len = sec_span.bytes_len();
+            let len = sec_span.bytes_len(BLK_SIZE);
 
             // if this write is not continuous, we need to 'jump' in the
             // staging sector accordingly
@@ -324,8 +324,8 @@ impl Debug for SectorMgr {
 mod tests {
 
     use super::*;
-    use base::{init_env, IntoRef};
-    use volume::storage::zbox::local_cache::CacheType;
+    use crate::base::{init_env, IntoRef};
+    use crate::volume::storage::zbox::local_cache::CacheType;
 
     #[test]
     fn sector_oper() {
@@ -336,11 +336,13 @@ mod tests {
             CacheType::Mem,
             1,
             Path::new(""),
+            false,
+            false,
             &repo_id,
             &access_key,
         )
         .unwrap();
-        cache.connect().unwrap();
+        cache.connect(false).unwrap();
         cache.init().unwrap();
 
         let mut sec_mgr = SectorMgr::new(&cache.into_ref());
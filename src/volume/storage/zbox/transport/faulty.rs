@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Cursor, Read};
 use std::path::PathBuf;
 use std::sync::Mutex;
@@ -12,6 +12,7 @@ use http::{HeaderMap, Response as HttpResponse, Uri};
 use super::{Response, Transport};
 use crate::error::Result;
 use crate::volume::storage::faulty_ctl::Controller;
+use crate::volume::storage::TransportConfig;
 
 lazy_static! {
     // static store
@@ -33,30 +34,85 @@ fn create_ok_response() -> Result<Response> {
     create_response(StatusCode::OK, Vec::new())
 }
 
+// get repo id from uri, which is the first component in uri path
+#[inline]
+fn repo_id_of(uri: &Uri) -> &str {
+    uri.path()
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap()
+}
+
 #[derive(Default)]
 struct StaticStore {
     map: HashMap<Uri, Vec<u8>>,
-    update_seq: usize,
-    is_opened: bool,
-    is_updated: bool,
+
+    // update sequence for each repo
+    update_seqs: HashMap<String, usize>,
+
+    // repos which are updated in current session
+    updated: HashSet<String>,
+
+    // visibility delay for each repo, in number of reads
+    delays: HashMap<String, usize>,
+
+    // objects which are written but not visible yet, and their remaining
+    // number of reads before they become visible
+    hidden: HashMap<Uri, usize>,
 }
 
 impl StaticStore {
     #[inline]
-    fn update(&mut self) {
-        if !self.is_updated {
-            self.update_seq += 1;
-            self.is_updated = true;
+    fn update_seq(&self, repo_id: &str) -> usize {
+        self.update_seqs.get(repo_id).cloned().unwrap_or(0)
+    }
+
+    // increase repo update sequence once in a session
+    #[inline]
+    fn update(&mut self, uri: &Uri) {
+        let repo_id = repo_id_of(uri);
+        if self.updated.insert(repo_id.to_owned()) {
+            *self.update_seqs.entry(repo_id.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    // check if object is hidden, this will decrease its remaining reads
+    fn is_hidden(&mut self, uri: &Uri) -> bool {
+        match self.hidden.get_mut(uri) {
+            Some(reads) if *reads > 0 => {
+                *reads -= 1;
+                true
+            }
+            Some(_) => {
+                self.hidden.remove(uri);
+                false
+            }
+            None => false,
         }
     }
 }
 
+/// Simulate eventually consistent remote for a repo
+///
+/// After this is set, each object written to the repo will return not found
+/// error for the next `reads` reads. Set `reads` to 0 to turn it off.
+#[cfg(test)]
+pub fn set_visibility_delay(repo_id: &str, reads: usize) {
+    let mut store = STORE.lock().unwrap();
+    if reads == 0 {
+        store.delays.remove(repo_id);
+    } else {
+        store.delays.insert(repo_id.to_owned(), reads);
+    }
+}
+
 pub struct FaultyTransport {
     ctlr: Controller,
 }
 
 impl FaultyTransport {
-    pub fn new(_timeout: u32) -> Result<Self> {
+    pub fn new(_cfg: &TransportConfig) -> Result<Self> {
         Ok(FaultyTransport {
             ctlr: Controller::new(),
         })
@@ -70,10 +126,6 @@ impl Transport for FaultyTransport {
         let mut store = STORE.lock().unwrap();
 
         if uri.path().ends_with("/open") {
-            if store.is_opened {
-                //return create_response(StatusCode::CONFLICT, Vec::new());
-            }
-
             // fixed response body
             let body = format!(
                 r#"{{
@@ -82,20 +134,18 @@ impl Transport for FaultyTransport {
                 "updateSeq":{},
                 "ttl":1544269210
             }}"#,
-                store.update_seq
+                store.update_seq(repo_id_of(uri))
             );
-            store.is_opened = true;
-            store.is_updated = false;
+            store.updated.remove(repo_id_of(uri));
             return create_response(StatusCode::OK, body.into_bytes());
         }
 
         if uri.path().ends_with("/close") {
-            store.is_opened = false;
             return create_ok_response();
         }
 
         if uri.path().ends_with("/exists") {
-            let body = if store.update_seq == 0 {
+            let body = if store.update_seq(repo_id_of(uri)) == 0 {
                 String::from(r#"{"result":false}"#)
             } else {
                 String::from(r#"{"result":true}"#)
@@ -103,6 +153,10 @@ impl Transport for FaultyTransport {
             return create_response(StatusCode::OK, body.into_bytes());
         }
 
+        if store.is_hidden(uri) {
+            return create_response(StatusCode::NOT_FOUND, Vec::new());
+        }
+
         match store.map.get(uri) {
             Some(body) => create_response(StatusCode::OK, body.clone()),
             None => create_response(StatusCode::NOT_FOUND, Vec::new()),
@@ -144,7 +198,12 @@ impl Transport for FaultyTransport {
                 }
             });
 
-        store.update();
+        // make object invisible for a while if visibility delay is set
+        if let Some(&reads) = store.delays.get(repo_id_of(uri)) {
+            store.hidden.insert(uri.to_owned(), reads);
+        }
+
+        store.update(uri);
         create_ok_response()
     }
 
@@ -153,7 +212,8 @@ impl Transport for FaultyTransport {
 
         let mut store = STORE.lock().unwrap();
         store.map.remove(uri);
-        store.update();
+        store.hidden.remove(uri);
+        store.update(uri);
         create_ok_response()
     }
 
@@ -177,9 +237,10 @@ impl Transport for FaultyTransport {
                 let url = base.to_owned() + uri.to_str().unwrap();
                 let url = url.parse::<Uri>().unwrap();
                 store.map.remove(&url);
+                store.hidden.remove(&url);
             }
         }
-        store.update();
+        store.update(uri);
         create_ok_response()
     }
 }
@@ -9,6 +9,7 @@ use jni::{JNIEnv, JavaVM};
 use super::{Response, Transport};
 use base::JVM;
 use error::{Error, Result};
+use volume::storage::TransportConfig;
 
 // create URL parameter for JNI call
 fn create_url_param<'a>(env: &JNIEnv<'a>, uri: &Uri) -> JValue<'a> {
@@ -111,7 +112,8 @@ pub struct JniTransport {
 }
 
 impl JniTransport {
-    pub fn new(timeout: u32) -> Result<Self> {
+    pub fn new(cfg: &TransportConfig) -> Result<Self> {
+        let timeout = cfg.request_timeout.as_secs();
         let jvm = unsafe {
             let jvm = JVM.lock().unwrap();
             JavaVM::from_raw(jvm.get_java_vm_pointer())?
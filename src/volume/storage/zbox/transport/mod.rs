@@ -24,12 +24,15 @@ pub struct Response {
 }
 
 impl Response {
+    // maximum length of error message taken from response body, in chars
+    const MAX_ERR_MSG_LEN: usize = 512;
+
     #[inline]
     pub fn new(inner: HttpResponse<Box<dyn Read>>) -> Self {
         Response { inner }
     }
 
-    pub fn error_for_status(self) -> Result<Self> {
+    pub fn error_for_status(mut self) -> Result<Self> {
         let status = self.inner.status();
 
         // 409 conflict error means remote session is already opened
@@ -38,7 +41,19 @@ impl Response {
         }
 
         if !status.is_success() {
-            return Err(Error::HttpStatus(status));
+            // keep response body as error message, error in reading body
+            // is ignored as we already have an error to return. Only the
+            // leading part of body is read, as a UTF-8 char takes at most 4
+            // bytes.
+            let mut buf = Vec::new();
+            let max_len = (Self::MAX_ERR_MSG_LEN * 4) as u64;
+            let _ = self.inner.body_mut().take(max_len).read_to_end(&mut buf);
+            let message = String::from_utf8_lossy(&buf)
+                .trim()
+                .chars()
+                .take(Self::MAX_ERR_MSG_LEN)
+                .collect();
+            return Err(Error::HttpStatus { status, message });
         }
 
         Ok(self)
@@ -116,3 +131,54 @@ impl Transport for DummyTransport {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn create_response(status: StatusCode, body: &str) -> Response {
+        let body = Cursor::new(body.as_bytes().to_vec());
+        Response::new(
+            HttpResponse::builder()
+                .status(status)
+                .body(Box::new(body) as Box<dyn Read>)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn error_for_status() {
+        let resp = create_response(StatusCode::OK, "ok");
+        assert!(resp.error_for_status().is_ok());
+
+        let resp = create_response(StatusCode::CONFLICT, "");
+        assert_eq!(resp.error_for_status().err().unwrap(), Error::RepoOpened);
+
+        // response body is kept as error message
+        let body = r#"{"error":"invalid range"}"#;
+        let resp = create_response(StatusCode::BAD_REQUEST, body);
+        let err = resp.error_for_status().err().unwrap();
+        assert_eq!(err, Error::from(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            err.to_string(),
+            format!("Http status 400 Bad Request: {}", body)
+        );
+
+        // long response body is truncated
+        let body = "x".repeat(Response::MAX_ERR_MSG_LEN * 2);
+        let resp = create_response(StatusCode::BAD_GATEWAY, &body);
+        match resp.error_for_status().err().unwrap() {
+            Error::HttpStatus { status, message } => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(message.len(), Response::MAX_ERR_MSG_LEN);
+            }
+            _ => unreachable!(),
+        }
+
+        // empty body has no message
+        let resp = create_response(StatusCode::NOT_FOUND, "");
+        let err = resp.error_for_status().err().unwrap();
+        assert_eq!(err.to_string(), "Http status 404 Not Found");
+    }
+}
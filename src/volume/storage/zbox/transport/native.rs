@@ -1,14 +1,16 @@
 use http::{HeaderMap, Response as HttpResponse, Uri};
 use std::io::Read;
+use std::thread;
 use std::time::Duration;
 
 use bytes::Buf;
 use futures::executor::block_on;
-use log::trace;
+use log::{trace, warn};
 use reqwest::{Client, Response as NativeResponse};
 
 use super::{Response, Transport};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::volume::storage::TransportConfig;
 
 // convert reqwest response to response
 fn create_response(resp: NativeResponse) -> Result<Response> {
@@ -22,31 +24,74 @@ fn create_response(resp: NativeResponse) -> Result<Response> {
     Ok(ret)
 }
 
+// maximum delay before a retry
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// get delay before a retry, retries is the number of retries already made
+fn backoff_delay(backoff: Duration, retries: u32) -> Duration {
+    backoff
+        .checked_mul(1u32.checked_shl(retries).unwrap_or(u32::MAX))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+// check if request result is a transient error which can be retried
+fn is_transient(result: &Result<Response>) -> bool {
+    match result {
+        Ok(resp) => resp.inner.status().is_server_error(),
+        Err(Error::Reqwest(err)) => err.is_timeout() || err.is_connect(),
+        Err(_) => false,
+    }
+}
+
 // transport using native http layer
 pub struct NativeTransport {
     client: Client,
+    cfg: TransportConfig,
 }
 
 impl NativeTransport {
-    pub fn new(timeout: u32) -> Result<Self> {
+    pub fn new(cfg: &TransportConfig) -> Result<Self> {
         let client = Client::builder()
-            .timeout(Duration::from_secs(u64::from(timeout)))
+            .connect_timeout(cfg.connect_timeout)
+            .timeout(cfg.request_timeout)
             .build()?;
 
-        Ok(NativeTransport { client })
+        Ok(NativeTransport { client, cfg: *cfg })
+    }
+
+    // send an idempotent request, retry it with exponential backoff if it
+    // failed with transient error
+    fn send_with_retry<F>(&self, mut send: F) -> Result<Response>
+    where
+        F: FnMut() -> Result<Response>,
+    {
+        let mut retries = 0;
+        loop {
+            let result = send();
+            if retries >= self.cfg.max_retries || !is_transient(&result) {
+                return result;
+            }
+            let delay = backoff_delay(self.cfg.backoff, retries);
+            retries += 1;
+            warn!("transient error, retry {} after {:?}", retries, delay);
+            thread::sleep(delay);
+        }
     }
 }
 
 impl Transport for NativeTransport {
     fn get(&self, uri: &Uri, headers: &HeaderMap) -> Result<Response> {
         trace!("get: {}, headers: {:?}", uri, headers);
-        let resp = block_on(
-            self.client
-                .get(&uri.to_string())
-                .headers(headers.clone())
-                .send(),
-        )?;
-        create_response(resp)
+        self.send_with_retry(|| {
+            let resp = block_on(
+                self.client
+                    .get(&uri.to_string())
+                    .headers(headers.clone())
+                    .send(),
+            )?;
+            create_response(resp)
+        })
     }
 
     fn put(
@@ -56,16 +101,19 @@ impl Transport for NativeTransport {
         body: &[u8],
     ) -> Result<Response> {
         trace!("put: {}, headers: {:?}", uri, headers);
-        let resp = block_on(
-            self.client
-                .put(&uri.to_string())
-                .headers(headers.clone())
-                .body(body.to_owned())
-                .send(),
-        )?;
-        create_response(resp)
+        self.send_with_retry(|| {
+            let resp = block_on(
+                self.client
+                    .put(&uri.to_string())
+                    .headers(headers.clone())
+                    .body(body.to_owned())
+                    .send(),
+            )?;
+            create_response(resp)
+        })
     }
 
+    // delete requests are not retried
     fn delete(&mut self, uri: &Uri, headers: &HeaderMap) -> Result<Response> {
         trace!("delete: {}, headers: {:?}", uri, headers);
         let resp = block_on(
@@ -94,3 +142,98 @@ impl Transport for NativeTransport {
         create_response(resp)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // start a http server which responds 503 for the first `failures`
+    // requests and 200 afterwards, return its uri and request counter
+    fn start_server(failures: usize) -> (Uri, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cnt = Arc::new(AtomicUsize::new(0));
+        let counter = cnt.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).unwrap();
+                let seq = counter.fetch_add(1, Ordering::SeqCst);
+                let status = if seq < failures {
+                    "503 Service Unavailable"
+                } else {
+                    "200 OK"
+                };
+                let resp = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\n\
+                     Connection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(resp.as_bytes()).unwrap();
+            }
+        });
+
+        let uri = format!("http://{}/repo/obj", addr).parse().unwrap();
+        (uri, cnt)
+    }
+
+    #[test]
+    fn backoff() {
+        let backoff = Duration::from_millis(200);
+        assert_eq!(backoff_delay(backoff, 0), Duration::from_millis(200));
+        assert_eq!(backoff_delay(backoff, 1), Duration::from_millis(400));
+        assert_eq!(backoff_delay(backoff, 3), Duration::from_millis(1600));
+        assert_eq!(backoff_delay(backoff, 20), MAX_BACKOFF);
+        assert_eq!(backoff_delay(backoff, 100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn retry_transient_error() {
+        // reqwest needs tokio runtime context to drive connections
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _guard = rt.enter();
+
+        let cfg = TransportConfig {
+            max_retries: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut transport = NativeTransport::new(&cfg).unwrap();
+        let headers = HeaderMap::new();
+
+        // succeed after retries
+        let (uri, cnt) = start_server(2);
+        let resp = transport.get(&uri, &headers).unwrap();
+        assert!(resp.inner.status().is_success());
+        assert_eq!(cnt.load(Ordering::SeqCst), 3);
+
+        // give up after max retries
+        let (uri, cnt) = start_server(10);
+        let resp = transport.put(&uri, &headers, b"foo").unwrap();
+        assert!(resp.inner.status().is_server_error());
+        assert_eq!(cnt.load(Ordering::SeqCst), 3);
+
+        // delete is never retried
+        let (uri, cnt) = start_server(1);
+        let resp = transport.delete(&uri, &headers).unwrap();
+        assert!(resp.inner.status().is_server_error());
+        assert_eq!(cnt.load(Ordering::SeqCst), 1);
+
+        // no retry if it is turned off
+        let cfg = TransportConfig {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let transport = NativeTransport::new(&cfg).unwrap();
+        let (uri, cnt) = start_server(1);
+        let resp = transport.get(&uri, &headers).unwrap();
+        assert!(resp.inner.status().is_server_error());
+        assert_eq!(cnt.load(Ordering::SeqCst), 1);
+    }
+}
@@ -8,6 +8,7 @@ use web_sys::{XmlHttpRequest, XmlHttpRequestResponseType};
 
 use super::{Response, Transport};
 use error::{Error, Result};
+use volume::storage::TransportConfig;
 
 // XMLHttpRequest ready state: DONE
 const READY_STATE_DONE: u16 = 4;
@@ -61,9 +62,9 @@ pub struct WasmTransport {
 }
 
 impl WasmTransport {
-    pub fn new(timeout: u32) -> Result<Self> {
+    pub fn new(cfg: &TransportConfig) -> Result<Self> {
         Ok(WasmTransport {
-            timeout: timeout * 1000,
+            timeout: cfg.request_timeout.as_millis() as u32,
         })
     }
 
@@ -2,7 +2,7 @@ use std::fmt::{self, Debug};
 use std::path::{Path, PathBuf};
 
 use super::index_accessor::IndexAccessor;
-use super::local_cache::{CacheType, LocalCache, LocalCacheRef};
+use super::local_cache::{CachePolicy, CacheType, LocalCache, LocalCacheRef};
 use super::sector::SectorMgr;
 use crate::base::crypto::{Crypto, Key};
 use crate::base::IntoRef;
@@ -10,28 +10,55 @@ use crate::error::{Error, Result};
 use crate::trans::Eid;
 use crate::volume::address::Span;
 use crate::volume::storage::index_mgr::{IndexMgr, Lsmt, MemTab, Tab};
-use crate::volume::storage::Storable;
+use crate::volume::storage::{CacheHook, CryptoCtx, Storable, TransportConfig};
+use crate::volume::BLK_SIZE;
+
+// parsed zbox storage uri
+#[derive(Debug)]
+struct ZboxUri<'a> {
+    access_key: &'a str,
+    repo_id: &'a str,
+    cache_type: CacheType,
+    cache_size: usize, // in mb
+    cache_dir: PathBuf,
+    cache_readonly: bool,
+    read_your_writes: bool,
+    cache_policy: CachePolicy,
+}
 
 // parse uri
 // example: access_key@repo_id?cache_type=mem&cache_size=2mb[&base=path]
-// return: (
-//   access_key: &str,
-//   repo_id: &str,
-//   cache_type: CacheType,
-//   cache_size: usize,
-//   base: PathBuf
-// )
-fn parse_uri(mut uri: &str) -> Result<(&str, &str, CacheType, usize, PathBuf)> {
+//          [&cache_dir=path][&cache_readonly=true][&read_your_writes=true]
+//
+// `cache_type` is `mem`, `file` or `indexeddb`. IndexedDB cache is only
+// available in browser, it persists across page reloads. Wait for
+// `load_indexeddb_cache` before opening repo to reuse the cached objects.
+//
+// `cache_policy` is the eviction policy of local cache, `lru` (default) or
+// `fifo`.
+//
+// `cache_dir` is the exact directory used by file cache, it overrides the
+// default location `base/repo_id`. `cache_readonly` makes the file cache
+// read-only, which is useful when a pre-populated cache is shared by
+// multiple processes.
+//
+// `read_your_writes` is for eventually consistent remote, it keeps recently
+// written objects in memory and serves them until remote makes them visible,
+// so reading right after writing will not get spurious not found error.
+fn parse_uri(mut uri: &str) -> Result<ZboxUri> {
+    let full_uri = format!("zbox://{}", uri);
+    let invalid = |reason: &str| Error::invalid_uri(&full_uri, reason);
+
     if !uri.is_ascii() {
-        return Err(Error::InvalidUri);
+        return Err(invalid("not an ASCII string"));
     }
 
     // parse access key, required
-    let mut idx = uri.find('@').ok_or(Error::InvalidUri)?;
+    let mut idx = uri.find('@').ok_or_else(|| invalid("missing access key"))?;
     let access_key = &uri[..idx];
     uri = &uri[idx + 1..];
     if uri.is_empty() {
-        return Err(Error::InvalidUri);
+        return Err(invalid("missing repo id"));
     }
 
     // parse repo id, required
@@ -48,52 +75,100 @@ fn parse_uri(mut uri: &str) -> Result<(&str, &str, CacheType, usize, PathBuf)> {
     let mut cache_type: Option<CacheType> = Some(CacheType::Mem);
     let mut cache_size: Option<usize> = Some(1);
     let mut base: Option<PathBuf> = None;
+    let mut cache_dir: Option<PathBuf> = None;
+    let mut cache_readonly = false;
+    let mut read_your_writes = false;
+    let mut cache_policy = CachePolicy::default();
 
     // parse parameters
     if !uri.is_empty() {
         for param in uri.split('&') {
-            idx = param.find('=').ok_or(Error::InvalidUri)?;
+            idx = param
+                .find('=')
+                .ok_or_else(|| invalid("parameter has no value"))?;
             let key = &param[..idx];
             let value = &param[idx + 1..];
 
             match key {
                 "cache_type" => {
-                    let ctype = value.parse::<CacheType>()?;
+                    let ctype = value
+                        .parse::<CacheType>()
+                        .map_err(|_| invalid("unknown cache_type"))?;
+                    if ctype == CacheType::IndexedDb
+                        && !cfg!(target_arch = "wasm32")
+                    {
+                        return Err(invalid(
+                            "indexeddb cache is only available in browser",
+                        ));
+                    }
                     cache_type = Some(ctype);
                 }
                 "cache_size" => {
                     let value = value.to_lowercase();
-                    let idx = value.find("mb").ok_or(Error::InvalidUri)?;
+                    let idx = value
+                        .find("mb")
+                        .ok_or_else(|| invalid("cache_size must be in mb"))?;
                     let value = &value[..idx];
                     let size = value
                         .parse::<usize>()
-                        .map_err(|_| Error::InvalidUri)?;
+                        .map_err(|_| invalid("invalid cache_size"))?;
                     if size < 1 {
                         // cache size must >= 1MB
-                        return Err(Error::InvalidUri);
+                        return Err(invalid("cache_size must be at least 1mb"));
                     }
                     cache_size = Some(size);
                 }
                 "base" => {
                     base = Some(PathBuf::from(value));
                 }
-                _ => return Err(Error::InvalidUri),
+                "cache_dir" => {
+                    cache_dir = Some(PathBuf::from(value));
+                }
+                "cache_readonly" => {
+                    cache_readonly = value
+                        .parse::<bool>()
+                        .map_err(|_| invalid("invalid cache_readonly"))?;
+                }
+                "read_your_writes" => {
+                    read_your_writes = value
+                        .parse::<bool>()
+                        .map_err(|_| invalid("invalid read_your_writes"))?;
+                }
+                "cache_policy" => {
+                    cache_policy = value
+                        .parse::<CachePolicy>()
+                        .map_err(|_| invalid("unknown cache_policy"))?;
+                }
+                _ => return Err(invalid("unknown parameter")),
             }
         }
     }
 
     // verify parameters
-    if cache_type == Some(CacheType::File) && base.is_none() {
-        return Err(Error::InvalidUri);
+    let cache_dir = match (cache_dir, base) {
+        (Some(dir), _) => dir,
+        (None, Some(base)) => base.join(repo_id),
+        (None, None) => PathBuf::from(""),
+    };
+    if cache_type == Some(CacheType::File) && cache_dir.as_os_str().is_empty() {
+        return Err(invalid("file cache requires base or cache_dir"));
+    }
+    if cache_readonly && cache_type != Some(CacheType::File) {
+        // only file cache can be shared, so read-only mode only applies
+        // to it
+        return Err(invalid("cache_readonly requires file cache"));
     }
 
-    Ok((
+    Ok(ZboxUri {
         access_key,
         repo_id,
-        cache_type.unwrap(),
-        cache_size.unwrap(),
-        base.unwrap_or_else(|| PathBuf::from("")),
-    ))
+        cache_type: cache_type.unwrap(),
+        cache_size: cache_size.unwrap(),
+        cache_dir,
+        cache_readonly,
+        read_your_writes,
+        cache_policy,
+    })
 }
 
 /// Zbox Storage
@@ -119,14 +194,20 @@ impl ZboxStorage {
     // create zbox storage
     pub fn new(uri: &str) -> Result<Self> {
         // parse uri string
-        let (access_key, repo_id, cache_type, cache_size, base) =
-            parse_uri(uri)?;
+        let uri = parse_uri(uri)?;
 
         // create local cache
-        let local_cache = LocalCache::new(
-            cache_type, cache_size, &base, repo_id, access_key,
-        )?
-        .into_ref();
+        let mut local_cache = LocalCache::new(
+            uri.cache_type,
+            uri.cache_size,
+            &uri.cache_dir,
+            uri.cache_readonly,
+            uri.read_your_writes,
+            uri.repo_id,
+            uri.access_key,
+        )?;
+        local_cache.set_policy(uri.cache_policy);
+        let local_cache = local_cache.into_ref();
 
         // create sector manager and index manager
         let sec_mgr = SectorMgr::new(&local_cache);
@@ -156,7 +237,7 @@ impl ZboxStorage {
         self.sec_mgr.set_crypto_ctx(crypto.clone(), subkey);
 
         let subkey = key.derive(Self::SUBKEY_ID_IDX_MGR);
-        self.idx_mgr.set_crypto_ctx(crypto, subkey);
+        self.idx_mgr.set_crypto_ctx(crypto, None, subkey);
     }
 }
 
@@ -173,7 +254,8 @@ impl Storable for ZboxStorage {
         local_cache.connect(force)
     }
 
-    fn init(&mut self, crypto: Crypto, key: Key) -> Result<()> {
+    fn init(&mut self, ctx: CryptoCtx) -> Result<()> {
+        let (crypto, _, key) = ctx.into_parts();
         self.set_crypto_ctx(crypto, key);
         {
             let mut local_cache = self.local_cache.write().unwrap();
@@ -184,7 +266,8 @@ impl Storable for ZboxStorage {
         Ok(())
     }
 
-    fn open(&mut self, crypto: Crypto, key: Key, _force: bool) -> Result<()> {
+    fn open(&mut self, ctx: CryptoCtx, _force: bool) -> Result<()> {
+        let (crypto, _, key) = ctx.into_parts();
         self.set_crypto_ctx(crypto, key);
         {
             let mut local_cache = self.local_cache.write().unwrap();
@@ -248,13 +331,13 @@ impl Storable for ZboxStorage {
 
     #[inline]
     fn get_blocks(&mut self, dst: &mut [u8], span: Span) -> Result<()> {
-        assert_eq!(dst.len(), span.bytes_len());
+        assert_eq!(dst.len(), span.bytes_len(BLK_SIZE));
         self.sec_mgr.get_blocks(dst, span)
     }
 
     #[inline]
     fn put_blocks(&mut self, span: Span, blks: &[u8]) -> Result<()> {
-        assert_eq!(blks.len(), span.bytes_len());
+        assert_eq!(blks.len(), span.bytes_len(BLK_SIZE));
         self.sec_mgr.put_blocks(span, blks)
     }
 
@@ -276,6 +359,35 @@ impl Storable for ZboxStorage {
         let mut local_cache = self.local_cache.write().unwrap();
         local_cache.destroy_repo()
     }
+
+    #[inline]
+    fn set_cache_hook(&mut self, hook: CacheHook) {
+        let mut local_cache = self.local_cache.write().unwrap();
+        local_cache.set_cache_hook(hook);
+    }
+
+    fn set_transport_config(&mut self, cfg: &TransportConfig) -> Result<()> {
+        let mut local_cache = self.local_cache.write().unwrap();
+        local_cache.set_transport_config(cfg)
+    }
+
+    #[inline]
+    fn local_cache_usage(&self) -> Result<(usize, usize, usize)> {
+        let local_cache = self.local_cache.read().unwrap();
+        Ok(local_cache.usage())
+    }
+
+    #[inline]
+    fn cache_mem_used(&self) -> usize {
+        let local_cache = self.local_cache.read().unwrap();
+        local_cache.mem_used()
+    }
+
+    #[inline]
+    fn set_cache_mem(&mut self, capacity: usize) {
+        let mut local_cache = self.local_cache.write().unwrap();
+        local_cache.set_mem_capacity(capacity);
+    }
 }
 
 impl Debug for ZboxStorage {
@@ -294,27 +406,90 @@ mod tests {
     use self::tempdir::TempDir;
 
     use super::*;
-    use base::init_env;
-    use volume::BLK_SIZE;
+    use crate::base::init_env;
+    use crate::volume::BLK_SIZE;
+
+    // uri and reason are not compared
+    fn invalid_uri() -> Error {
+        Error::invalid_uri("", "")
+    }
 
     #[test]
     fn zbox_parse_uri() {
-        assert_eq!(parse_uri("").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("abcd").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("中文").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("//").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("zbox://").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("zbox://foo").unwrap_err(), Error::InvalidUri);
-        assert_eq!(parse_uri("zbox://foo@").unwrap_err(), Error::InvalidUri);
+        assert_eq!(parse_uri("").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("abcd").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("中文").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("//").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("zbox://").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("zbox://foo").unwrap_err(), invalid_uri());
+        assert_eq!(parse_uri("zbox://foo@").unwrap_err(), invalid_uri());
         assert!(parse_uri("zbox://foo@bar").is_ok());
         assert!(parse_uri("zbox://foo@bar?").is_ok());
+
+        // cache directory and read-only cache
+        let uri = parse_uri("foo@bar?cache_type=file&base=/tmp").unwrap();
+        assert_eq!(uri.access_key, "foo");
+        assert_eq!(uri.repo_id, "bar");
+        assert_eq!(uri.cache_type, CacheType::File);
+        assert_eq!(uri.cache_size, 1);
+        assert_eq!(uri.cache_dir, Path::new("/tmp/bar"));
+        assert!(!uri.cache_readonly);
+        let uri = parse_uri(
+            "foo@bar?cache_type=file&base=/a&cache_dir=/b&cache_readonly=true",
+        )
+        .unwrap();
+        assert_eq!(uri.cache_dir, Path::new("/b"));
+        assert!(uri.cache_readonly);
+        assert_eq!(
+            parse_uri("foo@bar?cache_type=file").unwrap_err(),
+            invalid_uri()
+        );
+        assert_eq!(
+            parse_uri("foo@bar?cache_type=mem&cache_readonly=true")
+                .unwrap_err(),
+            invalid_uri()
+        );
+        assert_eq!(
+            parse_uri("foo@bar?cache_type=file&cache_dir=/a&cache_readonly=1")
+                .unwrap_err(),
+            invalid_uri()
+        );
+
+        // indexeddb cache is only available in browser
+        assert_eq!(
+            parse_uri("foo@bar?cache_type=indexeddb").unwrap_err(),
+            invalid_uri()
+        );
+
+        // cache policy
+        let uri = parse_uri("foo@bar").unwrap();
+        assert_eq!(uri.cache_policy, CachePolicy::Lru);
+        let uri = parse_uri("foo@bar?cache_policy=fifo").unwrap();
+        assert_eq!(uri.cache_policy, CachePolicy::Fifo);
+        assert_eq!(
+            parse_uri("foo@bar?cache_policy=lfu").unwrap_err(),
+            invalid_uri()
+        );
+
+        // read-your-writes mode
+        assert!(!parse_uri("foo@bar").unwrap().read_your_writes);
+        assert!(
+            parse_uri("foo@bar?read_your_writes=true")
+                .unwrap()
+                .read_your_writes
+        );
+        assert_eq!(
+            parse_uri("foo@bar?read_your_writes=yes").unwrap_err(),
+            invalid_uri()
+        );
     }
 
     fn do_test(uri: &str) {
         init_env();
         let mut zs = ZboxStorage::new(uri).unwrap();
-        zs.connect().unwrap();
-        zs.init(Crypto::default(), Key::new_empty()).unwrap();
+        zs.connect(false).unwrap();
+        zs.init(CryptoCtx::new(Crypto::default(), Key::new_empty()))
+            .unwrap();
 
         let id = Eid::new();
         let buf = vec![1, 2, 3];
@@ -362,8 +537,9 @@ mod tests {
         // re-open
         drop(zs);
         let mut zs = ZboxStorage::new(uri).unwrap();
-        zs.connect().unwrap();
-        zs.open(Crypto::default(), Key::new_empty()).unwrap();
+        zs.connect(false).unwrap();
+        zs.open(CryptoCtx::new(Crypto::default(), Key::new_empty()), false)
+            .unwrap();
 
         zs.get_blocks(&mut dst[..BLK_SIZE], Span::new(0, 1))
             .unwrap();
@@ -5,9 +5,14 @@ use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 
 use super::storage::Storage;
-use super::BLK_SIZE;
-use crate::base::crypto::{Cipher, Cost, Crypto, Key, Salt, SALT_SIZE};
+use super::{Compression, BLKS_PER_FRAME, BLK_SIZE};
+use crate::base::crypto::{
+    Cipher, Cost, Crypto, Key, Salt, KEY_SIZE, SALT_SIZE,
+};
 use crate::base::{Time, Version};
+use crate::content::{
+    DEFAULT_AVG_CHUNK_SIZE, DEFAULT_MAX_CHUNK_SIZE, DEFAULT_MIN_CHUNK_SIZE,
+};
 use crate::error::{Error, Result};
 use crate::trans::Eid;
 
@@ -17,11 +22,78 @@ pub(super) struct Head {
     pub salt: Salt,
     pub cost: Cost,
     pub cipher: Cipher,
+
+    // key factors required to derive volume key, saved in the high nibble
+    // of cipher byte, repos created before key file was added only use
+    // password
+    factors: u8,
 }
 
 impl Head {
     const BYTES_LEN: usize = SALT_SIZE + Cost::BYTES_LEN + Cipher::BYTES_LEN;
 
+    // key factor flags
+    const FACTOR_KEY_FILE: u8 = 1;
+    const FACTOR_NO_PWD: u8 = 2;
+
+    // set key factors, password is not used if it is empty and a key file
+    // is specified
+    pub fn set_factors(&mut self, pwd: &str, key_file: Option<&Key>) {
+        self.factors = match key_file {
+            Some(_) if pwd.is_empty() => {
+                Self::FACTOR_KEY_FILE | Self::FACTOR_NO_PWD
+            }
+            Some(_) => Self::FACTOR_KEY_FILE,
+            None => 0,
+        };
+    }
+
+    #[inline]
+    fn requires_key_file(&self) -> bool {
+        self.factors & Self::FACTOR_KEY_FILE != 0
+    }
+
+    #[inline]
+    fn requires_pwd(&self) -> bool {
+        self.factors & Self::FACTOR_NO_PWD == 0
+    }
+
+    // derive volume key from password and key file hash
+    fn derive_key(&self, pwd: &str, key_file: Option<&Key>) -> Result<Key> {
+        let crypto = Crypto::new(self.cost, self.cipher)?;
+
+        let key_file = match key_file {
+            Some(_) if !self.requires_key_file() => {
+                return Err(Error::InvalidArgument);
+            }
+            Some(key_file) => key_file,
+            None if self.requires_key_file() => {
+                return Err(Error::MissingKeyFactor);
+            }
+            None => return Ok(crypto.hash_pwd(pwd, &self.salt)?.value),
+        };
+
+        // key file hash is keyed hashed with salt
+        let file_hash = Crypto::hash_with_key(self.salt.as_ref(), key_file);
+        let mut vkey = Key::new_empty();
+        vkey.copy(&file_hash);
+        if !self.requires_pwd() {
+            return Ok(vkey);
+        }
+        if pwd.is_empty() {
+            return Err(Error::MissingKeyFactor);
+        }
+
+        // combine both factors
+        let pwd_hash = crypto.hash_pwd(pwd, &self.salt)?;
+        let mut buf = [0u8; KEY_SIZE];
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = file_hash[i] ^ pwd_hash.value.as_slice()[i];
+        }
+        vkey.copy(&buf);
+        Ok(vkey)
+    }
+
     fn seri(&self) -> Vec<u8> {
         let mut pos = 0;
         let mut buf = vec![0u8; Self::BYTES_LEN];
@@ -29,7 +101,7 @@ impl Head {
         pos += SALT_SIZE;
         buf[pos] = self.cost.to_u8();
         pos += Cost::BYTES_LEN;
-        buf[pos] = self.cipher.into();
+        buf[pos] = u8::from(self.cipher) | self.factors << 4;
         buf
     }
 
@@ -43,12 +115,25 @@ impl Head {
         pos += SALT_SIZE;
         let cost = Cost::from_u8(buf[pos])?;
         pos += Cost::BYTES_LEN;
-        let cipher = Cipher::from_u8(buf[pos])?;
-
-        Ok(Head { salt, cost, cipher })
+        let cipher = Cipher::from_u8(buf[pos] & 0x0f)?;
+        let factors = buf[pos] >> 4;
+
+        Ok(Head {
+            salt,
+            cost,
+            cipher,
+            factors,
+        })
     }
 }
 
+/// Cipher change in progress
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub(super) struct CipherChange {
+    pub from: Cipher,
+    pub to: Cipher,
+}
+
 /// Super block body, encrypted
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub(super) struct Body {
@@ -61,9 +146,91 @@ pub(super) struct Body {
     pub ctime: Time,
     pub mtime: Time,
     pub payload: Vec<u8>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "Body::default_blk_size")]
+    pub blk_size: usize,
+    #[serde(default = "Body::default_blks_per_frame")]
+    pub blks_per_frame: usize,
+
+    // compression algorithm, repos created before it was added only use
+    // the compress flag, which means LZ4
+    #[serde(default)]
+    pub compression: Option<Compression>,
+
+    // content chunk sizes, repos created before they were configurable use
+    // the defaults
+    #[serde(default = "Body::default_chunk_min_size")]
+    pub chunk_min_size: usize,
+    #[serde(default = "Body::default_chunk_avg_size")]
+    pub chunk_avg_size: usize,
+    #[serde(default = "Body::default_chunk_max_size")]
+    pub chunk_max_size: usize,
+
+    // cipher the storage depot's own metadata is encrypted with, it is
+    // re-encrypted along with entities only if the depot supports it, None
+    // means it is same as the cipher in head
+    #[serde(default)]
+    pub depot_cipher: Option<Cipher>,
+
+    // unfinished cipher change, entities may be encrypted with either of
+    // the ciphers
+    #[serde(default)]
+    pub cipher_change: Option<CipherChange>,
 }
 
 impl Body {
+    // repos created before block size was configurable use the defaults
+    #[inline]
+    fn default_blk_size() -> usize {
+        BLK_SIZE
+    }
+
+    #[inline]
+    fn default_blks_per_frame() -> usize {
+        BLKS_PER_FRAME
+    }
+
+    #[inline]
+    fn default_chunk_min_size() -> usize {
+        DEFAULT_MIN_CHUNK_SIZE
+    }
+
+    #[inline]
+    fn default_chunk_avg_size() -> usize {
+        DEFAULT_AVG_CHUNK_SIZE
+    }
+
+    #[inline]
+    fn default_chunk_max_size() -> usize {
+        DEFAULT_MAX_CHUNK_SIZE
+    }
+
+    #[inline]
+    pub fn depot_cipher(&self, head: &Head) -> Cipher {
+        self.depot_cipher.unwrap_or(head.cipher)
+    }
+
+    // the other cipher depot's metadata can be encrypted with if it was
+    // being re-encrypted when an unfinished cipher change is interrupted
+    #[inline]
+    pub fn depot_prev_cipher(&self, head: &Head) -> Option<Cipher> {
+        let depot_cipher = self.depot_cipher(head);
+        self.cipher_change.map(|change| {
+            if change.to == depot_cipher {
+                change.from
+            } else {
+                change.to
+            }
+        })
+    }
+
+    #[inline]
+    pub fn compression(&self) -> Compression {
+        self.compression
+            .unwrap_or_else(|| Compression::from_bool(self.compress))
+    }
+
     fn seri(&mut self) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
         self.seq += 1;
@@ -84,6 +251,9 @@ impl Body {
 pub(super) struct SuperBlk {
     pub head: Head,
     pub body: Body,
+
+    // volume key derived from user password
+    vkey: Key,
 }
 
 impl SuperBlk {
@@ -91,12 +261,24 @@ impl SuperBlk {
     const MAGIC: [u8; 4] = [233, 239, 241, 251];
 
     // save super blocks
-    pub fn save(&mut self, pwd: &str, storage: &mut Storage) -> Result<()> {
-        let crypto = Crypto::new(self.head.cost, self.head.cipher)?;
+    pub fn save(
+        &mut self,
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<()> {
+        // derive volume key from user specified plaintext password and key
+        // file
+        self.vkey = self.head.derive_key(pwd, key_file)?;
+
+        self.resave(storage)
+    }
 
-        // hash user specified plaintext password
-        let pwd_hash = crypto.hash_pwd(pwd, &self.head.salt)?;
-        let vkey = &pwd_hash.value;
+    // save super blocks using the volume key derived when it was loaded or
+    // saved last time
+    pub fn resave(&mut self, storage: &mut Storage) -> Result<()> {
+        let crypto = Crypto::new(self.head.cost, self.head.cipher)?;
+        let vkey = &self.vkey;
 
         // serialize head and body
         let head_buf = self.head.seri();
@@ -130,24 +312,42 @@ impl SuperBlk {
     }
 
     // load a specific super block arm
-    fn load_arm(suffix: u64, pwd: &str, storage: &mut Storage) -> Result<Self> {
+    fn load_arm(
+        suffix: u64,
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<Self> {
         // read raw bytes
         let buf = storage.get_super_block(suffix)?;
 
         // read header
         let head = Head::deseri(&buf)?;
 
-        // create crypto
-        let crypto = Crypto::new(head.cost, head.cipher)?;
-
         // derive volume key and use it to decrypt body
-        let pwd_hash = crypto.hash_pwd(pwd, &head.salt)?;
-        let vkey = &pwd_hash.value;
+        let vkey = head.derive_key(pwd, key_file)?;
+        Self::decrypt_arm(&buf, head, vkey)
+    }
+
+    // load a specific super block arm using a derived volume key
+    fn load_arm_with_key(
+        suffix: u64,
+        vkey: &Key,
+        storage: &mut Storage,
+    ) -> Result<Self> {
+        let buf = storage.get_super_block(suffix)?;
+        let head = Head::deseri(&buf)?;
+        Self::decrypt_arm(&buf, head, vkey.clone())
+    }
+
+    // decrypt super block arm body using the volume key
+    fn decrypt_arm(buf: &[u8], head: Head, vkey: Key) -> Result<Self> {
+        let crypto = Crypto::new(head.cost, head.cipher)?;
 
         // read encryped body
         let comp_buf = crypto.decrypt_with_ad(
             &buf[Head::BYTES_LEN..],
-            vkey,
+            &vkey,
             &Self::MAGIC,
         )?;
         let mut buf: [u8; 8] = Default::default();
@@ -155,14 +355,11 @@ impl SuperBlk {
         let body_buf_len = u64::from_le_bytes(buf) as usize;
         let body = Body::deseri(&comp_buf[8..8 + body_buf_len])?;
 
-        Ok(SuperBlk { head, body })
+        Ok(SuperBlk { head, body, vkey })
     }
 
-    // load super block from both left and right arm
-    pub fn load(pwd: &str, storage: &mut Storage) -> Result<Self> {
-        let left = Self::load_arm(0, pwd, storage)?;
-        let right = Self::load_arm(1, pwd, storage)?;
-
+    // check both arms are loaded and in sync
+    fn check_arms(left: Self, right: Self) -> Result<Self> {
         if left.body.seq == right.body.seq {
             Ok(left)
         } else {
@@ -170,10 +367,65 @@ impl SuperBlk {
         }
     }
 
+    // load super block from both left and right arm
+    pub fn load(
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<Self> {
+        let left = Self::load_arm(0, pwd, key_file, storage)?;
+        let right = Self::load_arm(1, pwd, key_file, storage)?;
+        Self::check_arms(left, right)
+    }
+
+    // load super block from both arms using a derived volume key
+    pub fn load_with_key(vkey: &Key, storage: &mut Storage) -> Result<Self> {
+        let left = Self::load_arm_with_key(0, vkey, storage)?;
+        let right = Self::load_arm_with_key(1, vkey, storage)?;
+        Self::check_arms(left, right)
+    }
+
+    // derive volume key from password and key file, using the salt, cost
+    // and key factors saved in super block head
+    pub fn derive_key(
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<Key> {
+        let buf = storage
+            .get_super_block(0)
+            .or_else(|_| storage.get_super_block(1))?;
+        let head = Head::deseri(&buf)?;
+        head.derive_key(pwd, key_file)
+    }
+
+    // verify password by decrypting super block arms, return false if the
+    // password is wrong
+    pub fn verify(
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<bool> {
+        let vkey = Self::derive_key(pwd, key_file, storage)?;
+        let mut result = Err(Error::InvalidSuperBlk);
+        for suffix in 0..2 {
+            match Self::load_arm_with_key(suffix, &vkey, storage) {
+                Ok(_) => return Ok(true),
+                Err(Error::Decrypt) => result = Ok(false),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
     // try to repair super block using at least one valid
-    pub fn repair(pwd: &str, storage: &mut Storage) -> Result<()> {
-        let left_arm = Self::load_arm(0, pwd, storage);
-        let right_arm = Self::load_arm(1, pwd, storage);
+    pub fn repair(
+        pwd: &str,
+        key_file: Option<&Key>,
+        storage: &mut Storage,
+    ) -> Result<()> {
+        let left_arm = Self::load_arm(0, pwd, key_file, storage);
+        let right_arm = Self::load_arm(1, pwd, key_file, storage);
 
         match left_arm {
             Ok(mut left) => match right_arm {
@@ -184,19 +436,21 @@ impl SuperBlk {
                         return Err(Error::InvalidSuperBlk);
                     }
                     match left.body.seq.cmp(&right.body.seq) {
-                        Ordering::Greater => left.save(pwd, storage)?,
-                        Ordering::Less => right.save(pwd, storage)?,
+                        Ordering::Greater => {
+                            left.save(pwd, key_file, storage)?
+                        }
+                        Ordering::Less => right.save(pwd, key_file, storage)?,
                         Ordering::Equal => {
                             debug!("super block all good, no need repair");
                             return Ok(());
                         }
                     }
                 }
-                Err(_) => left.save(pwd, storage)?,
+                Err(_) => left.save(pwd, key_file, storage)?,
             },
             Err(err) => {
                 if let Ok(mut right) = right_arm {
-                    right.save(pwd, storage)?;
+                    right.save(pwd, key_file, storage)?;
                 } else {
                     return Err(err);
                 }
@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt::{self, Debug};
 use std::io::{Read, Result as IoResult, Write};
 use std::sync::{Arc, RwLock, Weak};
@@ -5,17 +6,26 @@ use std::sync::{Arc, RwLock, Weak};
 use log::debug;
 
 use super::allocator::AllocatorRef;
-use super::storage::{self, Storage, StorageRef};
-use super::super_block::SuperBlk;
-use crate::base::crypto::{Cipher, Cost, Salt};
+use super::armor::Arm;
+use super::storage::{
+    self, CacheHook, Storage, StorageRef, SyncLevel, TransportConfig,
+};
+use super::super_block::{CipherChange, SuperBlk};
+use super::{Compression, MAX_BLK_SIZE, MAX_FRAME_SIZE, MIN_BLK_SIZE};
+use crate::base::crypto::{Cipher, Cost, Crypto, Key, Salt};
 use crate::base::lz4::{
     BlockMode, BlockSize, ContentChecksum, Decoder as Lz4Decoder,
     Encoder as Lz4Encoder, EncoderBuilder as Lz4EncoderBuilder,
 };
+
 use crate::base::{IntoRef, Time, Version};
 use crate::error::{Error, Result};
 use crate::fs::Config;
 use crate::trans::{Eid, Finish};
+#[cfg(feature = "compress-zstd")]
+use zstd::stream::{
+    read::Decoder as ZstdDecoder, write::Encoder as ZstdEncoder,
+};
 
 /// Volume info
 #[derive(Debug, Clone, Default)]
@@ -23,20 +33,34 @@ pub struct Info {
     pub id: Eid,
     pub ver: Version,
     pub uri: String,
-    pub compress: bool,
+    pub compression: Compression,
     pub cost: Cost,
     pub cipher: Cipher,
     pub ctime: Time,
+    pub label: Option<String>,
+    pub blk_size: usize,
+    pub blks_per_frame: usize,
+    pub chunk_min_size: usize,
+    pub chunk_avg_size: usize,
+    pub chunk_max_size: usize,
 }
 
 /// Volume
 #[derive(Debug, Default)]
 pub struct Volume {
     info: Info,
+    super_blk: SuperBlk,
     storage: StorageRef,
+
+    // hash of key file content, used together with password to derive
+    // volume key
+    key_file: Option<Key>,
 }
 
 impl Volume {
+    // maximum length of volume label, in bytes
+    const MAX_LABEL_LEN: usize = 255;
+
     /// Create volume instance
     pub fn new(uri: &str) -> Result<Self> {
         let info = Info {
@@ -44,7 +68,12 @@ impl Volume {
             ..Default::default()
         };
         let storage = Storage::new(uri)?.into_ref();
-        Ok(Volume { info, storage })
+        Ok(Volume {
+            info,
+            super_blk: SuperBlk::default(),
+            storage,
+            key_file: None,
+        })
     }
 
     /// Initialise volume
@@ -54,35 +83,54 @@ impl Volume {
         cfg: &Config,
         payload: &[u8],
     ) -> Result<()> {
+        Self::check_label(&cfg.label)?;
+        Self::check_block_size(cfg.blk_size, cfg.blks_per_frame)?;
+
         let mut storage = self.storage.write().unwrap();
         storage.connect(false)?;
 
         // initialise storage
+        storage.set_block_size(cfg.blk_size, cfg.blks_per_frame)?;
         storage.init(cfg.cost, cfg.cipher)?;
 
         // initialise info
         self.info.id = Eid::new();
         self.info.ver = Version::repo_version();
-        self.info.compress = cfg.compress;
+        self.info.compression = cfg.compression;
         self.info.cost = cfg.cost;
         self.info.cipher = cfg.cipher;
         self.info.ctime = Time::now();
+        self.info.label = cfg.label.clone();
+        self.info.blk_size = cfg.blk_size;
+        self.info.blks_per_frame = cfg.blks_per_frame;
+        self.info.chunk_min_size = cfg.chunk_min_size;
+        self.info.chunk_avg_size = cfg.chunk_avg_size;
+        self.info.chunk_max_size = cfg.chunk_max_size;
 
         // initialise super block
         let mut super_blk = SuperBlk::default();
         super_blk.head.salt = Salt::new();
         super_blk.head.cost = cfg.cost;
         super_blk.head.cipher = cfg.cipher;
+        super_blk.head.set_factors(pwd, self.key_file.as_ref());
         super_blk.body.volume_id = self.info.id.clone();
         super_blk.body.ver = self.info.ver.clone();
         super_blk.body.key = storage.get_key().clone();
         super_blk.body.uri = self.info.uri.clone();
-        super_blk.body.compress = cfg.compress;
+        super_blk.body.compress = cfg.compression.is_enabled();
+        super_blk.body.compression = Some(cfg.compression);
         super_blk.body.ctime = self.info.ctime;
         super_blk.body.payload = payload.to_vec();
+        super_blk.body.label = cfg.label.clone();
+        super_blk.body.blk_size = cfg.blk_size;
+        super_blk.body.blks_per_frame = cfg.blks_per_frame;
+        super_blk.body.chunk_min_size = cfg.chunk_min_size;
+        super_blk.body.chunk_avg_size = cfg.chunk_avg_size;
+        super_blk.body.chunk_max_size = cfg.chunk_max_size;
 
         // save super block
-        super_blk.save(pwd, &mut storage)?;
+        super_blk.save(pwd, self.key_file.as_ref(), &mut storage)?;
+        self.super_blk = super_blk;
 
         debug!("volume initialised");
 
@@ -91,43 +139,110 @@ impl Volume {
 
     /// Open volume, return super block payload and meta payload
     pub fn open(&mut self, pwd: &str, force: bool) -> Result<Vec<u8>> {
+        let super_blk = {
+            let mut storage = self.storage.write().unwrap();
+            storage.connect(force)?;
+            SuperBlk::load(pwd, self.key_file.as_ref(), &mut storage)?
+        };
+        self.open_super_blk(super_blk, force)
+    }
+
+    /// Open volume using a volume key derived by `derive_key`, return super
+    /// block payload
+    pub fn open_with_key(
+        &mut self,
+        vkey: &Key,
+        force: bool,
+    ) -> Result<Vec<u8>> {
+        let super_blk = {
+            let mut storage = self.storage.write().unwrap();
+            storage.connect(force)?;
+            SuperBlk::load_with_key(vkey, &mut storage)?
+        };
+        self.open_super_blk(super_blk, force)
+    }
+
+    /// Derive volume key from password
+    pub fn derive_key(&self, pwd: &str) -> Result<Key> {
         let mut storage = self.storage.write().unwrap();
-        storage.connect(force)?;
+        storage.connect(false)?;
+        SuperBlk::derive_key(pwd, self.key_file.as_ref(), &mut storage)
+    }
 
-        // load super block from storage
-        let super_blk = SuperBlk::load(pwd, &mut storage)?;
+    /// Verify password without opening volume
+    pub fn verify_password(&self, pwd: &str) -> Result<bool> {
+        let mut storage = self.storage.write().unwrap();
+        storage.connect(false)?;
+        SuperBlk::verify(pwd, self.key_file.as_ref(), &mut storage)
+    }
+
+    // open storage using the loaded super block
+    fn open_super_blk(
+        &mut self,
+        super_blk: SuperBlk,
+        force: bool,
+    ) -> Result<Vec<u8>> {
+        let mut storage = self.storage.write().unwrap();
 
         // check volume version
         if !super_blk.body.ver.match_repo_version() {
             return Err(Error::WrongVersion);
         }
 
-        // open storage
+        // compression must be supported in this build
+        super_blk.body.compression().check()?;
+
+        // open storage using the block size it was created with
+        storage.set_block_size(
+            super_blk.body.blk_size,
+            super_blk.body.blks_per_frame,
+        )?;
         storage.open(
             super_blk.head.cost,
-            super_blk.head.cipher,
+            super_blk.body.depot_cipher(&super_blk.head),
+            super_blk.body.depot_prev_cipher(&super_blk.head),
             super_blk.body.key.clone(),
             force,
         )?;
 
+        // entities can be encrypted with a cipher other than depot's after
+        // cipher is changed, or with either of two ciphers if the change is
+        // not finished yet
+        match super_blk.body.cipher_change {
+            Some(change) => storage.set_cipher(change.to, Some(change.from))?,
+            None if super_blk.body.depot_cipher.is_some() => {
+                storage.set_cipher(super_blk.head.cipher, None)?
+            }
+            None => {}
+        }
+
         // set up info
         self.info.id = super_blk.body.volume_id.clone();
-        self.info.ver = super_blk.body.ver;
-        self.info.compress = super_blk.body.compress;
+        self.info.ver = super_blk.body.ver.clone();
+        self.info.compression = super_blk.body.compression();
         self.info.cost = super_blk.head.cost;
         self.info.cipher = super_blk.head.cipher;
         self.info.ctime = super_blk.body.ctime;
+        self.info.label = super_blk.body.label.clone();
+        self.info.blk_size = super_blk.body.blk_size;
+        self.info.blks_per_frame = super_blk.body.blks_per_frame;
+        self.info.chunk_min_size = super_blk.body.chunk_min_size;
+        self.info.chunk_avg_size = super_blk.body.chunk_avg_size;
+        self.info.chunk_max_size = super_blk.body.chunk_max_size;
 
         debug!("volume opened: {}", *storage);
 
-        Ok(super_blk.body.payload)
+        let payload = super_blk.body.payload.clone();
+        self.super_blk = super_blk;
+
+        Ok(payload)
     }
 
     /// Try to repair super block
     pub fn repair_super_block(&mut self, pwd: &str) -> Result<()> {
         let mut storage = self.storage.write().unwrap();
         storage.connect(false)?;
-        SuperBlk::repair(pwd, &mut storage)
+        SuperBlk::repair(pwd, self.key_file.as_ref(), &mut storage)
     }
 
     /// Check specified volume if it exists
@@ -136,6 +251,12 @@ impl Volume {
         storage.exists()
     }
 
+    /// Check volume storage is reachable
+    pub fn ping(&self) -> Result<()> {
+        let storage = self.storage.read().unwrap();
+        storage.ping()
+    }
+
     /// Reset volume password
     pub fn reset_password(
         &mut self,
@@ -146,13 +267,176 @@ impl Volume {
         let mut storage = self.storage.write().unwrap();
 
         // load old super block
-        let mut super_blk = SuperBlk::load(old_pwd, &mut storage)?;
+        let key_file = self.key_file.as_ref();
+        let mut super_blk = SuperBlk::load(old_pwd, key_file, &mut storage)?;
 
-        // save new super block with new password and cost
+        // save new super block with new password and cost, password can be
+        // added to or removed from a repo using key file
         super_blk.head.cost = cost;
-        super_blk.save(new_pwd, &mut storage)?;
+        super_blk.head.set_factors(new_pwd, key_file);
+        super_blk.save(new_pwd, key_file, &mut storage)?;
 
         self.info.cost = cost;
+        self.super_blk = super_blk;
+
+        Ok(())
+    }
+
+    /// Start changing cipher of volume, or resume or roll back an unfinished
+    /// change, return false if the cipher is already in use
+    pub fn begin_cipher_change(
+        &mut self,
+        pwd: &str,
+        cipher: Cipher,
+    ) -> Result<bool> {
+        let mut storage = self.storage.write().unwrap();
+
+        // load super block to verify password
+        let mut super_blk =
+            SuperBlk::load(pwd, self.key_file.as_ref(), &mut storage)?;
+
+        let change = match super_blk.body.cipher_change {
+            None if cipher == super_blk.head.cipher => return Ok(false),
+            None => CipherChange {
+                from: super_blk.head.cipher,
+                to: cipher,
+            },
+            Some(change) if change.to == cipher => change,
+            Some(change) if change.from == cipher => CipherChange {
+                from: change.to,
+                to: cipher,
+            },
+            Some(_) => return Err(Error::InvalidArgument),
+        };
+
+        // the new cipher must be available
+        Crypto::new(super_blk.head.cost, cipher)?;
+
+        // save the change to super block before any entity is re-encrypted
+        if super_blk.body.depot_cipher.is_none() {
+            super_blk.body.depot_cipher = Some(super_blk.head.cipher);
+        }
+        super_blk.body.cipher_change = Some(change);
+        super_blk.resave(&mut storage)?;
+        storage.set_cipher(change.to, Some(change.from))?;
+        self.super_blk = super_blk;
+
+        Ok(true)
+    }
+
+    /// Check if there is an unfinished cipher change
+    #[inline]
+    pub fn has_cipher_change(&self) -> bool {
+        self.super_blk.body.cipher_change.is_some()
+    }
+
+    /// Re-encrypt an entity, including both of its arms, with the new
+    /// cipher
+    pub fn recrypt(&mut self, id: &Eid) -> Result<()> {
+        let (left_arm_id, right_arm_id) = Arm::both_eid(id);
+        let mut storage = self.storage.write().unwrap();
+        storage.recrypt(id)?;
+        storage.recrypt(&left_arm_id)?;
+        storage.recrypt(&right_arm_id)
+    }
+
+    /// Write new addresses of re-encrypted entities
+    #[inline]
+    pub fn commit_recrypt(&mut self) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.commit_recrypt()
+    }
+
+    /// Re-encrypt both arms of a wal with the new cipher
+    pub fn recrypt_wal(&mut self, id: &Eid) -> Result<()> {
+        let (left_arm_id, right_arm_id) = Arm::both_eid(id);
+        let mut storage = self.storage.write().unwrap();
+        storage.recrypt_wal(&left_arm_id)?;
+        storage.recrypt_wal(&right_arm_id)
+    }
+
+    /// Finish cipher change after all entities are re-encrypted, depot's
+    /// metadata is re-encrypted as well if depot supports it
+    pub fn finish_cipher_change(&mut self) -> Result<()> {
+        let change = match self.super_blk.body.cipher_change {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        let mut storage = self.storage.write().unwrap();
+
+        // depot keeps the cipher it was opened with if it cannot be
+        // re-encrypted, its metadata can be encrypted with either cipher if
+        // the change is resumed or rolled back after being interrupted
+        let depot_cipher = self.super_blk.body.depot_cipher;
+        let prev_cipher =
+            self.super_blk.body.depot_cipher(&self.super_blk.head);
+        let prev_cipher = if prev_cipher == change.to {
+            change.from
+        } else {
+            prev_cipher
+        };
+        if storage.recrypt_depot(change.to, prev_cipher)?
+            || depot_cipher == Some(change.to)
+        {
+            self.super_blk.body.depot_cipher = None;
+        }
+
+        // super block is updated last, the change is not finished until it
+        // is saved successfully
+        let cipher = self.super_blk.head.cipher;
+        self.super_blk.head.cipher = change.to;
+        self.super_blk.body.cipher_change = None;
+        if let Err(err) = self.super_blk.resave(&mut storage) {
+            self.super_blk.head.cipher = cipher;
+            self.super_blk.body.depot_cipher = depot_cipher;
+            self.super_blk.body.cipher_change = Some(change);
+            return Err(err);
+        }
+        storage.set_cipher(change.to, None)?;
+        self.info.cipher = change.to;
+
+        Ok(())
+    }
+
+    // check if volume label is valid
+    fn check_label(label: &Option<String>) -> Result<()> {
+        match label {
+            Some(label) if label.len() > Self::MAX_LABEL_LEN => {
+                Err(Error::InvalidArgument)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // check if block size and number of blocks per frame are valid, block
+    // size must be a power of two
+    fn check_block_size(blk_size: usize, blks_per_frame: usize) -> Result<()> {
+        if !blk_size.is_power_of_two()
+            || !(MIN_BLK_SIZE..=MAX_BLK_SIZE).contains(&blk_size)
+            || blks_per_frame == 0
+            || !matches!(
+                blk_size.checked_mul(blks_per_frame),
+                Some(frame_size) if frame_size <= MAX_FRAME_SIZE
+            )
+        {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    /// Set volume label and save it to super block
+    pub fn set_label(&mut self, label: Option<String>) -> Result<()> {
+        Self::check_label(&label)?;
+
+        let mut storage = self.storage.write().unwrap();
+        let old = self.super_blk.body.label.clone();
+        self.super_blk.body.label = label.clone();
+        if let Err(err) = self.super_blk.resave(&mut storage) {
+            self.super_blk.body.label = old;
+            return Err(err);
+        }
+
+        self.info.label = label;
 
         Ok(())
     }
@@ -163,6 +447,103 @@ impl Volume {
         self.info.clone()
     }
 
+    // get block size, in bytes
+    #[inline]
+    pub fn blk_size(&self) -> usize {
+        self.info.blk_size
+    }
+
+    // get frame size, in bytes
+    #[inline]
+    pub fn frame_size(&self) -> usize {
+        self.info.blk_size * self.info.blks_per_frame
+    }
+
+    // set hash of key file content used to derive volume key
+    #[inline]
+    pub fn set_key_file(&mut self, key_file: Option<Key>) {
+        self.key_file = key_file;
+    }
+
+    // set cache event hook for storage
+    #[inline]
+    pub fn set_transport_config(&self, cfg: &TransportConfig) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_transport_config(cfg)
+    }
+
+    // set write durability level for storage
+    #[inline]
+    pub fn set_sync_level(&self, level: SyncLevel) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_sync_level(level);
+    }
+
+    #[inline]
+    pub fn set_cache_hook(&self, hook: CacheHook) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_cache_hook(hook);
+    }
+
+    // get memory used by storage frame cache, address cache and depot's
+    // caches, in bytes
+    #[inline]
+    pub fn cache_mem_used(&self) -> (usize, usize, usize) {
+        let storage = self.storage.read().unwrap();
+        storage.cache_mem_used()
+    }
+
+    // set memory capacity of storage frame cache, address cache and
+    // depot's caches, in bytes
+    #[inline]
+    pub fn set_cache_mem(&self, frame: usize, addr: usize, depot: usize) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_cache_mem(frame, addr, depot);
+    }
+
+    // set storage frame cache capacity, in bytes
+    #[inline]
+    pub fn set_frame_cache_capacity(&self, capacity: usize) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_frame_cache_capacity(capacity);
+    }
+
+    // set storage address cache capacity, in number of single span
+    // addresses
+    #[inline]
+    pub fn set_addr_cache_capacity(&self, capacity: usize) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_addr_cache_capacity(capacity);
+    }
+
+    // set number of frames storage reads ahead
+    #[inline]
+    pub fn set_read_ahead(&self, frames: usize) {
+        let mut storage = self.storage.write().unwrap();
+        storage.set_read_ahead(frames);
+    }
+
+    // get storage frame and address cache hit and miss counts
+    #[inline]
+    pub fn cache_stats(&self) -> ((usize, usize), (usize, usize)) {
+        let storage = self.storage.read().unwrap();
+        storage.cache_stats()
+    }
+
+    // get storage local cache usage
+    #[inline]
+    pub fn local_cache_usage(&self) -> Result<(usize, usize, usize)> {
+        let storage = self.storage.read().unwrap();
+        storage.local_cache_usage()
+    }
+
+    // get used and maximum bytes of storage if it has a size cap
+    #[inline]
+    pub fn space_limit(&self) -> Option<(usize, usize)> {
+        let storage = self.storage.read().unwrap();
+        storage.space_limit()
+    }
+
     // get allocator from storage
     #[inline]
     pub fn get_allocator(&self) -> AllocatorRef {
@@ -170,6 +551,14 @@ impl Volume {
         storage.get_allocator()
     }
 
+    // get total length of blocks allocated in storage, in bytes
+    #[inline]
+    pub fn allocated_len(&self) -> usize {
+        let allocator_ref = self.get_allocator();
+        let allocator = allocator_ref.read().unwrap();
+        allocator.block_wmark() * self.info.blk_size
+    }
+
     // delete a wal
     #[inline]
     pub fn del_wal(&mut self, id: &Eid) -> Result<()> {
@@ -190,12 +579,30 @@ impl Volume {
         storage.flush()
     }
 
+    #[inline]
+    pub fn has_pending_writes(&self) -> bool {
+        let storage = self.storage.read().unwrap();
+        storage.is_dirty()
+    }
+
     // permanently destroy a volume
     #[inline]
     pub fn destroy(&mut self) -> Result<()> {
         let mut storage = self.storage.write().unwrap();
         storage.destroy()
     }
+
+    /// Take a snapshot of the whole volume storage
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let storage = self.storage.read().unwrap();
+        storage.snapshot()
+    }
+
+    /// Restore volume storage from a snapshot
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut storage = self.storage.write().unwrap();
+        storage.restore(snapshot)
+    }
 }
 
 impl IntoRef for Volume {}
@@ -232,18 +639,71 @@ pub struct Reader {
 }
 
 impl Reader {
+    #[inline]
     pub fn new(id: &Eid, vol: &VolumeRef) -> Result<Self> {
+        Self::new_with_ad(id, vol, &[])
+    }
+
+    // create a reader which decrypts data with associated data
+    #[inline]
+    pub fn new_with_ad(id: &Eid, vol: &VolumeRef, ad: &[u8]) -> Result<Self> {
+        Self::new_with_compress(id, vol, ad, None)
+    }
+
+    // create a reader which decrypts data with associated data, and
+    // decompresses data if compress is set or falls back to volume's
+    // compression option
+    pub fn new_with_compress(
+        id: &Eid,
+        vol: &VolumeRef,
+        ad: &[u8],
+        compress: Option<bool>,
+    ) -> Result<Self> {
         let vol = vol.read().unwrap();
-        let rdr = storage::Reader::new(id, &vol.storage)?;
-        if vol.info.compress {
-            Ok(Reader {
-                inner: Box::new(Lz4Decoder::new(rdr).unwrap()),
-            })
-        } else {
-            Ok(Reader {
-                inner: Box::new(rdr),
-            })
+        let rdr = storage::Reader::new(id, &vol.storage, ad)?;
+        let inner: Box<dyn Read> = match vol.info.compression.resolve(compress)
+        {
+            Compression::None => Box::new(rdr),
+            Compression::Lz4 => Box::new(Lz4Decoder::new(rdr).unwrap()),
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd(_) => Box::new(ZstdDecoder::new(rdr)?),
+            #[cfg(not(feature = "compress-zstd"))]
+            comp @ Compression::Zstd(_) => {
+                return Err(Error::UnsupportedCompression(comp.to_string()));
+            }
+        };
+        Ok(Reader { inner })
+    }
+
+    // create a reader which starts reading at `offset`, only frames from
+    // the offset are read, return None if data is compressed as compressed
+    // stream cannot be read from the middle
+    pub fn new_at(
+        id: &Eid,
+        vol: &VolumeRef,
+        ad: &[u8],
+        compress: Option<bool>,
+        offset: usize,
+    ) -> Result<Option<Self>> {
+        let vol = vol.read().unwrap();
+        if vol.info.compression.resolve(compress) != Compression::None {
+            return Ok(None);
         }
+        let mut rdr = storage::Reader::new(id, &vol.storage, ad)?;
+        rdr.skip_to(offset);
+        Ok(Some(Reader {
+            inner: Box::new(rdr),
+        }))
+    }
+
+    // create a reader which reads data as it is stored, without
+    // decompression
+    pub fn new_raw(id: &Eid, vol: &VolumeRef, ad: &[u8]) -> Result<Self> {
+        let vol = vol.read().unwrap();
+        let rdr = storage::Reader::new(id, &vol.storage, ad)?;
+        Ok(Reader {
+            inner: Box::new(rdr),
+        })
     }
 }
 
@@ -297,60 +757,205 @@ impl Finish for WalWriter {
 
 // volume inner writer wrapper
 enum InnerWriter {
-    Compress(Lz4Encoder<storage::Writer>),
+    Lz4(Lz4Encoder<storage::Writer>),
+    #[cfg(feature = "compress-zstd")]
+    Zstd(ZstdEncoder<'static, storage::Writer>),
     NoCompress(storage::Writer),
 }
 
 /// Volume writer
 pub struct Writer {
     inner: InnerWriter,
+
+    // length of data written to this writer
+    written: usize,
+
+    // marks of data written and its compressed length, taken when encoder
+    // is flushed, compressed data before a mark can be decompressed to the
+    // data written before that mark
+    marks: VecDeque<(usize, usize)>,
 }
 
 impl Writer {
+    #[inline]
     pub fn new(id: &Eid, vol: &VolumeWeakRef) -> Result<Self> {
+        Self::new_with_ad(id, vol, &[])
+    }
+
+    // create a writer which encrypts data with associated data
+    #[inline]
+    pub fn new_with_ad(
+        id: &Eid,
+        vol: &VolumeWeakRef,
+        ad: &[u8],
+    ) -> Result<Self> {
+        Self::new_with_compress(id, vol, ad, None)
+    }
+
+    // create a writer which encrypts data with associated data, and
+    // compresses data if compress is set or falls back to volume's
+    // compression option
+    pub fn new_with_compress(
+        id: &Eid,
+        vol: &VolumeWeakRef,
+        ad: &[u8],
+        compress: Option<bool>,
+    ) -> Result<Self> {
         let vol = vol.upgrade().ok_or(Error::RepoClosed)?;
         let vol = vol.read().unwrap();
-        let wtr = storage::Writer::new(id, &Arc::downgrade(&vol.storage))?;
-        let inner = if vol.info.compress {
-            let comp = Lz4EncoderBuilder::new()
-                .block_size(BlockSize::Default)
-                .block_mode(BlockMode::Linked)
-                .checksum(ContentChecksum::NoChecksum)
-                .level(0)
-                .auto_flush(true)
-                .build(wtr)?;
-            InnerWriter::Compress(comp)
-        } else {
-            InnerWriter::NoCompress(wtr)
+        let wtr = storage::Writer::new(id, &Arc::downgrade(&vol.storage), ad)?;
+        let inner = match vol.info.compression.resolve(compress) {
+            Compression::None => InnerWriter::NoCompress(wtr),
+            Compression::Lz4 => {
+                let comp = Lz4EncoderBuilder::new()
+                    .block_size(BlockSize::Default)
+                    .block_mode(BlockMode::Linked)
+                    .checksum(ContentChecksum::NoChecksum)
+                    .level(0)
+                    .auto_flush(true)
+                    .build(wtr)?;
+                InnerWriter::Lz4(comp)
+            }
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd(level) => {
+                InnerWriter::Zstd(ZstdEncoder::new(wtr, level)?)
+            }
+            #[cfg(not(feature = "compress-zstd"))]
+            comp @ Compression::Zstd(_) => {
+                return Err(Error::UnsupportedCompression(comp.to_string()));
+            }
+        };
+        Ok(Writer::from_inner(inner))
+    }
+
+    // create a writer which writes data as it is, without compression
+    pub fn new_raw(id: &Eid, vol: &VolumeWeakRef, ad: &[u8]) -> Result<Self> {
+        let vol = vol.upgrade().ok_or(Error::RepoClosed)?;
+        let vol = vol.read().unwrap();
+        let wtr = storage::Writer::new(id, &Arc::downgrade(&vol.storage), ad)?;
+        Ok(Writer::from_inner(InnerWriter::NoCompress(wtr)))
+    }
+
+    #[inline]
+    fn from_inner(inner: InnerWriter) -> Self {
+        Writer {
+            inner,
+            written: 0,
+            marks: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    fn storage_writer(&self) -> &storage::Writer {
+        match self.inner {
+            InnerWriter::Lz4(ref inner) => inner.get_ref(),
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(ref inner) => inner.get_ref(),
+            InnerWriter::NoCompress(ref inner) => inner,
+        }
+    }
+
+    // use minimal stage buffer in storage writer, must be set before writing
+    pub fn set_low_memory(&mut self) {
+        match self.inner {
+            InnerWriter::Lz4(ref mut inner) => inner.get_mut().set_low_memory(),
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(ref mut inner) => {
+                inner.get_mut().set_low_memory()
+            }
+            InnerWriter::NoCompress(ref mut inner) => inner.set_low_memory(),
+        }
+    }
+
+    // size of stage buffer allocated in storage writer, buffers used by
+    // compression encoder are not included
+    #[inline]
+    pub fn buf_len(&self) -> usize {
+        self.storage_writer().buf_len()
+    }
+
+    // take a mark after encoder is flushed, marks which are written to
+    // depot are dropped except the last one
+    fn mark(&mut self) {
+        if let InnerWriter::NoCompress(_) = self.inner {
+            return;
+        }
+        let (received, committed) = {
+            let wtr = self.storage_writer();
+            (wtr.received_len(), wtr.committed_len())
         };
-        Ok(Writer { inner })
+        self.marks.push_back((self.written, received));
+        while self.marks.len() > 1 && self.marks[1].1 <= committed {
+            self.marks.pop_front();
+        }
+    }
+
+    // length of data written to depot in frames, compressed data can only
+    // be counted up to the last flush written to depot
+    pub fn committed_len(&self) -> usize {
+        let wtr = self.storage_writer();
+        match self.inner {
+            InnerWriter::NoCompress(_) => wtr.committed_len(),
+            _ => self
+                .marks
+                .iter()
+                .take_while(|mark| mark.1 <= wtr.committed_len())
+                .last()
+                .map_or(0, |mark| mark.0),
+        }
+    }
+
+    // make written data durable in storage without finishing the writer
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.flush()?;
+        match self.inner {
+            InnerWriter::Lz4(ref mut inner) => inner.get_mut().sync_data(),
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(ref mut inner) => inner.get_mut().sync_data(),
+            InnerWriter::NoCompress(ref mut inner) => inner.sync_data(),
+        }
     }
 }
 
 impl Write for Writer {
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-        match self.inner {
-            InnerWriter::Compress(ref mut inner) => inner.write(buf),
-            InnerWriter::NoCompress(ref mut inner) => inner.write(buf),
+        let written = match self.inner {
+            InnerWriter::Lz4(ref mut inner) => inner.write(buf)?,
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(ref mut inner) => inner.write(buf)?,
+            InnerWriter::NoCompress(ref mut inner) => inner.write(buf)?,
+        };
+        self.written += written;
+
+        // lz4 encoder is flushed after each write
+        if let InnerWriter::Lz4(_) = self.inner {
+            self.mark();
         }
+        Ok(written)
     }
 
     fn flush(&mut self) -> IoResult<()> {
         match self.inner {
-            InnerWriter::Compress(ref mut inner) => inner.flush(),
-            InnerWriter::NoCompress(ref mut inner) => inner.flush(),
+            InnerWriter::Lz4(ref mut inner) => inner.flush()?,
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(ref mut inner) => inner.flush()?,
+            InnerWriter::NoCompress(ref mut inner) => inner.flush()?,
         }
+        self.mark();
+        Ok(())
     }
 }
 
 impl Finish for Writer {
     fn finish(self) -> Result<()> {
         match self.inner {
-            InnerWriter::Compress(inner) => {
+            InnerWriter::Lz4(inner) => {
                 let (wtr, result) = inner.finish();
                 result.map_err(Error::from)?;
                 wtr.finish()
             }
+            #[cfg(feature = "compress-zstd")]
+            InnerWriter::Zstd(inner) => inner.finish()?.finish(),
             InnerWriter::NoCompress(inner) => inner.finish(),
         }
     }
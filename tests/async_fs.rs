@@ -0,0 +1,183 @@
+#![cfg(feature = "async")]
+
+extern crate tokio;
+extern crate zbox;
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::Poll;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf};
+use zbox::{init_env, AsyncFile, AsyncRepo, Error, OpenOptions, RepoOpener};
+
+#[tokio::test]
+async fn async_repo() {
+    init_env();
+
+    let repo = RepoOpener::new()
+        .create(true)
+        .open_async("mem://async_repo", "pwd")
+        .await
+        .unwrap();
+    assert!(!repo.info().await.unwrap().is_read_only());
+
+    // write file and read it back
+    repo.create_dir_all("/dir/sub").await.unwrap();
+    let mut file = repo.create_file("/dir/file").await.unwrap();
+    file.write_all(b"foo bar").await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(file.metadata().await.unwrap().content_len(), 7);
+    assert_eq!(file.history().await.unwrap().len(), 1);
+
+    let mut file = repo.open_file("/dir/file").await.unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(&buf[..], b"foo bar");
+
+    // large file which needs multiple blocking reads and writes
+    {
+        let data = vec![42u8; 5 * 1024 * 1024 + 42];
+        let mut file = repo.create_file("/dir/large").await.unwrap();
+        file.write_all(&data).await.unwrap();
+        file.finish().await.unwrap();
+        let mut file = repo.open_file("/dir/large").await.unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+    }
+
+    // open file with options
+    let mut opts = OpenOptions::new();
+    opts.write(true).append(true);
+    let mut file = repo.open_file_with(opts, "/dir/file").await.unwrap();
+    file.write_all(b" baz").await.unwrap();
+    file.shutdown().await.unwrap();
+    let mut file = repo.open_file("/dir/file").await.unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "foo bar baz");
+
+    // directory operations
+    assert!(repo.is_dir("/dir/sub").await.unwrap());
+    assert!(repo.is_file("/dir/file").await.unwrap());
+    assert_eq!(repo.read_dir("/dir").await.unwrap().len(), 3);
+    repo.copy("/dir/file", "/dir/sub/copied").await.unwrap();
+    repo.rename("/dir/sub/copied", "/dir/sub/renamed")
+        .await
+        .unwrap();
+    assert!(repo.path_exists("/dir/sub/renamed").await.unwrap());
+    assert_eq!(
+        repo.metadata("/dir/sub/renamed")
+            .await
+            .unwrap()
+            .content_len(),
+        11
+    );
+    let history = repo.history("/dir/file").await.unwrap();
+    assert_eq!(history.last().unwrap().num(), 3);
+    repo.remove_file("/dir/large").await.unwrap();
+    assert_eq!(
+        repo.remove_dir("/dir/sub").await.unwrap_err(),
+        Error::NotEmpty
+    );
+    repo.remove_dir_all("/dir/sub").await.unwrap();
+    repo.create_dir("/dir/sub").await.unwrap();
+    repo.remove_dir("/dir/sub").await.unwrap();
+    assert!(!repo.path_exists("/dir/sub").await.unwrap());
+    assert_eq!(
+        repo.open_file("/non-exist").await.unwrap_err(),
+        Error::NotFound
+    );
+    repo.flush().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn async_repo_mt() {
+    init_env();
+
+    // convert from an opened repo and file
+    let mut sync_repo = RepoOpener::new()
+        .create(true)
+        .open("mem://async_repo_mt", "pwd")
+        .unwrap();
+    let mut file = sync_repo.create_file("/file").unwrap();
+    file.write_once(b"foo").unwrap();
+    let mut file = AsyncFile::from(sync_repo.open_file("/file").unwrap());
+    let mut content = String::new();
+    file.read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "foo");
+    let repo = AsyncRepo::from(sync_repo);
+
+    // run file operations from multiple tasks concurrently
+    let mut workers = Vec::new();
+    for i in 0..8 {
+        let repo = repo.clone();
+        workers.push(tokio::spawn(async move {
+            let path = format!("/file{}", i);
+            let mut file = repo.create_file(&path).await.unwrap();
+            file.write_all(path.as_bytes()).await.unwrap();
+            file.flush().await.unwrap();
+            file.shutdown().await.unwrap();
+        }));
+    }
+    for worker in workers {
+        worker.await.unwrap();
+    }
+
+    for i in 0..8 {
+        let path = format!("/file{}", i);
+        let mut file = repo.open_file(&path).await.unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, path);
+    }
+    assert_eq!(repo.read_dir("/").await.unwrap().len(), 9);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_file_abandoned_read() {
+    init_env();
+
+    let repo = RepoOpener::new()
+        .create(true)
+        .open_async("mem://async_file_abandoned_read", "pwd")
+        .await
+        .unwrap();
+    let data: Vec<u8> = (0..64u8).collect();
+    let mut file = repo.create_file("/file").await.unwrap();
+    file.write_all(&data).await.unwrap();
+    file.shutdown().await.unwrap();
+
+    // start a large read and abandon it after the first poll
+    let mut file = repo.open_file("/file").await.unwrap();
+    let mut large = [0u8; 64];
+    poll_fn(|cx| {
+        let mut buf = ReadBuf::new(&mut large);
+        let _ = Pin::new(&mut file).poll_read(cx, &mut buf);
+        Poll::Ready(())
+    })
+    .await;
+
+    // following reads with smaller buffer must not lose any data
+    let mut small = [0u8; 10];
+    let read = file.read(&mut small).await.unwrap();
+    assert_eq!(&small[..read], &data[..read]);
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(&rest[..], &data[read..]);
+
+    // write after partial read starts from where it is read to
+    let mut opts = OpenOptions::new();
+    opts.read(true).write(true);
+    let mut file = repo.open_file_with(opts, "/file").await.unwrap();
+    let read = file.read(&mut small).await.unwrap();
+    assert_eq!(read, 10);
+    file.write_all(b"xyz").await.unwrap();
+    file.shutdown().await.unwrap();
+    let mut file = repo.open_file("/file").await.unwrap();
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).await.unwrap();
+    assert_eq!(&content[..10], &data[..10]);
+    assert_eq!(&content[10..13], b"xyz");
+    assert_eq!(&content[13..], &data[13..]);
+}
@@ -5,10 +5,12 @@ extern crate zbox;
 
 mod common;
 
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::{thread, time};
 
-use zbox::Error;
+use zbox::{init_env, CopyOptions, Error, OpenOptions, Repo, RepoOpener};
 
 #[test]
 fn dir_create_st() {
@@ -89,6 +91,225 @@ fn dir_read() {
     assert!(repo.read_dir("non-exists").is_err());
 }
 
+#[test]
+fn dir_walk() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/w/a/a1").unwrap();
+    repo.create_dir("/w/b").unwrap();
+    write_file(repo, "/w/a/a1/f", b"foo");
+    write_file(repo, "/w/a/f", b"bar");
+    write_file(repo, "/w/f", b"baz");
+    repo.symlink("a", "/w/link").unwrap();
+
+    let walk = |repo: &Repo, root: &str, depth: usize, post: bool| {
+        let mut walk = repo.walk(root).unwrap();
+        walk.max_depth(depth).post_order(post);
+        walk.map(|ent| ent.unwrap().0).collect::<Vec<_>>()
+    };
+    let pos = |paths: &[PathBuf], path: &str| {
+        paths
+            .iter()
+            .position(|p| p == &PathBuf::from(path))
+            .unwrap()
+    };
+
+    // pre-order, parent comes before its children
+    let paths = walk(repo, "/w", usize::MAX, false);
+    assert_eq!(paths.len(), 8);
+    assert_eq!(paths[0], PathBuf::from("/w"));
+    assert!(pos(&paths, "/w/a") < pos(&paths, "/w/a/a1"));
+    assert!(pos(&paths, "/w/a/a1") < pos(&paths, "/w/a/a1/f"));
+
+    // post-order, parent comes after its children
+    let paths = walk(repo, "/w", usize::MAX, true);
+    assert_eq!(paths.len(), 8);
+    assert_eq!(paths[7], PathBuf::from("/w"));
+    assert!(pos(&paths, "/w/a") > pos(&paths, "/w/a/a1"));
+    assert!(pos(&paths, "/w/a/a1") > pos(&paths, "/w/a/a1/f"));
+
+    // symlink is not followed
+    let (_, md) = repo
+        .walk("/w")
+        .unwrap()
+        .map(|ent| ent.unwrap())
+        .find(|(path, _)| path == &PathBuf::from("/w/link"))
+        .unwrap();
+    assert!(md.is_symlink());
+
+    // max depth
+    assert_eq!(walk(repo, "/w", 0, false), vec![PathBuf::from("/w")]);
+    assert_eq!(walk(repo, "/w", 1, false).len(), 5);
+    assert_eq!(walk(repo, "/w", 2, true).len(), 7);
+
+    // walk a file
+    assert_eq!(walk(repo, "/w/f", 3, false), vec![PathBuf::from("/w/f")]);
+    assert_eq!(repo.walk("/non-exists").unwrap_err(), Error::NotFound);
+
+    // remove the whole tree in post-order
+    let mut walk = repo.walk("/w").unwrap();
+    walk.post_order(true).strict(true);
+    for ent in walk {
+        let (path, md) = ent.unwrap();
+        if md.is_dir() {
+            repo.remove_dir(&path).unwrap();
+        } else {
+            repo.remove_file(&path).unwrap();
+        }
+    }
+    assert!(!repo.path_exists("/w").unwrap());
+}
+
+#[test]
+fn dir_glob() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/data/2021/01").unwrap();
+    repo.create_dir_all("/logs").unwrap();
+    write_file(repo, "/data/a.json", b"{}");
+    write_file(repo, "/data/2021/b.json", b"{}");
+    write_file(repo, "/data/2021/01/c.json", b"{}");
+    write_file(repo, "/data/2021/01/c.txt", b"c");
+    write_file(repo, "/logs/x1.log", b"x");
+    write_file(repo, "/logs/x2.log", b"x");
+    write_file(repo, "/logs/y1.log", b"y");
+    write_file(repo, "/top.log", b"t");
+    repo.symlink("/data", "/logs/link").unwrap();
+
+    let glob = |repo: &Repo, pattern: &str| -> Vec<String> {
+        repo.glob(pattern)
+            .unwrap()
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(glob(repo, "*.log"), vec!["/top.log"]);
+    assert_eq!(
+        glob(repo, "/logs/x?.log"),
+        vec!["/logs/x1.log", "/logs/x2.log"]
+    );
+    assert_eq!(glob(repo, "logs/[!x]*"), vec!["/logs/link", "/logs/y1.log"]);
+    assert_eq!(
+        glob(repo, "data/**/*.json"),
+        vec!["/data/2021/01/c.json", "/data/2021/b.json", "/data/a.json"]
+    );
+    assert_eq!(
+        glob(repo, "**/c.*"),
+        vec!["/data/2021/01/c.json", "/data/2021/01/c.txt"]
+    );
+    assert_eq!(glob(repo, "/data/*"), vec!["/data/2021", "/data/a.json"]);
+
+    // symlink is not followed
+    assert!(glob(repo, "logs/link/*").is_empty());
+    assert!(glob(repo, "nothing/**").is_empty());
+
+    assert_eq!(repo.glob("").unwrap_err(), Error::InvalidArgument);
+    assert_eq!(repo.glob("logs/[x").unwrap_err(), Error::InvalidArgument);
+}
+
+#[test]
+fn dir_read_iter() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir("/dir").unwrap();
+    for i in 0..20 {
+        write_file(repo, &format!("/dir/file{:02}", i), b"foo");
+    }
+
+    // iterator gives same entries as read_dir
+    let ents: Vec<_> = repo
+        .read_dir_iter("/dir")
+        .unwrap()
+        .map(|ent| ent.unwrap().path().to_path_buf())
+        .collect();
+    let expected: Vec<_> = repo
+        .read_dir("/dir")
+        .unwrap()
+        .iter()
+        .map(|ent| ent.path().to_path_buf())
+        .collect();
+    assert_eq!(ents, expected);
+
+    // stop early
+    let mut iter = repo.read_dir_iter("/dir").unwrap();
+    assert_eq!(iter.size_hint(), (0, Some(20)));
+    let ent = iter
+        .find(|ent| ent.as_ref().unwrap().file_name() == "file05")
+        .unwrap()
+        .unwrap();
+    assert!(ent.metadata().is_file());
+
+    // removed entries are skipped
+    let mut iter = repo.read_dir_iter("/dir").unwrap();
+    let first = iter.next().unwrap().unwrap();
+    for ent in expected.iter() {
+        if ent != first.path() {
+            repo.remove_file(ent).unwrap();
+        }
+    }
+    assert!(iter.next().is_none());
+
+    assert_eq!(
+        repo.read_dir_iter("/non-exists").unwrap_err(),
+        Error::NotFound
+    );
+    assert_eq!(repo.read_dir_iter(first.path()).unwrap_err(), Error::NotDir);
+
+    // names are loaded page by page in name order
+    repo.create_dir("/big").unwrap();
+    for i in 0..600 {
+        repo.create_dir(format!("/big/{:03}", i)).unwrap();
+    }
+    let mut iter = repo.read_dir_iter("/big").unwrap();
+    assert_eq!(iter.size_hint(), (0, None));
+    let names: Vec<_> = iter
+        .by_ref()
+        .take(300)
+        .map(|ent| ent.unwrap().file_name().to_string())
+        .collect();
+    repo.remove_dir("/big/400").unwrap();
+    let rest: Vec<_> = iter
+        .map(|ent| ent.unwrap().file_name().to_string())
+        .collect();
+    let expected: Vec<_> = (0..600)
+        .filter(|i| *i != 400)
+        .map(|i| format!("{:03}", i))
+        .collect();
+    assert_eq!([names, rest].concat(), expected);
+}
+
+#[test]
+fn dir_read_iter_closed() {
+    init_env();
+
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .open("mem://dir_read_iter_closed", "pwd")
+        .unwrap();
+    repo.create_dir("/dir").unwrap();
+    for i in 0..3 {
+        repo.create_dir(format!("/dir/{}", i)).unwrap();
+    }
+
+    let mut iter = repo.read_dir_iter("/dir").unwrap();
+    let mut walk = repo.walk("/dir").unwrap();
+    assert!(iter.next().unwrap().is_ok());
+    assert!(walk.next().unwrap().is_ok());
+
+    // iterators cannot keep the repo open
+    drop(repo);
+    assert_eq!(iter.next().unwrap().unwrap_err(), Error::RepoClosed);
+    assert!(iter.next().is_none());
+    assert_eq!(walk.next().unwrap().unwrap_err(), Error::RepoClosed);
+    RepoOpener::new()
+        .open("mem://dir_read_iter_closed", "pwd")
+        .unwrap();
+}
+
 #[test]
 fn dir_remove() {
     let mut env = common::TestEnv::new();
@@ -166,6 +387,78 @@ fn dir_rename() {
     assert_eq!(repo.rename("/5/1", "/5").unwrap_err(), Error::NotEmpty);
 }
 
+#[test]
+fn dir_rename_no_replace() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    assert_eq!(
+        repo.rename_no_replace("/not_exist", "/xxx").unwrap_err(),
+        Error::NotFound
+    );
+
+    // rename to non-existing path
+    repo.create_dir_all("/aaa/bbb").unwrap();
+    repo.rename_no_replace("/aaa/bbb", "/ccc").unwrap();
+    assert!(!repo.path_exists("/aaa/bbb").unwrap());
+    assert!(repo.is_dir("/ccc").unwrap());
+
+    // rename to existing file or dir
+    repo.create_file("/file").unwrap();
+    repo.create_dir("/ddd").unwrap();
+    assert_eq!(
+        repo.rename_no_replace("/file", "/ccc").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert_eq!(
+        repo.rename_no_replace("/ddd", "/ccc").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert_eq!(
+        repo.rename_no_replace("/ddd", "/ddd").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert!(repo.is_file("/file").unwrap());
+    assert!(repo.is_dir("/ddd").unwrap());
+
+    // rename dir to root or into itself
+    assert_eq!(
+        repo.rename_no_replace("/ddd", "/").unwrap_err(),
+        Error::IsRoot
+    );
+    assert_eq!(
+        repo.rename_no_replace("/ddd", "/ddd/eee").unwrap_err(),
+        Error::InvalidArgument
+    );
+
+    // only one thread can win the same target
+    let env = Arc::new(RwLock::new(env));
+    let worker_cnt = 4;
+    for i in 0..worker_cnt {
+        let mut env = env.write().unwrap();
+        env.repo.create_file(format!("/tmp{}", i)).unwrap();
+    }
+    let mut workers = Vec::new();
+    for i in 0..worker_cnt {
+        let env = env.clone();
+        workers.push(thread::spawn(move || {
+            let mut env = env.write().unwrap();
+            env.repo
+                .rename_no_replace(format!("/tmp{}", i), "/lock")
+                .is_ok()
+        }));
+    }
+    let won = workers
+        .into_iter()
+        .map(|w| w.join().unwrap())
+        .filter(|won| *won)
+        .count();
+    assert_eq!(won, 1);
+    let env = env.read().unwrap();
+    assert!(env.repo.is_file("/lock").unwrap());
+    assert_eq!(env.repo.read_dir("/").unwrap().len(), worker_cnt + 4);
+}
+
 #[test]
 fn dir_copy() {
     let mut env = common::TestEnv::new();
@@ -218,3 +511,552 @@ fn dir_copy() {
     repo.copy_dir_all("/ccc/ccc1", "/ccc").unwrap();
     assert!(repo.path_exists("/ccc/ccc11").unwrap());
 }
+
+#[test]
+fn dir_copy_preserve_times() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/src/sub").unwrap();
+    write_file(repo, "/src/file", b"foo");
+    write_file(repo, "/src/sub/file", b"bar");
+    repo.symlink("file", "/src/link").unwrap();
+    thread::sleep(time::Duration::from_millis(10));
+
+    // times are not preserved by default
+    repo.copy("/src/file", "/file").unwrap();
+    let src_md = repo.metadata("/src/file").unwrap();
+    let md = repo.metadata("/file").unwrap();
+    assert!(md.created_at() > src_md.created_at());
+    assert!(md.modified_at() > src_md.modified_at());
+
+    // copy to existing file
+    let mut opts = CopyOptions::new();
+    opts.preserve_times(true);
+    repo.copy_with("/src/file", "/file", opts).unwrap();
+    let md = repo.metadata("/file").unwrap();
+    assert_eq!(md.created_at(), src_md.created_at());
+    assert_eq!(md.modified_at(), src_md.modified_at());
+
+    // copy dir tree
+    repo.copy_dir_all_with("/src", "/dst", opts).unwrap();
+    for path in ["", "/file", "/sub", "/sub/file"].iter() {
+        let src_md = repo.metadata(format!("/src{}", path)).unwrap();
+        let md = repo.metadata(format!("/dst{}", path)).unwrap();
+        assert_eq!(md.created_at(), src_md.created_at());
+        assert_eq!(md.modified_at(), src_md.modified_at());
+    }
+    let src_md = repo.symlink_metadata("/src/link").unwrap();
+    let md = repo.symlink_metadata("/dst/link").unwrap();
+    assert_eq!(md.modified_at(), src_md.modified_at());
+    let mut buf = Vec::new();
+    let mut f = repo.open_file("/dst/link").unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"foo");
+}
+
+#[test]
+fn dir_plan() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/src/sub").unwrap();
+    write_file(repo, "/src/file", b"foo");
+    write_file(repo, "/src/sub/file", b"bar");
+    repo.symlink("file", "/src/link").unwrap();
+    repo.create_dir("/dst").unwrap();
+    write_file(repo, "/dst/file", b"baz");
+
+    // copy plan lists directory before its children
+    let plan = repo.plan_copy_dir_all("/src", "/dst").unwrap();
+    let mut srcs: Vec<PathBuf> = plan.iter().map(|p| p.0.clone()).collect();
+    srcs.sort();
+    assert_eq!(
+        srcs,
+        vec![
+            PathBuf::from("/src/file"),
+            PathBuf::from("/src/link"),
+            PathBuf::from("/src/sub"),
+            PathBuf::from("/src/sub/file"),
+        ]
+    );
+    let sub = plan.iter().position(|p| p.0 == Path::new("/src/sub"));
+    let sub_file = plan.iter().position(|p| p.0 == Path::new("/src/sub/file"));
+    assert!(sub < sub_file);
+    for (from, to) in plan.iter() {
+        assert_eq!(
+            to,
+            &PathBuf::from("/dst").join(from.strip_prefix("/src").unwrap())
+        );
+    }
+    assert!(!repo.path_exists("/dst/sub").unwrap());
+    assert!(repo.plan_copy_dir_all("/src", "/src").unwrap().is_empty());
+    assert_eq!(
+        repo.plan_copy_dir_all("/src", "/src/sub").unwrap_err(),
+        Error::InvalidArgument
+    );
+    assert_eq!(
+        repo.plan_copy_dir_all("/src", "/dst/file").unwrap_err(),
+        Error::NotDir
+    );
+
+    // copied entries match the plan
+    repo.copy_dir_all("/src", "/dst").unwrap();
+    for (_, to) in plan.iter() {
+        assert!(repo.path_exists(to).unwrap());
+    }
+
+    // remove plan lists children before their parent directory
+    let plan = repo.plan_remove_dir_all("/dst").unwrap();
+    assert_eq!(plan.len(), 5);
+    assert_eq!(plan.last().unwrap(), &PathBuf::from("/dst"));
+    let sub = plan.iter().position(|p| p == Path::new("/dst/sub"));
+    let sub_file = plan.iter().position(|p| p == Path::new("/dst/sub/file"));
+    assert!(sub_file < sub);
+    for path in plan.iter() {
+        assert!(repo.path_exists(path).unwrap());
+    }
+    repo.remove_dir_all("/dst").unwrap();
+    assert!(!repo.path_exists("/dst").unwrap());
+
+    // root itself is not removed
+    let plan = repo.plan_remove_dir_all("/").unwrap();
+    assert_eq!(plan.len(), 5);
+    assert!(!plan.contains(&PathBuf::from("/")));
+    assert_eq!(
+        repo.plan_remove_dir_all("/src/file").unwrap_err(),
+        Error::NotDir
+    );
+    assert_eq!(
+        repo.plan_remove_dir_all("/not_exist").unwrap_err(),
+        Error::NotFound
+    );
+
+    // plan is a dry run, so it works on read-only repo
+    repo.set_read_only(true, false).unwrap();
+    assert_eq!(repo.plan_remove_dir_all("/src").unwrap().len(), 5);
+    assert_eq!(repo.remove_dir_all("/src").unwrap_err(), Error::ReadOnly);
+}
+
+#[test]
+fn dir_move_all() {
+    init_env();
+
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .version_limit(3)
+        .dedup_file(true)
+        .open("mem://dir_move_all", "pwd")
+        .unwrap();
+    let data = vec![42u8; 100 * 1024];
+
+    repo.create_dir_all("/src/sub").unwrap();
+    write_file(&mut repo, "/src/file", b"foo");
+    write_file(&mut repo, "/src/file", b"foo2");
+    write_file(&mut repo, "/src/sub/big", &data);
+    write_file(&mut repo, "/src/sub/big2", &data);
+    repo.create_dir_all("/dst/sub").unwrap();
+    write_file(&mut repo, "/dst/file", b"old");
+    write_file(&mut repo, "/dst/sub/other", b"other");
+    repo.create_dir("/dst/keep").unwrap();
+
+    let usage = repo.space_usage().unwrap();
+    let hist = repo.history("/src/file").unwrap();
+    assert_eq!(hist.len(), 3);
+
+    // error cases, nothing is moved
+    assert_eq!(
+        repo.move_all("/src", "/src/sub").unwrap_err(),
+        Error::InvalidArgument
+    );
+    assert_eq!(
+        repo.move_all("/src/file", "/dst").unwrap_err(),
+        Error::NotDir
+    );
+    assert_eq!(
+        repo.move_all("/src", "/dst/file").unwrap_err(),
+        Error::NotDir
+    );
+    repo.create_dir("/conflict").unwrap();
+    repo.create_dir("/conflict/file").unwrap();
+    assert_eq!(
+        repo.move_all("/src", "/conflict").unwrap_err(),
+        Error::IsDir
+    );
+    assert!(repo.path_exists("/src/sub/big").unwrap());
+    repo.remove_dir_all("/conflict").unwrap();
+
+    // move and merge into existing dir
+    repo.move_all("/src", "/dst").unwrap();
+    assert!(!repo.path_exists("/src").unwrap());
+    assert!(repo.is_dir("/dst/keep").unwrap());
+    let mut paths: Vec<PathBuf> = repo
+        .read_dir("/dst/sub")
+        .unwrap()
+        .iter()
+        .map(|ent| ent.path().to_path_buf())
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("/dst/sub/big"),
+            PathBuf::from("/dst/sub/big2"),
+            PathBuf::from("/dst/sub/other"),
+        ]
+    );
+
+    // versions and deduplicated content are kept
+    let moved_hist = repo.history("/dst/file").unwrap();
+    assert_eq!(moved_hist.len(), hist.len());
+    for (a, b) in moved_hist.iter().zip(hist.iter()) {
+        assert_eq!(a.num(), b.num());
+        assert_eq!(a.content_len(), b.content_len());
+    }
+    let mut buf = Vec::new();
+    repo.open_file("/dst/file")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"foo2");
+    let mut buf = Vec::new();
+    repo.open_file("/dst/sub/big2")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+    let moved_usage = repo.space_usage().unwrap();
+    assert!(moved_usage.segments() <= usage.segments());
+    assert_eq!(moved_usage.dedup_savings(), usage.dedup_savings());
+    assert_eq!(
+        moved_usage.logical_bytes(),
+        usage.logical_bytes() - "old".len()
+    );
+
+    // move to non-existing dir is a rename
+    repo.move_all("/dst/sub", "/new").unwrap();
+    assert!(repo.is_file("/new/big").unwrap());
+    assert!(!repo.path_exists("/dst/sub").unwrap());
+
+    // move file into dir or to a new name
+    repo.move_file("/dst/file", "/new").unwrap();
+    assert!(repo.is_file("/new/file").unwrap());
+    repo.move_file("/new/file", "/file2").unwrap();
+    assert_eq!(repo.history("/file2").unwrap().len(), hist.len());
+    assert_eq!(repo.move_file("/new", "/dst").unwrap_err(), Error::NotFile);
+}
+
+fn write_file(repo: &mut Repo, path: &str, buf: &[u8]) {
+    let mut f = OpenOptions::new().create(true).open(repo, path).unwrap();
+    f.write_once(buf).unwrap();
+}
+
+#[test]
+fn dir_modified_since() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/aaa/bbb").unwrap();
+    write_file(repo, "/file1", b"foo");
+    write_file(repo, "/aaa/file2", b"foo");
+
+    thread::sleep(time::Duration::from_millis(10));
+    let since = time::SystemTime::now();
+    thread::sleep(time::Duration::from_millis(10));
+
+    // no file modified yet
+    assert!(repo.modified_since(since).unwrap().is_empty());
+
+    // write new files and modify an existing file
+    write_file(repo, "/aaa/bbb/file3", b"bar");
+    write_file(repo, "/file4", b"bar");
+    write_file(repo, "/aaa/file2", b"bar");
+    repo.create_dir("/ccc").unwrap();
+
+    let paths = repo.modified_since(since).unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("/aaa/bbb/file3"),
+            PathBuf::from("/aaa/file2"),
+            PathBuf::from("/file4"),
+        ]
+    );
+}
+
+#[test]
+fn dir_size() {
+    fn check(repo: &Repo, expected: &[(&str, usize)]) {
+        for &(path, size) in expected.iter() {
+            assert_eq!(repo.dir_size(path).unwrap(), size, "{}", path);
+        }
+    }
+
+    fn write(repo: &mut Repo, path: &str, len: usize) {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .open(repo, path)
+            .unwrap();
+        f.write_once(&vec![42u8; len]).unwrap();
+    }
+
+    init_env();
+
+    for &track in [true, false].iter() {
+        let uri = format!("mem://dir_size_{}", track);
+        let mut repo = RepoOpener::new()
+            .create(true)
+            .track_dir_sizes(track)
+            .open(&uri, "pwd")
+            .unwrap();
+        assert_eq!(repo.info().unwrap().track_dir_sizes(), track);
+
+        // #1: write files
+        repo.create_dir_all("/a/b").unwrap();
+        repo.create_dir("/c").unwrap();
+        write(&mut repo, "/a/f1", 10);
+        write(&mut repo, "/a/b/f2", 20);
+        write(&mut repo, "/c/f3", 5);
+        check(&repo, &[("/", 35), ("/a", 30), ("/a/b", 20), ("/c", 5)]);
+        assert_eq!(repo.dir_size("/a/f1").unwrap_err(), Error::NotDir);
+
+        // #2: overwrite, append and set length
+        write(&mut repo, "/a/b/f2", 3);
+        {
+            let mut f = OpenOptions::new()
+                .append(true)
+                .open(&mut repo, "/c/f3")
+                .unwrap();
+            f.write_once(&[1u8; 7]).unwrap();
+            f.set_len(100).unwrap();
+        }
+        check(&repo, &[("/", 113), ("/a", 13), ("/a/b", 3), ("/c", 100)]);
+
+        // #3: rename, copy and remove
+        repo.rename("/a/b", "/c/b").unwrap();
+        check(&repo, &[("/", 113), ("/a", 10), ("/c", 103), ("/c/b", 3)]);
+        repo.copy("/c/b/f2", "/a/f4").unwrap();
+        check(&repo, &[("/", 116), ("/a", 13), ("/c", 103)]);
+        repo.rename("/a/f4", "/a/f1").unwrap();
+        check(&repo, &[("/", 106), ("/a", 3), ("/c", 103)]);
+        repo.remove_file("/a/f1").unwrap();
+        check(&repo, &[("/", 103), ("/a", 0), ("/c", 103)]);
+        repo.remove_dir_all("/c").unwrap();
+        check(&repo, &[("/", 0), ("/a", 0)]);
+
+        // #4: cached size is persistent
+        write(&mut repo, "/a/f5", 8);
+        drop(repo);
+        let repo = RepoOpener::new().open(&uri, "pwd").unwrap();
+        check(&repo, &[("/", 8), ("/a", 8)]);
+    }
+}
+
+#[test]
+fn dir_read_page() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir("/big").unwrap();
+    let mut names = Vec::new();
+    for i in 0..300 {
+        let name = format!("{:x}", i * 7919);
+        let path = format!("/big/{}", name);
+        if i % 3 == 0 {
+            repo.create_dir(&path).unwrap();
+        } else {
+            repo.create_file(&path).unwrap();
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    // page through the whole directory
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    loop {
+        let (ents, next) = repo.read_dir_page("/big", cursor, 7).unwrap();
+        assert!(ents.len() <= 7);
+        for ent in ents.iter() {
+            assert_eq!(ent.path(), PathBuf::from("/big").join(ent.file_name()));
+            listed.push(ent.file_name().to_string());
+        }
+        if next.is_none() {
+            break;
+        }
+        cursor = next;
+    }
+    assert_eq!(listed, names);
+
+    // change directory between pages
+    let (ents, cursor) = repo.read_dir_page("/big", None, 100).unwrap();
+    assert_eq!(ents.len(), 100);
+    let first = ents[0].path().to_path_buf();
+    if ents[0].metadata().is_dir() {
+        repo.remove_dir(&first).unwrap();
+    } else {
+        repo.remove_file(&first).unwrap();
+    }
+    repo.create_file("/big/zzz").unwrap();
+    let (ents, cursor) = repo.read_dir_page("/big", cursor, 300).unwrap();
+    assert!(cursor.is_none());
+    let rest: Vec<&str> = ents.iter().map(|e| e.file_name()).collect();
+    let mut expected: Vec<&str> =
+        names[100..].iter().map(|n| n.as_str()).collect();
+    expected.push("zzz");
+    assert_eq!(rest, expected);
+
+    // exact page size has no more entries
+    repo.create_dir("/small").unwrap();
+    repo.create_file("/small/a").unwrap();
+    repo.create_file("/small/b").unwrap();
+    let (ents, cursor) = repo.read_dir_page("/small", None, 2).unwrap();
+    assert_eq!(ents.len(), 2);
+    assert!(cursor.is_none());
+
+    // empty directory, file and zero limit
+    repo.create_dir("/empty").unwrap();
+    let (ents, cursor) = repo.read_dir_page("/empty", None, 10).unwrap();
+    assert!(ents.is_empty());
+    assert!(cursor.is_none());
+    assert_eq!(
+        repo.read_dir_page("/small/a", None, 10).unwrap_err(),
+        Error::NotDir
+    );
+    assert_eq!(
+        repo.read_dir_page("/small", None, 0).unwrap_err(),
+        Error::InvalidArgument
+    );
+}
+
+#[test]
+fn dir_symlink() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/a/b").unwrap();
+    write_file(repo, "/a/b/file", b"foo");
+
+    // absolute and relative links
+    repo.symlink("/a/b", "/abs").unwrap();
+    repo.symlink("b/file", "/a/rel").unwrap();
+    repo.symlink("../../a/./b", "/a/b/up").unwrap();
+    assert_eq!(repo.read_link("/abs").unwrap(), PathBuf::from("/a/b"));
+    assert_eq!(repo.read_link("/a/rel").unwrap(), PathBuf::from("b/file"));
+    assert_eq!(
+        repo.symlink("/a", "/abs").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert_eq!(repo.read_link("/a").unwrap_err(), Error::InvalidArgument);
+
+    // links are followed
+    assert!(repo.is_dir("/abs").unwrap());
+    assert!(repo.is_file("/abs/file").unwrap());
+    assert!(repo.is_file("/a/rel").unwrap());
+    assert!(repo.is_file("/a/b/up/up/file").unwrap());
+    let mut buf = Vec::new();
+    repo.open_file("/a/rel")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"foo");
+    assert_eq!(repo.read_dir("/abs").unwrap().len(), 2);
+    assert_eq!(repo.metadata("/a/rel").unwrap().content_len(), 3);
+
+    // link itself is not followed by symlink_metadata
+    let md = repo.symlink_metadata("/a/rel").unwrap();
+    assert!(md.is_symlink());
+    assert_eq!(md.content_len(), "b/file".len());
+    assert!(repo.symlink_metadata("/a/b/file").unwrap().is_file());
+
+    // dangling link
+    repo.symlink("/nowhere", "/dangling").unwrap();
+    assert!(!repo.path_exists("/dangling").unwrap());
+    assert!(repo.symlink_metadata("/dangling").unwrap().is_symlink());
+    assert_eq!(repo.metadata("/dangling").unwrap_err(), Error::NotFound);
+
+    // cycles are detected
+    repo.symlink("/loop2", "/loop1").unwrap();
+    repo.symlink("/loop1", "/loop2").unwrap();
+    repo.symlink("self", "/a/self").unwrap();
+    assert_eq!(repo.metadata("/loop1").unwrap_err(), Error::TooManySymlinks);
+    assert_eq!(
+        repo.metadata("/a/self/x").unwrap_err(),
+        Error::TooManySymlinks
+    );
+    assert!(repo.symlink_metadata("/loop1").unwrap().is_symlink());
+
+    // copy dir keeps links as links
+    repo.copy_dir_all("/a", "/c").unwrap();
+    assert_eq!(repo.read_link("/c/rel").unwrap(), PathBuf::from("b/file"));
+    assert!(repo.is_file("/c/rel").unwrap());
+
+    // rename and remove work on the link itself
+    repo.rename("/abs", "/abs2").unwrap();
+    assert!(repo.is_dir("/abs2").unwrap());
+    repo.remove_file("/abs2").unwrap();
+    assert!(!repo.path_exists("/abs2").unwrap());
+    assert!(repo.is_dir("/a/b").unwrap());
+    repo.remove_dir_all("/a").unwrap();
+    assert!(!repo.path_exists("/a").unwrap());
+    assert!(repo.symlink_metadata("/c/rel").unwrap().is_symlink());
+    assert!(!repo.path_exists("/c/b/up").unwrap());
+    assert!(repo.is_file("/c/b/file").unwrap());
+}
+
+#[test]
+fn dir_path_normalize() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/a/b").unwrap();
+    repo.create_file("/a/b/f").unwrap();
+
+    // different strings map to the same entity
+    assert!(repo.is_dir("/a/../a/b").unwrap());
+    assert!(repo.is_dir("/a/./b").unwrap());
+    assert!(repo.is_dir("//a///b").unwrap());
+    assert!(repo.is_dir("/a/b/").unwrap());
+    assert!(repo.is_file("/a/b/../b/./f").unwrap());
+
+    // entities are created at normalized paths
+    repo.create_dir("/a/../c").unwrap();
+    assert!(repo.is_dir("/c").unwrap());
+    assert!(!repo.path_exists("/a/c").unwrap());
+    repo.create_dir_all("//d/./e/").unwrap();
+    assert!(repo.is_dir("/d/e").unwrap());
+    let dirs: Vec<PathBuf> = repo
+        .read_dir("/a/./b/")
+        .unwrap()
+        .iter()
+        .map(|ent| ent.path().to_path_buf())
+        .collect();
+    assert_eq!(dirs, vec![PathBuf::from("/a/b/f")]);
+
+    // renaming to the same path after normalization does nothing
+    repo.rename("/c", "/a/../c/").unwrap();
+    assert!(repo.is_dir("/c").unwrap());
+
+    // '..' cannot escape root
+    assert_eq!(repo.metadata("/..").unwrap_err(), Error::InvalidPath);
+    assert_eq!(
+        repo.create_dir("/a/../../x").unwrap_err(),
+        Error::InvalidPath
+    );
+    assert!(repo.is_dir("/a/..").unwrap());
+
+    // path must be absolute
+    assert_eq!(repo.metadata("a/b").unwrap_err(), Error::InvalidPath);
+    assert_eq!(repo.create_dir("x").unwrap_err(), Error::InvalidPath);
+
+    // path must be valid UTF-8
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = OsStr::from_bytes(b"/a/\xff");
+        assert_eq!(repo.create_dir(path).unwrap_err(), Error::InvalidPath);
+    }
+}
@@ -9,10 +9,13 @@ mod common;
 
 use rand::{RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::thread;
-use zbox::{Error, File, OpenOptions};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zbox::{init_env, CompactOptions, Error, File, OpenOptions, RepoOpener};
 
 #[test]
 fn file_open_close() {
@@ -638,6 +641,80 @@ fn file_copy() {
     }
 }
 
+#[test]
+fn file_clone() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([12u8; 16]);
+    let mut data = vec![0u8; 100 * 1024];
+    rng.fill_bytes(&mut data);
+
+    {
+        let mut f =
+            OpenOptions::new().create(true).open(repo, "/file").unwrap();
+        f.write_once(&data).unwrap();
+    }
+    let usage = repo.disk_usage().unwrap();
+
+    // #1, clone shares content with source
+    repo.clone_file("/file", "/file2").unwrap();
+    assert_eq!(repo.disk_usage().unwrap(), usage);
+    {
+        let mut f = repo.open_file("/file2").unwrap();
+        verify_content(&mut f, &data);
+        assert_eq!(f.history().unwrap().len(), 1);
+    }
+
+    // #2, writing to clone doesn't change source
+    {
+        let mut f =
+            OpenOptions::new().write(true).open(repo, "/file2").unwrap();
+        f.write_once(&[1u8; 100]).unwrap();
+    }
+    {
+        let mut f = repo.open_file("/file").unwrap();
+        verify_content(&mut f, &data);
+    }
+
+    // #3, writing to source doesn't change another clone
+    repo.clone_file("/file", "/file3").unwrap();
+    {
+        let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+        f.write_once(&[2u8; 100]).unwrap();
+    }
+    {
+        let mut f = repo.open_file("/file3").unwrap();
+        verify_content(&mut f, &data);
+    }
+
+    // #4, source is removed, clone is still readable
+    repo.remove_file("/file").unwrap();
+    {
+        let mut f = repo.open_file("/file3").unwrap();
+        verify_content(&mut f, &data);
+    }
+
+    // #5, invalid source or target
+    assert_eq!(
+        repo.clone_file("/file2", "/file3").unwrap_err(),
+        Error::AlreadyExists
+    );
+    assert_eq!(
+        repo.clone_file("/file", "/file4").unwrap_err(),
+        Error::NotFound
+    );
+    repo.create_dir("/dir").unwrap();
+    assert_eq!(
+        repo.clone_file("/dir", "/file4").unwrap_err(),
+        Error::NotFile
+    );
+    assert_eq!(
+        repo.clone_file("/file2", "/file3/file4").unwrap_err(),
+        Error::NotDir
+    );
+}
+
 #[test]
 fn file_seek() {
     let mut env = common::TestEnv::new();
@@ -844,4 +921,2050 @@ fn file_rename() {
         repo.rename("/file6", "/file6").unwrap();
         assert!(repo.path_exists("/file6").unwrap());
     }
+
+    // #7, rename and return the replaced file's metadata
+    {
+        let buf = [1u8, 2u8, 3u8, 4u8];
+        let mut f = OpenOptions::new()
+            .create(true)
+            .open(repo, "/file7a")
+            .unwrap();
+        f.write_once(&buf[..]).unwrap();
+        drop(f);
+        let md = repo.metadata("/file7a").unwrap();
+        repo.create_file("/file7").unwrap();
+
+        let old = repo.rename_returning("/file7", "/file7a").unwrap().unwrap();
+        assert!(old.is_file());
+        assert_eq!(old.content_len(), buf.len());
+        assert_eq!(old.curr_version(), md.curr_version());
+        assert_eq!(old.created_at(), md.created_at());
+        assert_eq!(repo.metadata("/file7a").unwrap().content_len(), 0);
+
+        // target doesn't exist
+        assert!(repo
+            .rename_returning("/file7a", "/file7b")
+            .unwrap()
+            .is_none());
+        assert!(repo.path_exists("/file7b").unwrap());
+    }
+}
+
+#[test]
+fn file_touch() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // #1, touch non-existing file
+    {
+        repo.touch("/file").unwrap();
+        assert!(repo.is_file("/file").unwrap());
+        let meta = repo.metadata("/file").unwrap();
+        assert_eq!(meta.content_len(), 0);
+        assert_eq!(repo.history("/file").unwrap().len(), 1);
+    }
+
+    // #2, touch existing file
+    {
+        let buf = [1u8, 2u8, 3u8];
+        let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+        f.write_once(&buf[..]).unwrap();
+        let meta = repo.metadata("/file").unwrap();
+        let hist = repo.history("/file").unwrap();
+
+        thread::sleep(Duration::from_millis(10));
+        repo.touch("/file").unwrap();
+
+        let meta2 = repo.metadata("/file").unwrap();
+        assert!(meta2.modified_at() > meta.modified_at());
+        assert_eq!(meta2.created_at(), meta.created_at());
+        assert_eq!(meta2.curr_version(), meta.curr_version());
+        assert_eq!(repo.history("/file").unwrap().len(), hist.len());
+
+        let mut f = repo.open_file("/file").unwrap();
+        verify_content(&mut f, &buf);
+    }
+
+    // #3, touch existing dir
+    {
+        repo.create_dir("/dir").unwrap();
+        let meta = repo.metadata("/dir").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        repo.touch("/dir").unwrap();
+        let meta2 = repo.metadata("/dir").unwrap();
+        assert!(meta2.is_dir());
+        assert!(meta2.modified_at() > meta.modified_at());
+    }
+
+    // #4, touch file under non-existing dir
+    {
+        assert_eq!(repo.touch("/xxx/file").unwrap_err(), Error::NotFound);
+    }
+}
+
+#[test]
+fn file_lines() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let read_lines = |repo: &mut zbox::Repo, path: &str| -> Vec<String> {
+        let f = repo.open_file(path).unwrap();
+        f.lines().map(|line| line.unwrap()).collect()
+    };
+
+    // #1, LF line endings
+    {
+        let mut f = repo.create_file("/lf").unwrap();
+        f.write_once(b"foo\nbar\n\nbaz\n").unwrap();
+        assert_eq!(read_lines(repo, "/lf"), vec!["foo", "bar", "", "baz"]);
+    }
+
+    // #2, CRLF line endings
+    {
+        let mut f = repo.create_file("/crlf").unwrap();
+        f.write_once(b"foo\r\nbar\r\n").unwrap();
+        assert_eq!(read_lines(repo, "/crlf"), vec!["foo", "bar"]);
+    }
+
+    // #3, no trailing newline
+    {
+        let mut f = repo.create_file("/no_trailing").unwrap();
+        f.write_once(b"foo\nbar").unwrap();
+        assert_eq!(read_lines(repo, "/no_trailing"), vec!["foo", "bar"]);
+    }
+
+    // #4, empty file
+    {
+        repo.create_file("/empty").unwrap();
+        assert!(read_lines(repo, "/empty").is_empty());
+    }
+
+    // #5, invalid UTF-8
+    {
+        let mut f = repo.create_file("/invalid").unwrap();
+        f.write_once(b"foo\n\xff\xfe\nbar").unwrap();
+        let f = repo.open_file("/invalid").unwrap();
+        let lines: Vec<_> = f.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_ref().unwrap(), "foo");
+        assert!(lines[1].is_err());
+        assert_eq!(lines[2].as_ref().unwrap(), "bar");
+    }
+
+    // #6, lines longer than read buffer
+    {
+        let line = "x".repeat(200 * 1024);
+        let mut f = repo.create_file("/long").unwrap();
+        f.write_once(format!("{}\n{}", line, line).as_bytes())
+            .unwrap();
+        assert_eq!(read_lines(repo, "/long"), vec![line.clone(), line]);
+    }
+}
+
+#[test]
+fn file_buf_read() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // content spans multiple frames
+    let mut data = Vec::new();
+    for i in 0..50_000 {
+        data.extend_from_slice(format!("line {}\n", i).as_bytes());
+    }
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&data).unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+
+    // read line and mix with read and seek
+    let mut line = String::new();
+    f.read_line(&mut line).unwrap();
+    assert_eq!(line, "line 0\n");
+    assert_eq!(f.stream_position().unwrap(), 7);
+    let mut buf = [0u8; 7];
+    f.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"line 1\n");
+    assert_eq!(f.stream_position().unwrap(), 14);
+    let buf = f.fill_buf().unwrap();
+    assert!(buf.starts_with(b"line 2\n"));
+    f.consume(7);
+    f.seek(SeekFrom::Current(7)).unwrap();
+    line.clear();
+    f.read_line(&mut line).unwrap();
+    assert_eq!(line, "line 4\n");
+    let pos = data.len() as u64 - 11;
+    f.seek(SeekFrom::Start(pos)).unwrap();
+    line.clear();
+    f.read_line(&mut line).unwrap();
+    assert_eq!(line, "line 49999\n");
+    assert!(f.fill_buf().unwrap().is_empty());
+
+    // read all lines across frames
+    f.seek(SeekFrom::Start(0)).unwrap();
+    let mut cnt = 0;
+    for (i, line) in (&mut f).split(b'\n').enumerate() {
+        assert_eq!(line.unwrap(), format!("line {}", i).as_bytes());
+        cnt += 1;
+    }
+    assert_eq!(cnt, 50_000);
+
+    // version reader, including inline content
+    f.write_once(b"foo\n").unwrap();
+    let history = f.history().unwrap();
+    let mut rdr = f.version_reader(history[0].num()).unwrap();
+    line.clear();
+    rdr.read_line(&mut line).unwrap();
+    assert_eq!(line, "line 0\n");
+    rdr.seek(SeekFrom::End(-11)).unwrap();
+    line.clear();
+    rdr.read_line(&mut line).unwrap();
+    assert_eq!(line, "line 49999\n");
+    let mut rdr = f.version_reader(history[1].num()).unwrap();
+    rdr.seek(SeekFrom::End(-15)).unwrap();
+    let lines: Vec<String> = rdr.lines().map(|line| line.unwrap()).collect();
+    assert_eq!(lines, vec!["line 49999", "foo"]);
+
+    let mut f = repo.create_file("/inline").unwrap();
+    f.write_once(b"foo\nbar").unwrap();
+    let rdr = f.version_reader(f.curr_version().unwrap()).unwrap();
+    let lines: Vec<String> = rdr.lines().map(|line| line.unwrap()).collect();
+    assert_eq!(lines, vec!["foo", "bar"]);
+}
+
+#[test]
+fn file_raw_content() {
+    init_env();
+
+    let mut src = RepoOpener::new()
+        .create(true)
+        .compress(true)
+        .open("mem://raw_src", "pwd")
+        .unwrap();
+    let mut dst = RepoOpener::new()
+        .create(true)
+        .compress(true)
+        .dedup_file(true)
+        .open("mem://raw_dst", "pwd")
+        .unwrap();
+    let mut plain = RepoOpener::new()
+        .create(true)
+        .open("mem://raw_plain", "pwd")
+        .unwrap();
+
+    // compressible data spanning multiple segments, plus some random data
+    let mut data = vec![0u8; 3 * 1024 * 1024];
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = (i / 1024) as u8;
+    }
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut data[..64 * 1024]);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .open(&mut src, "/file")
+        .unwrap();
+    f.write_once(&data).unwrap();
+
+    // export raw content
+    let mut rdr = f.read_raw().unwrap();
+    assert!(rdr.is_compressed());
+    assert_eq!(rdr.content_len(), data.len());
+    let mut raw = Vec::new();
+    rdr.read_to_end(&mut raw).unwrap();
+    assert!(raw.len() < data.len());
+
+    // import to another repo and verify content
+    let mut f2 = OpenOptions::new()
+        .create(true)
+        .open(&mut dst, "/file")
+        .unwrap();
+    f2.write_raw(&raw[..]).unwrap();
+    assert_eq!(f2.curr_version().unwrap(), 2);
+    let mut buf = Vec::new();
+    f2.seek(SeekFrom::Start(0)).unwrap();
+    f2.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    // imported file can be exported again and written as usual
+    let mut raw2 = Vec::new();
+    f2.read_raw().unwrap().read_to_end(&mut raw2).unwrap();
+    let mut f3 = OpenOptions::new()
+        .create(true)
+        .open(&mut dst, "/file2")
+        .unwrap();
+    f3.write_raw(&raw2[..]).unwrap();
+    f3.write_once(b"foo").unwrap();
+    let mut buf = Vec::new();
+    f3.seek(SeekFrom::Start(0)).unwrap();
+    f3.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), data.len() + 3);
+    assert_eq!(&buf[..data.len()], &data[..]);
+    assert_eq!(&buf[data.len()..], b"foo");
+
+    // duplicated content is deduped in the destination repo
+    f2.write_raw(&raw2[..]).unwrap();
+    let mut buf = Vec::new();
+    dst.open_file("/file")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+
+    // empty file
+    let f = OpenOptions::new()
+        .create(true)
+        .open(&mut src, "/empty")
+        .unwrap();
+    let mut raw = Vec::new();
+    f.read_raw().unwrap().read_to_end(&mut raw).unwrap();
+    let mut f2 = OpenOptions::new()
+        .create(true)
+        .open(&mut dst, "/empty")
+        .unwrap();
+    f2.write_raw(&raw[..]).unwrap();
+    assert_eq!(f2.metadata().unwrap().content_len(), 0);
+
+    // compression option must match
+    let mut f = OpenOptions::new()
+        .create(true)
+        .open(&mut plain, "/file")
+        .unwrap();
+    assert_eq!(f.write_raw(&raw2[..]).unwrap_err(), Error::InvalidArgument);
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+
+    // corrupted stream
+    assert!(f.write_raw(&raw2[..raw2.len() / 2]).is_err());
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+}
+
+#[test]
+fn file_raw_content_shared_segment() {
+    init_env();
+
+    let mut src = RepoOpener::new()
+        .create(true)
+        .dedup_file(true)
+        .open("mem://raw_shared_src", "pwd")
+        .unwrap();
+    let mut dst = RepoOpener::new()
+        .create(true)
+        .open("mem://raw_shared_dst", "pwd")
+        .unwrap();
+
+    let mut data = vec![0u8; 300 * 1024];
+    let mut rng = XorShiftRng::from_seed([21u8; 16]);
+    rng.fill_bytes(&mut data);
+
+    // the two files share the same deduped content
+    let mut f = src.create_file("/a").unwrap();
+    f.write_once(&data).unwrap();
+    let mut f2 = src.create_file("/b").unwrap();
+    f2.write_once(&data).unwrap();
+
+    // truncate the second file, its new content only refers to the head
+    // chunks of the shared segment
+    let len = 50 * 1024;
+    f2.set_len(len).unwrap();
+
+    // export the second file, chunks only used by the first file must not
+    // be in the stream
+    let mut raw = Vec::new();
+    let mut rdr = f2.read_raw().unwrap();
+    assert!(!rdr.is_compressed());
+    assert_eq!(rdr.content_len(), len);
+    rdr.read_to_end(&mut raw).unwrap();
+    assert!(raw.len() < data.len() / 2);
+    let tail = &data[data.len() - 64..];
+    assert!(!raw.windows(tail.len()).any(|w| w == tail));
+
+    // import to another repo and verify content
+    let mut f3 = dst.create_file("/b").unwrap();
+    f3.write_raw(&raw[..]).unwrap();
+    let mut buf = Vec::new();
+    f3.seek(SeekFrom::Start(0)).unwrap();
+    f3.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[..len]);
+
+    // imported content can be exported again and appended as usual
+    let mut raw2 = Vec::new();
+    f3.read_raw().unwrap().read_to_end(&mut raw2).unwrap();
+    let mut f4 = dst.create_file("/c").unwrap();
+    f4.write_raw(&raw2[..]).unwrap();
+    f4.seek(SeekFrom::End(0)).unwrap();
+    f4.write_all(b"foo").unwrap();
+    f4.finish().unwrap();
+    let mut buf = Vec::new();
+    dst.open_file("/c").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..len], &data[..len]);
+    assert_eq!(&buf[len..], b"foo");
+
+    // the first file is not affected
+    let mut buf = Vec::new();
+    src.open_file("/a").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn file_append_reopen() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+
+    // every write session in append mode starts at EOF
+    let mut expected = b"foo".to_vec();
+    for i in 0..3 {
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_all(b"bar").unwrap();
+        f.finish().unwrap();
+        expected.extend_from_slice(b"bar");
+        assert_eq!(f.metadata().unwrap().content_len(), 6 + i * 3);
+    }
+    verify_content(&mut f, &expected);
+
+    // file extended by another handle
+    {
+        let mut f2 =
+            OpenOptions::new().write(true).open(repo, "/file").unwrap();
+        f2.seek(SeekFrom::End(0)).unwrap();
+        f2.write_once(b"baz").unwrap();
+        expected.extend_from_slice(b"baz");
+    }
+    f.write_once(b"qux").unwrap();
+    expected.extend_from_slice(b"qux");
+    verify_content(&mut f, &expected);
+
+    // append_all always writes at EOF, even not in append mode
+    let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+    f.append_all(b"end").unwrap();
+    expected.extend_from_slice(b"end");
+    assert_eq!(f.stream_position().unwrap(), expected.len() as u64);
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"FOO").unwrap();
+    expected[..3].copy_from_slice(b"FOO");
+    verify_content(&mut f, &expected);
+}
+
+#[test]
+fn file_append_only() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // create append-only file
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append_only(true)
+        .open(repo, "/log")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    assert!(f.metadata().unwrap().is_append_only());
+    assert!(repo.metadata("/log").unwrap().is_append_only());
+
+    // append is allowed
+    f.write_once(b"bar").unwrap();
+    f.set_len(8).unwrap();
+    f.seek(SeekFrom::End(0)).unwrap();
+    f.write_once(b"baz").unwrap();
+    let mut expected = b"foobar\0\0baz".to_vec();
+    verify_content(&mut f, &expected);
+
+    // overwrite is not allowed
+    f.seek(SeekFrom::Start(1)).unwrap();
+    assert_eq!(f.write_once(b"xxx").unwrap_err(), Error::AppendOnly);
+    f.seek(SeekFrom::Start(0)).unwrap();
+    assert!(f.write(b"xxx").is_err());
+
+    // truncate is not allowed
+    assert_eq!(f.set_len(2).unwrap_err(), Error::AppendOnly);
+    assert_eq!(
+        OpenOptions::new()
+            .truncate(true)
+            .open(repo, "/log")
+            .unwrap_err(),
+        Error::AppendOnly
+    );
+
+    // replace content is not allowed
+    let raw = {
+        let mut buf = Vec::new();
+        f.read_raw().unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    };
+    assert_eq!(f.write_raw(&raw[..]).unwrap_err(), Error::AppendOnly);
+    drop(f);
+    repo.create_file("/other").unwrap();
+    assert_eq!(repo.copy("/other", "/log").unwrap_err(), Error::AppendOnly);
+    assert_eq!(
+        repo.rename("/other", "/log").unwrap_err(),
+        Error::AppendOnly
+    );
+
+    // delete is not allowed
+    assert_eq!(repo.remove_file("/log").unwrap_err(), Error::AppendOnly);
+    repo.create_dir("/dir").unwrap();
+    repo.rename("/log", "/dir/log").unwrap();
+    assert_eq!(repo.remove_dir_all("/dir").unwrap_err(), Error::AppendOnly);
+
+    // append-only file is opened at its end for writing
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(repo, "/dir/log")
+        .unwrap();
+    f.write_once(b"qux").unwrap();
+    expected.extend_from_slice(b"qux");
+    verify_content(&mut f, &expected);
+    drop(f);
+
+    // clear append-only flag then it can be removed
+    repo.set_append_only("/dir/log", false).unwrap();
+    assert!(!repo.metadata("/dir/log").unwrap().is_append_only());
+    repo.remove_file("/dir/log").unwrap();
+
+    // only regular file can be append-only
+    assert_eq!(
+        repo.set_append_only("/dir", true).unwrap_err(),
+        Error::NotFile
+    );
+}
+
+#[test]
+fn file_low_memory() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // data spanning multiple frames and chunker buffers
+    let mut buf = vec![0u8; 3 * 128 * 1024 + 123];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    // #1, write in small pieces which are not aligned to frame boundary
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .low_memory(true)
+            .open(repo, "/file")
+            .unwrap();
+        for piece in buf.chunks(3000) {
+            f.write_all(piece).unwrap();
+        }
+        f.finish().unwrap();
+        verify_content(&mut f, &buf);
+    }
+
+    // #2, overwrite across frame boundary and read back from the middle
+    {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .low_memory(true)
+            .open(repo, "/file")
+            .unwrap();
+        let pos = 128 * 1024 - 100;
+        f.seek(SeekFrom::Start(pos as u64)).unwrap();
+        f.write_once(&[7u8; 300]).unwrap();
+        buf[pos..pos + 300].copy_from_slice(&[7u8; 300]);
+
+        let mut dst = vec![0u8; 500];
+        f.seek(SeekFrom::Start(pos as u64 - 100)).unwrap();
+        f.read_exact(&mut dst).unwrap();
+        assert_eq!(&dst[..], &buf[pos - 100..pos + 400]);
+        verify_content(&mut f, &buf);
+    }
+
+    // #3, lines longer than the block-sized read buffer
+    {
+        let line = "x".repeat(20 * 1024);
+        let mut f = repo.create_file("/lines").unwrap();
+        f.write_once(format!("{}\n{}", line, line).as_bytes())
+            .unwrap();
+        let f = OpenOptions::new()
+            .low_memory(true)
+            .open(repo, "/lines")
+            .unwrap();
+        let lines: Vec<String> = f.lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec![line.clone(), line]);
+    }
+
+    // #4, buffers allocated for each file being written
+    let write_buffers = |repo: &mut zbox::Repo, low_memory: bool| {
+        let mut files = Vec::new();
+        for i in 0..4 {
+            let mut f = OpenOptions::new()
+                .create(true)
+                .low_memory(low_memory)
+                .open(repo, format!("/handle_{}_{}", low_memory, i))
+                .unwrap();
+            f.write_all(&buf[..200 * 1024]).unwrap();
+            files.push(f);
+        }
+        let per_file = repo.memory_usage().write_buffers() / files.len();
+        for mut f in files {
+            f.finish().unwrap();
+            verify_content(&mut f, &buf[..200 * 1024]);
+        }
+        assert_eq!(repo.memory_usage().write_buffers(), 0);
+        per_file
+    };
+    let default = write_buffers(repo, false);
+    let low = write_buffers(repo, true);
+    assert!(low > 0);
+    assert!(low <= 256 * 1024);
+    assert!(low * 2 <= default);
+}
+
+#[test]
+fn file_aad() {
+    init_env();
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .open("mem://file_aad", "pwd")
+        .unwrap();
+    let buf = vec![42u8; 300 * 1024];
+
+    // write content bound to associated data
+    {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .aad(b"aad1")
+            .open(&mut repo, "/file")
+            .unwrap();
+        f.write_once(&buf).unwrap();
+    }
+
+    // read with the same associated data
+    {
+        let mut f = OpenOptions::new()
+            .aad(b"aad1")
+            .open(&mut repo, "/file")
+            .unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf);
+    }
+
+    // read with different or without associated data must fail
+    {
+        let mut f = OpenOptions::new()
+            .aad(b"aad2")
+            .open(&mut repo, "/file")
+            .unwrap();
+        let mut dst = Vec::new();
+        assert!(f.read_to_end(&mut dst).is_err());
+
+        let mut f = repo.open_file("/file").unwrap();
+        let mut dst = Vec::new();
+        assert!(f.read_to_end(&mut dst).is_err());
+    }
+
+    // write with different associated data must fail
+    {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .aad(b"aad2")
+            .open(&mut repo, "/file")
+            .unwrap();
+        assert_eq!(f.write_once(&buf[..10]).unwrap_err(), Error::Decrypt);
+        let mut f = OpenOptions::new()
+            .write(true)
+            .open(&mut repo, "/file")
+            .unwrap();
+        assert_eq!(f.write_once(&buf[..10]).unwrap_err(), Error::Decrypt);
+    }
+
+    // truncate and re-bind to new associated data
+    {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .aad(b"aad2")
+            .open(&mut repo, "/file")
+            .unwrap();
+        f.write_once(&buf[..1000]).unwrap();
+
+        let mut f = OpenOptions::new()
+            .aad(b"aad2")
+            .open(&mut repo, "/file")
+            .unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(&dst[..], &buf[..1000]);
+
+        let mut f = OpenOptions::new()
+            .aad(b"aad1")
+            .open(&mut repo, "/file")
+            .unwrap();
+        let mut dst = Vec::new();
+        assert!(f.read_to_end(&mut dst).is_err());
+    }
+
+    // copied file keeps associated data binding
+    {
+        repo.copy("/file", "/file2").unwrap();
+        let mut f = OpenOptions::new()
+            .aad(b"aad2")
+            .open(&mut repo, "/file2")
+            .unwrap();
+        let mut dst = Vec::new();
+        f.read_to_end(&mut dst).unwrap();
+        assert_eq!(&dst[..], &buf[..1000]);
+    }
+
+    // associated data cannot be used with deduplication
+    {
+        assert_eq!(
+            OpenOptions::new()
+                .create(true)
+                .dedup_chunk(true)
+                .aad(b"aad1")
+                .open(&mut repo, "/file3")
+                .unwrap_err(),
+            Error::InvalidArgument
+        );
+    }
+}
+
+#[test]
+fn file_version_readers() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(3)
+        .open(repo, "/file")
+        .unwrap();
+    let contents: Vec<Vec<u8>> =
+        (1..=4u8).map(|i| vec![i; 1000 * i as usize]).collect();
+    for ctn in contents.iter() {
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_once(ctn).unwrap();
+    }
+
+    // only the last 3 versions are retained, from oldest to newest
+    let mut cnt = 0;
+    for (i, item) in f.version_readers().unwrap().enumerate() {
+        let (ver, mut rdr) = item.unwrap();
+        assert_eq!(ver.content_len(), contents[i + 1].len());
+        assert_eq!(rdr.version().unwrap().num(), ver.num());
+        let mut dst = Vec::new();
+        rdr.read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, contents[i + 1]);
+        cnt += 1;
+    }
+    assert_eq!(cnt, 3);
+
+    // file without read access cannot get readers
+    let f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.version_readers().err(), Some(Error::CannotRead));
+}
+
+#[test]
+fn file_swap() {
+    let env_ref = Arc::new(RwLock::new(common::TestEnv::new()));
+    let buf_a = vec![1u8; 100 * 1024];
+    let buf_b = vec![2u8; 50 * 1024];
+
+    {
+        let mut env = env_ref.write().unwrap();
+        let repo = &mut env.repo;
+        repo.create_file("/a").unwrap().write_once(&buf_a).unwrap();
+        repo.create_file("/b").unwrap().write_once(&buf_b).unwrap();
+        repo.create_dir("/dir").unwrap();
+
+        // both must exist and cannot be ancestor of the other
+        assert_eq!(repo.swap("/a", "/c").unwrap_err(), Error::NotFound);
+        assert_eq!(
+            repo.swap("/dir", "/dir/x").unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(repo.swap("/", "/a").unwrap_err(), Error::InvalidArgument);
+
+        // swap files in different directories, then file with directory
+        repo.create_file("/dir/c")
+            .unwrap()
+            .write_once(&buf_b)
+            .unwrap();
+        repo.swap("/a", "/dir/c").unwrap();
+        let mut dst = Vec::new();
+        repo.open_file("/a").unwrap().read_to_end(&mut dst).unwrap();
+        assert_eq!(dst, buf_b);
+        repo.swap("/dir/c", "/a").unwrap();
+        repo.swap("/dir", "/b").unwrap();
+        assert!(repo.is_file("/dir").unwrap());
+        assert!(repo.is_dir("/b").unwrap());
+        assert!(repo.is_file("/b/c").unwrap());
+        repo.swap("/b", "/dir").unwrap();
+
+        // exchange is same as swap, it doesn't create parent directories
+        assert_eq!(repo.exchange("/a", "/x/a").unwrap_err(), Error::NotFound);
+        assert!(!repo.path_exists("/x").unwrap());
+        repo.exchange("/a", "/dir/c").unwrap();
+        repo.exchange("/dir/c", "/a").unwrap();
+    }
+
+    // reader always sees a complete file during swaps
+    let reader = {
+        let env = env_ref.clone();
+        let buf_a = buf_a.clone();
+        let buf_b = buf_b.clone();
+        thread::spawn(move || {
+            for _ in 0..50 {
+                let mut f = {
+                    let mut env = env.write().unwrap();
+                    env.repo.open_file("/a").unwrap()
+                };
+                let mut dst = Vec::new();
+                f.read_to_end(&mut dst).unwrap();
+                assert!(dst == buf_a || dst == buf_b);
+            }
+        })
+    };
+    let swapper = {
+        let env = env_ref.clone();
+        thread::spawn(move || {
+            for _ in 0..50 {
+                let mut env = env.write().unwrap();
+                env.repo.swap("/a", "/b").unwrap();
+            }
+        })
+    };
+    reader.join().unwrap();
+    swapper.join().unwrap();
+
+    // even number of swaps restores the original layout
+    let mut env = env_ref.write().unwrap();
+    let mut dst = Vec::new();
+    env.repo
+        .open_file("/a")
+        .unwrap()
+        .read_to_end(&mut dst)
+        .unwrap();
+    assert_eq!(dst, buf_a);
+}
+
+#[test]
+fn file_read_write_at() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+    f.write_once(&data).unwrap();
+
+    // positional read doesn't change file position
+    f.seek(SeekFrom::Start(10)).unwrap();
+    let mut buf = [0u8; 100];
+    assert_eq!(f.read_at(&mut buf, 150_000).unwrap(), 100);
+    assert_eq!(&buf[..], &data[150_000..150_100]);
+    let mut buf2 = [0u8; 10];
+    f.read_exact(&mut buf2).unwrap();
+    assert_eq!(&buf2[..], &data[10..20]);
+    assert_eq!(f.stream_position().unwrap(), 20);
+
+    // read at or past EOF
+    assert_eq!(f.read_at(&mut buf, 199_950).unwrap(), 50);
+    assert_eq!(&buf[..50], &data[199_950..]);
+    assert_eq!(f.read_at(&mut buf, 200_000).unwrap(), 0);
+    assert_eq!(f.read_at(&mut buf, 300_000).unwrap(), 0);
+
+    // multi-part positional write then finish
+    let ver = f.curr_version().unwrap();
+    assert_eq!(f.write_at(&[1, 2, 3], 1000).unwrap(), 3);
+    assert_eq!(f.write_at(&[4, 5], 1003).unwrap(), 2);
+    assert_eq!(f.write_at(&[6], 2000).unwrap(), 1);
+    assert!(f.write(&[7]).is_err());
+    f.finish().unwrap();
+    assert_eq!(f.curr_version().unwrap(), ver + 1);
+    assert_eq!(f.stream_position().unwrap(), 20);
+    assert_eq!(f.metadata().unwrap().content_len(), data.len());
+    let mut expected = data.clone();
+    expected[1000..1005].copy_from_slice(&[1, 2, 3, 4, 5]);
+    expected[2000] = 6;
+    let mut dst = Vec::new();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, expected);
+
+    // write beyond EOF fills the gap with zeros
+    f.seek(SeekFrom::Start(5)).unwrap();
+    assert_eq!(f.write_at(&[9, 9], 200_010).unwrap(), 2);
+    f.finish().unwrap();
+    assert_eq!(f.stream_position().unwrap(), 5);
+    expected.extend_from_slice(&[0u8; 10]);
+    expected.extend_from_slice(&[9, 9]);
+    dst.clear();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, expected);
+
+    // positional write cannot interleave with unfinished write
+    f.write_all(&[8]).unwrap();
+    assert_eq!(f.write_at(&[8], 0).unwrap_err(), Error::NotFinish);
+    f.finish().unwrap();
+
+    // read-only file cannot be written at offset
+    let mut f = OpenOptions::new().read(true).open(repo, "/file").unwrap();
+    assert_eq!(f.write_at(&[1], 0).unwrap_err(), Error::CannotWrite);
+}
+
+#[test]
+fn file_write_at_out_of_order() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([7u8; 16]);
+    let mut data = vec![0u8; 3 * 1024 * 1024];
+    rng.fill_bytes(&mut data);
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    f.write_once(&data).unwrap();
+    let ver = f.curr_version().unwrap();
+
+    // backwards, forwards, overlapping earlier writes and extending the
+    // file, all in one version
+    let mut expected = data.clone();
+    let writes: Vec<(usize, usize, u8)> = vec![
+        (2_000_000, 300_000, 1),
+        (10, 100, 2),
+        (2_100_000, 50_000, 3),
+        (1_000_000, 200_000, 4),
+        (2_000_000, 10, 5),
+        (3 * 1024 * 1024 - 100, 1000, 6),
+        (3 * 1024 * 1024 + 5000, 10, 7),
+        (500, 1, 8),
+    ];
+    for &(offset, len, val) in writes.iter() {
+        let buf = vec![val; len];
+        let mut written = 0;
+        while written < len {
+            written += f
+                .write_at(&buf[written..], (offset + written) as u64)
+                .unwrap();
+        }
+        if offset + len > expected.len() {
+            expected.resize(offset + len, 0);
+        }
+        expected[offset..offset + len].copy_from_slice(&buf);
+    }
+
+    // data is not visible until finish
+    let mut dst = [0u8; 100];
+    assert_eq!(f.read_at(&mut dst, 10).unwrap(), 100);
+    assert_eq!(&dst[..], &data[10..110]);
+
+    f.finish().unwrap();
+    assert_eq!(f.curr_version().unwrap(), ver + 1);
+    assert_eq!(f.metadata().unwrap().content_len(), expected.len());
+    verify_content(&mut f, &expected);
+
+    // overlaid data is reclaimed by compaction
+    drop(f);
+    repo.compact(CompactOptions::default()).unwrap();
+    let mut f = repo.open_file("/file").unwrap();
+    verify_content(&mut f, &expected);
+
+    // inline file written out of order and beyond its end
+    let mut f = OpenOptions::new()
+        .create(true)
+        .open(repo, "/inline")
+        .unwrap();
+    f.write_once(b"hello world").unwrap();
+    f.write_at(b"W", 6).unwrap();
+    f.write_at(b"H", 0).unwrap();
+    f.write_at(b"!", 15).unwrap();
+    f.finish().unwrap();
+    verify_content(&mut f, b"Hello World\0\0\0\0!");
+
+    // append-only file cannot be overwritten by a later positional write
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append_only(true)
+        .open(repo, "/append_only")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    f.write_at(b"bar", 3).unwrap();
+    assert_eq!(f.write_at(b"x", 1).unwrap_err(), Error::AppendOnly);
+}
+
+#[test]
+fn file_restore_version() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([7u8; 16]);
+    let mut data = vec![0u8; 300 * 1024];
+    rng.fill_bytes(&mut data);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(4)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&data).unwrap();
+    let ver1 = f.curr_version().unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(&[1u8; 1000]).unwrap();
+
+    // restore the first version, content is shared with it
+    let mut buf = Vec::new();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf.len(), data.len());
+    assert!(buf != data);
+    let usage = repo.disk_usage().unwrap();
+    f.restore_version(ver1).unwrap();
+    assert_eq!(repo.disk_usage().unwrap(), usage);
+    assert_eq!(f.history().unwrap().len(), 4);
+    assert_eq!(f.curr_version().unwrap(), ver1 + 2);
+    assert_eq!(f.metadata().unwrap().content_len(), data.len());
+    buf.clear();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert!(buf == data);
+
+    // the restored version is still readable
+    buf.clear();
+    f.version_reader(ver1)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert!(buf == data);
+
+    // non-existing version
+    assert_eq!(f.restore_version(100).unwrap_err(), Error::NoVersion);
+
+    // read-only file cannot restore
+    let mut f = OpenOptions::new().read(true).open(repo, "/file").unwrap();
+    assert_eq!(f.restore_version(ver1).unwrap_err(), Error::CannotWrite);
+}
+
+#[test]
+fn file_restore_version_retire_source() {
+    init_env();
+
+    let mut rng = XorShiftRng::from_seed([9u8; 16]);
+    let mut data = vec![0u8; 300 * 1024];
+    rng.fill_bytes(&mut data);
+
+    for &dedup in [false, true].iter() {
+        let uri = format!("mem://file_restore_retire_{}", dedup);
+        let mut repo = RepoOpener::new()
+            .create(true)
+            .dedup_file(dedup)
+            .open(&uri, "pwd")
+            .unwrap();
+
+        let mut f = OpenOptions::new()
+            .create(true)
+            .version_limit(2)
+            .open(&mut repo, "/file")
+            .unwrap();
+        f.write_once(&data).unwrap();
+        let ver1 = f.curr_version().unwrap();
+        f.write_once(&[1u8; 1000]).unwrap();
+
+        // version limit is reached, so restoring retires the source version
+        f.restore_version(ver1).unwrap();
+        let history = f.history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().all(|ver| ver.num() != ver1));
+        drop(f);
+
+        // make the removed entities recycled, then re-read after re-open
+        repo.create_dir("/dir").unwrap();
+        repo.create_dir("/dir2").unwrap();
+        drop(repo);
+        let mut repo = RepoOpener::new().open(&uri, "pwd").unwrap();
+        let mut f = repo.open_file("/file").unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert!(buf == data);
+    }
+}
+
+#[test]
+fn file_remove_version() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([11u8; 16]);
+    let mut data = vec![0u8; 3 * 100 * 1024];
+    rng.fill_bytes(&mut data);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(10)
+        .open(repo, "/file")
+        .unwrap();
+    let mut vers = Vec::new();
+    for chunk in data.chunks(100 * 1024) {
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_once(chunk).unwrap();
+        vers.push(f.curr_version().unwrap());
+    }
+    let hist_len = f.history().unwrap().len();
+
+    // removing a version reclaims its space
+    assert_eq!(repo.disk_usage().unwrap(), 3 * 100 * 1024);
+    f.remove_version(vers[0]).unwrap();
+    assert_eq!(repo.disk_usage().unwrap(), 2 * 100 * 1024);
+    assert_eq!(f.history().unwrap().len(), hist_len - 1);
+    assert!(f.history().unwrap().iter().all(|v| v.num() != vers[0]));
+    assert_eq!(f.version_reader(vers[0]).unwrap_err(), Error::NoVersion);
+    assert_eq!(f.remove_version(vers[0]).unwrap_err(), Error::NoVersion);
+
+    // current version cannot be removed
+    assert_eq!(
+        f.remove_version(vers[2]).unwrap_err(),
+        Error::InvalidArgument
+    );
+    verify_content(&mut f, &data[200 * 1024..]);
+    drop(f);
+
+    // prune all but the latest version
+    assert_eq!(repo.prune_versions("/file", 0).unwrap(), hist_len - 2);
+    assert_eq!(repo.prune_versions("/file", 1).unwrap(), 0);
+    let hist = repo.history("/file").unwrap();
+    assert_eq!(hist.len(), 1);
+    assert_eq!(hist[0].num(), vers[2]);
+    assert_eq!(repo.disk_usage().unwrap(), 100 * 1024);
+
+    // version limit is not changed
+    let mut f = OpenOptions::new().write(true).open(repo, "/file").unwrap();
+    f.write_once(b"foo").unwrap();
+    f.write_once(b"bar").unwrap();
+    assert_eq!(f.history().unwrap().len(), 3);
+
+    assert_eq!(repo.prune_versions("/", 1).unwrap_err(), Error::NotFile);
+}
+
+#[test]
+fn file_read_range() {
+    init_env();
+
+    let mut rng = XorShiftRng::from_seed([12u8; 16]);
+    let mut data = vec![0u8; 3 * 1024 * 1024 + 1234];
+    rng.fill_bytes(&mut data);
+    let len = data.len() as u64;
+    let ranges = [
+        0..0,
+        0..10,
+        100..70_000,
+        1_000_000..2_500_000,
+        len - 5..len,
+        len - 5..len + 100,
+        len + 1..len + 2,
+    ];
+
+    // uncompressed data is read directly, compressed data is loaded whole
+    for (uri, compress) in [
+        ("mem://file_read_range", false),
+        ("mem://file_read_range_lz4", true),
+    ]
+    .iter()
+    {
+        {
+            let mut repo = RepoOpener::new()
+                .create(true)
+                .compress(*compress)
+                .version_limit(2)
+                .open(uri, "pwd")
+                .unwrap();
+            let mut f = repo.create_file("/file").unwrap();
+            f.write_once(&data).unwrap();
+            f.seek(SeekFrom::Start(0)).unwrap();
+            f.write_once(&[1u8; 100]).unwrap();
+        }
+
+        // reopen repo so nothing is cached
+        let mut repo = RepoOpener::new().open(uri, "pwd").unwrap();
+        let mut f = repo.open_file("/file").unwrap();
+        let mut curr = data.clone();
+        curr[..100].copy_from_slice(&[1u8; 100]);
+        f.seek(SeekFrom::Start(42)).unwrap();
+
+        for range in ranges.iter() {
+            let end = range.end.min(len) as usize;
+            let start = (range.start as usize).min(end);
+            let mut buf = vec![9u8];
+            let read = f.read_range(range.clone(), &mut buf).unwrap();
+            assert_eq!(read, end - start);
+            assert_eq!(buf[0], 9);
+            assert!(buf[1..] == curr[start..end]);
+        }
+        assert_eq!(f.stream_position().unwrap(), 42);
+
+        // read range of the previous version
+        let hist = f.history().unwrap();
+        let mut rdr = f.version_reader(hist[0].num()).unwrap();
+        let mut buf = Vec::new();
+        rdr.read_range(50..150, &mut buf).unwrap();
+        assert!(buf[..] == data[50..150]);
+
+        let bad = Range { start: 10, end: 5 };
+        assert_eq!(
+            f.read_range(bad.clone(), &mut buf).unwrap_err(),
+            Error::InvalidArgument
+        );
+        assert_eq!(
+            rdr.read_range(bad, &mut buf).unwrap_err(),
+            Error::InvalidArgument
+        );
+    }
+}
+
+#[test]
+fn file_read_ahead() {
+    init_env();
+
+    let mut rng = XorShiftRng::from_seed([13u8; 16]);
+    let mut data = vec![0u8; 3 * 1024 * 1024 + 1234];
+    rng.fill_bytes(&mut data);
+    let small = &data[..20_000];
+    let uri = "mem://file_read_ahead";
+
+    {
+        let mut repo = RepoOpener::new().create(true).open(uri, "pwd").unwrap();
+        let mut f = repo.create_file("/file").unwrap();
+        f.write_once(&data).unwrap();
+        let mut f = repo.create_file("/small").unwrap();
+        f.write_once(small).unwrap();
+    }
+
+    // reopen repo so nothing is cached
+    let mut repo = RepoOpener::new().read_ahead(4).open(uri, "pwd").unwrap();
+
+    // read whole file sequentially
+    let mut f = repo.open_file("/file").unwrap();
+    let mut buf = Vec::new();
+    f.read_to_end(&mut buf).unwrap();
+    assert!(buf == data);
+
+    // seek back and forth, frames read ahead before seeking are discarded
+    for pos in [2_000_000, 100, 3_000_000].iter() {
+        f.seek(SeekFrom::Start(*pos)).unwrap();
+        let mut buf = vec![0u8; 100_000];
+        f.read_exact(&mut buf).unwrap();
+        let pos = *pos as usize;
+        assert!(buf[..] == data[pos..pos + 100_000]);
+    }
+
+    // small file is read from frame cache the second time
+    for _ in 0..2 {
+        let mut f = repo.open_file("/small").unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        assert!(buf[..] == small[..]);
+    }
+}
+
+#[test]
+fn file_sync_data() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([8u8; 16]);
+    let mut data = vec![0u8; 200 * 1024];
+    rng.fill_bytes(&mut data);
+
+    // sync without write does nothing
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    f.sync_data().unwrap();
+    assert_eq!(f.curr_version().unwrap(), 1);
+
+    // sync small inline data and keep writing to the same version
+    f.write_all(b"foo").unwrap();
+    f.sync_data().unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+    f.write_all(&data).unwrap();
+    f.sync_data().unwrap();
+    f.write_all(b"bar").unwrap();
+    f.sync_data().unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+    f.finish().unwrap();
+    assert_eq!(f.curr_version().unwrap(), 2);
+
+    let mut expected = b"foo".to_vec();
+    expected.extend_from_slice(&data);
+    expected.extend_from_slice(b"bar");
+    let mut buf = Vec::new();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, expected);
+
+    // append to existing file in a compressed repo
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .compress(true)
+        .open("mem://file_sync_data", "pwd")
+        .unwrap();
+    let mut f = repo.create_file("/file").unwrap();
+    f.write_once(&data).unwrap();
+    let mut f = OpenOptions::new()
+        .append(true)
+        .open(&mut repo, "/file")
+        .unwrap();
+    f.write_all(&data[..1000]).unwrap();
+    f.sync_data().unwrap();
+    f.write_all(&data[1000..]).unwrap();
+    f.sync_data().unwrap();
+    f.finish().unwrap();
+    buf.clear();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..data.len()], &data[..]);
+    assert_eq!(&buf[data.len()..], &data[..]);
+}
+
+#[test]
+fn file_committed_len() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([9u8; 16]);
+    let mut data = vec![0u8; 2 * 1024 * 1024];
+    rng.fill_bytes(&mut data);
+
+    // no in-progress write
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    assert_eq!(f.committed_len(), 0);
+
+    // small inline data is not counted
+    f.write_all(b"foo").unwrap();
+    assert_eq!(f.committed_len(), 0);
+
+    // committed length grows as frames are written, data buffered in
+    // chunker and the last partial frame is not counted
+    let frame_size = 128 * 1024;
+    let max_chunk = 64 * 1024;
+    let chunker_buf = 8 * max_chunk;
+    let mut written = 3;
+    let mut last = 0;
+    for part in data.chunks(256 * 1024) {
+        f.write_all(part).unwrap();
+        written += part.len();
+        let committed = f.committed_len();
+        assert!(committed >= last);
+        assert!(committed < written);
+        assert!(written - committed <= chunker_buf + frame_size + max_chunk);
+        last = committed;
+    }
+
+    // sync flushes chunker, only the last partial frame is left
+    f.sync_data().unwrap();
+    let committed = f.committed_len();
+    assert!(committed >= last);
+    assert!(committed < written);
+    assert!(written - committed <= frame_size + max_chunk);
+
+    f.finish().unwrap();
+    assert_eq!(f.committed_len(), 0);
+    assert_eq!(f.metadata().unwrap().content_len(), data.len() + 3);
+
+    // compressed data is counted up to the compressed frames written
+    let mut f = OpenOptions::new()
+        .create(true)
+        .compress(true)
+        .open(repo, "/compressed")
+        .unwrap();
+    let mut last = 0;
+    for part in data.chunks(256 * 1024) {
+        f.write_all(part).unwrap();
+        let committed = f.committed_len();
+        assert!(committed >= last);
+        last = committed;
+    }
+    f.sync_data().unwrap();
+    let committed = f.committed_len();
+    assert!(committed > 0);
+    assert!(committed < data.len());
+    assert!(data.len() - committed <= frame_size + max_chunk);
+    f.finish().unwrap();
+}
+
+#[test]
+fn file_auto_sync() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut rng = XorShiftRng::from_seed([10u8; 16]);
+    let mut data = vec![0u8; 300 * 1024];
+    rng.fill_bytes(&mut data);
+
+    // data is synced every 100KB, synced data is written to volume except
+    // the last partial frame
+    let mut f = OpenOptions::new()
+        .create(true)
+        .auto_sync_bytes(100 * 1024)
+        .open(repo, "/file")
+        .unwrap();
+    for (i, part) in data.chunks(50 * 1024).enumerate() {
+        f.write_all(part).unwrap();
+        if i % 2 == 1 {
+            let written = (i + 1) * 50 * 1024;
+            let committed = f.committed_len();
+            assert!(committed <= written);
+            assert!(written - committed <= (128 + 64) * 1024);
+        }
+    }
+    assert_eq!(f.curr_version().unwrap(), 1);
+    f.finish().unwrap();
+    assert_eq!(f.curr_version().unwrap(), 2);
+
+    // positional write is synced as well
+    f.write_at(&data, 0).unwrap();
+    assert!(data.len() - f.committed_len() <= (128 + 64) * 1024);
+    f.finish().unwrap();
+    assert_eq!(f.curr_version().unwrap(), 3);
+
+    let mut buf = Vec::new();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn file_compress() {
+    init_env();
+
+    // compressible data
+    let mut data = vec![0u8; 300 * 1024];
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = (i / 1024) as u8;
+    }
+
+    let uri = "mem://file_compress";
+    {
+        let mut repo = RepoOpener::new()
+            .create(true)
+            .dedup_file(true)
+            .open(uri, "pwd")
+            .unwrap();
+
+        // override repo default for a single file
+        let mut f = OpenOptions::new()
+            .create(true)
+            .compress(true)
+            .open(&mut repo, "/comp")
+            .unwrap();
+        f.write_once(&data).unwrap();
+        let mut rdr = f.read_raw().unwrap();
+        assert!(rdr.is_compressed());
+        let mut raw = Vec::new();
+        rdr.read_to_end(&mut raw).unwrap();
+        assert!(raw.len() < data.len() / 2);
+
+        // file without override uses repo default
+        let mut f = repo.create_file("/plain").unwrap();
+        f.write_once(&data[..1000]).unwrap();
+        assert!(!f.read_raw().unwrap().is_compressed());
+
+        // content deduped from compressed file, then appended with
+        // uncompressed data
+        let mut f = OpenOptions::new()
+            .create(true)
+            .compress(false)
+            .open(&mut repo, "/dup")
+            .unwrap();
+        f.write_once(&data).unwrap();
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(&mut repo, "/dup")
+            .unwrap();
+        f.write_once(&data[..1000]).unwrap();
+    }
+
+    // setting is kept with the file after reopening repo
+    let mut repo = RepoOpener::new().open(uri, "pwd").unwrap();
+    let mut buf = Vec::new();
+    repo.open_file("/comp")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+    let mut f = OpenOptions::new()
+        .append(true)
+        .open(&mut repo, "/comp")
+        .unwrap();
+    f.write_once(&data[..1000]).unwrap();
+    buf.clear();
+    repo.open_file("/comp")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(&buf[..data.len()], &data[..]);
+    assert_eq!(&buf[data.len()..], &data[..1000]);
+    buf.clear();
+    repo.open_file("/dup")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(&buf[..data.len()], &data[..]);
+    assert_eq!(&buf[data.len()..], &data[..1000]);
+
+    // file in compressed repo can disable compression
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .compress(true)
+        .open("mem://file_compress2", "pwd")
+        .unwrap();
+    let mut f = OpenOptions::new()
+        .create(true)
+        .compress(false)
+        .open(&mut repo, "/file")
+        .unwrap();
+    f.write_once(&data).unwrap();
+    assert!(!f.read_raw().unwrap().is_compressed());
+    buf.clear();
+    repo.open_file("/file")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn file_set_modified() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let past = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let future = SystemTime::now() + Duration::from_secs(86_400);
+    let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+
+    // set modified time by path, for both file and directory
+    repo.create_dir("/dir").unwrap();
+    repo.create_file("/dir/file")
+        .unwrap()
+        .write_once(b"foo")
+        .unwrap();
+    repo.set_modified("/dir", past).unwrap();
+    repo.set_modified("/dir/file", future).unwrap();
+    assert_eq!(repo.metadata("/dir").unwrap().modified_at(), past);
+    assert_eq!(repo.metadata("/dir/file").unwrap().modified_at(), future);
+    assert_eq!(
+        repo.set_modified("/dir/file", before_epoch).unwrap_err(),
+        Error::InvalidArgument
+    );
+    assert_eq!(
+        repo.set_modified("/non-exist", past).unwrap_err(),
+        Error::NotFound
+    );
+
+    // set modified time by file, content is not changed
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(repo, "/dir/file")
+        .unwrap();
+    let ver = f.curr_version().unwrap();
+    f.set_modified(past).unwrap();
+    assert_eq!(f.metadata().unwrap().modified_at(), past);
+    assert_eq!(f.curr_version().unwrap(), ver);
+    assert_eq!(
+        f.set_modified(before_epoch).unwrap_err(),
+        Error::InvalidArgument
+    );
+
+    // cannot set while writing or on read-only file
+    f.write_all(b"bar").unwrap();
+    assert_eq!(f.set_modified(past).unwrap_err(), Error::NotFinish);
+    f.finish().unwrap();
+    let mut f = repo.open_file("/dir/file").unwrap();
+    assert_eq!(f.set_modified(past).unwrap_err(), Error::CannotWrite);
+    assert!(repo.metadata("/dir/file").unwrap().modified_at() > past);
+}
+
+#[test]
+fn file_truncate_by_path() {
+    init_env();
+
+    let uri = "mem://file_truncate_by_path";
+    {
+        let mut repo = RepoOpener::new().create(true).open(uri, "pwd").unwrap();
+        repo.create_dir("/dir").unwrap();
+        repo.create_file("/file")
+            .unwrap()
+            .write_once(b"Hello, world!")
+            .unwrap();
+
+        // shrink
+        repo.truncate("/file", 5).unwrap();
+        let mut buf = Vec::new();
+        repo.open_file("/file")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"Hello");
+
+        // extend with zeros
+        repo.truncate("/file", 8).unwrap();
+        buf.clear();
+        repo.open_file("/file")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"Hello\0\0\0");
+
+        // empty file
+        repo.truncate("/file", 0).unwrap();
+        assert_eq!(repo.metadata("/file").unwrap().content_len(), 0);
+
+        assert_eq!(repo.truncate("/dir", 0).unwrap_err(), Error::IsDir);
+        assert_eq!(
+            repo.truncate("/non-exist", 0).unwrap_err(),
+            Error::NotFound
+        );
+    }
+
+    // read-only repo
+    let mut repo = RepoOpener::new().read_only(true).open(uri, "pwd").unwrap();
+    assert_eq!(repo.truncate("/file", 0).unwrap_err(), Error::ReadOnly);
+}
+
+#[test]
+fn file_content_hash() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    // BLAKE2b-256 of empty message
+    let empty_hash = [
+        0x0e, 0x57, 0x51, 0xc0, 0x26, 0xe5, 0x43, 0xb2, 0xe8, 0xab, 0x2e, 0xb0,
+        0x60, 0x99, 0xda, 0xa1, 0xd1, 0xe5, 0xdf, 0x47, 0x77, 0x8f, 0x77, 0x87,
+        0xfa, 0xab, 0x45, 0xcd, 0xf1, 0x2f, 0xe3, 0xa8,
+    ];
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(2)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f.content_hash().unwrap(), empty_hash);
+
+    // content spans multiple frames
+    let mut buf = vec![0u8; 3 * 1024 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+    f.write_once(&buf).unwrap();
+    let hash = f.content_hash().unwrap();
+    assert_ne!(hash, empty_hash);
+
+    // same content in other file has the same hash
+    let mut f2 = OpenOptions::new()
+        .create(true)
+        .open(repo, "/file2")
+        .unwrap();
+    f2.write_once(&buf).unwrap();
+    assert_eq!(f2.content_hash().unwrap(), hash);
+
+    // file position is not changed
+    f.seek(SeekFrom::Start(3)).unwrap();
+    let hash2 = f.content_hash().unwrap();
+    assert_eq!(hash2, hash);
+    let mut dst = [0u8; 2];
+    f.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[3..5]);
+
+    // hash of history versions
+    f.write_once(b"foo").unwrap();
+    assert_ne!(f.content_hash().unwrap(), hash);
+    let history = f.history().unwrap();
+    assert_eq!(history.len(), 2);
+    let mut rdr = f.version_reader(history[0].num()).unwrap();
+    rdr.seek(SeekFrom::Start(5)).unwrap();
+    assert_eq!(rdr.content_hash().unwrap(), hash);
+    rdr.read_exact(&mut dst).unwrap();
+    assert_eq!(&dst[..], &buf[5..7]);
+}
+
+#[test]
+fn file_version_content_hash() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut buf = vec![0u8; 3 * 1024 * 1024];
+    let mut rng = XorShiftRng::from_seed([43u8; 16]);
+    rng.fill_bytes(&mut buf);
+
+    // same content gives same version hash, small content hash is same as
+    // file content hash
+    let mut f = OpenOptions::new()
+        .create(true)
+        .version_limit(8)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    let foo_hash = f.content_hash().unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"bar").unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"foo").unwrap();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(&buf).unwrap();
+    let history = f.history().unwrap();
+    assert_eq!(history.len(), 5);
+    assert_eq!(history[1].content_hash(), foo_hash);
+    assert_ne!(history[2].content_hash(), foo_hash);
+    assert_eq!(history[3].content_hash(), foo_hash);
+    assert_ne!(history[4].content_hash(), foo_hash);
+    let rdr = f.version_reader(history[4].num()).unwrap();
+    assert_eq!(
+        rdr.version().unwrap().content_hash(),
+        history[4].content_hash()
+    );
+    assert_eq!(
+        repo.history("/file").unwrap()[4].content_hash(),
+        history[4].content_hash()
+    );
+    drop(rdr);
+    drop(f);
+
+    // skip identical versions
+    let mut f = OpenOptions::new()
+        .write(true)
+        .skip_identical_versions(true)
+        .open(repo, "/file")
+        .unwrap();
+    f.write_once(&buf).unwrap();
+    assert_eq!(f.curr_version().unwrap(), 5);
+
+    // overwrite part of content with the same data
+    f.seek(SeekFrom::Start(1024 * 1024)).unwrap();
+    f.write_once(&buf[1024 * 1024..1024 * 1024 + 100]).unwrap();
+    assert_eq!(f.curr_version().unwrap(), 5);
+    f.seek(SeekFrom::Start(1024 * 1024)).unwrap();
+    f.write_once(b"bar").unwrap();
+    assert_eq!(f.curr_version().unwrap(), 6);
+
+    let mut dst = Vec::new();
+    let mut f = repo.open_file("/file").unwrap();
+    f.read_to_end(&mut dst).unwrap();
+    assert_eq!(&dst[..1024 * 1024], &buf[..1024 * 1024]);
+    assert_eq!(&dst[1024 * 1024..1024 * 1024 + 3], b"bar");
+    assert_eq!(&dst[1024 * 1024 + 3..], &buf[1024 * 1024 + 3..]);
+
+    // inline content
+    let mut f = OpenOptions::new()
+        .create(true)
+        .skip_identical_versions(true)
+        .open(repo, "/file2")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    assert_eq!(f.curr_version().unwrap(), 2);
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"foo").unwrap();
+    assert_eq!(f.curr_version().unwrap(), 2);
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"bar").unwrap();
+    assert_eq!(f.curr_version().unwrap(), 3);
+}
+
+#[test]
+fn file_open_shared() {
+    init_env();
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .open("mem://file_open_shared", "pwd")
+        .unwrap();
+    let mut buf = vec![0u8; 300 * 1024];
+    let mut rng = XorShiftRng::from_seed([42u8; 16]);
+    rng.fill_bytes(&mut buf);
+    repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+    repo.create_dir("/dir").unwrap();
+
+    assert_eq!(repo.open_file_shared("/dir").unwrap_err(), Error::IsDir);
+    assert_eq!(
+        repo.open_file_shared("/non-exist").unwrap_err(),
+        Error::NotFound
+    );
+
+    // reader is bound to the version at the time it is opened
+    let mut rdr = repo.open_file_shared("/file").unwrap();
+    repo.create_file("/file")
+        .unwrap()
+        .write_once(b"foo")
+        .unwrap();
+    let mut dst = Vec::new();
+    rdr.read_to_end(&mut dst).unwrap();
+    assert_eq!(dst, buf);
+    repo.create_file("/file").unwrap().write_once(&buf).unwrap();
+
+    // read concurrently from multiple threads
+    let repo = Arc::new(repo);
+    let buf = Arc::new(buf);
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let repo = repo.clone();
+            let buf = buf.clone();
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    let mut rdr = repo.open_file_shared("/file").unwrap();
+                    let mut dst = Vec::new();
+                    rdr.read_to_end(&mut dst).unwrap();
+                    assert_eq!(&dst, &*buf);
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+}
+
+#[test]
+fn file_open_handles() {
+    init_env();
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .open("mem://file_open_handles", "pwd")
+        .unwrap();
+    assert!(repo.open_handles().is_empty());
+
+    let f = repo.create_file("/file").unwrap();
+    let f2 = repo.open_file("/file").unwrap();
+    repo.create_dir("/dir").unwrap();
+    let mut f3 = repo.create_file("/dir/file").unwrap();
+    f3.write_once(b"foo").unwrap();
+    assert_eq!(
+        repo.open_handles(),
+        vec![
+            PathBuf::from("/dir/file"),
+            PathBuf::from("/file"),
+            PathBuf::from("/file")
+        ]
+    );
+
+    // reader keeps the handle alive after file is dropped
+    let rdr = f3.version_reader(f3.curr_version().unwrap()).unwrap();
+    drop(f3);
+    drop(f);
+    assert_eq!(
+        repo.open_handles(),
+        vec![PathBuf::from("/dir/file"), PathBuf::from("/file")]
+    );
+
+    drop(rdr);
+    drop(f2);
+    assert!(repo.open_handles().is_empty());
+
+    // shared reader is tracked as well
+    let rdr = repo.open_file_shared("/dir/file").unwrap();
+    assert_eq!(repo.open_handles(), vec![PathBuf::from("/dir/file")]);
+    drop(rdr);
+    assert!(repo.open_handles().is_empty());
+}
+
+#[test]
+fn file_io_error_source() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_file("/file")
+        .unwrap()
+        .write_once(b"foo")
+        .unwrap();
+
+    // zbox error is kept inside io error
+    let mut f = repo.open_file("/file").unwrap();
+    let err = f.write(b"bar").unwrap_err();
+    let inner = err.get_ref().unwrap().downcast_ref::<Error>();
+    assert_eq!(inner, Some(&Error::CannotWrite));
+
+    let mut f = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    let mut buf = Vec::new();
+    let err = f.read_to_end(&mut buf).unwrap_err();
+    let inner = err.get_ref().unwrap().downcast_ref::<Error>();
+    assert_eq!(inner, Some(&Error::CannotRead));
+
+    // io error converted back is the original error
+    assert_eq!(Error::from(err), Error::CannotRead);
+    let err = Error::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+    assert!(matches!(err, Error::Io(_)));
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn file_lock() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_file("/file")
+        .unwrap()
+        .write_once(b"foo")
+        .unwrap();
+    let mut f1 = repo.open_file("/file").unwrap();
+    let mut f2 = repo.open_file("/file").unwrap();
+    let mut f3 = repo.open_file("/file").unwrap();
+
+    // exclusive lock blocks other locks
+    f1.lock_exclusive().unwrap();
+    f1.lock_exclusive().unwrap();
+    assert_eq!(f2.try_lock_shared().unwrap_err(), Error::WouldBlock);
+    assert_eq!(f2.try_lock_exclusive().unwrap_err(), Error::WouldBlock);
+
+    // shared locks can be held together
+    f1.unlock().unwrap();
+    f2.try_lock_shared().unwrap();
+    f3.try_lock_shared().unwrap();
+    assert_eq!(f1.try_lock_exclusive().unwrap_err(), Error::WouldBlock);
+    f2.unlock().unwrap();
+    assert_eq!(f1.try_lock_exclusive().unwrap_err(), Error::WouldBlock);
+
+    // lock is released when file is dropped
+    drop(f3);
+    f1.try_lock_exclusive().unwrap();
+
+    // lock is kept on the file after it is renamed
+    repo.rename("/file", "/file2").unwrap();
+    let mut f4 = repo.open_file("/file2").unwrap();
+    assert_eq!(f4.try_lock_shared().unwrap_err(), Error::WouldBlock);
+
+    // blocking lock waits until the lock is released
+    let handle = thread::spawn(move || {
+        f4.lock_shared().unwrap();
+        f4
+    });
+    thread::sleep(Duration::from_millis(100));
+    assert!(!handle.is_finished());
+    f1.unlock().unwrap();
+    let mut f4 = handle.join().unwrap();
+    assert_eq!(f1.try_lock_exclusive().unwrap_err(), Error::WouldBlock);
+
+    // converting lock releases the existing one
+    f4.lock_exclusive().unwrap();
+    assert_eq!(f2.try_lock_shared().unwrap_err(), Error::WouldBlock);
+    f4.lock_shared().unwrap();
+    f2.try_lock_shared().unwrap();
+}
+
+#[test]
+fn file_path_after_rename() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    repo.create_dir_all("/dir/sub").unwrap();
+    let mut f = OpenOptions::new()
+        .create(true)
+        .open(repo, "/dir/sub/file")
+        .unwrap();
+    f.write_once(b"foo").unwrap();
+    assert_eq!(f.path().unwrap(), PathBuf::from("/dir/sub/file"));
+
+    // rename ancestor dir while file is open
+    repo.rename("/dir", "/dir2").unwrap();
+    assert_eq!(f.path().unwrap(), PathBuf::from("/dir2/sub/file"));
+
+    // file keeps reading and writing after rename
+    let mut buf = Vec::new();
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..], b"foo");
+    f.write_once(b"bar").unwrap();
+    let mut f2 = repo.open_file("/dir2/sub/file").unwrap();
+    buf.clear();
+    f2.read_to_end(&mut buf).unwrap();
+    assert_eq!(&buf[..], b"foobar");
+
+    // rename file itself
+    repo.rename("/dir2/sub/file", "/dir2/file2").unwrap();
+    assert_eq!(f.path().unwrap(), PathBuf::from("/dir2/file2"));
+    assert_eq!(f2.path().unwrap(), PathBuf::from("/dir2/file2"));
+}
+
+#[test]
+fn file_max_len() {
+    init_env();
+    let mut repo = RepoOpener::new()
+        .create(true)
+        .max_file_len(8 * 1024)
+        .open("mem://file_max_len", "pwd")
+        .unwrap();
+
+    let mut rng = XorShiftRng::from_seed([11u8; 16]);
+    let mut data = vec![0u8; 16 * 1024];
+    rng.fill_bytes(&mut data);
+
+    // write within the repository default limit
+    let mut f = repo.create_file("/file").unwrap();
+    f.write_once(&data[..8 * 1024]).unwrap();
+    assert_eq!(f.curr_version().unwrap(), 2);
+
+    // write beyond the limit is rejected and no version is created
+    f.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(
+        Error::from(f.write_all(&data[..1]).unwrap_err()),
+        Error::FileTooLarge
+    );
+    assert_eq!(f.set_len(8 * 1024 + 1).unwrap_err(), Error::FileTooLarge);
+    assert_eq!(f.curr_version().unwrap(), 2);
+    assert_eq!(f.metadata().unwrap().content_len(), 8 * 1024);
+
+    // partial writes stop once the limit is reached
+    let mut f = OpenOptions::new()
+        .create(true)
+        .max_len(12 * 1024)
+        .open(&mut repo, "/file2")
+        .unwrap();
+    f.write_all(&data[..10 * 1024]).unwrap();
+    assert_eq!(
+        Error::from(f.write_all(&data[..4 * 1024]).unwrap_err()),
+        Error::FileTooLarge
+    );
+    assert_eq!(f.finish().unwrap_err(), Error::NotWrite);
+    assert_eq!(f.curr_version().unwrap(), 1);
+    assert_eq!(f.metadata().unwrap().content_len(), 0);
+
+    // per-file limit overrides the repository default
+    f.write_once(&data[..12 * 1024]).unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 12 * 1024);
+    f.set_len(4 * 1024).unwrap();
+    assert_eq!(f.metadata().unwrap().content_len(), 4 * 1024);
+}
+
+#[test]
+fn file_read_all_cached() {
+    let mut env = common::TestEnv::new();
+    let repo = &mut env.repo;
+
+    let mut f = OpenOptions::new().create(true).open(repo, "/file").unwrap();
+    f.write_once(b"foo").unwrap();
+
+    // repeated reads share the same buffer, also across handles
+    let content = f.read_all_cached().unwrap();
+    assert_eq!(&content[..], b"foo");
+    assert!(Arc::ptr_eq(&content, &f.read_all_cached().unwrap()));
+    let mut f2 = repo.open_file("/file").unwrap();
+    assert!(Arc::ptr_eq(&content, &f2.read_all_cached().unwrap()));
+
+    // new version is read again
+    f.seek(SeekFrom::Start(0)).unwrap();
+    f.write_once(b"bar").unwrap();
+    let content2 = f2.read_all_cached().unwrap();
+    assert_eq!(&content2[..], b"bar");
+    assert!(!Arc::ptr_eq(&content, &content2));
+    assert_eq!(&content[..], b"foo");
+
+    f.set_len(1).unwrap();
+    assert_eq!(&f2.read_all_cached().unwrap()[..], b"b");
+
+    // write-only file cannot read
+    let mut f3 = OpenOptions::new()
+        .read(false)
+        .write(true)
+        .open(repo, "/file")
+        .unwrap();
+    assert_eq!(f3.read_all_cached().unwrap_err(), Error::CannotRead);
 }
@@ -211,6 +211,28 @@ fn test_file_perf(data: &[u8], dir: &Path) {
         .unwrap();
     let mut files = make_files(&mut repo);
     test_perf(&mut repo, &mut files, data);
+
+    println!("---------------------------------------------");
+    println!("File storage performance test (background commit)");
+    println!("---------------------------------------------");
+    let mut repo = RepoOpener::new()
+        .create_new(true)
+        .background_commit(true)
+        .open(&format!("file://{}/repo3", dir.display()), "pwd")
+        .unwrap();
+    let mut files = make_files(&mut repo);
+    test_perf(&mut repo, &mut files, data);
+
+    println!("---------------------------------------------");
+    println!("File storage performance test (read ahead)");
+    println!("---------------------------------------------");
+    let mut repo = RepoOpener::new()
+        .create_new(true)
+        .read_ahead(4)
+        .open(&format!("file://{}/repo4", dir.display()), "pwd")
+        .unwrap();
+    let mut files = make_files(&mut repo);
+    test_perf(&mut repo, &mut files, data);
 }
 
 #[test]
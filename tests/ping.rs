@@ -0,0 +1,29 @@
+#![cfg(any(feature = "storage-faulty", feature = "storage-zbox-faulty"))]
+
+extern crate zbox;
+
+use zbox::{init_env, FaultyController, RepoOpener};
+
+#[test]
+fn ping_unreachable() {
+    init_env();
+
+    let uri = if cfg!(feature = "storage-faulty") {
+        "faulty://ping_unreachable"
+    } else {
+        "zbox://foo@ping_unreachable?cache_type=mem&cache_size=1mb"
+    };
+    let repo = RepoOpener::new().create(true).open(uri, "pwd").unwrap();
+    repo.ping().unwrap();
+
+    // make every storage access fail to simulate unreachable backend
+    let ctlr = FaultyController::new();
+    ctlr.reset(&[0u8; 32], 1.0);
+    ctlr.turn_on();
+    let result = repo.ping();
+    ctlr.turn_off();
+    assert_eq!(result.unwrap_err().to_string(), "Faulty error");
+
+    // backend is reachable again
+    repo.ping().unwrap();
+}